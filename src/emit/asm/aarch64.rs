@@ -0,0 +1,430 @@
+use std::io::Write;
+use crate::ir::builder::ctx::IRContext;
+use crate::ir::values::function::Function;
+use crate::ir::values::global::GlobalVariable;
+use crate::ir::linkage::Linkage;
+use crate::targets::layout::DataLayout;
+use crate::emit::asm::{data, unsupported, FrameOptions};
+use crate::emit::asm::regalloc::allocate;
+use crate::emit::asm::aarch64::inst::{Inst, MachineBlock, MachineFunction};
+use crate::emit::asm::aarch64::lower::FunctionLowering;
+use crate::emit::asm::aarch64::regalloc::Registers;
+
+pub mod abi;
+pub mod frame;
+pub mod inst;
+pub mod lower;
+pub mod regalloc;
+
+struct Aarch64Emitter {
+    ctx: IRContext,
+    frame_options: FrameOptions,
+}
+
+impl Aarch64Emitter {
+    pub fn new(ctx: IRContext, frame_options: FrameOptions) -> Self {
+        Self {
+            ctx,
+            frame_options,
+        }
+    }
+
+    pub fn emit_module(&mut self, file: &mut impl Write) -> Result<(), std::io::Error> {
+        writeln!(file, "\t\t.text")?;
+
+        let functions = self.ctx.get_module().get_functions().clone();
+        let globals = self.ctx.get_module().get_global_variables().clone();
+        for function in &functions {
+            self.emit_function(file, &function.borrow(), true)?;
+        }
+        for global in &globals {
+            self.emit_global(file, &global.borrow(), true)?;
+        }
+        for global in &globals {
+            self.emit_global(file, &global.borrow(), false)?;
+        }
+        if !globals.is_empty() {
+            writeln!(file, "\t\t.text")?;
+        }
+        for function in functions {
+            self.emit_function(file, &function.borrow(), false)?;
+        }
+
+        writeln!(file, "\t\t.section .note.GNU-stack,\"\",@progbits")?;
+        Ok(())
+    }
+
+    pub fn emit_global(&mut self, file: &mut impl Write, global: &GlobalVariable, decl: bool) -> Result<(), std::io::Error> {
+        if decl {
+            match global.get_linkage() {
+                Linkage::ExternalLinkage => writeln!(file, "\t\t.extern {}", global.get_name())?,
+                Linkage::InternalLinkage => writeln!(file, "\t\t.globl {}", global.get_name())?,
+                // private globals stay local to the object, common ones are declared by `.comm`
+                Linkage::PrivateLinkage | Linkage::CommonLinkage => {}
+                Linkage::ExternalWeakLinkage | Linkage::LinkonceLinkage | Linkage::WeakLinkage => writeln!(file, "\t\t.weak {}", global.get_name())?,
+
+                Linkage::AppendingLinkage => return Err(unsupported(format!("appending linkage on global {}", global.get_name()))),
+            }
+            return Ok(());
+        }
+
+        data::emit_global(file, self.ctx.get_module().data_layout(), global)
+    }
+
+    pub fn emit_function(&mut self, file: &mut impl Write, func: &Function, decl: bool) -> Result<(), std::io::Error> {
+        if decl {
+            // write the function prefix for linkage
+            match func.get_linkage() {
+                Linkage::ExternalLinkage => writeln!(file, "\t\t.extern {}", func.get_name())?,
+                Linkage::InternalLinkage => writeln!(file, "\t\t.globl {}", func.get_name())?,
+                Linkage::PrivateLinkage => writeln!(file)?,
+                Linkage::ExternalWeakLinkage => writeln!(file, "\t\t.weak {}", func.get_name())?,
+                Linkage::CommonLinkage => writeln!(file, "\t\t.extern {}", func.get_name())?,
+                Linkage::LinkonceLinkage | Linkage::WeakLinkage => writeln!(file, "\t\t.weak {}", func.get_name())?,
+
+                Linkage::AppendingLinkage => return Err(unsupported(format!("appending linkage on function {}", func.get_name()))),
+            }
+            return Ok(());
+        }
+
+        if func.is_external() || func.get_blocks().is_empty() {
+            return Ok(());
+        }
+
+        let mf = compile_function(func, self.ctx.get_module().data_layout(), self.frame_options)?;
+
+        // write the function name
+        writeln!(file, "\t\t.p2align 2")?;
+        writeln!(file, "\t\t.type {}, %function", func.get_name())?;
+        writeln!(file, "{}:", func.get_name())?;
+        for block in &mf.blocks {
+            self.emit_basic_block(file, block)?;
+        }
+
+        writeln!(file)?;
+        Ok(())
+    }
+
+    pub fn emit_basic_block(&mut self, file: &mut impl Write, bb: &MachineBlock) -> Result<(), std::io::Error> {
+        match &bb.comment {
+            Some(comment) => writeln!(file, "{}:\t// {}", bb.label, comment)?,
+            None => writeln!(file, "{}:", bb.label)?,
+        }
+
+        for inst in &bb.insts {
+            self.emit_instruction(file, inst)?;
+        }
+        Ok(())
+    }
+
+    pub fn emit_instruction(&mut self, file: &mut impl Write, x: &Inst) -> Result<(), std::io::Error> {
+        writeln!(file, "\t\t{}", x)
+    }
+}
+
+/// Lowers a function with a body to machine code over physical registers,
+/// with its frame laid out.
+pub fn compile_function(func: &Function, layout: &DataLayout, frame_options: FrameOptions) -> Result<MachineFunction, std::io::Error> {
+    let mut mf = FunctionLowering::new(func, layout).lower()?;
+    allocate::<Registers>(&mut mf);
+    frame::lay_out(&mut mf, frame_options);
+    Ok(mf)
+}
+
+pub fn emit_module(ctx: IRContext, frame_options: FrameOptions, file: &mut impl Write) -> Result<(), std::io::Error> {
+    let mut emitter = Aarch64Emitter::new(ctx, frame_options);
+    emitter.emit_module(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::values::value::Type;
+    use crate::ir::values::instruction::FloatPredicate;
+    use crate::error::Error;
+    use crate::ir::builder::{Builder, IRContext};
+    use crate::ir::linkage::Linkage;
+    use crate::ir::module::Module;
+    use crate::targets::{DataLayout, TargetTriple};
+
+    fn builder() -> Builder {
+        let triple = TargetTriple::new("aarch64-unknown-linux-gnu").unwrap();
+        let module = Module::new("test", DataLayout::from_triple(&triple), triple);
+        Builder::new(IRContext::new(module))
+    }
+
+    /// Returns the instructions and labels of the emitted assembly, one per entry.
+    fn emit(builder: &Builder) -> Vec<String> {
+        let mut out = Vec::new();
+        builder.emit_assembly(&mut out).unwrap();
+        String::from_utf8(out).unwrap().lines().map(|line| line.trim().to_string()).collect()
+    }
+
+    #[test]
+    fn lowers_arithmetic_and_branches() -> Result<(), Error> {
+        let mut builder = builder();
+        let params = vec![(builder.get_i32_type(), Some("a")), (builder.get_i32_type(), Some("b"))];
+        let main = builder.create_function_with_param_names("main", params, builder.get_i32_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main.clone())?;
+        let yes = builder.create_block("yes", main.clone())?;
+        let no = builder.create_block("no", main.clone())?;
+        builder.set_insertion_point(entry);
+        let product = builder.mul(builder.get_param(&main, 0)?, builder.get_param(&main, 1)?, None)?;
+        let quotient = builder.sdiv(product.clone().into(), builder.get_i32(3), None)?;
+        let less = builder.slt(quotient.clone().into(), builder.get_i32(10), None)?;
+        builder.branch_if(less.into(), yes.clone(), no.clone())?;
+        builder.set_insertion_point(yes);
+        builder.ret(quotient.into())?;
+        builder.set_insertion_point(no);
+        let rest = builder.srem(product.into(), builder.get_i32(7), None)?;
+        builder.ret(rest.into())?;
+
+        let lines = emit(&builder);
+        assert!(lines.contains(&"main:".to_string()));
+        assert_eq!(lines[lines.iter().position(|line| line == "main:").unwrap() + 2..][..2], ["stp x29, x30, [sp, #-16]!", "mov x29, sp"]);
+        // the parameters arrive in w0 and w1
+        assert!(lines.contains(&"mul w1, w0, w1".to_string()));
+        assert!(lines.iter().any(|line| line.starts_with("sdiv w")));
+        assert!(lines.contains(&"cset w2, lt".to_string()));
+        assert!(lines.contains(&"b.ne .Lmain.yes".to_string()));
+        // there is no remainder instruction
+        assert!(lines.contains(&"sub w0, w1, w2".to_string()));
+        assert_eq!(lines.iter().filter(|line| *line == "ldp x29, x30, [sp], #16").count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn lowers_calls_globals_and_allocas() -> Result<(), Error> {
+        let mut builder = builder();
+        let counter = builder.create_global("counter", builder.get_i32_type(), Some(builder.const_int(builder.get_i32_type(), 7)?), Linkage::InternalLinkage, false)?;
+        let errno = builder.create_global("errno", builder.get_i32_type(), None, Linkage::ExternalLinkage, false)?;
+        let tls = builder.create_global("tls", builder.get_i32_type(), Some(builder.const_int(builder.get_i32_type(), 1)?), Linkage::InternalLinkage, false)?;
+        tls.borrow_mut().set_thread_local(true);
+        let args = (0..10).map(|_| builder.get_i64_type()).collect();
+        let sink = builder.create_function("sink", args, builder.get_i64_type(), Linkage::ExternalLinkage, false)?;
+
+        let main = builder.create_function("main", vec![], builder.get_i64_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main)?;
+        builder.set_insertion_point(entry);
+        let a = builder.load(builder.get_i32_type(), counter.borrow().clone().into(), None)?;
+        let b = builder.load(builder.get_i32_type(), errno.borrow().clone().into(), None)?;
+        let c = builder.load(builder.get_i32_type(), tls.borrow().clone().into(), None)?;
+        let sum = builder.add(a.into(), b.into(), None)?;
+        let sum = builder.add(sum.into(), c.into(), None)?;
+        let array = builder.alloca(builder.get_i32_type(), Some(sum.into()), None, None)?;
+        builder.store(array.into(), builder.get_i32(0))?;
+        let args = (0..10).map(|i| builder.get_i64(i)).collect();
+        let result = builder.call(sink.borrow().clone().into(), args, None)?;
+        builder.ret(result.into())?;
+
+        let lines = emit(&builder);
+        let after = |label: &str| &lines[lines.iter().position(|line| line == label).unwrap() + 1];
+        assert_eq!(after("counter:"), ".long 7");
+        assert_eq!(after("tls:"), ".long 1");
+        assert!(lines.contains(&".section .tdata,\"awT\",@progbits".to_string()));
+
+        assert_eq!(after("adrp x1, counter"), "add x1, x1, :lo12:counter");
+        assert_eq!(after("adrp x2, :got:errno"), "ldr x2, [x2, :got_lo12:errno]");
+        assert!(lines.contains(&"mrs x3, tpidr_el0".to_string()));
+        assert_eq!(after("adrp x4, :gottprel:tls"), "ldr x4, [x4, :gottprel_lo12:tls]");
+
+        // the dynamic alloca moves sp and starts above the outgoing arguments
+        assert!(lines.contains(&"sub sp, sp, x1".to_string()));
+        assert!(lines.contains(&"add x1, sp, #16".to_string()));
+        // eight arguments go in registers, the last two on the stack
+        assert!(lines.contains(&"movz x7, #0x7".to_string()));
+        assert!(lines.contains(&"str x1, [sp, #8]".to_string()));
+        assert!(lines.contains(&"bl sink".to_string()));
+        assert!(lines.contains(&"add sp, x29, #0".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn lowers_conversions() -> Result<(), Error> {
+        let mut builder = builder();
+        let params = vec![(builder.get_i32_type(), Some("a")), (builder.get_i64_type(), Some("b")), (builder.get_f64_type(), Some("x"))];
+        let main = builder.create_function_with_param_names("main", params, builder.get_f64_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main.clone())?;
+        builder.set_insertion_point(entry);
+        let (a, b, x) = (builder.get_param(&main, 0)?, builder.get_param(&main, 1)?, builder.get_param(&main, 2)?);
+        let wide = builder.sext(a.clone(), builder.get_i64_type(), None)?;
+        let float = builder.si_to_fp(wide.into(), builder.get_f64_type(), None)?;
+        builder.fp_trunc(float.into(), builder.get_f32_type(), None)?;
+        let byte = builder.trunc(b, builder.get_i8_type(), None)?;
+        builder.zext(byte.into(), builder.get_i32_type(), None)?;
+        let single = builder.ui_to_fp(a, builder.get_f32_type(), None)?;
+        let double = builder.fp_ext(single.into(), builder.get_f64_type(), None)?;
+        builder.fp_to_si(x.clone(), builder.get_i32_type(), None)?;
+        builder.fp_to_ui(x.clone(), builder.get_i64_type(), None)?;
+        builder.bitcast(x, builder.get_i64_type(), None)?;
+        builder.ret(double.into())?;
+
+        let lines = emit(&builder);
+        let main = lines.iter().position(|line| line == "main:").unwrap();
+        assert_eq!(lines[main + 6..][..10], [
+            "sxtw x4, w2",
+            "scvtf d1, x4",
+            "fcvt s1, d1",
+            "uxtb w3, w3",
+            "ucvtf s1, w2",
+            "fcvt d1, s1",
+            "fcvtzs w2, d0",
+            "fcvtzu x2, d0",
+            "fmov x2, d0",
+            "fmov d0, d1",
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn lowers_unsigned_division_shifts_and_comparisons() -> Result<(), Error> {
+        let mut builder = builder();
+        let params = vec![(builder.get_i32_type(), Some("a")), (builder.get_i32_type(), Some("b"))];
+        let main = builder.create_function_with_param_names("main", params, builder.get_i32_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main.clone())?;
+        let yes = builder.create_block("yes", main.clone())?;
+        let no = builder.create_block("no", main.clone())?;
+        builder.set_insertion_point(entry);
+        let (a, b) = (builder.get_param(&main, 0)?, builder.get_param(&main, 1)?);
+        let quotient = builder.udiv(a.clone(), b.clone(), None)?;
+        let rest = builder.urem(quotient.clone().into(), b.clone(), None)?;
+        let shifted = builder.lshr(rest.into(), b, None)?;
+        let less = builder.ult(shifted.clone().into(), a, None)?;
+        builder.branch_if(less.into(), yes.clone(), no.clone())?;
+        builder.set_insertion_point(yes);
+        let flipped = builder.not(shifted.into(), None)?;
+        builder.ret(flipped.into())?;
+        builder.set_insertion_point(no);
+        builder.ret(quotient.into())?;
+
+        let lines = emit(&builder);
+        let main = lines.iter().position(|line| line == "main:").unwrap();
+        assert_eq!(lines[main + 4..][..7], [
+            "udiv w2, w0, w1",
+            // the remainder is what the quotient leaves
+            "udiv w3, w2, w1",
+            "mul w3, w3, w1",
+            "sub w3, w2, w3",
+            "lsr w1, w3, w1",
+            "cmp w1, w0",
+            "cset w0, lo",
+        ]);
+        // all ones is no logical immediate, so it comes from a register
+        assert!(lines.windows(2).any(|pair| pair[0] == "movn w0, #0x0" && pair[1] == "eor w0, w1, w0"));
+        Ok(())
+    }
+
+    #[test]
+    fn lowers_float_arithmetic_and_comparisons() -> Result<(), Error> {
+        let mut builder = builder();
+        let params = vec![(builder.get_f64_type(), Some("a")), (builder.get_f64_type(), Some("b"))];
+        let main = builder.create_function_with_param_names("main", params, builder.get_f64_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main.clone())?;
+        let yes = builder.create_block("yes", main.clone())?;
+        let no = builder.create_block("no", main.clone())?;
+        builder.set_insertion_point(entry);
+        let (a, b) = (builder.get_param(&main, 0)?, builder.get_param(&main, 1)?);
+        let sum = builder.fadd(a.clone(), builder.get_f64(1.5), None)?;
+        let quotient = builder.fdiv(sum.into(), b.clone(), None)?;
+        let rest = builder.frem(quotient.clone().into(), b, None)?;
+        let negated = builder.fneg(rest.into(), None)?;
+        let unordered = builder.fcmp(FloatPredicate::Ult, negated.clone().into(), a, None)?;
+        builder.branch_if(unordered.into(), yes.clone(), no.clone())?;
+        builder.set_insertion_point(yes);
+        builder.ret(negated.into())?;
+        builder.set_insertion_point(no);
+        builder.ret(quotient.into())?;
+
+        let lines = emit(&builder);
+        let entry = lines.iter().position(|line| line.starts_with(".Lmain.entry:")).unwrap();
+        assert_eq!(
+            lines[entry + 5..][..11],
+            [
+                "movz x0, #0x3ff8, lsl #48",
+                "fmov d2, x0",
+                "fadd d2, d8, d2",
+                "fdiv d9, d2, d1",
+                "fmov d0, d9",
+                "bl fmod",
+                "fneg d0, d0",
+                "fcmp d0, d8",
+                // lt also holds for unordered operands
+                "cset w0, lt",
+                "tst w0, #1",
+                "b.ne .Lmain.yes",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn lowers_constant_addresses_and_aggregates() -> Result<(), Error> {
+        let mut builder = builder();
+        let array = builder.get_array_type(builder.get_i32_type(), 2);
+        let elements = vec![builder.const_int(builder.get_i32_type(), 7)?, builder.const_int(builder.get_i32_type(), -1)?];
+        let table = builder.create_global("table", array.clone(), Some(builder.const_array(builder.get_i32_type(), elements)?), Linkage::InternalLinkage, false)?;
+        let address = builder.const_address(&table.borrow().clone().into())?;
+        let address = builder.const_cast("ptrtoint", address, builder.get_i64_type())?;
+        let end = builder.const_binary("add", address.clone(), builder.const_int(builder.get_i64_type(), 8)?)?;
+        builder.create_global("end", builder.get_i64_type(), Some(end), Linkage::InternalLinkage, false)?;
+
+        let params = vec![(builder.get_i64_type(), Some("a"))];
+        let main = builder.create_function_with_param_names("main", params, builder.get_i64_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main.clone())?;
+        builder.set_insertion_point(entry);
+        let second = builder.const_binary("add", address, builder.const_int(builder.get_i64_type(), 4)?)?;
+        let sum = builder.add(builder.get_param(&main, 0)?, second.into(), None)?;
+        let wide = builder.add(sum.into(), builder.const_int(builder.get_i64_type(), 0x1234_5678_9abc)?.into(), None)?;
+        builder.ret(wide.into())?;
+
+        let lines = emit(&builder);
+        let after = |label: &str| lines.iter().skip_while(|line| *line != label).skip(1).take(2).map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(after("table:"), [".long 7", ".long 4294967295"]);
+        assert_eq!(after("end:")[0], ".quad table+8");
+        let entry = lines.iter().position(|line| line.starts_with(".Lmain.entry:")).unwrap();
+        assert_eq!(lines[entry + 3..][..9], [
+            "adrp x1, :got:table",
+            "ldr x1, [x1, :got_lo12:table]",
+            "movz x2, #0x4",
+            "add x1, x1, x2",
+            "add x0, x0, x1",
+            "movz x1, #0x9abc",
+            "movk x1, #0x5678, lsl #16",
+            "movk x1, #0x1234, lsl #32",
+            "add x0, x0, x1",
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn lowers_element_addresses() -> Result<(), Error> {
+        let mut builder = builder();
+        let array = builder.get_array_type(builder.get_i32_type(), 4);
+        let pair = builder.get_pointer_type(Type::Struct(vec![builder.get_i8_type(), array]));
+        let params = vec![(pair, Some("p")), (builder.get_i32_type(), Some("i"))];
+        let main = builder.create_function_with_param_names("main", params, builder.get_i32_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main.clone())?;
+        builder.set_insertion_point(entry);
+        let field = builder.struct_gep(builder.get_param(&main, 0)?, 1, None)?;
+        let element = builder.array_gep(field.into(), builder.get_param(&main, 1)?, None)?;
+        let value = builder.load(builder.get_i32_type(), element.clone().into(), None)?;
+        builder.store(element.into(), builder.get_i32(7))?;
+        builder.ret(value.into())?;
+
+        let lines = emit(&builder);
+        let entry = lines.iter().position(|line| line.starts_with(".Lmain.entry:")).unwrap();
+        assert_eq!(lines[entry + 3..][..10], [
+            "mov w2, w1",
+            "add x0, x0, #4",
+            "add x0, x0, #0",
+            // the i32 index is sign-extended before scaling
+            "sxtw x2, w2",
+            "movz x3, #0x4",
+            "mul x2, x2, x3",
+            "add x0, x0, x2",
+            "ldr w2, [x0]",
+            "movz w3, #0x7",
+            "str w3, [x0]",
+        ]);
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+use crate::emit::asm::aarch64::inst::PReg;
+use crate::emit::asm::unsupported;
+use crate::ir::values::value::Type;
+use crate::targets::layout::DataLayout;
+use std::io::Error;
+
+/// Registers used for integer, pointer and small composite arguments, in order.
+pub const INT_ARGUMENT_REGS: [PReg; 8] = [PReg::X0, PReg::X1, PReg::X2, PReg::X3, PReg::X4, PReg::X5, PReg::X6, PReg::X7];
+/// Registers used for floating point arguments and homogeneous aggregates, in order.
+pub const FLOAT_ARGUMENT_REGS: [PReg; 8] = [PReg::V0, PReg::V1, PReg::V2, PReg::V3, PReg::V4, PReg::V5, PReg::V6, PReg::V7];
+/// Holds the address of the caller's buffer for results returned in memory.
+pub const INDIRECT_RESULT_REG: PReg = PReg::X8;
+
+/// Part of a value passed in a register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Piece {
+    pub reg: PReg,
+    /// Offset of the part within the value.
+    pub offset: u64,
+    /// Number of bytes of the value that live in the register.
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgLocation {
+    /// General purpose registers hold eight bytes each, floating point
+    /// registers one member of a homogeneous aggregate.
+    Regs(Vec<Piece>),
+    /// In the argument area, at `offset` bytes from the stack pointer at the call.
+    Stack { offset: u64, size: u64 },
+    /// Composites larger than 16 bytes are copied to memory by the caller,
+    /// which passes the address of the copy like a pointer argument.
+    Reference(Box<ArgLocation>),
+    /// Zero-sized values are not passed at all.
+    Ignore,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReturnLocation {
+    Void,
+    Regs(Vec<Piece>),
+    /// The caller passes a buffer in `x8`.
+    Memory,
+}
+
+/// Where the arguments and return value of a call live.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallAbi {
+    pub args: Vec<ArgLocation>,
+    pub ret: ReturnLocation,
+    /// Size of the stack argument area, a multiple of 16.
+    pub stack_size: u64,
+}
+
+/// Collects the scalar members of a value of type `ty` with their offsets.
+fn flatten(layout: &DataLayout, ty: &Type, offset: u64, members: &mut Vec<(Type, u64)>) -> Result<(), Error> {
+    match ty {
+        Type::Integer(_) | Type::Pointer(_) | Type::FunctionType(_, _) | Type::Float(32) | Type::Float(64) => members.push((ty.clone(), offset)),
+        Type::Array(len, element) => {
+            let stride = layout.stride_of(element);
+            for i in 0..*len as u64 {
+                flatten(layout, element, offset + i * stride, members)?;
+            }
+        }
+        Type::Struct(fields) => {
+            for (field, field_offset) in fields.iter().zip(layout.struct_field_offsets(fields)) {
+                flatten(layout, field, offset + field_offset, members)?;
+            }
+        }
+        Type::Float(_) | Type::Void | Type::Branch => return Err(unsupported(format!("passing values of type {}", ty))),
+    }
+    Ok(())
+}
+
+/// Returns the members of a homogeneous floating point aggregate: a
+/// composite of one to four floating point members of the same type.
+fn homogeneous_members(layout: &DataLayout, ty: &Type) -> Result<Option<Vec<(Type, u64)>>, Error> {
+    if !ty.is_struct() && !ty.is_array() {
+        return Ok(None);
+    }
+    let mut members = Vec::new();
+    flatten(layout, ty, 0, &mut members)?;
+    let homogeneous = (1..=4).contains(&members.len())
+        && members[0].0.is_float()
+        && members.iter().all(|(member, _)| *member == members[0].0);
+    Ok(homogeneous.then_some(members))
+}
+
+/// Registers a value of type `ty` is passed in if enough of them are left,
+/// taking them from the given pools only if all of them fit.
+fn assign<'a>(layout: &DataLayout, ty: &Type, ints: &mut &'a [PReg], floats: &mut &'a [PReg]) -> Result<Option<Vec<Piece>>, Error> {
+    let size = layout.size_of(ty);
+    if let Some(members) = homogeneous_members(layout, ty)? {
+        if members.len() > floats.len() {
+            // once a homogeneous aggregate goes to the stack, so do all later floating point arguments
+            *floats = &[];
+            return Ok(None);
+        }
+        let pieces = members.iter().zip(floats.iter())
+            .map(|((member, offset), reg)| Piece { reg: *reg, offset: *offset, size: layout.size_of(member) })
+            .collect();
+        *floats = &floats[members.len()..];
+        return Ok(Some(pieces));
+    }
+    if ty.is_float() {
+        let Some((reg, rest)) = floats.split_first() else {
+            return Ok(None);
+        };
+        *floats = rest;
+        return Ok(Some(vec![Piece { reg: *reg, offset: 0, size }]));
+    }
+
+    // composites aligned to 16 bytes start at an even register
+    if layout.align_of(ty) == 16 && (INT_ARGUMENT_REGS.len() - ints.len()) % 2 == 1 && !ints.is_empty() {
+        *ints = &ints[1..];
+    }
+    let needed = size.div_ceil(8) as usize;
+    if needed > ints.len() {
+        *ints = &[];
+        return Ok(None);
+    }
+    let pieces = (0..needed).map(|i| {
+        let offset = i as u64 * 8;
+        Piece { reg: ints[i], offset, size: (size - offset).min(8) }
+    }).collect();
+    *ints = &ints[needed..];
+    Ok(Some(pieces))
+}
+
+fn is_composite(ty: &Type) -> bool {
+    ty.is_struct() || ty.is_array()
+}
+
+/// Computes where the arguments and the return value of a call with the
+/// given argument and return types are passed, following the procedure
+/// call standard for the Arm 64-bit architecture. Variadic arguments are
+/// passed like named ones.
+pub fn classify_call(layout: &DataLayout, args: &[Type], ret: &Type) -> Result<CallAbi, Error> {
+    let ret = if ret.is_void() {
+        ReturnLocation::Void
+    } else if layout.size_of(ret) > 16 && homogeneous_members(layout, ret)?.is_none() {
+        ReturnLocation::Memory
+    } else {
+        let mut ints: &[PReg] = &INT_ARGUMENT_REGS[..2];
+        let mut floats: &[PReg] = &FLOAT_ARGUMENT_REGS[..4];
+        match assign(layout, ret, &mut ints, &mut floats)? {
+            Some(pieces) => ReturnLocation::Regs(pieces),
+            None => return Err(unsupported(format!("returning values of type {}", ret))),
+        }
+    };
+
+    let mut ints: &[PReg] = &INT_ARGUMENT_REGS;
+    let mut floats: &[PReg] = &FLOAT_ARGUMENT_REGS;
+    let mut stack_size: u64 = 0;
+    let mut on_stack = |size: u64, align: u64| {
+        let offset = stack_size.next_multiple_of(align.clamp(8, 16));
+        stack_size = offset + size.next_multiple_of(8);
+        ArgLocation::Stack { offset, size }
+    };
+
+    let mut locations = Vec::new();
+    for arg in args {
+        let size = layout.size_of(arg);
+        if size == 0 {
+            locations.push(ArgLocation::Ignore);
+            continue;
+        }
+        if is_composite(arg) && size > 16 && homogeneous_members(layout, arg)?.is_none() {
+            let pointer = Type::Pointer(Box::new(arg.clone()));
+            let location = match assign(layout, &pointer, &mut ints, &mut floats)? {
+                Some(pieces) => ArgLocation::Regs(pieces),
+                None => on_stack(8, 8),
+            };
+            locations.push(ArgLocation::Reference(Box::new(location)));
+            continue;
+        }
+        match assign(layout, arg, &mut ints, &mut floats)? {
+            Some(pieces) => locations.push(ArgLocation::Regs(pieces)),
+            None => locations.push(on_stack(size, layout.align_of(arg))),
+        }
+    }
+
+    Ok(CallAbi {
+        args: locations,
+        ret,
+        stack_size: stack_size.next_multiple_of(16),
+    })
+}
@@ -0,0 +1,245 @@
+use crate::emit::asm::frame::{self as shared, FrameOptions};
+use crate::emit::asm::aarch64::inst::{mov_imm, AluOp, Base, Inst, MachineFunction, Mem, Operand, PReg, RegClass, Size, CALLEE_SAVED};
+
+/// Register the frame uses to reach addresses out of range of an immediate
+/// offset. The allocator never hands it out.
+pub const FRAME_SCRATCH: PReg = PReg::X15;
+
+/// The stack frame of one function.
+///
+/// With a frame pointer the frame looks like this, from high to low addresses:
+///
+/// ```text
+/// stack arguments
+/// saved x29 and x30        <- x29
+/// saved callee-saved registers
+/// stack slots
+/// outgoing call arguments  <- sp
+/// ```
+///
+/// Without one, `x29` is left untouched, `x30` is saved with the callee-saved
+/// registers if the function makes calls, and everything is addressed from
+/// `sp`. Registers are saved in pairs, so `sp` is always a multiple of 16.
+/// Functions with dynamically sized stack allocations always keep the frame
+/// pointer, since those move `sp` below the outgoing argument area, and
+/// address their slots from it.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// Callee-saved registers the function writes, pushed in pairs in this order.
+    pub saved: Vec<PReg>,
+    /// Bytes reserved below the pushed registers.
+    pub size: u64,
+    pub frame_pointer: bool,
+    /// Whether `sp` may have moved by the time the function returns.
+    dynamic: bool,
+    outgoing_args: u64,
+    /// Offset of each stack slot from `x29` when `sp` moves, or from `sp` otherwise.
+    offsets: Vec<i64>,
+}
+
+/// Splits registers into the pairs they are pushed in, general purpose
+/// registers first. A register without a partner of its class is pushed alone.
+fn pairs(saved: &[PReg]) -> Vec<(PReg, Option<PReg>)> {
+    let mut pairs = Vec::new();
+    for class in [RegClass::Int, RegClass::Float] {
+        let regs = saved.iter().filter(|reg| reg.class() == class).copied().collect::<Vec<_>>();
+        pairs.extend(regs.chunks(2).map(|pair| (pair[0], pair.get(1).copied())));
+    }
+    pairs
+}
+
+impl Frame {
+    /// Computes the frame of `mf` from the registers it writes, its stack
+    /// slots and the space its calls need for stack arguments.
+    pub fn new(mf: &mut MachineFunction, options: FrameOptions) -> Self {
+        let mut saved = shared::written_callee_saved(mf, &CALLEE_SAVED);
+        let frame_pointer = !options.omit_frame_pointer || mf.dynamic_stack;
+        if shared::makes_calls(mf) && !frame_pointer {
+            // calls overwrite the return address
+            saved.push(PReg::X30);
+            saved.sort();
+        }
+
+        let (offsets, end) = shared::slot_offsets(mf);
+        let size = end.next_multiple_of(16);
+        let pushed = 16 * pairs(&saved).len() as u64;
+
+        let offsets = offsets.into_iter().map(|offset| if mf.dynamic_stack {
+            offset as i64 - (size + pushed) as i64
+        } else {
+            offset as i64
+        }).collect();
+
+        Self { saved, size, frame_pointer, dynamic: mf.dynamic_stack, outgoing_args: mf.outgoing_args, offsets }
+    }
+
+    /// Returns the bytes the callee-saved registers take, below the frame record.
+    fn pushed(&self) -> u64 {
+        16 * pairs(&self.saved).len() as u64
+    }
+
+    /// Returns the address of a stack slot.
+    pub fn slot_address(&self, slot: u32, offset: i64) -> Mem {
+        let base = if self.dynamic { PReg::X29 } else { PReg::Sp };
+        Mem::base(base, self.offsets[slot as usize] + offset)
+    }
+
+    /// Returns the address of the stack arguments passed by the caller, which
+    /// start where `sp` was at the call.
+    pub fn incoming_args_address(&self, offset: i64) -> Mem {
+        if self.frame_pointer {
+            Mem::base(PReg::X29, 16 + offset)
+        } else {
+            Mem::base(PReg::Sp, (self.size + self.pushed()) as i64 + offset)
+        }
+    }
+
+    /// Moves `sp` by `bytes`, through the scratch register if that does not fit an immediate.
+    fn adjust_sp(op: AluOp, bytes: u64, insts: &mut Vec<Inst>) {
+        if bytes < 4096 {
+            insts.push(Inst::Alu { op, size: Size::Double, dst: PReg::Sp.into(), lhs: PReg::Sp.into(), rhs: Operand::Imm(bytes as i64) });
+        } else {
+            insts.extend(mov_imm(FRAME_SCRATCH.into(), Size::Double, bytes as i64));
+            insts.push(Inst::Alu { op, size: Size::Double, dst: PReg::Sp.into(), lhs: PReg::Sp.into(), rhs: FRAME_SCRATCH.into() });
+        }
+    }
+
+    pub fn prologue(&self) -> Vec<Inst> {
+        let mut insts = Vec::new();
+        if self.frame_pointer {
+            insts.push(Inst::Push { first: PReg::X29, second: Some(PReg::X30) });
+            insts.push(Inst::Mov { size: Size::Double, dst: PReg::X29.into(), src: PReg::Sp.into() });
+        }
+        insts.extend(pairs(&self.saved).into_iter().map(|(first, second)| Inst::Push { first, second }));
+        if self.size > 0 {
+            Self::adjust_sp(AluOp::Sub, self.size, &mut insts);
+        }
+        insts
+    }
+
+    /// Returns the instructions that undo the prologue, to be placed before each `ret`.
+    pub fn epilogue(&self) -> Vec<Inst> {
+        let mut insts = Vec::new();
+        if self.dynamic {
+            let addr = Mem::base(PReg::X29, -(self.pushed() as i64));
+            insts.push(Inst::Lea { dst: PReg::Sp.into(), addr });
+        } else if self.size > 0 {
+            Self::adjust_sp(AluOp::Add, self.size, &mut insts);
+        }
+        insts.extend(pairs(&self.saved).into_iter().rev().map(|(first, second)| Inst::Pop { first, second }));
+        if self.frame_pointer {
+            insts.push(Inst::Pop { first: PReg::X29, second: Some(PReg::X30) });
+        }
+        insts
+    }
+
+    /// Resolves the stack slots of `mf` to frame addresses, rewrites accesses
+    /// out of reach of their offsets and wraps the body in the prologue and
+    /// epilogue.
+    pub fn apply(&self, mf: &mut MachineFunction) {
+        shared::insert_prologue_and_epilogues(mf, self.prologue(), &self.epilogue(), |mut inst, out| {
+            inst.visit_mems(&mut |mem: &mut Mem| {
+                match mem.base {
+                    Base::Slot(slot) => *mem = self.slot_address(slot, mem.offset),
+                    Base::IncomingArgs => *mem = self.incoming_args_address(mem.offset),
+                    Base::ArgsEnd => *mem = Mem::base(PReg::Sp, mem.offset + self.outgoing_args as i64),
+                    Base::Reg(_) => {}
+                }
+            });
+            legalize(inst, out);
+        });
+    }
+}
+
+/// Appends `inst` to `out`, first computing its address in the scratch
+/// register if its offset does not fit the instruction.
+fn legalize(mut inst: Inst, out: &mut Vec<Inst>) {
+    let fits = match &inst {
+        Inst::Load { size, addr, .. } | Inst::Store { size, addr, .. } => addr.fits(*size),
+        Inst::Lea { addr, .. } => addr.offset.unsigned_abs() < 4096,
+        _ => true,
+    };
+    if fits {
+        out.push(inst);
+        return;
+    }
+    inst.visit_mems(&mut |mem: &mut Mem| {
+        let Base::Reg(base) = mem.base else {
+            unreachable!("frame addresses are resolved before they are legalized");
+        };
+        out.extend(mov_imm(FRAME_SCRATCH.into(), Size::Double, mem.offset));
+        out.push(Inst::Alu { op: AluOp::Add, size: Size::Double, dst: FRAME_SCRATCH.into(), lhs: base, rhs: FRAME_SCRATCH.into() });
+        *mem = Mem::base(FRAME_SCRATCH, 0);
+    });
+    out.push(inst);
+}
+
+/// Lays out the stack frame of `mf` and inserts its prologue and epilogues.
+pub fn lay_out(mf: &mut MachineFunction, options: FrameOptions) -> Frame {
+    let frame = Frame::new(mf, options);
+    frame.apply(mf);
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emit::asm::aarch64::inst::{CallTarget, MachineBlock};
+
+    /// A function that writes two callee-saved integer registers and one
+    /// vector register, makes a call and stores to a slot out of reach of
+    /// an immediate offset.
+    fn function() -> MachineFunction {
+        let mut mf = MachineFunction::new("f");
+        mf.new_slot(40000, 8);
+        let far = mf.new_slot(8, 8);
+        mf.blocks.push(MachineBlock::new("f".to_string(), None));
+        mf.blocks[0].insts = vec![
+            Inst::Mov { size: Size::Double, dst: PReg::X19.into(), src: PReg::X0.into() },
+            Inst::Mov { size: Size::Double, dst: PReg::X20.into(), src: PReg::X1.into() },
+            Inst::FNeg { size: Size::Double, dst: PReg::V8.into(), src: PReg::V0.into() },
+            Inst::Call { target: CallTarget::Symbol("g".to_string()), args: Vec::new() },
+            Inst::Store { size: Size::Double, src: PReg::X19.into(), addr: Mem::slot(far, 0) },
+            Inst::Ret,
+        ];
+        mf
+    }
+
+    #[test]
+    fn saves_registers_in_pairs_below_the_frame_record() {
+        let mut mf = function();
+        let frame = lay_out(&mut mf, FrameOptions::default());
+        assert_eq!(frame.saved, [PReg::X19, PReg::X20, PReg::V8]);
+        assert_eq!(frame.size, 40016);
+
+        let insts = &mf.blocks[0].insts;
+        assert_eq!(insts[..4], [
+            Inst::Push { first: PReg::X29, second: Some(PReg::X30) },
+            Inst::Mov { size: Size::Double, dst: PReg::X29.into(), src: PReg::Sp.into() },
+            Inst::Push { first: PReg::X19, second: Some(PReg::X20) },
+            Inst::Push { first: PReg::V8, second: None },
+        ]);
+        // the frame is too big for an immediate, and so is the offset of the far slot
+        assert!(insts.contains(&Inst::Alu { op: AluOp::Sub, size: Size::Double, dst: PReg::Sp.into(), lhs: PReg::Sp.into(), rhs: FRAME_SCRATCH.into() }));
+        assert!(insts.contains(&Inst::Store { size: Size::Double, src: PReg::X19.into(), addr: Mem::base(FRAME_SCRATCH, 0) }));
+        assert_eq!(insts[insts.len() - 4..], [
+            Inst::Pop { first: PReg::V8, second: None },
+            Inst::Pop { first: PReg::X19, second: Some(PReg::X20) },
+            Inst::Pop { first: PReg::X29, second: Some(PReg::X30) },
+            Inst::Ret,
+        ]);
+    }
+
+    #[test]
+    fn saves_the_link_register_without_a_frame_pointer() {
+        let mut mf = function();
+        let frame = lay_out(&mut mf, FrameOptions { omit_frame_pointer: true });
+        assert!(!frame.frame_pointer);
+        assert_eq!(frame.saved, [PReg::X19, PReg::X20, PReg::X30, PReg::V8]);
+        assert_eq!(mf.blocks[0].insts[..3], [
+            Inst::Push { first: PReg::X19, second: Some(PReg::X20) },
+            Inst::Push { first: PReg::X30, second: None },
+            Inst::Push { first: PReg::V8, second: None },
+        ]);
+    }
+}
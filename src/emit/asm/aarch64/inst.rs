@@ -0,0 +1,702 @@
+use crate::emit::asm::machine::{self, MachineInst, RegCopy};
+pub use crate::emit::asm::machine::{RegClass, RegUse, StackSlot, VReg};
+use std::fmt::{Display, Formatter};
+
+/// A physical AArch64 register. `Sp` is the stack pointer, which shares its
+/// encoding with the zero register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PReg {
+    X0, X1, X2, X3, X4, X5, X6, X7, X8, X9, X10, X11, X12, X13, X14, X15,
+    X16, X17, X18, X19, X20, X21, X22, X23, X24, X25, X26, X27, X28, X29, X30, Sp,
+    V0, V1, V2, V3, V4, V5, V6, V7, V8, V9, V10, V11, V12, V13, V14, V15,
+    V16, V17, V18, V19, V20, V21, V22, V23, V24, V25, V26, V27, V28, V29, V30, V31,
+}
+
+/// Registers a call may overwrite. Only the low 64 bits of `v8`-`v15` are
+/// preserved, which is all the backend uses of them.
+pub const CALLER_SAVED: [PReg; 43] = [
+    PReg::X0, PReg::X1, PReg::X2, PReg::X3, PReg::X4, PReg::X5, PReg::X6, PReg::X7, PReg::X8,
+    PReg::X9, PReg::X10, PReg::X11, PReg::X12, PReg::X13, PReg::X14, PReg::X15, PReg::X16, PReg::X17, PReg::X30,
+    PReg::V0, PReg::V1, PReg::V2, PReg::V3, PReg::V4, PReg::V5, PReg::V6, PReg::V7,
+    PReg::V16, PReg::V17, PReg::V18, PReg::V19, PReg::V20, PReg::V21, PReg::V22, PReg::V23,
+    PReg::V24, PReg::V25, PReg::V26, PReg::V27, PReg::V28, PReg::V29, PReg::V30, PReg::V31,
+];
+/// Registers a function must restore before returning, apart from the frame record.
+pub const CALLEE_SAVED: [PReg; 18] = [
+    PReg::X19, PReg::X20, PReg::X21, PReg::X22, PReg::X23, PReg::X24, PReg::X25, PReg::X26, PReg::X27, PReg::X28,
+    PReg::V8, PReg::V9, PReg::V10, PReg::V11, PReg::V12, PReg::V13, PReg::V14, PReg::V15,
+];
+
+pub type Reg = machine::Reg<PReg>;
+pub type MachineBlock = machine::MachineBlock<Inst>;
+pub type MachineFunction = machine::MachineFunction<Inst>;
+
+/// Access width. Arithmetic only comes in `Word` and `Double`, narrower
+/// values are computed in 32-bit registers whose upper bits are undefined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Size {
+    Byte,
+    Half,
+    Word,
+    Double,
+}
+
+/// The base of a memory operand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Base {
+    Reg(Reg),
+    /// A frame slot, resolved to a frame-relative address once the frame is laid out.
+    Slot(u32),
+    /// The lowest address above the outgoing argument area, which is where
+    /// dynamically sized stack allocations start.
+    ArgsEnd,
+    /// The stack arguments the caller passed, resolved to a frame-relative address.
+    IncomingArgs,
+}
+
+/// `[base, #offset]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mem {
+    pub base: Base,
+    pub offset: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operand {
+    Reg(Reg),
+    Imm(i64),
+}
+
+/// Condition codes, as used by `b.cond` and `cset`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cond {
+    Eq, Ne, Hs, Lo, Mi, Pl, Vs, Vc, Hi, Ls, Ge, Lt, Gt, Le,
+}
+
+/// Operations of the form `op dst, lhs, rhs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AluOp {
+    Add,
+    Sub,
+    And,
+    Orr,
+    Eor,
+    Mul,
+    Sdiv,
+    Udiv,
+    Lsl,
+    Lsr,
+    Asr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovWideOp {
+    /// Sets the register to the shifted immediate.
+    Movz,
+    /// Sets the register to the inverse of the shifted immediate.
+    Movn,
+    /// Replaces 16 bits of the register, keeping the rest.
+    Movk,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// How a symbol is addressed by an `adrp` and the instruction completing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolReloc {
+    /// The symbol itself.
+    Abs,
+    /// The GOT entry holding the address of the symbol.
+    Got,
+    /// The GOT entry holding the offset of a thread-local symbol from the thread pointer.
+    GotTprel,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallTarget {
+    Symbol(String),
+    Indirect(Reg),
+}
+
+/// A single AArch64 machine instruction. Floating point instructions use
+/// `Size::Word` for single and `Size::Double` for double precision.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inst {
+    /// `mov` between general purpose registers, `fmov` between floating point ones.
+    Mov { size: Size, dst: Reg, src: Reg },
+    /// A 16-bit immediate shifted left by `shift` bits.
+    MovWide { op: MovWideOp, size: Size, dst: Reg, imm: u16, shift: u8 },
+    /// Immediates are only allowed for `add`, `sub` and shifts, and for
+    /// logical operations when they are valid bitmask immediates.
+    Alu { op: AluOp, size: Size, dst: Reg, lhs: Reg, rhs: Operand },
+    Neg { size: Size, dst: Reg, src: Reg },
+    /// Sign- or zero-extends the low `from` bits of `src` to `to` bits.
+    Extend { signed: bool, from: Size, to: Size, dst: Reg, src: Reg },
+    Cmp { size: Size, lhs: Reg, rhs: Operand },
+    Tst { size: Size, lhs: Reg, rhs: Operand },
+    Cset { cond: Cond, dst: Reg },
+    /// Computes the address of a memory operand.
+    Lea { dst: Reg, addr: Mem },
+    /// Loads `size` bytes, zero-extended unless `signed` is set.
+    Load { size: Size, signed: bool, dst: Reg, addr: Mem },
+    Store { size: Size, src: Reg, addr: Mem },
+    /// The address of the 4 KiB page holding a symbol or its GOT entry.
+    Adrp { dst: Reg, symbol: String, reloc: SymbolReloc },
+    /// Adds the low 12 bits of the address of a symbol to its page.
+    AddLo12 { dst: Reg, src: Reg, symbol: String },
+    /// Loads a GOT entry from its page.
+    LoadLo12 { dst: Reg, base: Reg, symbol: String, reloc: SymbolReloc },
+    /// Reads the thread pointer, `tpidr_el0`.
+    ThreadPointer { dst: Reg },
+    B { target: String },
+    BCond { cond: Cond, target: String },
+    /// `args` are the registers carrying arguments, which the call reads.
+    Call { target: CallTarget, args: Vec<PReg> },
+    Ret,
+    Brk,
+    /// Pushes one or two registers of the same class, keeping `sp` 16-byte aligned.
+    Push { first: PReg, second: Option<PReg> },
+    /// Pops what a `Push` with the same registers pushed.
+    Pop { first: PReg, second: Option<PReg> },
+    FloatAlu { op: FloatOp, size: Size, dst: Reg, lhs: Reg, rhs: Reg },
+    FNeg { size: Size, dst: Reg, src: Reg },
+    FCmp { size: Size, lhs: Reg, rhs: Reg },
+    /// `fmov` between a general purpose and a floating point register, keeping the bits.
+    FMovBits { size: Size, dst: Reg, src: Reg },
+    /// `scvtf`/`ucvtf` of an integer of `int_size` to a float of `size`.
+    IntToFloat { signed: bool, int_size: Size, size: Size, dst: Reg, src: Reg },
+    /// `fcvtzs`/`fcvtzu` of a float of `size` to an integer of `int_size`, rounding toward zero.
+    FloatToInt { signed: bool, int_size: Size, size: Size, dst: Reg, src: Reg },
+    /// `fcvt` to a float of `size`.
+    FCvt { size: Size, dst: Reg, src: Reg },
+}
+
+impl PReg {
+    pub const XS: [PReg; 32] = [
+        PReg::X0, PReg::X1, PReg::X2, PReg::X3, PReg::X4, PReg::X5, PReg::X6, PReg::X7,
+        PReg::X8, PReg::X9, PReg::X10, PReg::X11, PReg::X12, PReg::X13, PReg::X14, PReg::X15,
+        PReg::X16, PReg::X17, PReg::X18, PReg::X19, PReg::X20, PReg::X21, PReg::X22, PReg::X23,
+        PReg::X24, PReg::X25, PReg::X26, PReg::X27, PReg::X28, PReg::X29, PReg::X30, PReg::Sp,
+    ];
+
+    pub const VS: [PReg; 32] = [
+        PReg::V0, PReg::V1, PReg::V2, PReg::V3, PReg::V4, PReg::V5, PReg::V6, PReg::V7,
+        PReg::V8, PReg::V9, PReg::V10, PReg::V11, PReg::V12, PReg::V13, PReg::V14, PReg::V15,
+        PReg::V16, PReg::V17, PReg::V18, PReg::V19, PReg::V20, PReg::V21, PReg::V22, PReg::V23,
+        PReg::V24, PReg::V25, PReg::V26, PReg::V27, PReg::V28, PReg::V29, PReg::V30, PReg::V31,
+    ];
+
+    /// Returns the 5-bit hardware number of the register.
+    pub fn encoding(self) -> u8 {
+        (self as u8) & 0x1f
+    }
+
+    pub fn class(self) -> RegClass {
+        if self >= PReg::V0 {
+            RegClass::Float
+        } else {
+            RegClass::Int
+        }
+    }
+
+    /// Returns the assembler name of the register when accessed with the given width.
+    pub fn name(self, size: Size) -> String {
+        match (self.class(), size) {
+            (RegClass::Int, _) if self == PReg::Sp => if size == Size::Double { "sp" } else { "wsp" }.to_string(),
+            (RegClass::Int, Size::Double) => format!("x{}", self.encoding()),
+            (RegClass::Int, _) => format!("w{}", self.encoding()),
+            (RegClass::Float, Size::Byte) => format!("b{}", self.encoding()),
+            (RegClass::Float, Size::Half) => format!("h{}", self.encoding()),
+            (RegClass::Float, Size::Word) => format!("s{}", self.encoding()),
+            (RegClass::Float, Size::Double) => format!("d{}", self.encoding()),
+        }
+    }
+}
+
+impl Size {
+    pub fn from_bytes(bytes: u64) -> Option<Size> {
+        match bytes {
+            1 => Some(Size::Byte),
+            2 => Some(Size::Half),
+            4 => Some(Size::Word),
+            8 => Some(Size::Double),
+            _ => None,
+        }
+    }
+
+    pub fn bytes(self) -> u64 {
+        match self {
+            Size::Byte => 1,
+            Size::Half => 2,
+            Size::Word => 4,
+            Size::Double => 8,
+        }
+    }
+
+    /// Returns the width of the register arithmetic on values of this size is done in.
+    pub fn register(self) -> Size {
+        self.max(Size::Word)
+    }
+}
+
+impl From<PReg> for Reg {
+    fn from(reg: PReg) -> Self {
+        Reg::Phys(reg)
+    }
+}
+
+impl From<Reg> for Operand {
+    fn from(reg: Reg) -> Self {
+        Operand::Reg(reg)
+    }
+}
+
+impl From<PReg> for Operand {
+    fn from(reg: PReg) -> Self {
+        Operand::Reg(Reg::Phys(reg))
+    }
+}
+
+impl From<VReg> for Operand {
+    fn from(reg: VReg) -> Self {
+        Operand::Reg(Reg::Virt(reg))
+    }
+}
+
+impl Mem {
+    /// `[reg, #offset]`
+    pub fn base(reg: impl Into<Reg>, offset: i64) -> Self {
+        Self { base: Base::Reg(reg.into()), offset }
+    }
+
+    pub fn slot(slot: u32, offset: i64) -> Self {
+        Self { base: Base::Slot(slot), offset }
+    }
+
+    /// Returns whether a load or store of `size` bytes can reach this
+    /// address directly, with a scaled unsigned or an unscaled signed offset.
+    pub fn fits(&self, size: Size) -> bool {
+        let bytes = size.bytes() as i64;
+        (-256..256).contains(&self.offset) || (self.offset >= 0 && self.offset % bytes == 0 && self.offset / bytes < 4096)
+    }
+}
+
+impl Cond {
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Cond::Eq => "eq",
+            Cond::Ne => "ne",
+            Cond::Hs => "hs",
+            Cond::Lo => "lo",
+            Cond::Mi => "mi",
+            Cond::Pl => "pl",
+            Cond::Vs => "vs",
+            Cond::Vc => "vc",
+            Cond::Hi => "hi",
+            Cond::Ls => "ls",
+            Cond::Ge => "ge",
+            Cond::Lt => "lt",
+            Cond::Gt => "gt",
+            Cond::Le => "le",
+        }
+    }
+
+    /// Returns the condition that holds exactly when `self` does not.
+    pub fn invert(self) -> Cond {
+        match self {
+            Cond::Eq => Cond::Ne,
+            Cond::Ne => Cond::Eq,
+            Cond::Hs => Cond::Lo,
+            Cond::Lo => Cond::Hs,
+            Cond::Mi => Cond::Pl,
+            Cond::Pl => Cond::Mi,
+            Cond::Vs => Cond::Vc,
+            Cond::Vc => Cond::Vs,
+            Cond::Hi => Cond::Ls,
+            Cond::Ls => Cond::Hi,
+            Cond::Ge => Cond::Lt,
+            Cond::Lt => Cond::Ge,
+            Cond::Gt => Cond::Le,
+            Cond::Le => Cond::Gt,
+        }
+    }
+}
+
+/// Returns the `movz`/`movn` and `movk` instructions that put `value`,
+/// truncated to `size`, into `dst`.
+pub fn mov_imm(dst: Reg, size: Size, value: i64) -> Vec<Inst> {
+    let size = size.register();
+    let halves = if size == Size::Double { 4 } else { 2 };
+    let chunk = |i: u8| (value as u64 >> (16 * i)) as u16;
+    // start from all ones when that leaves fewer halves to patch
+    let ones = (0..halves).filter(|i| chunk(*i) == 0xffff).count();
+    let zeros = (0..halves).filter(|i| chunk(*i) == 0).count();
+    let (op, fill) = if ones > zeros { (MovWideOp::Movn, 0xffff) } else { (MovWideOp::Movz, 0) };
+
+    let first = (0..halves).find(|i| chunk(*i) != fill).unwrap_or(0);
+    let imm = if op == MovWideOp::Movn { !chunk(first) } else { chunk(first) };
+    let mut insts = vec![Inst::MovWide { op, size, dst, imm, shift: 16 * first }];
+    for i in first + 1..halves {
+        if chunk(i) != fill {
+            insts.push(Inst::MovWide { op: MovWideOp::Movk, size, dst, imm: chunk(i), shift: 16 * i });
+        }
+    }
+    insts
+}
+
+impl Operand {
+    fn visit_regs(&mut self, access: RegUse, f: &mut dyn FnMut(&mut Reg, RegUse)) {
+        if let Operand::Reg(reg) = self {
+            f(reg, access);
+        }
+    }
+}
+
+impl Mem {
+    fn visit_regs(&mut self, f: &mut dyn FnMut(&mut Reg, RegUse)) {
+        if let Base::Reg(reg) = &mut self.base {
+            f(reg, RegUse::Use);
+        }
+    }
+}
+
+impl MachineInst for Inst {
+    type PReg = PReg;
+
+    fn visit_regs(&mut self, f: &mut dyn FnMut(&mut Reg, RegUse)) {
+        match self {
+            Inst::Mov { dst, src, .. } | Inst::Neg { dst, src, .. } | Inst::Extend { dst, src, .. } | Inst::FNeg { dst, src, .. }
+            | Inst::AddLo12 { dst, src, .. } | Inst::LoadLo12 { dst, base: src, .. } | Inst::FMovBits { dst, src, .. }
+            | Inst::IntToFloat { dst, src, .. } | Inst::FloatToInt { dst, src, .. } | Inst::FCvt { dst, src, .. } => {
+                f(src, RegUse::Use);
+                f(dst, RegUse::Def);
+            }
+            Inst::MovWide { op, dst, .. } => f(dst, if *op == MovWideOp::Movk { RegUse::UseDef } else { RegUse::Def }),
+            Inst::Alu { dst, lhs, rhs, .. } => {
+                f(lhs, RegUse::Use);
+                rhs.visit_regs(RegUse::Use, f);
+                f(dst, RegUse::Def);
+            }
+            Inst::FloatAlu { dst, lhs, rhs, .. } => {
+                f(lhs, RegUse::Use);
+                f(rhs, RegUse::Use);
+                f(dst, RegUse::Def);
+            }
+            Inst::Cmp { lhs, rhs, .. } | Inst::Tst { lhs, rhs, .. } => {
+                f(lhs, RegUse::Use);
+                rhs.visit_regs(RegUse::Use, f);
+            }
+            Inst::FCmp { lhs, rhs, .. } => {
+                f(lhs, RegUse::Use);
+                f(rhs, RegUse::Use);
+            }
+            Inst::Lea { dst, addr } | Inst::Load { dst, addr, .. } => {
+                addr.visit_regs(f);
+                f(dst, RegUse::Def);
+            }
+            Inst::Store { src, addr, .. } => {
+                f(src, RegUse::Use);
+                addr.visit_regs(f);
+            }
+            Inst::Cset { dst, .. } | Inst::Adrp { dst, .. } | Inst::ThreadPointer { dst } => f(dst, RegUse::Def),
+            Inst::Call { target: CallTarget::Indirect(target), .. } => f(target, RegUse::Use),
+            Inst::B { .. } | Inst::BCond { .. } | Inst::Call { .. } | Inst::Ret | Inst::Brk | Inst::Push { .. } | Inst::Pop { .. } => {}
+        }
+    }
+
+    fn visit_fixed_regs(&self, f: &mut dyn FnMut(PReg, RegUse)) {
+        if let Inst::Call { args, .. } = self {
+            args.iter().for_each(|arg| f(*arg, RegUse::Use));
+        }
+    }
+
+    fn clobbers(&self) -> &'static [PReg] {
+        match self {
+            Inst::Call { .. } => &CALLER_SAVED,
+            _ => &[],
+        }
+    }
+
+    fn is_terminator(&self) -> bool {
+        matches!(self, Inst::B { .. } | Inst::Ret | Inst::Brk)
+    }
+
+    fn is_return(&self) -> bool {
+        *self == Inst::Ret
+    }
+
+    fn is_call(&self) -> bool {
+        matches!(self, Inst::Call { .. })
+    }
+
+    fn branch_target(&self) -> Option<&str> {
+        match self {
+            Inst::B { target } | Inst::BCond { target, .. } => Some(target),
+            _ => None,
+        }
+    }
+
+    fn as_copy(&self) -> Option<RegCopy<PReg>> {
+        match self {
+            Inst::Mov { dst, src, .. } => Some((*dst, *src)),
+            _ => None,
+        }
+    }
+}
+
+impl Inst {
+    /// Calls `f` on every memory operand of the instruction.
+    pub fn visit_mems(&mut self, f: &mut dyn FnMut(&mut Mem)) {
+        if let Inst::Lea { addr, .. } | Inst::Load { addr, .. } | Inst::Store { addr, .. } = self {
+            f(addr);
+        }
+    }
+}
+
+fn fmt_reg(f: &mut Formatter<'_>, reg: &Reg, size: Size) -> std::fmt::Result {
+    match reg {
+        Reg::Phys(reg) => write!(f, "{}", reg.name(size)),
+        Reg::Virt(reg) => write!(f, "%v{}", reg.index),
+    }
+}
+
+fn fmt_operand(f: &mut Formatter<'_>, op: &Operand, size: Size) -> std::fmt::Result {
+    match op {
+        Operand::Reg(reg) => fmt_reg(f, reg, size),
+        Operand::Imm(imm) => write!(f, "#{}", imm),
+    }
+}
+
+fn fmt_address(f: &mut Formatter<'_>, mem: &Mem) -> std::fmt::Result {
+    write!(f, "[")?;
+    match &mem.base {
+        Base::Reg(reg) => fmt_reg(f, reg, Size::Double)?,
+        Base::Slot(slot) => write!(f, "slot{}", slot)?,
+        Base::ArgsEnd => write!(f, "args_end")?,
+        Base::IncomingArgs => write!(f, "incoming_args")?,
+    }
+    if mem.offset != 0 {
+        write!(f, ", #{}", mem.offset)?;
+    }
+    write!(f, "]")
+}
+
+/// Writes `mnemonic dst, lhs, rhs` with all registers of the same width.
+fn fmt_three(f: &mut Formatter<'_>, mnemonic: &str, size: Size, dst: &Reg, lhs: &Reg, rhs: &Operand) -> std::fmt::Result {
+    write!(f, "{} ", mnemonic)?;
+    fmt_reg(f, dst, size)?;
+    write!(f, ", ")?;
+    fmt_reg(f, lhs, size)?;
+    write!(f, ", ")?;
+    fmt_operand(f, rhs, size)
+}
+
+/// Writes `mnemonic dst, src` with both registers of the same width.
+fn fmt_two(f: &mut Formatter<'_>, mnemonic: &str, size: Size, dst: &Reg, src: &Operand) -> std::fmt::Result {
+    write!(f, "{} ", mnemonic)?;
+    fmt_reg(f, dst, size)?;
+    write!(f, ", ")?;
+    fmt_operand(f, src, size)
+}
+
+fn reloc_prefix(reloc: SymbolReloc, lo12: bool) -> &'static str {
+    match (reloc, lo12) {
+        (SymbolReloc::Abs, false) => "",
+        (SymbolReloc::Abs, true) => ":lo12:",
+        (SymbolReloc::Got, false) => ":got:",
+        (SymbolReloc::Got, true) => ":got_lo12:",
+        (SymbolReloc::GotTprel, false) => ":gottprel:",
+        (SymbolReloc::GotTprel, true) => ":gottprel_lo12:",
+    }
+}
+
+/// Returns whether a register is a general purpose one, or a virtual one of that class.
+fn is_int(reg: &Reg) -> bool {
+    match reg {
+        Reg::Phys(reg) => reg.class() == RegClass::Int,
+        Reg::Virt(reg) => reg.class == RegClass::Int,
+    }
+}
+
+impl Display for Inst {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Inst::Mov { size, dst, src } => {
+                let mnemonic = if is_int(dst) { "mov" } else { "fmov" };
+                fmt_two(f, mnemonic, size.register(), dst, &Operand::Reg(*src))
+            }
+            Inst::MovWide { op, size, dst, imm, shift } => {
+                let mnemonic = match op {
+                    MovWideOp::Movz => "movz",
+                    MovWideOp::Movn => "movn",
+                    MovWideOp::Movk => "movk",
+                };
+                write!(f, "{} ", mnemonic)?;
+                fmt_reg(f, dst, *size)?;
+                write!(f, ", #{:#x}", imm)?;
+                if *shift > 0 {
+                    write!(f, ", lsl #{}", shift)?;
+                }
+                Ok(())
+            }
+            Inst::Alu { op, size, dst, lhs, rhs } => {
+                let mnemonic = match op {
+                    AluOp::Add => "add",
+                    AluOp::Sub => "sub",
+                    AluOp::And => "and",
+                    AluOp::Orr => "orr",
+                    AluOp::Eor => "eor",
+                    AluOp::Mul => "mul",
+                    AluOp::Sdiv => "sdiv",
+                    AluOp::Udiv => "udiv",
+                    AluOp::Lsl => "lsl",
+                    AluOp::Lsr => "lsr",
+                    AluOp::Asr => "asr",
+                };
+                fmt_three(f, mnemonic, *size, dst, lhs, rhs)
+            }
+            Inst::Neg { size, dst, src } => fmt_two(f, "neg", *size, dst, &Operand::Reg(*src)),
+            Inst::Extend { signed, from, to, dst, src } => {
+                match (signed, from) {
+                    (true, Size::Byte) => write!(f, "sxtb ")?,
+                    (true, Size::Half) => write!(f, "sxth ")?,
+                    (true, _) => write!(f, "sxtw ")?,
+                    // writing a 32-bit register zeroes the upper half
+                    (false, Size::Byte) => return fmt_two(f, "uxtb", Size::Word, dst, &Operand::Reg(*src)),
+                    (false, Size::Half) => return fmt_two(f, "uxth", Size::Word, dst, &Operand::Reg(*src)),
+                    (false, _) => return fmt_two(f, "mov", Size::Word, dst, &Operand::Reg(*src)),
+                }
+                fmt_reg(f, dst, *to)?;
+                write!(f, ", ")?;
+                fmt_reg(f, src, Size::Word)
+            }
+            Inst::Cmp { size, lhs, rhs } => fmt_two(f, "cmp", *size, lhs, rhs),
+            Inst::Tst { size, lhs, rhs } => fmt_two(f, "tst", *size, lhs, rhs),
+            Inst::Cset { cond, dst } => {
+                write!(f, "cset ")?;
+                fmt_reg(f, dst, Size::Word)?;
+                write!(f, ", {}", cond.suffix())
+            }
+            Inst::Lea { dst, addr } => {
+                let mnemonic = if addr.offset < 0 { "sub" } else { "add" };
+                write!(f, "{} ", mnemonic)?;
+                fmt_reg(f, dst, Size::Double)?;
+                write!(f, ", ")?;
+                match &addr.base {
+                    Base::Reg(reg) => fmt_reg(f, reg, Size::Double)?,
+                    Base::Slot(slot) => write!(f, "slot{}", slot)?,
+                    Base::ArgsEnd => write!(f, "args_end")?,
+                    Base::IncomingArgs => write!(f, "incoming_args")?,
+                }
+                write!(f, ", #{}", addr.offset.unsigned_abs())
+            }
+            Inst::Load { size, signed, dst, addr } => {
+                let scaled = addr.offset >= 0 && addr.offset % size.bytes() as i64 == 0;
+                let mnemonic = match (is_int(dst), size, signed) {
+                    (true, Size::Byte, false) => "ldrb",
+                    (true, Size::Byte, true) => "ldrsb",
+                    (true, Size::Half, false) => "ldrh",
+                    (true, Size::Half, true) => "ldrsh",
+                    (true, Size::Word, true) => "ldrsw",
+                    _ => "ldr",
+                };
+                let size = match (is_int(dst), signed) {
+                    (true, true) if *size == Size::Word => Size::Double,
+                    (true, _) => size.register(),
+                    (false, _) => *size,
+                };
+                // unscaled offsets need the `ldur` forms
+                write!(f, "{} ", if scaled { mnemonic.to_string() } else { mnemonic.replacen("ldr", "ldur", 1) })?;
+                fmt_reg(f, dst, size)?;
+                write!(f, ", ")?;
+                fmt_address(f, addr)
+            }
+            Inst::Store { size, src, addr } => {
+                let scaled = addr.offset >= 0 && addr.offset % size.bytes() as i64 == 0;
+                let mnemonic = match (is_int(src), size) {
+                    (true, Size::Byte) => "strb",
+                    (true, Size::Half) => "strh",
+                    _ => "str",
+                };
+                let size = if is_int(src) { size.register() } else { *size };
+                write!(f, "{} ", if scaled { mnemonic.to_string() } else { mnemonic.replacen("str", "stur", 1) })?;
+                fmt_reg(f, src, size)?;
+                write!(f, ", ")?;
+                fmt_address(f, addr)
+            }
+            Inst::Adrp { dst, symbol, reloc } => {
+                write!(f, "adrp ")?;
+                fmt_reg(f, dst, Size::Double)?;
+                write!(f, ", {}{}", reloc_prefix(*reloc, false), symbol)
+            }
+            Inst::AddLo12 { dst, src, symbol } => {
+                write!(f, "add ")?;
+                fmt_reg(f, dst, Size::Double)?;
+                write!(f, ", ")?;
+                fmt_reg(f, src, Size::Double)?;
+                write!(f, ", :lo12:{}", symbol)
+            }
+            Inst::LoadLo12 { dst, base, symbol, reloc } => {
+                write!(f, "ldr ")?;
+                fmt_reg(f, dst, Size::Double)?;
+                write!(f, ", [")?;
+                fmt_reg(f, base, Size::Double)?;
+                write!(f, ", {}{}]", reloc_prefix(*reloc, true), symbol)
+            }
+            Inst::ThreadPointer { dst } => {
+                write!(f, "mrs ")?;
+                fmt_reg(f, dst, Size::Double)?;
+                write!(f, ", tpidr_el0")
+            }
+            Inst::B { target } => write!(f, "b {}", target),
+            Inst::BCond { cond, target } => write!(f, "b.{} {}", cond.suffix(), target),
+            Inst::Call { target: CallTarget::Symbol(symbol), .. } => write!(f, "bl {}", symbol),
+            Inst::Call { target: CallTarget::Indirect(target), .. } => {
+                write!(f, "blr ")?;
+                fmt_reg(f, target, Size::Double)
+            }
+            Inst::Ret => write!(f, "ret"),
+            Inst::Brk => write!(f, "brk #0x3e8"),
+            Inst::Push { first, second: Some(second) } => write!(f, "stp {}, {}, [sp, #-16]!", first.name(Size::Double), second.name(Size::Double)),
+            Inst::Push { first, second: None } => write!(f, "str {}, [sp, #-16]!", first.name(Size::Double)),
+            Inst::Pop { first, second: Some(second) } => write!(f, "ldp {}, {}, [sp], #16", first.name(Size::Double), second.name(Size::Double)),
+            Inst::Pop { first, second: None } => write!(f, "ldr {}, [sp], #16", first.name(Size::Double)),
+            Inst::FloatAlu { op, size, dst, lhs, rhs } => {
+                let mnemonic = match op {
+                    FloatOp::Add => "fadd",
+                    FloatOp::Sub => "fsub",
+                    FloatOp::Mul => "fmul",
+                    FloatOp::Div => "fdiv",
+                };
+                fmt_three(f, mnemonic, *size, dst, lhs, &Operand::Reg(*rhs))
+            }
+            Inst::FNeg { size, dst, src } => fmt_two(f, "fneg", *size, dst, &Operand::Reg(*src)),
+            Inst::FCmp { size, lhs, rhs } => fmt_two(f, "fcmp", *size, lhs, &Operand::Reg(*rhs)),
+            Inst::FMovBits { size, dst, src } => fmt_two(f, "fmov", *size, dst, &Operand::Reg(*src)),
+            Inst::IntToFloat { signed, int_size, size, dst, src } => {
+                write!(f, "{} ", if *signed { "scvtf" } else { "ucvtf" })?;
+                fmt_reg(f, dst, *size)?;
+                write!(f, ", ")?;
+                fmt_reg(f, src, *int_size)
+            }
+            Inst::FloatToInt { signed, int_size, size, dst, src } => {
+                write!(f, "{} ", if *signed { "fcvtzs" } else { "fcvtzu" })?;
+                fmt_reg(f, dst, *int_size)?;
+                write!(f, ", ")?;
+                fmt_reg(f, src, *size)
+            }
+            Inst::FCvt { size, dst, src } => {
+                write!(f, "fcvt ")?;
+                fmt_reg(f, dst, *size)?;
+                write!(f, ", ")?;
+                fmt_reg(f, src, if *size == Size::Word { Size::Double } else { Size::Word })
+            }
+        }
+    }
+}
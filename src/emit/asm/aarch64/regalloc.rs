@@ -0,0 +1,57 @@
+use crate::emit::asm::regalloc::RegisterFile;
+use crate::emit::asm::aarch64::inst::{Inst, Mem, PReg, RegClass, Size};
+
+/// Integer registers handed out by the allocator, caller-saved ones first so
+/// that callee-saved registers are only used by values live across calls.
+/// `x15` is left free for the frame, `x16` and `x17` as spill scratch
+/// registers, and `x18` is reserved by the platform.
+const INT_ALLOCATABLE: [PReg; 25] = [
+    PReg::X0, PReg::X1, PReg::X2, PReg::X3, PReg::X4, PReg::X5, PReg::X6, PReg::X7,
+    PReg::X8, PReg::X9, PReg::X10, PReg::X11, PReg::X12, PReg::X13, PReg::X14,
+    PReg::X19, PReg::X20, PReg::X21, PReg::X22, PReg::X23, PReg::X24, PReg::X25, PReg::X26, PReg::X27, PReg::X28,
+];
+/// `v30` and `v31` are left free as spill scratch registers.
+const FLOAT_ALLOCATABLE: [PReg; 30] = [
+    PReg::V0, PReg::V1, PReg::V2, PReg::V3, PReg::V4, PReg::V5, PReg::V6, PReg::V7,
+    PReg::V16, PReg::V17, PReg::V18, PReg::V19, PReg::V20, PReg::V21, PReg::V22,
+    PReg::V23, PReg::V24, PReg::V25, PReg::V26, PReg::V27, PReg::V28, PReg::V29,
+    PReg::V8, PReg::V9, PReg::V10, PReg::V11, PReg::V12, PReg::V13, PReg::V14, PReg::V15,
+];
+/// Registers kept free to reload spilled values around a single instruction.
+const INT_SCRATCH: [PReg; 2] = [PReg::X16, PReg::X17];
+const FLOAT_SCRATCH: [PReg; 2] = [PReg::V30, PReg::V31];
+/// Registers the caller reads after `ret`.
+const RETURN_REGS: [PReg; 6] = [PReg::X0, PReg::X1, PReg::V0, PReg::V1, PReg::V2, PReg::V3];
+
+/// The registers of the AAPCS64 ABI.
+pub struct Registers;
+
+impl RegisterFile for Registers {
+    type Inst = Inst;
+
+    fn allocatable(class: RegClass) -> &'static [PReg] {
+        match class {
+            RegClass::Int => &INT_ALLOCATABLE,
+            RegClass::Float => &FLOAT_ALLOCATABLE,
+        }
+    }
+
+    fn scratch(class: RegClass) -> &'static [PReg] {
+        match class {
+            RegClass::Int => &INT_SCRATCH,
+            RegClass::Float => &FLOAT_SCRATCH,
+        }
+    }
+
+    fn return_regs() -> &'static [PReg] {
+        &RETURN_REGS
+    }
+
+    fn load(_class: RegClass, dst: PReg, slot: u32) -> Inst {
+        Inst::Load { size: Size::Double, signed: false, dst: dst.into(), addr: Mem::slot(slot, 0) }
+    }
+
+    fn store(_class: RegClass, src: PReg, slot: u32) -> Inst {
+        Inst::Store { size: Size::Double, src: src.into(), addr: Mem::slot(slot, 0) }
+    }
+}
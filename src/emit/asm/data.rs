@@ -0,0 +1,244 @@
+use crate::emit::asm::unsupported;
+use crate::ir::linkage::Linkage;
+use crate::ir::values::constant::{Constant, Scalar};
+use crate::ir::values::global::GlobalVariable;
+use crate::ir::values::value::Type;
+use crate::targets::layout::DataLayout;
+use std::io::{Error, Write};
+
+/// The section a global is placed in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataSection {
+    pub name: String,
+    pub writable: bool,
+    pub thread_local: bool,
+    /// Whether the section takes no space in the file, like `.bss`.
+    pub nobits: bool,
+}
+
+/// A piece of the contents of a global, in the order it is laid out.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Datum {
+    /// That many zero bytes.
+    Zero(u64),
+    /// An integer of 1, 2, 4 or 8 bytes.
+    Int(u64, i64),
+    F32(f32),
+    F64(f64),
+    Bytes(Vec<u8>),
+    /// The address of a symbol plus an offset, of the given size in bytes.
+    Symbol(u64, String, i64),
+}
+
+/// Returns the section of a global, picking `.bss`/`.tbss` for zero-filled
+/// mutable data.
+pub fn data_section(global: &GlobalVariable) -> DataSection {
+    let zero = is_zero_initialized(global);
+    if let Some(section) = global.get_section() {
+        return DataSection {
+            name: section.to_string(),
+            writable: !global.is_constant(),
+            thread_local: global.is_thread_local(),
+            nobits: zero && section.contains("bss"),
+        };
+    }
+    let (name, writable, nobits) = match (global.is_thread_local(), global.is_constant(), zero) {
+        (true, _, true) => (".tbss", true, true),
+        (true, _, false) => (".tdata", true, false),
+        (false, true, _) => (".rodata", false, false),
+        (false, false, true) => (".bss", true, true),
+        (false, false, false) => (".data", true, false),
+    };
+    DataSection { name: name.to_string(), writable, thread_local: global.is_thread_local(), nobits }
+}
+
+/// Returns whether every byte of a global starts out zero.
+fn is_zero_initialized(global: &GlobalVariable) -> bool {
+    global.get_initializer().is_none_or(Constant::is_zero)
+}
+
+/// Returns the section directive for a global.
+fn section(global: &GlobalVariable) -> String {
+    let section = data_section(global);
+    if global.get_section().is_none() && !section.thread_local {
+        return match section.name.as_str() {
+            ".rodata" => ".section .rodata".to_string(),
+            name => name.to_string(),
+        };
+    }
+    let mut flags = String::from("a");
+    if section.writable {
+        flags.push('w');
+    }
+    if section.thread_local {
+        flags.push('T');
+    }
+    let kind = if section.nobits { "@nobits" } else { "@progbits" };
+    format!(".section {},\"{}\",{}", section.name, flags, kind)
+}
+
+/// Returns the size and alignment of the value a global holds.
+pub fn size_and_align(layout: &DataLayout, global: &GlobalVariable) -> (u64, u64) {
+    let ty = global.get_value_type();
+    (layout.size_of(ty), global.get_alignment().unwrap_or_else(|| layout.align_of(ty)))
+}
+
+/// Checks that a common global can be emitted, which needs it to be zero
+/// and not thread-local.
+pub fn check_common(global: &GlobalVariable) -> Result<(), Error> {
+    if !is_zero_initialized(global) || global.is_thread_local() {
+        return Err(unsupported(format!("common global {} must be zero-initialized and not thread-local", global.get_name())));
+    }
+    Ok(())
+}
+
+/// Writes the definition of a global variable. Declarations of globals
+/// defined elsewhere produce nothing.
+pub fn emit_global(file: &mut impl Write, layout: &DataLayout, global: &GlobalVariable) -> Result<(), Error> {
+    if global.is_external() {
+        return Ok(());
+    }
+
+    let name = global.get_name();
+    let (size, align) = size_and_align(layout, global);
+
+    if *global.get_linkage() == Linkage::CommonLinkage {
+        check_common(global)?;
+        return writeln!(file, "\t\t.comm {}, {}, {}", name, size, align);
+    }
+
+    writeln!(file, "\t\t{}", section(global))?;
+    writeln!(file, "\t\t.balign {}", align)?;
+    writeln!(file, "\t\t.type {}, @{}", name, if global.is_thread_local() { "tls_object" } else { "object" })?;
+    writeln!(file, "\t\t.size {}, {}", name, size)?;
+    writeln!(file, "{}:", name)?;
+    for datum in global_data(layout, global)? {
+        emit_datum(file, &datum)?;
+    }
+    writeln!(file)
+}
+
+fn emit_datum(file: &mut impl Write, datum: &Datum) -> Result<(), Error> {
+    match datum {
+        Datum::Zero(size) => writeln!(file, "\t\t.zero {}", size),
+        Datum::Int(1, value) => writeln!(file, "\t\t.byte {}", *value as u8),
+        Datum::Int(2, value) => writeln!(file, "\t\t.short {}", *value as u16),
+        Datum::Int(4, value) => writeln!(file, "\t\t.long {}", *value as u32),
+        Datum::Int(8, value) => writeln!(file, "\t\t.quad {}", value),
+        Datum::Int(size, _) => Err(unsupported(format!("{}-byte integer data", size))),
+        Datum::F32(value) => writeln!(file, "\t\t.long {}", value.to_bits()),
+        Datum::F64(value) => writeln!(file, "\t\t.quad {}", value.to_bits()),
+        Datum::Bytes(bytes) => {
+            let mut ascii = String::new();
+            for byte in bytes {
+                match byte {
+                    b'"' => ascii.push_str("\\\""),
+                    b'\\' => ascii.push_str("\\\\"),
+                    b' '..=b'~' => ascii.push(*byte as char),
+                    _ => ascii.push_str(&format!("\\{:03o}", byte)),
+                }
+            }
+            writeln!(file, "\t\t.ascii \"{}\"", ascii)
+        }
+        Datum::Symbol(size, symbol, addend) => {
+            let directive = if *size == 4 { ".long" } else { ".quad" };
+            match addend {
+                0 => writeln!(file, "\t\t{} {}", directive, symbol),
+                addend => writeln!(file, "\t\t{} {}{:+}", directive, symbol, addend),
+            }
+        }
+    }
+}
+
+/// Returns the contents of a global defined in the module.
+pub fn global_data(layout: &DataLayout, global: &GlobalVariable) -> Result<Vec<Datum>, Error> {
+    let ty = global.get_value_type();
+    match global.get_initializer() {
+        Some(initializer) if !initializer.is_zero() => constant_data(layout, ty, initializer),
+        _ => {
+            let mut data = Vec::new();
+            zero(&mut data, layout.size_of(ty));
+            Ok(data)
+        }
+    }
+}
+
+/// An address in constant data: its offset, size, symbol and addend.
+pub type DataSymbol = (usize, u64, String, i64);
+
+/// Returns the bytes of `data`, for code that builds a constant in memory.
+/// Addresses are left zero and returned separately.
+pub fn data_bytes(data: &[Datum]) -> (Vec<u8>, Vec<DataSymbol>) {
+    let mut bytes = Vec::new();
+    let mut symbols = Vec::new();
+    for datum in data {
+        match datum {
+            Datum::Zero(size) => bytes.resize(bytes.len() + *size as usize, 0),
+            Datum::Int(size, value) => bytes.extend_from_slice(&value.to_le_bytes()[..*size as usize]),
+            Datum::F32(value) => bytes.extend_from_slice(&value.to_le_bytes()),
+            Datum::F64(value) => bytes.extend_from_slice(&value.to_le_bytes()),
+            Datum::Bytes(data) => bytes.extend_from_slice(data),
+            Datum::Symbol(size, symbol, addend) => {
+                symbols.push((bytes.len(), *size, symbol.clone(), *addend));
+                bytes.resize(bytes.len() + *size as usize, 0);
+            }
+        }
+    }
+    (bytes, symbols)
+}
+
+/// Returns the in-memory representation of a constant of type `ty`.
+pub fn constant_data(layout: &DataLayout, ty: &Type, constant: &Constant) -> Result<Vec<Datum>, Error> {
+    let mut data = Vec::new();
+    initializer_data(&mut data, layout, ty, constant)?;
+    Ok(data)
+}
+
+fn zero(data: &mut Vec<Datum>, size: u64) {
+    if size > 0 {
+        data.push(Datum::Zero(size));
+    }
+}
+
+/// Appends the data for a constant of type `ty`, including any padding.
+fn initializer_data(data: &mut Vec<Datum>, layout: &DataLayout, ty: &Type, constant: &Constant) -> Result<(), Error> {
+    let size = layout.size_of(ty);
+    match (ty, constant) {
+        // undefined values may as well be zero
+        (_, Constant::Zero(_) | Constant::Null(_) | Constant::Undef(_) | Constant::Poison(_)) => zero(data, size),
+        (Type::Array(_, _), Constant::Bytes(bytes)) => {
+            data.push(Datum::Bytes(bytes.clone()));
+            zero(data, size - bytes.len() as u64);
+        }
+        (Type::Array(len, element), Constant::Array(_, elements)) if elements.len() == *len => {
+            let stride = layout.stride_of(element);
+            for element_init in elements {
+                initializer_data(data, layout, element, element_init)?;
+                zero(data, stride - layout.size_of(element));
+            }
+        }
+        (Type::Struct(fields), Constant::Struct(inits)) if fields.len() == inits.len() => {
+            let mut offset = 0;
+            for ((field, field_offset), init) in fields.iter().zip(layout.struct_field_offsets(fields)).zip(inits) {
+                zero(data, field_offset - offset);
+                initializer_data(data, layout, field, init)?;
+                offset = field_offset + layout.size_of(field);
+            }
+            zero(data, size - offset);
+        }
+        _ => match constant.fold() {
+            Some(Scalar::Int(value)) if matches!(size, 1 | 2 | 4 | 8) => data.push(Datum::Int(size, value.to_machine_i64())),
+            Some(Scalar::Int(value)) => data.push(Datum::Bytes(value.to_le_bytes(size as usize))),
+            Some(Scalar::Float(32, value)) => data.push(Datum::F32(f64::from_bits(value) as f32)),
+            Some(Scalar::Float(64, value)) => data.push(Datum::F64(f64::from_bits(value))),
+            Some(Scalar::Address(symbol, addend)) if size == layout.pointer_size => data.push(Datum::Symbol(size, symbol, addend)),
+            // a wider integer holds the address zero-extended
+            Some(Scalar::Address(symbol, addend)) if size > layout.pointer_size => {
+                data.push(Datum::Symbol(layout.pointer_size, symbol, addend));
+                zero(data, size - layout.pointer_size);
+            }
+            _ => return Err(unsupported(format!("initializer {} for a value of type {}", constant, ty))),
+        },
+    }
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use crate::emit::asm::machine::{MachineFunction, MachineInst, Reg, RegUse};
+
+/// Options controlling the shape of the stack frames the backends build.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameOptions {
+    /// Address the frame through the stack pointer and leave the frame
+    /// pointer register untouched.
+    pub omit_frame_pointer: bool,
+}
+
+/// Returns the registers of `callee_saved` that `mf` writes, in register order.
+pub fn written_callee_saved<I: MachineInst>(mf: &mut MachineFunction<I>, callee_saved: &[I::PReg]) -> Vec<I::PReg> {
+    let mut saved = Vec::new();
+    for block in &mut mf.blocks {
+        for inst in &mut block.insts {
+            inst.visit_regs(&mut |reg, access| {
+                if let Reg::Phys(preg) = reg {
+                    if access != RegUse::Use && callee_saved.contains(preg) && !saved.contains(preg) {
+                        saved.push(*preg);
+                    }
+                }
+            });
+        }
+    }
+    saved.sort();
+    saved
+}
+
+pub fn makes_calls<I: MachineInst>(mf: &MachineFunction<I>) -> bool {
+    mf.blocks.iter().any(|block| block.insts.iter().any(I::is_call))
+}
+
+/// Lays the stack slots of `mf` out upwards from the top of its outgoing
+/// argument area. Returns the offset of each slot from the bottom of the
+/// frame and where the last one ends.
+pub fn slot_offsets<I>(mf: &MachineFunction<I>) -> (Vec<u64>, u64) {
+    let mut offsets = Vec::new();
+    let mut end = mf.outgoing_args;
+    for slot in &mf.slots {
+        let offset = end.next_multiple_of(slot.align);
+        offsets.push(offset);
+        end = offset + slot.size;
+    }
+    (offsets, end)
+}
+
+/// Passes every instruction of `mf` through `rewrite`, which appends it or
+/// its replacement to the block again, and wraps the body in `prologue` and
+/// `epilogue`, the latter placed before each return.
+pub fn insert_prologue_and_epilogues<I: MachineInst>(mf: &mut MachineFunction<I>, prologue: Vec<I>, epilogue: &[I], mut rewrite: impl FnMut(I, &mut Vec<I>)) {
+    for block in &mut mf.blocks {
+        for inst in std::mem::take(&mut block.insts) {
+            if inst.is_return() {
+                block.insts.extend(epilogue.iter().cloned());
+            }
+            rewrite(inst, &mut block.insts);
+        }
+    }
+    mf.blocks[0].insts.splice(0..0, prologue);
+}
@@ -0,0 +1,135 @@
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// The register file a value lives in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RegClass {
+    Int,
+    Float,
+}
+
+/// A virtual register, later replaced by a physical register or a stack slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VReg {
+    pub index: u32,
+    pub class: RegClass,
+}
+
+/// A register operand, over the physical registers `P` of a target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Reg<P> {
+    Phys(P),
+    Virt(VReg),
+}
+
+/// How an instruction accesses a register operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegUse {
+    Use,
+    Def,
+    UseDef,
+}
+
+/// The destination and source of a copy from one register to another.
+pub type RegCopy<P> = (Reg<P>, Reg<P>);
+
+impl<P> From<VReg> for Reg<P> {
+    fn from(vreg: VReg) -> Self {
+        Reg::Virt(vreg)
+    }
+}
+
+/// What the register allocator and the frame need to know about the
+/// instructions of a target.
+pub trait MachineInst: Clone {
+    type PReg: Debug + Copy + Eq + Ord + Hash + 'static;
+
+    /// Calls `f` on every register the instruction names explicitly, together
+    /// with how the register is accessed. Registers inside memory operands are
+    /// always uses.
+    fn visit_regs(&mut self, f: &mut dyn FnMut(&mut Reg<Self::PReg>, RegUse));
+    /// Calls `f` on the physical registers the instruction reads or writes
+    /// without naming them as operands.
+    fn visit_fixed_regs(&self, f: &mut dyn FnMut(Self::PReg, RegUse));
+    /// Returns the registers whose contents the instruction destroys, like
+    /// those a call doesn't preserve.
+    fn clobbers(&self) -> &'static [Self::PReg];
+    fn is_terminator(&self) -> bool;
+    fn is_return(&self) -> bool;
+    fn is_call(&self) -> bool;
+    /// Returns the label of the block the instruction may jump to.
+    fn branch_target(&self) -> Option<&str>;
+    /// Returns the destination and source of a copy from one register to another.
+    fn as_copy(&self) -> Option<RegCopy<Self::PReg>>;
+}
+
+/// A label followed by straight-line machine code.
+#[derive(Debug, Clone)]
+pub struct MachineBlock<I> {
+    pub label: String,
+    pub comment: Option<String>,
+    pub insts: Vec<I>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackSlot {
+    pub size: u64,
+    pub align: u64,
+}
+
+/// The machine code of one function, before or after register allocation.
+#[derive(Debug, Clone)]
+pub struct MachineFunction<I> {
+    pub name: String,
+    pub blocks: Vec<MachineBlock<I>>,
+    pub slots: Vec<StackSlot>,
+    /// Bytes at the bottom of the frame where calls find their stack arguments.
+    pub outgoing_args: u64,
+    /// Whether the function moves the stack pointer to allocate memory of a
+    /// size only known at run time.
+    pub dynamic_stack: bool,
+    vreg_count: u32,
+}
+
+impl<I> MachineBlock<I> {
+    pub fn new(label: String, comment: Option<String>) -> Self {
+        Self {
+            label,
+            comment,
+            insts: Vec::new(),
+        }
+    }
+}
+
+impl<I> MachineFunction<I> {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            blocks: Vec::new(),
+            slots: Vec::new(),
+            outgoing_args: 0,
+            dynamic_stack: false,
+            vreg_count: 0,
+        }
+    }
+
+    pub fn new_vreg(&mut self, class: RegClass) -> VReg {
+        let index = self.vreg_count;
+        self.vreg_count += 1;
+        VReg { index, class }
+    }
+
+    pub fn vreg_count(&self) -> u32 {
+        self.vreg_count
+    }
+
+    pub fn new_slot(&mut self, size: u64, align: u64) -> u32 {
+        self.slots.push(StackSlot { size, align });
+        (self.slots.len() - 1) as u32
+    }
+
+    /// Makes sure the outgoing argument area holds at least `size` bytes.
+    pub fn reserve_outgoing_args(&mut self, size: u64) {
+        self.outgoing_args = self.outgoing_args.max(size);
+    }
+}
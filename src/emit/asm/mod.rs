@@ -1,27 +1,65 @@
 
 use crate::ir::builder::ctx::IRContext;
-use crate::targets::TargetTriple;
 use crate::targets::triple::Arch;
+use crate::error::Error;
 
-use std::io::Write;
+use std::io::{ErrorKind, Write};
 
+pub mod aarch64;
+pub mod data;
+pub mod frame;
+pub mod machine;
+pub mod regalloc;
+pub mod riscv64;
+pub mod x86;
 pub mod x86_64;
- 
+
+pub use crate::emit::asm::frame::FrameOptions;
+
+/// Returns the error a backend reports for IR it cannot compile.
+pub(crate) fn unsupported(what: String) -> std::io::Error {
+    std::io::Error::new(ErrorKind::Unsupported, what)
+}
+
 pub struct AssemblyEmitter {
     ctx: IRContext,
+    frame_options: FrameOptions,
+    show_encoding: bool,
 }
 
 impl AssemblyEmitter {
     pub fn new(ctx: IRContext) -> Self {
         Self {
-            ctx
+            ctx,
+            frame_options: FrameOptions::default(),
+            show_encoding: false,
         }
     }
 
-    pub fn emit_module(&mut self, file: &mut impl Write) -> Result<(), std::io::Error> {
-        match self.ctx.get_module().target_triple().arch() {
-            Arch::X86_64 => x86_64::emit_module(self.ctx.clone(), file),
-            _ => todo!()
+    pub fn with_frame_options(ctx: IRContext, frame_options: FrameOptions) -> Self {
+        Self {
+            ctx,
+            frame_options,
+            show_encoding: false,
         }
-    }   
+    }
+
+    /// Sets whether each instruction is followed by a comment with its machine code.
+    pub fn set_show_encoding(&mut self, show_encoding: bool) {
+        self.show_encoding = show_encoding;
+    }
+
+    pub fn emit_module(&mut self, file: &mut impl Write) -> Result<(), Error> {
+        let triple = self.ctx.get_module().target_triple();
+        match triple.arch() {
+            Arch::X86_64 => Ok(x86_64::emit_module(self.ctx.clone(), self.frame_options, self.show_encoding, file)?),
+            Arch::Aarch64 if self.show_encoding => Err(unsupported("instruction encodings for aarch64".to_string()).into()),
+            Arch::Aarch64 => Ok(aarch64::emit_module(self.ctx.clone(), self.frame_options, file)?),
+            Arch::Riscv64 if self.show_encoding => Err(unsupported("instruction encodings for riscv64".to_string()).into()),
+            Arch::Riscv64 => Ok(riscv64::emit_module(self.ctx.clone(), self.frame_options, file)?),
+            Arch::X86 if self.show_encoding => Err(unsupported("instruction encodings for i686".to_string()).into()),
+            Arch::X86 => Ok(x86::emit_module(self.ctx.clone(), self.frame_options, file)?),
+            _ => Err(Error::UnsupportedTarget(triple.clone())),
+        }
+    }
 }
\ No newline at end of file
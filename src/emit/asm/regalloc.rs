@@ -0,0 +1,328 @@
+use crate::emit::asm::machine::{MachineFunction, MachineInst, Reg, RegClass, RegUse, VReg};
+use std::collections::{HashMap, HashSet};
+
+type PReg<R> = <<R as RegisterFile>::Inst as MachineInst>::PReg;
+
+/// The registers of a target, as the allocator hands them out, and the
+/// instructions it inserts to keep spilled values in stack slots.
+pub trait RegisterFile {
+    type Inst: MachineInst;
+
+    /// Registers handed out for values of `class`, in the order they are tried.
+    fn allocatable(class: RegClass) -> &'static [PReg<Self>];
+    /// Registers kept free to reload spilled values of `class` around a
+    /// single instruction. There must be at least as many as any instruction
+    /// reads registers of `class`, or writes ones it doesn't read.
+    fn scratch(class: RegClass) -> &'static [PReg<Self>];
+    /// Registers the caller reads after the function returns.
+    fn return_regs() -> &'static [PReg<Self>];
+    /// Returns the size and alignment of the stack slot a spilled value of `class` takes.
+    fn spill_slot(_class: RegClass) -> (u64, u64) {
+        (8, 8)
+    }
+    /// Returns the instruction reloading a spilled value of `class` into `dst`.
+    fn load(class: RegClass, dst: PReg<Self>, slot: u32) -> Self::Inst;
+    /// Returns the instruction spilling a value of `class` from `src`.
+    fn store(class: RegClass, src: PReg<Self>, slot: u32) -> Self::Inst;
+}
+
+// Every instruction `n` has two positions: `2n` where it reads its inputs
+// and `2n + 1` where it writes its outputs. Ranges are inclusive.
+fn use_pos(n: usize) -> u32 {
+    2 * n as u32
+}
+
+fn def_pos(n: usize) -> u32 {
+    2 * n as u32 + 1
+}
+
+fn overlaps(a: (u32, u32), b: (u32, u32)) -> bool {
+    a.0 <= b.1 && b.0 <= a.1
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Interval {
+    vreg: u32,
+    class: RegClass,
+    start: u32,
+    end: u32,
+}
+
+/// Where each register is live, over the whole function.
+struct Liveness<P> {
+    intervals: Vec<Interval>,
+    /// Ranges in which physical registers hold values or get clobbered.
+    fixed: HashMap<P, Vec<(u32, u32)>>,
+    /// Registers each virtual register is copied from or to, tried first.
+    hints: HashMap<u32, Vec<Reg<P>>>,
+}
+
+fn successors<I: MachineInst>(mf: &MachineFunction<I>) -> Vec<Vec<usize>> {
+    let labels = mf.blocks.iter().enumerate().map(|(i, block)| (block.label.as_str(), i)).collect::<HashMap<_, _>>();
+    mf.blocks.iter().enumerate().map(|(i, block)| {
+        let mut succs = Vec::new();
+        for inst in &block.insts {
+            if let Some(target) = inst.branch_target() {
+                succs.push(labels[target]);
+            }
+        }
+        if !block.insts.last().is_some_and(I::is_terminator) && i + 1 < mf.blocks.len() {
+            succs.push(i + 1);
+        }
+        succs
+    }).collect()
+}
+
+fn analyze<R: RegisterFile>(mf: &mut MachineFunction<R::Inst>) -> Liveness<PReg<R>> {
+    let succs = successors(mf);
+
+    // the virtual registers each block reads before writing them, and writes
+    let mut uses = vec![HashSet::new(); mf.blocks.len()];
+    let mut defs = vec![HashSet::new(); mf.blocks.len()];
+    let mut classes = HashMap::new();
+    for (i, block) in mf.blocks.iter_mut().enumerate() {
+        for inst in &mut block.insts {
+            inst.visit_regs(&mut |reg, access| {
+                if let Reg::Virt(vreg) = reg {
+                    classes.insert(vreg.index, vreg.class);
+                    if access != RegUse::Def && !defs[i].contains(&vreg.index) {
+                        uses[i].insert(vreg.index);
+                    }
+                    if access != RegUse::Use {
+                        defs[i].insert(vreg.index);
+                    }
+                }
+            });
+        }
+    }
+
+    let mut live_in: Vec<HashSet<u32>> = vec![HashSet::new(); mf.blocks.len()];
+    let mut live_out: Vec<HashSet<u32>> = vec![HashSet::new(); mf.blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in (0..mf.blocks.len()).rev() {
+            let out = succs[i].iter().flat_map(|succ| live_in[*succ].iter().copied()).collect::<HashSet<_>>();
+            let mut in_ = uses[i].clone();
+            in_.extend(out.difference(&defs[i]));
+            if in_ != live_in[i] || out != live_out[i] {
+                live_in[i] = in_;
+                live_out[i] = out;
+                changed = true;
+            }
+        }
+    }
+
+    let mut ranges: HashMap<u32, (RegClass, u32, u32)> = HashMap::new();
+    let mut extend = |vreg: u32, class: RegClass, pos: u32| {
+        let range = ranges.entry(vreg).or_insert((class, pos, pos));
+        range.1 = range.1.min(pos);
+        range.2 = range.2.max(pos);
+    };
+    let mut fixed: HashMap<PReg<R>, Vec<(u32, u32)>> = HashMap::new();
+    let mut hints: HashMap<u32, Vec<Reg<PReg<R>>>> = HashMap::new();
+
+    let mut n = 0;
+    for (i, block) in mf.blocks.iter_mut().enumerate() {
+        let first = n;
+        let last = n + block.insts.len().saturating_sub(1);
+        // open ranges of physical registers: where they were written and last read
+        let mut open: HashMap<PReg<R>, (u32, u32)> = HashMap::new();
+        for inst in &mut block.insts {
+            if let Some((dst, src)) = inst.as_copy() {
+                for (a, b) in [(dst, src), (src, dst)] {
+                    if let Reg::Virt(vreg) = a {
+                        hints.entry(vreg.index).or_default().push(b);
+                    }
+                }
+            }
+
+            let mut accesses = Vec::new();
+            inst.visit_regs(&mut |reg, access| accesses.push((*reg, access)));
+            inst.visit_fixed_regs(&mut |reg, access| accesses.push((Reg::Phys(reg), access)));
+            if inst.is_return() {
+                // the return value is read by the caller
+                accesses.extend(R::return_regs().iter().filter(|reg| open.contains_key(reg)).map(|reg| (Reg::Phys(*reg), RegUse::Use)));
+            }
+
+            for (reg, _) in accesses.iter().filter(|(_, access)| *access != RegUse::Def) {
+                match reg {
+                    Reg::Virt(vreg) => extend(vreg.index, vreg.class, use_pos(n)),
+                    Reg::Phys(preg) => {
+                        let range = open.entry(*preg).or_insert((use_pos(first), use_pos(first)));
+                        range.1 = use_pos(n);
+                    }
+                }
+            }
+            for preg in inst.clobbers() {
+                if let Some(range) = open.remove(preg) {
+                    fixed.entry(*preg).or_default().push(range);
+                }
+                fixed.entry(*preg).or_default().push((def_pos(n), def_pos(n)));
+            }
+            for (reg, _) in accesses.iter().filter(|(_, access)| *access != RegUse::Use) {
+                match reg {
+                    Reg::Virt(vreg) => extend(vreg.index, vreg.class, def_pos(n)),
+                    Reg::Phys(preg) => {
+                        if let Some(range) = open.insert(*preg, (def_pos(n), def_pos(n))) {
+                            fixed.entry(*preg).or_default().push(range);
+                        }
+                    }
+                }
+            }
+            n += 1;
+        }
+        for (preg, range) in open {
+            fixed.entry(preg).or_default().push(range);
+        }
+
+        for vreg in &live_in[i] {
+            extend(*vreg, classes[vreg], use_pos(first));
+        }
+        for vreg in &live_out[i] {
+            extend(*vreg, classes[vreg], def_pos(last));
+        }
+    }
+
+    let mut intervals = ranges.into_iter()
+        .map(|(vreg, (class, start, end))| Interval { vreg, class, start, end })
+        .collect::<Vec<_>>();
+    intervals.sort_by_key(|interval| (interval.start, interval.vreg));
+    Liveness { intervals, fixed, hints }
+}
+
+/// Assigns physical registers to the virtual registers of `mf` with a linear
+/// scan over their live intervals. Values that do not fit are spilled to
+/// stack slots and reloaded around each use.
+pub fn allocate<R: RegisterFile>(mf: &mut MachineFunction<R::Inst>) {
+    let Liveness { intervals, fixed, hints } = analyze::<R>(mf);
+    let is_free = |preg: PReg<R>, interval: &Interval| {
+        fixed.get(&preg).is_none_or(|ranges| ranges.iter().all(|range| !overlaps(*range, (interval.start, interval.end))))
+    };
+
+    let mut assigned: HashMap<u32, PReg<R>> = HashMap::new();
+    let mut spilled: HashSet<u32> = HashSet::new();
+    let mut active: Vec<Interval> = Vec::new();
+    for interval in &intervals {
+        active.retain(|other| other.end >= interval.start);
+        let taken = active.iter().map(|other| assigned[&other.vreg]).collect::<HashSet<_>>();
+        let pool = R::allocatable(interval.class);
+
+        let preferred = hints.get(&interval.vreg).into_iter().flatten().filter_map(|hint| match hint {
+            Reg::Phys(preg) => Some(*preg),
+            Reg::Virt(vreg) => assigned.get(&vreg.index).copied(),
+        });
+        let choice = preferred.chain(pool.iter().copied())
+            .find(|preg| pool.contains(preg) && !taken.contains(preg) && is_free(*preg, interval));
+        if let Some(preg) = choice {
+            assigned.insert(interval.vreg, preg);
+            active.push(*interval);
+            continue;
+        }
+
+        // evict the active value that stays live the longest, if it outlives this one
+        let victim = active.iter().enumerate()
+            .filter(|(_, other)| other.class == interval.class && other.end > interval.end && is_free(assigned[&other.vreg], interval))
+            .max_by_key(|(_, other)| other.end)
+            .map(|(i, _)| i);
+        match victim {
+            Some(i) => {
+                let victim = active.remove(i);
+                let preg = assigned.remove(&victim.vreg).unwrap();
+                spilled.insert(victim.vreg);
+                assigned.insert(interval.vreg, preg);
+                active.push(*interval);
+            }
+            None => {
+                spilled.insert(interval.vreg);
+            }
+        }
+    }
+
+    let classes = intervals.iter().map(|interval| (interval.vreg, interval.class)).collect::<HashMap<_, _>>();
+    // slots are handed out in register order so that the frame does not depend on hashing
+    let mut spilled = spilled.into_iter().collect::<Vec<_>>();
+    spilled.sort();
+    let mut slots = HashMap::new();
+    for vreg in spilled {
+        let (size, align) = R::spill_slot(classes[&vreg]);
+        slots.insert(vreg, mf.new_slot(size, align));
+    }
+    for block in &mut mf.blocks {
+        for mut inst in std::mem::take(&mut block.insts) {
+            inst.visit_regs(&mut |reg, _| {
+                if let Reg::Virt(vreg) = reg {
+                    if let Some(preg) = assigned.get(&vreg.index) {
+                        *reg = Reg::Phys(*preg);
+                    }
+                }
+            });
+            rewrite::<R>(inst, &slots, &mut block.insts);
+        }
+        // copies whose source and destination ended up in the same register
+        block.insts.retain(|inst| inst.as_copy().is_none_or(|(dst, src)| dst != src));
+    }
+}
+
+/// Rewrites the virtual registers of `inst` that live in `slots`, appending
+/// the result together with its reloads and stores to `out`.
+fn rewrite<R: RegisterFile>(mut inst: R::Inst, slots: &HashMap<u32, u32>, out: &mut Vec<R::Inst>) {
+    let mut accesses: Vec<(VReg, RegUse)> = Vec::new();
+    inst.visit_regs(&mut |reg, access| {
+        if let Reg::Virt(vreg) = reg {
+            if !slots.contains_key(&vreg.index) {
+                return;
+            }
+            match accesses.iter_mut().find(|(v, _)| v == vreg) {
+                Some((_, existing)) if *existing != access => *existing = RegUse::UseDef,
+                Some(_) => {}
+                None => accesses.push((*vreg, access)),
+            }
+        }
+    });
+
+    // Inputs are read before outputs are written, so an output that isn't
+    // also an input may take the scratch register of one. Per class, inputs
+    // count up from the first scratch register, read-write ones first, and
+    // outputs continue after the read-write ones.
+    let mut read = [0, 0];
+    let mut written = [0, 0];
+    let mut assigned: Vec<(VReg, PReg<R>)> = Vec::new();
+    for pass in [RegUse::UseDef, RegUse::Use, RegUse::Def] {
+        for (vreg, _) in accesses.iter().filter(|(_, access)| *access == pass) {
+            let class = vreg.class as usize;
+            let next = match pass {
+                RegUse::UseDef => {
+                    written[class] += 1;
+                    &mut read[class]
+                }
+                RegUse::Use => &mut read[class],
+                RegUse::Def => &mut written[class],
+            };
+            let pool = R::scratch(vreg.class);
+            debug_assert!(*next < pool.len(), "an instruction needs more than {} scratch registers of {:?}", pool.len(), vreg.class);
+            let scratch = pool[*next];
+            *next += 1;
+            assigned.push((*vreg, scratch));
+            if pass != RegUse::Def {
+                out.push(R::load(vreg.class, scratch, slots[&vreg.index]));
+            }
+        }
+    }
+
+    inst.visit_regs(&mut |reg, _| {
+        if let Reg::Virt(vreg) = reg {
+            if let Some((_, scratch)) = assigned.iter().find(|(v, _)| v == vreg) {
+                *reg = Reg::Phys(*scratch);
+            }
+        }
+    });
+    out.push(inst);
+
+    for (vreg, access) in &accesses {
+        if *access != RegUse::Use {
+            let scratch = assigned.iter().find(|(v, _)| v == vreg).unwrap().1;
+            out.push(R::store(vreg.class, scratch, slots[&vreg.index]));
+        }
+    }
+}
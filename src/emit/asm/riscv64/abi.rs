@@ -0,0 +1,227 @@
+use crate::emit::asm::riscv64::inst::PReg;
+use crate::emit::asm::unsupported;
+use crate::ir::values::value::Type;
+use crate::targets::layout::DataLayout;
+use std::io::Error;
+
+/// Registers used for integer, pointer and composite arguments, in order.
+pub const INT_ARGUMENT_REGS: [PReg; 8] = [PReg::A0, PReg::A1, PReg::A2, PReg::A3, PReg::A4, PReg::A5, PReg::A6, PReg::A7];
+/// Registers used for floating point arguments and members of small structs, in order.
+pub const FLOAT_ARGUMENT_REGS: [PReg; 8] = [PReg::Fa0, PReg::Fa1, PReg::Fa2, PReg::Fa3, PReg::Fa4, PReg::Fa5, PReg::Fa6, PReg::Fa7];
+/// Holds the address of the caller's buffer for results returned in memory,
+/// in place of the first argument.
+pub const INDIRECT_RESULT_REG: PReg = PReg::A0;
+
+/// Part of a value passed in a register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Piece {
+    pub reg: PReg,
+    /// Offset of the part within the value.
+    pub offset: u64,
+    /// Number of bytes of the value that live in the register.
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgLocation {
+    /// General purpose registers hold eight bytes each, floating point
+    /// registers one floating point member.
+    Regs(Vec<Piece>),
+    /// In the argument area, at `offset` bytes from the stack pointer at the call.
+    Stack { offset: u64, size: u64 },
+    /// A composite that got the last argument register: its first eight
+    /// bytes are in `piece`, the rest in the argument area at `offset`.
+    Split { piece: Piece, offset: u64 },
+    /// Composites larger than 16 bytes are copied to memory by the caller,
+    /// which passes the address of the copy like a pointer argument.
+    Reference(Box<ArgLocation>),
+    /// Zero-sized values are not passed at all.
+    Ignore,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReturnLocation {
+    Void,
+    Regs(Vec<Piece>),
+    /// The caller passes a buffer in `a0`.
+    Memory,
+}
+
+/// Where the arguments and return value of a call live.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallAbi {
+    pub args: Vec<ArgLocation>,
+    pub ret: ReturnLocation,
+    /// Size of the stack argument area, a multiple of 16.
+    pub stack_size: u64,
+}
+
+/// Collects the scalar members of a value of type `ty` with their offsets.
+fn flatten(layout: &DataLayout, ty: &Type, offset: u64, members: &mut Vec<(Type, u64)>) -> Result<(), Error> {
+    match ty {
+        Type::Integer(_) | Type::Pointer(_) | Type::FunctionType(_, _) | Type::Float(32) | Type::Float(64) => members.push((ty.clone(), offset)),
+        Type::Array(len, element) => {
+            let stride = layout.stride_of(element);
+            for i in 0..*len as u64 {
+                flatten(layout, element, offset + i * stride, members)?;
+            }
+        }
+        Type::Struct(fields) => {
+            for (field, field_offset) in fields.iter().zip(layout.struct_field_offsets(fields)) {
+                flatten(layout, field, offset + field_offset, members)?;
+            }
+        }
+        Type::Float(_) | Type::Void | Type::Branch => return Err(unsupported(format!("passing values of type {}", ty))),
+    }
+    Ok(())
+}
+
+/// Returns the members of a composite passed in floating point registers
+/// when enough are left: a single floating point member, two of them, or
+/// one together with an integer of at most eight bytes.
+fn float_members(layout: &DataLayout, ty: &Type) -> Result<Option<Vec<(Type, u64)>>, Error> {
+    if !ty.is_struct() && !ty.is_array() {
+        return Ok(None);
+    }
+    let mut members = Vec::new();
+    flatten(layout, ty, 0, &mut members)?;
+    let floats = members.iter().filter(|(member, _)| member.is_float()).count();
+    let ints = members.iter().filter(|(member, _)| matches!(member, Type::Integer(bits) if *bits <= 64)).count();
+    let eligible = match members.len() {
+        1 => floats == 1,
+        2 => floats >= 1 && floats + ints == 2,
+        _ => false,
+    };
+    Ok(eligible.then_some(members))
+}
+
+/// The argument registers not yet taken by earlier arguments.
+struct Available<'a> {
+    ints: &'a [PReg],
+    floats: &'a [PReg],
+}
+
+impl Available<'_> {
+    /// Takes the registers for the members of a composite eligible for
+    /// floating point registers, if enough of both kinds are left.
+    fn take_members(&mut self, layout: &DataLayout, members: &[(Type, u64)]) -> Option<Vec<Piece>> {
+        let floats = members.iter().filter(|(member, _)| member.is_float()).count();
+        if floats > self.floats.len() || members.len() - floats > self.ints.len() {
+            return None;
+        }
+        let pieces = members.iter().map(|(member, offset)| {
+            let pool = if member.is_float() { &mut self.floats } else { &mut self.ints };
+            let (reg, rest) = pool.split_first().unwrap();
+            *pool = rest;
+            Piece { reg: *reg, offset: *offset, size: layout.size_of(member) }
+        }).collect();
+        Some(pieces)
+    }
+
+    /// Takes one general purpose register for each eight bytes of a value
+    /// of `size` bytes, if enough are left.
+    fn take_ints(&mut self, size: u64) -> Option<Vec<Piece>> {
+        let needed = size.div_ceil(8) as usize;
+        if needed > self.ints.len() {
+            return None;
+        }
+        let pieces = (0..needed).map(|i| {
+            let offset = i as u64 * 8;
+            Piece { reg: self.ints[i], offset, size: (size - offset).min(8) }
+        }).collect();
+        self.ints = &self.ints[needed..];
+        Some(pieces)
+    }
+}
+
+fn is_composite(ty: &Type) -> bool {
+    ty.is_struct() || ty.is_array()
+}
+
+/// Computes where the arguments and the return value of a call with the
+/// given argument and return types are passed, following the LP64D
+/// calling convention of the RISC-V ELF psABI. Arguments from index `named`
+/// on are variadic, which never go in floating point registers.
+pub fn classify_call(layout: &DataLayout, args: &[Type], ret: &Type, named: usize) -> Result<CallAbi, Error> {
+    let mut ints: &[PReg] = &INT_ARGUMENT_REGS;
+    let ret = if ret.is_void() {
+        ReturnLocation::Void
+    } else {
+        let mut available = Available { ints: &INT_ARGUMENT_REGS[..2], floats: &FLOAT_ARGUMENT_REGS[..2] };
+        let pieces = match float_members(layout, ret)? {
+            Some(members) => available.take_members(layout, &members),
+            None if ret.is_float() => Some(vec![Piece { reg: PReg::Fa0, offset: 0, size: layout.size_of(ret) }]),
+            None => available.take_ints(layout.size_of(ret)),
+        };
+        match pieces {
+            Some(pieces) => ReturnLocation::Regs(pieces),
+            None => {
+                // the buffer takes the place of the first argument
+                ints = &INT_ARGUMENT_REGS[1..];
+                ReturnLocation::Memory
+            }
+        }
+    };
+
+    let mut available = Available { ints, floats: &FLOAT_ARGUMENT_REGS };
+    let mut stack_size: u64 = 0;
+    let mut on_stack = |size: u64, align: u64| {
+        let offset = stack_size.next_multiple_of(align.clamp(8, 16));
+        stack_size = offset + size.next_multiple_of(8);
+        offset
+    };
+
+    let mut locations = Vec::new();
+    for (i, arg) in args.iter().enumerate() {
+        let size = layout.size_of(arg);
+        if size == 0 {
+            locations.push(ArgLocation::Ignore);
+            continue;
+        }
+        let variadic = i >= named;
+        if is_composite(arg) && size > 16 {
+            let location = match available.take_ints(8) {
+                Some(pieces) => ArgLocation::Regs(pieces),
+                None => ArgLocation::Stack { offset: on_stack(8, 8), size: 8 },
+            };
+            locations.push(ArgLocation::Reference(Box::new(location)));
+            continue;
+        }
+        if !variadic {
+            if arg.is_float() && !available.floats.is_empty() {
+                let (reg, rest) = available.floats.split_first().unwrap();
+                available.floats = rest;
+                locations.push(ArgLocation::Regs(vec![Piece { reg: *reg, offset: 0, size }]));
+                continue;
+            }
+            if let Some(members) = float_members(layout, arg)? {
+                if let Some(pieces) = available.take_members(layout, &members) {
+                    locations.push(ArgLocation::Regs(pieces));
+                    continue;
+                }
+            }
+        }
+
+        // everything else follows the integer calling convention
+        let align = layout.align_of(arg);
+        if variadic && align == 16 && (INT_ARGUMENT_REGS.len() - available.ints.len()) % 2 == 1 && !available.ints.is_empty() {
+            // variadic values aligned to 16 bytes start at an even register
+            available.ints = &available.ints[1..];
+        }
+        if let Some(pieces) = available.take_ints(size) {
+            locations.push(ArgLocation::Regs(pieces));
+        } else if available.ints.len() == 1 {
+            let piece = Piece { reg: available.ints[0], offset: 0, size: 8 };
+            available.ints = &[];
+            locations.push(ArgLocation::Split { piece, offset: on_stack(size - 8, 8) });
+        } else {
+            locations.push(ArgLocation::Stack { offset: on_stack(size, align), size });
+        }
+    }
+
+    Ok(CallAbi {
+        args: locations,
+        ret,
+        stack_size: stack_size.next_multiple_of(16),
+    })
+}
@@ -0,0 +1,237 @@
+use crate::emit::asm::frame::{self as shared, FrameOptions};
+use crate::emit::asm::riscv64::inst::{is_imm12, AluOp, Base, Inst, MachineFunction, Mem, Operand, PReg, Size, CALLEE_SAVED};
+
+/// Register the frame uses to reach addresses out of range of an immediate
+/// offset. The allocator never hands it out.
+pub const FRAME_SCRATCH: PReg = PReg::T0;
+
+/// The stack frame of one function.
+///
+/// With a frame pointer the frame looks like this, from high to low addresses:
+///
+/// ```text
+/// stack arguments          <- s0
+/// saved ra and s0
+/// saved callee-saved registers
+/// stack slots
+/// outgoing call arguments  <- sp
+/// ```
+///
+/// Without one, `s0` is left untouched, `ra` is saved with the callee-saved
+/// registers if the function makes calls, and everything is addressed from
+/// `sp`, which is always a multiple of 16. Functions with dynamically sized
+/// stack allocations always keep the frame pointer, since those move `sp`
+/// below the outgoing argument area, and address their slots from it.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// Registers saved below the incoming stack arguments, highest first.
+    pub saved: Vec<PReg>,
+    /// Bytes reserved below the saved registers.
+    pub size: u64,
+    pub frame_pointer: bool,
+    /// Whether `sp` may have moved by the time the function returns.
+    dynamic: bool,
+    outgoing_args: u64,
+    /// Offset of each stack slot from `s0` when `sp` moves, or from `sp` otherwise.
+    offsets: Vec<i64>,
+}
+
+impl Frame {
+    /// Computes the frame of `mf` from the registers it writes, its stack
+    /// slots and the space its calls need for stack arguments.
+    pub fn new(mf: &mut MachineFunction, options: FrameOptions) -> Self {
+        let mut saved = shared::written_callee_saved(mf, &CALLEE_SAVED);
+        let frame_pointer = !options.omit_frame_pointer || mf.dynamic_stack;
+        if frame_pointer {
+            saved.splice(0..0, [PReg::Ra, PReg::S0]);
+        } else if shared::makes_calls(mf) {
+            // calls overwrite the return address
+            saved.insert(0, PReg::Ra);
+        }
+
+        let (offsets, end) = shared::slot_offsets(mf);
+        let size = end.next_multiple_of(16);
+        let pushed = (8 * saved.len() as u64).next_multiple_of(16);
+
+        let offsets = offsets.into_iter().map(|offset| if mf.dynamic_stack {
+            offset as i64 - (size + pushed) as i64
+        } else {
+            offset as i64
+        }).collect();
+
+        Self { saved, size, frame_pointer, dynamic: mf.dynamic_stack, outgoing_args: mf.outgoing_args, offsets }
+    }
+
+    /// Returns the bytes the saved registers take, rounded up to keep `sp` aligned.
+    fn pushed(&self) -> u64 {
+        (8 * self.saved.len() as u64).next_multiple_of(16)
+    }
+
+    /// Returns the address of a stack slot.
+    pub fn slot_address(&self, slot: u32, offset: i64) -> Mem {
+        let base = if self.dynamic { PReg::S0 } else { PReg::Sp };
+        Mem::base(base, self.offsets[slot as usize] + offset)
+    }
+
+    /// Returns the address of the stack arguments passed by the caller, which
+    /// start where `sp` was at the call.
+    pub fn incoming_args_address(&self, offset: i64) -> Mem {
+        if self.frame_pointer {
+            Mem::base(PReg::S0, offset)
+        } else {
+            Mem::base(PReg::Sp, (self.size + self.pushed()) as i64 + offset)
+        }
+    }
+
+    /// Moves `sp` up by `bytes`, or down if negative, through the scratch
+    /// register if that does not fit an immediate.
+    fn adjust_sp(bytes: i64, insts: &mut Vec<Inst>) {
+        if is_imm12(bytes) {
+            insts.push(Inst::Alu { op: AluOp::Add, size: Size::Double, dst: PReg::Sp.into(), lhs: PReg::Sp.into(), rhs: Operand::Imm(bytes) });
+        } else {
+            insts.push(Inst::Li { dst: FRAME_SCRATCH.into(), imm: bytes });
+            insts.push(Inst::Alu { op: AluOp::Add, size: Size::Double, dst: PReg::Sp.into(), lhs: PReg::Sp.into(), rhs: FRAME_SCRATCH.into() });
+        }
+    }
+
+    /// Returns where a saved register lives, relative to `sp` right after the registers are saved.
+    fn save_address(&self, i: usize) -> Mem {
+        Mem::base(PReg::Sp, self.pushed() as i64 - 8 * (i as i64 + 1))
+    }
+
+    pub fn prologue(&self) -> Vec<Inst> {
+        let mut insts = Vec::new();
+        if self.pushed() > 0 {
+            Self::adjust_sp(-(self.pushed() as i64), &mut insts);
+        }
+        for (i, reg) in self.saved.iter().enumerate() {
+            insts.push(Inst::Store { size: Size::Double, src: (*reg).into(), addr: self.save_address(i) });
+        }
+        if self.frame_pointer {
+            insts.push(Inst::Lea { dst: PReg::S0.into(), addr: Mem::base(PReg::Sp, self.pushed() as i64) });
+        }
+        if self.size > 0 {
+            Self::adjust_sp(-(self.size as i64), &mut insts);
+        }
+        insts
+    }
+
+    /// Returns the instructions that undo the prologue, to be placed before each `ret`.
+    pub fn epilogue(&self) -> Vec<Inst> {
+        let mut insts = Vec::new();
+        if self.dynamic {
+            let addr = Mem::base(PReg::S0, -(self.pushed() as i64));
+            insts.push(Inst::Lea { dst: PReg::Sp.into(), addr });
+        } else if self.size > 0 {
+            Self::adjust_sp(self.size as i64, &mut insts);
+        }
+        for (i, reg) in self.saved.iter().enumerate().rev() {
+            insts.push(Inst::Load { size: Size::Double, signed: false, dst: (*reg).into(), addr: self.save_address(i) });
+        }
+        if self.pushed() > 0 {
+            Self::adjust_sp(self.pushed() as i64, &mut insts);
+        }
+        insts
+    }
+
+    /// Resolves the stack slots of `mf` to frame addresses, rewrites accesses
+    /// out of reach of their offsets and wraps the body in the prologue and
+    /// epilogue.
+    pub fn apply(&self, mf: &mut MachineFunction) {
+        shared::insert_prologue_and_epilogues(mf, self.prologue(), &self.epilogue(), |mut inst, out| {
+            inst.visit_mems(&mut |mem: &mut Mem| {
+                match mem.base {
+                    Base::Slot(slot) => *mem = self.slot_address(slot, mem.offset),
+                    Base::IncomingArgs => *mem = self.incoming_args_address(mem.offset),
+                    Base::ArgsEnd => *mem = Mem::base(PReg::Sp, mem.offset + self.outgoing_args as i64),
+                    Base::Reg(_) => {}
+                }
+            });
+            legalize(inst, out);
+        });
+    }
+}
+
+/// Appends `inst` to `out`, first computing its address in the scratch
+/// register if its offset does not fit the instruction.
+fn legalize(mut inst: Inst, out: &mut Vec<Inst>) {
+    let mut fits = true;
+    inst.visit_mems(&mut |mem: &mut Mem| fits = mem.fits());
+    if fits {
+        out.push(inst);
+        return;
+    }
+    inst.visit_mems(&mut |mem: &mut Mem| {
+        let Base::Reg(base) = mem.base else {
+            unreachable!("frame addresses are resolved before they are legalized");
+        };
+        out.push(Inst::Li { dst: FRAME_SCRATCH.into(), imm: mem.offset });
+        out.push(Inst::Alu { op: AluOp::Add, size: Size::Double, dst: FRAME_SCRATCH.into(), lhs: base, rhs: FRAME_SCRATCH.into() });
+        *mem = Mem::base(FRAME_SCRATCH, 0);
+    });
+    out.push(inst);
+}
+
+/// Lays out the stack frame of `mf` and inserts its prologue and epilogues.
+pub fn lay_out(mf: &mut MachineFunction, options: FrameOptions) -> Frame {
+    let frame = Frame::new(mf, options);
+    frame.apply(mf);
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emit::asm::riscv64::inst::{CallTarget, MachineBlock};
+
+    /// A function that writes a callee-saved integer and float register,
+    /// makes a call and stores to a slot out of reach of an immediate offset.
+    fn function() -> MachineFunction {
+        let mut mf = MachineFunction::new("f");
+        mf.new_slot(40000, 8);
+        let far = mf.new_slot(8, 8);
+        mf.blocks.push(MachineBlock::new("f".to_string(), None));
+        mf.blocks[0].insts = vec![
+            Inst::Mov { size: Size::Double, dst: PReg::S1.into(), src: PReg::A0.into() },
+            Inst::FNeg { size: Size::Double, dst: PReg::Fs0.into(), src: PReg::Fa0.into() },
+            Inst::Call { target: CallTarget::Symbol("g".to_string()), args: Vec::new() },
+            Inst::Store { size: Size::Double, src: PReg::S1.into(), addr: Mem::slot(far, 0) },
+            Inst::Ret,
+        ];
+        mf
+    }
+
+    #[test]
+    fn saves_the_return_address_and_frame_pointer_first() {
+        let mut mf = function();
+        let frame = lay_out(&mut mf, FrameOptions::default());
+        assert_eq!(frame.saved, [PReg::Ra, PReg::S0, PReg::S1, PReg::Fs0]);
+        assert_eq!(frame.size, 40016);
+
+        let insts = &mf.blocks[0].insts;
+        assert_eq!(insts[..8], [
+            Inst::Alu { op: AluOp::Add, size: Size::Double, dst: PReg::Sp.into(), lhs: PReg::Sp.into(), rhs: Operand::Imm(-32) },
+            Inst::Store { size: Size::Double, src: PReg::Ra.into(), addr: Mem::base(PReg::Sp, 24) },
+            Inst::Store { size: Size::Double, src: PReg::S0.into(), addr: Mem::base(PReg::Sp, 16) },
+            Inst::Store { size: Size::Double, src: PReg::S1.into(), addr: Mem::base(PReg::Sp, 8) },
+            Inst::Store { size: Size::Double, src: PReg::Fs0.into(), addr: Mem::base(PReg::Sp, 0) },
+            Inst::Lea { dst: PReg::S0.into(), addr: Mem::base(PReg::Sp, 32) },
+            // the frame is too big for an immediate
+            Inst::Li { dst: FRAME_SCRATCH.into(), imm: -40016 },
+            Inst::Alu { op: AluOp::Add, size: Size::Double, dst: PReg::Sp.into(), lhs: PReg::Sp.into(), rhs: FRAME_SCRATCH.into() },
+        ]);
+        // and so is the offset of the far slot
+        assert!(insts.contains(&Inst::Li { dst: FRAME_SCRATCH.into(), imm: 40000 }));
+        assert!(insts.contains(&Inst::Store { size: Size::Double, src: PReg::S1.into(), addr: Mem::base(FRAME_SCRATCH, 0) }));
+        assert_eq!(insts[insts.len() - 2..], [Inst::Alu { op: AluOp::Add, size: Size::Double, dst: PReg::Sp.into(), lhs: PReg::Sp.into(), rhs: Operand::Imm(32) }, Inst::Ret]);
+    }
+
+    #[test]
+    fn saves_only_the_return_address_without_a_frame_pointer() {
+        let mut mf = function();
+        let frame = lay_out(&mut mf, FrameOptions { omit_frame_pointer: true });
+        assert!(!frame.frame_pointer);
+        assert_eq!(frame.saved, [PReg::Ra, PReg::S1, PReg::Fs0]);
+        assert!(!mf.blocks[0].insts.iter().any(|inst| matches!(inst, Inst::Lea { dst, .. } if *dst == PReg::S0.into())));
+    }
+}
@@ -0,0 +1,540 @@
+use crate::emit::asm::machine::{self, MachineInst, RegCopy};
+pub use crate::emit::asm::machine::{RegClass, RegUse, StackSlot, VReg};
+use std::fmt::{Display, Formatter};
+
+/// A physical RISC-V register, named by its ABI name and ordered by its
+/// encoding within each register file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PReg {
+    Zero, Ra, Sp, Gp, Tp, T0, T1, T2, S0, S1, A0, A1, A2, A3, A4, A5,
+    A6, A7, S2, S3, S4, S5, S6, S7, S8, S9, S10, S11, T3, T4, T5, T6,
+    Ft0, Ft1, Ft2, Ft3, Ft4, Ft5, Ft6, Ft7, Fs0, Fs1, Fa0, Fa1, Fa2, Fa3, Fa4, Fa5,
+    Fa6, Fa7, Fs2, Fs3, Fs4, Fs5, Fs6, Fs7, Fs8, Fs9, Fs10, Fs11, Ft8, Ft9, Ft10, Ft11,
+}
+
+/// Registers a call may overwrite.
+pub const CALLER_SAVED: [PReg; 36] = [
+    PReg::Ra, PReg::T0, PReg::T1, PReg::T2, PReg::T3, PReg::T4, PReg::T5, PReg::T6,
+    PReg::A0, PReg::A1, PReg::A2, PReg::A3, PReg::A4, PReg::A5, PReg::A6, PReg::A7,
+    PReg::Ft0, PReg::Ft1, PReg::Ft2, PReg::Ft3, PReg::Ft4, PReg::Ft5, PReg::Ft6, PReg::Ft7,
+    PReg::Ft8, PReg::Ft9, PReg::Ft10, PReg::Ft11,
+    PReg::Fa0, PReg::Fa1, PReg::Fa2, PReg::Fa3, PReg::Fa4, PReg::Fa5, PReg::Fa6, PReg::Fa7,
+];
+/// Registers a function must restore before returning, apart from the frame record.
+pub const CALLEE_SAVED: [PReg; 23] = [
+    PReg::S1, PReg::S2, PReg::S3, PReg::S4, PReg::S5, PReg::S6, PReg::S7, PReg::S8, PReg::S9, PReg::S10, PReg::S11,
+    PReg::Fs0, PReg::Fs1, PReg::Fs2, PReg::Fs3, PReg::Fs4, PReg::Fs5, PReg::Fs6, PReg::Fs7, PReg::Fs8, PReg::Fs9, PReg::Fs10, PReg::Fs11,
+];
+
+pub type Reg = machine::Reg<PReg>;
+pub type MachineBlock = machine::MachineBlock<Inst>;
+pub type MachineFunction = machine::MachineFunction<Inst>;
+
+/// Access width. Arithmetic only comes in `Word` and `Double`, narrower
+/// values are computed with the 32-bit forms and their upper bits are undefined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Size {
+    Byte,
+    Half,
+    Word,
+    Double,
+}
+
+/// The base of a memory operand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Base {
+    Reg(Reg),
+    /// A frame slot, resolved to a frame-relative address once the frame is laid out.
+    Slot(u32),
+    /// The lowest address above the outgoing argument area, which is where
+    /// dynamically sized stack allocations start.
+    ArgsEnd,
+    /// The stack arguments the caller passed, resolved to a frame-relative address.
+    IncomingArgs,
+}
+
+/// `offset(base)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mem {
+    pub base: Base,
+    pub offset: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operand {
+    Reg(Reg),
+    Imm(i64),
+}
+
+/// Operations of the form `op dst, lhs, rhs`. Only `Add`, the logical
+/// operations, shifts and the comparisons have immediate forms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AluOp {
+    Add,
+    Sub,
+    And,
+    Or,
+    Xor,
+    Mul,
+    Div,
+    Divu,
+    Rem,
+    Remu,
+    Sll,
+    Srl,
+    Sra,
+    /// Sets `dst` to whether `lhs` is less than `rhs`, signed.
+    Slt,
+    /// Sets `dst` to whether `lhs` is less than `rhs`, unsigned.
+    Sltu,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// Comparisons setting an integer register, all false for unordered operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatCond {
+    Eq,
+    Lt,
+    Le,
+}
+
+/// How the address of a symbol is formed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolReloc {
+    /// Relative to the program counter, for symbols the module defines.
+    Local,
+    /// Loaded from the GOT entry of the symbol.
+    Got,
+    /// The offset of a thread-local symbol from the thread pointer, loaded from its GOT entry.
+    TlsIe,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallTarget {
+    Symbol(String),
+    Indirect(Reg),
+}
+
+/// A single RISC-V machine instruction, or an assembler pseudo-instruction
+/// that expands to a fixed sequence of them. Floating point instructions use
+/// `Size::Word` for single and `Size::Double` for double precision.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inst {
+    /// `mv` between general purpose registers, `fmv` between floating point ones.
+    Mov { size: Size, dst: Reg, src: Reg },
+    /// `li`, which the assembler expands to as many instructions as the value needs.
+    Li { dst: Reg, imm: i64 },
+    /// Immediates are 12-bit signed values, or shift amounts.
+    Alu { op: AluOp, size: Size, dst: Reg, lhs: Reg, rhs: Operand },
+    Neg { size: Size, dst: Reg, src: Reg },
+    /// Sets `dst` to whether `src` is zero.
+    Seqz { dst: Reg, src: Reg },
+    /// Sets `dst` to whether `src` is not zero.
+    Snez { dst: Reg, src: Reg },
+    /// Computes the address of a memory operand.
+    Lea { dst: Reg, addr: Mem },
+    /// Loads `size` bytes, zero-extended unless `signed` is set.
+    Load { size: Size, signed: bool, dst: Reg, addr: Mem },
+    Store { size: Size, src: Reg, addr: Mem },
+    /// Puts the address of a symbol, or what its GOT entry holds, in `dst`.
+    La { dst: Reg, symbol: String, reloc: SymbolReloc },
+    J { target: String },
+    Beqz { src: Reg, target: String },
+    Bnez { src: Reg, target: String },
+    /// `args` are the registers carrying arguments, which the call reads.
+    Call { target: CallTarget, args: Vec<PReg> },
+    Ret,
+    Unimp,
+    FloatAlu { op: FloatOp, size: Size, dst: Reg, lhs: Reg, rhs: Reg },
+    FNeg { size: Size, dst: Reg, src: Reg },
+    FCmp { cond: FloatCond, size: Size, dst: Reg, lhs: Reg, rhs: Reg },
+    /// Moves the bits of a floating point value between register files.
+    FMv { size: Size, dst: Reg, src: Reg },
+    /// `fcvt.s.w` and its kin, converting an integer of `int_size` to a float of `size`.
+    IntToFloat { signed: bool, int_size: Size, size: Size, dst: Reg, src: Reg },
+    /// `fcvt.w.s` and its kin, converting a float of `size` to an integer of `int_size`, rounding toward zero.
+    FloatToInt { signed: bool, int_size: Size, size: Size, dst: Reg, src: Reg },
+    /// `fcvt.s.d`/`fcvt.d.s`, converting to a float of `size`.
+    FCvt { size: Size, dst: Reg, src: Reg },
+}
+
+impl PReg {
+    /// Returns the 5-bit hardware number of the register.
+    pub fn encoding(self) -> u8 {
+        (self as u8) & 0x1f
+    }
+
+    pub fn class(self) -> RegClass {
+        if self >= PReg::Ft0 {
+            RegClass::Float
+        } else {
+            RegClass::Int
+        }
+    }
+
+    /// Returns the assembler name of the register.
+    pub fn name(self) -> String {
+        format!("{:?}", self).to_lowercase()
+    }
+}
+
+impl Size {
+    pub fn from_bytes(bytes: u64) -> Option<Size> {
+        match bytes {
+            1 => Some(Size::Byte),
+            2 => Some(Size::Half),
+            4 => Some(Size::Word),
+            8 => Some(Size::Double),
+            _ => None,
+        }
+    }
+
+    pub fn bytes(self) -> u64 {
+        match self {
+            Size::Byte => 1,
+            Size::Half => 2,
+            Size::Word => 4,
+            Size::Double => 8,
+        }
+    }
+
+    /// Returns the width of the arithmetic done on values of this size.
+    pub fn register(self) -> Size {
+        self.max(Size::Word)
+    }
+
+    /// Returns the suffix of floating point instructions of this precision.
+    fn float_suffix(self) -> &'static str {
+        if self == Size::Double { "d" } else { "s" }
+    }
+
+    /// Returns the suffix naming an integer of this width in conversions.
+    fn int_suffix(self, signed: bool) -> &'static str {
+        match (self == Size::Double, signed) {
+            (false, true) => "w",
+            (false, false) => "wu",
+            (true, true) => "l",
+            (true, false) => "lu",
+        }
+    }
+}
+
+impl From<PReg> for Reg {
+    fn from(reg: PReg) -> Self {
+        Reg::Phys(reg)
+    }
+}
+
+impl From<Reg> for Operand {
+    fn from(reg: Reg) -> Self {
+        Operand::Reg(reg)
+    }
+}
+
+impl From<PReg> for Operand {
+    fn from(reg: PReg) -> Self {
+        Operand::Reg(Reg::Phys(reg))
+    }
+}
+
+impl From<VReg> for Operand {
+    fn from(reg: VReg) -> Self {
+        Operand::Reg(Reg::Virt(reg))
+    }
+}
+
+/// Returns whether `imm` fits the 12-bit signed immediate of I- and S-type instructions.
+pub fn is_imm12(imm: i64) -> bool {
+    (-2048..2048).contains(&imm)
+}
+
+impl Mem {
+    /// `offset(reg)`
+    pub fn base(reg: impl Into<Reg>, offset: i64) -> Self {
+        Self { base: Base::Reg(reg.into()), offset }
+    }
+
+    pub fn slot(slot: u32, offset: i64) -> Self {
+        Self { base: Base::Slot(slot), offset }
+    }
+
+    /// Returns whether a load, store or `addi` can reach this address directly.
+    pub fn fits(&self) -> bool {
+        is_imm12(self.offset)
+    }
+}
+
+impl Operand {
+    fn visit_regs(&mut self, access: RegUse, f: &mut dyn FnMut(&mut Reg, RegUse)) {
+        if let Operand::Reg(reg) = self {
+            f(reg, access);
+        }
+    }
+}
+
+impl Mem {
+    fn visit_regs(&mut self, f: &mut dyn FnMut(&mut Reg, RegUse)) {
+        if let Base::Reg(reg) = &mut self.base {
+            f(reg, RegUse::Use);
+        }
+    }
+}
+
+impl MachineInst for Inst {
+    type PReg = PReg;
+
+    fn visit_regs(&mut self, f: &mut dyn FnMut(&mut Reg, RegUse)) {
+        match self {
+            Inst::Mov { dst, src, .. } | Inst::Neg { dst, src, .. } | Inst::Seqz { dst, src } | Inst::Snez { dst, src }
+            | Inst::FNeg { dst, src, .. } | Inst::FMv { dst, src, .. } | Inst::IntToFloat { dst, src, .. }
+            | Inst::FloatToInt { dst, src, .. } | Inst::FCvt { dst, src, .. } => {
+                f(src, RegUse::Use);
+                f(dst, RegUse::Def);
+            }
+            Inst::Alu { dst, lhs, rhs, .. } => {
+                f(lhs, RegUse::Use);
+                rhs.visit_regs(RegUse::Use, f);
+                f(dst, RegUse::Def);
+            }
+            Inst::FloatAlu { dst, lhs, rhs, .. } | Inst::FCmp { dst, lhs, rhs, .. } => {
+                f(lhs, RegUse::Use);
+                f(rhs, RegUse::Use);
+                f(dst, RegUse::Def);
+            }
+            Inst::Lea { dst, addr } | Inst::Load { dst, addr, .. } => {
+                addr.visit_regs(f);
+                f(dst, RegUse::Def);
+            }
+            Inst::Store { src, addr, .. } => {
+                f(src, RegUse::Use);
+                addr.visit_regs(f);
+            }
+            Inst::Beqz { src, .. } | Inst::Bnez { src, .. } => f(src, RegUse::Use),
+            Inst::Li { dst, .. } | Inst::La { dst, .. } => f(dst, RegUse::Def),
+            Inst::Call { target: CallTarget::Indirect(target), .. } => f(target, RegUse::Use),
+            Inst::J { .. } | Inst::Call { .. } | Inst::Ret | Inst::Unimp => {}
+        }
+    }
+
+    fn visit_fixed_regs(&self, f: &mut dyn FnMut(PReg, RegUse)) {
+        if let Inst::Call { args, .. } = self {
+            args.iter().for_each(|arg| f(*arg, RegUse::Use));
+        }
+    }
+
+    fn clobbers(&self) -> &'static [PReg] {
+        match self {
+            Inst::Call { .. } => &CALLER_SAVED,
+            _ => &[],
+        }
+    }
+
+    fn is_terminator(&self) -> bool {
+        matches!(self, Inst::J { .. } | Inst::Ret | Inst::Unimp)
+    }
+
+    fn is_return(&self) -> bool {
+        *self == Inst::Ret
+    }
+
+    fn is_call(&self) -> bool {
+        matches!(self, Inst::Call { .. })
+    }
+
+    fn branch_target(&self) -> Option<&str> {
+        match self {
+            Inst::J { target } | Inst::Beqz { target, .. } | Inst::Bnez { target, .. } => Some(target),
+            _ => None,
+        }
+    }
+
+    fn as_copy(&self) -> Option<RegCopy<PReg>> {
+        match self {
+            Inst::Mov { dst, src, .. } => Some((*dst, *src)),
+            _ => None,
+        }
+    }
+}
+
+impl Inst {
+    /// Calls `f` on every memory operand of the instruction.
+    pub fn visit_mems(&mut self, f: &mut dyn FnMut(&mut Mem)) {
+        if let Inst::Lea { addr, .. } | Inst::Load { addr, .. } | Inst::Store { addr, .. } = self {
+            f(addr);
+        }
+    }
+}
+
+impl Display for Reg {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reg::Phys(reg) => write!(f, "{}", reg.name()),
+            Reg::Virt(reg) => write!(f, "%v{}", reg.index),
+        }
+    }
+}
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operand::Reg(reg) => write!(f, "{}", reg),
+            Operand::Imm(imm) => write!(f, "{}", imm),
+        }
+    }
+}
+
+impl Display for Base {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Base::Reg(reg) => write!(f, "{}", reg),
+            Base::Slot(slot) => write!(f, "slot{}", slot),
+            Base::ArgsEnd => write!(f, "args_end"),
+            Base::IncomingArgs => write!(f, "incoming_args"),
+        }
+    }
+}
+
+impl Display for Mem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}({})", self.offset, self.base)
+    }
+}
+
+/// Returns whether a register is a general purpose one, or a virtual one of that class.
+fn is_int(reg: &Reg) -> bool {
+    match reg {
+        Reg::Phys(reg) => reg.class() == RegClass::Int,
+        Reg::Virt(reg) => reg.class == RegClass::Int,
+    }
+}
+
+impl Display for Inst {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Inst::Mov { dst, src, .. } if is_int(dst) => write!(f, "mv {}, {}", dst, src),
+            Inst::Mov { size, dst, src } => write!(f, "fmv.{} {}, {}", size.float_suffix(), dst, src),
+            Inst::Li { dst, imm } => write!(f, "li {}, {}", dst, imm),
+            Inst::Alu { op, size, dst, lhs, rhs } => {
+                let mnemonic = match op {
+                    AluOp::Add => "add",
+                    AluOp::Sub => "sub",
+                    AluOp::And => "and",
+                    AluOp::Or => "or",
+                    AluOp::Xor => "xor",
+                    AluOp::Mul => "mul",
+                    AluOp::Div => "div",
+                    AluOp::Divu => "divu",
+                    AluOp::Rem => "rem",
+                    AluOp::Remu => "remu",
+                    AluOp::Sll => "sll",
+                    AluOp::Srl => "srl",
+                    AluOp::Sra => "sra",
+                    AluOp::Slt => "slt",
+                    AluOp::Sltu => "sltu",
+                };
+                let immediate = if matches!(rhs, Operand::Imm(_)) { "i" } else { "" };
+                // only arithmetic and shifts have forms working on the low 32 bits
+                let word = match op {
+                    AluOp::And | AluOp::Or | AluOp::Xor | AluOp::Slt | AluOp::Sltu => "",
+                    _ if size.register() == Size::Word => "w",
+                    _ => "",
+                };
+                // the immediate form of `sltu` is `sltiu`
+                if *op == AluOp::Sltu && !immediate.is_empty() {
+                    return write!(f, "sltiu {}, {}, {}", dst, lhs, rhs);
+                }
+                write!(f, "{}{}{} {}, {}, {}", mnemonic, immediate, word, dst, lhs, rhs)
+            }
+            Inst::Neg { size, dst, src } => {
+                let mnemonic = if size.register() == Size::Word { "negw" } else { "neg" };
+                write!(f, "{} {}, {}", mnemonic, dst, src)
+            }
+            Inst::Seqz { dst, src } => write!(f, "seqz {}, {}", dst, src),
+            Inst::Snez { dst, src } => write!(f, "snez {}, {}", dst, src),
+            Inst::Lea { dst, addr } => write!(f, "addi {}, {}, {}", dst, addr.base, addr.offset),
+            Inst::Load { size, signed, dst, addr } => {
+                let mnemonic = match (is_int(dst), size, signed) {
+                    (true, Size::Byte, true) => "lb",
+                    (true, Size::Byte, false) => "lbu",
+                    (true, Size::Half, true) => "lh",
+                    (true, Size::Half, false) => "lhu",
+                    (true, Size::Word, true) => "lw",
+                    (true, Size::Word, false) => "lwu",
+                    (true, Size::Double, _) => "ld",
+                    (false, Size::Double, _) => "fld",
+                    (false, _, _) => "flw",
+                };
+                write!(f, "{} {}, {}", mnemonic, dst, addr)
+            }
+            Inst::Store { size, src, addr } => {
+                let mnemonic = match (is_int(src), size) {
+                    (true, Size::Byte) => "sb",
+                    (true, Size::Half) => "sh",
+                    (true, Size::Word) => "sw",
+                    (true, Size::Double) => "sd",
+                    (false, Size::Double) => "fsd",
+                    (false, _) => "fsw",
+                };
+                write!(f, "{} {}, {}", mnemonic, src, addr)
+            }
+            Inst::La { dst, symbol, reloc } => {
+                let mnemonic = match reloc {
+                    SymbolReloc::Local => "lla",
+                    SymbolReloc::Got => "la",
+                    SymbolReloc::TlsIe => "la.tls.ie",
+                };
+                write!(f, "{} {}, {}", mnemonic, dst, symbol)
+            }
+            Inst::J { target } => write!(f, "j {}", target),
+            Inst::Beqz { src, target } => write!(f, "beqz {}, {}", src, target),
+            Inst::Bnez { src, target } => write!(f, "bnez {}, {}", src, target),
+            Inst::Call { target: CallTarget::Symbol(symbol), .. } => write!(f, "call {}", symbol),
+            Inst::Call { target: CallTarget::Indirect(target), .. } => write!(f, "jalr {}", target),
+            Inst::Ret => write!(f, "ret"),
+            Inst::Unimp => write!(f, "unimp"),
+            Inst::FloatAlu { op, size, dst, lhs, rhs } => {
+                let mnemonic = match op {
+                    FloatOp::Add => "fadd",
+                    FloatOp::Sub => "fsub",
+                    FloatOp::Mul => "fmul",
+                    FloatOp::Div => "fdiv",
+                };
+                write!(f, "{}.{} {}, {}, {}", mnemonic, size.float_suffix(), dst, lhs, rhs)
+            }
+            Inst::FNeg { size, dst, src } => write!(f, "fneg.{} {}, {}", size.float_suffix(), dst, src),
+            Inst::FCmp { cond, size, dst, lhs, rhs } => {
+                let mnemonic = match cond {
+                    FloatCond::Eq => "feq",
+                    FloatCond::Lt => "flt",
+                    FloatCond::Le => "fle",
+                };
+                write!(f, "{}.{} {}, {}, {}", mnemonic, size.float_suffix(), dst, lhs, rhs)
+            }
+            Inst::FMv { size, dst, src } => {
+                let width = if *size == Size::Double { "d" } else { "w" };
+                if is_int(dst) {
+                    write!(f, "fmv.x.{} {}, {}", width, dst, src)
+                } else {
+                    write!(f, "fmv.{}.x {}, {}", width, dst, src)
+                }
+            }
+            Inst::IntToFloat { signed, int_size, size, dst, src } => {
+                write!(f, "fcvt.{}.{} {}, {}", size.float_suffix(), int_size.int_suffix(*signed), dst, src)
+            }
+            Inst::FloatToInt { signed, int_size, size, dst, src } => {
+                write!(f, "fcvt.{}.{} {}, {}, rtz", int_size.int_suffix(*signed), size.float_suffix(), dst, src)
+            }
+            Inst::FCvt { size, dst, src } => {
+                let from = if *size == Size::Double { "s" } else { "d" };
+                write!(f, "fcvt.{}.{} {}, {}", size.float_suffix(), from, dst, src)
+            }
+        }
+    }
+}
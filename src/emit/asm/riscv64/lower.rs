@@ -0,0 +1,1080 @@
+use crate::emit::asm::riscv64::abi::{classify_call, ArgLocation, CallAbi, Piece, ReturnLocation, INDIRECT_RESULT_REG};
+use crate::emit::asm::riscv64::inst::{is_imm12, AluOp, Base, CallTarget, FloatCond, FloatOp, Inst, MachineBlock, MachineFunction, Mem, Operand, PReg, Reg, RegClass, Size, SymbolReloc, VReg};
+use crate::emit::asm::data::{constant_data, data_bytes, Datum};
+use crate::emit::asm::unsupported;
+use crate::ir::values::basic_block::BasicBlock;
+use crate::ir::values::constant::{Constant, Scalar};
+use crate::ir::values::function::Function;
+use crate::ir::values::instruction::{FloatPredicate, Instruction, InstructionType};
+use crate::ir::values::value::{Type, ValueEntity};
+use crate::targets::layout::DataLayout;
+use std::collections::HashMap;
+use std::io::Error;
+
+/// Returns the register class and width used to hold a value of type `ty`.
+pub fn scalar_type(ty: &Type) -> Result<(RegClass, Size), Error> {
+    match ty {
+        Type::Integer(1) | Type::Integer(8) => Ok((RegClass::Int, Size::Byte)),
+        Type::Integer(16) => Ok((RegClass::Int, Size::Half)),
+        Type::Integer(32) => Ok((RegClass::Int, Size::Word)),
+        Type::Integer(64) => Ok((RegClass::Int, Size::Double)),
+        Type::Pointer(_) | Type::FunctionType(_, _) => Ok((RegClass::Int, Size::Double)),
+        Type::Float(32) => Ok((RegClass::Float, Size::Word)),
+        Type::Float(64) => Ok((RegClass::Float, Size::Double)),
+        _ => Err(unsupported(format!("values of type {} are not supported by the riscv64 backend", ty))),
+    }
+}
+
+/// Returns the register class used for a value of type `ty`. Structs and
+/// arrays are held as the address of a frame slot containing the value.
+fn value_class(ty: &Type) -> Result<RegClass, Error> {
+    if is_aggregate(ty) {
+        Ok(RegClass::Int)
+    } else {
+        scalar_type(ty).map(|(class, _)| class)
+    }
+}
+
+fn is_aggregate(ty: &Type) -> bool {
+    ty.is_struct() || ty.is_array()
+}
+
+/// Sign-extends the low bits of a constant of type `ty`, or takes its lowest
+/// bit for booleans, the way the value is compared.
+fn normalize(imm: i64, ty: &Type) -> i64 {
+    match ty {
+        Type::Integer(1) => imm & 1,
+        Type::Integer(bits) if *bits < 64 => imm << (64 - bits) >> (64 - bits),
+        _ => imm,
+    }
+}
+
+/// Zero-extends the low bits of a constant of type `ty`.
+fn zero_extend(imm: i64, ty: &Type) -> i64 {
+    match ty {
+        Type::Integer(bits) if *bits < 64 => imm & ((1 << bits) - 1),
+        _ => imm,
+    }
+}
+
+/// The comparison an IR instruction makes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cond {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Returns the assembler label of a basic block.
+pub fn block_label(function: &str, block: &str) -> String {
+    format!(".L{}.{}", function, block.trim_start_matches('%'))
+}
+
+/// Lowers the IR of one function into RISC-V machine code over virtual registers.
+pub struct FunctionLowering<'a> {
+    func: &'a Function,
+    layout: &'a DataLayout,
+    mf: MachineFunction,
+    values: HashMap<String, VReg>,
+    phi_temps: HashMap<String, VReg>,
+    current: usize,
+    /// Holds the caller's result buffer when the function returns in memory.
+    sret: Option<VReg>,
+}
+
+impl<'a> FunctionLowering<'a> {
+    pub fn new(func: &'a Function, layout: &'a DataLayout) -> Self {
+        Self {
+            func,
+            layout,
+            mf: MachineFunction::new(&func.get_name()),
+            values: HashMap::new(),
+            phi_temps: HashMap::new(),
+            current: 0,
+            sret: None,
+        }
+    }
+
+    /// Returns how arguments and the return value are passed to this function.
+    pub fn abi(&self) -> Result<CallAbi, Error> {
+        let ty = self.func.get_type();
+        let args = ty.get_function_argument_types();
+        classify_call(self.layout, args, &ty.get_function_return_type(), args.len())
+    }
+
+    pub fn lower(mut self) -> Result<MachineFunction, Error> {
+        let blocks = self.func.get_blocks().clone();
+        let abi = self.abi()?;
+
+        // every value gets its register up front, so uses may precede definitions in block order
+        for param in self.func.get_params() {
+            let vreg = self.mf.new_vreg(value_class(&param.get_type())?);
+            self.values.insert(param.get_name(), vreg);
+        }
+        for block in &blocks {
+            for inst in block.borrow().get_instructions() {
+                let ValueEntity::Instruction(inst) = inst else {
+                    continue;
+                };
+                if inst.get_type().is_void() {
+                    continue;
+                }
+                let class = value_class(&inst.get_type())?;
+                let vreg = self.mf.new_vreg(class);
+                self.values.insert(inst.get_name(), vreg);
+                if let InstructionType::Phi(_) = inst.instruction_type() {
+                    let temp = self.mf.new_vreg(class);
+                    self.phi_temps.insert(inst.get_name(), temp);
+                }
+            }
+        }
+
+        for (i, block) in blocks.iter().enumerate() {
+            let block = block.borrow();
+            self.start_block(block_label(&self.func.get_name(), &block.get_name()), Some(block.get_name()));
+            if i == 0 && abi.ret == ReturnLocation::Memory {
+                let sret = self.mf.new_vreg(RegClass::Int);
+                self.emit(Inst::Mov { size: Size::Double, dst: sret.into(), src: INDIRECT_RESULT_REG.into() });
+                self.sret = Some(sret);
+            }
+            if i == 0 {
+                self.lower_params(&abi)?;
+            }
+            for inst in block.get_instructions() {
+                if let ValueEntity::Instruction(inst) = inst {
+                    self.lower_instruction(&block, inst)?;
+                }
+            }
+        }
+
+        // drop jumps to the block that follows anyway
+        for i in 0..self.mf.blocks.len().saturating_sub(1) {
+            let next = self.mf.blocks[i + 1].label.clone();
+            if let Some(Inst::J { target }) = self.mf.blocks[i].insts.last() {
+                if *target == next {
+                    self.mf.blocks[i].insts.pop();
+                }
+            }
+        }
+
+        Ok(self.mf)
+    }
+
+    fn start_block(&mut self, label: String, comment: Option<String>) {
+        self.mf.blocks.push(MachineBlock::new(label, comment));
+        self.current = self.mf.blocks.len() - 1;
+    }
+
+    fn emit(&mut self, inst: Inst) {
+        self.mf.blocks[self.current].insts.push(inst);
+    }
+
+    fn label(&self, block: &str) -> String {
+        block_label(&self.func.get_name(), block)
+    }
+
+    fn result(&self, inst: &Instruction) -> Result<VReg, Error> {
+        self.values.get(&inst.get_name()).copied()
+            .ok_or_else(|| unsupported(format!("instruction {} has no result register", inst.get_name())))
+    }
+
+    /// Puts the address of `symbol` in a fresh register: directly for
+    /// symbols the module defines, through the GOT for the rest.
+    fn symbol_address(&mut self, symbol: &str, external: bool) -> VReg {
+        let vreg = self.mf.new_vreg(RegClass::Int);
+        let reloc = if external { SymbolReloc::Got } else { SymbolReloc::Local };
+        self.emit(Inst::La { dst: vreg.into(), symbol: symbol.to_string(), reloc });
+        vreg
+    }
+
+    /// Returns the machine operand holding `value`, which is an immediate for constants.
+    fn operand(&mut self, value: &ValueEntity) -> Result<Operand, Error> {
+        match value {
+            ValueEntity::Instruction(inst) => self.values.get(&inst.get_name()).map(|vreg| Operand::Reg(Reg::Virt(*vreg)))
+                .ok_or_else(|| unsupported(format!("use of undefined value {}", inst.get_name()))),
+            ValueEntity::Constant(constant) => self.constant(constant),
+            ValueEntity::Function(function) => Ok(self.symbol_address(&function.get_name(), function.is_external()).into()),
+            ValueEntity::GlobalVariable(global) => {
+                if !global.is_thread_local() {
+                    return Ok(self.symbol_address(&global.get_name(), global.is_external()).into());
+                }
+                let vreg = self.mf.new_vreg(RegClass::Int);
+                self.emit(Inst::La { dst: vreg.into(), symbol: global.get_name(), reloc: SymbolReloc::TlsIe });
+                self.emit(Inst::Alu { op: AluOp::Add, size: Size::Double, dst: vreg.into(), lhs: vreg.into(), rhs: PReg::Tp.into() });
+                Ok(vreg.into())
+            }
+            ValueEntity::Argument(argument) => self.values.get(&argument.get_name()).map(|vreg| Operand::Reg(Reg::Virt(*vreg)))
+                .ok_or_else(|| unsupported(format!("use of argument {} outside its function", argument.get_name()))),
+            ValueEntity::BasicBlock(block) => Err(unsupported(format!("basic block {} used as a value", block.get_name()))),
+        }
+    }
+
+    /// Returns the machine operand holding a constant; aggregates are built in
+    /// a frame slot. Addresses go through the GOT, since the symbol may be
+    /// defined in another module.
+    fn constant(&mut self, constant: &Constant) -> Result<Operand, Error> {
+        let ty = constant.get_type();
+        if is_aggregate(&ty) {
+            let data = constant_data(self.layout, &ty, constant)?;
+            let dst = self.mf.new_vreg(RegClass::Int);
+            self.aggregate_slot(dst, &ty);
+            self.store_data(dst, &data);
+            return Ok(dst.into());
+        }
+        match constant.fold() {
+            Some(Scalar::Int(value)) if value.bits() <= 64 => Ok(Operand::Imm(value.to_machine_i64())),
+            // there are no float immediates, so the bits go through an integer register
+            Some(Scalar::Float(bits, value)) => {
+                let (size, bits) = match bits {
+                    32 => (Size::Word, (f64::from_bits(value) as f32).to_bits() as i64),
+                    _ => (Size::Double, value as i64),
+                };
+                let src = self.imm_reg(bits);
+                let vreg = self.mf.new_vreg(RegClass::Float);
+                self.emit(Inst::FMv { size, dst: vreg.into(), src });
+                Ok(Operand::Reg(vreg.into()))
+            }
+            Some(Scalar::Address(symbol, offset)) => Ok(self.constant_address(&symbol, offset).into()),
+            _ => Err(unsupported(format!("constant {} of type {}", constant, ty))),
+        }
+    }
+
+    fn constant_address(&mut self, symbol: &str, offset: i64) -> VReg {
+        let vreg = self.symbol_address(symbol, true);
+        if offset != 0 {
+            let offset = self.imm_reg(offset);
+            self.emit(Inst::Alu { op: AluOp::Add, size: Size::Double, dst: vreg.into(), lhs: vreg.into(), rhs: offset.into() });
+        }
+        vreg
+    }
+
+    /// Stores the contents of a constant to the address in `dst`.
+    fn store_data(&mut self, dst: VReg, data: &[Datum]) {
+        let (bytes, symbols) = data_bytes(data);
+        let mut offset = 0;
+        for chunk in [Size::Double, Size::Word, Size::Half, Size::Byte] {
+            let len = chunk.bytes() as usize;
+            while bytes.len() - offset >= len {
+                let mut value = [0; 8];
+                value[..len].copy_from_slice(&bytes[offset..offset + len]);
+                let src = self.imm_reg(i64::from_le_bytes(value));
+                self.emit(Inst::Store { size: chunk, src, addr: Mem::base(dst, offset as i64) });
+                offset += len;
+            }
+        }
+        for (offset, size, symbol, addend) in symbols {
+            let address = self.constant_address(&symbol, addend);
+            let size = if size == 8 { Size::Double } else { Size::Word };
+            self.emit(Inst::Store { size, src: address.into(), addr: Mem::base(dst, offset as i64) });
+        }
+    }
+
+    /// Moves the arguments from where the caller put them into their registers.
+    fn lower_params(&mut self, abi: &CallAbi) -> Result<(), Error> {
+        for (param, location) in self.func.get_params().iter().zip(&abi.args) {
+            let ty = param.get_type();
+            let dst = self.values[&param.get_name()];
+            self.receive(dst, &ty, location)?;
+        }
+        Ok(())
+    }
+
+    /// Moves one argument from where the caller put it into `dst`.
+    fn receive(&mut self, dst: VReg, ty: &Type, location: &ArgLocation) -> Result<(), Error> {
+        let incoming = |offset: u64| Mem { base: Base::IncomingArgs, offset: offset as i64 };
+        match location {
+            ArgLocation::Regs(pieces) if is_aggregate(ty) => {
+                self.aggregate_slot(dst, ty);
+                for piece in pieces {
+                    self.store_piece(*piece, dst.into());
+                }
+            }
+            ArgLocation::Regs(pieces) => {
+                let (class, size) = scalar_type(ty)?;
+                let src = pieces[0].reg;
+                if class != src.class() {
+                    // floating point values that ran out of their own registers
+                    self.emit(Inst::FMv { size, dst: dst.into(), src: src.into() });
+                } else {
+                    self.copy(class, size, dst.into(), src.into());
+                }
+            }
+            // aggregates passed in memory are the callee's own copy
+            ArgLocation::Stack { offset, .. } if is_aggregate(ty) => self.emit(Inst::Lea { dst: dst.into(), addr: incoming(*offset) }),
+            ArgLocation::Stack { offset, .. } => {
+                let (_, size) = scalar_type(ty)?;
+                self.emit(Inst::Load { size, signed: false, dst: dst.into(), addr: incoming(*offset) });
+            }
+            ArgLocation::Split { piece, offset } => {
+                self.aggregate_slot(dst, ty);
+                self.store_piece(*piece, dst.into());
+                let size = self.layout.size_of(ty);
+                self.copy_bytes_to(Mem::base(dst, 8), incoming(*offset), size - 8);
+            }
+            // so are the copies passed by reference
+            ArgLocation::Reference(pointer) => self.receive(dst, &Type::Pointer(Box::new(ty.clone())), pointer)?,
+            ArgLocation::Ignore => self.aggregate_slot(dst, ty),
+        }
+        Ok(())
+    }
+
+    /// Like `operand`, but immediates are first moved into a fresh register.
+    fn reg(&mut self, value: &ValueEntity) -> Result<Reg, Error> {
+        match self.operand(value)? {
+            Operand::Reg(reg) => Ok(reg),
+            Operand::Imm(imm) => Ok(self.imm_reg(imm)),
+        }
+    }
+
+    fn imm_reg(&mut self, imm: i64) -> Reg {
+        let vreg = self.mf.new_vreg(RegClass::Int);
+        self.emit(Inst::Li { dst: vreg.into(), imm });
+        vreg.into()
+    }
+
+    /// Sign- or zero-extends the low `from` bits of `src` into all 64 bits of `dst`.
+    fn extend(&mut self, signed: bool, from: Size, dst: Reg, src: Reg) {
+        let shift = 64 - 8 * from.bytes() as i64;
+        let (op, size) = match (signed, from) {
+            (_, Size::Double) => return self.emit(Inst::Mov { size: Size::Double, dst, src }),
+            (true, Size::Word) => return self.emit(Inst::Alu { op: AluOp::Add, size: Size::Word, dst, lhs: src, rhs: Operand::Imm(0) }),
+            (false, Size::Byte) => return self.emit(Inst::Alu { op: AluOp::And, size: Size::Double, dst, lhs: src, rhs: Operand::Imm(0xff) }),
+            (true, _) => (AluOp::Sra, Size::Double),
+            (false, _) => (AluOp::Srl, Size::Double),
+        };
+        self.emit(Inst::Alu { op: AluOp::Sll, size, dst, lhs: src, rhs: Operand::Imm(shift) });
+        self.emit(Inst::Alu { op, size, dst, lhs: dst, rhs: Operand::Imm(shift) });
+    }
+
+    /// Returns a register holding `value` with all 64 bits defined: sign- or
+    /// zero-extended if it is narrower, and always zero-extended for booleans.
+    fn extended(&mut self, value: &ValueEntity, ty: &Type, signed: bool) -> Result<Reg, Error> {
+        let (_, size) = scalar_type(ty)?;
+        let signed = signed && *ty != Type::Integer(1);
+        match self.operand(value)? {
+            Operand::Imm(imm) if signed => Ok(self.imm_reg(normalize(imm, ty))),
+            Operand::Imm(imm) => Ok(self.imm_reg(zero_extend(imm, ty))),
+            Operand::Reg(reg) if size < Size::Double => {
+                let extended = self.mf.new_vreg(RegClass::Int);
+                self.extend(signed, size, extended.into(), reg);
+                Ok(extended.into())
+            }
+            Operand::Reg(reg) => Ok(reg),
+        }
+    }
+
+
+    fn copy(&mut self, class: RegClass, size: Size, dst: Reg, src: Operand) {
+        match src {
+            Operand::Reg(src) => self.emit(Inst::Mov { size: if class == RegClass::Int { Size::Double } else { size }, dst, src }),
+            Operand::Imm(imm) => self.emit(Inst::Li { dst, imm }),
+        }
+    }
+
+    fn lower_instruction(&mut self, block: &BasicBlock, inst: &Instruction) -> Result<(), Error> {
+        match inst.instruction_type() {
+            InstructionType::Add(a, b) => self.lower_alu(inst, AluOp::Add, a, b),
+            InstructionType::Sub(a, b) => self.lower_alu(inst, AluOp::Sub, a, b),
+            InstructionType::Mul(a, b) => self.lower_alu(inst, AluOp::Mul, a, b),
+            InstructionType::And(a, b) => self.lower_alu(inst, AluOp::And, a, b),
+            InstructionType::Or(a, b) => self.lower_alu(inst, AluOp::Or, a, b),
+            InstructionType::Xor(a, b) => self.lower_alu(inst, AluOp::Xor, a, b),
+            InstructionType::SDiv(a, b) => self.lower_div(inst, AluOp::Div, a, b),
+            InstructionType::UDiv(a, b) => self.lower_div(inst, AluOp::Divu, a, b),
+            InstructionType::SRem(a, b) => self.lower_div(inst, AluOp::Rem, a, b),
+            InstructionType::URem(a, b) => self.lower_div(inst, AluOp::Remu, a, b),
+            InstructionType::Shl(a, b) => self.lower_shift(inst, AluOp::Sll, a, b),
+            InstructionType::AShr(a, b) => self.lower_shift(inst, AluOp::Sra, a, b),
+            InstructionType::LShr(a, b) => self.lower_shift(inst, AluOp::Srl, a, b),
+            InstructionType::Eq(a, b) => self.lower_compare(inst, Cond::Eq, true, a, b),
+            InstructionType::Ne(a, b) => self.lower_compare(inst, Cond::Ne, true, a, b),
+            InstructionType::SLt(a, b) => self.lower_compare(inst, Cond::Lt, true, a, b),
+            InstructionType::SLe(a, b) => self.lower_compare(inst, Cond::Le, true, a, b),
+            InstructionType::SGt(a, b) => self.lower_compare(inst, Cond::Gt, true, a, b),
+            InstructionType::SGe(a, b) => self.lower_compare(inst, Cond::Ge, true, a, b),
+            InstructionType::ULt(a, b) => self.lower_compare(inst, Cond::Lt, false, a, b),
+            InstructionType::ULe(a, b) => self.lower_compare(inst, Cond::Le, false, a, b),
+            InstructionType::UGt(a, b) => self.lower_compare(inst, Cond::Gt, false, a, b),
+            InstructionType::UGe(a, b) => self.lower_compare(inst, Cond::Ge, false, a, b),
+            InstructionType::Neg(a) => {
+                let (_, size) = scalar_type(&a.get_type())?;
+                let dst = self.result(inst)?;
+                let src = self.reg(a)?;
+                self.emit(Inst::Neg { size: size.register(), dst: dst.into(), src });
+                Ok(())
+            }
+            InstructionType::FAdd(a, b, _) => self.lower_float(inst, FloatOp::Add, a, b),
+            InstructionType::FSub(a, b, _) => self.lower_float(inst, FloatOp::Sub, a, b),
+            InstructionType::FMul(a, b, _) => self.lower_float(inst, FloatOp::Mul, a, b),
+            InstructionType::FDiv(a, b, _) => self.lower_float(inst, FloatOp::Div, a, b),
+            InstructionType::FRem(a, b, _) => {
+                // there is no remainder instruction, so it comes from libm
+                let (_, size) = scalar_type(&a.get_type())?;
+                let dst = self.result(inst)?;
+                let lhs = self.reg(a)?;
+                let rhs = self.reg(b)?;
+                self.emit(Inst::Mov { size, dst: PReg::Fa0.into(), src: lhs });
+                self.emit(Inst::Mov { size, dst: PReg::Fa1.into(), src: rhs });
+                let symbol = if size == Size::Word { "fmodf" } else { "fmod" };
+                self.emit(Inst::Call { target: CallTarget::Symbol(symbol.to_string()), args: vec![PReg::Fa0, PReg::Fa1] });
+                self.emit(Inst::Mov { size, dst: dst.into(), src: PReg::Fa0.into() });
+                Ok(())
+            }
+            InstructionType::FNeg(a, _) => {
+                let (_, size) = scalar_type(&a.get_type())?;
+                let dst = self.result(inst)?;
+                let src = self.reg(a)?;
+                self.emit(Inst::FNeg { size, dst: dst.into(), src });
+                Ok(())
+            }
+            InstructionType::FCmp(predicate, a, b, _) => self.lower_float_compare(inst, *predicate, a, b),
+            InstructionType::Not(a) => {
+                let dst = self.result(inst)?;
+                let src = self.reg(a)?;
+                // booleans stay 0 or 1
+                let rhs = Operand::Imm(if a.get_type() == Type::Integer(1) { 1 } else { -1 });
+                self.emit(Inst::Alu { op: AluOp::Xor, size: Size::Double, dst: dst.into(), lhs: src, rhs });
+                Ok(())
+            }
+            InstructionType::Trunc(a) | InstructionType::ZExt(a) | InstructionType::SExt(a) | InstructionType::FPTrunc(a)
+            | InstructionType::FPExt(a) | InstructionType::FPToSI(a) | InstructionType::FPToUI(a) | InstructionType::SIToFP(a)
+            | InstructionType::UIToFP(a) | InstructionType::PtrToInt(a) | InstructionType::IntToPtr(a) | InstructionType::Bitcast(a) => {
+                let (opcode, _) = inst.instruction_type().as_cast().unwrap();
+                self.lower_cast(inst, opcode, a)
+            }
+            InstructionType::Alloca(ty, count, align) => self.lower_alloca(inst, ty, count.as_deref(), *align),
+            InstructionType::GetElementPtr(ptr, indices) => self.lower_element_address(inst, ptr, indices),
+            InstructionType::Load(ptr) => {
+                let dst = self.result(inst)?;
+                let ptr = self.reg(ptr)?;
+                if is_aggregate(&inst.get_type()) {
+                    let size = self.layout.size_of(&inst.get_type());
+                    self.aggregate_slot(dst, &inst.get_type());
+                    self.copy_bytes(dst.into(), ptr, size);
+                    return Ok(());
+                }
+                let (_, size) = scalar_type(&inst.get_type())?;
+                self.emit(Inst::Load { size, signed: false, dst: dst.into(), addr: Mem::base(ptr, 0) });
+                Ok(())
+            }
+            InstructionType::Store(ptr, value) => {
+                let ptr = self.reg(ptr)?;
+                if is_aggregate(&value.get_type()) {
+                    let src = self.reg(value)?;
+                    self.copy_bytes(ptr, src, self.layout.size_of(&value.get_type()));
+                    return Ok(());
+                }
+                let (_, size) = scalar_type(&value.get_type())?;
+                let src = self.reg(value)?;
+                self.emit(Inst::Store { size, src, addr: Mem::base(ptr, 0) });
+                Ok(())
+            }
+            InstructionType::Call(callee, args) => self.lower_call(inst, callee, args),
+            InstructionType::Return(value) => {
+                match self.abi()?.ret {
+                    ReturnLocation::Memory => {
+                        let sret = self.sret.unwrap();
+                        let src = self.reg(value)?;
+                        self.copy_bytes(sret.into(), src, self.layout.size_of(&value.get_type()));
+                    }
+                    ReturnLocation::Regs(pieces) if is_aggregate(&value.get_type()) => {
+                        let src = self.reg(value)?;
+                        for piece in pieces {
+                            self.load_piece(piece, src);
+                        }
+                    }
+                    ReturnLocation::Regs(pieces) => {
+                        let src = self.operand(value)?;
+                        self.move_to_arg_reg(&value.get_type(), pieces[0].reg, src);
+                    }
+                    ReturnLocation::Void => {}
+                }
+                self.emit(Inst::Ret);
+                Ok(())
+            }
+            InstructionType::VoidReturn => {
+                self.emit(Inst::Ret);
+                Ok(())
+            }
+            InstructionType::Branch(target) => {
+                self.emit_phi_copies(block, &target.get_name())?;
+                let target = self.label(&target.get_name());
+                self.emit(Inst::J { target });
+                Ok(())
+            }
+            InstructionType::BranchIf(cond, if_true, if_false) => {
+                let if_true = if_true.borrow().get_name();
+                let if_false = if_false.borrow().get_name();
+                self.lower_branch_if(block, cond, &if_true, &if_false)
+            }
+            InstructionType::Phi(_) => {
+                let (class, size) = self.register_type(&inst.get_type())?;
+                let dst = self.result(inst)?;
+                let temp = self.phi_temps[&inst.get_name()];
+                self.copy(class, size, dst.into(), temp.into());
+                Ok(())
+            }
+            InstructionType::Unreachable => {
+                self.emit(Inst::Unimp);
+                Ok(())
+            }
+        }
+    }
+
+    /// Adds the constant offset and the scaled indices of a `getelementptr` to its pointer.
+    fn lower_element_address(&mut self, inst: &Instruction, ptr: &ValueEntity, indices: &[Box<ValueEntity>]) -> Result<(), Error> {
+        let dst = self.result(inst)?;
+        let (offset, scaled) = self.layout.indexed_offset(&ptr.get_type().get_pointer_element_type(), indices)
+            .ok_or_else(|| unsupported(format!("`{}` has indices its type can't take", inst)))?;
+        let base = self.reg(ptr)?;
+        let offset = if is_imm12(offset) { Operand::Imm(offset) } else { self.imm_reg(offset).into() };
+        self.emit(Inst::Alu { op: AluOp::Add, size: Size::Double, dst: dst.into(), lhs: base, rhs: offset });
+        for (index, stride) in scaled {
+            let ty = index.get_type();
+            let (_, size) = scalar_type(&ty)?;
+            let mut reg = self.reg(index)?;
+            if size != Size::Double {
+                let wide = self.mf.new_vreg(RegClass::Int);
+                // a boolean is 0 or 1, so it is negated rather than sign-extended
+                let is_bool = ty == Type::Integer(1);
+                self.extend(!is_bool, size, wide.into(), reg);
+                if is_bool {
+                    self.emit(Inst::Neg { size: Size::Double, dst: wide.into(), src: wide.into() });
+                }
+                reg = wide.into();
+            }
+            if stride != 1 {
+                let stride = self.imm_reg(stride as i64);
+                let product = self.mf.new_vreg(RegClass::Int);
+                self.emit(Inst::Alu { op: AluOp::Mul, size: Size::Double, dst: product.into(), lhs: reg, rhs: stride.into() });
+                reg = product.into();
+            }
+            self.emit(Inst::Alu { op: AluOp::Add, size: Size::Double, dst: dst.into(), lhs: dst.into(), rhs: reg.into() });
+        }
+        Ok(())
+    }
+
+    fn lower_alloca(&mut self, inst: &Instruction, ty: &Type, count: Option<&ValueEntity>, align: u64) -> Result<(), Error> {
+        let dst = self.result(inst)?;
+        let stride = self.layout.stride_of(ty);
+        // the frame only guarantees 16-byte alignment, anything stricter is done by hand
+        let padding = align.saturating_sub(16);
+        let (count, count_type) = match count {
+            Some(count) => (self.operand(count)?, count.get_type()),
+            None => (Operand::Imm(1), Type::Integer(64)),
+        };
+        // where an over-aligned address is rounded down from
+        let offset = if padding > 0 { align as i64 - 1 } else { 0 };
+        let mask = if padding > 0 { self.imm12_operand(-(align as i64)) } else { Operand::Imm(0) };
+
+        match count {
+            Operand::Imm(count) => {
+                let size = (count as u64 * stride).max(1);
+                if padding == 0 {
+                    let slot = self.mf.new_slot(size, align);
+                    self.emit(Inst::Lea { dst: dst.into(), addr: Mem::slot(slot, 0) });
+                } else {
+                    let slot = self.mf.new_slot(size + padding, 16);
+                    self.emit(Inst::Lea { dst: dst.into(), addr: Mem::slot(slot, offset) });
+                    self.emit(Inst::Alu { op: AluOp::And, size: Size::Double, dst: dst.into(), lhs: dst.into(), rhs: mask });
+                }
+            }
+            Operand::Reg(count) => {
+                let (_, count_size) = scalar_type(&count_type)?;
+                let bytes = self.mf.new_vreg(RegClass::Int);
+                self.extend(false, count_size, bytes.into(), count);
+                let stride_reg = self.imm_reg(stride as i64);
+                self.emit(Inst::Alu { op: AluOp::Mul, size: Size::Double, dst: bytes.into(), lhs: bytes.into(), rhs: stride_reg.into() });
+                // keep sp a multiple of 16
+                let round = self.imm12_operand(15 + padding as i64);
+                self.emit(Inst::Alu { op: AluOp::Add, size: Size::Double, dst: bytes.into(), lhs: bytes.into(), rhs: round });
+                self.emit(Inst::Alu { op: AluOp::And, size: Size::Double, dst: bytes.into(), lhs: bytes.into(), rhs: Operand::Imm(-16) });
+                self.emit(Inst::Alu { op: AluOp::Sub, size: Size::Double, dst: PReg::Sp.into(), lhs: PReg::Sp.into(), rhs: bytes.into() });
+                self.emit(Inst::Lea { dst: dst.into(), addr: Mem { base: Base::ArgsEnd, offset } });
+                if padding > 0 {
+                    self.emit(Inst::Alu { op: AluOp::And, size: Size::Double, dst: dst.into(), lhs: dst.into(), rhs: mask });
+                }
+                self.mf.dynamic_stack = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns `imm` as an operand if it fits a 12-bit immediate, or a register holding it.
+    fn imm12_operand(&mut self, imm: i64) -> Operand {
+        if is_imm12(imm) {
+            Operand::Imm(imm)
+        } else {
+            self.imm_reg(imm).into()
+        }
+    }
+
+    fn lower_alu(&mut self, inst: &Instruction, op: AluOp, a: &ValueEntity, b: &ValueEntity) -> Result<(), Error> {
+        let ty = a.get_type();
+        let (_, size) = scalar_type(&ty)?;
+        let dst = self.result(inst)?;
+        let lhs = self.reg(a)?;
+        let (op, rhs) = match (op, self.operand(b)?) {
+            // there is no subtraction of an immediate, only addition of its negation
+            (AluOp::Sub, Operand::Imm(imm)) if is_imm12(-normalize(imm, &ty)) => (AluOp::Add, Operand::Imm(-normalize(imm, &ty))),
+            (AluOp::Add | AluOp::And | AluOp::Or | AluOp::Xor, Operand::Imm(imm)) if is_imm12(normalize(imm, &ty)) => (op, Operand::Imm(normalize(imm, &ty))),
+            (_, Operand::Imm(imm)) => (op, self.imm_reg(imm).into()),
+            (_, rhs) => (op, rhs),
+        };
+        self.emit(Inst::Alu { op, size: size.register(), dst: dst.into(), lhs, rhs });
+        Ok(())
+    }
+
+    /// Lowers the conversion `opcode` of `a` to the type of `inst`. Integers
+    /// narrower than 32 bits are extended to 64 before they turn into floats,
+    /// and booleans are kept to 0 or 1.
+    fn lower_cast(&mut self, inst: &Instruction, opcode: &str, a: &ValueEntity) -> Result<(), Error> {
+        let (from, to) = (a.get_type(), inst.get_type());
+        let (from_class, from_size) = scalar_type(&from)?;
+        let (to_class, to_size) = scalar_type(&to)?;
+        let dst = self.result(inst)?;
+        let src = self.reg(a)?;
+        let is_bool = to == Type::Integer(1);
+        match opcode {
+            "trunc" | "zext" | "ptrtoint" | "inttoptr" if from_size >= to_size => {
+                if is_bool {
+                    self.emit(Inst::Alu { op: AluOp::And, size: Size::Double, dst: dst.into(), lhs: src, rhs: Operand::Imm(1) });
+                } else {
+                    self.emit(Inst::Mov { size: Size::Double, dst: dst.into(), src });
+                }
+            }
+            "zext" | "sext" | "ptrtoint" | "inttoptr" => self.extend_int(opcode == "sext", &from, dst.into(), src),
+            "fptrunc" | "fpext" => self.emit(Inst::FCvt { size: to_size, dst: dst.into(), src }),
+            "sitofp" | "uitofp" => {
+                let signed = opcode == "sitofp";
+                let (src, int_size) = if from_size < Size::Word {
+                    let wide = self.mf.new_vreg(RegClass::Int);
+                    self.extend_int(signed, &from, wide.into(), src);
+                    (wide.into(), Size::Double)
+                } else {
+                    (src, from_size)
+                };
+                self.emit(Inst::IntToFloat { signed, int_size, size: to_size, dst: dst.into(), src });
+            }
+            "fptosi" | "fptoui" => {
+                self.emit(Inst::FloatToInt { signed: opcode == "fptosi", int_size: to_size.register(), size: from_size, dst: dst.into(), src });
+                if is_bool {
+                    self.emit(Inst::Alu { op: AluOp::And, size: Size::Double, dst: dst.into(), lhs: dst.into(), rhs: Operand::Imm(1) });
+                }
+            }
+            // bitcasts
+            _ if from_class != to_class => self.emit(Inst::FMv { size: to_size, dst: dst.into(), src }),
+            _ => self.copy(from_class, to_size, dst.into(), src.into()),
+        }
+        Ok(())
+    }
+
+    /// Sign- or zero-extends `src`, an integer of type `ty`, into all 64 bits
+    /// of `dst`. A boolean is 0 or 1, so it is negated rather than sign-extended.
+    fn extend_int(&mut self, signed: bool, ty: &Type, dst: Reg, src: Reg) {
+        let Ok((_, from)) = scalar_type(ty) else {
+            return;
+        };
+        if *ty == Type::Integer(1) {
+            self.extend(false, from, dst, src);
+            if signed {
+                self.emit(Inst::Neg { size: Size::Double, dst, src: dst });
+            }
+        } else {
+            self.extend(signed, from, dst, src);
+        }
+    }
+
+    fn lower_float(&mut self, inst: &Instruction, op: FloatOp, a: &ValueEntity, b: &ValueEntity) -> Result<(), Error> {
+        let (_, size) = scalar_type(&a.get_type())?;
+        let dst = self.result(inst)?;
+        let lhs = self.reg(a)?;
+        let rhs = self.reg(b)?;
+        self.emit(Inst::FloatAlu { op, size, dst: dst.into(), lhs, rhs });
+        Ok(())
+    }
+
+    /// Lowers a float comparison. `feq`, `flt` and `fle` are all false for
+    /// unordered operands, so the unordered predicates are the inverses of
+    /// ordered ones.
+    fn lower_float_compare(&mut self, inst: &Instruction, predicate: FloatPredicate, a: &ValueEntity, b: &ValueEntity) -> Result<(), Error> {
+        let (_, size) = scalar_type(&a.get_type())?;
+        let dst = self.result(inst)?;
+        let lhs = self.reg(a)?;
+        let rhs = self.reg(b)?;
+        let ordered = if predicate.is_ordered() { predicate } else { predicate.inverse() };
+        match ordered {
+            FloatPredicate::Oeq => self.emit(Inst::FCmp { cond: FloatCond::Eq, size, dst: dst.into(), lhs, rhs }),
+            FloatPredicate::Olt => self.emit(Inst::FCmp { cond: FloatCond::Lt, size, dst: dst.into(), lhs, rhs }),
+            FloatPredicate::Ole => self.emit(Inst::FCmp { cond: FloatCond::Le, size, dst: dst.into(), lhs, rhs }),
+            FloatPredicate::Ogt => self.emit(Inst::FCmp { cond: FloatCond::Lt, size, dst: dst.into(), lhs: rhs, rhs: lhs }),
+            FloatPredicate::Oge => self.emit(Inst::FCmp { cond: FloatCond::Le, size, dst: dst.into(), lhs: rhs, rhs: lhs }),
+            FloatPredicate::One | FloatPredicate::Ord => {
+                let (cond, op, first, second) = if ordered == FloatPredicate::One {
+                    (FloatCond::Lt, AluOp::Or, (lhs, rhs), (rhs, lhs))
+                } else {
+                    (FloatCond::Eq, AluOp::And, (lhs, lhs), (rhs, rhs))
+                };
+                let other = self.mf.new_vreg(RegClass::Int);
+                self.emit(Inst::FCmp { cond, size, dst: dst.into(), lhs: first.0, rhs: first.1 });
+                self.emit(Inst::FCmp { cond, size, dst: other.into(), lhs: second.0, rhs: second.1 });
+                self.emit(Inst::Alu { op, size: Size::Double, dst: dst.into(), lhs: dst.into(), rhs: other.into() });
+            }
+            _ => unreachable!(),
+        }
+        if !predicate.is_ordered() {
+            self.emit(Inst::Alu { op: AluOp::Xor, size: Size::Double, dst: dst.into(), lhs: dst.into(), rhs: Operand::Imm(1) });
+        }
+        Ok(())
+    }
+
+    /// Lowers a division or remainder. The 32-bit forms read only the low half
+    /// of their operands, narrower ones need them sign- or zero-extended.
+    fn lower_div(&mut self, inst: &Instruction, op: AluOp, a: &ValueEntity, b: &ValueEntity) -> Result<(), Error> {
+        let ty = a.get_type();
+        let (_, size) = scalar_type(&ty)?;
+        let dst = self.result(inst)?;
+        let signed = matches!(op, AluOp::Div | AluOp::Rem);
+        let (lhs, rhs) = if size < Size::Word {
+            (self.extended(a, &ty, signed)?, self.extended(b, &ty, signed)?)
+        } else {
+            (self.reg(a)?, self.reg(b)?)
+        };
+        self.emit(Inst::Alu { op, size: size.register(), dst: dst.into(), lhs, rhs: rhs.into() });
+        Ok(())
+    }
+
+    fn lower_shift(&mut self, inst: &Instruction, op: AluOp, a: &ValueEntity, b: &ValueEntity) -> Result<(), Error> {
+        let ty = a.get_type();
+        let (_, size) = scalar_type(&ty)?;
+        let dst = self.result(inst)?;
+        // shifting right brings in bits from above the value, which have to be its sign or zeros
+        let lhs = if op != AluOp::Sll && size < Size::Word { self.extended(a, &ty, op == AluOp::Sra)? } else { self.reg(a)? };
+        let amount = match self.operand(b)? {
+            Operand::Imm(amount) => Operand::Imm((amount as u64 % (size.bytes() * 8)) as i64),
+            amount => amount,
+        };
+        self.emit(Inst::Alu { op, size: size.register(), dst: dst.into(), lhs, rhs: amount });
+        Ok(())
+    }
+
+    /// Lowers a comparison, of integers read as signed ones unless `signed` is
+    /// false. Integers are sign-extended either way, which keeps their
+    /// unsigned order too.
+    fn lower_compare(&mut self, inst: &Instruction, cond: Cond, signed: bool, a: &ValueEntity, b: &ValueEntity) -> Result<(), Error> {
+        let ty = a.get_type();
+        scalar_type(&ty)?;
+        let dst = self.result(inst)?;
+        let lhs = self.extended(a, &ty, true)?;
+        if let Cond::Eq | Cond::Ne = cond {
+            let diff = match self.operand(b)? {
+                Operand::Imm(imm) if normalize(imm, &ty) == 0 => lhs,
+                Operand::Imm(imm) => {
+                    let rhs = self.imm12_operand(normalize(imm, &ty));
+                    let diff = self.mf.new_vreg(RegClass::Int);
+                    self.emit(Inst::Alu { op: AluOp::Xor, size: Size::Double, dst: diff.into(), lhs, rhs });
+                    diff.into()
+                }
+                Operand::Reg(_) => {
+                    let rhs = self.extended(b, &ty, true)?;
+                    let diff = self.mf.new_vreg(RegClass::Int);
+                    self.emit(Inst::Alu { op: AluOp::Xor, size: Size::Double, dst: diff.into(), lhs, rhs: rhs.into() });
+                    diff.into()
+                }
+            };
+            if cond == Cond::Eq {
+                self.emit(Inst::Seqz { dst: dst.into(), src: diff });
+            } else {
+                self.emit(Inst::Snez { dst: dst.into(), src: diff });
+            }
+            return Ok(());
+        }
+
+        // booleans are held as 0 or 1, but a true one is -1 when signed
+        let cond = match cond {
+            Cond::Lt if signed && ty == Type::Integer(1) => Cond::Gt,
+            Cond::Le if signed && ty == Type::Integer(1) => Cond::Ge,
+            Cond::Gt if signed && ty == Type::Integer(1) => Cond::Lt,
+            Cond::Ge if signed && ty == Type::Integer(1) => Cond::Le,
+            cond => cond,
+        };
+        let op = if signed && ty != Type::Integer(1) { AluOp::Slt } else { AluOp::Sltu };
+        let rhs = match (cond, self.operand(b)?) {
+            (Cond::Lt | Cond::Ge, Operand::Imm(imm)) if is_imm12(normalize(imm, &ty)) => Operand::Imm(normalize(imm, &ty)),
+            _ => self.extended(b, &ty, true)?.into(),
+        };
+        // `a > b` is `b < a`, and `>=` and `<=` are the inverses of `<` and `>`
+        match (cond, rhs) {
+            (Cond::Lt | Cond::Ge, rhs) => self.emit(Inst::Alu { op, size: Size::Double, dst: dst.into(), lhs, rhs }),
+            (_, Operand::Reg(rhs)) => self.emit(Inst::Alu { op, size: Size::Double, dst: dst.into(), lhs: rhs, rhs: lhs.into() }),
+            (_, Operand::Imm(_)) => unreachable!("only `<` and `>=` compare with immediates"),
+        }
+        if let Cond::Ge | Cond::Le = cond {
+            self.emit(Inst::Alu { op: AluOp::Xor, size: Size::Double, dst: dst.into(), lhs: dst.into(), rhs: Operand::Imm(1) });
+        }
+        Ok(())
+    }
+
+    fn lower_call(&mut self, inst: &Instruction, callee: &ValueEntity, args: &[Box<ValueEntity>]) -> Result<(), Error> {
+        let (callee_type, named) = match callee {
+            ValueEntity::Function(function) if function.is_var_arg() => {
+                let ty = function.get_type();
+                let named = ty.get_function_argument_types().len();
+                (ty, named)
+            }
+            ValueEntity::Function(function) => (function.get_type(), args.len()),
+            // the variadic flag is not part of the type, so indirect calls pass every argument as named
+            callee => (callee.get_type().get_pointer_element_type(), args.len()),
+        };
+        let arg_types = args.iter().map(|arg| arg.get_type()).collect::<Vec<_>>();
+        let ret_type = callee_type.get_function_return_type();
+        let abi = classify_call(self.layout, &arg_types, &ret_type, named)?;
+
+        let mut operands = Vec::new();
+        for ((arg, ty), location) in args.iter().zip(&arg_types).zip(&abi.args) {
+            match (location, self.operand(arg)?) {
+                // the callee may modify aggregates passed by reference, so it gets a copy
+                (ArgLocation::Reference(pointer), Operand::Reg(src)) => {
+                    let copy = self.mf.new_vreg(RegClass::Int);
+                    self.aggregate_slot(copy, ty);
+                    self.copy_bytes(copy.into(), src, self.layout.size_of(ty));
+                    operands.push((copy.into(), Type::Pointer(Box::new(ty.clone())), (**pointer).clone()));
+                }
+                (location, operand) => operands.push((operand, ty.clone(), location.clone())),
+            }
+        }
+        let target = match callee {
+            ValueEntity::Function(function) => CallTarget::Symbol(function.get_name()),
+            callee => CallTarget::Indirect(self.reg(callee)?),
+        };
+        let sret = if abi.ret == ReturnLocation::Memory {
+            let sret = self.mf.new_vreg(RegClass::Int);
+            self.aggregate_slot(sret, &ret_type);
+            Some(sret)
+        } else {
+            None
+        };
+
+        // stack arguments go to the bottom of the frame, which the prologue reserves
+        self.mf.reserve_outgoing_args(abi.stack_size);
+        for (src, ty, location) in &operands {
+            match location {
+                ArgLocation::Stack { offset, size } if is_aggregate(ty) => {
+                    let Operand::Reg(src) = src else { unreachable!() };
+                    self.copy_bytes_to(Mem::base(PReg::Sp, *offset as i64), Mem::base(*src, 0), *size);
+                }
+                ArgLocation::Stack { offset, .. } => {
+                    let (_, size) = scalar_type(ty)?;
+                    let src = match src {
+                        Operand::Reg(reg) => *reg,
+                        Operand::Imm(imm) => self.imm_reg(*imm),
+                    };
+                    self.emit(Inst::Store { size, src, addr: Mem::base(PReg::Sp, *offset as i64) });
+                }
+                ArgLocation::Split { offset, .. } => {
+                    let Operand::Reg(src) = src else { unreachable!() };
+                    let size = self.layout.size_of(ty);
+                    self.copy_bytes_to(Mem::base(PReg::Sp, *offset as i64), Mem::base(*src, 8), size - 8);
+                }
+                _ => {}
+            }
+        }
+        // registers are written last, so nothing in between can clobber them
+        for (src, ty, location) in operands {
+            match location {
+                ArgLocation::Regs(pieces) if is_aggregate(&ty) => {
+                    let Operand::Reg(src) = src else { unreachable!() };
+                    for piece in pieces {
+                        self.load_piece(piece, src);
+                    }
+                }
+                ArgLocation::Regs(pieces) => self.move_to_arg_reg(&ty, pieces[0].reg, src),
+                ArgLocation::Split { piece, .. } => {
+                    let Operand::Reg(src) = src else { unreachable!() };
+                    self.load_piece(piece, src);
+                }
+                _ => {}
+            }
+        }
+        let mut arg_regs = abi.args.iter().flat_map(|location| match location {
+            ArgLocation::Regs(pieces) => pieces.iter().map(|piece| piece.reg).collect(),
+            ArgLocation::Split { piece, .. } => vec![piece.reg],
+            ArgLocation::Reference(pointer) => match &**pointer {
+                ArgLocation::Regs(pieces) => vec![pieces[0].reg],
+                _ => Vec::new(),
+            },
+            _ => Vec::new(),
+        }).collect::<Vec<_>>();
+        if let Some(sret) = sret {
+            self.emit(Inst::Mov { size: Size::Double, dst: INDIRECT_RESULT_REG.into(), src: sret.into() });
+            arg_regs.push(INDIRECT_RESULT_REG);
+        }
+        self.emit(Inst::Call { target, args: arg_regs });
+
+        match abi.ret {
+            ReturnLocation::Void => {}
+            ReturnLocation::Memory => {
+                let dst = self.result(inst)?;
+                self.emit(Inst::Mov { size: Size::Double, dst: dst.into(), src: sret.unwrap().into() });
+            }
+            ReturnLocation::Regs(pieces) if is_aggregate(&ret_type) => {
+                let dst = self.result(inst)?;
+                self.aggregate_slot(dst, &ret_type);
+                for piece in pieces {
+                    self.store_piece(piece, dst.into());
+                }
+            }
+            ReturnLocation::Regs(pieces) => {
+                let (class, size) = scalar_type(&ret_type)?;
+                let dst = self.result(inst)?;
+                self.copy(class, size, dst.into(), pieces[0].reg.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves a scalar into an argument or return register. Integers are
+    /// widened to 64 bits, sign-extended except for booleans, as the
+    /// calling convention requires, and floating point values that did
+    /// not get a floating point register are passed by their bits.
+    fn move_to_arg_reg(&mut self, ty: &Type, reg: PReg, src: Operand) {
+        let Ok((class, size)) = scalar_type(ty) else {
+            return;
+        };
+        match src {
+            Operand::Reg(src) if class != reg.class() => self.emit(Inst::FMv { size, dst: reg.into(), src }),
+            Operand::Reg(src) if class == RegClass::Int => self.extend(*ty != Type::Integer(1), size, reg.into(), src),
+            Operand::Imm(imm) => self.emit(Inst::Li { dst: reg.into(), imm: normalize(imm, ty) }),
+            src => self.copy(class, size, reg.into(), src),
+        }
+    }
+
+    /// Returns the width of the access to one piece of an aggregate in memory.
+    /// Aggregate slots are padded to eightbytes, so pieces of other sizes read a whole one.
+    fn piece_size(piece: Piece) -> Size {
+        if piece.reg.class() == RegClass::Int {
+            Size::from_bytes(piece.size).unwrap_or(Size::Double)
+        } else if piece.size <= 4 {
+            Size::Word
+        } else {
+            Size::Double
+        }
+    }
+
+    /// Loads one piece of the aggregate at `base` into its register.
+    fn load_piece(&mut self, piece: Piece, base: Reg) {
+        let addr = Mem::base(base, piece.offset as i64);
+        self.emit(Inst::Load { size: Self::piece_size(piece), signed: false, dst: piece.reg.into(), addr });
+    }
+
+    /// Stores one piece of an aggregate from its register to `base`.
+    fn store_piece(&mut self, piece: Piece, base: Reg) {
+        let addr = Mem::base(base, piece.offset as i64);
+        self.emit(Inst::Store { size: Self::piece_size(piece), src: piece.reg.into(), addr });
+    }
+
+    /// Returns the register class and width used to hold a value of type `ty`,
+    /// where aggregates are held by address.
+    fn register_type(&self, ty: &Type) -> Result<(RegClass, Size), Error> {
+        if is_aggregate(ty) {
+            Ok((RegClass::Int, Size::Double))
+        } else {
+            scalar_type(ty)
+        }
+    }
+
+    /// Allocates a frame slot for an aggregate, padded to whole eightbytes, and puts its address in `dst`.
+    fn aggregate_slot(&mut self, dst: VReg, ty: &Type) {
+        let size = self.layout.size_of(ty).next_multiple_of(8);
+        let slot = self.mf.new_slot(size, self.layout.align_of(ty).max(8));
+        self.emit(Inst::Lea { dst: dst.into(), addr: Mem::slot(slot, 0) });
+    }
+
+    /// Copies `size` bytes from the address in `src` to the address in `dst`.
+    fn copy_bytes(&mut self, dst: Reg, src: Reg, size: u64) {
+        self.copy_bytes_to(Mem::base(dst, 0), Mem::base(src, 0), size);
+    }
+
+    fn copy_bytes_to(&mut self, dst: Mem, src: Mem, size: u64) {
+        let mut offset = 0;
+        for chunk in [Size::Double, Size::Word, Size::Half, Size::Byte] {
+            while size - offset >= chunk.bytes() {
+                let temp = self.mf.new_vreg(RegClass::Int);
+                let mut from = src.clone();
+                from.offset += offset as i64;
+                self.emit(Inst::Load { size: chunk, signed: false, dst: temp.into(), addr: from });
+                let mut to = dst.clone();
+                to.offset += offset as i64;
+                self.emit(Inst::Store { size: chunk, src: temp.into(), addr: to });
+                offset += chunk.bytes();
+            }
+        }
+    }
+
+    fn lower_branch_if(&mut self, block: &BasicBlock, cond: &ValueEntity, if_true: &str, if_false: &str) -> Result<(), Error> {
+        let cond = match self.operand(cond)? {
+            Operand::Imm(value) => {
+                let target = if value != 0 { if_true } else { if_false };
+                self.emit_phi_copies(block, target)?;
+                let target = self.label(target);
+                self.emit(Inst::J { target });
+                return Ok(());
+            }
+            Operand::Reg(cond) => {
+                let bit = self.mf.new_vreg(RegClass::Int);
+                self.emit(Inst::Alu { op: AluOp::And, size: Size::Double, dst: bit.into(), lhs: cond, rhs: Operand::Imm(1) });
+                Reg::from(bit)
+            }
+        };
+
+        if !self.has_phis(if_true) {
+            let target = self.label(if_true);
+            self.emit(Inst::Bnez { src: cond, target });
+        } else {
+            // the true edge needs its own copies, so it gets a block of its own
+            let edge = format!("{}.{}", self.label(&block.get_name()), self.mf.blocks.len());
+            self.emit(Inst::Beqz { src: cond, target: edge.clone() });
+            self.emit_phi_copies(block, if_true)?;
+            let target = self.label(if_true);
+            self.emit(Inst::J { target });
+            self.start_block(edge, None);
+        }
+        self.emit_phi_copies(block, if_false)?;
+        let target = self.label(if_false);
+        self.emit(Inst::J { target });
+        Ok(())
+    }
+
+    fn has_phis(&self, block: &str) -> bool {
+        self.func.get_block(block).is_some_and(|block| {
+            block.borrow().get_instructions().iter().any(|inst| matches!(inst, ValueEntity::Instruction(inst) if matches!(inst.instruction_type(), InstructionType::Phi(_))))
+        })
+    }
+
+    /// Writes the incoming values of the phis in `target` for the edge coming from `block`.
+    fn emit_phi_copies(&mut self, block: &BasicBlock, target: &str) -> Result<(), Error> {
+        let Some(target) = self.func.get_block(target).cloned() else {
+            return Err(unsupported(format!("branch to unknown block {}", target)));
+        };
+        let target = target.borrow();
+        for inst in target.get_instructions() {
+            let ValueEntity::Instruction(inst) = inst else {
+                continue;
+            };
+            let InstructionType::Phi(incoming) = inst.instruction_type() else {
+                continue;
+            };
+            let Some((value, _)) = incoming.iter().find(|(_, from)| from.get_name() == block.get_name()) else {
+                continue;
+            };
+            let (class, size) = self.register_type(&inst.get_type())?;
+            let temp = self.phi_temps[&inst.get_name()];
+            let src = self.operand(value)?;
+            self.copy(class, size, temp.into(), src);
+        }
+        Ok(())
+    }
+}
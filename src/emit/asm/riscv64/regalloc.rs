@@ -0,0 +1,56 @@
+use crate::emit::asm::regalloc::RegisterFile;
+use crate::emit::asm::riscv64::inst::{Inst, Mem, PReg, RegClass, Size};
+
+/// Integer registers handed out by the allocator, caller-saved ones first so
+/// that callee-saved registers are only used by values live across calls.
+/// `t0` is left free for the frame, `t1` and `t2` as spill scratch registers,
+/// and `s0` is the frame pointer.
+const INT_ALLOCATABLE: [PReg; 23] = [
+    PReg::A0, PReg::A1, PReg::A2, PReg::A3, PReg::A4, PReg::A5, PReg::A6, PReg::A7,
+    PReg::T3, PReg::T4, PReg::T5, PReg::T6,
+    PReg::S1, PReg::S2, PReg::S3, PReg::S4, PReg::S5, PReg::S6, PReg::S7, PReg::S8, PReg::S9, PReg::S10, PReg::S11,
+];
+/// `ft10` and `ft11` are left free as spill scratch registers.
+const FLOAT_ALLOCATABLE: [PReg; 30] = [
+    PReg::Fa0, PReg::Fa1, PReg::Fa2, PReg::Fa3, PReg::Fa4, PReg::Fa5, PReg::Fa6, PReg::Fa7,
+    PReg::Ft0, PReg::Ft1, PReg::Ft2, PReg::Ft3, PReg::Ft4, PReg::Ft5, PReg::Ft6, PReg::Ft7, PReg::Ft8, PReg::Ft9,
+    PReg::Fs0, PReg::Fs1, PReg::Fs2, PReg::Fs3, PReg::Fs4, PReg::Fs5, PReg::Fs6, PReg::Fs7, PReg::Fs8, PReg::Fs9, PReg::Fs10, PReg::Fs11,
+];
+/// Registers kept free to reload spilled values around a single instruction.
+const INT_SCRATCH: [PReg; 2] = [PReg::T1, PReg::T2];
+const FLOAT_SCRATCH: [PReg; 2] = [PReg::Ft10, PReg::Ft11];
+/// Registers the caller reads after `ret`.
+const RETURN_REGS: [PReg; 4] = [PReg::A0, PReg::A1, PReg::Fa0, PReg::Fa1];
+
+/// The registers of the RISC-V LP64D ABI.
+pub struct Registers;
+
+impl RegisterFile for Registers {
+    type Inst = Inst;
+
+    fn allocatable(class: RegClass) -> &'static [PReg] {
+        match class {
+            RegClass::Int => &INT_ALLOCATABLE,
+            RegClass::Float => &FLOAT_ALLOCATABLE,
+        }
+    }
+
+    fn scratch(class: RegClass) -> &'static [PReg] {
+        match class {
+            RegClass::Int => &INT_SCRATCH,
+            RegClass::Float => &FLOAT_SCRATCH,
+        }
+    }
+
+    fn return_regs() -> &'static [PReg] {
+        &RETURN_REGS
+    }
+
+    fn load(_class: RegClass, dst: PReg, slot: u32) -> Inst {
+        Inst::Load { size: Size::Double, signed: false, dst: dst.into(), addr: Mem::slot(slot, 0) }
+    }
+
+    fn store(_class: RegClass, src: PReg, slot: u32) -> Inst {
+        Inst::Store { size: Size::Double, src: src.into(), addr: Mem::slot(slot, 0) }
+    }
+}
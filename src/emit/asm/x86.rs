@@ -0,0 +1,406 @@
+use std::io::Write;
+use crate::ir::builder::ctx::IRContext;
+use crate::ir::values::function::Function;
+use crate::ir::values::global::GlobalVariable;
+use crate::ir::linkage::Linkage;
+use crate::targets::layout::DataLayout;
+use crate::emit::asm::{data, unsupported, FrameOptions};
+use crate::emit::asm::regalloc::allocate;
+use crate::emit::asm::x86_64::frame;
+use crate::emit::asm::x86_64::inst::{Inst, MachineBlock, MachineFunction, Size};
+use crate::emit::asm::x86::lower::FunctionLowering;
+use crate::emit::asm::x86::regalloc::Registers;
+
+pub mod abi;
+pub mod lower;
+pub mod regalloc;
+
+struct X86Emitter {
+    ctx: IRContext,
+    frame_options: FrameOptions,
+}
+
+impl X86Emitter {
+    pub fn new(ctx: IRContext, frame_options: FrameOptions) -> Self {
+        Self {
+            ctx,
+            frame_options,
+        }
+    }
+
+    pub fn emit_module(&mut self, file: &mut impl Write) -> Result<(), std::io::Error> {
+        writeln!(file, "\t\t.intel_syntax noprefix")?;
+        writeln!(file, "\t\t.text")?;
+
+        let functions = self.ctx.get_module().get_functions().clone();
+        let globals = self.ctx.get_module().get_global_variables().clone();
+        for function in &functions {
+            self.emit_function(file, &function.borrow(), true)?;
+        }
+        for global in &globals {
+            self.emit_global(file, &global.borrow(), true)?;
+        }
+        for global in &globals {
+            self.emit_global(file, &global.borrow(), false)?;
+        }
+        if !globals.is_empty() {
+            writeln!(file, "\t\t.text")?;
+        }
+        for function in functions {
+            self.emit_function(file, &function.borrow(), false)?;
+        }
+
+        writeln!(file, "\t\t.section .note.GNU-stack,\"\",@progbits")?;
+        Ok(())
+    }
+
+    pub fn emit_global(&mut self, file: &mut impl Write, global: &GlobalVariable, decl: bool) -> Result<(), std::io::Error> {
+        if decl {
+            match global.get_linkage() {
+                Linkage::ExternalLinkage => writeln!(file, "\t\t.extern {}", global.get_name())?,
+                Linkage::InternalLinkage => writeln!(file, "\t\t.globl {}", global.get_name())?,
+                // private globals stay local to the object, common ones are declared by `.comm`
+                Linkage::PrivateLinkage | Linkage::CommonLinkage => {}
+                Linkage::ExternalWeakLinkage | Linkage::LinkonceLinkage | Linkage::WeakLinkage => writeln!(file, "\t\t.weak {}", global.get_name())?,
+
+                Linkage::AppendingLinkage => return Err(unsupported(format!("appending linkage on global {}", global.get_name()))),
+            }
+            return Ok(());
+        }
+
+        data::emit_global(file, self.ctx.get_module().data_layout(), global)
+    }
+
+    pub fn emit_function(&mut self, file: &mut impl Write, func: &Function, decl: bool) -> Result<(), std::io::Error> {
+        if decl {
+            // write the function prefix for linkage
+            match func.get_linkage() {
+                Linkage::ExternalLinkage => writeln!(file, "\t\t.extern {}", func.get_name())?,
+                Linkage::InternalLinkage => writeln!(file, "\t\t.globl {}", func.get_name())?,
+                Linkage::PrivateLinkage => writeln!(file)?,
+                Linkage::ExternalWeakLinkage => writeln!(file, "\t\t.weak {}", func.get_name())?,
+                Linkage::CommonLinkage => writeln!(file, "\t\t.extern {}", func.get_name())?,
+                Linkage::LinkonceLinkage | Linkage::WeakLinkage => writeln!(file, "\t\t.weak {}", func.get_name())?,
+
+                Linkage::AppendingLinkage => return Err(unsupported(format!("appending linkage on function {}", func.get_name()))),
+            }
+            return Ok(());
+        }
+
+        if func.is_external() || func.get_blocks().is_empty() {
+            return Ok(());
+        }
+
+        let mf = compile_function(func, self.ctx.get_module().data_layout(), self.frame_options)?;
+
+        // write the function name
+        writeln!(file, "\t\t.p2align 2")?;
+        writeln!(file, "\t\t.type {}, @function", func.get_name())?;
+        writeln!(file, "{}:", func.get_name())?;
+        for block in &mf.blocks {
+            self.emit_basic_block(file, block)?;
+        }
+
+        writeln!(file)?;
+        Ok(())
+    }
+
+    pub fn emit_basic_block(&mut self, file: &mut impl Write, bb: &MachineBlock) -> Result<(), std::io::Error> {
+        match &bb.comment {
+            Some(comment) => writeln!(file, "{}:\t# {}", bb.label, comment)?,
+            None => writeln!(file, "{}:", bb.label)?,
+        }
+
+        for inst in &bb.insts {
+            self.emit_instruction(file, inst)?;
+        }
+        Ok(())
+    }
+
+    pub fn emit_instruction(&mut self, file: &mut impl Write, x: &Inst) -> Result<(), std::io::Error> {
+        writeln!(file, "\t\t{}", x.display(Size::Dword))
+    }
+}
+
+/// Lowers a function with a body to machine code over physical registers,
+/// with its frame laid out.
+pub fn compile_function(func: &Function, layout: &DataLayout, frame_options: FrameOptions) -> Result<MachineFunction, std::io::Error> {
+    let mut mf = FunctionLowering::new(func, layout).lower()?;
+    allocate::<Registers>(&mut mf);
+    frame::lay_out(&mut mf, frame_options, Size::Dword);
+    Ok(mf)
+}
+
+pub fn emit_module(ctx: IRContext, frame_options: FrameOptions, file: &mut impl Write) -> Result<(), std::io::Error> {
+    let mut emitter = X86Emitter::new(ctx, frame_options);
+    emitter.emit_module(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::values::value::Type;
+    use crate::ir::values::instruction::FloatPredicate;
+    use crate::error::Error;
+    use crate::ir::builder::{Builder, IRContext};
+    use crate::ir::calling_conv::CallingConv;
+    use crate::ir::linkage::Linkage;
+    use crate::ir::module::Module;
+    use crate::targets::{DataLayout, TargetTriple};
+
+    fn builder() -> Builder {
+        let triple = TargetTriple::new("i686-unknown-linux-gnu").unwrap();
+        let module = Module::new("test", DataLayout::from_triple(&triple), triple);
+        Builder::new(IRContext::new(module))
+    }
+
+    /// Returns the instructions and labels of the emitted assembly, one per entry.
+    fn emit(builder: &Builder) -> Vec<String> {
+        let mut out = Vec::new();
+        builder.emit_assembly(&mut out).unwrap();
+        String::from_utf8(out).unwrap().lines().map(|line| line.trim().to_string()).collect()
+    }
+
+    #[test]
+    fn lowers_64_bit_arithmetic_in_register_pairs() -> Result<(), Error> {
+        let mut builder = builder();
+        let params = vec![(builder.get_i64_type(), Some("a")), (builder.get_i64_type(), Some("b"))];
+        let main = builder.create_function_with_param_names("main", params, builder.get_i64_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main.clone())?;
+        builder.set_insertion_point(entry);
+        let sum = builder.add(builder.get_param(&main, 0)?, builder.get_param(&main, 1)?, None)?;
+        let product = builder.mul(sum.into(), builder.get_param(&main, 1)?, None)?;
+        let shifted = builder.shl(product.into(), builder.get_param(&main, 0)?, None)?;
+        let quotient = builder.sdiv(shifted.into(), builder.get_i64(3), None)?;
+        builder.ret(quotient.into())?;
+
+        let lines = emit(&builder);
+        let main = lines.iter().position(|line| line == "main:").unwrap();
+        assert_eq!(lines[main + 2..][..3], ["push ebp", "mov ebp, esp", "push ebx"]);
+        // sums carry from the low into the high half
+        assert!(lines.iter().any(|line| line.starts_with("adc ")));
+        // products combine the full product of the low halves with the cross products
+        assert!(lines.iter().any(|line| line.starts_with("mul ")));
+        assert_eq!(lines.iter().filter(|line| line.starts_with("imul ")).count(), 2);
+        // variable shifts move a half over for amounts of 32 or more
+        assert!(lines.iter().any(|line| line.starts_with("shld ")));
+        assert!(lines.contains(&"test ecx, 32".to_string()));
+        assert_eq!(lines.iter().filter(|line| line.starts_with("cmovne ")).count(), 2);
+        // division is left to libgcc, with the result in edx:eax
+        assert!(lines.contains(&"mov dword ptr [esp + 8], 3".to_string()));
+        assert!(lines.contains(&"call __divdi3".to_string()));
+        assert_eq!(lines.iter().filter(|line| *line == "ret").count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn lowers_calls_globals_and_calling_conventions() -> Result<(), Error> {
+        let mut builder = builder();
+        let counter = builder.create_global("counter", builder.get_i32_type(), Some(builder.const_int(builder.get_i32_type(), 7)?), Linkage::InternalLinkage, false)?;
+        let tls = builder.create_global("tls", builder.get_i32_type(), Some(builder.const_int(builder.get_i32_type(), 1)?), Linkage::InternalLinkage, false)?;
+        tls.borrow_mut().set_thread_local(true);
+        let args = vec![builder.get_i32_type(), builder.get_i32_type(), builder.get_i32_type()];
+        let fast = builder.create_function("fast", args.clone(), builder.get_i32_type(), Linkage::ExternalLinkage, false)?;
+        fast.borrow_mut().set_calling_conv(CallingConv::X86Fastcall);
+        let std = builder.create_function("std", args, builder.get_f64_type(), Linkage::ExternalLinkage, false)?;
+        std.borrow_mut().set_calling_conv(CallingConv::X86Stdcall);
+
+        let params = vec![(builder.get_i32_type(), Some("a"))];
+        let main = builder.create_function_with_param_names("main", params, builder.get_f64_type(), Linkage::InternalLinkage, false)?;
+        main.borrow_mut().set_calling_conv(CallingConv::X86Stdcall);
+        let entry = builder.create_block("entry", main.clone())?;
+        builder.set_insertion_point(entry);
+        let a = builder.load(builder.get_i32_type(), counter.borrow().clone().into(), None)?;
+        let b = builder.load(builder.get_i32_type(), tls.borrow().clone().into(), None)?;
+        let args = vec![a.into(), b.into(), builder.get_param(&main, 0)?];
+        let c = builder.call(fast.borrow().clone().into(), args, None)?;
+        let args = vec![c.into(), builder.get_i32(2), builder.get_i32(3)];
+        let result = builder.call(std.borrow().clone().into(), args, None)?;
+        builder.ret(result.into())?;
+
+        let lines = emit(&builder);
+        let after = |label: &str| &lines[lines.iter().position(|line| line == label).unwrap() + 1];
+        assert_eq!(after("counter:"), ".long 7");
+        assert!(lines.contains(&".section .tdata,\"awT\",@progbits".to_string()));
+
+        // globals are addressed absolutely, thread-locals from the thread pointer in gs
+        assert!(lines.iter().any(|line| line.starts_with("lea ") && line.ends_with(", [counter]")));
+        assert!(lines.iter().any(|line| line.starts_with("mov ") && line.ends_with(", dword ptr gs:0")));
+        assert!(lines.iter().any(|line| line.ends_with(", dword ptr [tls@INDNTPOFF]")));
+
+        // fastcall passes two arguments in ecx and edx and pops the third off the stack
+        assert_eq!(after("call fast"), "sub esp, 4");
+        assert!(lines.contains(&"mov dword ptr [esp + 8], 3".to_string()));
+        // stdcall callees pop all of their arguments, and doubles come back on the x87 stack
+        assert_eq!(after("call std"), "sub esp, 12");
+        assert!(lines.iter().skip_while(|line| *line != "call std").any(|line| line.starts_with("fstp qword ptr [ebp - ")));
+        assert!(lines.iter().any(|line| line.starts_with("fld qword ptr [ebp - ")));
+        assert_eq!(lines[lines.len() - 3], "ret 4");
+        Ok(())
+    }
+
+    #[test]
+    fn lowers_conversions() -> Result<(), Error> {
+        let mut builder = builder();
+        let params = vec![(builder.get_i32_type(), Some("a")), (builder.get_i64_type(), Some("b")), (builder.get_f64_type(), Some("x"))];
+        let main = builder.create_function_with_param_names("main", params, builder.get_f64_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main.clone())?;
+        builder.set_insertion_point(entry);
+        let (a, b, x) = (builder.get_param(&main, 0)?, builder.get_param(&main, 1)?, builder.get_param(&main, 2)?);
+        let wide = builder.sext(a.clone(), builder.get_i64_type(), None)?;
+        let float = builder.si_to_fp(wide.into(), builder.get_f64_type(), None)?;
+        builder.fp_trunc(float.into(), builder.get_f32_type(), None)?;
+        let byte = builder.trunc(b, builder.get_i8_type(), None)?;
+        builder.zext(byte.into(), builder.get_i32_type(), None)?;
+        let single = builder.ui_to_fp(a, builder.get_f32_type(), None)?;
+        let double = builder.fp_ext(single.into(), builder.get_f64_type(), None)?;
+        builder.fp_to_si(x.clone(), builder.get_i32_type(), None)?;
+        builder.fp_to_ui(x.clone(), builder.get_i64_type(), None)?;
+        builder.bitcast(x, builder.get_i64_type(), None)?;
+        builder.ret(double.into())?;
+
+        let lines = emit(&builder);
+        // 64-bit integers are converted by libgcc
+        assert!(lines.contains(&"sar ebx, 31".to_string()));
+        assert!(lines.contains(&"call __floatdidf".to_string()));
+        assert!(lines.contains(&"call __fixunsdfdi".to_string()));
+        assert!(lines.contains(&"and ecx, 255".to_string()));
+        // unsigned dwords are flipped into the signed range and 2^31 added back
+        assert!(lines.contains(&"xor ecx, -2147483648".to_string()));
+        assert!(lines.contains(&"cvtsi2sd xmm0, ecx".to_string()));
+        assert!(lines.contains(&"psllq xmm1, 32".to_string()));
+        assert!(lines.contains(&"cvtss2sd xmm6, xmm0".to_string()));
+        assert!(lines.contains(&"cvttsd2si ecx, xmm6".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn lowers_unsigned_division_shifts_and_comparisons() -> Result<(), Error> {
+        let mut builder = builder();
+        let params = vec![(builder.get_i32_type(), Some("a")), (builder.get_i32_type(), Some("b"))];
+        let main = builder.create_function_with_param_names("main", params, builder.get_i32_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main.clone())?;
+        let yes = builder.create_block("yes", main.clone())?;
+        let no = builder.create_block("no", main.clone())?;
+        builder.set_insertion_point(entry);
+        let (a, b) = (builder.get_param(&main, 0)?, builder.get_param(&main, 1)?);
+        let quotient = builder.udiv(a.clone(), b.clone(), None)?;
+        let rest = builder.urem(quotient.clone().into(), b.clone(), None)?;
+        let shifted = builder.lshr(rest.into(), b, None)?;
+        let less = builder.ult(shifted.clone().into(), a, None)?;
+        builder.branch_if(less.into(), yes.clone(), no.clone())?;
+        builder.set_insertion_point(yes);
+        let flipped = builder.not(shifted.into(), None)?;
+        builder.ret(flipped.into())?;
+        builder.set_insertion_point(no);
+        builder.ret(quotient.into())?;
+
+        let lines = emit(&builder);
+        // unsigned division clears edx instead of sign-extending eax into it
+        assert_eq!(lines.iter().filter(|line| *line == "mov edx, 0").count(), 2);
+        assert_eq!(lines.iter().filter(|line| *line == "div ecx").count(), 2);
+        assert!(!lines.contains(&"cdq".to_string()));
+        assert!(lines.contains(&"shr edx, cl".to_string()));
+        assert!(lines.contains(&"setb al".to_string()));
+        assert!(lines.contains(&"not edx".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn lowers_float_arithmetic_and_comparisons() -> Result<(), Error> {
+        let mut builder = builder();
+        let params = vec![(builder.get_f64_type(), Some("a")), (builder.get_f64_type(), Some("b"))];
+        let main = builder.create_function_with_param_names("main", params, builder.get_f64_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main.clone())?;
+        let yes = builder.create_block("yes", main.clone())?;
+        let no = builder.create_block("no", main.clone())?;
+        builder.set_insertion_point(entry);
+        let (a, b) = (builder.get_param(&main, 0)?, builder.get_param(&main, 1)?);
+        let sum = builder.fadd(a.clone(), builder.get_f64(1.5), None)?;
+        let quotient = builder.fdiv(sum.into(), b.clone(), None)?;
+        let rest = builder.frem(quotient.clone().into(), b, None)?;
+        let negated = builder.fneg(rest.into(), None)?;
+        let unordered = builder.fcmp(FloatPredicate::Ult, negated.clone().into(), a, None)?;
+        builder.branch_if(unordered.into(), yes.clone(), no.clone())?;
+        builder.set_insertion_point(yes);
+        builder.ret(negated.into())?;
+        builder.set_insertion_point(no);
+        builder.ret(quotient.into())?;
+
+        let lines = emit(&builder);
+        assert!(lines.contains(&"mov eax, 1073217536".to_string()));
+        assert!(lines.contains(&"psllq xmm1, 32".to_string()));
+        assert!(lines.contains(&"addsd xmm2, xmm1".to_string()));
+        // fmod takes its arguments on the stack and returns in st(0)
+        let call = lines.iter().position(|line| line == "call fmod").unwrap();
+        assert_eq!(lines[call - 2..call], ["movsd qword ptr [esp], xmm6", "movsd qword ptr [esp + 8], xmm0"]);
+        assert_eq!(lines[call + 1], "fstp qword ptr [ebp - 40]");
+        assert!(lines.contains(&"xorps xmm0, xmm1".to_string()));
+        assert!(lines.contains(&"ucomisd xmm0, xmm6".to_string()));
+        assert!(lines.contains(&"setb al".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn lowers_constant_addresses_and_aggregates() -> Result<(), Error> {
+        let mut builder = builder();
+        let array = builder.get_array_type(builder.get_i32_type(), 2);
+        let elements = vec![builder.const_int(builder.get_i32_type(), 7)?, builder.const_int(builder.get_i32_type(), -1)?];
+        let table = builder.create_global("table", array.clone(), Some(builder.const_array(builder.get_i32_type(), elements)?), Linkage::InternalLinkage, false)?;
+        let address = builder.const_address(&table.borrow().clone().into())?;
+        let address = builder.const_cast("ptrtoint", address, builder.get_i64_type())?;
+        let end = builder.const_binary("add", address.clone(), builder.const_int(builder.get_i64_type(), 8)?)?;
+        builder.create_global("end", builder.get_i64_type(), Some(end), Linkage::InternalLinkage, false)?;
+
+        let params = vec![(builder.get_i64_type(), Some("a"))];
+        let main = builder.create_function_with_param_names("main", params, builder.get_i64_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main.clone())?;
+        builder.set_insertion_point(entry);
+        let second = builder.const_binary("add", address, builder.const_int(builder.get_i64_type(), 4)?)?;
+        let sum = builder.add(builder.get_param(&main, 0)?, second.into(), None)?;
+        let wide = builder.add(sum.into(), builder.const_int(builder.get_i64_type(), 0x1234_5678_9abc)?.into(), None)?;
+        builder.ret(wide.into())?;
+
+        let lines = emit(&builder);
+        let after = |label: &str| lines.iter().skip_while(|line| *line != label).skip(1).take(2).map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(after("table:"), [".long 7", ".long 4294967295"]);
+        // the upper half of a 64-bit address is zero
+        assert_eq!(after("end:"), [".long table+8", ".zero 4"]);
+        let entry = lines.iter().position(|line| line.starts_with(".Lmain.entry:")).unwrap();
+        assert_eq!(lines[entry + 5..][..5], [
+            "lea edx, [table + 4]",
+            "add eax, edx",
+            "adc ecx, 0",
+            "add eax, 1450744508",
+            "adc ecx, 4660",
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn lowers_element_addresses() -> Result<(), Error> {
+        let mut builder = builder();
+        let array = builder.get_array_type(builder.get_i32_type(), 4);
+        let pair = builder.get_pointer_type(Type::Struct(vec![builder.get_i8_type(), array]));
+        let params = vec![(pair, Some("p")), (builder.get_i32_type(), Some("i"))];
+        let main = builder.create_function_with_param_names("main", params, builder.get_i32_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main.clone())?;
+        builder.set_insertion_point(entry);
+        let field = builder.struct_gep(builder.get_param(&main, 0)?, 1, None)?;
+        let element = builder.array_gep(field.into(), builder.get_param(&main, 1)?, None)?;
+        let value = builder.load(builder.get_i32_type(), element.clone().into(), None)?;
+        builder.store(element.into(), builder.get_i32(7))?;
+        builder.ret(value.into())?;
+
+        let lines = emit(&builder);
+        let entry = lines.iter().position(|line| line.starts_with(".Lmain.entry:")).unwrap();
+        assert_eq!(lines[entry + 5..][..6], [
+            "add eax, 4",
+            "mov edx, 4",
+            "imul ecx, edx",
+            "add eax, ecx",
+            "mov ecx, dword ptr [eax]",
+            "mov dword ptr [eax], 7",
+        ]);
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+use crate::emit::asm::x86_64::inst::PReg;
+use crate::emit::asm::unsupported;
+use crate::ir::calling_conv::CallingConv;
+use crate::ir::values::value::Type;
+use crate::targets::layout::DataLayout;
+use std::io::Error;
+
+/// Registers fastcall passes its first two eligible arguments in, in order.
+pub const FASTCALL_REGS: [PReg; 2] = [PReg::Rcx, PReg::Rdx];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgLocation {
+    Reg(PReg),
+    /// In the argument area, at `offset` bytes from the stack pointer at the call.
+    Stack { offset: u64, size: u64 },
+    /// Zero-sized values are not passed at all.
+    Ignore,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReturnLocation {
+    Void,
+    /// `eax`, or `edx:eax` for 64-bit integers.
+    Regs(Vec<PReg>),
+    /// The top of the x87 stack, for floating point values.
+    X87,
+    /// The caller passes the address of a buffer where the argument is, and
+    /// gets it back in `eax`.
+    Memory(ArgLocation),
+}
+
+/// Where the arguments and return value of a call live.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallAbi {
+    pub args: Vec<ArgLocation>,
+    pub ret: ReturnLocation,
+    /// Size of the stack argument area, a multiple of 4.
+    pub stack_size: u64,
+    /// Bytes of stack arguments the callee pops when it returns.
+    pub callee_pops: u64,
+}
+
+fn is_aggregate(ty: &Type) -> bool {
+    ty.is_struct() || ty.is_array()
+}
+
+/// Returns whether fastcall may pass a value of type `ty` in a register,
+/// which it does for integers and pointers of at most 32 bits.
+fn fits_fastcall_reg(layout: &DataLayout, ty: &Type) -> bool {
+    matches!(ty, Type::Integer(_) | Type::Pointer(_) | Type::FunctionType(_, _)) && layout.size_of(ty) <= 4
+}
+
+/// Computes where the arguments and the return value of a call with the
+/// given argument and return types are passed under `conv`, following the
+/// i386 System V ABI for cdecl. Stack arguments take 4-byte aligned
+/// multiples of 4 bytes in order, and aggregates are always returned in
+/// memory.
+pub fn classify_call(layout: &DataLayout, args: &[Type], ret: &Type, conv: CallingConv) -> Result<CallAbi, Error> {
+    let mut regs: &[PReg] = match conv {
+        CallingConv::X86Fastcall => &FASTCALL_REGS,
+        CallingConv::C | CallingConv::X86Stdcall => &[],
+    };
+    let mut stack_size: u64 = 0;
+    let mut on_stack = |size: u64| {
+        let offset = stack_size;
+        stack_size += size.next_multiple_of(4);
+        ArgLocation::Stack { offset, size }
+    };
+
+    let ret = match ret {
+        Type::Void => ReturnLocation::Void,
+        ty if is_aggregate(ty) => {
+            // the buffer address is the first argument
+            let location = match regs.split_first() {
+                Some((reg, rest)) => {
+                    regs = rest;
+                    ArgLocation::Reg(*reg)
+                }
+                None => on_stack(4),
+            };
+            ReturnLocation::Memory(location)
+        }
+        Type::Float(32) | Type::Float(64) => ReturnLocation::X87,
+        Type::Integer(64) => ReturnLocation::Regs(vec![PReg::Rax, PReg::Rdx]),
+        ty if fits_fastcall_reg(layout, ty) => ReturnLocation::Regs(vec![PReg::Rax]),
+        ty => return Err(unsupported(format!("returning values of type {}", ty))),
+    };
+
+    let mut locations = Vec::new();
+    for arg in args {
+        let size = layout.size_of(arg);
+        if size == 0 {
+            locations.push(ArgLocation::Ignore);
+            continue;
+        }
+        if !is_aggregate(arg) && !matches!(arg, Type::Integer(_) | Type::Pointer(_) | Type::FunctionType(_, _) | Type::Float(32) | Type::Float(64)) {
+            return Err(unsupported(format!("passing values of type {}", arg)));
+        }
+        if arg.is_float() {
+            locations.push(on_stack(size));
+            continue;
+        }
+        // everything else uses up registers by the word, even when it is then
+        // passed on the stack, and anything that does not fit takes the rest
+        let words = size.div_ceil(4) as usize;
+        if words > regs.len() {
+            regs = &[];
+            locations.push(on_stack(size));
+            continue;
+        }
+        let (used, rest) = regs.split_at(words);
+        regs = rest;
+        if fits_fastcall_reg(layout, arg) {
+            locations.push(ArgLocation::Reg(used[0]));
+        } else {
+            locations.push(on_stack(size));
+        }
+    }
+
+    let callee_pops = match (conv, &ret) {
+        (CallingConv::X86Stdcall | CallingConv::X86Fastcall, _) => stack_size,
+        // even cdecl callees pop the buffer address of a result returned in memory
+        (CallingConv::C, ReturnLocation::Memory(ArgLocation::Stack { .. })) => 4,
+        (CallingConv::C, _) => 0,
+    };
+    Ok(CallAbi { args: locations, ret, stack_size, callee_pops })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_cdecl_arguments_on_the_stack_in_words() {
+        let layout = DataLayout::new_x86();
+        let args = [Type::Integer(8), Type::Integer(64), Type::Float(64)];
+        let abi = classify_call(&layout, &args, &Type::Integer(64), CallingConv::C).unwrap();
+        assert_eq!(abi.args, [
+            ArgLocation::Stack { offset: 0, size: 1 },
+            ArgLocation::Stack { offset: 4, size: 8 },
+            ArgLocation::Stack { offset: 12, size: 8 },
+        ]);
+        assert_eq!(abi.ret, ReturnLocation::Regs(vec![PReg::Rax, PReg::Rdx]));
+        assert_eq!(abi.stack_size, 20);
+        assert_eq!(abi.callee_pops, 0);
+
+        let abi = classify_call(&layout, &[], &Type::Float(32), CallingConv::C).unwrap();
+        assert_eq!(abi.ret, ReturnLocation::X87);
+    }
+
+    #[test]
+    fn callee_pops_stdcall_arguments_and_cdecl_result_buffers() {
+        let layout = DataLayout::new_x86();
+        let abi = classify_call(&layout, &[Type::Integer(32), Type::Integer(16)], &Type::Void, CallingConv::X86Stdcall).unwrap();
+        assert_eq!(abi.stack_size, 8);
+        assert_eq!(abi.callee_pops, 8);
+
+        let pair = Type::Struct(vec![Type::Integer(32), Type::Integer(32)]);
+        let abi = classify_call(&layout, &[Type::Integer(32)], &pair, CallingConv::C).unwrap();
+        assert_eq!(abi.ret, ReturnLocation::Memory(ArgLocation::Stack { offset: 0, size: 4 }));
+        assert_eq!(abi.args, [ArgLocation::Stack { offset: 4, size: 4 }]);
+        assert_eq!(abi.callee_pops, 4);
+    }
+
+    #[test]
+    fn passes_fastcall_arguments_in_ecx_and_edx() {
+        let layout = DataLayout::new_x86();
+        let args = [Type::Integer(32), Type::Float(64), Type::Integer(8), Type::Integer(32)];
+        let abi = classify_call(&layout, &args, &Type::Integer(32), CallingConv::X86Fastcall).unwrap();
+        assert_eq!(abi.args, [
+            ArgLocation::Reg(PReg::Rcx),
+            ArgLocation::Stack { offset: 0, size: 8 },
+            ArgLocation::Reg(PReg::Rdx),
+            ArgLocation::Stack { offset: 8, size: 4 },
+        ]);
+        assert_eq!(abi.callee_pops, 12);
+
+        // a 64-bit integer does not fit the registers left and uses them up
+        let abi = classify_call(&layout, &[Type::Integer(32), Type::Integer(64), Type::Integer(32)], &Type::Void, CallingConv::X86Fastcall).unwrap();
+        assert_eq!(abi.args, [
+            ArgLocation::Reg(PReg::Rcx),
+            ArgLocation::Stack { offset: 0, size: 8 },
+            ArgLocation::Stack { offset: 8, size: 4 },
+        ]);
+    }
+}
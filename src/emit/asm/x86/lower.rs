@@ -0,0 +1,1401 @@
+use crate::emit::asm::data::{constant_data, data_bytes, Datum};
+use crate::emit::asm::unsupported;
+use crate::emit::asm::x86::abi::{classify_call, ArgLocation, CallAbi, ReturnLocation};
+use crate::emit::asm::x86_64::inst::{AluOp, Base, CallTarget, Cond, DoubleShiftOp, Inst, MachineBlock, MachineFunction, Mem, Operand, PReg, Reg, RegClass, ShiftOp, Size, SseOp, UnaryOp, VReg};
+use crate::ir::calling_conv::CallingConv;
+use crate::ir::values::basic_block::BasicBlock;
+use crate::ir::values::constant::{Constant, Scalar};
+use crate::ir::values::function::Function;
+use crate::ir::values::instruction::{FastMathFlags, FloatPredicate, Instruction, InstructionType};
+use crate::ir::values::value::{Type, ValueEntity};
+use crate::targets::layout::DataLayout;
+use std::collections::HashMap;
+use std::io::Error;
+
+/// Returns the register class and width used to hold a value of type `ty`.
+/// 64-bit integers are held in a pair of registers.
+pub fn scalar_type(ty: &Type) -> Result<(RegClass, Size), Error> {
+    match ty {
+        Type::Integer(1) | Type::Integer(8) => Ok((RegClass::Int, Size::Byte)),
+        Type::Integer(16) => Ok((RegClass::Int, Size::Word)),
+        Type::Integer(32) => Ok((RegClass::Int, Size::Dword)),
+        Type::Integer(64) => Ok((RegClass::Int, Size::Qword)),
+        Type::Pointer(_) | Type::FunctionType(_, _) => Ok((RegClass::Int, Size::Dword)),
+        Type::Float(32) => Ok((RegClass::Float, Size::Dword)),
+        Type::Float(64) => Ok((RegClass::Float, Size::Qword)),
+        _ => Err(unsupported(format!("values of type {} are not supported by the x86 backend", ty))),
+    }
+}
+
+/// Returns the register class used for a value of type `ty`. Structs and
+/// arrays are held as the address of a frame slot containing the value.
+fn value_class(ty: &Type) -> Result<RegClass, Error> {
+    if is_aggregate(ty) {
+        Ok(RegClass::Int)
+    } else {
+        scalar_type(ty).map(|(class, _)| class)
+    }
+}
+
+fn is_aggregate(ty: &Type) -> bool {
+    ty.is_struct() || ty.is_array()
+}
+
+/// Returns whether values of type `ty` take a pair of registers, low half first.
+fn is_pair(ty: &Type) -> bool {
+    *ty == Type::Integer(64)
+}
+
+/// Returns the number of low bits of a register holding an integer of type
+/// `ty` that make up its value. The bits above those of narrower integers
+/// are undefined, so they are extended wherever the difference shows.
+fn value_bits(ty: &Type) -> u8 {
+    match ty {
+        Type::Integer(bits @ (1 | 8 | 16)) => *bits as u8,
+        _ => 32,
+    }
+}
+
+/// Returns the assembler label of a basic block.
+pub fn block_label(function: &str, block: &str) -> String {
+    format!(".L{}.{}", function, block.trim_start_matches('%'))
+}
+
+/// Lowers the IR of one function into i686 machine code over virtual registers.
+pub struct FunctionLowering<'a> {
+    func: &'a Function,
+    layout: &'a DataLayout,
+    mf: MachineFunction,
+    values: HashMap<String, VReg>,
+    /// The high halves of 64-bit integers, whose low halves are in `values`.
+    high_halves: HashMap<String, VReg>,
+    phi_temps: HashMap<String, VReg>,
+    phi_high_temps: HashMap<String, VReg>,
+    current: usize,
+    /// Holds the caller's result buffer when the function returns in memory.
+    sret: Option<VReg>,
+}
+
+impl<'a> FunctionLowering<'a> {
+    pub fn new(func: &'a Function, layout: &'a DataLayout) -> Self {
+        Self {
+            func,
+            layout,
+            mf: MachineFunction::new(&func.get_name()),
+            values: HashMap::new(),
+            high_halves: HashMap::new(),
+            phi_temps: HashMap::new(),
+            phi_high_temps: HashMap::new(),
+            current: 0,
+            sret: None,
+        }
+    }
+
+    /// Returns how arguments and the return value are passed to this function.
+    pub fn abi(&self) -> Result<CallAbi, Error> {
+        let ty = self.func.get_type();
+        classify_call(self.layout, ty.get_function_argument_types(), &ty.get_function_return_type(), self.func.get_calling_conv())
+    }
+
+    pub fn lower(mut self) -> Result<MachineFunction, Error> {
+        let blocks = self.func.get_blocks().clone();
+        let abi = self.abi()?;
+
+        // every value gets its registers up front, so uses may precede definitions in block order
+        for param in self.func.get_params() {
+            self.new_value(&param.get_name(), &param.get_type())?;
+        }
+        for block in &blocks {
+            for inst in block.borrow().get_instructions() {
+                let ValueEntity::Instruction(inst) = inst else {
+                    continue;
+                };
+                if inst.get_type().is_void() {
+                    continue;
+                }
+                let class = value_class(&inst.get_type())?;
+                self.new_value(&inst.get_name(), &inst.get_type())?;
+                if let InstructionType::Phi(_) = inst.instruction_type() {
+                    let temp = self.mf.new_vreg(class);
+                    self.phi_temps.insert(inst.get_name(), temp);
+                    if is_pair(&inst.get_type()) {
+                        let high = self.mf.new_vreg(class);
+                        self.phi_high_temps.insert(inst.get_name(), high);
+                    }
+                }
+            }
+        }
+
+        for (i, block) in blocks.iter().enumerate() {
+            let block = block.borrow();
+            self.start_block(block_label(&self.func.get_name(), &block.get_name()), Some(block.get_name()));
+            if i == 0 {
+                if let ReturnLocation::Memory(location) = &abi.ret {
+                    let sret = self.mf.new_vreg(RegClass::Int);
+                    let src = match location {
+                        ArgLocation::Reg(reg) => (*reg).into(),
+                        _ => Mem { base: Base::IncomingArgs, index: None, disp: 0 }.into(),
+                    };
+                    self.emit(Inst::Mov { size: Size::Dword, dst: sret.into(), src });
+                    self.sret = Some(sret);
+                }
+                self.lower_params(&abi)?;
+            }
+            for inst in block.get_instructions() {
+                if let ValueEntity::Instruction(inst) = inst {
+                    self.lower_instruction(&block, inst)?;
+                }
+            }
+        }
+
+        // drop jumps to the block that follows anyway
+        for i in 0..self.mf.blocks.len().saturating_sub(1) {
+            let next = self.mf.blocks[i + 1].label.clone();
+            if let Some(Inst::Jmp { target }) = self.mf.blocks[i].insts.last() {
+                if *target == next {
+                    self.mf.blocks[i].insts.pop();
+                }
+            }
+        }
+
+        Ok(self.mf)
+    }
+
+    /// Creates the registers holding the value `name` of type `ty`.
+    fn new_value(&mut self, name: &str, ty: &Type) -> Result<(), Error> {
+        let vreg = self.mf.new_vreg(value_class(ty)?);
+        self.values.insert(name.to_string(), vreg);
+        if is_pair(ty) {
+            let high = self.mf.new_vreg(RegClass::Int);
+            self.high_halves.insert(name.to_string(), high);
+        }
+        Ok(())
+    }
+
+    fn start_block(&mut self, label: String, comment: Option<String>) {
+        self.mf.blocks.push(MachineBlock::new(label, comment));
+        self.current = self.mf.blocks.len() - 1;
+    }
+
+    fn emit(&mut self, inst: Inst) {
+        self.mf.blocks[self.current].insts.push(inst);
+    }
+
+    /// Returns to the caller, popping `pop` bytes of stack arguments.
+    fn emit_return(&mut self, pop: u64) {
+        match pop {
+            0 => self.emit(Inst::Ret),
+            pop => self.emit(Inst::RetPop { bytes: pop as u16 }),
+        }
+    }
+
+    fn label(&self, block: &str) -> String {
+        block_label(&self.func.get_name(), block)
+    }
+
+    fn result(&self, inst: &Instruction) -> Result<VReg, Error> {
+        self.values.get(&inst.get_name()).copied()
+            .ok_or_else(|| unsupported(format!("instruction {} has no result register", inst.get_name())))
+    }
+
+    /// Returns the low and high halves of the result of a 64-bit instruction.
+    fn result_pair(&self, inst: &Instruction) -> Result<(VReg, VReg), Error> {
+        let high = self.high_halves.get(&inst.get_name()).copied()
+            .ok_or_else(|| unsupported(format!("instruction {} has no 64-bit result", inst.get_name())))?;
+        Ok((self.result(inst)?, high))
+    }
+
+    /// Returns the machine operand holding `value`, which is an immediate for
+    /// constants. Only the low half of 64-bit integers is returned.
+    fn operand(&mut self, value: &ValueEntity) -> Result<Operand, Error> {
+        match value {
+            ValueEntity::Instruction(inst) => self.values.get(&inst.get_name()).map(|vreg| Operand::Reg(Reg::Virt(*vreg)))
+                .ok_or_else(|| unsupported(format!("use of undefined value {}", inst.get_name()))),
+            ValueEntity::Constant(constant) => self.constant(constant),
+            ValueEntity::Function(function) => {
+                let vreg = self.mf.new_vreg(RegClass::Int);
+                self.emit(Inst::Lea { dst: vreg.into(), addr: Mem::symbol(&function.get_name()) });
+                Ok(vreg.into())
+            }
+            ValueEntity::GlobalVariable(global) => {
+                let vreg = self.mf.new_vreg(RegClass::Int);
+                if global.is_thread_local() {
+                    let thread_pointer = self.mf.new_vreg(RegClass::Int);
+                    self.emit(Inst::ThreadPointer { dst: thread_pointer.into() });
+                    self.emit(Inst::Mov { size: Size::Dword, dst: vreg.into(), src: Mem::indntpoff(&global.get_name()).into() });
+                    self.emit(Inst::Alu { op: AluOp::Add, size: Size::Dword, dst: vreg.into(), src: thread_pointer.into() });
+                } else {
+                    self.emit(Inst::Lea { dst: vreg.into(), addr: Mem::symbol(&global.get_name()) });
+                }
+                Ok(vreg.into())
+            }
+            ValueEntity::Argument(argument) => self.values.get(&argument.get_name()).map(|vreg| Operand::Reg(Reg::Virt(*vreg)))
+                .ok_or_else(|| unsupported(format!("use of argument {} outside its function", argument.get_name()))),
+            ValueEntity::BasicBlock(block) => Err(unsupported(format!("basic block {} used as a value", block.get_name()))),
+        }
+    }
+
+    /// Returns the machine operand holding a constant; aggregates are built in
+    /// a frame slot. Only the low half of 64-bit integers is returned.
+    fn constant(&mut self, constant: &Constant) -> Result<Operand, Error> {
+        let ty = constant.get_type();
+        if is_aggregate(&ty) {
+            let data = constant_data(self.layout, &ty, constant)?;
+            let dst = self.mf.new_vreg(RegClass::Int);
+            self.aggregate_slot(dst, &ty);
+            self.store_data(dst, &data);
+            return Ok(dst.into());
+        }
+        match constant.fold() {
+            Some(Scalar::Int(value)) if value.bits() <= 64 => Ok(Operand::Imm(value.to_machine_i64() as i32 as i64)),
+            Some(Scalar::Float(32, bits)) => Ok(self.float_constant(Size::Dword, (f64::from_bits(bits) as f32).to_bits()).into()),
+            Some(Scalar::Float(_, bits)) if bits as u32 == 0 => Ok(self.float_constant(Size::Qword, (bits >> 32) as u32).into()),
+            Some(Scalar::Float(_, bits)) => {
+                // both halves go through memory, as SSE2 has no 64-bit move from a pair of registers
+                let vreg = self.mf.new_vreg(RegClass::Float);
+                let slot = self.mf.new_slot(8, 8);
+                self.emit(Inst::Mov { size: Size::Dword, dst: Mem::slot(slot, 0).into(), src: Operand::Imm(bits as i32 as i64) });
+                self.emit(Inst::Mov { size: Size::Dword, dst: Mem::slot(slot, 4).into(), src: Operand::Imm((bits >> 32) as i32 as i64) });
+                self.emit(Inst::MovSse { size: Size::Qword, dst: vreg.into(), src: Mem::slot(slot, 0).into() });
+                Ok(vreg.into())
+            }
+            Some(Scalar::Address(symbol, offset)) => Ok(self.constant_address(&symbol, offset).into()),
+            _ => Err(unsupported(format!("constant {} of type {}", constant, ty))),
+        }
+    }
+
+    fn constant_address(&mut self, symbol: &str, offset: i64) -> VReg {
+        let vreg = self.mf.new_vreg(RegClass::Int);
+        self.emit(Inst::Lea { dst: vreg.into(), addr: Mem { disp: offset as i32, ..Mem::symbol(symbol) } });
+        vreg
+    }
+
+    /// Stores the contents of a constant to the address in `dst`.
+    fn store_data(&mut self, dst: VReg, data: &[Datum]) {
+        let (bytes, symbols) = data_bytes(data);
+        let mut offset = 0;
+        for chunk in [Size::Dword, Size::Word, Size::Byte] {
+            let len = chunk.bytes() as usize;
+            while bytes.len() - offset >= len {
+                let mut value = [0; 4];
+                value[..len].copy_from_slice(&bytes[offset..offset + len]);
+                let src = Operand::Imm(i32::from_le_bytes(value) as i64);
+                self.emit(Inst::Mov { size: chunk, dst: Mem::base(dst, offset as i32).into(), src });
+                offset += len;
+            }
+        }
+        for (offset, _, symbol, addend) in symbols {
+            let address = self.constant_address(&symbol, addend);
+            self.emit(Inst::Mov { size: Size::Dword, dst: Mem::base(dst, offset as i32).into(), src: address.into() });
+        }
+    }
+
+    /// Returns the low and high halves of a 64-bit integer.
+    fn pair(&mut self, value: &ValueEntity) -> Result<(Operand, Operand), Error> {
+        let name = match value {
+            ValueEntity::Constant(constant) => match constant.fold() {
+                Some(Scalar::Int(value)) if value.bits() <= 64 => {
+                    let value = value.to_i64();
+                    return Ok((Operand::Imm(value as i32 as i64), Operand::Imm((value >> 32) as i32 as i64)));
+                }
+                // addresses are zero-extended
+                Some(Scalar::Address(symbol, offset)) => return Ok((self.constant_address(&symbol, offset).into(), Operand::Imm(0))),
+                _ => return Err(unsupported(format!("constant {} used as a 64-bit integer", constant))),
+            },
+            ValueEntity::Instruction(inst) => inst.get_name(),
+            ValueEntity::Argument(argument) => argument.get_name(),
+            value => return Err(unsupported(format!("{} used as a 64-bit integer", value.get_as_ref()))),
+        };
+        match (self.values.get(&name), self.high_halves.get(&name)) {
+            (Some(low), Some(high)) => Ok(((*low).into(), (*high).into())),
+            _ => Err(unsupported(format!("use of undefined 64-bit value {}", name))),
+        }
+    }
+
+    /// Moves the arguments from where the caller put them into their registers.
+    fn lower_params(&mut self, abi: &CallAbi) -> Result<(), Error> {
+        for (param, location) in self.func.get_params().iter().zip(&abi.args) {
+            let ty = param.get_type();
+            let dst = self.values[&param.get_name()];
+            let incoming = |offset: u64| Mem { base: Base::IncomingArgs, index: None, disp: offset as i32 };
+            match location {
+                ArgLocation::Reg(reg) => self.emit(Inst::Mov { size: Size::Dword, dst: dst.into(), src: (*reg).into() }),
+                // aggregates passed in memory are the callee's own copy
+                ArgLocation::Stack { offset, .. } if is_aggregate(&ty) => self.emit(Inst::Lea { dst: dst.into(), addr: incoming(*offset) }),
+                ArgLocation::Stack { offset, .. } if is_pair(&ty) => {
+                    let high = self.high_halves[&param.get_name()];
+                    self.emit(Inst::Mov { size: Size::Dword, dst: dst.into(), src: incoming(*offset).into() });
+                    self.emit(Inst::Mov { size: Size::Dword, dst: high.into(), src: incoming(*offset + 4).into() });
+                }
+                ArgLocation::Stack { offset, .. } => {
+                    // small integers fill a whole stack word, whose upper bits are undefined like those of registers
+                    let (class, size) = scalar_type(&ty)?;
+                    self.copy(class, size.max(Size::Dword), dst.into(), incoming(*offset).into());
+                }
+                ArgLocation::Ignore => self.aggregate_slot(dst, &ty),
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves `op` into a fresh register unless it is in one already.
+    fn in_reg(&mut self, op: Operand) -> Reg {
+        match op {
+            Operand::Reg(reg) => reg,
+            src => {
+                let vreg = self.mf.new_vreg(RegClass::Int);
+                self.emit(Inst::Mov { size: Size::Dword, dst: vreg.into(), src });
+                vreg.into()
+            }
+        }
+    }
+
+    /// Like `operand`, but immediates are first moved into a fresh register.
+    fn reg(&mut self, value: &ValueEntity) -> Result<Reg, Error> {
+        let op = self.operand(value)?;
+        Ok(self.in_reg(op))
+    }
+
+    /// Returns `src` with its low `bits` bits sign or zero extended to the
+    /// whole register, copying it to a fresh register if that changes anything.
+    fn extend(&mut self, signed: bool, bits: u8, src: Operand) -> Operand {
+        if bits >= 32 {
+            return src;
+        }
+        let shift = 32 - bits as u32;
+        if let Operand::Imm(imm) = src {
+            let imm = imm << shift;
+            let imm = if signed { (imm as i32) >> shift } else { ((imm as u32) >> shift) as i32 };
+            return Operand::Imm(imm as i64);
+        }
+        let vreg = self.mf.new_vreg(RegClass::Int);
+        self.emit(Inst::Mov { size: Size::Dword, dst: vreg.into(), src });
+        if signed {
+            self.emit(Inst::Shift { op: ShiftOp::Shl, size: Size::Dword, dst: vreg.into(), amount: Some(shift as u8) });
+            self.emit(Inst::Shift { op: ShiftOp::Sar, size: Size::Dword, dst: vreg.into(), amount: Some(shift as u8) });
+        } else {
+            self.emit(Inst::Alu { op: AluOp::And, size: Size::Dword, dst: vreg.into(), src: Operand::Imm((1 << bits) - 1) });
+        }
+        vreg.into()
+    }
+
+    fn copy(&mut self, class: RegClass, size: Size, dst: Operand, src: Operand) {
+        match class {
+            RegClass::Int => self.emit(Inst::Mov { size, dst, src }),
+            RegClass::Float => self.emit(Inst::MovSse { size, dst, src }),
+        }
+    }
+
+    /// Sets `dst` to 1 if `cond` holds and to 0 otherwise. Only `eax` to
+    /// `ebx` have byte registers, so the flag goes through `al`.
+    fn set_bool(&mut self, cond: Cond, dst: VReg) {
+        self.emit(Inst::Setcc { cond, dst: PReg::Rax.into() });
+        self.emit(Inst::Movzx { dst_size: Size::Dword, src_size: Size::Byte, dst: dst.into(), src: PReg::Rax.into() });
+    }
+
+    fn lower_instruction(&mut self, block: &BasicBlock, inst: &Instruction) -> Result<(), Error> {
+        match inst.instruction_type() {
+            InstructionType::Add(a, b) => self.lower_alu(inst, AluOp::Add, a, b),
+            InstructionType::Sub(a, b) => self.lower_alu(inst, AluOp::Sub, a, b),
+            InstructionType::And(a, b) => self.lower_alu(inst, AluOp::And, a, b),
+            InstructionType::Or(a, b) => self.lower_alu(inst, AluOp::Or, a, b),
+            InstructionType::Xor(a, b) => self.lower_alu(inst, AluOp::Xor, a, b),
+            InstructionType::Mul(a, b) => {
+                if is_pair(&a.get_type()) {
+                    return self.lower_pair_mul(inst, a, b);
+                }
+                // the low bits of a 32-bit product are those of a narrower one
+                let dst = self.result(inst)?;
+                let lhs = self.operand(a)?;
+                self.emit(Inst::Mov { size: Size::Dword, dst: dst.into(), src: lhs });
+                let rhs = self.reg(b)?;
+                self.emit(Inst::Imul { size: Size::Dword, dst: dst.into(), src: rhs.into() });
+                Ok(())
+            }
+            InstructionType::SDiv(a, b) => {
+                if is_pair(&a.get_type()) {
+                    return self.lower_pair_libcall(inst, "__divdi3", a, b);
+                }
+                self.lower_div(inst, a, b, PReg::Rax, true)
+            }
+            InstructionType::UDiv(a, b) => {
+                if is_pair(&a.get_type()) {
+                    return self.lower_pair_libcall(inst, "__udivdi3", a, b);
+                }
+                self.lower_div(inst, a, b, PReg::Rax, false)
+            }
+            InstructionType::SRem(a, b) => {
+                if is_pair(&a.get_type()) {
+                    return self.lower_pair_libcall(inst, "__moddi3", a, b);
+                }
+                self.lower_div(inst, a, b, PReg::Rdx, true)
+            }
+            InstructionType::URem(a, b) => {
+                if is_pair(&a.get_type()) {
+                    return self.lower_pair_libcall(inst, "__umoddi3", a, b);
+                }
+                self.lower_div(inst, a, b, PReg::Rdx, false)
+            }
+            InstructionType::Shl(a, b) => self.lower_shift(inst, ShiftOp::Shl, a, b),
+            InstructionType::AShr(a, b) => self.lower_shift(inst, ShiftOp::Sar, a, b),
+            InstructionType::LShr(a, b) => self.lower_shift(inst, ShiftOp::Shr, a, b),
+            InstructionType::Eq(a, b) => self.lower_compare(inst, Cond::E, a, b),
+            InstructionType::Ne(a, b) => self.lower_compare(inst, Cond::Ne, a, b),
+            InstructionType::SLt(a, b) => self.lower_compare(inst, Cond::L, a, b),
+            InstructionType::SLe(a, b) => self.lower_compare(inst, Cond::Le, a, b),
+            InstructionType::SGt(a, b) => self.lower_compare(inst, Cond::G, a, b),
+            InstructionType::SGe(a, b) => self.lower_compare(inst, Cond::Ge, a, b),
+            InstructionType::ULt(a, b) => self.lower_compare(inst, Cond::B, a, b),
+            InstructionType::ULe(a, b) => self.lower_compare(inst, Cond::Be, a, b),
+            InstructionType::UGt(a, b) => self.lower_compare(inst, Cond::A, a, b),
+            InstructionType::UGe(a, b) => self.lower_compare(inst, Cond::Ae, a, b),
+            InstructionType::Neg(a) => {
+                if is_pair(&a.get_type()) {
+                    let (low, high) = self.result_pair(inst)?;
+                    let (src_low, src_high) = self.pair(a)?;
+                    self.emit(Inst::Mov { size: Size::Dword, dst: low.into(), src: src_low });
+                    self.emit(Inst::Mov { size: Size::Dword, dst: high.into(), src: src_high });
+                    self.emit(Inst::Unary { op: UnaryOp::Neg, size: Size::Dword, dst: low.into() });
+                    self.emit(Inst::Alu { op: AluOp::Adc, size: Size::Dword, dst: high.into(), src: Operand::Imm(0) });
+                    self.emit(Inst::Unary { op: UnaryOp::Neg, size: Size::Dword, dst: high.into() });
+                    return Ok(());
+                }
+                let dst = self.result(inst)?;
+                let src = self.operand(a)?;
+                self.emit(Inst::Mov { size: Size::Dword, dst: dst.into(), src });
+                self.emit(Inst::Unary { op: UnaryOp::Neg, size: Size::Dword, dst: dst.into() });
+                Ok(())
+            }
+            InstructionType::FAdd(a, b, _) => self.lower_sse(inst, SseOp::Add, a, b),
+            InstructionType::FSub(a, b, _) => self.lower_sse(inst, SseOp::Sub, a, b),
+            InstructionType::FMul(a, b, _) => self.lower_sse(inst, SseOp::Mul, a, b),
+            InstructionType::FDiv(a, b, _) => self.lower_sse(inst, SseOp::Div, a, b),
+            InstructionType::FRem(a, b, _) => {
+                // SSE has no remainder, so it comes from libm, which returns on the x87 stack
+                let (_, size) = scalar_type(&a.get_type())?;
+                let dst = self.result(inst)?;
+                let lhs = self.operand(a)?;
+                let rhs = self.operand(b)?;
+                let bytes = size.bytes() as i32;
+                self.mf.reserve_outgoing_args(2 * bytes as u64);
+                self.emit(Inst::MovSse { size, dst: Mem::base(PReg::Rsp, 0).into(), src: lhs });
+                self.emit(Inst::MovSse { size, dst: Mem::base(PReg::Rsp, bytes).into(), src: rhs });
+                let symbol = if size == Size::Dword { "fmodf" } else { "fmod" };
+                self.emit(Inst::Call { target: CallTarget::Symbol(symbol.to_string()), args: Vec::new() });
+                let slot = self.mf.new_slot(8, 8);
+                self.emit(Inst::Fstp { size, dst: Mem::slot(slot, 0) });
+                self.emit(Inst::MovSse { size, dst: dst.into(), src: Mem::slot(slot, 0).into() });
+                Ok(())
+            }
+            InstructionType::FNeg(a, _) => {
+                let (_, size) = scalar_type(&a.get_type())?;
+                let dst = self.result(inst)?;
+                let src = self.operand(a)?;
+                self.emit(Inst::MovSse { size, dst: dst.into(), src });
+                // flip the sign bit
+                let sign = self.float_constant(size, 0x8000_0000);
+                self.emit(Inst::Xorps { dst: dst.into(), src: sign.into() });
+                Ok(())
+            }
+            InstructionType::FCmp(predicate, a, b, flags) => self.lower_float_compare(inst, *predicate, *flags, a, b),
+            InstructionType::Not(a) => {
+                if is_pair(&a.get_type()) {
+                    let (low, high) = self.result_pair(inst)?;
+                    let (src_low, src_high) = self.pair(a)?;
+                    self.emit(Inst::Mov { size: Size::Dword, dst: low.into(), src: src_low });
+                    self.emit(Inst::Mov { size: Size::Dword, dst: high.into(), src: src_high });
+                    self.emit(Inst::Unary { op: UnaryOp::Not, size: Size::Dword, dst: low.into() });
+                    self.emit(Inst::Unary { op: UnaryOp::Not, size: Size::Dword, dst: high.into() });
+                    return Ok(());
+                }
+                let dst = self.result(inst)?;
+                let src = self.operand(a)?;
+                self.emit(Inst::Mov { size: Size::Dword, dst: dst.into(), src });
+                if a.get_type() == Type::Integer(1) {
+                    // booleans stay 0 or 1
+                    self.emit(Inst::Alu { op: AluOp::Xor, size: Size::Dword, dst: dst.into(), src: Operand::Imm(1) });
+                } else {
+                    self.emit(Inst::Unary { op: UnaryOp::Not, size: Size::Dword, dst: dst.into() });
+                }
+                Ok(())
+            }
+            InstructionType::Trunc(a) | InstructionType::ZExt(a) | InstructionType::SExt(a) | InstructionType::FPTrunc(a)
+            | InstructionType::FPExt(a) | InstructionType::FPToSI(a) | InstructionType::FPToUI(a) | InstructionType::SIToFP(a)
+            | InstructionType::UIToFP(a) | InstructionType::PtrToInt(a) | InstructionType::IntToPtr(a) | InstructionType::Bitcast(a) => {
+                let (opcode, _) = inst.instruction_type().as_cast().unwrap();
+                self.lower_cast(inst, opcode, a)
+            }
+            InstructionType::Alloca(ty, count, align) => self.lower_alloca(inst, ty, count.as_deref(), *align),
+            InstructionType::GetElementPtr(ptr, indices) => self.lower_element_address(inst, ptr, indices),
+            InstructionType::Load(ptr) => {
+                let ty = inst.get_type();
+                let ptr = self.reg(ptr)?;
+                if is_aggregate(&ty) {
+                    let dst = self.result(inst)?;
+                    self.aggregate_slot(dst, &ty);
+                    self.copy_bytes(dst.into(), ptr, self.layout.size_of(&ty));
+                    return Ok(());
+                }
+                if is_pair(&ty) {
+                    let (low, high) = self.result_pair(inst)?;
+                    self.emit(Inst::Mov { size: Size::Dword, dst: low.into(), src: Mem::base(ptr, 0).into() });
+                    self.emit(Inst::Mov { size: Size::Dword, dst: high.into(), src: Mem::base(ptr, 4).into() });
+                    return Ok(());
+                }
+                let dst = self.result(inst)?;
+                match scalar_type(&ty)? {
+                    (RegClass::Int, size @ (Size::Byte | Size::Word)) => self.emit(Inst::Movzx { dst_size: Size::Dword, src_size: size, dst: dst.into(), src: Mem::base(ptr, 0).into() }),
+                    (class, size) => self.copy(class, size, dst.into(), Mem::base(ptr, 0).into()),
+                }
+                Ok(())
+            }
+            InstructionType::Store(ptr, value) => {
+                let ptr = self.reg(ptr)?;
+                if is_aggregate(&value.get_type()) {
+                    let src = self.reg(value)?;
+                    self.copy_bytes(ptr, src, self.layout.size_of(&value.get_type()));
+                    return Ok(());
+                }
+                self.store_scalar(&value.get_type(), Mem::base(ptr, 0), value)
+            }
+            InstructionType::Call(callee, args) => self.lower_call(inst, callee, args),
+            InstructionType::Return(value) => {
+                let abi = self.abi()?;
+                match abi.ret {
+                    ReturnLocation::Memory(_) => {
+                        let sret = self.sret.unwrap();
+                        let src = self.reg(value)?;
+                        self.copy_bytes(sret.into(), src, self.layout.size_of(&value.get_type()));
+                        self.emit(Inst::Mov { size: Size::Dword, dst: PReg::Rax.into(), src: sret.into() });
+                    }
+                    ReturnLocation::Regs(regs) if regs.len() == 2 => {
+                        let (low, high) = self.pair(value)?;
+                        self.emit(Inst::Mov { size: Size::Dword, dst: PReg::Rax.into(), src: low });
+                        self.emit(Inst::Mov { size: Size::Dword, dst: PReg::Rdx.into(), src: high });
+                    }
+                    ReturnLocation::X87 => {
+                        let (_, size) = scalar_type(&value.get_type())?;
+                        let src = self.operand(value)?;
+                        let slot = self.mf.new_slot(8, 8);
+                        self.emit(Inst::MovSse { size, dst: Mem::slot(slot, 0).into(), src });
+                        self.emit(Inst::Fld { size, src: Mem::slot(slot, 0) });
+                    }
+                    _ => {
+                        let src = self.operand(value)?;
+                        self.move_to_arg_reg(&value.get_type(), PReg::Rax, src);
+                    }
+                }
+                self.emit_return(abi.callee_pops);
+                Ok(())
+            }
+            InstructionType::VoidReturn => {
+                let pop = self.abi()?.callee_pops;
+                self.emit_return(pop);
+                Ok(())
+            }
+            InstructionType::Branch(target) => {
+                self.emit_phi_copies(block, &target.get_name())?;
+                let target = self.label(&target.get_name());
+                self.emit(Inst::Jmp { target });
+                Ok(())
+            }
+            InstructionType::BranchIf(cond, if_true, if_false) => {
+                let if_true = if_true.borrow().get_name();
+                let if_false = if_false.borrow().get_name();
+                self.lower_branch_if(block, cond, &if_true, &if_false)
+            }
+            InstructionType::Phi(_) => {
+                let (class, size) = self.register_type(&inst.get_type())?;
+                let dst = self.result(inst)?;
+                let temp = self.phi_temps[&inst.get_name()];
+                self.copy(class, size, dst.into(), temp.into());
+                if let Some(high_temp) = self.phi_high_temps.get(&inst.get_name()).copied() {
+                    let (_, high) = self.result_pair(inst)?;
+                    self.copy(class, size, high.into(), high_temp.into());
+                }
+                Ok(())
+            }
+            InstructionType::Unreachable => {
+                self.emit(Inst::Ud2);
+                Ok(())
+            }
+        }
+    }
+
+    /// Stores a scalar of type `ty` to `dst`.
+    fn store_scalar(&mut self, ty: &Type, dst: Mem, value: &ValueEntity) -> Result<(), Error> {
+        if is_pair(ty) {
+            let (low, high) = self.pair(value)?;
+            let mut high_dst = dst.clone();
+            high_dst.disp += 4;
+            self.emit(Inst::Mov { size: Size::Dword, dst: dst.into(), src: low });
+            self.emit(Inst::Mov { size: Size::Dword, dst: high_dst.into(), src: high });
+            return Ok(());
+        }
+        let (class, size) = scalar_type(ty)?;
+        let value = self.operand(value)?;
+        match (class, size, value) {
+            (RegClass::Int, Size::Byte, Operand::Imm(imm)) => self.emit(Inst::Mov { size, dst: dst.into(), src: Operand::Imm(imm as i8 as i64) }),
+            (RegClass::Int, Size::Word, Operand::Imm(imm)) => self.emit(Inst::Mov { size, dst: dst.into(), src: Operand::Imm(imm as i16 as i64) }),
+            (RegClass::Int, Size::Byte, value) => {
+                // only `eax` to `ebx` have byte registers
+                self.emit(Inst::Mov { size: Size::Dword, dst: PReg::Rax.into(), src: value });
+                self.emit(Inst::Mov { size, dst: dst.into(), src: PReg::Rax.into() });
+            }
+            (class, size, value) => self.copy(class, size, dst.into(), value),
+        }
+        Ok(())
+    }
+
+    /// Adds the constant offset and the scaled indices of a `getelementptr`
+    /// to its pointer. Only the low half of a 64-bit index reaches the address.
+    fn lower_element_address(&mut self, inst: &Instruction, ptr: &ValueEntity, indices: &[Box<ValueEntity>]) -> Result<(), Error> {
+        let dst = self.result(inst)?;
+        let (offset, scaled) = self.layout.indexed_offset(&ptr.get_type().get_pointer_element_type(), indices)
+            .ok_or_else(|| unsupported(format!("`{}` has indices its type can't take", inst)))?;
+        let base = self.operand(ptr)?;
+        self.emit(Inst::Mov { size: Size::Dword, dst: dst.into(), src: base });
+        if offset as i32 != 0 {
+            self.emit(Inst::Alu { op: AluOp::Add, size: Size::Dword, dst: dst.into(), src: Operand::Imm(offset as i32 as i64) });
+        }
+        for (index, stride) in scaled {
+            let ty = index.get_type();
+            let low = match ty {
+                Type::Integer(64) => self.pair(index)?.0,
+                // a boolean is 0 or 1, so it is negated rather than sign-extended
+                Type::Integer(1) => self.operand(index).map(|op| self.extend(false, 1, op))?,
+                Type::Integer(bits) => self.operand(index).map(|op| self.extend(true, bits as u8, op))?,
+                ty => return Err(unsupported(format!("index of type {}", ty))),
+            };
+            let reg = self.mf.new_vreg(RegClass::Int);
+            self.emit(Inst::Mov { size: Size::Dword, dst: reg.into(), src: low });
+            if ty == Type::Integer(1) {
+                self.emit(Inst::Unary { op: UnaryOp::Neg, size: Size::Dword, dst: reg.into() });
+            }
+            if stride != 1 {
+                let stride = self.in_reg(Operand::Imm(stride as i32 as i64));
+                self.emit(Inst::Imul { size: Size::Dword, dst: reg.into(), src: stride.into() });
+            }
+            self.emit(Inst::Alu { op: AluOp::Add, size: Size::Dword, dst: dst.into(), src: reg.into() });
+        }
+        Ok(())
+    }
+
+    fn lower_alloca(&mut self, inst: &Instruction, ty: &Type, count: Option<&ValueEntity>, align: u64) -> Result<(), Error> {
+        let dst = self.result(inst)?;
+        let stride = self.layout.stride_of(ty);
+        // the frame only guarantees 16-byte alignment, anything stricter is done by hand
+        let padding = align.saturating_sub(16);
+        let (count, count_type) = match count {
+            Some(count) => (self.operand(count)?, count.get_type()),
+            None => (Operand::Imm(1), Type::Integer(32)),
+        };
+        // where an over-aligned address is rounded down from
+        let disp = if padding > 0 { align as i32 - 1 } else { 0 };
+        match count {
+            Operand::Imm(count) => {
+                let size = (count as u64 * stride).max(1);
+                if padding == 0 {
+                    let slot = self.mf.new_slot(size, align);
+                    self.emit(Inst::Lea { dst: dst.into(), addr: Mem::slot(slot, 0) });
+                } else {
+                    let slot = self.mf.new_slot(size + padding, 16);
+                    self.emit(Inst::Lea { dst: dst.into(), addr: Mem::slot(slot, disp) });
+                    self.emit(Inst::Alu { op: AluOp::And, size: Size::Dword, dst: dst.into(), src: Operand::Imm(-(align as i64)) });
+                }
+            }
+            count => {
+                // a 64-bit count only has its low half looked at
+                let bytes = self.extend(false, value_bits(&count_type), count);
+                let bytes = self.in_reg(bytes);
+                let total = self.mf.new_vreg(RegClass::Int);
+                self.emit(Inst::Mov { size: Size::Dword, dst: total.into(), src: bytes.into() });
+                let stride_reg = self.mf.new_vreg(RegClass::Int);
+                self.emit(Inst::Mov { size: Size::Dword, dst: stride_reg.into(), src: Operand::Imm(stride as i64) });
+                self.emit(Inst::Imul { size: Size::Dword, dst: total.into(), src: stride_reg.into() });
+                // keep esp a multiple of 16
+                self.emit(Inst::Alu { op: AluOp::Add, size: Size::Dword, dst: total.into(), src: Operand::Imm((15 + padding) as i64) });
+                self.emit(Inst::Alu { op: AluOp::And, size: Size::Dword, dst: total.into(), src: Operand::Imm(-16) });
+                self.emit(Inst::Alu { op: AluOp::Sub, size: Size::Dword, dst: PReg::Rsp.into(), src: total.into() });
+                let addr = Mem { base: Base::ArgsEnd, index: None, disp };
+                self.emit(Inst::Lea { dst: dst.into(), addr });
+                if padding > 0 {
+                    self.emit(Inst::Alu { op: AluOp::And, size: Size::Dword, dst: dst.into(), src: Operand::Imm(-(align as i64)) });
+                }
+                self.mf.dynamic_stack = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn lower_alu(&mut self, inst: &Instruction, op: AluOp, a: &ValueEntity, b: &ValueEntity) -> Result<(), Error> {
+        if is_pair(&a.get_type()) {
+            return self.lower_pair_alu(inst, op, a, b);
+        }
+        let dst = self.result(inst)?;
+        let lhs = self.operand(a)?;
+        let rhs = self.operand(b)?;
+        self.emit(Inst::Mov { size: Size::Dword, dst: dst.into(), src: lhs });
+        self.emit(Inst::Alu { op, size: Size::Dword, dst: dst.into(), src: rhs });
+        Ok(())
+    }
+
+    /// Lowers an operation on 64-bit integers one half at a time, carrying
+    /// from the low half into the high half for sums and differences.
+    fn lower_pair_alu(&mut self, inst: &Instruction, op: AluOp, a: &ValueEntity, b: &ValueEntity) -> Result<(), Error> {
+        let (lhs_low, lhs_high) = self.pair(a)?;
+        let (rhs_low, rhs_high) = self.pair(b)?;
+        let high_op = match op {
+            AluOp::Add => AluOp::Adc,
+            AluOp::Sub => AluOp::Sbb,
+            op => op,
+        };
+        let (low, high) = if inst.get_type() == Type::Integer(1) {
+            (self.mf.new_vreg(RegClass::Int), self.mf.new_vreg(RegClass::Int))
+        } else {
+            self.result_pair(inst)?
+        };
+        self.emit(Inst::Mov { size: Size::Dword, dst: low.into(), src: lhs_low });
+        self.emit(Inst::Mov { size: Size::Dword, dst: high.into(), src: lhs_high });
+        self.emit(Inst::Alu { op, size: Size::Dword, dst: low.into(), src: rhs_low });
+        self.emit(Inst::Alu { op: high_op, size: Size::Dword, dst: high.into(), src: rhs_high });
+        if inst.get_type() == Type::Integer(1) {
+            // a boolean result of wider operands is true when any bit is set
+            let dst = self.result(inst)?;
+            self.emit(Inst::Alu { op: AluOp::Or, size: Size::Dword, dst: low.into(), src: high.into() });
+            self.set_bool(Cond::Ne, dst);
+        }
+        Ok(())
+    }
+
+    /// Multiplies 64-bit integers from the full product of the low halves
+    /// and the low halves of the two cross products.
+    fn lower_pair_mul(&mut self, inst: &Instruction, a: &ValueEntity, b: &ValueEntity) -> Result<(), Error> {
+        let (low, high) = self.result_pair(inst)?;
+        let (lhs_low, lhs_high) = self.pair(a)?;
+        let (rhs_low, rhs_high) = self.pair(b)?;
+        let rhs_low = self.in_reg(rhs_low);
+        let rhs_high = self.in_reg(rhs_high);
+
+        let cross = self.mf.new_vreg(RegClass::Int);
+        self.emit(Inst::Mov { size: Size::Dword, dst: cross.into(), src: lhs_low.clone() });
+        self.emit(Inst::Imul { size: Size::Dword, dst: cross.into(), src: rhs_high.into() });
+        let other_cross = self.mf.new_vreg(RegClass::Int);
+        self.emit(Inst::Mov { size: Size::Dword, dst: other_cross.into(), src: lhs_high });
+        self.emit(Inst::Imul { size: Size::Dword, dst: other_cross.into(), src: rhs_low.into() });
+        self.emit(Inst::Alu { op: AluOp::Add, size: Size::Dword, dst: cross.into(), src: other_cross.into() });
+
+        self.emit(Inst::Mov { size: Size::Dword, dst: PReg::Rax.into(), src: lhs_low });
+        self.emit(Inst::Mul { size: Size::Dword, src: rhs_low.into() });
+        self.emit(Inst::Mov { size: Size::Dword, dst: low.into(), src: PReg::Rax.into() });
+        self.emit(Inst::Mov { size: Size::Dword, dst: high.into(), src: PReg::Rdx.into() });
+        self.emit(Inst::Alu { op: AluOp::Add, size: Size::Dword, dst: high.into(), src: cross.into() });
+        Ok(())
+    }
+
+    /// Lowers a 64-bit operation to a call of the libgcc routine `symbol`,
+    /// which takes two 64-bit integers and returns one.
+    fn lower_pair_libcall(&mut self, inst: &Instruction, symbol: &str, a: &ValueEntity, b: &ValueEntity) -> Result<(), Error> {
+        let (low, high) = self.result_pair(inst)?;
+        let (lhs_low, lhs_high) = self.pair(a)?;
+        let (rhs_low, rhs_high) = self.pair(b)?;
+        self.mf.reserve_outgoing_args(16);
+        for (i, src) in [lhs_low, lhs_high, rhs_low, rhs_high].into_iter().enumerate() {
+            self.emit(Inst::Mov { size: Size::Dword, dst: Mem::base(PReg::Rsp, 4 * i as i32).into(), src });
+        }
+        self.emit(Inst::Call { target: CallTarget::Symbol(symbol.to_string()), args: Vec::new() });
+        self.emit(Inst::Mov { size: Size::Dword, dst: low.into(), src: PReg::Rax.into() });
+        self.emit(Inst::Mov { size: Size::Dword, dst: high.into(), src: PReg::Rdx.into() });
+        Ok(())
+    }
+
+    /// Lowers the conversion `opcode` of `a` to the type of `inst`. Floats
+    /// convert to and from 32-bit integers in SSE registers, unsigned ones
+    /// through the signed range, and to and from 64-bit integers in libgcc.
+    fn lower_cast(&mut self, inst: &Instruction, opcode: &str, a: &ValueEntity) -> Result<(), Error> {
+        let (from, to) = (a.get_type(), inst.get_type());
+        let (from_class, from_size) = scalar_type(&from)?;
+        let (to_class, to_size) = scalar_type(&to)?;
+        match opcode {
+            "bitcast" if is_pair(&from) && to_class == RegClass::Float => {
+                let dst = self.result(inst)?;
+                let (low, high) = self.pair(a)?;
+                let slot = self.mf.new_slot(8, 8);
+                self.emit(Inst::Mov { size: Size::Dword, dst: Mem::slot(slot, 0).into(), src: low });
+                self.emit(Inst::Mov { size: Size::Dword, dst: Mem::slot(slot, 4).into(), src: high });
+                self.emit(Inst::MovSse { size: Size::Qword, dst: dst.into(), src: Mem::slot(slot, 0).into() });
+            }
+            "bitcast" if is_pair(&to) && from_class == RegClass::Float => {
+                let (low, high) = self.result_pair(inst)?;
+                let src = self.operand(a)?;
+                let slot = self.mf.new_slot(8, 8);
+                self.emit(Inst::MovSse { size: Size::Qword, dst: Mem::slot(slot, 0).into(), src });
+                self.emit(Inst::Mov { size: Size::Dword, dst: low.into(), src: Mem::slot(slot, 0).into() });
+                self.emit(Inst::Mov { size: Size::Dword, dst: high.into(), src: Mem::slot(slot, 4).into() });
+            }
+            "bitcast" if is_pair(&from) => {
+                let (low, high) = self.result_pair(inst)?;
+                let (src_low, src_high) = self.pair(a)?;
+                self.emit(Inst::Mov { size: Size::Dword, dst: low.into(), src: src_low });
+                self.emit(Inst::Mov { size: Size::Dword, dst: high.into(), src: src_high });
+            }
+            "bitcast" => {
+                let dst = self.result(inst)?;
+                let src = self.operand(a)?;
+                match (from_class, to_class) {
+                    (RegClass::Int, RegClass::Float) => {
+                        let src = self.in_reg(src);
+                        self.emit(Inst::MovToXmm { size: Size::Dword, dst: dst.into(), src: src.into() });
+                    }
+                    (RegClass::Float, RegClass::Int) => {
+                        let Operand::Reg(src) = src else { unreachable!() };
+                        self.emit(Inst::MovFromXmm { size: Size::Dword, dst: dst.into(), src });
+                    }
+                    (class, _) => self.copy(class, to_size.max(Size::Dword), dst.into(), src),
+                }
+            }
+            "fptrunc" | "fpext" => {
+                let dst = self.result(inst)?;
+                let src = self.operand(a)?;
+                self.emit(Inst::Cvts2s { size: to_size, dst: dst.into(), src });
+            }
+            "sitofp" | "uitofp" if is_pair(&from) => {
+                let symbol = match (opcode, to_size) {
+                    ("sitofp", Size::Dword) => "__floatdisf",
+                    ("sitofp", _) => "__floatdidf",
+                    (_, Size::Dword) => "__floatundisf",
+                    _ => "__floatundidf",
+                };
+                let dst = self.result(inst)?;
+                let (low, high) = self.pair(a)?;
+                self.mf.reserve_outgoing_args(8);
+                self.emit(Inst::Mov { size: Size::Dword, dst: Mem::base(PReg::Rsp, 0).into(), src: low });
+                self.emit(Inst::Mov { size: Size::Dword, dst: Mem::base(PReg::Rsp, 4).into(), src: high });
+                self.emit(Inst::Call { target: CallTarget::Symbol(symbol.to_string()), args: Vec::new() });
+                let slot = self.mf.new_slot(8, 8);
+                self.emit(Inst::Fstp { size: to_size, dst: Mem::slot(slot, 0) });
+                self.emit(Inst::MovSse { size: to_size, dst: dst.into(), src: Mem::slot(slot, 0).into() });
+            }
+            "uitofp" if value_bits(&from) == 32 => {
+                // offset into the signed range and back, exactly in double precision
+                let dst = self.result(inst)?;
+                let src = self.operand(a)?;
+                let signed = self.mf.new_vreg(RegClass::Int);
+                let converted = self.mf.new_vreg(RegClass::Float);
+                self.emit(Inst::Mov { size: Size::Dword, dst: signed.into(), src });
+                self.emit(Inst::Alu { op: AluOp::Xor, size: Size::Dword, dst: signed.into(), src: Operand::Imm(i32::MIN as i64) });
+                self.emit(Inst::Cvtsi2s { int_size: Size::Dword, size: Size::Qword, dst: converted.into(), src: signed.into() });
+                let bias = self.float_constant(Size::Qword, 0x41e0_0000);
+                self.emit(Inst::SseAlu { op: SseOp::Add, size: Size::Qword, dst: converted.into(), src: bias.into() });
+                if to_size == Size::Dword {
+                    self.emit(Inst::Cvts2s { size: Size::Dword, dst: dst.into(), src: converted.into() });
+                } else {
+                    self.emit(Inst::MovSse { size: Size::Qword, dst: dst.into(), src: converted.into() });
+                }
+            }
+            "sitofp" | "uitofp" => {
+                let dst = self.result(inst)?;
+                let src = self.operand(a)?;
+                let src = self.extend(opcode == "sitofp", value_bits(&from), src);
+                let src = self.in_reg(src);
+                self.emit(Inst::Cvtsi2s { int_size: Size::Dword, size: to_size, dst: dst.into(), src: src.into() });
+            }
+            "fptosi" | "fptoui" if is_pair(&to) => {
+                let symbol = match (opcode, from_size) {
+                    ("fptosi", Size::Dword) => "__fixsfdi",
+                    ("fptosi", _) => "__fixdfdi",
+                    (_, Size::Dword) => "__fixunssfdi",
+                    _ => "__fixunsdfdi",
+                };
+                let (low, high) = self.result_pair(inst)?;
+                let src = self.operand(a)?;
+                self.mf.reserve_outgoing_args(8);
+                self.emit(Inst::MovSse { size: from_size, dst: Mem::base(PReg::Rsp, 0).into(), src });
+                self.emit(Inst::Call { target: CallTarget::Symbol(symbol.to_string()), args: Vec::new() });
+                self.emit(Inst::Mov { size: Size::Dword, dst: low.into(), src: PReg::Rax.into() });
+                self.emit(Inst::Mov { size: Size::Dword, dst: high.into(), src: PReg::Rdx.into() });
+            }
+            "fptoui" if value_bits(&to) == 32 => {
+                // values from 2^31 on overflow the signed conversion to exactly
+                // its top bit, so the conversion of `a - 2^31` supplies the other bits
+                let dst = self.result(inst)?;
+                let src = self.reg(a)?;
+                let bias = self.float_constant(from_size, if from_size == Size::Dword { 0x4f00_0000 } else { 0x41e0_0000 });
+                let reduced = self.mf.new_vreg(RegClass::Float);
+                let [high, mask] = [(); 2].map(|_| self.mf.new_vreg(RegClass::Int));
+                self.emit(Inst::Cvtts2si { int_size: Size::Dword, size: from_size, dst: dst.into(), src: src.into() });
+                self.emit(Inst::MovSse { size: from_size, dst: reduced.into(), src: src.into() });
+                self.emit(Inst::SseAlu { op: SseOp::Sub, size: from_size, dst: reduced.into(), src: bias.into() });
+                self.emit(Inst::Cvtts2si { int_size: Size::Dword, size: from_size, dst: high.into(), src: reduced.into() });
+                self.emit(Inst::Mov { size: Size::Dword, dst: mask.into(), src: dst.into() });
+                self.emit(Inst::Shift { op: ShiftOp::Sar, size: Size::Dword, dst: mask.into(), amount: Some(31) });
+                self.emit(Inst::Alu { op: AluOp::And, size: Size::Dword, dst: high.into(), src: mask.into() });
+                self.emit(Inst::Alu { op: AluOp::Or, size: Size::Dword, dst: dst.into(), src: high.into() });
+            }
+            "fptosi" | "fptoui" => {
+                // every value that fits a narrower integer is in the signed 32-bit range
+                let dst = self.result(inst)?;
+                let src = self.operand(a)?;
+                self.emit(Inst::Cvtts2si { int_size: Size::Dword, size: from_size, dst: dst.into(), src });
+                if to == Type::Integer(1) {
+                    self.emit(Inst::Alu { op: AluOp::And, size: Size::Dword, dst: dst.into(), src: Operand::Imm(1) });
+                }
+            }
+            // the rest convert integers and pointers
+            _ if is_pair(&to) => {
+                let (low, high) = self.result_pair(inst)?;
+                let src = self.operand(a)?;
+                let signed = opcode == "sext";
+                let src = self.extend(signed, value_bits(&from), src);
+                self.emit(Inst::Mov { size: Size::Dword, dst: low.into(), src });
+                if signed {
+                    self.emit(Inst::Mov { size: Size::Dword, dst: high.into(), src: low.into() });
+                    self.emit(Inst::Shift { op: ShiftOp::Sar, size: Size::Dword, dst: high.into(), amount: Some(31) });
+                } else {
+                    self.emit(Inst::Mov { size: Size::Dword, dst: high.into(), src: Operand::Imm(0) });
+                }
+            }
+            _ => {
+                let dst = self.result(inst)?;
+                let src = if is_pair(&from) { self.pair(a)?.0 } else { self.operand(a)? };
+                let src = match opcode {
+                    "zext" | "inttoptr" => self.extend(false, value_bits(&from), src),
+                    "sext" => self.extend(true, value_bits(&from), src),
+                    _ => src,
+                };
+                self.emit(Inst::Mov { size: Size::Dword, dst: dst.into(), src });
+                if to == Type::Integer(1) {
+                    self.emit(Inst::Alu { op: AluOp::And, size: Size::Dword, dst: dst.into(), src: Operand::Imm(1) });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns a register holding the float of `size` whose bits are `high`
+    /// followed by zeros, which for a `float` is all of them.
+    fn float_constant(&mut self, size: Size, high: u32) -> VReg {
+        let bits = self.mf.new_vreg(RegClass::Int);
+        let value = self.mf.new_vreg(RegClass::Float);
+        self.emit(Inst::Mov { size: Size::Dword, dst: bits.into(), src: Operand::Imm(high as i32 as i64) });
+        self.emit(Inst::MovToXmm { size: Size::Dword, dst: value.into(), src: bits.into() });
+        if size == Size::Qword {
+            self.emit(Inst::Psllq { dst: value.into(), amount: 32 });
+        }
+        value
+    }
+
+    fn lower_sse(&mut self, inst: &Instruction, op: SseOp, a: &ValueEntity, b: &ValueEntity) -> Result<(), Error> {
+        let (_, size) = scalar_type(&a.get_type())?;
+        let dst = self.result(inst)?;
+        let lhs = self.operand(a)?;
+        let rhs = self.operand(b)?;
+        self.emit(Inst::MovSse { size, dst: dst.into(), src: lhs });
+        self.emit(Inst::SseAlu { op, size, dst: dst.into(), src: rhs });
+        Ok(())
+    }
+
+    fn lower_div(&mut self, inst: &Instruction, a: &ValueEntity, b: &ValueEntity, result: PReg, signed: bool) -> Result<(), Error> {
+        let bits = value_bits(&a.get_type());
+        let dst = self.result(inst)?;
+        let lhs = self.operand(a)?;
+        let lhs = self.extend(signed, bits, lhs);
+        let rhs = self.operand(b)?;
+        let rhs = self.extend(signed, bits, rhs);
+        let rhs = self.in_reg(rhs);
+        self.emit(Inst::Mov { size: Size::Dword, dst: PReg::Rax.into(), src: lhs });
+        if signed {
+            self.emit(Inst::SignExtendAcc { size: Size::Dword });
+        } else {
+            self.emit(Inst::Mov { size: Size::Dword, dst: PReg::Rdx.into(), src: Operand::Imm(0) });
+        }
+        self.emit(Inst::Div { signed, size: Size::Dword, src: rhs.into() });
+        self.emit(Inst::Mov { size: Size::Dword, dst: dst.into(), src: result.into() });
+        Ok(())
+    }
+
+    fn lower_shift(&mut self, inst: &Instruction, op: ShiftOp, a: &ValueEntity, b: &ValueEntity) -> Result<(), Error> {
+        if is_pair(&a.get_type()) {
+            return self.lower_pair_shift(inst, op, a, b);
+        }
+        let (_, size) = scalar_type(&a.get_type())?;
+        let dst = self.result(inst)?;
+        let lhs = self.operand(a)?;
+        // bits shifted in from the right must be copies of the sign bit, or zeros
+        let lhs = match op {
+            ShiftOp::Shl => lhs,
+            op => self.extend(op == ShiftOp::Sar, value_bits(&a.get_type()), lhs),
+        };
+        let amount = self.operand(b)?;
+        self.emit(Inst::Mov { size: Size::Dword, dst: dst.into(), src: lhs });
+        match amount {
+            Operand::Imm(amount) => {
+                let amount = (amount as u64 % (size.bytes() * 8)) as u8;
+                self.emit(Inst::Shift { op, size: Size::Dword, dst: dst.into(), amount: Some(amount) });
+            }
+            amount => {
+                self.emit(Inst::Mov { size: Size::Dword, dst: PReg::Rcx.into(), src: amount });
+                self.emit(Inst::Shift { op, size: Size::Dword, dst: dst.into(), amount: None });
+            }
+        }
+        Ok(())
+    }
+
+    /// Shifts a 64-bit integer with `shld`/`shrd`, which only look at the
+    /// low five bits of the amount, and moves a half over to the other for
+    /// amounts of 32 or more.
+    fn lower_pair_shift(&mut self, inst: &Instruction, op: ShiftOp, a: &ValueEntity, b: &ValueEntity) -> Result<(), Error> {
+        let (low, high) = self.result_pair(inst)?;
+        let (src_low, src_high) = self.pair(a)?;
+        // the half the bits move into, and the one they move out of
+        let (into, from, double_op) = if op == ShiftOp::Shl { (high, low, DoubleShiftOp::Shld) } else { (low, high, DoubleShiftOp::Shrd) };
+        let (from_src, into_src) = if op == ShiftOp::Shl { (src_low, src_high) } else { (src_high, src_low) };
+        // what the emptied half is filled with
+        let fill = self.mf.new_vreg(RegClass::Int);
+        if op != ShiftOp::Sar {
+            self.emit(Inst::Mov { size: Size::Dword, dst: fill.into(), src: Operand::Imm(0) });
+        } else {
+            self.emit(Inst::Mov { size: Size::Dword, dst: fill.into(), src: from_src.clone() });
+            self.emit(Inst::Shift { op: ShiftOp::Sar, size: Size::Dword, dst: fill.into(), amount: Some(31) });
+        }
+
+        match self.operand(b)? {
+            Operand::Imm(amount) => {
+                let amount = (amount as u64 % 64) as u8;
+                if amount < 32 {
+                    self.emit(Inst::Mov { size: Size::Dword, dst: into.into(), src: into_src });
+                    self.emit(Inst::Mov { size: Size::Dword, dst: from.into(), src: from_src });
+                    if amount > 0 {
+                        self.emit(Inst::DoubleShift { op: double_op, size: Size::Dword, dst: into.into(), src: from.into(), amount: Some(amount) });
+                        self.emit(Inst::Shift { op, size: Size::Dword, dst: from.into(), amount: Some(amount) });
+                    }
+                } else {
+                    self.emit(Inst::Mov { size: Size::Dword, dst: into.into(), src: from_src });
+                    self.emit(Inst::Shift { op, size: Size::Dword, dst: into.into(), amount: Some(amount - 32) });
+                    self.emit(Inst::Mov { size: Size::Dword, dst: from.into(), src: fill.into() });
+                }
+            }
+            amount => {
+                self.emit(Inst::Mov { size: Size::Dword, dst: PReg::Rcx.into(), src: amount });
+                self.emit(Inst::Mov { size: Size::Dword, dst: into.into(), src: into_src });
+                self.emit(Inst::Mov { size: Size::Dword, dst: from.into(), src: from_src });
+                self.emit(Inst::DoubleShift { op: double_op, size: Size::Dword, dst: into.into(), src: from.into(), amount: None });
+                self.emit(Inst::Shift { op, size: Size::Dword, dst: from.into(), amount: None });
+                self.emit(Inst::Test { size: Size::Dword, lhs: PReg::Rcx.into(), rhs: Operand::Imm(32) });
+                self.emit(Inst::Cmov { cond: Cond::Ne, size: Size::Dword, dst: into.into(), src: from.into() });
+                self.emit(Inst::Cmov { cond: Cond::Ne, size: Size::Dword, dst: from.into(), src: fill.into() });
+            }
+        }
+        Ok(())
+    }
+
+    fn lower_compare(&mut self, inst: &Instruction, cond: Cond, a: &ValueEntity, b: &ValueEntity) -> Result<(), Error> {
+        let dst = self.result(inst)?;
+        if is_pair(&a.get_type()) {
+            return self.lower_pair_compare(dst, cond, a, b);
+        }
+        let bits = value_bits(&a.get_type());
+        let signed = !matches!(cond, Cond::B | Cond::Be | Cond::A | Cond::Ae);
+        let lhs = self.operand(a)?;
+        let lhs = self.extend(signed, bits, lhs);
+        let lhs = self.in_reg(lhs);
+        let rhs = self.operand(b)?;
+        let rhs = self.extend(signed, bits, rhs);
+        self.emit(Inst::Cmp { size: Size::Dword, lhs: lhs.into(), rhs });
+        self.set_bool(cond, dst);
+        Ok(())
+    }
+
+    /// Lowers `fcmp` the way the x86_64 backend does: `ucomis` reports
+    /// "unordered" as ZF=PF=CF=1, so orderings that reject NaN use `a`/`ae`
+    /// and those that accept it `b`/`be`, and equality also checks parity.
+    fn lower_float_compare(&mut self, inst: &Instruction, predicate: FloatPredicate, flags: FastMathFlags, a: &ValueEntity, b: &ValueEntity) -> Result<(), Error> {
+        let (_, size) = scalar_type(&a.get_type())?;
+        let dst = self.result(inst)?;
+        let lhs = self.reg(a)?;
+        let rhs = self.reg(b)?;
+        let predicate = match predicate {
+            FloatPredicate::Oeq if flags.nnan => FloatPredicate::Ueq,
+            FloatPredicate::Une if flags.nnan => FloatPredicate::One,
+            predicate => predicate,
+        };
+        let (swap, cond) = match predicate {
+            FloatPredicate::Ogt => (false, Cond::A),
+            FloatPredicate::Oge => (false, Cond::Ae),
+            FloatPredicate::Olt => (true, Cond::A),
+            FloatPredicate::Ole => (true, Cond::Ae),
+            FloatPredicate::Ugt => (true, Cond::B),
+            FloatPredicate::Uge => (true, Cond::Be),
+            FloatPredicate::Ult => (false, Cond::B),
+            FloatPredicate::Ule => (false, Cond::Be),
+            FloatPredicate::Oeq | FloatPredicate::Ueq => (false, Cond::E),
+            FloatPredicate::One | FloatPredicate::Une => (false, Cond::Ne),
+            FloatPredicate::Ord => (false, Cond::Np),
+            FloatPredicate::Uno => (false, Cond::P),
+        };
+        let (lhs, rhs) = if swap { (rhs, lhs) } else { (lhs, rhs) };
+        self.emit(Inst::Ucomi { size, lhs, rhs: rhs.into() });
+        if matches!(predicate, FloatPredicate::Oeq | FloatPredicate::Une) {
+            let parity = self.mf.new_vreg(RegClass::Int);
+            let (parity_cond, combine) = if predicate == FloatPredicate::Oeq { (Cond::Np, AluOp::And) } else { (Cond::P, AluOp::Or) };
+            self.set_bool(parity_cond, parity);
+            self.set_bool(cond, dst);
+            self.emit(Inst::Alu { op: combine, size: Size::Dword, dst: dst.into(), src: parity.into() });
+        } else {
+            self.set_bool(cond, dst);
+        }
+        Ok(())
+    }
+
+    /// Compares 64-bit integers. Equality checks both halves at once, orders
+    /// come from the flags of subtracting the high halves with the borrow of
+    /// the low halves.
+    fn lower_pair_compare(&mut self, dst: VReg, cond: Cond, a: &ValueEntity, b: &ValueEntity) -> Result<(), Error> {
+        let (lhs_low, lhs_high) = self.pair(a)?;
+        let (rhs_low, rhs_high) = self.pair(b)?;
+        if matches!(cond, Cond::E | Cond::Ne) {
+            let low = self.mf.new_vreg(RegClass::Int);
+            let high = self.mf.new_vreg(RegClass::Int);
+            self.emit(Inst::Mov { size: Size::Dword, dst: low.into(), src: lhs_low });
+            self.emit(Inst::Alu { op: AluOp::Xor, size: Size::Dword, dst: low.into(), src: rhs_low });
+            self.emit(Inst::Mov { size: Size::Dword, dst: high.into(), src: lhs_high });
+            self.emit(Inst::Alu { op: AluOp::Xor, size: Size::Dword, dst: high.into(), src: rhs_high });
+            self.emit(Inst::Alu { op: AluOp::Or, size: Size::Dword, dst: low.into(), src: high.into() });
+            self.set_bool(cond, dst);
+            return Ok(());
+        }
+
+        // only `l`, `ge`, `b` and `ae` can be read off a subtraction, so `a > b` is `b < a`
+        let (cond, (x_low, x_high), (y_low, y_high)) = match cond {
+            Cond::L | Cond::Ge | Cond::B | Cond::Ae => (cond, (lhs_low, lhs_high), (rhs_low, rhs_high)),
+            Cond::G => (Cond::L, (rhs_low, rhs_high), (lhs_low, lhs_high)),
+            Cond::Le => (Cond::Ge, (rhs_low, rhs_high), (lhs_low, lhs_high)),
+            Cond::A => (Cond::B, (rhs_low, rhs_high), (lhs_low, lhs_high)),
+            _ => (Cond::Ae, (rhs_low, rhs_high), (lhs_low, lhs_high)),
+        };
+        let x_low = self.in_reg(x_low);
+        let high = self.mf.new_vreg(RegClass::Int);
+        self.emit(Inst::Mov { size: Size::Dword, dst: high.into(), src: x_high });
+        self.emit(Inst::Cmp { size: Size::Dword, lhs: x_low.into(), rhs: y_low });
+        self.emit(Inst::Alu { op: AluOp::Sbb, size: Size::Dword, dst: high.into(), src: y_high });
+        self.set_bool(cond, dst);
+        Ok(())
+    }
+
+    fn lower_call(&mut self, inst: &Instruction, callee: &ValueEntity, args: &[Box<ValueEntity>]) -> Result<(), Error> {
+        let (callee_type, conv) = match callee {
+            ValueEntity::Function(function) => (function.get_type(), function.get_calling_conv()),
+            // the calling convention is not part of the type, so indirect calls use the default one
+            callee => (callee.get_type().get_pointer_element_type(), CallingConv::C),
+        };
+        let arg_types = args.iter().map(|arg| arg.get_type()).collect::<Vec<_>>();
+        let ret_type = callee_type.get_function_return_type();
+        let abi = classify_call(self.layout, &arg_types, &ret_type, conv)?;
+
+        let target = match callee {
+            ValueEntity::Function(function) => CallTarget::Symbol(function.get_name()),
+            callee => CallTarget::Indirect(self.reg(callee)?.into()),
+        };
+        let sret = if let ReturnLocation::Memory(_) = abi.ret {
+            let sret = self.mf.new_vreg(RegClass::Int);
+            self.aggregate_slot(sret, &ret_type);
+            Some(sret)
+        } else {
+            None
+        };
+
+        // stack arguments go to the bottom of the frame, which the prologue reserves
+        self.mf.reserve_outgoing_args(abi.stack_size);
+        if let (Some(sret), ReturnLocation::Memory(ArgLocation::Stack { offset, .. })) = (sret, &abi.ret) {
+            self.emit(Inst::Mov { size: Size::Dword, dst: Mem::base(PReg::Rsp, *offset as i32).into(), src: sret.into() });
+        }
+        for ((location, ty), arg) in abi.args.iter().zip(&arg_types).zip(args) {
+            let ArgLocation::Stack { offset, size } = location else {
+                continue;
+            };
+            let dst = Mem::base(PReg::Rsp, *offset as i32);
+            if is_aggregate(ty) {
+                let src = self.reg(arg)?;
+                self.copy_bytes_to(dst, src, *size);
+            } else if is_pair(ty) || ty.is_float() {
+                self.store_scalar(ty, dst, arg)?;
+            } else {
+                // small integers are widened to a whole stack word
+                let src = self.operand(arg)?;
+                let src = self.extend(*ty != Type::Integer(1), value_bits(ty), src);
+                self.emit(Inst::Mov { size: Size::Dword, dst: dst.into(), src });
+            }
+        }
+        // registers are written last, so nothing in between can clobber them
+        let mut arg_regs = Vec::new();
+        for ((location, ty), arg) in abi.args.iter().zip(&arg_types).zip(args) {
+            if let ArgLocation::Reg(reg) = location {
+                let src = self.operand(arg)?;
+                self.move_to_arg_reg(ty, *reg, src);
+                arg_regs.push(*reg);
+            }
+        }
+        if let (Some(sret), ReturnLocation::Memory(ArgLocation::Reg(reg))) = (sret, &abi.ret) {
+            self.emit(Inst::Mov { size: Size::Dword, dst: (*reg).into(), src: sret.into() });
+            arg_regs.push(*reg);
+        }
+        self.emit(Inst::Call { target, args: arg_regs });
+        if abi.callee_pops > 0 {
+            // give back what the callee popped of the outgoing argument area
+            self.emit(Inst::Alu { op: AluOp::Sub, size: Size::Dword, dst: PReg::Rsp.into(), src: Operand::Imm(abi.callee_pops as i64) });
+        }
+
+        match abi.ret {
+            ReturnLocation::Void => {}
+            ReturnLocation::Memory(_) => {
+                let dst = self.result(inst)?;
+                self.emit(Inst::Mov { size: Size::Dword, dst: dst.into(), src: sret.unwrap().into() });
+            }
+            ReturnLocation::Regs(regs) if regs.len() == 2 => {
+                let (low, high) = self.result_pair(inst)?;
+                self.emit(Inst::Mov { size: Size::Dword, dst: low.into(), src: PReg::Rax.into() });
+                self.emit(Inst::Mov { size: Size::Dword, dst: high.into(), src: PReg::Rdx.into() });
+            }
+            ReturnLocation::Regs(_) => {
+                let dst = self.result(inst)?;
+                self.emit(Inst::Mov { size: Size::Dword, dst: dst.into(), src: PReg::Rax.into() });
+            }
+            ReturnLocation::X87 => {
+                // the result has to come off the x87 stack even when it is not used
+                let (_, size) = scalar_type(&ret_type)?;
+                let dst = self.result(inst)?;
+                let slot = self.mf.new_slot(8, 8);
+                self.emit(Inst::Fstp { size, dst: Mem::slot(slot, 0) });
+                self.emit(Inst::MovSse { size, dst: dst.into(), src: Mem::slot(slot, 0).into() });
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves a scalar into an argument or return register, widening small integers to 32 bits.
+    fn move_to_arg_reg(&mut self, ty: &Type, reg: PReg, src: Operand) {
+        let Ok((class, size)) = scalar_type(ty) else {
+            return;
+        };
+        match class {
+            RegClass::Int => {
+                let src = self.extend(*ty != Type::Integer(1), value_bits(ty), src);
+                self.emit(Inst::Mov { size: Size::Dword, dst: reg.into(), src });
+            }
+            RegClass::Float => self.emit(Inst::MovSse { size, dst: reg.into(), src }),
+        }
+    }
+
+    /// Returns the register class and width used to hold a value of type `ty`,
+    /// where aggregates are held by address and 64-bit integers by halves.
+    fn register_type(&self, ty: &Type) -> Result<(RegClass, Size), Error> {
+        if is_aggregate(ty) {
+            Ok((RegClass::Int, Size::Dword))
+        } else {
+            scalar_type(ty).map(|(class, size)| if class == RegClass::Int { (class, Size::Dword) } else { (class, size) })
+        }
+    }
+
+    /// Allocates a frame slot for an aggregate, padded to whole stack words, and puts its address in `dst`.
+    fn aggregate_slot(&mut self, dst: VReg, ty: &Type) {
+        let size = self.layout.size_of(ty).next_multiple_of(4);
+        let slot = self.mf.new_slot(size, self.layout.align_of(ty).max(4));
+        self.emit(Inst::Lea { dst: dst.into(), addr: Mem::slot(slot, 0) });
+    }
+
+    /// Copies `size` bytes from the address in `src` to the address in `dst`.
+    fn copy_bytes(&mut self, dst: Reg, src: Reg, size: u64) {
+        self.copy_bytes_to(Mem::base(dst, 0), src, size);
+    }
+
+    fn copy_bytes_to(&mut self, dst: Mem, src: Reg, size: u64) {
+        let mut offset = 0;
+        for chunk in [Size::Dword, Size::Word, Size::Byte] {
+            while size - offset >= chunk.bytes() {
+                let mut to = dst.clone();
+                to.disp += offset as i32;
+                let from = Mem::base(src, offset as i32);
+                if chunk == Size::Byte {
+                    // only `eax` to `ebx` have byte registers
+                    self.emit(Inst::Movzx { dst_size: Size::Dword, src_size: chunk, dst: PReg::Rax.into(), src: from.into() });
+                    self.emit(Inst::Mov { size: chunk, dst: to.into(), src: PReg::Rax.into() });
+                } else {
+                    let temp = self.mf.new_vreg(RegClass::Int);
+                    self.emit(Inst::Mov { size: chunk, dst: temp.into(), src: from.into() });
+                    self.emit(Inst::Mov { size: chunk, dst: to.into(), src: temp.into() });
+                }
+                offset += chunk.bytes();
+            }
+        }
+    }
+
+    fn lower_branch_if(&mut self, block: &BasicBlock, cond: &ValueEntity, if_true: &str, if_false: &str) -> Result<(), Error> {
+        let cond = self.operand(cond)?;
+        let (taken, other) = match cond {
+            Operand::Imm(value) => {
+                let target = if value != 0 { if_true } else { if_false };
+                self.emit_phi_copies(block, target)?;
+                let target = self.label(target);
+                self.emit(Inst::Jmp { target });
+                return Ok(());
+            }
+            cond => {
+                self.emit(Inst::Test { size: Size::Dword, lhs: cond, rhs: Operand::Imm(1) });
+                (if_true, if_false)
+            }
+        };
+
+        if !self.has_phis(taken) {
+            let target = self.label(taken);
+            self.emit(Inst::Jcc { cond: Cond::Ne, target });
+        } else {
+            // the true edge needs its own copies, so it gets a block of its own
+            let edge = format!("{}.{}", self.label(&block.get_name()), self.mf.blocks.len());
+            self.emit(Inst::Jcc { cond: Cond::E, target: edge.clone() });
+            self.emit_phi_copies(block, taken)?;
+            let target = self.label(taken);
+            self.emit(Inst::Jmp { target });
+            self.start_block(edge, None);
+        }
+        self.emit_phi_copies(block, other)?;
+        let target = self.label(other);
+        self.emit(Inst::Jmp { target });
+        Ok(())
+    }
+
+    fn has_phis(&self, block: &str) -> bool {
+        self.func.get_block(block).is_some_and(|block| {
+            block.borrow().get_instructions().iter().any(|inst| matches!(inst, ValueEntity::Instruction(inst) if matches!(inst.instruction_type(), InstructionType::Phi(_))))
+        })
+    }
+
+    /// Writes the incoming values of the phis in `target` for the edge coming from `block`.
+    fn emit_phi_copies(&mut self, block: &BasicBlock, target: &str) -> Result<(), Error> {
+        let Some(target) = self.func.get_block(target).cloned() else {
+            return Err(unsupported(format!("branch to unknown block {}", target)));
+        };
+        let target = target.borrow();
+        for inst in target.get_instructions() {
+            let ValueEntity::Instruction(inst) = inst else {
+                continue;
+            };
+            let InstructionType::Phi(incoming) = inst.instruction_type() else {
+                continue;
+            };
+            let Some((value, _)) = incoming.iter().find(|(_, from)| from.get_name() == block.get_name()) else {
+                continue;
+            };
+            let (class, size) = self.register_type(&inst.get_type())?;
+            let temp = self.phi_temps[&inst.get_name()];
+            if let Some(high_temp) = self.phi_high_temps.get(&inst.get_name()).copied() {
+                let (low, high) = self.pair(value)?;
+                self.copy(class, size, temp.into(), low);
+                self.copy(class, size, high_temp.into(), high);
+            } else {
+                let src = self.operand(value)?;
+                self.copy(class, size, temp.into(), src);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+use crate::emit::asm::regalloc::RegisterFile;
+use crate::emit::asm::x86_64::inst::{Inst, Mem, PReg, RegClass, Size};
+
+/// Integer registers handed out by the allocator, caller-saved ones first so
+/// that callee-saved registers are only used by values live across calls.
+/// `esi` and `edi` are left free as spill scratch registers, and `ebp`
+/// holds the frame pointer.
+const INT_ALLOCATABLE: [PReg; 4] = [PReg::Rax, PReg::Rcx, PReg::Rdx, PReg::Rbx];
+/// `xmm6` and `xmm7` are left free as spill scratch registers.
+const FLOAT_ALLOCATABLE: [PReg; 6] = [PReg::Xmm0, PReg::Xmm1, PReg::Xmm2, PReg::Xmm3, PReg::Xmm4, PReg::Xmm5];
+/// Registers kept free to reload spilled values around a single instruction.
+/// Neither is ever used implicitly by an instruction, and spilled integers
+/// never need byte registers, which these lack.
+const INT_SCRATCH: [PReg; 2] = [PReg::Rsi, PReg::Rdi];
+const FLOAT_SCRATCH: [PReg; 2] = [PReg::Xmm6, PReg::Xmm7];
+/// Registers the caller reads after `ret`. Floating point results are
+/// returned on the x87 stack instead.
+const RETURN_REGS: [PReg; 2] = [PReg::Rax, PReg::Rdx];
+
+/// The registers of the i386 System V ABI.
+pub struct Registers;
+
+impl RegisterFile for Registers {
+    type Inst = Inst;
+
+    fn allocatable(class: RegClass) -> &'static [PReg] {
+        match class {
+            RegClass::Int => &INT_ALLOCATABLE,
+            RegClass::Float => &FLOAT_ALLOCATABLE,
+        }
+    }
+
+    fn scratch(class: RegClass) -> &'static [PReg] {
+        match class {
+            RegClass::Int => &INT_SCRATCH,
+            RegClass::Float => &FLOAT_SCRATCH,
+        }
+    }
+
+    fn return_regs() -> &'static [PReg] {
+        &RETURN_REGS
+    }
+
+    fn spill_slot(class: RegClass) -> (u64, u64) {
+        match class {
+            RegClass::Int => (4, 4),
+            RegClass::Float => (8, 8),
+        }
+    }
+
+    fn load(class: RegClass, dst: PReg, slot: u32) -> Inst {
+        match class {
+            RegClass::Int => Inst::Mov { size: Size::Dword, dst: dst.into(), src: Mem::slot(slot, 0).into() },
+            RegClass::Float => Inst::MovSse { size: Size::Qword, dst: dst.into(), src: Mem::slot(slot, 0).into() },
+        }
+    }
+
+    fn store(class: RegClass, src: PReg, slot: u32) -> Inst {
+        match class {
+            RegClass::Int => Inst::Mov { size: Size::Dword, dst: Mem::slot(slot, 0).into(), src: src.into() },
+            RegClass::Float => Inst::MovSse { size: Size::Qword, dst: Mem::slot(slot, 0).into(), src: src.into() },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emit::asm::machine::{MachineInst, StackSlot};
+    use crate::emit::asm::regalloc::allocate;
+    use crate::emit::asm::x86_64::inst::{AluOp, MachineBlock, MachineFunction, Operand, Reg};
+
+    fn mov(dst: impl Into<Operand>, src: impl Into<Operand>) -> Inst {
+        Inst::Mov { size: Size::Dword, dst: dst.into(), src: src.into() }
+    }
+
+    #[test]
+    fn spills_integers_to_four_byte_slots_through_esi_and_edi() {
+        let mut mf = MachineFunction::new("f");
+        mf.blocks.push(MachineBlock::new("f".to_string(), None));
+        let values = (0..6).map(|_| mf.new_vreg(RegClass::Int)).collect::<Vec<_>>();
+        let mut insts = values.iter().enumerate().map(|(i, value)| mov(*value, Operand::Imm(i as i64))).collect::<Vec<_>>();
+        insts.extend(values.iter().map(|value| Inst::Alu { op: AluOp::Add, size: Size::Dword, dst: PReg::Rax.into(), src: (*value).into() }));
+        insts.push(Inst::Ret);
+        mf.blocks[0].insts = insts;
+        allocate::<Registers>(&mut mf);
+
+        // the sums take eax, which leaves three registers for six values
+        assert_eq!(mf.slots, [StackSlot { size: 4, align: 4 }; 3]);
+        for inst in &mut mf.blocks[0].insts {
+            inst.visit_regs(&mut |reg, _| match reg {
+                Reg::Phys(preg) => assert!(INT_ALLOCATABLE.contains(preg) || INT_SCRATCH.contains(preg)),
+                Reg::Virt(vreg) => panic!("%v{} was not allocated", vreg.index),
+            });
+        }
+    }
+
+    #[test]
+    fn returns_results_in_edx_and_eax() {
+        let mut mf = MachineFunction::new("f");
+        mf.blocks.push(MachineBlock::new("f".to_string(), None));
+        let low = mf.new_vreg(RegClass::Int);
+        let high = mf.new_vreg(RegClass::Int);
+        mf.blocks[0].insts = vec![
+            mov(low, Operand::Imm(1)),
+            mov(high, Operand::Imm(2)),
+            mov(PReg::Rax, low),
+            mov(PReg::Rdx, high),
+            Inst::RetPop { bytes: 4 },
+        ];
+        allocate::<Registers>(&mut mf);
+        assert_eq!(mf.blocks[0].insts, [mov(PReg::Rax, Operand::Imm(1)), mov(PReg::Rdx, Operand::Imm(2)), Inst::RetPop { bytes: 4 }]);
+    }
+}
@@ -1,20 +1,37 @@
 use std::io::Write;
 use crate::ir::builder::ctx::IRContext;
 use crate::ir::values::function::Function;
+use crate::ir::values::global::GlobalVariable;
 use crate::ir::linkage::Linkage;
-use crate::ir::values::basic_block::BasicBlock;
-use crate::ir::values::instruction::Instruction;
-use crate::ir::values::value::ValueEntity;
-use crate::ir::values::instruction::InstructionType;
+use crate::targets::layout::DataLayout;
+use crate::emit::asm::FrameOptions;
+use crate::emit::asm::regalloc::allocate;
+use crate::emit::asm::x86_64::encode::{encode_function, Code};
+use crate::emit::asm::x86_64::inst::{Inst, MachineBlock, MachineFunction, Size};
+use crate::emit::asm::{data, unsupported};
+use crate::emit::asm::x86_64::lower::FunctionLowering;
+use crate::emit::asm::x86_64::regalloc::Registers;
+
+pub mod abi;
+pub mod encode;
+pub mod frame;
+pub mod inst;
+pub mod lower;
+pub mod regalloc;
 
 struct X86_64Emitter {
     ctx: IRContext,
+    frame_options: FrameOptions,
+    /// Follow each instruction with a comment holding its machine code.
+    show_encoding: bool,
 }
 
 impl X86_64Emitter {
-    pub fn new(ctx: IRContext) -> Self {
+    pub fn new(ctx: IRContext, frame_options: FrameOptions, show_encoding: bool) -> Self {
         Self {
-            ctx
+            ctx,
+            frame_options,
+            show_encoding,
         }
     }
 
@@ -23,79 +40,468 @@ impl X86_64Emitter {
         writeln!(file, "\t\t.intel_syntax noprefix")?;
 
         let functions = self.ctx.get_module().get_functions().clone();
+        let globals = self.ctx.get_module().get_global_variables().clone();
         for function in &functions {
             self.emit_function(file, &function.borrow(), true)?;
         }
+        for global in &globals {
+            self.emit_global(file, &global.borrow(), true)?;
+        }
+        for global in &globals {
+            self.emit_global(file, &global.borrow(), false)?;
+        }
+        if !globals.is_empty() {
+            writeln!(file, "\t\t.text")?;
+        }
         for function in functions {
             self.emit_function(file, &function.borrow(), false)?;
         }
 
+        writeln!(file, "\t\t.section .note.GNU-stack,\"\",@progbits")?;
         Ok(())
     }
 
+    pub fn emit_global(&mut self, file: &mut impl Write, global: &GlobalVariable, decl: bool) -> Result<(), std::io::Error> {
+        if decl {
+            match global.get_linkage() {
+                Linkage::ExternalLinkage => writeln!(file, "\t\t.extern {}", global.get_name())?,
+                Linkage::InternalLinkage => writeln!(file, "\t\t.globl {}", global.get_name())?,
+                // private globals stay local to the object, common ones are declared by `.comm`
+                Linkage::PrivateLinkage | Linkage::CommonLinkage => {}
+                Linkage::ExternalWeakLinkage | Linkage::LinkonceLinkage | Linkage::WeakLinkage => writeln!(file, "\t\t.weak {}", global.get_name())?,
+
+                Linkage::AppendingLinkage => return Err(unsupported(format!("appending linkage on global {}", global.get_name()))),
+            }
+            return Ok(());
+        }
+
+        data::emit_global(file, self.ctx.get_module().data_layout(), global)
+    }
+
     pub fn emit_function(&mut self, file: &mut impl Write, func: &Function, decl: bool) -> Result<(), std::io::Error> {
         if decl {
             // write the function prefix for linkage
             match func.get_linkage() {
                 Linkage::ExternalLinkage => writeln!(file, "\t\t.extern {}", func.get_name())?,
                 Linkage::InternalLinkage => writeln!(file, "\t\t.globl {}", func.get_name())?,
-                Linkage::PrivateLinkage => writeln!(file, "")?,
-                Linkage::ExternalWeakLinkage => writeln!(file, "\t\t.extern {}", func.get_name())?,
+                Linkage::PrivateLinkage => writeln!(file)?,
+                Linkage::ExternalWeakLinkage => writeln!(file, "\t\t.weak {}", func.get_name())?,
                 Linkage::CommonLinkage => writeln!(file, "\t\t.extern {}", func.get_name())?,
+                Linkage::LinkonceLinkage | Linkage::WeakLinkage => writeln!(file, "\t\t.weak {}", func.get_name())?,
 
-                Linkage::AppendingLinkage => todo!(),
-                Linkage::LinkonceLinkage => todo!(),
-                Linkage::WeakLinkage => todo!(),
+                Linkage::AppendingLinkage => return Err(unsupported(format!("appending linkage on function {}", func.get_name()))),
             }
             return Ok(());
         }
 
-        if *func.get_linkage() == Linkage::ExternalLinkage {
+        if func.is_external() || func.get_blocks().is_empty() {
             return Ok(());
         }
 
+        let mf = compile_function(func, self.ctx.get_module().data_layout(), self.frame_options)?;
+
+        let code = if self.show_encoding { Some(encode_function(&mf)?) } else { None };
+
         // write the function name
+        writeln!(file, "\t\t.type {}, @function", func.get_name())?;
         writeln!(file, "{}:", func.get_name())?;
-        let blocks = func.get_blocks().clone();
-        let mut i = 0;
-        for block in blocks {
-            self.emit_basic_block(file, block.borrow().clone(), func, i == 0)?;
-            i += 1;
+        let mut index = 0;
+        for block in &mf.blocks {
+            self.emit_basic_block(file, block, code.as_ref(), index)?;
+            index += block.insts.len();
         }
 
-        writeln!(file, "")?;
+        writeln!(file)?;
         Ok(())
     }
 
-    pub fn emit_basic_block(&mut self, file: &mut impl Write, bb: BasicBlock, func: &Function, is_entry: bool) -> Result<(), std::io::Error> {
-        if !is_entry {
-            write!(file, ".{}.L{}:", func.get_name(), bb.get_name())?;
+    /// Writes a block, whose first instruction has the given index in `code` if that is present.
+    pub fn emit_basic_block(&mut self, file: &mut impl Write, bb: &MachineBlock, code: Option<&Code>, index: usize) -> Result<(), std::io::Error> {
+        match &bb.comment {
+            Some(comment) => writeln!(file, "{}:\t# {}", bb.label, comment)?,
+            None => writeln!(file, "{}:", bb.label)?,
         }
 
-        writeln!(file, "# %{}", bb.get_name())?;
-
-        let insts = bb.get_instructions().clone();
-        for inst in insts {
-            self.emit_instruction(file, inst)?;
+        for (i, inst) in bb.insts.iter().enumerate() {
+            self.emit_instruction(file, inst, code.map(|code| encoding(code, index + i)))?;
         }
         Ok(())
     }
 
-    pub fn emit_instruction(&mut self, file: &mut impl Write, x: ValueEntity) -> Result<(), std::io::Error> {
-        //match x {
-        //    ValueEntity::Instruction(mut ref inst) => {
-        //        match inst.get_type() {
-        //            InstructionType::Add(x, y) => {
-        //                writeln!(file, "  add rax, rax")?;
-        //            }
-        //    }
-        //    _ => panic!("expected instruction")
-        //}
-        Ok(())
+    pub fn emit_instruction(&mut self, file: &mut impl Write, x: &Inst, encoding: Option<String>) -> Result<(), std::io::Error> {
+        match encoding {
+            Some(encoding) => writeln!(file, "\t\t{}\t# encoding: [{}]", x, encoding),
+            None => writeln!(file, "\t\t{}", x),
+        }
     }
 }
 
-pub fn emit_module(ctx: IRContext, file: &mut impl Write) -> Result<(), std::io::Error> {
-    let mut emitter = X86_64Emitter::new(ctx);
+/// Formats the machine code of an instruction, with `A` for the bytes left
+/// to the linker.
+fn encoding(code: &Code, index: usize) -> String {
+    let start = code.offsets[index];
+    let bytes = code.inst_bytes(index);
+    bytes.iter().enumerate().map(|(i, byte)| {
+        let offset = start + i as u64;
+        if code.relocs.iter().any(|reloc| (reloc.offset..reloc.offset + 4).contains(&offset)) {
+            "A".to_string()
+        } else {
+            format!("0x{:02x}", byte)
+        }
+    }).collect::<Vec<_>>().join(",")
+}
+
+/// Lowers a function with a body to machine code over physical registers,
+/// with its frame laid out.
+pub fn compile_function(func: &Function, layout: &DataLayout, frame_options: FrameOptions) -> Result<MachineFunction, std::io::Error> {
+    let mut mf = FunctionLowering::new(func, layout).lower()?;
+    allocate::<Registers>(&mut mf);
+    frame::lay_out(&mut mf, frame_options, Size::Qword);
+    Ok(mf)
+}
+
+pub fn emit_module(ctx: IRContext, frame_options: FrameOptions, show_encoding: bool, file: &mut impl Write) -> Result<(), std::io::Error> {
+    let mut emitter = X86_64Emitter::new(ctx, frame_options, show_encoding);
     emitter.emit_module(file)
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::values::value::Type;
+    use crate::ir::values::instruction::FloatPredicate;
+    use crate::error::Error;
+    use crate::ir::builder::{Builder, IRContext};
+    use crate::ir::linkage::Linkage;
+    use crate::ir::module::Module;
+    use crate::targets::{DataLayout, TargetTriple};
+
+    fn builder() -> Builder {
+        let triple = TargetTriple::new("x86_64-unknown-linux-gnu").unwrap();
+        let module = Module::new("test", DataLayout::from_triple(&triple), triple);
+        Builder::new(IRContext::new(module))
+    }
+
+    /// Returns the instructions and labels of the emitted assembly, one per entry.
+    fn emit(builder: &Builder) -> Vec<String> {
+        let mut out = Vec::new();
+        builder.emit_assembly(&mut out).unwrap();
+        String::from_utf8(out).unwrap().lines().map(|line| line.trim().to_string()).collect()
+    }
+
+    fn has(lines: &[String], prefix: &str) -> bool {
+        lines.iter().any(|line| line.starts_with(prefix))
+    }
+
+    #[test]
+    fn lowers_arithmetic() -> Result<(), Error> {
+        let mut builder = builder();
+        let main = builder.create_function("main", vec![], builder.get_i32_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main)?;
+        builder.set_insertion_point(entry);
+        let sum = builder.add(builder.get_i32(1), builder.get_i32(2), None)?;
+        let product = builder.mul(sum.into(), builder.get_i32(3), None)?;
+        let quotient = builder.sdiv(product.into(), builder.get_i32(4), None)?;
+        builder.ret(quotient.into())?;
+
+        let lines = emit(&builder);
+        assert!(lines.contains(&".globl main".to_string()));
+        assert!(lines.contains(&"main:".to_string()));
+        assert!(has(&lines, "add "));
+        assert!(has(&lines, "imul "));
+        assert!(has(&lines, "cdq"));
+        assert!(has(&lines, "idiv "));
+        assert_eq!(lines.iter().rev().find(|line| line.starts_with("ret")).map(String::as_str), Some("ret"));
+        Ok(())
+    }
+
+    #[test]
+    fn lowers_conditional_branches() -> Result<(), Error> {
+        let mut builder = builder();
+        let main = builder.create_function("main", vec![], builder.get_i32_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main.clone())?;
+        let yes = builder.create_block("yes", main.clone())?;
+        let no = builder.create_block("no", main)?;
+        builder.set_insertion_point(entry);
+        let equal = builder.eq(builder.get_i32(1), builder.get_i32(2), None)?;
+        builder.branch_if(equal.into(), yes.clone(), no.clone())?;
+        builder.set_insertion_point(yes);
+        builder.ret(builder.get_i32(1))?;
+        builder.set_insertion_point(no);
+        builder.ret(builder.get_i32(0))?;
+
+        let lines = emit(&builder);
+        assert!(has(&lines, "sete "));
+        assert!(has(&lines, ".Lmain.yes:"));
+        assert!(has(&lines, ".Lmain.no:"));
+        assert!(lines.iter().any(|line| line.starts_with('j') && line.ends_with(".Lmain.no")));
+        assert_eq!(lines.iter().filter(|line| *line == "ret").count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn names_values_per_function() -> Result<(), Error> {
+        let mut builder = builder();
+        let main = builder.create_function("main", vec![], builder.get_i32_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main.clone())?;
+        let exit = builder.create_block("exit", main)?;
+        builder.set_insertion_point(entry);
+        let first = builder.add(builder.get_i32(1), builder.get_i32(2), None)?;
+        builder.branch(exit.borrow().clone().into())?;
+        builder.set_insertion_point(exit);
+        let second = builder.add(first.clone().into(), builder.get_i32(3), None)?;
+        builder.ret(second.clone().into())?;
+
+        assert_ne!(first.get_name(), second.get_name());
+        emit(&builder);
+        Ok(())
+    }
+
+    #[test]
+    fn lowers_allocas() -> Result<(), Error> {
+        let mut builder = builder();
+        let main = builder.create_function("main", vec![], builder.get_i32_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main)?;
+        builder.set_insertion_point(entry);
+        let count = builder.alloca(builder.get_i32_type(), None, None, None)?;
+        builder.store(count.clone().into(), builder.get_i32(3))?;
+        let loaded = builder.load(builder.get_i32_type(), count.into(), None)?;
+        let array = builder.alloca(builder.get_i32_type(), Some(loaded.into()), Some(64), None)?;
+        let first = builder.load(builder.get_i32_type(), array.into(), None)?;
+        builder.ret(first.into())?;
+
+        let lines = emit(&builder);
+        // the constant alloca is a frame slot
+        assert!(lines.iter().any(|line| line.starts_with("lea ") && line.ends_with("[rbp - 16]")));
+        // the dynamic one moves rsp, rounded to 16 bytes and then aligned by hand
+        assert!(lines.iter().any(|line| line.starts_with("and ") && line.ends_with(", -16")));
+        assert!(lines.iter().any(|line| line.starts_with("sub rsp, r")));
+        assert!(lines.iter().any(|line| line.starts_with("and ") && line.ends_with(", -64")));
+        assert!(lines.contains(&"mov rsp, rbp".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn lowers_globals() -> Result<(), Error> {
+        let mut builder = builder();
+        let counter = builder.create_global("counter", builder.get_i32_type(), Some(builder.const_int(builder.get_i32_type(), 7)?), Linkage::InternalLinkage, false)?;
+        let zeroed = builder.create_global("zeroed", builder.get_i64_type(), Some(builder.const_zero(builder.get_i64_type())?), Linkage::InternalLinkage, false)?;
+        let errno = builder.create_global("errno", builder.get_i32_type(), None, Linkage::ExternalLinkage, false)?;
+        let tls = builder.create_global("tls", builder.get_i32_type(), Some(builder.const_int(builder.get_i32_type(), 1)?), Linkage::InternalLinkage, false)?;
+        tls.borrow_mut().set_thread_local(true);
+        builder.create_global_string("message", "hi")?;
+        builder.create_global("shared", builder.get_i64_type(), None, Linkage::CommonLinkage, false)?;
+
+        let main = builder.create_function("main", vec![], builder.get_i32_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main)?;
+        builder.set_insertion_point(entry);
+        let a = builder.load(builder.get_i32_type(), counter.borrow().clone().into(), None)?;
+        builder.store(zeroed.borrow().clone().into(), builder.get_i64(0))?;
+        let b = builder.load(builder.get_i32_type(), errno.borrow().clone().into(), None)?;
+        let c = builder.load(builder.get_i32_type(), tls.borrow().clone().into(), None)?;
+        let sum = builder.add(a.into(), b.into(), None)?;
+        let sum = builder.add(sum.into(), c.into(), None)?;
+        builder.ret(sum.into())?;
+
+        let lines = emit(&builder);
+        let after = |label: &str| &lines[lines.iter().position(|line| line == label).unwrap() + 1];
+        assert_eq!(after("counter:"), ".long 7");
+        assert_eq!(after("zeroed:"), ".zero 8");
+        assert_eq!(after("tls:"), ".long 1");
+        assert_eq!(after("message:"), ".ascii \"hi\\000\"");
+        assert!(lines.contains(&".section .tdata,\"awT\",@progbits".to_string()));
+        assert!(lines.contains(&".comm shared, 8, 8".to_string()));
+        assert!(!has(&lines, "errno:"));
+
+        assert!(lines.contains(&"lea rax, [rip + counter]".to_string()));
+        assert!(lines.iter().any(|line| line.ends_with("[rip + errno@GOTPCREL]")));
+        assert!(lines.iter().any(|line| line.ends_with("qword ptr fs:0")));
+        assert!(lines.iter().any(|line| line.ends_with("[rip + tls@GOTTPOFF]")));
+        Ok(())
+    }
+
+    #[test]
+    fn lowers_parameters() -> Result<(), Error> {
+        let mut builder = builder();
+        let params = (0..8).map(|_| (builder.get_i64_type(), None)).chain([(builder.get_f64_type(), Some("scale"))]).collect();
+        let sum = builder.create_function_with_param_names("sum", params, builder.get_i64_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", sum.clone())?;
+        builder.set_insertion_point(entry);
+        let total = builder.add(builder.get_param(&sum, 0)?, builder.get_param(&sum, 7)?, None)?;
+        builder.ret(total.into())?;
+
+        assert_eq!(builder.get_param(&sum, 1)?.get_name(), "%1");
+        assert_eq!(builder.get_param(&sum, 8)?.get_name(), "%scale");
+
+        let lines = emit(&builder);
+        // the first argument arrives in rdi, the eighth is the second one on the stack
+        assert!(has(&lines, "add rdi, "));
+        assert!(lines.iter().any(|line| line.ends_with("qword ptr [rbp + 24]")));
+        Ok(())
+    }
+
+    #[test]
+    fn lowers_conversions() -> Result<(), Error> {
+        let mut builder = builder();
+        let params = vec![(builder.get_i32_type(), Some("a")), (builder.get_i64_type(), Some("b")), (builder.get_f64_type(), Some("x"))];
+        let main = builder.create_function_with_param_names("main", params, builder.get_f64_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main.clone())?;
+        builder.set_insertion_point(entry);
+        let (a, b, x) = (builder.get_param(&main, 0)?, builder.get_param(&main, 1)?, builder.get_param(&main, 2)?);
+        let wide = builder.sext(a.clone(), builder.get_i64_type(), None)?;
+        let float = builder.si_to_fp(wide.into(), builder.get_f64_type(), None)?;
+        builder.fp_trunc(float.into(), builder.get_f32_type(), None)?;
+        let byte = builder.trunc(b, builder.get_i8_type(), None)?;
+        builder.zext(byte.into(), builder.get_i32_type(), None)?;
+        let single = builder.ui_to_fp(a, builder.get_f32_type(), None)?;
+        let double = builder.fp_ext(single.into(), builder.get_f64_type(), None)?;
+        builder.fp_to_si(x.clone(), builder.get_i32_type(), None)?;
+        builder.fp_to_ui(x.clone(), builder.get_i64_type(), None)?;
+        builder.bitcast(x, builder.get_i64_type(), None)?;
+        builder.ret(double.into())?;
+
+        let lines = emit(&builder);
+        assert!(lines.contains(&"movsxd rax, edi".to_string()));
+        assert!(lines.contains(&"cvtsi2sd xmm1, rax".to_string()));
+        assert!(lines.contains(&"cvtsd2ss xmm1, xmm1".to_string()));
+        assert!(lines.contains(&"movzx eax, sil".to_string()));
+        // unsigned dwords are zero-extended and converted as signed qwords
+        assert!(lines.contains(&"cvtsi2ss xmm1, rax".to_string()));
+        assert!(lines.contains(&"cvtss2sd xmm1, xmm1".to_string()));
+        assert!(lines.contains(&"cvttsd2si eax, xmm0".to_string()));
+        // doubles of 2^63 and more are converted after subtracting 2^63
+        assert!(lines.contains(&"movabs rcx, 4890909195324358656".to_string()));
+        assert!(lines.contains(&"subsd xmm3, xmm2".to_string()));
+        assert!(lines.contains(&"movq rax, xmm0".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn lowers_unsigned_division_shifts_and_comparisons() -> Result<(), Error> {
+        let mut builder = builder();
+        let params = vec![(builder.get_i32_type(), Some("a")), (builder.get_i32_type(), Some("b"))];
+        let main = builder.create_function_with_param_names("main", params, builder.get_i32_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main.clone())?;
+        let yes = builder.create_block("yes", main.clone())?;
+        let no = builder.create_block("no", main.clone())?;
+        builder.set_insertion_point(entry);
+        let (a, b) = (builder.get_param(&main, 0)?, builder.get_param(&main, 1)?);
+        let quotient = builder.udiv(a.clone(), b.clone(), None)?;
+        let rest = builder.urem(quotient.clone().into(), b.clone(), None)?;
+        let shifted = builder.lshr(rest.into(), b, None)?;
+        let less = builder.ult(shifted.clone().into(), a, None)?;
+        builder.branch_if(less.into(), yes.clone(), no.clone())?;
+        builder.set_insertion_point(yes);
+        let flipped = builder.not(shifted.into(), None)?;
+        builder.ret(flipped.into())?;
+        builder.set_insertion_point(no);
+        builder.ret(quotient.into())?;
+
+        let lines = emit(&builder);
+        // unsigned division clears edx instead of sign-extending eax into it
+        assert_eq!(lines.iter().filter(|line| *line == "mov edx, 0").count(), 2);
+        assert_eq!(lines.iter().filter(|line| *line == "div esi").count(), 2);
+        assert!(!has(&lines, "cdq"));
+        assert!(lines.contains(&"shr edx, cl".to_string()));
+        assert!(lines.contains(&"setb al".to_string()));
+        assert!(lines.contains(&"not edx".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn lowers_float_arithmetic_and_comparisons() -> Result<(), Error> {
+        let mut builder = builder();
+        let params = vec![(builder.get_f64_type(), Some("a")), (builder.get_f64_type(), Some("b"))];
+        let main = builder.create_function_with_param_names("main", params, builder.get_f64_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main.clone())?;
+        let yes = builder.create_block("yes", main.clone())?;
+        let no = builder.create_block("no", main.clone())?;
+        builder.set_insertion_point(entry);
+        let (a, b) = (builder.get_param(&main, 0)?, builder.get_param(&main, 1)?);
+        let sum = builder.fadd(a.clone(), builder.get_f64(1.5), None)?;
+        let quotient = builder.fdiv(sum.into(), b.clone(), None)?;
+        let rest = builder.frem(quotient.clone().into(), b, None)?;
+        let negated = builder.fneg(rest.into(), None)?;
+        let unordered = builder.fcmp(FloatPredicate::Ult, negated.clone().into(), a, None)?;
+        builder.branch_if(unordered.into(), yes.clone(), no.clone())?;
+        builder.set_insertion_point(yes);
+        builder.ret(negated.into())?;
+        builder.set_insertion_point(no);
+        builder.ret(quotient.into())?;
+
+        let lines = emit(&builder);
+        // 1.5 is materialized through a general purpose register
+        assert!(lines.contains(&"movabs rax, 4609434218613702656".to_string()));
+        assert!(lines.contains(&"addsd xmm3, xmm2".to_string()));
+        assert!(lines.contains(&"divsd xmm14, xmm1".to_string()));
+        assert!(lines.contains(&"call fmod".to_string()));
+        // fneg flips the sign bit, ult is "below" after an unordered compare
+        assert!(lines.contains(&"xorps xmm0, xmm1".to_string()));
+        assert!(lines.contains(&"ucomisd xmm0, xmm14".to_string()));
+        assert!(lines.contains(&"setb al".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn lowers_constant_addresses_and_aggregates() -> Result<(), Error> {
+        let mut builder = builder();
+        let array = builder.get_array_type(builder.get_i32_type(), 2);
+        let elements = vec![builder.const_int(builder.get_i32_type(), 7)?, builder.const_int(builder.get_i32_type(), -1)?];
+        let table = builder.create_global("table", array.clone(), Some(builder.const_array(builder.get_i32_type(), elements)?), Linkage::InternalLinkage, false)?;
+        let address = builder.const_address(&table.borrow().clone().into())?;
+        let address = builder.const_cast("ptrtoint", address, builder.get_i64_type())?;
+        let end = builder.const_binary("add", address.clone(), builder.const_int(builder.get_i64_type(), 8)?)?;
+        builder.create_global("end", builder.get_i64_type(), Some(end), Linkage::InternalLinkage, false)?;
+
+        let params = vec![(builder.get_i64_type(), Some("a"))];
+        let main = builder.create_function_with_param_names("main", params, builder.get_i64_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main.clone())?;
+        builder.set_insertion_point(entry);
+        let second = builder.const_binary("add", address, builder.const_int(builder.get_i64_type(), 4)?)?;
+        let sum = builder.add(builder.get_param(&main, 0)?, second.into(), None)?;
+        let wide = builder.add(sum.into(), builder.const_int(builder.get_i64_type(), 0x1234_5678_9abc)?.into(), None)?;
+        builder.ret(wide.into())?;
+
+        let lines = emit(&builder);
+        let after = |label: &str| lines.iter().skip_while(|line| *line != label).skip(1).take(2).map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(after("table:"), [".long 7", ".long 4294967295"]);
+        assert_eq!(after("end:")[0], ".quad table+8");
+        let entry = lines.iter().position(|line| line.starts_with(".Lmain.entry:")).unwrap();
+        assert_eq!(lines[entry + 3..][..5], [
+            // the address may be in another module, so it comes from the GOT
+            "mov rax, qword ptr [rip + table@GOTPCREL]",
+            "add rax, 4",
+            "add rdi, rax",
+            "movabs rax, 20015998343868",
+            "add rdi, rax",
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn lowers_element_addresses() -> Result<(), Error> {
+        let mut builder = builder();
+        let array = builder.get_array_type(builder.get_i32_type(), 4);
+        let pair = builder.get_pointer_type(Type::Struct(vec![builder.get_i8_type(), array]));
+        let params = vec![(pair, Some("p")), (builder.get_i32_type(), Some("i"))];
+        let main = builder.create_function_with_param_names("main", params, builder.get_i32_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main.clone())?;
+        builder.set_insertion_point(entry);
+        let field = builder.struct_gep(builder.get_param(&main, 0)?, 1, None)?;
+        let element = builder.array_gep(field.into(), builder.get_param(&main, 1)?, None)?;
+        let value = builder.load(builder.get_i32_type(), element.clone().into(), None)?;
+        builder.store(element.into(), builder.get_i32(7))?;
+        builder.ret(value.into())?;
+
+        let lines = emit(&builder);
+        let entry = lines.iter().position(|line| line.starts_with(".Lmain.entry:")).unwrap();
+        // the field offset is computed once; the scaled index is folded into each access
+        assert_eq!(lines[entry + 3..][..5], [
+            "lea rax, [rdi + 4]",
+            "movsxd rcx, esi",
+            "mov ecx, dword ptr [rax + rcx*4]",
+            "movsxd rdx, esi",
+            "mov dword ptr [rax + rdx*4], 7",
+        ]);
+        Ok(())
+    }
+}
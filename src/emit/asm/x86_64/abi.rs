@@ -0,0 +1,240 @@
+use crate::emit::asm::x86_64::inst::PReg;
+use crate::emit::asm::unsupported;
+use crate::ir::values::value::Type;
+use crate::targets::layout::DataLayout;
+use std::io::Error;
+
+/// Registers used for INTEGER class arguments, in order.
+pub const INT_ARGUMENT_REGS: [PReg; 6] = [PReg::Rdi, PReg::Rsi, PReg::Rdx, PReg::Rcx, PReg::R8, PReg::R9];
+/// Registers used for SSE class arguments, in order.
+pub const FLOAT_ARGUMENT_REGS: [PReg; 8] = [PReg::Xmm0, PReg::Xmm1, PReg::Xmm2, PReg::Xmm3, PReg::Xmm4, PReg::Xmm5, PReg::Xmm6, PReg::Xmm7];
+const INT_RETURN_REGS: [PReg; 2] = [PReg::Rax, PReg::Rdx];
+const FLOAT_RETURN_REGS: [PReg; 2] = [PReg::Xmm0, PReg::Xmm1];
+
+/// The class of one eightbyte of a value, as defined by the System V AMD64 ABI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgClass {
+    NoClass,
+    Integer,
+    Sse,
+    Memory,
+}
+
+/// One eightbyte of a value passed in a register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Piece {
+    pub reg: PReg,
+    /// Offset of the eightbyte within the value.
+    pub offset: u64,
+    /// Number of bytes of the value that live in this eightbyte.
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgLocation {
+    /// One register per eightbyte.
+    Regs(Vec<Piece>),
+    /// In the argument area, at `offset` bytes from the stack pointer at the call.
+    Stack { offset: u64, size: u64 },
+    /// Zero-sized values are not passed at all.
+    Ignore,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReturnLocation {
+    Void,
+    Regs(Vec<Piece>),
+    /// The caller passes a buffer in `rdi` and gets its address back in `rax`.
+    Memory,
+}
+
+/// Where the arguments and return value of a call live.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallAbi {
+    pub args: Vec<ArgLocation>,
+    pub ret: ReturnLocation,
+    /// Size of the stack argument area, a multiple of 16.
+    pub stack_size: u64,
+    /// Number of vector registers used, which variadic callees expect in `al`.
+    pub sse_regs: u8,
+}
+
+fn merge(a: ArgClass, b: ArgClass) -> ArgClass {
+    match (a, b) {
+        (x, y) if x == y => x,
+        (ArgClass::NoClass, x) | (x, ArgClass::NoClass) => x,
+        (ArgClass::Memory, _) | (_, ArgClass::Memory) => ArgClass::Memory,
+        (ArgClass::Integer, _) | (_, ArgClass::Integer) => ArgClass::Integer,
+        _ => ArgClass::Sse,
+    }
+}
+
+fn classify_into(layout: &DataLayout, ty: &Type, offset: u64, classes: &mut [ArgClass]) -> Result<(), Error> {
+    let class = match ty {
+        Type::Integer(_) | Type::Pointer(_) | Type::FunctionType(_, _) => ArgClass::Integer,
+        Type::Float(32) | Type::Float(64) => ArgClass::Sse,
+        Type::Array(len, element) => {
+            let stride = layout.stride_of(element);
+            for i in 0..*len as u64 {
+                classify_into(layout, element, offset + i * stride, classes)?;
+            }
+            return Ok(());
+        }
+        Type::Struct(fields) => {
+            for (field, field_offset) in fields.iter().zip(layout.struct_field_offsets(fields)) {
+                classify_into(layout, field, offset + field_offset, classes)?;
+            }
+            return Ok(());
+        }
+        Type::Float(_) | Type::Void | Type::Branch => return Err(unsupported(format!("passing values of type {}", ty))),
+    };
+    let size = layout.size_of(ty);
+    if !offset.is_multiple_of(layout.align_of(ty)) {
+        classes.iter_mut().for_each(|c| *c = ArgClass::Memory);
+        return Ok(());
+    }
+    for eightbyte in offset / 8..(offset + size).div_ceil(8) {
+        classes[eightbyte as usize] = merge(classes[eightbyte as usize], class);
+    }
+    Ok(())
+}
+
+/// Classifies each eightbyte of a value of type `ty`. A single `Memory`
+/// entry means the value is passed in memory.
+pub fn classify(layout: &DataLayout, ty: &Type) -> Result<Vec<ArgClass>, Error> {
+    let size = layout.size_of(ty);
+    if size > 16 && (ty.is_struct() || ty.is_array()) {
+        return Ok(vec![ArgClass::Memory]);
+    }
+    if size > 16 {
+        return Err(unsupported(format!("passing values of type {}", ty)));
+    }
+    let mut classes = vec![ArgClass::NoClass; size.div_ceil(8) as usize];
+    classify_into(layout, ty, 0, &mut classes)?;
+    if classes.contains(&ArgClass::Memory) {
+        return Ok(vec![ArgClass::Memory]);
+    }
+    Ok(classes)
+}
+
+/// Assigns registers to the eightbytes of a value, taking them from the
+/// given pools only if all of them fit.
+fn assign<'a>(layout: &DataLayout, ty: &Type, classes: &[ArgClass], ints: &mut &'a [PReg], floats: &mut &'a [PReg]) -> Option<Vec<Piece>> {
+    let needed_ints = classes.iter().filter(|c| **c == ArgClass::Integer).count();
+    let needed_floats = classes.iter().filter(|c| **c == ArgClass::Sse).count();
+    if needed_ints > ints.len() || needed_floats > floats.len() {
+        return None;
+    }
+    let size = layout.size_of(ty);
+    let mut pieces = Vec::new();
+    for (i, class) in classes.iter().enumerate() {
+        let pool = match class {
+            ArgClass::Integer => &mut *ints,
+            ArgClass::Sse => &mut *floats,
+            _ => continue,
+        };
+        let offset = i as u64 * 8;
+        pieces.push(Piece { reg: pool[0], offset, size: (size - offset).min(8) });
+        *pool = &pool[1..];
+    }
+    Some(pieces)
+}
+
+/// Computes where the arguments and the return value of a call with the
+/// given argument and return types are passed.
+pub fn classify_call(layout: &DataLayout, args: &[Type], ret: &Type) -> Result<CallAbi, Error> {
+    let mut ints: &[PReg] = &INT_ARGUMENT_REGS;
+    let mut floats: &[PReg] = &FLOAT_ARGUMENT_REGS;
+
+    let ret = if ret.is_void() {
+        ReturnLocation::Void
+    } else {
+        let classes = classify(layout, ret)?;
+        if classes == [ArgClass::Memory] {
+            // the buffer address takes the first integer register
+            ints = &ints[1..];
+            ReturnLocation::Memory
+        } else {
+            let mut ret_ints: &[PReg] = &INT_RETURN_REGS;
+            let mut ret_floats: &[PReg] = &FLOAT_RETURN_REGS;
+            ReturnLocation::Regs(assign(layout, ret, &classes, &mut ret_ints, &mut ret_floats).unwrap())
+        }
+    };
+
+    let mut locations = Vec::new();
+    let mut stack_size: u64 = 0;
+    for arg in args {
+        let size = layout.size_of(arg);
+        if size == 0 {
+            locations.push(ArgLocation::Ignore);
+            continue;
+        }
+        let classes = classify(layout, arg)?;
+        if classes != [ArgClass::Memory] {
+            if let Some(pieces) = assign(layout, arg, &classes, &mut ints, &mut floats) {
+                locations.push(ArgLocation::Regs(pieces));
+                continue;
+            }
+        }
+        let offset = stack_size.next_multiple_of(layout.align_of(arg).max(8));
+        stack_size = offset + size.next_multiple_of(8);
+        locations.push(ArgLocation::Stack { offset, size });
+    }
+
+    Ok(CallAbi {
+        args: locations,
+        ret,
+        stack_size: stack_size.next_multiple_of(16),
+        sse_regs: (FLOAT_ARGUMENT_REGS.len() - floats.len()) as u8,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn piece(reg: PReg, offset: u64, size: u64) -> Piece {
+        Piece { reg, offset, size }
+    }
+
+    #[test]
+    fn classifies_eightbytes_of_aggregates() {
+        let layout = DataLayout::new_x86_64();
+        let mixed = Type::Struct(vec![Type::Integer(64), Type::Float(64)]);
+        assert_eq!(classify(&layout, &mixed).unwrap(), [ArgClass::Integer, ArgClass::Sse]);
+        // two floats share an eightbyte, the integer taints the second one
+        let packed = Type::Struct(vec![Type::Float(32), Type::Float(32), Type::Integer(32)]);
+        assert_eq!(classify(&layout, &packed).unwrap(), [ArgClass::Sse, ArgClass::Integer]);
+        let large = Type::Array(3, Box::new(Type::Integer(64)));
+        assert_eq!(classify(&layout, &large).unwrap(), [ArgClass::Memory]);
+    }
+
+    #[test]
+    fn passes_arguments_in_registers_then_on_the_stack() {
+        let layout = DataLayout::new_x86_64();
+        let args = vec![Type::Integer(64); 7].into_iter().chain([Type::Float(64)]).collect::<Vec<_>>();
+        let abi = classify_call(&layout, &args, &Type::Integer(32)).unwrap();
+        for (location, reg) in abi.args.iter().zip(INT_ARGUMENT_REGS) {
+            assert_eq!(*location, ArgLocation::Regs(vec![piece(reg, 0, 8)]));
+        }
+        assert_eq!(abi.args[6], ArgLocation::Stack { offset: 0, size: 8 });
+        assert_eq!(abi.args[7], ArgLocation::Regs(vec![piece(PReg::Xmm0, 0, 8)]));
+        assert_eq!(abi.ret, ReturnLocation::Regs(vec![piece(PReg::Rax, 0, 4)]));
+        assert_eq!(abi.stack_size, 16);
+        assert_eq!(abi.sse_regs, 1);
+    }
+
+    #[test]
+    fn returns_large_aggregates_through_memory() {
+        let layout = DataLayout::new_x86_64();
+        let large = Type::Struct(vec![Type::Integer(64); 3]);
+        let abi = classify_call(&layout, &[Type::Integer(32)], &large).unwrap();
+        assert_eq!(abi.ret, ReturnLocation::Memory);
+        // `rdi` holds the address of the buffer
+        assert_eq!(abi.args[0], ArgLocation::Regs(vec![piece(PReg::Rsi, 0, 4)]));
+
+        let pair = Type::Struct(vec![Type::Float(64), Type::Integer(32)]);
+        let abi = classify_call(&layout, &[], &pair).unwrap();
+        assert_eq!(abi.ret, ReturnLocation::Regs(vec![piece(PReg::Xmm0, 0, 8), piece(PReg::Rax, 8, 8)]));
+    }
+}
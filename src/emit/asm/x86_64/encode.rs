@@ -0,0 +1,669 @@
+use crate::emit::asm::x86_64::inst::{AluOp, Base, CallTarget, DoubleShiftOp, Inst, MachineFunction, Mem, Operand, PReg, Reg, RegClass, ShiftOp, Size, SseOp, UnaryOp};
+use crate::emit::asm::unsupported;
+use std::collections::{HashMap, HashSet};
+use std::io::Error;
+
+/// How the linker computes the value of a relocated field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocKind {
+    /// 32-bit offset of the symbol from the field.
+    Pc32,
+    /// Like `Pc32`, but through the PLT when the symbol is in a shared object.
+    Plt32,
+    /// 32-bit offset of the symbol's GOT entry from the field.
+    GotPcRel,
+    /// 32-bit offset of the GOT entry holding the symbol's offset from the thread pointer.
+    GotTpOff,
+    /// 64-bit address of the symbol.
+    Abs64,
+}
+
+/// A field the linker fills in with (a function of) the address of a symbol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reloc {
+    /// Offset of the field from the start of the code.
+    pub offset: u64,
+    pub kind: RelocKind,
+    pub symbol: String,
+    pub addend: i64,
+}
+
+/// The machine code of one function.
+#[derive(Debug, Clone, Default)]
+pub struct Code {
+    pub bytes: Vec<u8>,
+    pub relocs: Vec<Reloc>,
+    /// Offset of each instruction, in block order.
+    pub offsets: Vec<u64>,
+}
+
+impl Code {
+    /// Returns the bytes of the instruction with the given index.
+    pub fn inst_bytes(&self, index: usize) -> &[u8] {
+        let start = self.offsets[index] as usize;
+        let end = self.offsets.get(index + 1).map_or(self.bytes.len(), |end| *end as usize);
+        &self.bytes[start..end]
+    }
+}
+
+/// The legacy and REX prefixes of an instruction.
+#[derive(Debug, Clone, Copy, Default)]
+struct Prefix {
+    legacy: Option<u8>,
+    rex_w: bool,
+    /// Emit a REX prefix even without any bit set, to reach `spl`, `bpl`, `sil` and `dil`.
+    force_rex: bool,
+}
+
+impl Prefix {
+    /// Returns the prefixes selecting the operand size of an integer instruction.
+    fn sized(size: Size, force_rex: bool) -> Self {
+        Self {
+            legacy: (size == Size::Word).then_some(0x66),
+            rex_w: size == Size::Qword,
+            force_rex,
+        }
+    }
+
+    /// Returns the mandatory prefix of a scalar SSE instruction.
+    fn sse(legacy: u8) -> Self {
+        Self { legacy: Some(legacy), ..Self::default() }
+    }
+}
+
+/// The ModRM byte and what follows it, up to the immediate.
+struct Address {
+    bytes: Vec<u8>,
+    rex_x: bool,
+    rex_b: bool,
+    /// A rip-relative reference: the symbol, its displacement and where its field starts in `bytes`.
+    symbol: Option<(RelocKind, String, i32, usize)>,
+}
+
+/// Returns the hardware number of an allocated register.
+fn phys(reg: &Reg) -> Result<u8, Error> {
+    match reg {
+        Reg::Phys(preg) => Ok(preg.encoding()),
+        Reg::Virt(vreg) => Err(unsupported(format!("virtual register %v{} left after register allocation", vreg.index))),
+    }
+}
+
+/// Returns whether the low byte of `op` is only reachable with a REX prefix.
+fn byte_rex(op: &Operand) -> bool {
+    matches!(op, Operand::Reg(Reg::Phys(preg)) if preg.class() == RegClass::Int && (4..8).contains(&preg.encoding()))
+}
+
+/// Returns an immediate operand of an instruction of the given size, which is
+/// at most 32 bits wide and sign-extended for 64-bit instructions.
+fn imm(size: Size, value: i64) -> Result<(i64, usize), Error> {
+    match size {
+        Size::Byte => Ok((value, 1)),
+        Size::Word => Ok((value, 2)),
+        Size::Dword => Ok((value, 4)),
+        Size::Qword if i32::try_from(value).is_ok() => Ok((value, 4)),
+        Size::Qword => Err(unsupported(format!("immediate {} does not fit a sign-extended 32-bit field", value))),
+    }
+}
+
+/// Returns the opcode of the `test`/`not`/`neg`/`div` group for the given size.
+fn group3(size: Size) -> u8 {
+    if size == Size::Byte { 0xf6 } else { 0xf7 }
+}
+
+fn address(reg: u8, mem: &Mem) -> Result<Address, Error> {
+    let reg_bits = (reg & 7) << 3;
+    let (kind, symbol) = match &mem.base {
+        Base::Reg(base) => return base_address(reg_bits, phys(base)?, mem),
+        Base::Rip(symbol) => (RelocKind::Pc32, symbol),
+        Base::Got(symbol) => (RelocKind::GotPcRel, symbol),
+        Base::GotTpoff(symbol) => (RelocKind::GotTpOff, symbol),
+        Base::Slot(_) | Base::ArgsEnd | Base::IncomingArgs => return Err(unsupported("stack address left after frame layout".to_string())),
+        Base::Symbol(symbol) | Base::Indntpoff(symbol) => return Err(unsupported(format!("i686 address of {} in 64-bit code", symbol))),
+    };
+    if mem.index.is_some() {
+        return Err(unsupported(format!("rip-relative address of {} with an index register", symbol)));
+    }
+    Ok(Address {
+        bytes: vec![reg_bits | 0b101, 0, 0, 0, 0],
+        rex_x: false,
+        rex_b: false,
+        symbol: Some((kind, symbol.clone(), mem.disp, 1)),
+    })
+}
+
+fn base_address(reg_bits: u8, base: u8, mem: &Mem) -> Result<Address, Error> {
+    // `rbp` and `r13` as a base always take a displacement
+    let (mode, disp) = if mem.disp == 0 && base & 7 != 5 {
+        (0b00, vec![])
+    } else if let Ok(disp) = i8::try_from(mem.disp) {
+        (0b01, vec![disp as u8])
+    } else {
+        (0b10, mem.disp.to_le_bytes().to_vec())
+    };
+
+    let mut address = Address { bytes: Vec::new(), rex_x: false, rex_b: base >= 8, symbol: None };
+    // `rsp` and `r12` as a base need a SIB byte
+    if mem.index.is_some() || base & 7 == 4 {
+        // an index of `rsp` means no index
+        let (index, scale) = match &mem.index {
+            Some((index, scale)) => (phys(index)?, *scale),
+            None => (4, 1),
+        };
+        if mem.index.is_some() && index == 4 {
+            return Err(unsupported("rsp used as an index register".to_string()));
+        }
+        if !matches!(scale, 1 | 2 | 4 | 8) {
+            return Err(unsupported(format!("index scale {}", scale)));
+        }
+        address.rex_x = index >= 8;
+        address.bytes.push(mode << 6 | reg_bits | 0b100);
+        address.bytes.push((scale.trailing_zeros() as u8) << 6 | (index & 7) << 3 | base & 7);
+    } else {
+        address.bytes.push(mode << 6 | reg_bits | base & 7);
+    }
+    address.bytes.extend(disp);
+    Ok(address)
+}
+
+/// A field holding the distance from its end to a label.
+struct Fixup {
+    field: u64,
+    label: String,
+    /// Whether the field is a single byte, as in short jumps.
+    short: bool,
+    /// Number of the jump in the function.
+    jump: usize,
+}
+
+struct Encoder {
+    code: Code,
+    /// Offset of each block label.
+    labels: HashMap<String, u64>,
+    fixups: Vec<Fixup>,
+    /// Jumps found too far from their target for the short form.
+    near_jumps: HashSet<usize>,
+    jumps: usize,
+}
+
+impl Encoder {
+    fn pos(&self) -> u64 {
+        self.code.bytes.len() as u64
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.code.bytes.extend_from_slice(bytes);
+    }
+
+    fn imm(&mut self, (value, len): (i64, usize)) {
+        self.bytes(&value.to_le_bytes()[..len]);
+    }
+
+    /// Writes the legacy prefix and, if needed, the REX prefix.
+    fn prefix(&mut self, prefix: Prefix, rex_r: bool, rex_x: bool, rex_b: bool) {
+        if let Some(legacy) = prefix.legacy {
+            self.bytes(&[legacy]);
+        }
+        let rex = (prefix.rex_w as u8) << 3 | (rex_r as u8) << 2 | (rex_x as u8) << 1 | rex_b as u8;
+        if rex != 0 || prefix.force_rex {
+            self.bytes(&[0x40 | rex]);
+        }
+    }
+
+    /// Writes an instruction with a ModRM byte, which holds `reg`, a register
+    /// number or opcode extension, and `rm`, a register or memory operand.
+    fn modrm(&mut self, prefix: Prefix, opcode: &[u8], reg: u8, rm: &Operand, imm: Option<(i64, usize)>) -> Result<(), Error> {
+        let address = match rm {
+            Operand::Reg(rm) => {
+                let rm = phys(rm)?;
+                Address { bytes: vec![0b11 << 6 | (reg & 7) << 3 | rm & 7], rex_x: false, rex_b: rm >= 8, symbol: None }
+            }
+            Operand::Mem(mem) => address(reg, mem)?,
+            Operand::Imm(value) => return Err(unsupported(format!("immediate {} where a register or memory operand is needed", value))),
+        };
+
+        self.prefix(prefix, reg >= 8, address.rex_x, address.rex_b);
+        self.bytes(opcode);
+        let start = self.pos();
+        self.bytes(&address.bytes);
+        if let Some(imm) = imm {
+            self.imm(imm);
+        }
+        if let Some((kind, symbol, disp, field)) = address.symbol {
+            // rip is the address of the next instruction, not of the field
+            let offset = start + field as u64;
+            let addend = disp as i64 - (self.pos() - offset) as i64;
+            self.code.relocs.push(Reloc { offset, kind, symbol, addend });
+        }
+        Ok(())
+    }
+
+    /// Writes an instruction that encodes its register in the low bits of the opcode.
+    fn opcode_reg(&mut self, prefix: Prefix, opcode: u8, reg: u8) {
+        self.prefix(prefix, false, false, reg >= 8);
+        self.bytes(&[opcode + (reg & 7)]);
+    }
+
+    /// Writes a jump to `label`, with the `short` opcode and an 8-bit
+    /// distance unless it is known not to reach, else with the `near` opcode
+    /// and a 32-bit distance.
+    fn jump(&mut self, short: &[u8], near: &[u8], label: &str) {
+        let jump = self.jumps;
+        self.jumps += 1;
+        let is_short = !self.near_jumps.contains(&jump);
+        self.bytes(if is_short { short } else { near });
+        self.fixups.push(Fixup { field: self.pos(), label: label.to_string(), short: is_short, jump });
+        self.bytes(if is_short { &[0; 1] } else { &[0; 4] });
+    }
+
+    /// Writes an instruction with an implicit accumulator operand and an immediate.
+    fn acc_imm(&mut self, prefix: Prefix, opcode: u8, imm: (i64, usize)) {
+        self.prefix(prefix, false, false, false);
+        self.bytes(&[opcode]);
+        self.imm(imm);
+    }
+
+    fn inst(&mut self, inst: &Inst) -> Result<(), Error> {
+        match inst {
+            Inst::Mov { size, dst, src } => self.mov(*size, dst, src),
+            Inst::Movzx { dst_size, src_size, dst, src } => {
+                if *src_size == Size::Dword {
+                    // writing a 32-bit register zeroes the upper half
+                    return self.mov(Size::Dword, &Operand::Reg(*dst), src);
+                }
+                let opcode = if *src_size == Size::Byte { 0xb6 } else { 0xb7 };
+                self.modrm(Prefix::sized(*dst_size, *src_size == Size::Byte && byte_rex(src)), &[0x0f, opcode], phys(dst)?, src, None)
+            }
+            Inst::Movsx { dst_size, src_size, dst, src } => {
+                let prefix = Prefix::sized(*dst_size, *src_size == Size::Byte && byte_rex(src));
+                match src_size {
+                    Size::Byte => self.modrm(prefix, &[0x0f, 0xbe], phys(dst)?, src, None),
+                    Size::Word => self.modrm(prefix, &[0x0f, 0xbf], phys(dst)?, src, None),
+                    _ => self.modrm(prefix, &[0x63], phys(dst)?, src, None),
+                }
+            }
+            Inst::Lea { dst, addr } => self.modrm(Prefix::sized(Size::Qword, false), &[0x8d], phys(dst)?, &Operand::Mem(addr.clone()), None),
+            Inst::Alu { op, size, dst, src } => {
+                let ext = match op {
+                    AluOp::Add => 0,
+                    AluOp::Or => 1,
+                    AluOp::Adc => 2,
+                    AluOp::Sbb => 3,
+                    AluOp::And => 4,
+                    AluOp::Sub => 5,
+                    AluOp::Xor => 6,
+                };
+                self.alu(ext, *size, dst, src)
+            }
+            Inst::Cmp { size, lhs, rhs } => self.alu(7, *size, lhs, rhs),
+            Inst::Imul { size, dst, src } => {
+                let prefix = Prefix::sized(*size, false);
+                let dst_reg = phys(dst)?;
+                match src {
+                    _ if *size == Size::Byte => Err(unsupported("8-bit imul".to_string())),
+                    Operand::Imm(value) if i8::try_from(*value).is_ok() => self.modrm(prefix, &[0x6b], dst_reg, &Operand::Reg(*dst), Some((*value, 1))),
+                    Operand::Imm(value) => self.modrm(prefix, &[0x69], dst_reg, &Operand::Reg(*dst), Some(imm(*size, *value)?)),
+                    _ => self.modrm(prefix, &[0x0f, 0xaf], dst_reg, src, None),
+                }
+            }
+            Inst::Mul { size, src } => self.modrm(Prefix::sized(*size, *size == Size::Byte && byte_rex(src)), &[group3(*size)], 4, src, None),
+            Inst::Unary { op, size, dst } => {
+                let ext = if *op == UnaryOp::Neg { 3 } else { 2 };
+                self.modrm(Prefix::sized(*size, *size == Size::Byte && byte_rex(dst)), &[group3(*size)], ext, dst, None)
+            }
+            Inst::Shift { op, size, dst, amount } => {
+                let ext = match op {
+                    ShiftOp::Shl => 4,
+                    ShiftOp::Shr => 5,
+                    ShiftOp::Sar => 7,
+                };
+                let prefix = Prefix::sized(*size, *size == Size::Byte && byte_rex(dst));
+                let wide = (*size != Size::Byte) as u8;
+                match amount {
+                    Some(1) => self.modrm(prefix, &[0xd0 | wide], ext, dst, None),
+                    Some(amount) => self.modrm(prefix, &[0xc0 | wide], ext, dst, Some((*amount as i64, 1))),
+                    None => self.modrm(prefix, &[0xd2 | wide], ext, dst, None),
+                }
+            }
+            Inst::DoubleShift { op, size, dst, src, amount } => {
+                let opcode = if *op == DoubleShiftOp::Shld { 0xa4 } else { 0xac };
+                match amount {
+                    Some(amount) => self.modrm(Prefix::sized(*size, false), &[0x0f, opcode], phys(src)?, dst, Some((*amount as i64, 1))),
+                    None => self.modrm(Prefix::sized(*size, false), &[0x0f, opcode | 1], phys(src)?, dst, None),
+                }
+            }
+            Inst::Test { size, lhs, rhs } => {
+                let prefix = Prefix::sized(*size, *size == Size::Byte && (byte_rex(lhs) || byte_rex(rhs)));
+                let wide = (*size != Size::Byte) as u8;
+                match (lhs, rhs) {
+                    (Operand::Reg(Reg::Phys(PReg::Rax)), Operand::Imm(value)) => {
+                        self.acc_imm(prefix, 0xa8 | wide, imm(*size, *value)?);
+                        Ok(())
+                    }
+                    (_, Operand::Imm(value)) => self.modrm(prefix, &[group3(*size)], 0, lhs, Some(imm(*size, *value)?)),
+                    (_, Operand::Reg(reg)) => self.modrm(prefix, &[0x84 | wide], phys(reg)?, lhs, None),
+                    (Operand::Reg(reg), _) => self.modrm(prefix, &[0x84 | wide], phys(reg)?, rhs, None),
+                    _ => Err(unsupported("test of two memory operands".to_string())),
+                }
+            }
+            Inst::SignExtendAcc { size } => {
+                match size {
+                    Size::Byte => self.bytes(&[0x66, 0x98]),
+                    Size::Word => self.bytes(&[0x66, 0x99]),
+                    Size::Dword => self.bytes(&[0x99]),
+                    Size::Qword => self.bytes(&[0x48, 0x99]),
+                }
+                Ok(())
+            }
+            Inst::Div { signed, size, src } => {
+                let ext = if *signed { 7 } else { 6 };
+                self.modrm(Prefix::sized(*size, *size == Size::Byte && byte_rex(src)), &[group3(*size)], ext, src, None)
+            }
+            Inst::Setcc { cond, dst } => {
+                let prefix = Prefix { force_rex: byte_rex(dst), ..Prefix::default() };
+                self.modrm(prefix, &[0x0f, 0x90 | *cond as u8], 0, dst, None)
+            }
+            Inst::Cmov { cond, size, dst, src } => self.modrm(Prefix::sized(*size, false), &[0x0f, 0x40 | *cond as u8], phys(dst)?, src, None),
+            Inst::Jmp { target } => {
+                self.jump(&[0xeb], &[0xe9], target);
+                Ok(())
+            }
+            Inst::Jcc { cond, target } => {
+                self.jump(&[0x70 | *cond as u8], &[0x0f, 0x80 | *cond as u8], target);
+                Ok(())
+            }
+            Inst::Call { target: CallTarget::Symbol(symbol), .. } => {
+                self.bytes(&[0xe8]);
+                self.code.relocs.push(Reloc { offset: self.pos(), kind: RelocKind::Plt32, symbol: symbol.clone(), addend: -4 });
+                self.bytes(&[0; 4]);
+                Ok(())
+            }
+            Inst::Call { target: CallTarget::Indirect(target), .. } => self.modrm(Prefix::default(), &[0xff], 2, target, None),
+            Inst::Ret => {
+                self.bytes(&[0xc3]);
+                Ok(())
+            }
+            Inst::RetPop { bytes } => {
+                self.bytes(&[0xc2]);
+                self.bytes(&bytes.to_le_bytes());
+                Ok(())
+            }
+            Inst::Push { src } => {
+                self.opcode_reg(Prefix::default(), 0x50, phys(src)?);
+                Ok(())
+            }
+            Inst::Pop { dst } => {
+                self.opcode_reg(Prefix::default(), 0x58, phys(dst)?);
+                Ok(())
+            }
+            Inst::Ud2 => {
+                self.bytes(&[0x0f, 0x0b]);
+                Ok(())
+            }
+            Inst::ThreadPointer { dst } => {
+                // mov dst, qword ptr fs:0, addressed through a SIB byte with neither base nor index
+                let dst = phys(dst)?;
+                self.bytes(&[0x64]);
+                self.prefix(Prefix::sized(Size::Qword, false), dst >= 8, false, false);
+                self.bytes(&[0x8b, (dst & 7) << 3 | 0b100, 0x25, 0, 0, 0, 0]);
+                Ok(())
+            }
+            Inst::MovSse { size, dst, src } => {
+                let prefix = Prefix::sse(if *size == Size::Dword { 0xf3 } else { 0xf2 });
+                match (dst, src) {
+                    (Operand::Reg(dst), _) => self.modrm(prefix, &[0x0f, 0x10], phys(dst)?, src, None),
+                    (_, Operand::Reg(src)) => self.modrm(prefix, &[0x0f, 0x11], phys(src)?, dst, None),
+                    _ => Err(unsupported("SSE move without a register operand".to_string())),
+                }
+            }
+            Inst::SseAlu { op, size, dst, src } => {
+                let opcode = match op {
+                    SseOp::Add => 0x58,
+                    SseOp::Mul => 0x59,
+                    SseOp::Sub => 0x5c,
+                    SseOp::Div => 0x5e,
+                };
+                let prefix = Prefix::sse(if *size == Size::Dword { 0xf3 } else { 0xf2 });
+                self.modrm(prefix, &[0x0f, opcode], phys(dst)?, src, None)
+            }
+            Inst::Ucomi { size, lhs, rhs } => {
+                let prefix = if *size == Size::Dword { Prefix::default() } else { Prefix::sse(0x66) };
+                self.modrm(prefix, &[0x0f, 0x2e], phys(lhs)?, rhs, None)
+            }
+            Inst::Xorps { dst, src } => self.modrm(Prefix::default(), &[0x0f, 0x57], phys(dst)?, src, None),
+            Inst::Psllq { dst, amount } => self.modrm(Prefix::sse(0x66), &[0x0f, 0x73], 6, &Operand::Reg(*dst), Some((*amount as i64, 1))),
+            Inst::MovToXmm { size, dst, src } => {
+                let prefix = Prefix { legacy: Some(0x66), rex_w: *size == Size::Qword, force_rex: false };
+                self.modrm(prefix, &[0x0f, 0x6e], phys(dst)?, src, None)
+            }
+            Inst::MovFromXmm { size, dst, src } => {
+                let prefix = Prefix { legacy: Some(0x66), rex_w: *size == Size::Qword, force_rex: false };
+                self.modrm(prefix, &[0x0f, 0x7e], phys(src)?, dst, None)
+            }
+            Inst::Fld { size, src } => {
+                let opcode = if *size == Size::Dword { 0xd9 } else { 0xdd };
+                self.modrm(Prefix::default(), &[opcode], 0, &Operand::Mem(src.clone()), None)
+            }
+            Inst::Fstp { size, dst } => {
+                let opcode = if *size == Size::Dword { 0xd9 } else { 0xdd };
+                self.modrm(Prefix::default(), &[opcode], 3, &Operand::Mem(dst.clone()), None)
+            }
+            Inst::Cvtsi2s { int_size, size, dst, src } => {
+                let prefix = Prefix { legacy: Some(if *size == Size::Dword { 0xf3 } else { 0xf2 }), rex_w: *int_size == Size::Qword, force_rex: false };
+                self.modrm(prefix, &[0x0f, 0x2a], phys(dst)?, src, None)
+            }
+            Inst::Cvtts2si { int_size, size, dst, src } => {
+                let prefix = Prefix { legacy: Some(if *size == Size::Dword { 0xf3 } else { 0xf2 }), rex_w: *int_size == Size::Qword, force_rex: false };
+                self.modrm(prefix, &[0x0f, 0x2c], phys(dst)?, src, None)
+            }
+            // the prefix names the source precision
+            Inst::Cvts2s { size, dst, src } => {
+                let prefix = Prefix::sse(if *size == Size::Dword { 0xf2 } else { 0xf3 });
+                self.modrm(prefix, &[0x0f, 0x5a], phys(dst)?, src, None)
+            }
+        }
+    }
+
+    fn mov(&mut self, size: Size, dst: &Operand, src: &Operand) -> Result<(), Error> {
+        let prefix = Prefix::sized(size, size == Size::Byte && (byte_rex(dst) || byte_rex(src)));
+        let wide = (size != Size::Byte) as u8;
+        match (dst, src) {
+            (Operand::Reg(dst), Operand::Imm(value)) if size != Size::Qword || i32::try_from(*value).is_err() => {
+                // `mov r, imm` with an immediate as wide as the register, which is `movabs` for 64 bits
+                self.opcode_reg(prefix, if wide == 0 { 0xb0 } else { 0xb8 }, phys(dst)?);
+                let len = if size == Size::Qword { 8 } else { size.bytes() as usize };
+                self.imm((*value, len));
+                Ok(())
+            }
+            (_, Operand::Imm(value)) => self.modrm(prefix, &[0xc6 | wide], 0, dst, Some(imm(size, *value)?)),
+            (_, Operand::Reg(src)) => self.modrm(prefix, &[0x88 | wide], phys(src)?, dst, None),
+            (Operand::Reg(dst), _) => self.modrm(prefix, &[0x8a | wide], phys(dst)?, src, None),
+            _ => Err(unsupported("mov between two memory operands".to_string())),
+        }
+    }
+
+    /// Writes one of the classic two-operand ALU instructions, `ext` being its
+    /// number in the `0x80` group and in the opcode map.
+    fn alu(&mut self, ext: u8, size: Size, dst: &Operand, src: &Operand) -> Result<(), Error> {
+        let prefix = Prefix::sized(size, size == Size::Byte && (byte_rex(dst) || byte_rex(src)));
+        let wide = (size != Size::Byte) as u8;
+        match (dst, src) {
+            (_, Operand::Imm(value)) if wide == 1 && i8::try_from(*value).is_ok() => self.modrm(prefix, &[0x83], ext, dst, Some((*value, 1))),
+            (Operand::Reg(Reg::Phys(PReg::Rax)), Operand::Imm(value)) => {
+                self.acc_imm(prefix, ext << 3 | 4 | wide, imm(size, *value)?);
+                Ok(())
+            }
+            (_, Operand::Imm(value)) => self.modrm(prefix, &[0x80 | wide], ext, dst, Some(imm(size, *value)?)),
+            (_, Operand::Reg(src)) => self.modrm(prefix, &[ext << 3 | wide], phys(src)?, dst, None),
+            (Operand::Reg(dst), _) => self.modrm(prefix, &[ext << 3 | 2 | wide], phys(dst)?, src, None),
+            _ => Err(unsupported("ALU operation on two memory operands".to_string())),
+        }
+    }
+
+    /// Returns the distance from the end of a fixup's field to its label.
+    fn distance(&self, fixup: &Fixup) -> Result<i64, Error> {
+        let target = self.labels.get(&fixup.label).ok_or_else(|| unsupported(format!("jump to unknown label {}", fixup.label)))?;
+        let len = if fixup.short { 1 } else { 4 };
+        Ok(*target as i64 - (fixup.field + len) as i64)
+    }
+
+    /// Returns the short jumps whose label is out of their reach.
+    fn out_of_range(&self) -> Result<Vec<usize>, Error> {
+        let mut jumps = Vec::new();
+        for fixup in self.fixups.iter().filter(|fixup| fixup.short) {
+            if i8::try_from(self.distance(fixup)?).is_err() {
+                jumps.push(fixup.jump);
+            }
+        }
+        Ok(jumps)
+    }
+
+    /// Fills in the distances to labels, now that every block has an offset.
+    fn resolve(mut self) -> Result<Code, Error> {
+        for fixup in &self.fixups {
+            let distance = self.distance(fixup)?;
+            let field = fixup.field as usize;
+            if fixup.short {
+                self.code.bytes[field] = distance as u8;
+            } else {
+                self.code.bytes[field..field + 4].copy_from_slice(&(distance as i32).to_le_bytes());
+            }
+        }
+        Ok(self.code)
+    }
+}
+
+/// Encodes a function after register allocation and frame layout. Jumps
+/// between its blocks are resolved, references to symbols are left as
+/// relocations.
+pub fn encode_function(mf: &MachineFunction) -> Result<Code, Error> {
+    // start with every jump short and widen the ones that don't reach, until
+    // all do; widening only moves labels apart, so this ends
+    let mut near_jumps = HashSet::new();
+    loop {
+        let mut encoder = Encoder { code: Code::default(), labels: HashMap::new(), fixups: Vec::new(), near_jumps: near_jumps.clone(), jumps: 0 };
+        for block in &mf.blocks {
+            encoder.labels.insert(block.label.clone(), encoder.pos());
+            for inst in &block.insts {
+                encoder.code.offsets.push(encoder.pos());
+                encoder.inst(inst)?;
+            }
+        }
+        let out_of_range = encoder.out_of_range()?;
+        if out_of_range.is_empty() {
+            return encoder.resolve();
+        }
+        near_jumps.extend(out_of_range);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emit::asm::x86_64::inst::{Cond, MachineBlock};
+
+    fn function(blocks: Vec<(&str, Vec<Inst>)>) -> MachineFunction {
+        let mut mf = MachineFunction::new("f");
+        for (label, insts) in blocks {
+            let mut block = MachineBlock::new(label.to_string(), None);
+            block.insts = insts;
+            mf.blocks.push(block);
+        }
+        mf
+    }
+
+    fn encode(insts: Vec<Inst>) -> Code {
+        encode_function(&function(vec![("f", insts)])).unwrap()
+    }
+
+    fn alu(op: AluOp, size: Size, dst: PReg, src: i64) -> Inst {
+        Inst::Alu { op, size, dst: dst.into(), src: Operand::Imm(src) }
+    }
+
+    #[test]
+    fn keeps_jumps_short_when_they_reach() {
+        let code = encode_function(&function(vec![
+            ("a", vec![Inst::Jcc { cond: Cond::E, target: "b".to_string() }]),
+            ("b", vec![Inst::Jmp { target: "a".to_string() }]),
+        ])).unwrap();
+        assert_eq!(code.bytes, [0x74, 0x00, 0xeb, 0xfc]);
+    }
+
+    #[test]
+    fn relaxes_jumps_that_do_not_reach() {
+        let add = Inst::Alu { op: AluOp::Add, size: Size::Qword, dst: PReg::Rax.into(), src: PReg::Rcx.into() };
+        let code = encode_function(&function(vec![
+            ("entry", vec![Inst::Jcc { cond: Cond::L, target: "far".to_string() }, Inst::Jmp { target: "near".to_string() }]),
+            ("near", vec![add; 50].into_iter().chain([Inst::Ret]).collect()),
+            ("far", vec![Inst::Jmp { target: "entry".to_string() }]),
+        ])).unwrap();
+        // 6 + 2 bytes of jumps, 50 3-byte adds and a ret put `far` at 159
+        assert_eq!(code.inst_bytes(0), [0x0f, 0x8c, 153, 0, 0, 0]);
+        assert_eq!(code.inst_bytes(1), [0xeb, 0x00]);
+        assert_eq!(code.inst_bytes(53), [0xe9, 0x5c, 0xff, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn uses_accumulator_and_short_immediate_forms() {
+        let code = encode(vec![
+            alu(AluOp::Add, Size::Dword, PReg::Rax, 1000),
+            alu(AluOp::Add, Size::Dword, PReg::Rcx, 1000),
+            alu(AluOp::Add, Size::Dword, PReg::Rax, 1),
+            Inst::Cmp { size: Size::Qword, lhs: PReg::Rax.into(), rhs: Operand::Imm(1000) },
+            alu(AluOp::And, Size::Byte, PReg::Rax, 0x7f),
+            alu(AluOp::Sub, Size::Word, PReg::R8, 300),
+        ]);
+        assert_eq!(code.inst_bytes(0), [0x05, 0xe8, 0x03, 0, 0]);
+        assert_eq!(code.inst_bytes(1), [0x81, 0xc1, 0xe8, 0x03, 0, 0]);
+        assert_eq!(code.inst_bytes(2), [0x83, 0xc0, 0x01]);
+        assert_eq!(code.inst_bytes(3), [0x48, 0x3d, 0xe8, 0x03, 0, 0]);
+        assert_eq!(code.inst_bytes(4), [0x24, 0x7f]);
+        assert_eq!(code.inst_bytes(5), [0x66, 0x41, 0x81, 0xe8, 0x2c, 0x01]);
+    }
+
+    #[test]
+    fn encodes_moves_and_symbol_references() {
+        let code = encode(vec![
+            Inst::Mov { size: Size::Qword, dst: PReg::Rax.into(), src: Operand::Imm(1 << 40) },
+            Inst::Mov { size: Size::Qword, dst: PReg::Rcx.into(), src: Operand::Imm(-1) },
+            Inst::Mov { size: Size::Byte, dst: PReg::Rsi.into(), src: PReg::R9.into() },
+            Inst::Mov { size: Size::Dword, dst: PReg::Rax.into(), src: Mem::rip("counter").into() },
+            Inst::Call { target: CallTarget::Symbol("g".to_string()), args: Vec::new() },
+        ]);
+        assert_eq!(code.inst_bytes(0), [0x48, 0xb8, 0, 0, 0, 0, 0, 1, 0, 0]);
+        assert_eq!(code.inst_bytes(1), [0x48, 0xc7, 0xc1, 0xff, 0xff, 0xff, 0xff]);
+        // `sil` needs a REX prefix
+        assert_eq!(code.inst_bytes(2), [0x44, 0x88, 0xce]);
+        assert_eq!(code.inst_bytes(3), [0x8b, 0x05, 0, 0, 0, 0]);
+        assert_eq!(code.inst_bytes(4), [0xe8, 0, 0, 0, 0]);
+        assert_eq!(code.relocs, [
+            Reloc { offset: 22, kind: RelocKind::Pc32, symbol: "counter".to_string(), addend: -4 },
+            Reloc { offset: 27, kind: RelocKind::Plt32, symbol: "g".to_string(), addend: -4 },
+        ]);
+    }
+
+    #[test]
+    fn encodes_instructions_for_wide_integers_and_x87_results() {
+        let code = encode(vec![
+            Inst::Alu { op: AluOp::Adc, size: Size::Dword, dst: PReg::Rdx.into(), src: PReg::Rcx.into() },
+            alu(AluOp::Sbb, Size::Dword, PReg::Rdx, 0),
+            Inst::Mul { size: Size::Dword, src: PReg::Rcx.into() },
+            Inst::DoubleShift { op: DoubleShiftOp::Shld, size: Size::Dword, dst: PReg::Rdx.into(), src: PReg::Rax.into(), amount: Some(5) },
+            Inst::DoubleShift { op: DoubleShiftOp::Shrd, size: Size::Dword, dst: PReg::Rax.into(), src: PReg::Rdx.into(), amount: None },
+            Inst::Cmov { cond: Cond::Ne, size: Size::Dword, dst: PReg::Rax.into(), src: PReg::Rcx.into() },
+            Inst::Psllq { dst: PReg::Xmm1.into(), amount: 32 },
+            Inst::Fld { size: Size::Qword, src: Mem::base(PReg::Rsp, 8) },
+            Inst::Fstp { size: Size::Dword, dst: Mem::base(PReg::Rax, 0) },
+            Inst::RetPop { bytes: 8 },
+        ]);
+        assert_eq!(code.inst_bytes(0), [0x11, 0xca]);
+        assert_eq!(code.inst_bytes(1), [0x83, 0xda, 0x00]);
+        assert_eq!(code.inst_bytes(2), [0xf7, 0xe1]);
+        assert_eq!(code.inst_bytes(3), [0x0f, 0xa4, 0xc2, 0x05]);
+        assert_eq!(code.inst_bytes(4), [0x0f, 0xad, 0xd0]);
+        assert_eq!(code.inst_bytes(5), [0x0f, 0x45, 0xc1]);
+        assert_eq!(code.inst_bytes(6), [0x66, 0x0f, 0x73, 0xf1, 0x20]);
+        assert_eq!(code.inst_bytes(7), [0xdd, 0x44, 0x24, 0x08]);
+        assert_eq!(code.inst_bytes(8), [0xd9, 0x18]);
+        assert_eq!(code.inst_bytes(9), [0xc2, 0x08, 0x00]);
+    }
+}
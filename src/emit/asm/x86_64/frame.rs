@@ -0,0 +1,248 @@
+use crate::emit::asm::frame::{self as shared, FrameOptions};
+use crate::emit::asm::x86_64::inst::{AluOp, Base, Inst, MachineFunction, Mem, Operand, PReg, Size, CALLEE_SAVED, I686_CALLEE_SAVED};
+
+/// The stack frame of one function, on x86_64 or on i686, where everything
+/// pushed takes a `Dword` instead of a `Qword` and other registers are
+/// callee-saved.
+///
+/// With a frame pointer the frame looks like this, from high to low addresses:
+///
+/// ```text
+/// return address
+/// saved rbp                <- rbp
+/// saved callee-saved registers
+/// stack slots
+/// outgoing call arguments  <- rsp
+/// ```
+///
+/// Without one, `rbp` is not saved and everything is addressed from `rsp`.
+/// Either way `rsp` is a multiple of 16 in the body of any function that
+/// makes calls. Functions with dynamically sized stack allocations always
+/// keep the frame pointer, since those move `rsp` below the outgoing
+/// argument area.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// Callee-saved registers the function writes, pushed in this order.
+    pub saved: Vec<PReg>,
+    /// Bytes reserved below the pushed registers.
+    pub size: u64,
+    pub frame_pointer: bool,
+    /// Whether `rsp` may have moved by the time the function returns.
+    dynamic: bool,
+    outgoing_args: u64,
+    /// Offset of each stack slot from `rbp`, or from `rsp` without a frame pointer.
+    offsets: Vec<i32>,
+    /// The size of addresses and of everything pushed.
+    word: Size,
+}
+
+impl Frame {
+    /// Computes the frame of `mf` from the registers it writes, its stack
+    /// slots and the space its calls need for stack arguments, for a target
+    /// whose words are `word` wide.
+    pub fn new(mf: &mut MachineFunction, options: FrameOptions, word: Size) -> Self {
+        let callee_saved: &[PReg] = if word == Size::Qword { &CALLEE_SAVED } else { &I686_CALLEE_SAVED };
+        let saved = shared::written_callee_saved(mf, callee_saved);
+
+        let frame_pointer = !options.omit_frame_pointer || mf.dynamic_stack;
+        // bytes pushed since the last 16-byte boundary, which was right before the call to us
+        let pushed = word.bytes() * (saved.len() as u64 + 1 + frame_pointer as u64);
+
+        let (offsets, end) = shared::slot_offsets(mf);
+        let size = if shared::makes_calls(mf) || mf.dynamic_stack || end > 0 {
+            (end + pushed).next_multiple_of(16) - pushed
+        } else {
+            0
+        };
+
+        let offsets = offsets.into_iter().map(|offset| if frame_pointer {
+            offset as i32 - (size + word.bytes() * saved.len() as u64) as i32
+        } else {
+            offset as i32
+        }).collect();
+
+        Self { saved, size, frame_pointer, dynamic: mf.dynamic_stack, outgoing_args: mf.outgoing_args, offsets, word }
+    }
+
+    /// Returns the address of a stack slot.
+    pub fn slot_address(&self, slot: u32, disp: i32) -> Mem {
+        let base = if self.frame_pointer { PReg::Rbp } else { PReg::Rsp };
+        Mem::base(base, self.offsets[slot as usize] + disp)
+    }
+
+    /// Returns the address of the stack arguments passed by the caller, which
+    /// start right above the return address.
+    pub fn incoming_args_address(&self, disp: i32) -> Mem {
+        let word = self.word.bytes();
+        if self.frame_pointer {
+            Mem::base(PReg::Rbp, (2 * word) as i32 + disp)
+        } else {
+            Mem::base(PReg::Rsp, (self.size + word * self.saved.len() as u64 + word) as i32 + disp)
+        }
+    }
+
+    pub fn prologue(&self) -> Vec<Inst> {
+        let mut insts = Vec::new();
+        if self.frame_pointer {
+            insts.push(Inst::Push { src: PReg::Rbp.into() });
+            insts.push(Inst::Mov { size: self.word, dst: PReg::Rbp.into(), src: PReg::Rsp.into() });
+        }
+        insts.extend(self.saved.iter().map(|preg| Inst::Push { src: (*preg).into() }));
+        if self.size > 0 {
+            insts.push(Inst::Alu { op: AluOp::Sub, size: self.word, dst: PReg::Rsp.into(), src: Operand::Imm(self.size as i64) });
+        }
+        insts
+    }
+
+    /// Returns the instructions that undo the prologue, to be placed before each `ret`.
+    pub fn epilogue(&self) -> Vec<Inst> {
+        let mut insts = Vec::new();
+        if !self.frame_pointer {
+            if self.size > 0 {
+                insts.push(Inst::Alu { op: AluOp::Add, size: self.word, dst: PReg::Rsp.into(), src: Operand::Imm(self.size as i64) });
+            }
+        } else if (self.size > 0 || self.dynamic) && self.saved.is_empty() {
+            insts.push(Inst::Mov { size: self.word, dst: PReg::Rsp.into(), src: PReg::Rbp.into() });
+        } else if self.size > 0 || self.dynamic {
+            let saved_size = (self.word.bytes() * self.saved.len() as u64) as i32;
+            insts.push(Inst::Lea { dst: PReg::Rsp.into(), addr: Mem::base(PReg::Rbp, -saved_size) });
+        }
+        insts.extend(self.saved.iter().rev().map(|preg| Inst::Pop { dst: (*preg).into() }));
+        if self.frame_pointer {
+            insts.push(Inst::Pop { dst: PReg::Rbp.into() });
+        }
+        insts
+    }
+
+    /// Resolves the stack slots of `mf` to frame addresses and wraps its
+    /// body in the prologue and epilogue.
+    pub fn apply(&self, mf: &mut MachineFunction) {
+        shared::insert_prologue_and_epilogues(mf, self.prologue(), &self.epilogue(), |mut inst, out| {
+            inst.visit_mems(&mut |mem: &mut Mem| {
+                match mem.base {
+                    Base::Slot(slot) => {
+                        let address = self.slot_address(slot, mem.disp);
+                        mem.base = address.base;
+                        mem.disp = address.disp;
+                    }
+                    Base::IncomingArgs => {
+                        let address = self.incoming_args_address(mem.disp);
+                        mem.base = address.base;
+                        mem.disp = address.disp;
+                    }
+                    Base::ArgsEnd => {
+                        mem.base = Base::Reg(PReg::Rsp.into());
+                        mem.disp += self.outgoing_args as i32;
+                    }
+                    _ => {}
+                }
+            });
+            out.push(inst);
+        });
+    }
+}
+
+/// Lays out the stack frame of `mf`, for a target whose words are `word`
+/// wide, and inserts its prologue and epilogues.
+pub fn lay_out(mf: &mut MachineFunction, options: FrameOptions, word: Size) -> Frame {
+    let frame = Frame::new(mf, options, word);
+    frame.apply(mf);
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emit::asm::x86_64::inst::{CallTarget, MachineBlock};
+
+    /// A function that keeps a value in `rbx` across a call and uses one stack slot.
+    fn function() -> MachineFunction {
+        let mut mf = MachineFunction::new("f");
+        let slot = mf.new_slot(4, 4);
+        mf.blocks.push(MachineBlock::new("f".to_string(), None));
+        mf.blocks[0].insts = vec![
+            Inst::Mov { size: Size::Qword, dst: PReg::Rbx.into(), src: Operand::Imm(1) },
+            Inst::Mov { size: Size::Dword, dst: Mem::slot(slot, 0).into(), src: Operand::Imm(2) },
+            Inst::Call { target: CallTarget::Symbol("g".to_string()), args: Vec::new() },
+            Inst::Mov { size: Size::Qword, dst: PReg::Rax.into(), src: PReg::Rbx.into() },
+            Inst::Ret,
+        ];
+        mf
+    }
+
+    #[test]
+    fn saves_callee_saved_registers_below_the_frame_pointer() {
+        let mut mf = function();
+        let frame = lay_out(&mut mf, FrameOptions::default(), Size::Qword);
+        assert_eq!(frame.saved, [PReg::Rbx]);
+        // return address, rbp and rbx leave rsp 8 bytes off a 16-byte boundary
+        assert_eq!(frame.size, 8);
+
+        let insts = &mf.blocks[0].insts;
+        assert_eq!(insts[..4], [
+            Inst::Push { src: PReg::Rbp.into() },
+            Inst::Mov { size: Size::Qword, dst: PReg::Rbp.into(), src: PReg::Rsp.into() },
+            Inst::Push { src: PReg::Rbx.into() },
+            Inst::Alu { op: AluOp::Sub, size: Size::Qword, dst: PReg::Rsp.into(), src: Operand::Imm(8) },
+        ]);
+        assert_eq!(insts[5], Inst::Mov { size: Size::Dword, dst: Mem::base(PReg::Rbp, -16).into(), src: Operand::Imm(2) });
+        assert_eq!(insts[insts.len() - 4..], [
+            Inst::Lea { dst: PReg::Rsp.into(), addr: Mem::base(PReg::Rbp, -8) },
+            Inst::Pop { dst: PReg::Rbx.into() },
+            Inst::Pop { dst: PReg::Rbp.into() },
+            Inst::Ret,
+        ]);
+    }
+
+    #[test]
+    fn addresses_the_frame_through_rsp_without_a_frame_pointer() {
+        let mut mf = function();
+        let frame = lay_out(&mut mf, FrameOptions { omit_frame_pointer: true }, Size::Qword);
+        assert!(!frame.frame_pointer);
+        // return address and rbx leave rsp on a 16-byte boundary, the slot needs 16 more
+        assert_eq!(frame.size, 16);
+
+        let insts = &mf.blocks[0].insts;
+        assert_eq!(insts[..2], [
+            Inst::Push { src: PReg::Rbx.into() },
+            Inst::Alu { op: AluOp::Sub, size: Size::Qword, dst: PReg::Rsp.into(), src: Operand::Imm(16) },
+        ]);
+        assert_eq!(insts[3], Inst::Mov { size: Size::Dword, dst: Mem::base(PReg::Rsp, 0).into(), src: Operand::Imm(2) });
+        assert_eq!(insts[insts.len() - 3..], [
+            Inst::Alu { op: AluOp::Add, size: Size::Qword, dst: PReg::Rsp.into(), src: Operand::Imm(16) },
+            Inst::Pop { dst: PReg::Rbx.into() },
+            Inst::Ret,
+        ]);
+    }
+
+    #[test]
+    fn leaf_functions_without_slots_need_no_frame() {
+        let mut mf = MachineFunction::new("f");
+        mf.blocks.push(MachineBlock::new("f".to_string(), None));
+        mf.blocks[0].insts = vec![Inst::Ret];
+        lay_out(&mut mf, FrameOptions { omit_frame_pointer: true }, Size::Qword);
+        assert_eq!(mf.blocks[0].insts, [Inst::Ret]);
+    }
+
+    #[test]
+    fn pushes_words_of_four_bytes_on_i686() {
+        let mut mf = function();
+        let ret = mf.blocks[0].insts.len() - 1;
+        mf.blocks[0].insts[ret] = Inst::RetPop { bytes: 8 };
+        let frame = lay_out(&mut mf, FrameOptions::default(), Size::Dword);
+        assert_eq!(frame.saved, [PReg::Rbx]);
+        // return address, ebp and ebx leave esp 4 bytes above a 16-byte boundary
+        assert_eq!(frame.size, 4);
+        assert_eq!(frame.incoming_args_address(0), Mem::base(PReg::Rbp, 8));
+
+        let insts = &mf.blocks[0].insts;
+        assert_eq!(insts[3], Inst::Alu { op: AluOp::Sub, size: Size::Dword, dst: PReg::Rsp.into(), src: Operand::Imm(4) });
+        assert_eq!(insts[5], Inst::Mov { size: Size::Dword, dst: Mem::base(PReg::Rbp, -8).into(), src: Operand::Imm(2) });
+        assert_eq!(insts[insts.len() - 4..], [
+            Inst::Lea { dst: PReg::Rsp.into(), addr: Mem::base(PReg::Rbp, -4) },
+            Inst::Pop { dst: PReg::Rbx.into() },
+            Inst::Pop { dst: PReg::Rbp.into() },
+            Inst::RetPop { bytes: 8 },
+        ]);
+    }
+}
@@ -0,0 +1,784 @@
+use crate::emit::asm::machine::{self, MachineInst, RegCopy};
+pub use crate::emit::asm::machine::{RegClass, RegUse, StackSlot, VReg};
+use std::fmt::{Display, Formatter};
+
+/// A physical x86 register, in hardware encoding order. i686 code only has
+/// the first eight of each kind, which it names by their 32-bit forms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PReg {
+    Rax, Rcx, Rdx, Rbx, Rsp, Rbp, Rsi, Rdi,
+    R8, R9, R10, R11, R12, R13, R14, R15,
+    Xmm0, Xmm1, Xmm2, Xmm3, Xmm4, Xmm5, Xmm6, Xmm7,
+    Xmm8, Xmm9, Xmm10, Xmm11, Xmm12, Xmm13, Xmm14, Xmm15,
+}
+
+/// Registers a call may overwrite. i686 calls preserve `esi` and `edi`, but
+/// the i686 backend only uses those as scratch registers, which hold nothing
+/// across a call anyway.
+pub const CALLER_SAVED: [PReg; 25] = [
+    PReg::Rax, PReg::Rcx, PReg::Rdx, PReg::Rsi, PReg::Rdi, PReg::R8, PReg::R9, PReg::R10, PReg::R11,
+    PReg::Xmm0, PReg::Xmm1, PReg::Xmm2, PReg::Xmm3, PReg::Xmm4, PReg::Xmm5, PReg::Xmm6, PReg::Xmm7,
+    PReg::Xmm8, PReg::Xmm9, PReg::Xmm10, PReg::Xmm11, PReg::Xmm12, PReg::Xmm13, PReg::Xmm14, PReg::Xmm15,
+];
+/// Registers a function must restore before returning, apart from `rsp` and `rbp`.
+pub const CALLEE_SAVED: [PReg; 5] = [PReg::Rbx, PReg::R12, PReg::R13, PReg::R14, PReg::R15];
+/// Registers an i686 function must restore before returning, apart from `esp` and `ebp`.
+pub const I686_CALLEE_SAVED: [PReg; 3] = [PReg::Rbx, PReg::Rsi, PReg::Rdi];
+
+pub type Reg = machine::Reg<PReg>;
+pub type MachineBlock = machine::MachineBlock<Inst>;
+pub type MachineFunction = machine::MachineFunction<Inst>;
+
+/// Operand width.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Size {
+    Byte,
+    Word,
+    Dword,
+    Qword,
+}
+
+/// The base of a memory operand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Base {
+    Reg(Reg),
+    /// A frame slot, resolved to a frame-relative address once the frame is laid out.
+    Slot(u32),
+    /// The lowest address above the outgoing argument area, which is where
+    /// dynamically sized stack allocations start.
+    ArgsEnd,
+    /// The stack arguments the caller passed, resolved to a frame-relative address.
+    IncomingArgs,
+    /// `[rip + symbol]`.
+    Rip(String),
+    /// `[rip + symbol@GOTPCREL]`.
+    Got(String),
+    /// `[rip + symbol@GOTTPOFF]`, the offset of a thread-local symbol from the thread pointer.
+    GotTpoff(String),
+    /// The absolute address of a symbol, which only i686 code uses.
+    Symbol(String),
+    /// `[symbol@INDNTPOFF]`, the GOT entry holding the offset of an i686
+    /// thread-local symbol from the thread pointer.
+    Indntpoff(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mem {
+    pub base: Base,
+    pub index: Option<(Reg, u8)>,
+    pub disp: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operand {
+    Reg(Reg),
+    Imm(i64),
+    Mem(Mem),
+}
+
+/// Condition codes, as used by `jcc`, `setcc` and `cmovcc`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cond {
+    O, No, B, Ae, E, Ne, Be, A, S, Ns, P, Np, L, Ge, Le, G,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AluOp {
+    Add,
+    Sub,
+    And,
+    Or,
+    Xor,
+    /// Add with carry, for the high halves of sums wider than a register.
+    Adc,
+    /// Subtract with borrow, for the high halves of differences wider than a register.
+    Sbb,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShiftOp {
+    Shl,
+    Shr,
+    Sar,
+}
+
+/// Shifts that fill the vacated bits from a second register, used for
+/// shifts wider than a register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DoubleShiftOp {
+    Shld,
+    Shrd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SseOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallTarget {
+    Symbol(String),
+    Indirect(Operand),
+}
+
+/// A single x86 machine instruction, shared by the x86_64 and i686
+/// backends. Operand sizes are explicit, SSE and x87 instructions use
+/// `Size::Dword` for single and `Size::Qword` for double precision.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inst {
+    Mov { size: Size, dst: Operand, src: Operand },
+    Movzx { dst_size: Size, src_size: Size, dst: Reg, src: Operand },
+    Movsx { dst_size: Size, src_size: Size, dst: Reg, src: Operand },
+    Lea { dst: Reg, addr: Mem },
+    Alu { op: AluOp, size: Size, dst: Operand, src: Operand },
+    Imul { size: Size, dst: Reg, src: Operand },
+    /// The unsigned product of the accumulator and `src`, in `rdx:rax`.
+    Mul { size: Size, src: Operand },
+    Unary { op: UnaryOp, size: Size, dst: Operand },
+    /// Shift by an immediate, or by `cl` when `amount` is `None`.
+    Shift { op: ShiftOp, size: Size, dst: Operand, amount: Option<u8> },
+    /// Shifts `dst`, shifting in bits from `src`, by an immediate or by `cl`.
+    DoubleShift { op: DoubleShiftOp, size: Size, dst: Operand, src: Reg, amount: Option<u8> },
+    Cmp { size: Size, lhs: Operand, rhs: Operand },
+    Test { size: Size, lhs: Operand, rhs: Operand },
+    /// Sign-extends the accumulator into `rdx` (`cwd`/`cdq`/`cqo`).
+    SignExtendAcc { size: Size },
+    Div { signed: bool, size: Size, src: Operand },
+    Setcc { cond: Cond, dst: Operand },
+    Cmov { cond: Cond, size: Size, dst: Reg, src: Operand },
+    Jmp { target: String },
+    Jcc { cond: Cond, target: String },
+    /// `args` are the registers carrying arguments, which the call reads.
+    Call { target: CallTarget, args: Vec<PReg> },
+    Ret,
+    /// Returns, popping `bytes` bytes of arguments off the caller's stack.
+    RetPop { bytes: u16 },
+    Push { src: Reg },
+    Pop { dst: Reg },
+    Ud2,
+    /// Reads the thread pointer, `fs:0` on x86_64 and `gs:0` on i686.
+    ThreadPointer { dst: Reg },
+    MovSse { size: Size, dst: Operand, src: Operand },
+    SseAlu { op: SseOp, size: Size, dst: Reg, src: Operand },
+    Ucomi { size: Size, lhs: Reg, rhs: Operand },
+    Xorps { dst: Reg, src: Operand },
+    /// Shifts the low quadword of an XMM register left.
+    Psllq { dst: Reg, amount: u8 },
+    /// `movd`/`movq` from a general purpose register into an XMM register.
+    MovToXmm { size: Size, dst: Reg, src: Operand },
+    /// `movd`/`movq` from an XMM register into a general purpose register.
+    MovFromXmm { size: Size, dst: Operand, src: Reg },
+    /// Pushes a value onto the x87 stack, where i686 returns floating point values.
+    Fld { size: Size, src: Mem },
+    /// Pops the top of the x87 stack.
+    Fstp { size: Size, dst: Mem },
+    /// `cvtsi2ss`/`cvtsi2sd` of a signed integer of `int_size`, a dword or qword.
+    Cvtsi2s { int_size: Size, size: Size, dst: Reg, src: Operand },
+    /// `cvttss2si`/`cvttsd2si`, truncating a float to a signed integer of `int_size`.
+    Cvtts2si { int_size: Size, size: Size, dst: Reg, src: Operand },
+    /// `cvtsd2ss`/`cvtss2sd`, converting to a float of `size`.
+    Cvts2s { size: Size, dst: Reg, src: Operand },
+}
+
+impl PReg {
+    pub const GPRS: [PReg; 16] = [
+        PReg::Rax, PReg::Rcx, PReg::Rdx, PReg::Rbx, PReg::Rsp, PReg::Rbp, PReg::Rsi, PReg::Rdi,
+        PReg::R8, PReg::R9, PReg::R10, PReg::R11, PReg::R12, PReg::R13, PReg::R14, PReg::R15,
+    ];
+
+    pub const XMMS: [PReg; 16] = [
+        PReg::Xmm0, PReg::Xmm1, PReg::Xmm2, PReg::Xmm3, PReg::Xmm4, PReg::Xmm5, PReg::Xmm6, PReg::Xmm7,
+        PReg::Xmm8, PReg::Xmm9, PReg::Xmm10, PReg::Xmm11, PReg::Xmm12, PReg::Xmm13, PReg::Xmm14, PReg::Xmm15,
+    ];
+
+    /// Returns the 4-bit hardware number of the register.
+    pub fn encoding(self) -> u8 {
+        (self as u8) & 0xf
+    }
+
+    pub fn class(self) -> RegClass {
+        if self >= PReg::Xmm0 {
+            RegClass::Float
+        } else {
+            RegClass::Int
+        }
+    }
+
+    /// Returns the assembler name of the register when accessed with the given width.
+    pub fn name(self, size: Size) -> &'static str {
+        const NAMES: [[&str; 4]; 16] = [
+            ["al", "ax", "eax", "rax"],
+            ["cl", "cx", "ecx", "rcx"],
+            ["dl", "dx", "edx", "rdx"],
+            ["bl", "bx", "ebx", "rbx"],
+            ["spl", "sp", "esp", "rsp"],
+            ["bpl", "bp", "ebp", "rbp"],
+            ["sil", "si", "esi", "rsi"],
+            ["dil", "di", "edi", "rdi"],
+            ["r8b", "r8w", "r8d", "r8"],
+            ["r9b", "r9w", "r9d", "r9"],
+            ["r10b", "r10w", "r10d", "r10"],
+            ["r11b", "r11w", "r11d", "r11"],
+            ["r12b", "r12w", "r12d", "r12"],
+            ["r13b", "r13w", "r13d", "r13"],
+            ["r14b", "r14w", "r14d", "r14"],
+            ["r15b", "r15w", "r15d", "r15"],
+        ];
+        const XMM_NAMES: [&str; 16] = [
+            "xmm0", "xmm1", "xmm2", "xmm3", "xmm4", "xmm5", "xmm6", "xmm7",
+            "xmm8", "xmm9", "xmm10", "xmm11", "xmm12", "xmm13", "xmm14", "xmm15",
+        ];
+        match self.class() {
+            RegClass::Int => NAMES[self.encoding() as usize][size as usize],
+            RegClass::Float => XMM_NAMES[self.encoding() as usize],
+        }
+    }
+}
+
+impl Size {
+    pub fn from_bytes(bytes: u64) -> Option<Size> {
+        match bytes {
+            1 => Some(Size::Byte),
+            2 => Some(Size::Word),
+            4 => Some(Size::Dword),
+            8 => Some(Size::Qword),
+            _ => None,
+        }
+    }
+
+    pub fn bytes(self) -> u64 {
+        match self {
+            Size::Byte => 1,
+            Size::Word => 2,
+            Size::Dword => 4,
+            Size::Qword => 8,
+        }
+    }
+
+    fn ptr_name(self) -> &'static str {
+        match self {
+            Size::Byte => "byte ptr",
+            Size::Word => "word ptr",
+            Size::Dword => "dword ptr",
+            Size::Qword => "qword ptr",
+        }
+    }
+}
+
+impl From<PReg> for Reg {
+    fn from(reg: PReg) -> Self {
+        Reg::Phys(reg)
+    }
+}
+
+impl From<Reg> for Operand {
+    fn from(reg: Reg) -> Self {
+        Operand::Reg(reg)
+    }
+}
+
+impl From<PReg> for Operand {
+    fn from(reg: PReg) -> Self {
+        Operand::Reg(Reg::Phys(reg))
+    }
+}
+
+impl From<VReg> for Operand {
+    fn from(reg: VReg) -> Self {
+        Operand::Reg(Reg::Virt(reg))
+    }
+}
+
+impl From<Mem> for Operand {
+    fn from(mem: Mem) -> Self {
+        Operand::Mem(mem)
+    }
+}
+
+impl Mem {
+    /// `[reg + disp]`
+    pub fn base(reg: impl Into<Reg>, disp: i32) -> Self {
+        Self { base: Base::Reg(reg.into()), index: None, disp }
+    }
+
+    pub fn slot(slot: u32, disp: i32) -> Self {
+        Self { base: Base::Slot(slot), index: None, disp }
+    }
+
+    pub fn rip(symbol: &str) -> Self {
+        Self { base: Base::Rip(symbol.to_string()), index: None, disp: 0 }
+    }
+
+    pub fn got(symbol: &str) -> Self {
+        Self { base: Base::Got(symbol.to_string()), index: None, disp: 0 }
+    }
+
+    pub fn got_tpoff(symbol: &str) -> Self {
+        Self { base: Base::GotTpoff(symbol.to_string()), index: None, disp: 0 }
+    }
+
+    pub fn symbol(symbol: &str) -> Self {
+        Self { base: Base::Symbol(symbol.to_string()), index: None, disp: 0 }
+    }
+
+    pub fn indntpoff(symbol: &str) -> Self {
+        Self { base: Base::Indntpoff(symbol.to_string()), index: None, disp: 0 }
+    }
+}
+
+impl Cond {
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Cond::O => "o",
+            Cond::No => "no",
+            Cond::B => "b",
+            Cond::Ae => "ae",
+            Cond::E => "e",
+            Cond::Ne => "ne",
+            Cond::Be => "be",
+            Cond::A => "a",
+            Cond::S => "s",
+            Cond::Ns => "ns",
+            Cond::P => "p",
+            Cond::Np => "np",
+            Cond::L => "l",
+            Cond::Ge => "ge",
+            Cond::Le => "le",
+            Cond::G => "g",
+        }
+    }
+
+    /// Returns the condition that holds exactly when `self` does not.
+    pub fn invert(self) -> Cond {
+        match self {
+            Cond::O => Cond::No,
+            Cond::No => Cond::O,
+            Cond::B => Cond::Ae,
+            Cond::Ae => Cond::B,
+            Cond::E => Cond::Ne,
+            Cond::Ne => Cond::E,
+            Cond::Be => Cond::A,
+            Cond::A => Cond::Be,
+            Cond::S => Cond::Ns,
+            Cond::Ns => Cond::S,
+            Cond::P => Cond::Np,
+            Cond::Np => Cond::P,
+            Cond::L => Cond::Ge,
+            Cond::Ge => Cond::L,
+            Cond::Le => Cond::G,
+            Cond::G => Cond::Le,
+        }
+    }
+}
+
+impl Operand {
+    fn visit_regs(&mut self, access: RegUse, f: &mut dyn FnMut(&mut Reg, RegUse)) {
+        match self {
+            Operand::Reg(reg) => f(reg, access),
+            Operand::Imm(_) => {}
+            Operand::Mem(mem) => mem.visit_regs(f),
+        }
+    }
+}
+
+impl Mem {
+    fn visit_regs(&mut self, f: &mut dyn FnMut(&mut Reg, RegUse)) {
+        if let Base::Reg(reg) = &mut self.base {
+            f(reg, RegUse::Use);
+        }
+        if let Some((reg, _)) = &mut self.index {
+            f(reg, RegUse::Use);
+        }
+    }
+}
+
+impl MachineInst for Inst {
+    type PReg = PReg;
+
+    fn visit_regs(&mut self, f: &mut dyn FnMut(&mut Reg, RegUse)) {
+        match self {
+            Inst::Mov { dst, src, .. } | Inst::MovSse { dst, src, .. } => {
+                src.visit_regs(RegUse::Use, f);
+                dst.visit_regs(RegUse::Def, f);
+            }
+            Inst::Movzx { dst, src, .. } | Inst::Movsx { dst, src, .. } | Inst::MovToXmm { dst, src, .. }
+            | Inst::Cvtsi2s { dst, src, .. } | Inst::Cvtts2si { dst, src, .. } | Inst::Cvts2s { dst, src, .. } => {
+                src.visit_regs(RegUse::Use, f);
+                f(dst, RegUse::Def);
+            }
+            Inst::MovFromXmm { dst, src, .. } => {
+                f(src, RegUse::Use);
+                dst.visit_regs(RegUse::Def, f);
+            }
+            Inst::Lea { dst, addr } => {
+                addr.visit_regs(f);
+                f(dst, RegUse::Def);
+            }
+            Inst::Alu { op, dst, src, .. } => {
+                // `xor r, r` does not read its input
+                if *op == AluOp::Xor && dst == src {
+                    dst.visit_regs(RegUse::Def, f);
+                } else {
+                    src.visit_regs(RegUse::Use, f);
+                    dst.visit_regs(RegUse::UseDef, f);
+                }
+            }
+            Inst::Imul { dst, src, .. } | Inst::SseAlu { dst, src, .. } | Inst::Cmov { dst, src, .. } => {
+                src.visit_regs(RegUse::Use, f);
+                f(dst, RegUse::UseDef);
+            }
+            Inst::Xorps { dst, src } => {
+                if *src == Operand::Reg(*dst) {
+                    f(dst, RegUse::Def);
+                } else {
+                    src.visit_regs(RegUse::Use, f);
+                    f(dst, RegUse::UseDef);
+                }
+            }
+            Inst::Psllq { dst, .. } => f(dst, RegUse::UseDef),
+            Inst::Unary { dst, .. } | Inst::Shift { dst, .. } => dst.visit_regs(RegUse::UseDef, f),
+            Inst::DoubleShift { dst, src, .. } => {
+                f(src, RegUse::Use);
+                dst.visit_regs(RegUse::UseDef, f);
+            }
+            Inst::Cmp { lhs, rhs, .. } | Inst::Test { lhs, rhs, .. } => {
+                lhs.visit_regs(RegUse::Use, f);
+                rhs.visit_regs(RegUse::Use, f);
+            }
+            Inst::Ucomi { lhs, rhs, .. } => {
+                f(lhs, RegUse::Use);
+                rhs.visit_regs(RegUse::Use, f);
+            }
+            Inst::Mul { src, .. } | Inst::Div { src, .. } => src.visit_regs(RegUse::Use, f),
+            Inst::Setcc { dst, .. } => dst.visit_regs(RegUse::Def, f),
+            Inst::Call { target: CallTarget::Indirect(target), .. } => target.visit_regs(RegUse::Use, f),
+            Inst::Push { src } => f(src, RegUse::Use),
+            Inst::Pop { dst } | Inst::ThreadPointer { dst } => f(dst, RegUse::Def),
+            Inst::Fld { src: mem, .. } | Inst::Fstp { dst: mem, .. } => mem.visit_regs(f),
+            Inst::SignExtendAcc { .. } | Inst::Jmp { .. } | Inst::Jcc { .. } | Inst::Call { .. } | Inst::Ret | Inst::RetPop { .. }
+            | Inst::Ud2 => {}
+        }
+    }
+
+    fn visit_fixed_regs(&self, f: &mut dyn FnMut(PReg, RegUse)) {
+        match self {
+            Inst::SignExtendAcc { size: Size::Byte } => f(PReg::Rax, RegUse::UseDef),
+            Inst::SignExtendAcc { .. } => {
+                f(PReg::Rax, RegUse::Use);
+                f(PReg::Rdx, RegUse::Def);
+            }
+            Inst::Mul { .. } => {
+                f(PReg::Rax, RegUse::UseDef);
+                f(PReg::Rdx, RegUse::Def);
+            }
+            Inst::Div { .. } => {
+                f(PReg::Rax, RegUse::UseDef);
+                f(PReg::Rdx, RegUse::UseDef);
+            }
+            Inst::Shift { amount: None, .. } | Inst::DoubleShift { amount: None, .. } => f(PReg::Rcx, RegUse::Use),
+            Inst::Call { args, .. } => args.iter().for_each(|arg| f(*arg, RegUse::Use)),
+            _ => {}
+        }
+    }
+
+    fn clobbers(&self) -> &'static [PReg] {
+        match self {
+            Inst::Call { .. } => &CALLER_SAVED,
+            _ => &[],
+        }
+    }
+
+    fn is_terminator(&self) -> bool {
+        matches!(self, Inst::Jmp { .. } | Inst::Ret | Inst::RetPop { .. } | Inst::Ud2)
+    }
+
+    fn is_return(&self) -> bool {
+        matches!(self, Inst::Ret | Inst::RetPop { .. })
+    }
+
+    fn is_call(&self) -> bool {
+        matches!(self, Inst::Call { .. })
+    }
+
+    fn branch_target(&self) -> Option<&str> {
+        match self {
+            Inst::Jmp { target } | Inst::Jcc { target, .. } => Some(target),
+            _ => None,
+        }
+    }
+
+    fn as_copy(&self) -> Option<RegCopy<PReg>> {
+        match self {
+            Inst::Mov { dst: Operand::Reg(dst), src: Operand::Reg(src), .. }
+            | Inst::MovSse { dst: Operand::Reg(dst), src: Operand::Reg(src), .. } => Some((*dst, *src)),
+            _ => None,
+        }
+    }
+}
+
+impl Inst {
+    /// Calls `f` on every memory operand of the instruction.
+    pub fn visit_mems(&mut self, f: &mut dyn FnMut(&mut Mem)) {
+        let mut operand = |op: &mut Operand| {
+            if let Operand::Mem(mem) = op {
+                f(mem);
+            }
+        };
+        match self {
+            Inst::Mov { dst, src, .. } | Inst::MovSse { dst, src, .. } | Inst::Alu { dst, src, .. } => {
+                operand(dst);
+                operand(src);
+            }
+            Inst::Cmp { lhs, rhs, .. } | Inst::Test { lhs, rhs, .. } => {
+                operand(lhs);
+                operand(rhs);
+            }
+            Inst::Movzx { src, .. } | Inst::Movsx { src, .. } | Inst::Imul { src, .. } | Inst::Mul { src, .. } | Inst::Div { src, .. }
+            | Inst::Cmov { src, .. } | Inst::SseAlu { src, .. } | Inst::Ucomi { rhs: src, .. } | Inst::Xorps { src, .. }
+            | Inst::MovToXmm { src, .. } | Inst::Cvtsi2s { src, .. } | Inst::Cvtts2si { src, .. } | Inst::Cvts2s { src, .. } => operand(src),
+            Inst::Unary { dst, .. } | Inst::Shift { dst, .. } | Inst::DoubleShift { dst, .. } | Inst::Setcc { dst, .. }
+            | Inst::MovFromXmm { dst, .. } => operand(dst),
+            Inst::Call { target: CallTarget::Indirect(target), .. } => operand(target),
+            Inst::Lea { addr, .. } | Inst::Fld { src: addr, .. } | Inst::Fstp { dst: addr, .. } => f(addr),
+            Inst::SignExtendAcc { .. } | Inst::Jmp { .. } | Inst::Jcc { .. } | Inst::Call { .. } | Inst::Ret | Inst::RetPop { .. }
+            | Inst::Push { .. } | Inst::Pop { .. } | Inst::Ud2 | Inst::ThreadPointer { .. } | Inst::Psllq { .. } => {}
+        }
+    }
+}
+
+fn fmt_reg(f: &mut Formatter<'_>, reg: &Reg, size: Size) -> std::fmt::Result {
+    match reg {
+        Reg::Phys(reg) => write!(f, "{}", reg.name(size)),
+        Reg::Virt(reg) => write!(f, "%v{}", reg.index),
+    }
+}
+
+/// Writes `[address]`, where registers take the width `ptr_size` of addresses.
+fn fmt_address(f: &mut Formatter<'_>, mem: &Mem, ptr_size: Size) -> std::fmt::Result {
+    write!(f, "[")?;
+    match &mem.base {
+        Base::Reg(reg) => fmt_reg(f, reg, ptr_size)?,
+        Base::Slot(slot) => write!(f, "slot{}", slot)?,
+        Base::ArgsEnd => write!(f, "args_end")?,
+        Base::IncomingArgs => write!(f, "incoming_args")?,
+        Base::Rip(symbol) => write!(f, "rip + {}", symbol)?,
+        Base::Got(symbol) => write!(f, "rip + {}@GOTPCREL", symbol)?,
+        Base::GotTpoff(symbol) => write!(f, "rip + {}@GOTTPOFF", symbol)?,
+        Base::Symbol(symbol) => write!(f, "{}", symbol)?,
+        Base::Indntpoff(symbol) => write!(f, "{}@INDNTPOFF", symbol)?,
+    }
+    if let Some((index, scale)) = &mem.index {
+        write!(f, " + ")?;
+        fmt_reg(f, index, ptr_size)?;
+        write!(f, "*{}", scale)?;
+    }
+    if mem.disp > 0 {
+        write!(f, " + {}", mem.disp)?;
+    } else if mem.disp < 0 {
+        write!(f, " - {}", -(mem.disp as i64))?;
+    }
+    write!(f, "]")
+}
+
+fn fmt_operand(f: &mut Formatter<'_>, op: &Operand, size: Size, ptr_size: Size) -> std::fmt::Result {
+    match op {
+        Operand::Reg(reg) => fmt_reg(f, reg, size),
+        Operand::Imm(imm) => write!(f, "{}", imm),
+        Operand::Mem(mem) => {
+            write!(f, "{} ", size.ptr_name())?;
+            fmt_address(f, mem, ptr_size)
+        }
+    }
+}
+
+fn fmt_binary(f: &mut Formatter<'_>, mnemonic: &str, dst: &Operand, dst_size: Size, src: &Operand, src_size: Size, ptr_size: Size) -> std::fmt::Result {
+    write!(f, "{} ", mnemonic)?;
+    fmt_operand(f, dst, dst_size, ptr_size)?;
+    write!(f, ", ")?;
+    fmt_operand(f, src, src_size, ptr_size)
+}
+
+fn sse_suffix(size: Size) -> &'static str {
+    if size == Size::Dword { "ss" } else { "sd" }
+}
+
+/// An instruction formatted for a target whose addresses are `ptr_size`
+/// wide, which decides the names of address and stack registers and the
+/// segment of the thread pointer.
+pub struct Intel<'a> {
+    inst: &'a Inst,
+    ptr_size: Size,
+}
+
+impl Inst {
+    /// Formats the instruction for a target with addresses of `ptr_size`.
+    pub fn display(&self, ptr_size: Size) -> Intel<'_> {
+        Intel { inst: self, ptr_size }
+    }
+}
+
+impl Display for Inst {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.display(Size::Qword).fmt(f)
+    }
+}
+
+impl Display for Intel<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let ptr = self.ptr_size;
+        match self.inst {
+            Inst::Mov { size, dst, src } => {
+                let wide = matches!(src, Operand::Imm(imm) if i32::try_from(*imm).is_err());
+                let mnemonic = if wide && matches!(dst, Operand::Reg(_)) { "movabs" } else { "mov" };
+                fmt_binary(f, mnemonic, dst, *size, src, *size, ptr)
+            }
+            Inst::Movzx { dst_size, src_size, dst, src } => {
+                if *src_size == Size::Dword {
+                    // writing a 32-bit register zeroes the upper half
+                    fmt_binary(f, "mov", &Operand::Reg(*dst), Size::Dword, src, Size::Dword, ptr)
+                } else {
+                    fmt_binary(f, "movzx", &Operand::Reg(*dst), *dst_size, src, *src_size, ptr)
+                }
+            }
+            Inst::Movsx { dst_size, src_size, dst, src } => {
+                let mnemonic = if *src_size == Size::Dword { "movsxd" } else { "movsx" };
+                fmt_binary(f, mnemonic, &Operand::Reg(*dst), *dst_size, src, *src_size, ptr)
+            }
+            Inst::Lea { dst, addr } => {
+                write!(f, "lea ")?;
+                fmt_reg(f, dst, ptr)?;
+                write!(f, ", ")?;
+                fmt_address(f, addr, ptr)
+            }
+            Inst::Alu { op, size, dst, src } => {
+                let mnemonic = match op {
+                    AluOp::Add => "add",
+                    AluOp::Sub => "sub",
+                    AluOp::And => "and",
+                    AluOp::Or => "or",
+                    AluOp::Xor => "xor",
+                    AluOp::Adc => "adc",
+                    AluOp::Sbb => "sbb",
+                };
+                fmt_binary(f, mnemonic, dst, *size, src, *size, ptr)
+            }
+            Inst::Imul { size, dst, src } => fmt_binary(f, "imul", &Operand::Reg(*dst), *size, src, *size, ptr),
+            Inst::Mul { size, src } => {
+                write!(f, "mul ")?;
+                fmt_operand(f, src, *size, ptr)
+            }
+            Inst::Unary { op, size, dst } => {
+                write!(f, "{} ", if *op == UnaryOp::Neg { "neg" } else { "not" })?;
+                fmt_operand(f, dst, *size, ptr)
+            }
+            Inst::Shift { op, size, dst, amount } => {
+                let mnemonic = match op {
+                    ShiftOp::Shl => "shl",
+                    ShiftOp::Shr => "shr",
+                    ShiftOp::Sar => "sar",
+                };
+                write!(f, "{} ", mnemonic)?;
+                fmt_operand(f, dst, *size, ptr)?;
+                match amount {
+                    Some(amount) => write!(f, ", {}", amount),
+                    None => write!(f, ", cl"),
+                }
+            }
+            Inst::DoubleShift { op, size, dst, src, amount } => {
+                let mnemonic = if *op == DoubleShiftOp::Shld { "shld" } else { "shrd" };
+                fmt_binary(f, mnemonic, dst, *size, &Operand::Reg(*src), *size, ptr)?;
+                match amount {
+                    Some(amount) => write!(f, ", {}", amount),
+                    None => write!(f, ", cl"),
+                }
+            }
+            Inst::Cmp { size, lhs, rhs } => fmt_binary(f, "cmp", lhs, *size, rhs, *size, ptr),
+            Inst::Test { size, lhs, rhs } => fmt_binary(f, "test", lhs, *size, rhs, *size, ptr),
+            Inst::SignExtendAcc { size } => write!(f, "{}", match size {
+                Size::Byte => "cbw",
+                Size::Word => "cwd",
+                Size::Dword => "cdq",
+                Size::Qword => "cqo",
+            }),
+            Inst::Div { signed, size, src } => {
+                write!(f, "{} ", if *signed { "idiv" } else { "div" })?;
+                fmt_operand(f, src, *size, ptr)
+            }
+            Inst::Setcc { cond, dst } => {
+                write!(f, "set{} ", cond.suffix())?;
+                fmt_operand(f, dst, Size::Byte, ptr)
+            }
+            Inst::Cmov { cond, size, dst, src } => fmt_binary(f, &format!("cmov{}", cond.suffix()), &Operand::Reg(*dst), *size, src, *size, ptr),
+            Inst::Jmp { target } => write!(f, "jmp {}", target),
+            Inst::Jcc { cond, target } => write!(f, "j{} {}", cond.suffix(), target),
+            Inst::Call { target: CallTarget::Symbol(symbol), .. } => write!(f, "call {}", symbol),
+            Inst::Call { target: CallTarget::Indirect(target), .. } => {
+                write!(f, "call ")?;
+                fmt_operand(f, target, ptr, ptr)
+            }
+            Inst::Ret => write!(f, "ret"),
+            Inst::RetPop { bytes } => write!(f, "ret {}", bytes),
+            Inst::Push { src } => {
+                write!(f, "push ")?;
+                fmt_reg(f, src, ptr)
+            }
+            Inst::Pop { dst } => {
+                write!(f, "pop ")?;
+                fmt_reg(f, dst, ptr)
+            }
+            Inst::Ud2 => write!(f, "ud2"),
+            Inst::ThreadPointer { dst } => {
+                write!(f, "mov ")?;
+                fmt_reg(f, dst, ptr)?;
+                let segment = if ptr == Size::Qword { "fs" } else { "gs" };
+                write!(f, ", {} {}:0", ptr.ptr_name(), segment)
+            }
+            Inst::MovSse { size, dst, src } => fmt_binary(f, &format!("mov{}", sse_suffix(*size)), dst, *size, src, *size, ptr),
+            Inst::SseAlu { op, size, dst, src } => {
+                let op = match op {
+                    SseOp::Add => "add",
+                    SseOp::Sub => "sub",
+                    SseOp::Mul => "mul",
+                    SseOp::Div => "div",
+                };
+                fmt_binary(f, &format!("{}{}", op, sse_suffix(*size)), &Operand::Reg(*dst), *size, src, *size, ptr)
+            }
+            Inst::Ucomi { size, lhs, rhs } => fmt_binary(f, &format!("ucomi{}", if *size == Size::Dword { "ss" } else { "sd" }), &Operand::Reg(*lhs), *size, rhs, *size, ptr),
+            Inst::Xorps { dst, src } => fmt_binary(f, "xorps", &Operand::Reg(*dst), Size::Qword, src, Size::Qword, ptr),
+            Inst::Psllq { dst, amount } => {
+                write!(f, "psllq ")?;
+                fmt_reg(f, dst, Size::Qword)?;
+                write!(f, ", {}", amount)
+            }
+            Inst::MovToXmm { size, dst, src } => {
+                let mnemonic = if *size == Size::Dword { "movd" } else { "movq" };
+                fmt_binary(f, mnemonic, &Operand::Reg(*dst), *size, src, *size, ptr)
+            }
+            Inst::MovFromXmm { size, dst, src } => {
+                let mnemonic = if *size == Size::Dword { "movd" } else { "movq" };
+                fmt_binary(f, mnemonic, dst, *size, &Operand::Reg(*src), *size, ptr)
+            }
+            Inst::Fld { size, src } => {
+                write!(f, "fld {} ", size.ptr_name())?;
+                fmt_address(f, src, ptr)
+            }
+            Inst::Fstp { size, dst } => {
+                write!(f, "fstp {} ", size.ptr_name())?;
+                fmt_address(f, dst, ptr)
+            }
+            Inst::Cvtsi2s { int_size, size, dst, src } => fmt_binary(f, &format!("cvtsi2{}", sse_suffix(*size)), &Operand::Reg(*dst), *size, src, *int_size, ptr),
+            Inst::Cvtts2si { int_size, size, dst, src } => fmt_binary(f, &format!("cvtt{}2si", sse_suffix(*size)), &Operand::Reg(*dst), *int_size, src, *size, ptr),
+            Inst::Cvts2s { size: Size::Dword, dst, src } => fmt_binary(f, "cvtsd2ss", &Operand::Reg(*dst), Size::Dword, src, Size::Qword, ptr),
+            Inst::Cvts2s { dst, src, .. } => fmt_binary(f, "cvtss2sd", &Operand::Reg(*dst), Size::Qword, src, Size::Dword, ptr),
+        }
+    }
+}
@@ -0,0 +1,1162 @@
+use crate::emit::asm::data::{constant_data, data_bytes, Datum};
+use crate::emit::asm::unsupported;
+use crate::emit::asm::x86_64::abi::{classify_call, ArgLocation, CallAbi, Piece, ReturnLocation};
+use crate::emit::asm::x86_64::inst::{AluOp, Base, CallTarget, Cond, Inst, MachineBlock, MachineFunction, Mem, Operand, PReg, Reg, RegClass, ShiftOp, Size, SseOp, UnaryOp, VReg};
+use crate::ir::values::basic_block::BasicBlock;
+use crate::ir::values::constant::{Constant, Scalar};
+use crate::ir::values::function::Function;
+use crate::ir::values::instruction::{FastMathFlags, FloatPredicate, Instruction, InstructionType};
+use crate::ir::values::value::{Type, ValueEntity};
+use crate::targets::layout::DataLayout;
+use std::collections::{HashMap, HashSet};
+use std::io::Error;
+
+/// Returns the register class and width used to hold a value of type `ty`.
+pub fn scalar_type(ty: &Type) -> Result<(RegClass, Size), Error> {
+    match ty {
+        Type::Integer(1) | Type::Integer(8) => Ok((RegClass::Int, Size::Byte)),
+        Type::Integer(16) => Ok((RegClass::Int, Size::Word)),
+        Type::Integer(32) => Ok((RegClass::Int, Size::Dword)),
+        Type::Integer(64) => Ok((RegClass::Int, Size::Qword)),
+        Type::Pointer(_) | Type::FunctionType(_, _) => Ok((RegClass::Int, Size::Qword)),
+        Type::Float(32) => Ok((RegClass::Float, Size::Dword)),
+        Type::Float(64) => Ok((RegClass::Float, Size::Qword)),
+        _ => Err(unsupported(format!("values of type {} are not supported by the x86_64 backend", ty))),
+    }
+}
+
+/// Returns the register class used for a value of type `ty`. Structs and
+/// arrays are held as the address of a frame slot containing the value.
+fn value_class(ty: &Type) -> Result<RegClass, Error> {
+    if is_aggregate(ty) {
+        Ok(RegClass::Int)
+    } else {
+        scalar_type(ty).map(|(class, _)| class)
+    }
+}
+
+fn is_aggregate(ty: &Type) -> bool {
+    ty.is_struct() || ty.is_array()
+}
+
+/// Returns the `getelementptr`s whose only uses are as the address of
+/// scalar loads and stores, which compute it in their memory operand.
+fn folded_addresses(func: &Function) -> HashMap<String, Instruction> {
+    let mut geps = HashMap::new();
+    let mut other_uses = HashSet::new();
+    for block in func.get_blocks() {
+        for inst in block.borrow().get_instructions() {
+            let ValueEntity::Instruction(inst) = inst else {
+                continue;
+            };
+            if let InstructionType::GetElementPtr(..) = inst.instruction_type() {
+                geps.insert(inst.get_name(), inst.clone());
+            }
+            let address = match inst.instruction_type() {
+                InstructionType::Load(ptr) if !is_aggregate(&inst.get_type()) => Some(ptr.as_ref()),
+                InstructionType::Store(ptr, value) if !is_aggregate(&value.get_type()) => Some(ptr.as_ref()),
+                _ => None,
+            };
+            for operand in inst.get_operands() {
+                if let ValueEntity::Instruction(used) = operand {
+                    if !address.is_some_and(|address| std::ptr::eq(address, operand)) {
+                        other_uses.insert(used.get_name());
+                    }
+                }
+            }
+        }
+    }
+    geps.retain(|name, _| !other_uses.contains(name));
+    geps
+}
+
+/// Returns the assembler label of a basic block.
+pub fn block_label(function: &str, block: &str) -> String {
+    format!(".L{}.{}", function, block.trim_start_matches('%'))
+}
+
+/// Lowers the IR of one function into x86_64 machine code over virtual registers.
+pub struct FunctionLowering<'a> {
+    func: &'a Function,
+    layout: &'a DataLayout,
+    mf: MachineFunction,
+    values: HashMap<String, VReg>,
+    phi_temps: HashMap<String, VReg>,
+    /// The `getelementptr`s left to the loads and stores that use them.
+    addresses: HashMap<String, Instruction>,
+    current: usize,
+    /// Holds the caller's result buffer when the function returns in memory.
+    sret: Option<VReg>,
+}
+
+impl<'a> FunctionLowering<'a> {
+    pub fn new(func: &'a Function, layout: &'a DataLayout) -> Self {
+        Self {
+            func,
+            layout,
+            mf: MachineFunction::new(&func.get_name()),
+            values: HashMap::new(),
+            phi_temps: HashMap::new(),
+            addresses: folded_addresses(func),
+            current: 0,
+            sret: None,
+        }
+    }
+
+    /// Returns how arguments and the return value are passed to this function.
+    pub fn abi(&self) -> Result<CallAbi, Error> {
+        let ty = self.func.get_type();
+        classify_call(self.layout, ty.get_function_argument_types(), &ty.get_function_return_type())
+    }
+
+    pub fn lower(mut self) -> Result<MachineFunction, Error> {
+        let blocks = self.func.get_blocks().clone();
+        let abi = self.abi()?;
+
+        // every value gets its register up front, so uses may precede definitions in block order
+        for param in self.func.get_params() {
+            let vreg = self.mf.new_vreg(value_class(&param.get_type())?);
+            self.values.insert(param.get_name(), vreg);
+        }
+        for block in &blocks {
+            for inst in block.borrow().get_instructions() {
+                let ValueEntity::Instruction(inst) = inst else {
+                    continue;
+                };
+                if inst.get_type().is_void() {
+                    continue;
+                }
+                let class = value_class(&inst.get_type())?;
+                let vreg = self.mf.new_vreg(class);
+                self.values.insert(inst.get_name(), vreg);
+                if let InstructionType::Phi(_) = inst.instruction_type() {
+                    let temp = self.mf.new_vreg(class);
+                    self.phi_temps.insert(inst.get_name(), temp);
+                }
+            }
+        }
+
+        for (i, block) in blocks.iter().enumerate() {
+            let block = block.borrow();
+            self.start_block(block_label(&self.func.get_name(), &block.get_name()), Some(block.get_name()));
+            if i == 0 && abi.ret == ReturnLocation::Memory {
+                let sret = self.mf.new_vreg(RegClass::Int);
+                self.emit(Inst::Mov { size: Size::Qword, dst: sret.into(), src: PReg::Rdi.into() });
+                self.sret = Some(sret);
+            }
+            if i == 0 {
+                self.lower_params(&abi)?;
+            }
+            for inst in block.get_instructions() {
+                if let ValueEntity::Instruction(inst) = inst {
+                    self.lower_instruction(&block, inst)?;
+                }
+            }
+        }
+
+        // drop jumps to the block that follows anyway
+        for i in 0..self.mf.blocks.len().saturating_sub(1) {
+            let next = self.mf.blocks[i + 1].label.clone();
+            if let Some(Inst::Jmp { target }) = self.mf.blocks[i].insts.last() {
+                if *target == next {
+                    self.mf.blocks[i].insts.pop();
+                }
+            }
+        }
+
+        Ok(self.mf)
+    }
+
+    fn start_block(&mut self, label: String, comment: Option<String>) {
+        self.mf.blocks.push(MachineBlock::new(label, comment));
+        self.current = self.mf.blocks.len() - 1;
+    }
+
+    fn emit(&mut self, inst: Inst) {
+        self.mf.blocks[self.current].insts.push(inst);
+    }
+
+    fn label(&self, block: &str) -> String {
+        block_label(&self.func.get_name(), block)
+    }
+
+    fn result(&self, inst: &Instruction) -> Result<VReg, Error> {
+        self.values.get(&inst.get_name()).copied()
+            .ok_or_else(|| unsupported(format!("instruction {} has no result register", inst.get_name())))
+    }
+
+    /// Returns the machine operand holding `value`, which is an immediate for constants.
+    fn operand(&mut self, value: &ValueEntity) -> Result<Operand, Error> {
+        match value {
+            ValueEntity::Instruction(inst) => self.values.get(&inst.get_name()).map(|vreg| Operand::Reg(Reg::Virt(*vreg)))
+                .ok_or_else(|| unsupported(format!("use of undefined value {}", inst.get_name()))),
+            ValueEntity::Constant(constant) => self.constant(constant),
+            ValueEntity::Function(function) => {
+                let vreg = self.mf.new_vreg(RegClass::Int);
+                if function.is_external() {
+                    self.emit(Inst::Mov { size: Size::Qword, dst: vreg.into(), src: Mem::got(&function.get_name()).into() });
+                } else {
+                    self.emit(Inst::Lea { dst: vreg.into(), addr: Mem::rip(&function.get_name()) });
+                }
+                Ok(vreg.into())
+            }
+            ValueEntity::GlobalVariable(global) => {
+                let vreg = self.mf.new_vreg(RegClass::Int);
+                if global.is_thread_local() {
+                    let thread_pointer = self.mf.new_vreg(RegClass::Int);
+                    self.emit(Inst::ThreadPointer { dst: thread_pointer.into() });
+                    self.emit(Inst::Mov { size: Size::Qword, dst: vreg.into(), src: Mem::got_tpoff(&global.get_name()).into() });
+                    self.emit(Inst::Alu { op: AluOp::Add, size: Size::Qword, dst: vreg.into(), src: thread_pointer.into() });
+                } else if global.is_external() {
+                    self.emit(Inst::Mov { size: Size::Qword, dst: vreg.into(), src: Mem::got(&global.get_name()).into() });
+                } else {
+                    self.emit(Inst::Lea { dst: vreg.into(), addr: Mem::rip(&global.get_name()) });
+                }
+                Ok(vreg.into())
+            }
+            ValueEntity::Argument(argument) => self.values.get(&argument.get_name()).map(|vreg| Operand::Reg(Reg::Virt(*vreg)))
+                .ok_or_else(|| unsupported(format!("use of argument {} outside its function", argument.get_name()))),
+            ValueEntity::BasicBlock(block) => Err(unsupported(format!("basic block {} used as a value", block.get_name()))),
+        }
+    }
+
+    /// Returns the machine operand holding a constant. Addresses are loaded
+    /// from the GOT, since the symbol may be defined in another module, and
+    /// structs and arrays are built in a frame slot.
+    fn constant(&mut self, constant: &Constant) -> Result<Operand, Error> {
+        let ty = constant.get_type();
+        if is_aggregate(&ty) {
+            let data = constant_data(self.layout, &ty, constant)?;
+            let dst = self.mf.new_vreg(RegClass::Int);
+            self.aggregate_slot(dst, &ty);
+            self.store_data(dst, &data);
+            return Ok(dst.into());
+        }
+        match constant.fold() {
+            Some(Scalar::Int(value)) if value.bits() <= 64 => Ok(Operand::Imm(value.to_machine_i64())),
+            // SSE has no immediates, so the bits go through a general register
+            Some(Scalar::Float(bits, value)) => {
+                let (size, bits) = match bits {
+                    32 => (Size::Dword, (f64::from_bits(value) as f32).to_bits() as i64),
+                    _ => (Size::Qword, value as i64),
+                };
+                let int = self.mf.new_vreg(RegClass::Int);
+                let vreg = self.mf.new_vreg(RegClass::Float);
+                self.emit(Inst::Mov { size: Size::Qword, dst: int.into(), src: Operand::Imm(bits) });
+                self.emit(Inst::MovToXmm { size, dst: vreg.into(), src: int.into() });
+                Ok(vreg.into())
+            }
+            Some(Scalar::Address(symbol, offset)) => Ok(self.symbol_address(&symbol, offset).into()),
+            _ => Err(unsupported(format!("constant {} of type {}", constant, ty))),
+        }
+    }
+
+    fn symbol_address(&mut self, symbol: &str, offset: i64) -> VReg {
+        let vreg = self.mf.new_vreg(RegClass::Int);
+        self.emit(Inst::Mov { size: Size::Qword, dst: vreg.into(), src: Mem::got(symbol).into() });
+        if offset != 0 {
+            self.add_imm(vreg, offset);
+        }
+        vreg
+    }
+
+    /// Adds `imm` to the 64-bit value in `dst`.
+    fn add_imm(&mut self, dst: VReg, imm: i64) {
+        let src = match i32::try_from(imm) {
+            Ok(_) => Operand::Imm(imm),
+            Err(_) => {
+                let temp = self.mf.new_vreg(RegClass::Int);
+                self.emit(Inst::Mov { size: Size::Qword, dst: temp.into(), src: Operand::Imm(imm) });
+                temp.into()
+            }
+        };
+        self.emit(Inst::Alu { op: AluOp::Add, size: Size::Qword, dst: dst.into(), src });
+    }
+
+    /// Stores the contents of a constant to the address in `dst`.
+    fn store_data(&mut self, dst: VReg, data: &[Datum]) {
+        let (bytes, symbols) = data_bytes(data);
+        let mut offset = 0;
+        for chunk in [Size::Qword, Size::Dword, Size::Word, Size::Byte] {
+            let len = chunk.bytes() as usize;
+            while bytes.len() - offset >= len {
+                let mut value = [0; 8];
+                value[..len].copy_from_slice(&bytes[offset..offset + len]);
+                let src = match i64::from_le_bytes(value) {
+                    // the upper half of a byte, word or dword immediate is ignored
+                    imm if chunk != Size::Qword || i32::try_from(imm).is_ok() => Operand::Imm(imm),
+                    imm => {
+                        let temp = self.mf.new_vreg(RegClass::Int);
+                        self.emit(Inst::Mov { size: Size::Qword, dst: temp.into(), src: Operand::Imm(imm) });
+                        temp.into()
+                    }
+                };
+                self.emit(Inst::Mov { size: chunk, dst: Mem::base(dst, offset as i32).into(), src });
+                offset += len;
+            }
+        }
+        for (offset, size, symbol, addend) in symbols {
+            let address = self.symbol_address(&symbol, addend);
+            let size = Size::from_bytes(size).unwrap_or(Size::Qword);
+            self.emit(Inst::Mov { size, dst: Mem::base(dst, offset as i32).into(), src: address.into() });
+        }
+    }
+
+    /// Moves the arguments from where the caller put them into their registers.
+    fn lower_params(&mut self, abi: &CallAbi) -> Result<(), Error> {
+        for (param, location) in self.func.get_params().iter().zip(&abi.args) {
+            let ty = param.get_type();
+            let dst = self.values[&param.get_name()];
+            let incoming = |offset: u64| Mem { base: Base::IncomingArgs, index: None, disp: offset as i32 };
+            match location {
+                ArgLocation::Regs(pieces) if is_aggregate(&ty) => {
+                    self.aggregate_slot(dst, &ty);
+                    for piece in pieces {
+                        self.store_piece(*piece, dst.into());
+                    }
+                }
+                ArgLocation::Regs(pieces) => {
+                    let (class, size) = scalar_type(&ty)?;
+                    self.copy(class, size, dst.into(), pieces[0].reg.into());
+                }
+                // aggregates passed in memory are the callee's own copy
+                ArgLocation::Stack { offset, .. } if is_aggregate(&ty) => self.emit(Inst::Lea { dst: dst.into(), addr: incoming(*offset) }),
+                ArgLocation::Stack { offset, .. } => {
+                    let (class, size) = scalar_type(&ty)?;
+                    self.copy(class, size, dst.into(), incoming(*offset).into());
+                }
+                ArgLocation::Ignore => self.aggregate_slot(dst, &ty),
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `operand`, but immediates are first moved into a fresh register.
+    fn reg(&mut self, value: &ValueEntity, size: Size) -> Result<Reg, Error> {
+        match self.operand(value)? {
+            Operand::Reg(reg) => Ok(reg),
+            src => {
+                let vreg = self.mf.new_vreg(RegClass::Int);
+                self.emit(Inst::Mov { size, dst: vreg.into(), src });
+                Ok(vreg.into())
+            }
+        }
+    }
+
+    /// Like `operand`, but immediates that do not fit a sign-extended 32-bit field are moved into a register.
+    fn imm32_operand(&mut self, value: &ValueEntity, size: Size) -> Result<Operand, Error> {
+        match self.operand(value)? {
+            Operand::Imm(imm) if i32::try_from(imm).is_err() => {
+                let vreg = self.mf.new_vreg(RegClass::Int);
+                self.emit(Inst::Mov { size, dst: vreg.into(), src: Operand::Imm(imm) });
+                Ok(vreg.into())
+            }
+            op => Ok(op),
+        }
+    }
+
+    fn copy(&mut self, class: RegClass, size: Size, dst: Operand, src: Operand) {
+        match class {
+            RegClass::Int => self.emit(Inst::Mov { size, dst, src }),
+            RegClass::Float => self.emit(Inst::MovSse { size, dst, src }),
+        }
+    }
+
+    fn lower_instruction(&mut self, block: &BasicBlock, inst: &Instruction) -> Result<(), Error> {
+        match inst.instruction_type() {
+            InstructionType::Add(a, b) => self.lower_alu(inst, AluOp::Add, a, b),
+            InstructionType::Sub(a, b) => self.lower_alu(inst, AluOp::Sub, a, b),
+            InstructionType::And(a, b) => self.lower_alu(inst, AluOp::And, a, b),
+            InstructionType::Or(a, b) => self.lower_alu(inst, AluOp::Or, a, b),
+            InstructionType::Xor(a, b) => self.lower_alu(inst, AluOp::Xor, a, b),
+            InstructionType::Mul(a, b) => {
+                let (_, size) = scalar_type(&a.get_type())?;
+                // there is no two-operand 8-bit imul; the low byte of a 32-bit product is the same
+                let size = size.max(Size::Dword);
+                let dst = self.result(inst)?;
+                let lhs = self.operand(a)?;
+                self.emit(Inst::Mov { size, dst: dst.into(), src: lhs });
+                let rhs = self.reg(b, size)?;
+                self.emit(Inst::Imul { size, dst: dst.into(), src: rhs.into() });
+                Ok(())
+            }
+            InstructionType::SDiv(a, b) => self.lower_div(inst, a, b, PReg::Rax, true),
+            InstructionType::UDiv(a, b) => self.lower_div(inst, a, b, PReg::Rax, false),
+            InstructionType::SRem(a, b) => self.lower_div(inst, a, b, PReg::Rdx, true),
+            InstructionType::URem(a, b) => self.lower_div(inst, a, b, PReg::Rdx, false),
+            InstructionType::Shl(a, b) => self.lower_shift(inst, ShiftOp::Shl, a, b),
+            InstructionType::AShr(a, b) => self.lower_shift(inst, ShiftOp::Sar, a, b),
+            InstructionType::LShr(a, b) => self.lower_shift(inst, ShiftOp::Shr, a, b),
+            InstructionType::Eq(a, b) => self.lower_compare(inst, Cond::E, a, b),
+            InstructionType::Ne(a, b) => self.lower_compare(inst, Cond::Ne, a, b),
+            InstructionType::SLt(a, b) => self.lower_compare(inst, Cond::L, a, b),
+            InstructionType::SLe(a, b) => self.lower_compare(inst, Cond::Le, a, b),
+            InstructionType::SGt(a, b) => self.lower_compare(inst, Cond::G, a, b),
+            InstructionType::SGe(a, b) => self.lower_compare(inst, Cond::Ge, a, b),
+            InstructionType::ULt(a, b) => self.lower_compare(inst, Cond::B, a, b),
+            InstructionType::ULe(a, b) => self.lower_compare(inst, Cond::Be, a, b),
+            InstructionType::UGt(a, b) => self.lower_compare(inst, Cond::A, a, b),
+            InstructionType::UGe(a, b) => self.lower_compare(inst, Cond::Ae, a, b),
+            InstructionType::Neg(a) => {
+                let (_, size) = scalar_type(&a.get_type())?;
+                let dst = self.result(inst)?;
+                let src = self.operand(a)?;
+                self.emit(Inst::Mov { size, dst: dst.into(), src });
+                self.emit(Inst::Unary { op: UnaryOp::Neg, size, dst: dst.into() });
+                Ok(())
+            }
+            InstructionType::FAdd(a, b, _) => self.lower_sse(inst, SseOp::Add, a, b),
+            InstructionType::FSub(a, b, _) => self.lower_sse(inst, SseOp::Sub, a, b),
+            InstructionType::FMul(a, b, _) => self.lower_sse(inst, SseOp::Mul, a, b),
+            InstructionType::FDiv(a, b, _) => self.lower_sse(inst, SseOp::Div, a, b),
+            InstructionType::FRem(a, b, _) => {
+                // SSE has no remainder, so it comes from libm
+                let (_, size) = scalar_type(&a.get_type())?;
+                let dst = self.result(inst)?;
+                let lhs = self.operand(a)?;
+                let rhs = self.operand(b)?;
+                self.emit(Inst::MovSse { size, dst: PReg::Xmm0.into(), src: lhs });
+                self.emit(Inst::MovSse { size, dst: PReg::Xmm1.into(), src: rhs });
+                let symbol = if size == Size::Dword { "fmodf" } else { "fmod" };
+                self.emit(Inst::Call { target: CallTarget::Symbol(symbol.to_string()), args: vec![PReg::Xmm0, PReg::Xmm1] });
+                self.emit(Inst::MovSse { size, dst: dst.into(), src: PReg::Xmm0.into() });
+                Ok(())
+            }
+            InstructionType::FNeg(a, _) => {
+                let (_, size) = scalar_type(&a.get_type())?;
+                let dst = self.result(inst)?;
+                let src = self.operand(a)?;
+                self.emit(Inst::MovSse { size, dst: dst.into(), src });
+                // flip the sign bit
+                let mask = if size == Size::Dword { 0x8000_0000 } else { i64::MIN };
+                let bits = self.mf.new_vreg(RegClass::Int);
+                let sign = self.mf.new_vreg(RegClass::Float);
+                self.emit(Inst::Mov { size: Size::Qword, dst: bits.into(), src: Operand::Imm(mask) });
+                self.emit(Inst::MovToXmm { size, dst: sign.into(), src: bits.into() });
+                self.emit(Inst::Xorps { dst: dst.into(), src: sign.into() });
+                Ok(())
+            }
+            InstructionType::FCmp(predicate, a, b, flags) => self.lower_float_compare(inst, *predicate, *flags, a, b),
+            InstructionType::Not(a) => {
+                let (_, size) = scalar_type(&a.get_type())?;
+                let dst = self.result(inst)?;
+                let src = self.operand(a)?;
+                self.emit(Inst::Mov { size, dst: dst.into(), src });
+                if a.get_type() == Type::Integer(1) {
+                    // booleans stay 0 or 1
+                    self.emit(Inst::Alu { op: AluOp::Xor, size, dst: dst.into(), src: Operand::Imm(1) });
+                } else {
+                    self.emit(Inst::Unary { op: UnaryOp::Not, size, dst: dst.into() });
+                }
+                Ok(())
+            }
+            InstructionType::Trunc(a) | InstructionType::ZExt(a) | InstructionType::SExt(a) | InstructionType::FPTrunc(a)
+            | InstructionType::FPExt(a) | InstructionType::FPToSI(a) | InstructionType::FPToUI(a) | InstructionType::SIToFP(a)
+            | InstructionType::UIToFP(a) | InstructionType::PtrToInt(a) | InstructionType::IntToPtr(a) | InstructionType::Bitcast(a) => {
+                let (opcode, _) = inst.instruction_type().as_cast().unwrap();
+                self.lower_cast(inst, opcode, a)
+            }
+            InstructionType::Alloca(ty, count, align) => self.lower_alloca(inst, ty, count.as_deref(), *align),
+            InstructionType::GetElementPtr(ptr, indices) => {
+                if self.addresses.contains_key(&inst.get_name()) {
+                    return Ok(());
+                }
+                let dst = self.result(inst)?;
+                let addr = self.element_address(inst, ptr, indices)?;
+                self.emit(Inst::Lea { dst: dst.into(), addr });
+                Ok(())
+            }
+            InstructionType::Load(ptr) => {
+                let dst = self.result(inst)?;
+                if is_aggregate(&inst.get_type()) {
+                    let ptr = self.reg(ptr, Size::Qword)?;
+                    let size = self.layout.size_of(&inst.get_type());
+                    self.aggregate_slot(dst, &inst.get_type());
+                    self.copy_bytes(dst.into(), ptr, size);
+                    return Ok(());
+                }
+                let (class, size) = scalar_type(&inst.get_type())?;
+                let addr = self.address(ptr)?;
+                self.copy(class, size, dst.into(), addr.into());
+                Ok(())
+            }
+            InstructionType::Store(ptr, value) => {
+                if is_aggregate(&value.get_type()) {
+                    let ptr = self.reg(ptr, Size::Qword)?;
+                    let src = self.reg(value, Size::Qword)?;
+                    self.copy_bytes(ptr, src, self.layout.size_of(&value.get_type()));
+                    return Ok(());
+                }
+                let addr = self.address(ptr)?;
+                let (class, size) = scalar_type(&value.get_type())?;
+                let value = match class {
+                    RegClass::Int => self.imm32_operand(value, size)?,
+                    RegClass::Float => self.operand(value)?,
+                };
+                self.copy(class, size, addr.into(), value);
+                Ok(())
+            }
+            InstructionType::Call(callee, args) => self.lower_call(inst, callee, args),
+            InstructionType::Return(value) => {
+                match self.abi()?.ret {
+                    ReturnLocation::Memory => {
+                        let sret = self.sret.unwrap();
+                        let src = self.reg(value, Size::Qword)?;
+                        self.copy_bytes(sret.into(), src, self.layout.size_of(&value.get_type()));
+                        self.emit(Inst::Mov { size: Size::Qword, dst: PReg::Rax.into(), src: sret.into() });
+                    }
+                    ReturnLocation::Regs(pieces) if is_aggregate(&value.get_type()) => {
+                        let src = self.reg(value, Size::Qword)?;
+                        for piece in pieces {
+                            self.load_piece(piece, src);
+                        }
+                    }
+                    _ => {
+                        let (class, _) = scalar_type(&value.get_type())?;
+                        let src = self.operand(value)?;
+                        let ret = if class == RegClass::Int { PReg::Rax } else { PReg::Xmm0 };
+                        self.move_to_arg_reg(&value.get_type(), ret, src);
+                    }
+                }
+                self.emit(Inst::Ret);
+                Ok(())
+            }
+            InstructionType::VoidReturn => {
+                self.emit(Inst::Ret);
+                Ok(())
+            }
+            InstructionType::Branch(target) => {
+                self.emit_phi_copies(block, &target.get_name())?;
+                let target = self.label(&target.get_name());
+                self.emit(Inst::Jmp { target });
+                Ok(())
+            }
+            InstructionType::BranchIf(cond, if_true, if_false) => {
+                let if_true = if_true.borrow().get_name();
+                let if_false = if_false.borrow().get_name();
+                self.lower_branch_if(block, cond, &if_true, &if_false)
+            }
+            InstructionType::Phi(_) => {
+                let (class, size) = self.register_type(&inst.get_type())?;
+                let dst = self.result(inst)?;
+                let temp = self.phi_temps[&inst.get_name()];
+                self.copy(class, size, dst.into(), temp.into());
+                Ok(())
+            }
+            InstructionType::Unreachable => {
+                self.emit(Inst::Ud2);
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the memory operand for the address in `ptr`, computing it in
+    /// place when it comes from a `getelementptr` left to its uses.
+    fn address(&mut self, ptr: &ValueEntity) -> Result<Mem, Error> {
+        if let ValueEntity::Instruction(inst) = ptr {
+            if let Some(gep) = self.addresses.get(&inst.get_name()).cloned() {
+                if let InstructionType::GetElementPtr(base, indices) = gep.instruction_type() {
+                    return self.element_address(&gep, base, indices);
+                }
+            }
+        }
+        Ok(Mem::base(self.reg(ptr, Size::Qword)?, 0))
+    }
+
+    /// Returns the address a `getelementptr` computes as a memory operand.
+    /// Constant indices end up in the displacement and the first index with
+    /// a stride of 1, 2, 4 or 8 in the index register; the others are
+    /// multiplied out and added to the base.
+    fn element_address(&mut self, inst: &Instruction, ptr: &ValueEntity, indices: &[Box<ValueEntity>]) -> Result<Mem, Error> {
+        let (offset, scaled) = self.layout.indexed_offset(&ptr.get_type().get_pointer_element_type(), indices)
+            .ok_or_else(|| unsupported(format!("`{}` has indices its type can't take", inst)))?;
+        let mut base = self.reg(ptr, Size::Qword)?;
+        let mut index = None;
+        for (value, stride) in scaled {
+            let reg = self.index_reg(value)?;
+            if index.is_none() && matches!(stride, 1 | 2 | 4 | 8) {
+                index = Some((reg, stride as u8));
+                continue;
+            }
+            let product = self.mf.new_vreg(RegClass::Int);
+            let stride_reg = self.mf.new_vreg(RegClass::Int);
+            self.emit(Inst::Mov { size: Size::Qword, dst: product.into(), src: reg.into() });
+            self.emit(Inst::Mov { size: Size::Qword, dst: stride_reg.into(), src: Operand::Imm(stride as i64) });
+            self.emit(Inst::Imul { size: Size::Qword, dst: product.into(), src: stride_reg.into() });
+            let sum = self.mf.new_vreg(RegClass::Int);
+            self.emit(Inst::Lea { dst: sum.into(), addr: Mem { index: Some((product.into(), 1)), ..Mem::base(base, 0) } });
+            base = sum.into();
+        }
+        let disp = match i32::try_from(offset) {
+            Ok(disp) => disp,
+            Err(_) => {
+                let sum = self.mf.new_vreg(RegClass::Int);
+                self.emit(Inst::Mov { size: Size::Qword, dst: sum.into(), src: base.into() });
+                self.add_imm(sum, offset);
+                base = sum.into();
+                0
+            }
+        };
+        Ok(Mem { index, ..Mem::base(base, disp) })
+    }
+
+    /// Returns a register holding the index `value` sign-extended to 64 bits.
+    fn index_reg(&mut self, value: &ValueEntity) -> Result<Reg, Error> {
+        let (_, size) = scalar_type(&value.get_type())?;
+        let src = self.reg(value, size)?;
+        if size == Size::Qword {
+            return Ok(src);
+        }
+        let dst = self.mf.new_vreg(RegClass::Int);
+        self.sign_extend(value, Size::Qword, dst, src);
+        Ok(dst.into())
+    }
+
+    fn lower_alloca(&mut self, inst: &Instruction, ty: &Type, count: Option<&ValueEntity>, align: u64) -> Result<(), Error> {
+        let dst = self.result(inst)?;
+        let stride = self.layout.stride_of(ty);
+        // the frame only guarantees 16-byte alignment, anything stricter is done by hand
+        let padding = align.saturating_sub(16);
+        let (count, count_type) = match count {
+            Some(count) => (self.operand(count)?, count.get_type()),
+            None => (Operand::Imm(1), Type::Integer(64)),
+        };
+        // where an over-aligned address is rounded down from
+        let disp = if padding > 0 { align as i32 - 1 } else { 0 };
+
+        match count {
+            Operand::Imm(count) => {
+                let size = (count as u64 * stride).max(1);
+                if padding == 0 {
+                    let slot = self.mf.new_slot(size, align);
+                    self.emit(Inst::Lea { dst: dst.into(), addr: Mem::slot(slot, 0) });
+                } else {
+                    let slot = self.mf.new_slot(size + padding, 16);
+                    self.emit(Inst::Lea { dst: dst.into(), addr: Mem::slot(slot, disp) });
+                    self.emit(Inst::Alu { op: AluOp::And, size: Size::Qword, dst: dst.into(), src: Operand::Imm(-(align as i64)) });
+                }
+            }
+            count => {
+                let (_, count_size) = scalar_type(&count_type)?;
+                let bytes = self.mf.new_vreg(RegClass::Int);
+                if count_size == Size::Qword {
+                    self.emit(Inst::Mov { size: Size::Qword, dst: bytes.into(), src: count });
+                } else {
+                    self.emit(Inst::Movzx { dst_size: Size::Qword, src_size: count_size, dst: bytes.into(), src: count });
+                }
+                let stride_reg = self.mf.new_vreg(RegClass::Int);
+                self.emit(Inst::Mov { size: Size::Qword, dst: stride_reg.into(), src: Operand::Imm(stride as i64) });
+                self.emit(Inst::Imul { size: Size::Qword, dst: bytes.into(), src: stride_reg.into() });
+                // keep rsp a multiple of 16
+                self.emit(Inst::Alu { op: AluOp::Add, size: Size::Qword, dst: bytes.into(), src: Operand::Imm((15 + padding) as i64) });
+                self.emit(Inst::Alu { op: AluOp::And, size: Size::Qword, dst: bytes.into(), src: Operand::Imm(-16) });
+                self.emit(Inst::Alu { op: AluOp::Sub, size: Size::Qword, dst: PReg::Rsp.into(), src: bytes.into() });
+                let addr = Mem { base: Base::ArgsEnd, index: None, disp };
+                self.emit(Inst::Lea { dst: dst.into(), addr });
+                if padding > 0 {
+                    self.emit(Inst::Alu { op: AluOp::And, size: Size::Qword, dst: dst.into(), src: Operand::Imm(-(align as i64)) });
+                }
+                self.mf.dynamic_stack = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn lower_alu(&mut self, inst: &Instruction, op: AluOp, a: &ValueEntity, b: &ValueEntity) -> Result<(), Error> {
+        let (_, size) = scalar_type(&a.get_type())?;
+        let dst = self.result(inst)?;
+        let lhs = self.operand(a)?;
+        let rhs = self.imm32_operand(b, size)?;
+        self.emit(Inst::Mov { size, dst: dst.into(), src: lhs });
+        self.emit(Inst::Alu { op, size, dst: dst.into(), src: rhs });
+        Ok(())
+    }
+
+    fn lower_sse(&mut self, inst: &Instruction, op: SseOp, a: &ValueEntity, b: &ValueEntity) -> Result<(), Error> {
+        let (_, size) = scalar_type(&a.get_type())?;
+        let dst = self.result(inst)?;
+        let lhs = self.operand(a)?;
+        let rhs = self.operand(b)?;
+        self.emit(Inst::MovSse { size, dst: dst.into(), src: lhs });
+        self.emit(Inst::SseAlu { op, size, dst: dst.into(), src: rhs });
+        Ok(())
+    }
+
+    /// Lowers a division whose result is left in `result`. Narrow operands
+    /// are extended to 32 bits, with their sign when `signed`.
+    fn lower_div(&mut self, inst: &Instruction, a: &ValueEntity, b: &ValueEntity, result: PReg, signed: bool) -> Result<(), Error> {
+        let (_, size) = scalar_type(&a.get_type())?;
+        let dst = self.result(inst)?;
+        let wide = size.max(Size::Dword);
+        let lhs = self.operand(a)?;
+        let rhs = self.reg(b, size)?;
+        let extend = |dst: Reg, src: Operand| match signed {
+            true => Inst::Movsx { dst_size: wide, src_size: size, dst, src },
+            false => Inst::Movzx { dst_size: wide, src_size: size, dst, src },
+        };
+        let rhs = if size < Size::Dword {
+            let extended = self.mf.new_vreg(RegClass::Int);
+            self.emit(extend(extended.into(), rhs.into()));
+            extended.into()
+        } else {
+            rhs
+        };
+        match lhs {
+            Operand::Reg(_) if size < Size::Dword => self.emit(extend(PReg::Rax.into(), lhs)),
+            // an immediate is already extended, but an unsigned one must lose its sign
+            Operand::Imm(imm) if !signed && size < Size::Dword => {
+                let imm = imm & ((1 << (size.bytes() * 8)) - 1);
+                self.emit(Inst::Mov { size: wide, dst: PReg::Rax.into(), src: Operand::Imm(imm) });
+            }
+            _ => self.emit(Inst::Mov { size: wide, dst: PReg::Rax.into(), src: lhs }),
+        }
+        if signed {
+            self.emit(Inst::SignExtendAcc { size: wide });
+        } else {
+            self.emit(Inst::Mov { size: Size::Dword, dst: PReg::Rdx.into(), src: Operand::Imm(0) });
+        }
+        self.emit(Inst::Div { signed, size: wide, src: rhs.into() });
+        self.emit(Inst::Mov { size, dst: dst.into(), src: result.into() });
+        Ok(())
+    }
+
+    fn lower_shift(&mut self, inst: &Instruction, op: ShiftOp, a: &ValueEntity, b: &ValueEntity) -> Result<(), Error> {
+        let (_, size) = scalar_type(&a.get_type())?;
+        let dst = self.result(inst)?;
+        let lhs = self.operand(a)?;
+        let amount = self.operand(b)?;
+        self.emit(Inst::Mov { size, dst: dst.into(), src: lhs });
+        match amount {
+            Operand::Imm(amount) => {
+                let amount = (amount as u64 % (size.bytes() * 8)) as u8;
+                self.emit(Inst::Shift { op, size, dst: dst.into(), amount: Some(amount) });
+            }
+            amount => {
+                self.emit(Inst::Mov { size: Size::Byte, dst: PReg::Rcx.into(), src: amount });
+                self.emit(Inst::Shift { op, size, dst: dst.into(), amount: None });
+            }
+        }
+        Ok(())
+    }
+
+    fn lower_compare(&mut self, inst: &Instruction, cond: Cond, a: &ValueEntity, b: &ValueEntity) -> Result<(), Error> {
+        let (_, size) = scalar_type(&a.get_type())?;
+        let dst = self.result(inst)?;
+        // a true boolean is -1 when signed, so signed orderings of booleans are reversed
+        let cond = match (a.get_type(), cond) {
+            (Type::Integer(1), Cond::L) => Cond::G,
+            (Type::Integer(1), Cond::Le) => Cond::Ge,
+            (Type::Integer(1), Cond::G) => Cond::L,
+            (Type::Integer(1), Cond::Ge) => Cond::Le,
+            _ => cond,
+        };
+        let lhs = self.reg(a, size)?;
+        let rhs = self.imm32_operand(b, size)?;
+        self.emit(Inst::Cmp { size, lhs: lhs.into(), rhs });
+        self.emit(Inst::Setcc { cond, dst: dst.into() });
+        Ok(())
+    }
+
+    /// Lowers `fcmp`. `ucomis` reports "unordered" as ZF=PF=CF=1, so the
+    /// orderings that must reject NaN compare the other way round and use
+    /// `a`/`ae`, and those that must accept it use `b`/`be`. Only equality
+    /// needs the parity flag as well, unless NaN is ruled out.
+    fn lower_float_compare(&mut self, inst: &Instruction, predicate: FloatPredicate, flags: FastMathFlags, a: &ValueEntity, b: &ValueEntity) -> Result<(), Error> {
+        let (_, size) = scalar_type(&a.get_type())?;
+        let dst = self.result(inst)?;
+        let lhs = self.operand(a)?;
+        let rhs = self.operand(b)?;
+        let (Operand::Reg(lhs), Operand::Reg(rhs)) = (lhs, rhs) else {
+            return Err(unsupported(format!("`{}` on operands that are not registers", inst)));
+        };
+        let predicate = match predicate {
+            FloatPredicate::Oeq if flags.nnan => FloatPredicate::Ueq,
+            FloatPredicate::Une if flags.nnan => FloatPredicate::One,
+            predicate => predicate,
+        };
+        let (swap, cond) = match predicate {
+            FloatPredicate::Ogt => (false, Cond::A),
+            FloatPredicate::Oge => (false, Cond::Ae),
+            FloatPredicate::Olt => (true, Cond::A),
+            FloatPredicate::Ole => (true, Cond::Ae),
+            FloatPredicate::Ugt => (true, Cond::B),
+            FloatPredicate::Uge => (true, Cond::Be),
+            FloatPredicate::Ult => (false, Cond::B),
+            FloatPredicate::Ule => (false, Cond::Be),
+            FloatPredicate::Oeq | FloatPredicate::Ueq => (false, Cond::E),
+            FloatPredicate::One | FloatPredicate::Une => (false, Cond::Ne),
+            FloatPredicate::Ord => (false, Cond::Np),
+            FloatPredicate::Uno => (false, Cond::P),
+        };
+        let (lhs, rhs) = if swap { (rhs, lhs) } else { (lhs, rhs) };
+        self.emit(Inst::Ucomi { size, lhs, rhs: rhs.into() });
+        self.emit(Inst::Setcc { cond, dst: dst.into() });
+        if matches!(predicate, FloatPredicate::Oeq | FloatPredicate::Une) {
+            let parity = self.mf.new_vreg(RegClass::Int);
+            if predicate == FloatPredicate::Oeq {
+                self.emit(Inst::Setcc { cond: Cond::Np, dst: parity.into() });
+                self.emit(Inst::Alu { op: AluOp::And, size: Size::Byte, dst: dst.into(), src: parity.into() });
+            } else {
+                self.emit(Inst::Setcc { cond: Cond::P, dst: parity.into() });
+                self.emit(Inst::Alu { op: AluOp::Or, size: Size::Byte, dst: dst.into(), src: parity.into() });
+            }
+        }
+        Ok(())
+    }
+
+    /// Lowers the conversion `opcode` of `a` to the type of `inst`. Integers
+    /// narrower than 32 bits are extended before they turn into floats, and
+    /// booleans are kept to 0 or 1.
+    fn lower_cast(&mut self, inst: &Instruction, opcode: &str, a: &ValueEntity) -> Result<(), Error> {
+        let (from_class, from_size) = scalar_type(&a.get_type())?;
+        let (to_class, to_size) = scalar_type(&inst.get_type())?;
+        let dst = self.result(inst)?;
+        let src = self.reg(a, from_size)?;
+        let is_bool = inst.get_type() == Type::Integer(1);
+        match opcode {
+            "trunc" | "zext" | "ptrtoint" | "inttoptr" if from_size >= to_size => {
+                self.emit(Inst::Mov { size: to_size, dst: dst.into(), src: src.into() });
+                if is_bool {
+                    self.emit(Inst::Alu { op: AluOp::And, size: Size::Byte, dst: dst.into(), src: Operand::Imm(1) });
+                }
+            }
+            "zext" | "ptrtoint" | "inttoptr" => self.emit(Inst::Movzx { dst_size: to_size, src_size: from_size, dst: dst.into(), src: src.into() }),
+            "sext" => self.sign_extend(a, to_size, dst, src),
+            "fptrunc" | "fpext" => self.emit(Inst::Cvts2s { size: to_size, dst: dst.into(), src: src.into() }),
+            "sitofp" | "uitofp" if from_size < Size::Dword => {
+                let wide = self.mf.new_vreg(RegClass::Int);
+                if opcode == "sitofp" {
+                    self.sign_extend(a, Size::Dword, wide, src);
+                } else {
+                    self.emit(Inst::Movzx { dst_size: Size::Dword, src_size: from_size, dst: wide.into(), src: src.into() });
+                }
+                self.emit(Inst::Cvtsi2s { int_size: Size::Dword, size: to_size, dst: dst.into(), src: wide.into() });
+            }
+            "sitofp" => self.emit(Inst::Cvtsi2s { int_size: from_size, size: to_size, dst: dst.into(), src: src.into() }),
+            "uitofp" if from_size == Size::Dword => {
+                let wide = self.mf.new_vreg(RegClass::Int);
+                self.emit(Inst::Movzx { dst_size: Size::Qword, src_size: Size::Dword, dst: wide.into(), src: src.into() });
+                self.emit(Inst::Cvtsi2s { int_size: Size::Qword, size: to_size, dst: dst.into(), src: wide.into() });
+            }
+            "uitofp" => self.lower_u64_to_float(to_size, dst, src),
+            "fptosi" => {
+                self.emit(Inst::Cvtts2si { int_size: to_size.max(Size::Dword), size: from_size, dst: dst.into(), src: src.into() });
+                if is_bool {
+                    self.emit(Inst::Alu { op: AluOp::And, size: Size::Byte, dst: dst.into(), src: Operand::Imm(1) });
+                }
+            }
+            "fptoui" if to_size < Size::Qword => {
+                // every value that fits is in range of the signed 64-bit conversion
+                self.emit(Inst::Cvtts2si { int_size: Size::Qword, size: from_size, dst: dst.into(), src: src.into() });
+                if is_bool {
+                    self.emit(Inst::Alu { op: AluOp::And, size: Size::Byte, dst: dst.into(), src: Operand::Imm(1) });
+                }
+            }
+            "fptoui" => self.lower_float_to_u64(from_size, dst, src),
+            // bitcasts
+            _ => match (from_class, to_class) {
+                (RegClass::Int, RegClass::Float) => self.emit(Inst::MovToXmm { size: to_size, dst: dst.into(), src: src.into() }),
+                (RegClass::Float, RegClass::Int) => self.emit(Inst::MovFromXmm { size: from_size, dst: dst.into(), src }),
+                (class, _) => self.copy(class, to_size, dst.into(), src.into()),
+            },
+        }
+        Ok(())
+    }
+
+    /// Sign-extends the integer `a`, held in `src`, into `dst` of `size`. A
+    /// boolean is 0 or 1, so it is negated rather than sign-extended.
+    fn sign_extend(&mut self, a: &ValueEntity, size: Size, dst: VReg, src: Reg) {
+        if a.get_type() == Type::Integer(1) {
+            self.emit(Inst::Movzx { dst_size: size, src_size: Size::Byte, dst: dst.into(), src: src.into() });
+            self.emit(Inst::Unary { op: UnaryOp::Neg, size, dst: dst.into() });
+        } else {
+            let (_, from_size) = scalar_type(&a.get_type()).unwrap();
+            self.emit(Inst::Movsx { dst_size: size, src_size: from_size, dst: dst.into(), src: src.into() });
+        }
+    }
+
+    /// Converts the unsigned 64-bit `src` to a float without branching. A
+    /// value with the top bit set is halved, keeping the lowest bit so that
+    /// it still rounds the same, converted as a signed one and doubled.
+    fn lower_u64_to_float(&mut self, size: Size, dst: VReg, src: Reg) {
+        let [half, low, mask, value, scale] = [(); 5].map(|_| self.mf.new_vreg(RegClass::Int));
+        let factor = self.mf.new_vreg(RegClass::Float);
+        self.emit(Inst::Mov { size: Size::Qword, dst: half.into(), src: src.into() });
+        self.emit(Inst::Shift { op: ShiftOp::Shr, size: Size::Qword, dst: half.into(), amount: Some(1) });
+        self.emit(Inst::Mov { size: Size::Qword, dst: low.into(), src: src.into() });
+        self.emit(Inst::Alu { op: AluOp::And, size: Size::Qword, dst: low.into(), src: Operand::Imm(1) });
+        self.emit(Inst::Alu { op: AluOp::Or, size: Size::Qword, dst: half.into(), src: low.into() });
+        // all ones when the top bit is set; picks the halved value and a factor of two
+        self.emit(Inst::Mov { size: Size::Qword, dst: mask.into(), src: src.into() });
+        self.emit(Inst::Shift { op: ShiftOp::Sar, size: Size::Qword, dst: mask.into(), amount: Some(63) });
+        self.emit(Inst::Mov { size: Size::Qword, dst: value.into(), src: src.into() });
+        self.emit(Inst::Alu { op: AluOp::Xor, size: Size::Qword, dst: value.into(), src: half.into() });
+        self.emit(Inst::Alu { op: AluOp::And, size: Size::Qword, dst: value.into(), src: mask.into() });
+        self.emit(Inst::Alu { op: AluOp::Xor, size: Size::Qword, dst: value.into(), src: src.into() });
+        self.emit(Inst::Cvtsi2s { int_size: Size::Qword, size, dst: dst.into(), src: value.into() });
+        self.emit(Inst::Mov { size: Size::Qword, dst: scale.into(), src: Operand::Imm(1) });
+        self.emit(Inst::Alu { op: AluOp::Sub, size: Size::Qword, dst: scale.into(), src: mask.into() });
+        self.emit(Inst::Cvtsi2s { int_size: Size::Qword, size, dst: factor.into(), src: scale.into() });
+        self.emit(Inst::SseAlu { op: SseOp::Mul, size, dst: dst.into(), src: factor.into() });
+    }
+
+    /// Converts the float `src` to an unsigned 64-bit integer without
+    /// branching. Values from 2^63 on overflow the signed conversion to
+    /// exactly its top bit, so the conversion of `src - 2^63` supplies the
+    /// other bits.
+    fn lower_float_to_u64(&mut self, size: Size, dst: VReg, src: Reg) {
+        let bits = if size == Size::Dword { 0x5f00_0000 } else { 0x43e0_0000_0000_0000 };
+        let [constant, high, mask] = [(); 3].map(|_| self.mf.new_vreg(RegClass::Int));
+        let [bias, reduced] = [(); 2].map(|_| self.mf.new_vreg(RegClass::Float));
+        self.emit(Inst::Cvtts2si { int_size: Size::Qword, size, dst: dst.into(), src: src.into() });
+        self.emit(Inst::Mov { size: Size::Qword, dst: constant.into(), src: Operand::Imm(bits) });
+        self.emit(Inst::MovToXmm { size, dst: bias.into(), src: constant.into() });
+        self.emit(Inst::MovSse { size, dst: reduced.into(), src: src.into() });
+        self.emit(Inst::SseAlu { op: SseOp::Sub, size, dst: reduced.into(), src: bias.into() });
+        self.emit(Inst::Cvtts2si { int_size: Size::Qword, size, dst: high.into(), src: reduced.into() });
+        self.emit(Inst::Mov { size: Size::Qword, dst: mask.into(), src: dst.into() });
+        self.emit(Inst::Shift { op: ShiftOp::Sar, size: Size::Qword, dst: mask.into(), amount: Some(63) });
+        self.emit(Inst::Alu { op: AluOp::And, size: Size::Qword, dst: high.into(), src: mask.into() });
+        self.emit(Inst::Alu { op: AluOp::Or, size: Size::Qword, dst: dst.into(), src: high.into() });
+    }
+
+    fn lower_call(&mut self, inst: &Instruction, callee: &ValueEntity, args: &[Box<ValueEntity>]) -> Result<(), Error> {
+        let (callee_type, is_var_arg) = match callee {
+            ValueEntity::Function(function) => (function.get_type(), function.is_var_arg()),
+            // the variadic flag is not part of the type, so indirect calls always set `al`
+            callee => (callee.get_type().get_pointer_element_type(), true),
+        };
+        let arg_types = args.iter().map(|arg| arg.get_type()).collect::<Vec<_>>();
+        let ret_type = callee_type.get_function_return_type();
+        let abi = classify_call(self.layout, &arg_types, &ret_type)?;
+
+        let mut operands = Vec::new();
+        for arg in args {
+            operands.push(self.operand(arg)?);
+        }
+        let target = match callee {
+            ValueEntity::Function(function) => CallTarget::Symbol(function.get_name()),
+            callee => CallTarget::Indirect(self.reg(callee, Size::Qword)?.into()),
+        };
+        let sret = if abi.ret == ReturnLocation::Memory {
+            let sret = self.mf.new_vreg(RegClass::Int);
+            self.aggregate_slot(sret, &ret_type);
+            Some(sret)
+        } else {
+            None
+        };
+
+        // stack arguments go to the bottom of the frame, which the prologue reserves
+        self.mf.reserve_outgoing_args(abi.stack_size);
+        for ((location, ty), src) in abi.args.iter().zip(&arg_types).zip(&operands) {
+            let ArgLocation::Stack { offset, size } = location else {
+                continue;
+            };
+            if is_aggregate(ty) {
+                let Operand::Reg(src) = src else { unreachable!() };
+                self.copy_bytes_to(Mem::base(PReg::Rsp, *offset as i32), *src, *size);
+            } else {
+                let (class, size) = scalar_type(ty)?;
+                let src = match src {
+                    Operand::Imm(imm) if i32::try_from(*imm).is_err() => {
+                        let vreg = self.mf.new_vreg(RegClass::Int);
+                        self.emit(Inst::Mov { size, dst: vreg.into(), src: Operand::Imm(*imm) });
+                        vreg.into()
+                    }
+                    src => src.clone(),
+                };
+                self.copy(class, size, Mem::base(PReg::Rsp, *offset as i32).into(), src);
+            }
+        }
+        // registers are written last, so nothing in between can clobber them
+        for ((location, ty), src) in abi.args.iter().zip(&arg_types).zip(operands) {
+            let ArgLocation::Regs(pieces) = location else {
+                continue;
+            };
+            if is_aggregate(ty) {
+                let Operand::Reg(src) = src else { unreachable!() };
+                for piece in pieces {
+                    self.load_piece(*piece, src);
+                }
+            } else {
+                self.move_to_arg_reg(ty, pieces[0].reg, src);
+            }
+        }
+        let mut arg_regs = abi.args.iter().flat_map(|location| match location {
+            ArgLocation::Regs(pieces) => pieces.iter().map(|piece| piece.reg).collect(),
+            _ => Vec::new(),
+        }).collect::<Vec<_>>();
+        if let Some(sret) = sret {
+            self.emit(Inst::Mov { size: Size::Qword, dst: PReg::Rdi.into(), src: sret.into() });
+            arg_regs.push(PReg::Rdi);
+        }
+        if is_var_arg {
+            self.emit(Inst::Mov { size: Size::Dword, dst: PReg::Rax.into(), src: Operand::Imm(abi.sse_regs as i64) });
+            arg_regs.push(PReg::Rax);
+        }
+        self.emit(Inst::Call { target, args: arg_regs });
+
+        match abi.ret {
+            ReturnLocation::Void => {}
+            ReturnLocation::Memory => {
+                let dst = self.result(inst)?;
+                self.emit(Inst::Mov { size: Size::Qword, dst: dst.into(), src: sret.unwrap().into() });
+            }
+            ReturnLocation::Regs(pieces) if is_aggregate(&ret_type) => {
+                let dst = self.result(inst)?;
+                self.aggregate_slot(dst, &ret_type);
+                for piece in pieces {
+                    self.store_piece(piece, dst.into());
+                }
+            }
+            ReturnLocation::Regs(pieces) => {
+                let (class, size) = scalar_type(&ret_type)?;
+                let dst = self.result(inst)?;
+                self.copy(class, size, dst.into(), pieces[0].reg.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves a scalar into an argument or return register, widening small integers to 32 bits.
+    fn move_to_arg_reg(&mut self, ty: &Type, reg: PReg, src: Operand) {
+        let Ok((class, size)) = scalar_type(ty) else {
+            return;
+        };
+        match (class, &src) {
+            (RegClass::Int, Operand::Reg(_)) if size < Size::Dword => {
+                let dst_size = Size::Dword;
+                if *ty == Type::Integer(1) {
+                    self.emit(Inst::Movzx { dst_size, src_size: size, dst: reg.into(), src });
+                } else {
+                    self.emit(Inst::Movsx { dst_size, src_size: size, dst: reg.into(), src });
+                }
+            }
+            (RegClass::Int, _) => self.emit(Inst::Mov { size: size.max(Size::Dword), dst: reg.into(), src }),
+            (RegClass::Float, _) => self.emit(Inst::MovSse { size, dst: reg.into(), src }),
+        }
+    }
+
+    /// Loads one eightbyte of the aggregate at `base` into its register.
+    fn load_piece(&mut self, piece: Piece, base: Reg) {
+        let src = Mem::base(base, piece.offset as i32).into();
+        if piece.reg.class() == RegClass::Int {
+            // aggregate slots are padded to eightbytes, so reading a whole one is safe
+            self.emit(Inst::Mov { size: Size::Qword, dst: piece.reg.into(), src });
+        } else {
+            let size = if piece.size <= 4 { Size::Dword } else { Size::Qword };
+            self.emit(Inst::MovSse { size, dst: piece.reg.into(), src });
+        }
+    }
+
+    /// Stores one eightbyte of an aggregate from its register to `base`.
+    fn store_piece(&mut self, piece: Piece, base: Reg) {
+        let dst = Mem::base(base, piece.offset as i32).into();
+        if piece.reg.class() == RegClass::Int {
+            self.emit(Inst::Mov { size: Size::Qword, dst, src: piece.reg.into() });
+        } else {
+            let size = if piece.size <= 4 { Size::Dword } else { Size::Qword };
+            self.emit(Inst::MovSse { size, dst, src: piece.reg.into() });
+        }
+    }
+
+    /// Returns the register class and width used to hold a value of type `ty`,
+    /// where aggregates are held by address.
+    fn register_type(&self, ty: &Type) -> Result<(RegClass, Size), Error> {
+        if is_aggregate(ty) {
+            Ok((RegClass::Int, Size::Qword))
+        } else {
+            scalar_type(ty)
+        }
+    }
+
+    /// Allocates a frame slot for an aggregate, padded to whole eightbytes, and puts its address in `dst`.
+    fn aggregate_slot(&mut self, dst: VReg, ty: &Type) {
+        let size = self.layout.size_of(ty).next_multiple_of(8);
+        let slot = self.mf.new_slot(size, self.layout.align_of(ty).max(8));
+        self.emit(Inst::Lea { dst: dst.into(), addr: Mem::slot(slot, 0) });
+    }
+
+    /// Copies `size` bytes from the address in `src` to the address in `dst`.
+    fn copy_bytes(&mut self, dst: Reg, src: Reg, size: u64) {
+        self.copy_bytes_to(Mem::base(dst, 0), src, size);
+    }
+
+    fn copy_bytes_to(&mut self, dst: Mem, src: Reg, size: u64) {
+        let mut offset = 0;
+        for chunk in [Size::Qword, Size::Dword, Size::Word, Size::Byte] {
+            while size - offset >= chunk.bytes() {
+                let temp = self.mf.new_vreg(RegClass::Int);
+                self.emit(Inst::Mov { size: chunk, dst: temp.into(), src: Mem::base(src, offset as i32).into() });
+                let mut to = dst.clone();
+                to.disp += offset as i32;
+                self.emit(Inst::Mov { size: chunk, dst: to.into(), src: temp.into() });
+                offset += chunk.bytes();
+            }
+        }
+    }
+
+    fn lower_branch_if(&mut self, block: &BasicBlock, cond: &ValueEntity, if_true: &str, if_false: &str) -> Result<(), Error> {
+        let cond = self.operand(cond)?;
+        let (taken, other) = match cond {
+            Operand::Imm(value) => {
+                let target = if value != 0 { if_true } else { if_false };
+                self.emit_phi_copies(block, target)?;
+                let target = self.label(target);
+                self.emit(Inst::Jmp { target });
+                return Ok(());
+            }
+            cond => {
+                self.emit(Inst::Test { size: Size::Byte, lhs: cond.clone(), rhs: cond });
+                (if_true, if_false)
+            }
+        };
+
+        if !self.has_phis(taken) {
+            let target = self.label(taken);
+            self.emit(Inst::Jcc { cond: Cond::Ne, target });
+        } else {
+            // the true edge needs its own copies, so it gets a block of its own
+            let edge = format!("{}.{}", self.label(&block.get_name()), self.mf.blocks.len());
+            self.emit(Inst::Jcc { cond: Cond::E, target: edge.clone() });
+            self.emit_phi_copies(block, taken)?;
+            let target = self.label(taken);
+            self.emit(Inst::Jmp { target });
+            self.start_block(edge, None);
+        }
+        self.emit_phi_copies(block, other)?;
+        let target = self.label(other);
+        self.emit(Inst::Jmp { target });
+        Ok(())
+    }
+
+    fn has_phis(&self, block: &str) -> bool {
+        self.func.get_block(block).is_some_and(|block| {
+            block.borrow().get_instructions().iter().any(|inst| matches!(inst, ValueEntity::Instruction(inst) if matches!(inst.instruction_type(), InstructionType::Phi(_))))
+        })
+    }
+
+    /// Writes the incoming values of the phis in `target` for the edge coming from `block`.
+    fn emit_phi_copies(&mut self, block: &BasicBlock, target: &str) -> Result<(), Error> {
+        let Some(target) = self.func.get_block(target).cloned() else {
+            return Err(unsupported(format!("branch to unknown block {}", target)));
+        };
+        let target = target.borrow();
+        for inst in target.get_instructions() {
+            let ValueEntity::Instruction(inst) = inst else {
+                continue;
+            };
+            let InstructionType::Phi(incoming) = inst.instruction_type() else {
+                continue;
+            };
+            let Some((value, _)) = incoming.iter().find(|(_, from)| from.get_name() == block.get_name()) else {
+                continue;
+            };
+            let (class, size) = self.register_type(&inst.get_type())?;
+            let temp = self.phi_temps[&inst.get_name()];
+            let src = self.operand(value)?;
+            self.copy(class, size, temp.into(), src);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use crate::emit::asm::regalloc::RegisterFile;
+use crate::emit::asm::x86_64::inst::{Inst, Mem, PReg, RegClass, Size};
+
+/// Integer registers handed out by the allocator, caller-saved ones first so
+/// that callee-saved registers are only used by values live across calls.
+/// `r10`, `r11` and `r15` are left free as spill scratch registers.
+const INT_ALLOCATABLE: [PReg; 11] = [
+    PReg::Rax, PReg::Rcx, PReg::Rdx, PReg::Rsi, PReg::Rdi, PReg::R8, PReg::R9,
+    PReg::Rbx, PReg::R12, PReg::R13, PReg::R14,
+];
+/// `xmm14` and `xmm15` are left free as spill scratch registers.
+const FLOAT_ALLOCATABLE: [PReg; 14] = [
+    PReg::Xmm0, PReg::Xmm1, PReg::Xmm2, PReg::Xmm3, PReg::Xmm4, PReg::Xmm5, PReg::Xmm6,
+    PReg::Xmm7, PReg::Xmm8, PReg::Xmm9, PReg::Xmm10, PReg::Xmm11, PReg::Xmm12, PReg::Xmm13,
+];
+/// Registers kept free to reload spilled values around a single instruction.
+/// A store through a folded address reads a base, an index and a value.
+const INT_SCRATCH: [PReg; 3] = [PReg::R10, PReg::R11, PReg::R15];
+const FLOAT_SCRATCH: [PReg; 2] = [PReg::Xmm14, PReg::Xmm15];
+/// Registers the caller reads after `ret`.
+const RETURN_REGS: [PReg; 4] = [PReg::Rax, PReg::Rdx, PReg::Xmm0, PReg::Xmm1];
+
+/// The registers of the System V AMD64 ABI.
+pub struct Registers;
+
+impl RegisterFile for Registers {
+    type Inst = Inst;
+
+    fn allocatable(class: RegClass) -> &'static [PReg] {
+        match class {
+            RegClass::Int => &INT_ALLOCATABLE,
+            RegClass::Float => &FLOAT_ALLOCATABLE,
+        }
+    }
+
+    fn scratch(class: RegClass) -> &'static [PReg] {
+        match class {
+            RegClass::Int => &INT_SCRATCH,
+            RegClass::Float => &FLOAT_SCRATCH,
+        }
+    }
+
+    fn return_regs() -> &'static [PReg] {
+        &RETURN_REGS
+    }
+
+    fn load(class: RegClass, dst: PReg, slot: u32) -> Inst {
+        match class {
+            RegClass::Int => Inst::Mov { size: Size::Qword, dst: dst.into(), src: Mem::slot(slot, 0).into() },
+            RegClass::Float => Inst::MovSse { size: Size::Qword, dst: dst.into(), src: Mem::slot(slot, 0).into() },
+        }
+    }
+
+    fn store(class: RegClass, src: PReg, slot: u32) -> Inst {
+        match class {
+            RegClass::Int => Inst::Mov { size: Size::Qword, dst: Mem::slot(slot, 0).into(), src: src.into() },
+            RegClass::Float => Inst::MovSse { size: Size::Qword, dst: Mem::slot(slot, 0).into(), src: src.into() },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emit::asm::machine::MachineInst;
+    use crate::emit::asm::regalloc::allocate;
+    use crate::emit::asm::x86_64::inst::{AluOp, CallTarget, MachineBlock, MachineFunction, Operand, Reg, VReg, CALLER_SAVED};
+
+    fn function() -> MachineFunction {
+        let mut mf = MachineFunction::new("f");
+        mf.blocks.push(MachineBlock::new("f".to_string(), None));
+        mf
+    }
+
+    fn mov(dst: impl Into<Operand>, src: impl Into<Operand>) -> Inst {
+        Inst::Mov { size: Size::Qword, dst: dst.into(), src: src.into() }
+    }
+
+    #[test]
+    fn coalesces_copies_into_the_return_register() {
+        let mut mf = function();
+        let value = mf.new_vreg(RegClass::Int);
+        mf.blocks[0].insts = vec![mov(value, Operand::Imm(1)), mov(PReg::Rax, value), Inst::Ret];
+        allocate::<Registers>(&mut mf);
+        assert_eq!(mf.blocks[0].insts, [mov(PReg::Rax, Operand::Imm(1)), Inst::Ret]);
+    }
+
+    #[test]
+    fn keeps_values_live_across_calls_in_callee_saved_registers() {
+        let mut mf = function();
+        let value = mf.new_vreg(RegClass::Int);
+        mf.blocks[0].insts = vec![
+            mov(value, Operand::Imm(1)),
+            Inst::Call { target: CallTarget::Symbol("g".to_string()), args: Vec::new() },
+            mov(PReg::Rax, value),
+            Inst::Ret,
+        ];
+        allocate::<Registers>(&mut mf);
+        let Inst::Mov { dst: Operand::Reg(Reg::Phys(preg)), .. } = mf.blocks[0].insts[0] else {
+            panic!("unexpected {:?}", mf.blocks[0].insts[0]);
+        };
+        assert!(!CALLER_SAVED.contains(&preg));
+        assert!(mf.slots.is_empty());
+    }
+
+    #[test]
+    fn spills_values_that_do_not_fit() {
+        let mut mf = function();
+        let values = (0..16).map(|_| mf.new_vreg(RegClass::Int)).collect::<Vec<_>>();
+        let mut insts = values.iter().enumerate().map(|(i, value)| mov(*value, Operand::Imm(i as i64))).collect::<Vec<_>>();
+        insts.extend(values.iter().map(|value| Inst::Alu { op: AluOp::Add, size: Size::Qword, dst: PReg::Rax.into(), src: (*value).into() }));
+        insts.push(Inst::Ret);
+        mf.blocks[0].insts = insts;
+        allocate::<Registers>(&mut mf);
+        assert!(!mf.slots.is_empty());
+
+        // spilled values are reloaded into the scratch registers
+        for inst in &mut mf.blocks[0].insts {
+            inst.visit_regs(&mut |reg, _| match reg {
+                Reg::Phys(preg) => assert!(INT_ALLOCATABLE.contains(preg) || INT_SCRATCH.contains(preg)),
+                Reg::Virt(vreg) => panic!("%v{} was not allocated", vreg.index),
+            });
+        }
+    }
+
+    #[test]
+    fn reloads_spilled_operands_into_distinct_scratch_registers() {
+        let mut mf = function();
+        let values = (0..6).map(|_| mf.new_vreg(RegClass::Int)).collect::<Vec<_>>();
+        let add = |dst: VReg, src: VReg| Inst::Alu { op: AluOp::Add, size: Size::Qword, dst: dst.into(), src: src.into() };
+        let mut insts = values.iter().enumerate().map(|(i, value)| mov(*value, Operand::Imm(i as i64))).collect::<Vec<_>>();
+        // six values live across a call only fit the four callee-saved
+        // registers left to the allocator, so the two that stay live the
+        // longest get spilled
+        insts.extend([
+            Inst::Call { target: CallTarget::Symbol("g".to_string()), args: Vec::new() },
+            add(values[4], values[5]),
+            add(values[0], values[1]),
+            add(values[0], values[4]),
+            mov(PReg::Rax, values[0]),
+            add(values[2], values[3]),
+            Inst::Ret,
+        ]);
+        mf.blocks[0].insts = insts;
+        allocate::<Registers>(&mut mf);
+        assert_eq!(mf.slots.len(), 2);
+
+        let insts = &mf.blocks[0].insts;
+        // outputs that are not inputs reuse the first scratch register
+        assert_eq!(insts[2..4], [mov(PReg::R10, Operand::Imm(2)), mov(Mem::slot(0, 0), PReg::R10)]);
+        assert_eq!(insts[4..6], [mov(PReg::R10, Operand::Imm(3)), mov(Mem::slot(1, 0), PReg::R10)]);
+        assert_eq!(insts[insts.len() - 5..], [
+            mov(PReg::R10, Mem::slot(0, 0)),
+            mov(PReg::R11, Mem::slot(1, 0)),
+            Inst::Alu { op: AluOp::Add, size: Size::Qword, dst: PReg::R10.into(), src: PReg::R11.into() },
+            mov(Mem::slot(0, 0), PReg::R10),
+            Inst::Ret,
+        ]);
+    }
+
+    #[test]
+    fn reloads_a_store_through_a_spilled_address_into_three_scratch_registers() {
+        let mut mf = function();
+        let values = (0..7).map(|_| mf.new_vreg(RegClass::Int)).collect::<Vec<_>>();
+        let add = |dst: VReg, src: VReg| Inst::Alu { op: AluOp::Add, size: Size::Qword, dst: dst.into(), src: src.into() };
+        let mut insts = values.iter().enumerate().map(|(i, value)| mov(*value, Operand::Imm(i as i64))).collect::<Vec<_>>();
+        // the base, index and value of the store live the longest, so they are the ones spilled
+        let address = Mem { index: Some((values[5].into(), 4)), ..Mem::base(values[4], 8) };
+        insts.extend([
+            Inst::Call { target: CallTarget::Symbol("g".to_string()), args: Vec::new() },
+            add(values[0], values[1]),
+            add(values[0], values[2]),
+            add(values[0], values[3]),
+            mov(PReg::Rax, values[0]),
+            mov(address, values[6]),
+            Inst::Ret,
+        ]);
+        mf.blocks[0].insts = insts;
+        allocate::<Registers>(&mut mf);
+        assert_eq!(mf.slots.len(), 3);
+
+        let insts = &mf.blocks[0].insts;
+        assert_eq!(insts[insts.len() - 5..], [
+            mov(PReg::R10, Mem::slot(2, 0)),
+            mov(PReg::R11, Mem::slot(0, 0)),
+            mov(PReg::R15, Mem::slot(1, 0)),
+            mov(Mem { index: Some((PReg::R15.into(), 4)), ..Mem::base(PReg::R11, 8) }, PReg::R10),
+            Inst::Ret,
+        ]);
+    }
+}
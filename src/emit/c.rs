@@ -0,0 +1,1058 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use crate::ir::builder::ctx::IRContext;
+use crate::ir::calling_conv::CallingConv;
+use crate::ir::linkage::Linkage;
+use crate::ir::values::basic_block::BasicBlock;
+use crate::ir::values::function::Function;
+use crate::ir::values::constant::{Constant, Scalar};
+use crate::ir::values::global::GlobalVariable;
+use crate::ir::values::instruction::{get_field_index, FloatPredicate, Instruction, InstructionType};
+use crate::ir::values::value::{Type, ValueEntity};
+use crate::targets::layout::DataLayout;
+use crate::emit::asm::unsupported;
+use crate::error::Error;
+
+/// The start of every file. What C99 can't express maps onto compiler
+/// extensions through these macros, and is dropped where there are none.
+const PRELUDE: &str = "\
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <math.h>
+
+#if defined(__GNUC__)
+#define SSLB_WEAK __attribute__((weak))
+#define SSLB_ALIGNED(n) __attribute__((aligned(n)))
+#define SSLB_SECTION(name) __attribute__((section(name)))
+#define SSLB_THREAD_LOCAL __thread
+#define SSLB_ALLOCA(size) __builtin_alloca(size)
+#define SSLB_TRAP() __builtin_trap()
+#elif defined(_MSC_VER)
+#include <malloc.h>
+#define SSLB_WEAK
+#define SSLB_ALIGNED(n) __declspec(align(n))
+#define SSLB_SECTION(name)
+#define SSLB_THREAD_LOCAL __declspec(thread)
+#define SSLB_ALLOCA(size) _alloca(size)
+#define SSLB_TRAP() abort()
+#else
+#include <alloca.h>
+#define SSLB_WEAK
+#define SSLB_ALIGNED(n)
+#define SSLB_SECTION(name)
+#define SSLB_THREAD_LOCAL _Thread_local
+#define SSLB_ALLOCA(size) alloca(size)
+#define SSLB_TRAP() abort()
+#endif
+
+#if defined(__GNUC__) && defined(__i386__)
+#define SSLB_STDCALL __attribute__((stdcall))
+#define SSLB_FASTCALL __attribute__((fastcall))
+#elif defined(_MSC_VER) && defined(_M_IX86)
+#define SSLB_STDCALL __stdcall
+#define SSLB_FASTCALL __fastcall
+#else
+#define SSLB_STDCALL
+#define SSLB_FASTCALL
+#endif
+";
+
+const KEYWORDS: &[&str] = &[
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else", "enum", "extern", "float", "for",
+    "goto", "if", "inline", "int", "long", "register", "restrict", "return", "short", "signed", "sizeof", "static", "struct",
+    "switch", "typedef", "union", "unsigned", "void", "volatile", "while", "_Bool", "_Complex", "_Imaginary",
+];
+
+/// Returns the C name of a function or global, which has to be the symbol
+/// name itself to link against other code.
+fn symbol(name: &str) -> Result<String, Error> {
+    let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid || KEYWORDS.contains(&name) {
+        return Err(unsupported(format!("symbol {} is not a C identifier", name)).into());
+    }
+    Ok(name.to_string())
+}
+
+/// Returns the C name of a value, label or buffer local to a function.
+/// Characters names can contain but C identifiers can't are escaped so that
+/// different names stay different.
+fn local(prefix: &str, name: &str) -> String {
+    let mut escaped = format!("{}_", prefix);
+    for c in name.trim_start_matches('%').chars() {
+        match c {
+            '_' => escaped.push_str("__"),
+            '.' => escaped.push_str("_d"),
+            '$' => escaped.push_str("_s"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn is_aggregate(ty: &Type) -> bool {
+    ty.is_struct() || ty.is_array()
+}
+
+/// Returns an integer literal for `value` as a value of type `ty`, wrapped to
+/// its width.
+fn int_literal(value: i64, ty: &Type) -> String {
+    match ty {
+        Type::Integer(1) => (value & 1).to_string(),
+        Type::Integer(8) => (value as i8).to_string(),
+        Type::Integer(16) => (value as i16).to_string(),
+        Type::Integer(64) if value == i64::MIN => "INT64_MIN".to_string(),
+        Type::Integer(64) => format!("INT64_C({})", value),
+        _ if value as i32 == i32::MIN => "INT32_MIN".to_string(),
+        _ => (value as i32).to_string(),
+    }
+}
+
+/// Returns the unsigned C type of the same width as the integer or pointer type `ty`.
+fn unsigned_name(ty: &Type) -> String {
+    match ty {
+        Type::Integer(1) => "_Bool".to_string(),
+        Type::Integer(bits) => format!("uint{}_t", bits),
+        _ => "uintptr_t".to_string(),
+    }
+}
+
+fn float_literal(value: f64, bits: usize) -> String {
+    let suffix = if bits == 32 { "f" } else { "" };
+    if value.is_nan() {
+        format!("({})NAN", if bits == 32 { "float" } else { "double" })
+    } else if value.is_infinite() {
+        format!("{}({})INFINITY", if value < 0.0 { "-" } else { "" }, if bits == 32 { "float" } else { "double" })
+    } else if bits == 32 {
+        format!("{:?}{}", value as f32, suffix)
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+/// The C names of IR types, and the typedefs that define them.
+#[derive(Default)]
+struct TypeNames {
+    names: Vec<(Type, String)>,
+    typedefs: Vec<String>,
+}
+
+impl TypeNames {
+    fn name(&mut self, ty: &Type) -> Result<String, Error> {
+        Ok(match ty {
+            Type::Integer(1) => "_Bool".to_string(),
+            Type::Integer(bits @ (8 | 16 | 32 | 64)) => format!("int{}_t", bits),
+            Type::Float(32) => "float".to_string(),
+            Type::Float(64) => "double".to_string(),
+            Type::Void => "void".to_string(),
+            // function values are already pointers to the function
+            Type::Pointer(inner) if inner.is_function() => self.name(inner)?,
+            Type::Pointer(inner) => format!("{}*", self.name(inner)?),
+            Type::FunctionType(_, _) | Type::Array(_, _) | Type::Struct(_) => self.typedef(ty)?,
+            ty => return Err(unsupported(format!("values of type {} in C", ty)).into()),
+        })
+    }
+
+    /// Returns the name of the typedef for a function, array or struct type,
+    /// defining it first if it is new. Arrays are wrapped in a struct, so that
+    /// they can be copied by assignment like the other values.
+    fn typedef(&mut self, ty: &Type) -> Result<String, Error> {
+        if let Some((_, name)) = self.names.iter().find(|(known, _)| known == ty) {
+            return Ok(name.clone());
+        }
+        let (name, definition) = match ty {
+            Type::FunctionType(args, ret) => {
+                let ret = self.name(ret)?;
+                let args = args.iter().map(|arg| self.name(arg)).collect::<Result<Vec<_>, _>>()?;
+                let args = if args.is_empty() { "void".to_string() } else { args.join(", ") };
+                let name = format!("sslb_fn{}", self.names.len());
+                let definition = format!("typedef {} (*{})({});", ret, name, args);
+                (name, definition)
+            }
+            Type::Array(len, element) => {
+                if *len == 0 {
+                    return Err(unsupported(format!("empty array type {} in C", ty)).into());
+                }
+                let element = self.name(element)?;
+                let name = format!("sslb_array{}", self.names.len());
+                (name.clone(), format!("typedef struct {{ {} e[{}]; }} {};", element, len, name))
+            }
+            Type::Struct(fields) => {
+                if fields.is_empty() {
+                    return Err(unsupported(format!("empty struct type {} in C", ty)).into());
+                }
+                let mut members = String::new();
+                for (i, field) in fields.iter().enumerate() {
+                    members.push_str(&format!("{} f{}; ", self.name(field)?, i));
+                }
+                let name = format!("sslb_struct{}", self.names.len());
+                (name.clone(), format!("typedef struct {{ {}}} {};", members, name))
+            }
+            _ => unreachable!(),
+        };
+        self.names.push((ty.clone(), name.clone()));
+        self.typedefs.push(definition);
+        Ok(name)
+    }
+}
+
+/// Writes a module as C99 source. Each function becomes a C function whose
+/// blocks are labels, phis are assigned on the edges into their block, and
+/// integer arithmetic wraps like it does in the other backends, so the
+/// output can serve as a reference for them.
+pub struct CEmitter {
+    ctx: IRContext,
+    types: TypeNames,
+    /// The names of the functions of the module.
+    functions: HashSet<String>,
+    /// The constant globals of the module, whose address needs a cast to drop the `const`.
+    constants: HashMap<String, Type>,
+}
+
+impl CEmitter {
+    pub fn new(ctx: IRContext) -> Self {
+        Self {
+            ctx,
+            types: TypeNames::default(),
+            functions: HashSet::new(),
+            constants: HashMap::new(),
+        }
+    }
+
+    pub fn emit_module(&mut self, file: &mut impl Write) -> Result<(), Error> {
+        let ctx = self.ctx.clone();
+        let module = ctx.get_module();
+        let functions = module.get_functions().iter().map(|f| f.borrow().clone()).collect::<Vec<_>>();
+        let globals = module.get_global_variables().iter().map(|g| g.borrow().clone()).collect::<Vec<_>>();
+        self.functions = functions.iter().map(|f| f.get_name()).collect();
+        self.constants = globals.iter().filter(|g| g.is_constant()).map(|g| (g.get_name(), g.get_type())).collect();
+
+        // everything is declared before it is defined, so the order of the module doesn't matter
+        let mut declarations = Vec::new();
+        for function in &functions {
+            declarations.push(format!("{};", self.function_header(function)?));
+        }
+        for global in &globals {
+            declarations.push(format!("{};", self.global_declaration(global, true)?));
+        }
+        let mut definitions = Vec::new();
+        for global in globals.iter().filter(|g| !g.is_external()) {
+            definitions.push(self.global_definition(global)?);
+        }
+        let mut bodies = Vec::new();
+        for function in functions.iter().filter(|f| !f.is_external() && !f.get_blocks().is_empty()) {
+            bodies.push(self.function_definition(module.data_layout(), function)?);
+        }
+
+        writeln!(file, "/* Generated from module {}. */", module.name())?;
+        write!(file, "{}", PRELUDE)?;
+        for section in [&self.types.typedefs, &declarations, &definitions] {
+            if section.is_empty() {
+                continue;
+            }
+            writeln!(file)?;
+            for line in section {
+                writeln!(file, "{}", line)?;
+            }
+        }
+        for body in bodies {
+            writeln!(file)?;
+            writeln!(file, "{}", body)?;
+        }
+        Ok(())
+    }
+
+    fn function_header(&mut self, function: &Function) -> Result<String, Error> {
+        let name = symbol(&function.get_name())?;
+        let is_declaration = function.is_external() || function.get_blocks().is_empty();
+        let storage = match function.get_linkage() {
+            Linkage::PrivateLinkage if !is_declaration => "static ",
+            Linkage::ExternalWeakLinkage | Linkage::WeakLinkage | Linkage::LinkonceLinkage => "SSLB_WEAK ",
+            Linkage::AppendingLinkage => return Err(unsupported(format!("appending linkage on {}", name)).into()),
+            _ => "",
+        };
+        let conv = match function.get_calling_conv() {
+            CallingConv::C => "",
+            CallingConv::X86Stdcall => "SSLB_STDCALL ",
+            CallingConv::X86Fastcall => "SSLB_FASTCALL ",
+        };
+        let ret = self.types.name(&function.get_function_return_type())?;
+        let mut params = Vec::new();
+        for param in function.get_params() {
+            params.push(format!("{} {}", self.types.name(&param.get_type())?, local("v", &param.get_name())));
+        }
+        let params = match (params.is_empty(), function.is_var_arg()) {
+            (true, false) => "void".to_string(),
+            // C99 needs a named parameter before `...`, so these stay unprototyped
+            (true, true) => String::new(),
+            (false, false) => params.join(", "),
+            (false, true) => format!("{}, ...", params.join(", ")),
+        };
+        Ok(format!("{}{} {}{}({})", storage, ret, conv, name, params))
+    }
+
+    /// Returns the declaration of a global, without its initializer. A
+    /// `forward` declaration of a global defined in the module does not define
+    /// it, except for private ones, which C can only declare tentatively.
+    fn global_declaration(&mut self, global: &GlobalVariable, forward: bool) -> Result<String, Error> {
+        let name = symbol(&global.get_name())?;
+        let mut declaration = String::new();
+        match global.get_linkage() {
+            Linkage::PrivateLinkage if !global.is_external() => declaration.push_str("static "),
+            Linkage::AppendingLinkage => return Err(unsupported(format!("appending linkage on {}", name)).into()),
+            _ if forward || global.is_external() => declaration.push_str("extern "),
+            _ => {}
+        }
+        if global.is_thread_local() {
+            declaration.push_str("SSLB_THREAD_LOCAL ");
+        }
+        if matches!(global.get_linkage(), Linkage::ExternalWeakLinkage | Linkage::WeakLinkage | Linkage::LinkonceLinkage) {
+            declaration.push_str("SSLB_WEAK ");
+        }
+        if let Some(align) = global.get_alignment() {
+            declaration.push_str(&format!("SSLB_ALIGNED({}) ", align));
+        }
+        if let (Some(section), false) = (global.get_section(), forward) {
+            declaration.push_str(&format!("SSLB_SECTION({:?}) ", section));
+        }
+        if global.is_constant() {
+            declaration.push_str("const ");
+        }
+        declaration.push_str(&format!("{} {}", self.types.name(global.get_value_type())?, name));
+        Ok(declaration)
+    }
+
+    fn global_definition(&mut self, global: &GlobalVariable) -> Result<String, Error> {
+        let declaration = self.global_declaration(global, false)?;
+        // common symbols are C's tentative definitions
+        if *global.get_linkage() == Linkage::CommonLinkage {
+            crate::emit::asm::data::check_common(global)?;
+            return Ok(format!("{};", declaration));
+        }
+        let initializer = match global.get_initializer() {
+            Some(initializer) => self.initializer(global.get_value_type(), initializer)?,
+            None => self.initializer(global.get_value_type(), &Constant::Zero(global.get_value_type().clone()))?,
+        };
+        Ok(format!("{} = {};", declaration, initializer))
+    }
+
+    /// Returns the C initializer for a constant of type `ty`: braces for
+    /// structs and arrays, and a constant expression for the rest.
+    fn initializer(&mut self, ty: &Type, constant: &Constant) -> Result<String, Error> {
+        Ok(match (ty, constant) {
+            (ty, Constant::Zero(_) | Constant::Undef(_) | Constant::Poison(_)) if is_aggregate(ty) => "{ 0 }".to_string(),
+            (Type::Array(_, element), Constant::Bytes(bytes)) => {
+                let bytes = bytes.iter().map(|byte| int_literal(*byte as i64, element)).collect::<Vec<_>>();
+                format!("{{ {{ {} }} }}", bytes.join(", "))
+            }
+            (Type::Array(_, element), Constant::Array(_, elements)) => {
+                let elements = elements.iter().map(|init| self.initializer(element, init)).collect::<Result<Vec<_>, _>>()?;
+                format!("{{ {{ {} }} }}", elements.join(", "))
+            }
+            (Type::Struct(fields), Constant::Struct(inits)) if fields.len() == inits.len() => {
+                let fields = fields.iter().zip(inits).map(|(field, init)| self.initializer(field, init)).collect::<Result<Vec<_>, _>>()?;
+                format!("{{ {} }}", fields.join(", "))
+            }
+            (ty, constant) if !is_aggregate(ty) => self.scalar(ty, constant)?,
+            _ => return Err(unsupported(format!("initializer {} for a value of type {}", constant, ty)).into()),
+        })
+    }
+
+    /// Returns the C constant expression for a scalar constant of type `ty`.
+    fn scalar(&mut self, ty: &Type, constant: &Constant) -> Result<String, Error> {
+        Ok(match (ty, constant.fold()) {
+            (Type::Pointer(_), Some(Scalar::Int(value))) => format!("({})(uintptr_t){}", self.types.name(ty)?, int_literal(value.to_i64(), &Type::Integer(64))),
+            (Type::Integer(_), Some(Scalar::Int(value))) if value.bits() <= 64 => int_literal(value.to_i64(), ty),
+            (Type::Float(bits @ (32 | 64)), Some(Scalar::Float(_, value))) => float_literal(f64::from_bits(value), *bits),
+            (Type::Pointer(_) | Type::Integer(_), Some(Scalar::Address(name, 0))) => format!("({}){}", self.types.name(ty)?, self.address(&name)?),
+            (Type::Pointer(_) | Type::Integer(_), Some(Scalar::Address(name, offset))) => {
+                format!("({})((char *){} + {})", self.types.name(ty)?, self.address(&name)?, int_literal(offset, &Type::Integer(64)))
+            }
+            _ => return Err(unsupported(format!("constant {} of type {}", constant, ty)).into()),
+        })
+    }
+
+    /// Returns the expression for the address of a function or global.
+    fn address(&mut self, name: &str) -> Result<String, Error> {
+        let c_name = symbol(name)?;
+        if self.functions.contains(name) {
+            return Ok(c_name);
+        }
+        match self.constants.get(name).cloned() {
+            Some(ty) => Ok(format!("({})&{}", self.types.name(&ty)?, c_name)),
+            None => Ok(format!("&{}", c_name)),
+        }
+    }
+
+    fn function_definition(&mut self, layout: &DataLayout, function: &Function) -> Result<String, Error> {
+        let mut writer = FunctionWriter::new(self, layout, function);
+        writer.write()?;
+        let FunctionWriter { locals, body, .. } = writer;
+        let mut definition = format!("{} {{\n", self.function_header(function)?);
+        for line in locals.iter().chain(&body) {
+            definition.push_str(line);
+            definition.push('\n');
+        }
+        definition.push('}');
+        Ok(definition)
+    }
+}
+
+/// Writes the body of one function.
+struct FunctionWriter<'a> {
+    emitter: &'a mut CEmitter,
+    layout: &'a DataLayout,
+    func: &'a Function,
+    /// The declarations of the locals, which all come first so that no goto jumps past one.
+    locals: Vec<String>,
+    body: Vec<String>,
+}
+
+impl<'a> FunctionWriter<'a> {
+    fn new(emitter: &'a mut CEmitter, layout: &'a DataLayout, func: &'a Function) -> Self {
+        Self { emitter, layout, func, locals: Vec::new(), body: Vec::new() }
+    }
+
+    fn type_name(&mut self, ty: &Type) -> Result<String, Error> {
+        self.emitter.types.name(ty)
+    }
+
+    fn emit(&mut self, line: String) {
+        self.body.push(format!("    {}", line));
+    }
+
+    fn write(&mut self) -> Result<(), Error> {
+        let blocks = self.func.get_blocks().clone();
+        let mut targets = HashSet::new();
+        for block in &blocks {
+            for inst in block.borrow().get_instructions() {
+                let ValueEntity::Instruction(inst) = inst else {
+                    continue;
+                };
+                targets.extend(inst.get_successors());
+                if inst.get_type().is_void() {
+                    continue;
+                }
+                let ty = self.type_name(&inst.get_type())?;
+                self.locals.push(format!("    {} {};", ty, local("v", &inst.get_name())));
+                // phis are assigned through a copy, as all of a block's phis take their values at once
+                if let InstructionType::Phi(_) = inst.instruction_type() {
+                    self.locals.push(format!("    {} {};", ty, local("p", &inst.get_name())));
+                }
+            }
+        }
+
+        for block in &blocks {
+            let block = block.borrow();
+            if targets.contains(&block.get_name()) {
+                self.body.push(format!("{}:;", local("b", &block.get_name())));
+            }
+            for inst in block.get_instructions() {
+                if let ValueEntity::Instruction(inst) = inst {
+                    self.write_instruction(&block, inst)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the expression for `value`, used as a value of type `ty`.
+    fn value(&mut self, value: &ValueEntity, ty: &Type) -> Result<String, Error> {
+        match value {
+            ValueEntity::Instruction(inst) => Ok(local("v", &inst.get_name())),
+            ValueEntity::Argument(argument) => Ok(local("v", &argument.get_name())),
+            ValueEntity::Function(function) => self.emitter.address(&function.get_name()),
+            ValueEntity::GlobalVariable(global) => self.emitter.address(&global.get_name()),
+            // structs and arrays become compound literals
+            ValueEntity::Constant(constant) if is_aggregate(ty) => Ok(format!("({}){}", self.type_name(ty)?, self.emitter.initializer(ty, constant)?)),
+            ValueEntity::Constant(constant) => self.emitter.scalar(ty, constant),
+            ValueEntity::BasicBlock(block) => Err(unsupported(format!("basic block {} used as a value", block.get_name())).into()),
+        }
+    }
+
+    /// Returns the expression for an address index, which is read as signed.
+    fn index(&mut self, index: &ValueEntity) -> Result<String, Error> {
+        let ty = index.get_type();
+        let x = self.value(index, &ty)?;
+        // a true i1 steps back one element
+        Ok(if ty == Type::Integer(1) { format!("-(int32_t){}", x) } else { x })
+    }
+
+    /// Returns the expression for a binary operation. Signed overflow is
+    /// undefined in C, so additions, subtractions, multiplications and left
+    /// shifts that could overflow `int` happen on unsigned values, and
+    /// conversions back wrap. Shift amounts are masked like x86 does.
+    fn binary(&mut self, inst: &Instruction, a: &ValueEntity, b: &ValueEntity) -> Result<String, Error> {
+        let ty = a.get_type();
+        let (x, y) = (self.value(a, &ty)?, self.value(b, &ty)?);
+        let kind = inst.instruction_type();
+        let op = match kind {
+            InstructionType::Add(..) => "+",
+            InstructionType::Sub(..) => "-",
+            InstructionType::Mul(..) => "*",
+            InstructionType::SDiv(..) | InstructionType::UDiv(..) => "/",
+            InstructionType::SRem(..) | InstructionType::URem(..) => "%",
+            InstructionType::Shl(..) => "<<",
+            InstructionType::AShr(..) | InstructionType::LShr(..) => ">>",
+            InstructionType::And(..) => "&",
+            InstructionType::Or(..) => "|",
+            InstructionType::Xor(..) => "^",
+            InstructionType::Eq(..) => "==",
+            InstructionType::Ne(..) => "!=",
+            InstructionType::SLt(..) | InstructionType::ULt(..) => "<",
+            InstructionType::SLe(..) | InstructionType::ULe(..) => "<=",
+            InstructionType::SGt(..) | InstructionType::UGt(..) => ">",
+            InstructionType::SGe(..) | InstructionType::UGe(..) => ">=",
+            _ => unreachable!(),
+        };
+        match kind {
+            InstructionType::Eq(..) | InstructionType::Ne(..) => return Ok(format!("{} {} {}", x, op, y)),
+            // a true boolean is -1 when signed, so signed orderings of booleans are reversed
+            InstructionType::SLt(..) | InstructionType::SLe(..) | InstructionType::SGt(..) | InstructionType::SGe(..) => {
+                return Ok(if ty == Type::Integer(1) { format!("{} {} {}", y, op, x) } else { format!("{} {} {}", x, op, y) });
+            }
+            InstructionType::ULt(..) | InstructionType::ULe(..) | InstructionType::UGt(..) | InstructionType::UGe(..) => {
+                let unsigned = unsigned_name(&ty);
+                return Ok(format!("({1}){0} {2} ({1}){3}", x, unsigned, op, y));
+            }
+            _ => {}
+        }
+        Ok(match (&ty, kind) {
+            (Type::Integer(bits @ (32 | 64)), InstructionType::Add(..) | InstructionType::Sub(..) | InstructionType::Mul(..)) => {
+                format!("(int{0}_t)((uint{0}_t){1} {2} (uint{0}_t){3})", bits, x, op, y)
+            }
+            (Type::Integer(bits @ (32 | 64)), InstructionType::Shl(..)) => format!("(int{0}_t)((uint{0}_t){1} << ({2} & {3}))", bits, x, y, bits - 1),
+            (Type::Integer(bits @ (32 | 64)), InstructionType::AShr(..)) => format!("{} >> ({} & {})", x, y, bits - 1),
+            (Type::Integer(bits @ (32 | 64)), InstructionType::LShr(..)) => format!("(int{0}_t)((uint{0}_t){1} >> ({2} & {3}))", bits, x, y, bits - 1),
+            (Type::Integer(bits), InstructionType::UDiv(..) | InstructionType::URem(..)) => {
+                format!("(int{0}_t)((uint{0}_t){1} {2} (uint{0}_t){3})", (*bits).max(8), x, op, y)
+            }
+            // narrower integers are promoted to int, where nothing overflows
+            (Type::Integer(1), InstructionType::Add(..) | InstructionType::Sub(..) | InstructionType::Mul(..)) => format!("({} {} {}) & 1", x, op, y),
+            (Type::Integer(1), InstructionType::Shl(..)) => format!("((uint32_t){} << ({} & 31)) & 1", x, y),
+            (Type::Integer(_), InstructionType::Shl(..)) => format!("(uint32_t){} << ({} & 31)", x, y),
+            (Type::Integer(_), InstructionType::AShr(..)) => format!("{} >> ({} & 31)", x, y),
+            (Type::Integer(_), InstructionType::LShr(..)) => format!("(uint32_t)({}){} >> ({} & 31)", unsigned_name(&ty), x, y),
+            (Type::Integer(_), _) => format!("{} {} {}", x, op, y),
+            _ => return Err(unsupported(format!("`{}` on values of type {}", inst, ty)).into()),
+        })
+    }
+
+    /// Returns the expression for a float operation. Nothing is reordered or
+    /// contracted, so the fast-math flags are left out.
+    fn float_binary(&mut self, inst: &Instruction, a: &ValueEntity, b: &ValueEntity) -> Result<String, Error> {
+        let ty = a.get_type();
+        let (x, y) = (self.value(a, &ty)?, self.value(b, &ty)?);
+        Ok(match (&ty, inst.instruction_type()) {
+            (Type::Float(_), InstructionType::FAdd(..)) => format!("{} + {}", x, y),
+            (Type::Float(_), InstructionType::FSub(..)) => format!("{} - {}", x, y),
+            (Type::Float(_), InstructionType::FMul(..)) => format!("{} * {}", x, y),
+            (Type::Float(_), InstructionType::FDiv(..)) => format!("{} / {}", x, y),
+            (Type::Float(bits), InstructionType::FRem(..)) => format!("{}({}, {})", if *bits == 32 { "fmodf" } else { "fmod" }, x, y),
+            // the comparison macros of math.h are false for NaN without raising exceptions
+            (Type::Float(_), InstructionType::FCmp(predicate, ..)) => {
+                let ordered = if predicate.is_ordered() { *predicate } else { predicate.inverse() };
+                let expr = match ordered {
+                    FloatPredicate::Oeq => format!("{} == {}", x, y),
+                    FloatPredicate::Ogt => format!("isgreater({}, {})", x, y),
+                    FloatPredicate::Oge => format!("isgreaterequal({}, {})", x, y),
+                    FloatPredicate::Olt => format!("isless({}, {})", x, y),
+                    FloatPredicate::Ole => format!("islessequal({}, {})", x, y),
+                    FloatPredicate::One => format!("islessgreater({}, {})", x, y),
+                    _ => format!("!isunordered({}, {})", x, y),
+                };
+                if predicate.is_ordered() { expr } else { format!("!({})", expr) }
+            }
+            _ => return Err(unsupported(format!("`{}` on values of type {}", inst, ty)).into()),
+        })
+    }
+
+    /// Assigns the incoming values from `block` to the phis of `target`.
+    fn write_phi_copies(&mut self, block: &BasicBlock, target: &BasicBlock) -> Result<(), Error> {
+        for inst in target.get_instructions() {
+            let ValueEntity::Instruction(inst) = inst else {
+                continue;
+            };
+            let InstructionType::Phi(incoming) = inst.instruction_type() else {
+                continue;
+            };
+            let Some((value, _)) = incoming.iter().find(|(_, from)| from.get_name() == block.get_name()) else {
+                continue;
+            };
+            let value = self.value(value, &inst.get_type())?;
+            self.emit(format!("{} = {};", local("p", &inst.get_name()), value));
+        }
+        Ok(())
+    }
+
+    fn block(&self, name: &str) -> Result<BasicBlock, Error> {
+        let block = self.func.get_blocks().iter().find(|block| block.borrow().get_name() == name)
+            .ok_or_else(|| unsupported(format!("branch to unknown block {}", name)))?;
+        Ok(block.borrow().clone())
+    }
+
+    fn write_branch(&mut self, block: &BasicBlock, target: &str) -> Result<(), Error> {
+        let target = self.block(target)?;
+        self.write_phi_copies(block, &target)?;
+        self.emit(format!("goto {};", local("b", &target.get_name())));
+        Ok(())
+    }
+
+    fn write_instruction(&mut self, block: &BasicBlock, inst: &Instruction) -> Result<(), Error> {
+        let dst = local("v", &inst.get_name());
+        match inst.instruction_type() {
+            InstructionType::Add(a, b) | InstructionType::Sub(a, b) | InstructionType::Mul(a, b) | InstructionType::SDiv(a, b)
+            | InstructionType::UDiv(a, b) | InstructionType::SRem(a, b) | InstructionType::URem(a, b) | InstructionType::Shl(a, b)
+            | InstructionType::AShr(a, b) | InstructionType::LShr(a, b) | InstructionType::And(a, b) | InstructionType::Or(a, b)
+            | InstructionType::Xor(a, b) | InstructionType::Eq(a, b) | InstructionType::Ne(a, b) | InstructionType::SLt(a, b)
+            | InstructionType::SLe(a, b) | InstructionType::SGt(a, b) | InstructionType::SGe(a, b) | InstructionType::ULt(a, b)
+            | InstructionType::ULe(a, b) | InstructionType::UGt(a, b) | InstructionType::UGe(a, b) => {
+                let expr = self.binary(inst, a, b)?;
+                self.emit(format!("{} = {};", dst, expr));
+            }
+            InstructionType::Neg(a) => {
+                let ty = a.get_type();
+                let x = self.value(a, &ty)?;
+                let expr = match ty {
+                    Type::Integer(bits @ (32 | 64)) => format!("(int{0}_t)(0 - (uint{0}_t){1})", bits, x),
+                    _ => format!("-{}", x),
+                };
+                self.emit(format!("{} = {};", dst, expr));
+            }
+            InstructionType::FAdd(a, b, _) | InstructionType::FSub(a, b, _) | InstructionType::FMul(a, b, _)
+            | InstructionType::FDiv(a, b, _) | InstructionType::FRem(a, b, _) | InstructionType::FCmp(_, a, b, _) => {
+                let expr = self.float_binary(inst, a, b)?;
+                self.emit(format!("{} = {};", dst, expr));
+            }
+            InstructionType::FNeg(a, _) => {
+                let x = self.value(a, &a.get_type())?;
+                self.emit(format!("{} = -({});", dst, x));
+            }
+            InstructionType::Not(a) => {
+                let ty = a.get_type();
+                let x = self.value(a, &ty)?;
+                // a _Bool is promoted to int, where ~ would set the bits above it
+                let op = if ty == Type::Integer(1) { "!" } else { "~" };
+                self.emit(format!("{} = {}{};", dst, op, x));
+            }
+            InstructionType::Trunc(a) | InstructionType::ZExt(a) | InstructionType::SExt(a) | InstructionType::FPTrunc(a)
+            | InstructionType::FPExt(a) | InstructionType::FPToSI(a) | InstructionType::FPToUI(a) | InstructionType::SIToFP(a)
+            | InstructionType::UIToFP(a) | InstructionType::PtrToInt(a) | InstructionType::IntToPtr(a) | InstructionType::Bitcast(a) => {
+                let (opcode, _) = inst.instruction_type().as_cast().unwrap();
+                let expr = self.cast(opcode, a, &inst.get_type())?;
+                self.emit(format!("{} = {};", dst, expr));
+            }
+            InstructionType::Alloca(ty, count, align) => self.write_alloca(inst, ty, count.as_deref(), *align)?,
+            InstructionType::GetElementPtr(ptr, indices) => {
+                // arrays are wrapped in a struct, whose member is `e`
+                let mut ty = ptr.get_type().get_pointer_element_type();
+                let mut place = self.value(ptr, &ptr.get_type())?;
+                for (position, index) in indices.iter().enumerate() {
+                    ty = match ty {
+                        _ if position == 0 => {
+                            place = format!("({})[{}]", place, self.index(index)?);
+                            ty
+                        }
+                        Type::Struct(fields) => {
+                            let field = get_field_index(index).filter(|field| *field < fields.len())
+                                .ok_or_else(|| unsupported(format!("`{}` selects a field by {}", inst, index.get_as_ref())))?;
+                            place = format!("{}.f{}", place, field);
+                            fields[field].clone()
+                        }
+                        Type::Array(_, element) => {
+                            place = format!("{}.e[{}]", place, self.index(index)?);
+                            *element
+                        }
+                        ty => return Err(unsupported(format!("`{}` indexes into a value of type {}", inst, ty)).into()),
+                    };
+                }
+                self.emit(format!("{} = &{};", dst, place));
+            }
+            InstructionType::Load(ptr) => {
+                let ptr = self.value(ptr, &ptr.get_type())?;
+                self.emit(format!("{} = *{};", dst, ptr));
+            }
+            InstructionType::Store(ptr, value) => {
+                let ty = ptr.get_type().get_pointer_element_type();
+                let ptr = self.value(ptr, &ptr.get_type())?;
+                let value = self.value(value, &ty)?;
+                self.emit(format!("*{} = {};", ptr, value));
+            }
+            InstructionType::Call(callee, args) => {
+                let callee_type = match callee.get_type() {
+                    ty if ty.is_function_type() => ty,
+                    ty => ty.get_pointer_element_type(),
+                };
+                let params = callee_type.get_function_argument_types().clone();
+                let mut values = Vec::new();
+                for (i, arg) in args.iter().enumerate() {
+                    let ty = params.get(i).cloned().unwrap_or_else(|| arg.get_type());
+                    values.push(self.value(arg, &ty)?);
+                }
+                let callee = self.value(callee, &callee.get_type())?;
+                let call = format!("{}({})", callee, values.join(", "));
+                if inst.get_type().is_void() {
+                    self.emit(format!("{};", call));
+                } else {
+                    self.emit(format!("{} = {};", dst, call));
+                }
+            }
+            InstructionType::Return(value) => {
+                let value = self.value(value, &self.func.get_function_return_type())?;
+                self.emit(format!("return {};", value));
+            }
+            InstructionType::VoidReturn => self.emit("return;".to_string()),
+            InstructionType::Branch(target) => self.write_branch(block, &target.get_name())?,
+            InstructionType::BranchIf(cond, if_true, if_false) => {
+                let cond = self.value(cond, &Type::Integer(1))?;
+                self.emit(format!("if ({}) {{", cond));
+                let (if_true, if_false) = (if_true.borrow().get_name(), if_false.borrow().get_name());
+                self.write_branch(block, &if_true)?;
+                self.emit("} else {".to_string());
+                self.write_branch(block, &if_false)?;
+                self.emit("}".to_string());
+            }
+            InstructionType::Phi(_) => self.emit(format!("{} = {};", dst, local("p", &inst.get_name()))),
+            InstructionType::Unreachable => self.emit("SSLB_TRAP();".to_string()),
+        }
+        Ok(())
+    }
+
+    /// Returns the expression for the conversion `opcode` of `a` to `to`.
+    /// Booleans convert like one-bit integers rather than like `_Bool`, so
+    /// they are masked on the way in and negated where they are signed.
+    fn cast(&mut self, opcode: &str, a: &ValueEntity, to: &Type) -> Result<String, Error> {
+        let from = a.get_type();
+        let x = self.value(a, &from)?;
+        let ty = self.type_name(to)?;
+        Ok(match opcode {
+            "trunc" if *to == Type::Integer(1) => format!("({})({} & 1)", ty, x),
+            "fptoui" if *to == Type::Integer(1) => format!("(_Bool)((uint32_t){} & 1)", x),
+            "zext" | "fptoui" | "uitofp" => format!("({})({}){}", ty, unsigned_name(if opcode == "fptoui" { to } else { &from }), x),
+            "sext" | "sitofp" if from == Type::Integer(1) => format!("({})-(int32_t){}", ty, x),
+            "fptosi" if *to == Type::Integer(1) => format!("(_Bool)((int32_t){} & 1)", x),
+            "ptrtoint" if *to == Type::Integer(1) => format!("(_Bool)((uintptr_t){} & 1)", x),
+            "ptrtoint" => format!("({})(uintptr_t){}", ty, x),
+            "inttoptr" => format!("({})(uintptr_t)({}){}", ty, unsigned_name(&from), x),
+            // the bits of an integer and a float are swapped through a union
+            "bitcast" if from.is_float() != to.is_float() => {
+                let from_name = self.type_name(&from)?;
+                format!("((union {{ {} from; {} to; }}){{ .from = {} }}).to", from_name, ty, x)
+            }
+            _ => format!("({}){}", ty, x),
+        })
+    }
+
+    fn write_alloca(&mut self, inst: &Instruction, ty: &Type, count: Option<&ValueEntity>, align: u64) -> Result<(), Error> {
+        let dst = local("v", &inst.get_name());
+        let element = self.type_name(ty)?;
+        let constant = match count {
+            None => Some(1),
+            Some(ValueEntity::Constant(count)) => match count.fold() {
+                Some(Scalar::Int(count)) => count.to_u64(),
+                _ => None,
+            },
+            Some(_) => None,
+        };
+        if let Some(count) = constant {
+            // a buffer in the function's frame, like the other backends use
+            let buffer = local("a", &inst.get_name());
+            let aligned = if align > self.layout.align_of(ty) { format!("SSLB_ALIGNED({}) ", align) } else { String::new() };
+            self.locals.push(format!("    {}{} {}[{}];", aligned, element, buffer, count.max(1)));
+            self.emit(format!("{} = {};", dst, buffer));
+            return Ok(());
+        }
+
+        let count = count.unwrap();
+        let count_type = count.get_type();
+        // counts are unsigned
+        let unsigned = match count_type {
+            Type::Integer(bits @ (8 | 16 | 32 | 64)) => format!("uint{}_t", bits),
+            _ => "uint32_t".to_string(),
+        };
+        let count = self.value(count, &count_type)?;
+        let size = format!("(size_t)({}){} * sizeof({})", unsigned, count, element);
+        if align <= 16 {
+            self.emit(format!("{} = ({}*)SSLB_ALLOCA({});", dst, element, size));
+        } else {
+            self.emit(format!("{} = ({}*)(((uintptr_t)SSLB_ALLOCA({} + {}) + {}) & ~(uintptr_t){});", dst, element, size, align - 1, align - 1, align - 1));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::parser::parse_module;
+
+    /// Returns the lines after the prelude.
+    fn emit(source: &str) -> Vec<String> {
+        let module = parse_module(source).unwrap_or_else(|e| panic!("{}", e));
+        let mut bytes = Vec::new();
+        CEmitter::new(IRContext::new(module)).emit_module(&mut bytes).unwrap();
+        let text = String::from_utf8(bytes).unwrap();
+        let body = &text[text.find(PRELUDE).unwrap() + PRELUDE.len()..];
+        body.lines().map(|line| line.trim().to_string()).filter(|line| !line.is_empty()).collect()
+    }
+
+    #[test]
+    fn writes_loops_as_gotos_through_phi_variables() {
+        let lines = emit(r#"
+            target triple = "x86_64-unknown-linux-gnu"
+            @message = private constant [3 x i8] c"hi\00"
+            @count = internal global i64 0
+
+            declare external function @puts(%s: [3 x i8]*) -> i32
+
+            define internal function @sum(%n: i32) -> i32 {
+            %entry:
+              %printed = call [3 x i8]* -> i32 @puts(@message)
+              branch %loop
+            %loop:
+              %i = phi i32 0, %entry, %next, %loop
+              %added = add i32 %i, %n
+              %next = add i32 %i, 1
+              %done = sgt i32 %next, %n
+              branch %done, %exit, %loop
+            %exit:
+              %old = load i64* @count
+              store i64* @count, %old
+              return i32 %added
+            }
+        "#);
+        let has = |line: &str| lines.iter().any(|l| l == line);
+        assert_eq!(lines[0], "typedef struct { int8_t e[3]; } sslb_array0;");
+        assert!(has("int32_t puts(sslb_array0* v_s);"));
+        assert!(has("static const sslb_array0 message = { { 104, 105, 0 } };"));
+        assert!(has("int64_t count = INT64_C(0);"));
+        let body = lines.iter().skip_while(|l| *l != "int32_t sum(int32_t v_n) {").map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(body[body.len() - 19..], [
+            "v_printed = puts((sslb_array0*)&message);",
+            "p_i = 0;",
+            "goto b_loop;",
+            "b_loop:;",
+            "v_i = p_i;",
+            // signed overflow wraps, as it does in the IR
+            "v_added = (int32_t)((uint32_t)v_i + (uint32_t)v_n);",
+            "v_next = (int32_t)((uint32_t)v_i + (uint32_t)1);",
+            "v_done = v_next > v_n;",
+            "if (v_done) {",
+            "goto b_exit;",
+            "} else {",
+            "p_i = v_next;",
+            "goto b_loop;",
+            "}",
+            "b_exit:;",
+            "v_old = *&count;",
+            "*&count = v_old;",
+            "return v_added;",
+            "}",
+        ][..]);
+    }
+
+    #[test]
+    fn writes_attributes_through_the_prelude_macros() {
+        let lines = emit(r#"
+            target triple = "i686-unknown-linux-gnu"
+            @tls = internal thread_local global i32 1
+            @maybe = weak global i32 2
+
+            declare external x86_stdcallcc function @callback(%x: i32) -> i32
+
+            define internal function @call() -> i32 {
+            %entry:
+              %result = call i32 -> i32 @callback(7)
+              return i32 %result
+            }
+        "#);
+        assert_eq!(lines, [
+            "int32_t SSLB_STDCALL callback(int32_t v_x);",
+            "int32_t call(void);",
+            "extern SSLB_THREAD_LOCAL int32_t tls;",
+            "extern SSLB_WEAK int32_t maybe;",
+            "SSLB_THREAD_LOCAL int32_t tls = 1;",
+            "SSLB_WEAK int32_t maybe = 2;",
+            "int32_t call(void) {",
+            "int32_t v_result;",
+            "v_result = callback(7);",
+            "return v_result;",
+            "}",
+        ]);
+    }
+
+    #[test]
+    fn converts_through_casts_and_unions() {
+        let lines = emit(r#"
+            target triple = "x86_64-unknown-linux-gnu"
+            define internal function @conv(%a: i32, %b: i64, %x: f64, %p: i8*) -> f64 {
+            %entry:
+              %wide = sext i32 %a to i64
+              %float = sitofp i64 %wide to f64
+              %single = fptrunc f64 %float to f32
+              %byte = trunc i64 %b to i8
+              %unsigned = zext i8 %byte to i32
+              %from = uitofp i32 %a to f32
+              %double = fpext f32 %from to f64
+              %int = fptosi f64 %x to i32
+              %big = fptoui f64 %x to i64
+              %bits = bitcast f64 %x to i64
+              %address = ptrtoint i8* %p to i64
+              %pointer = inttoptr i64 %address to i32*
+              return f64 %double
+            }
+        "#);
+        let body = lines.iter().skip_while(|l| !l.starts_with("v_wide = ")).map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(body, [
+            "v_wide = (int64_t)v_a;",
+            "v_float = (double)v_wide;",
+            "v_single = (float)v_float;",
+            "v_byte = (int8_t)v_b;",
+            "v_unsigned = (int32_t)(uint8_t)v_byte;",
+            "v_from = (float)(uint32_t)v_a;",
+            "v_double = (double)v_from;",
+            "v_int = (int32_t)v_x;",
+            "v_big = (int64_t)(uint64_t)v_x;",
+            "v_bits = ((union { double from; int64_t to; }){ .from = v_x }).to;",
+            "v_address = (int64_t)(uintptr_t)v_p;",
+            "v_pointer = (int32_t*)(uintptr_t)(uint64_t)v_address;",
+            "return v_double;",
+            "}",
+        ]);
+    }
+
+    #[test]
+    fn reads_operands_as_unsigned_through_casts() {
+        let lines = emit(r#"
+            target triple = "x86_64-unknown-linux-gnu"
+            define internal function @compare(%a: i32, %b: i8) -> i1 {
+            %entry:
+              %quotient = udiv i32 %a, 10
+              %rest = urem i8 %b, 3
+              %shifted = lshr i8 %b, %rest
+              %less = ult i8 %shifted, %b
+              %greater = uge i32 %quotient, %a
+              %both = and i1 %less, %greater
+              return i1 %both
+            }
+        "#);
+        let body = lines.iter().skip_while(|l| !l.starts_with("v_quotient = ")).map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(body, [
+            "v_quotient = (int32_t)((uint32_t)v_a / (uint32_t)10);",
+            "v_rest = (int8_t)((uint8_t)v_b % (uint8_t)3);",
+            // narrow values are promoted to int before shifting
+            "v_shifted = (uint32_t)(uint8_t)v_b >> (v_rest & 31);",
+            "v_less = (uint8_t)v_shifted < (uint8_t)v_b;",
+            "v_greater = (uint32_t)v_quotient >= (uint32_t)v_a;",
+            "v_both = v_less & v_greater;",
+            "return v_both;",
+            "}",
+        ]);
+    }
+
+    #[test]
+    fn flips_booleans_and_integers_differently() {
+        let lines = emit(r#"
+            target triple = "x86_64-unknown-linux-gnu"
+            define internal function @flip(%a: i32, %b: i32) -> i32 {
+            %entry:
+              %both = and i32 %a, %b
+              %flipped = not i32 %both
+              %odd = trunc i32 %flipped to i1
+              %even = not i1 %odd
+              %wide = zext i1 %even to i32
+              return i32 %wide
+            }
+        "#);
+        let body = lines.iter().skip_while(|l| !l.starts_with("v_both = ")).take(4).map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(body[..2], ["v_both = v_a & v_b;", "v_flipped = ~v_both;"]);
+        // a _Bool is promoted to int, so ~ would leave it true
+        assert_eq!(body[3], "v_even = !v_odd;");
+    }
+
+    #[test]
+    fn writes_float_operations() {
+        let lines = emit(r#"
+            target triple = "x86_64-unknown-linux-gnu"
+            define internal function @measure(%a: f64, %b: f32) -> i1 {
+            %entry:
+              %sum = fadd fast f64 %a, 1.5
+              %half = fmul nnan f32 %b, 0.5
+              %rest = frem f64 %sum, %a
+              %negated = fneg f64 %rest
+              %less = fcmp ult f64 %negated, %a
+              %same = fcmp oeq f32 %half, %b
+              %both = and i1 %less, %same
+              return i1 %both
+            }
+        "#);
+        let body = lines.iter().skip_while(|l| !l.starts_with("v_sum = ")).map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(body, [
+            "v_sum = v_a + 1.5;",
+            "v_half = v_b * 0.5f;",
+            "v_rest = fmod(v_sum, v_a);",
+            "v_negated = -(v_rest);",
+            // unordered predicates negate the quiet comparison macros
+            "v_less = !(isgreaterequal(v_negated, v_a));",
+            "v_same = v_half == v_b;",
+            "v_both = v_less & v_same;",
+            "return v_both;",
+            "}",
+        ]);
+    }
+
+    #[test]
+    fn writes_constant_expressions() {
+        let lines = emit(r#"
+            target triple = "x86_64-unknown-linux-gnu"
+            @table = internal global [2 x i32] [7, -1]
+            @end = internal global i64 add (i64 ptrtoint ([2 x i32]* @table to i64), 8)
+            @small = internal global i16 -2
+
+            define internal function @second(%a: i64) -> i64 {
+            %entry:
+              %address = add i64 %a, add (i64 ptrtoint ([2 x i32]* @table to i64), 4)
+              %pointer = inttoptr i64 %address to i32*
+              %value = load i32* %pointer
+              %wide = sext i32 %value to i64
+              %sum = add i64 %wide, undef
+              return i64 %sum
+            }
+        "#);
+        assert!(lines.contains(&"sslb_array0 table = { { 7, -1 } };".to_string()));
+        // address arithmetic counts bytes
+        assert!(lines.contains(&"int64_t end = (int64_t)((char *)&table + INT64_C(8));".to_string()));
+        assert!(lines.contains(&"int16_t small = -2;".to_string()));
+        let body = lines.iter().skip_while(|l| !l.starts_with("v_address = ")).map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(body[..2], [
+            "v_address = (int64_t)((uint64_t)v_a + (uint64_t)(int64_t)((char *)&table + INT64_C(4)));",
+            "v_pointer = (int32_t*)(uintptr_t)(uint64_t)v_address;",
+        ]);
+        // undefined values are zero
+        assert_eq!(body[4], "v_sum = (int64_t)((uint64_t)v_wide + (uint64_t)INT64_C(0));");
+    }
+
+    #[test]
+    fn writes_element_addresses_as_member_accesses() {
+        let lines = emit(r#"
+            target triple = "x86_64-unknown-linux-gnu"
+            define internal function @element(%p: { i8, [4 x i32] }*, %i: i64) -> i32 {
+            %entry:
+              %element = getelementptr { i8, [4 x i32] }* %p, i64 1, i32 1, i64 %i
+              %value = load i32* %element
+              store i32* %element, 7
+              return i32 %value
+            }
+        "#);
+        let body = lines.iter().skip_while(|l| !l.starts_with("v_element = ")).map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(body, [
+            // arrays are wrapped in structs, so that they can be assigned
+            "v_element = &(v_p)[INT64_C(1)].f1.e[v_i];",
+            "v_value = *v_element;",
+            "*v_element = 7;",
+            "return v_value;",
+            "}",
+        ]);
+    }
+}
@@ -0,0 +1,895 @@
+use std::collections::HashMap;
+use std::io::Write;
+use crate::ir::builder::ctx::IRContext;
+use crate::ir::calling_conv::CallingConv;
+use crate::ir::linkage::Linkage;
+use crate::ir::values::function::Function;
+use crate::ir::values::constant::{Constant, ConstantExpr};
+use crate::ir::values::global::GlobalVariable;
+use crate::ir::values::instruction::{get_field_index, FastMathFlags, Instruction, InstructionType};
+use crate::ir::values::value::{Type, ValueEntity};
+use crate::targets::layout::DataLayout;
+use crate::targets::triple::{Arch, TargetOS, TargetTriple};
+use crate::emit::asm::unsupported;
+use crate::emit::asm::x86;
+use crate::emit::asm::x86::abi::ArgLocation;
+use crate::error::Error;
+
+/// Returns the LLVM linkage of a function or global defined in the module.
+/// Internal symbols are visible to other modules here, which is LLVM's
+/// default, and private ones are LLVM's internal symbols.
+fn linkage(name: &str, linkage: &Linkage, is_function: bool) -> Result<&'static str, Error> {
+    match linkage {
+        Linkage::ExternalLinkage | Linkage::InternalLinkage => Ok(""),
+        Linkage::PrivateLinkage => Ok("internal "),
+        Linkage::ExternalWeakLinkage => Ok("extern_weak "),
+        Linkage::WeakLinkage => Ok("weak "),
+        Linkage::LinkonceLinkage => Ok("linkonce "),
+        Linkage::CommonLinkage if !is_function => Ok("common "),
+        Linkage::AppendingLinkage if !is_function => Ok("appending "),
+        _ => Err(unsupported(format!("{} linkage on function {}", linkage, name)).into()),
+    }
+}
+
+/// Returns `name` as an LLVM identifier, quoted when it isn't a valid one.
+fn identifier(sigil: char, name: &str) -> String {
+    let name = name.trim_start_matches(sigil);
+    if name.chars().next().is_some_and(|c| !c.is_ascii_digit()) {
+        format!("{}{}", sigil, name)
+    } else {
+        format!("{}\"{}\"", sigil, name)
+    }
+}
+
+/// Returns the fast-math flags as they follow an opcode, with a leading space when there are any.
+fn fast_math_flags(flags: &FastMathFlags) -> String {
+    if flags.is_empty() {
+        String::new()
+    } else {
+        format!(" {}", flags)
+    }
+}
+
+/// Returns a float literal, in the hexadecimal form that is always exact.
+/// LLVM writes floats as the double they convert to.
+fn float_literal(value: f64, bits: usize) -> String {
+    let value = if bits == 32 { value as f32 as f64 } else { value };
+    format!("0x{:016X}", value.to_bits())
+}
+
+/// Returns the `target datalayout` string describing `layout` on `triple`.
+fn datalayout(layout: &DataLayout, triple: &TargetTriple) -> String {
+    let endian = match triple.arch() {
+        Arch::Mips | Arch::Powerpc | Arch::Powerpc64 | Arch::S390x => "E",
+        _ => "e",
+    };
+    let mangling = match (triple.os(), triple.arch()) {
+        (TargetOS::Darwin, _) => "o",
+        (TargetOS::Windows, Arch::X86) => "x",
+        (TargetOS::Windows, _) => "w",
+        _ => "e",
+    };
+    let bits = |size: u64, align: u64| format!("{}:{}", size * 8, align * 8);
+    format!(
+        "{}-m:{}-p:{}-i8:{}-i16:{}-i32:{}-i64:{}-i128:{}-f32:{}-f64:{}",
+        endian,
+        mangling,
+        bits(layout.pointer_size, layout.pointer_align),
+        layout.i8_align * 8,
+        layout.i16_align * 8,
+        layout.i32_align * 8,
+        layout.i64_align * 8,
+        layout.i128_align * 8,
+        layout.f32_align * 8,
+        layout.f64_align * 8,
+    )
+}
+
+/// Writes a module as textual LLVM IR that `llc`, `opt` and the rest of the
+/// LLVM tools accept. Integer operations are signed, and shift amounts are
+/// masked like x86 does, so that the result computes what the other backends do.
+/// Aggregates are passed and returned as LLVM first-class values, which
+/// only agrees with the C ABI between functions of the same module.
+pub struct LlvmEmitter {
+    ctx: IRContext,
+    /// Write pointers as `ptr`, which LLVM 15 and later expect, instead of typed pointers.
+    opaque_pointers: bool,
+    /// The LLVM type of each function and global, which can differ from the
+    /// IR's for variadic functions.
+    symbols: HashMap<String, String>,
+}
+
+impl LlvmEmitter {
+    pub fn new(ctx: IRContext) -> Self {
+        Self {
+            ctx,
+            opaque_pointers: false,
+            symbols: HashMap::new(),
+        }
+    }
+
+    /// Sets whether pointers are written as `ptr` rather than as typed pointers.
+    pub fn set_opaque_pointers(&mut self, opaque_pointers: bool) {
+        self.opaque_pointers = opaque_pointers;
+    }
+
+    fn type_name(&self, ty: &Type) -> Result<String, Error> {
+        Ok(match ty {
+            Type::Integer(bits) => format!("i{}", bits),
+            Type::Float(16) => "half".to_string(),
+            Type::Float(32) => "float".to_string(),
+            Type::Float(64) => "double".to_string(),
+            Type::Float(128) => "fp128".to_string(),
+            Type::Void => "void".to_string(),
+            Type::Branch => "label".to_string(),
+            _ if self.opaque_pointers && (ty.is_pointer() || ty.is_function()) => "ptr".to_string(),
+            // function values are already pointers to the function
+            Type::Pointer(inner) if inner.is_function() => self.type_name(inner)?,
+            Type::Pointer(inner) if inner.is_void() => "i8*".to_string(),
+            Type::Pointer(inner) => format!("{}*", self.type_name(inner)?),
+            Type::FunctionType(_, _) => format!("{}*", self.function_type(ty, false)?),
+            Type::Array(len, element) => format!("[{} x {}]", len, self.type_name(element)?),
+            Type::Struct(fields) if fields.is_empty() => "{}".to_string(),
+            Type::Struct(fields) => {
+                let fields = fields.iter().map(|field| self.type_name(field)).collect::<Result<Vec<_>, _>>()?;
+                format!("{{ {} }}", fields.join(", "))
+            }
+            ty => return Err(unsupported(format!("values of type {} in LLVM IR", ty)).into()),
+        })
+    }
+
+    /// Returns the LLVM function type for a function of type `ty`, like `i32 (i8*, ...)`.
+    fn function_type(&self, ty: &Type, is_var_arg: bool) -> Result<String, Error> {
+        let mut params = ty.get_function_argument_types().iter().map(|arg| self.type_name(arg)).collect::<Result<Vec<_>, _>>()?;
+        if is_var_arg {
+            params.push("...".to_string());
+        }
+        Ok(format!("{} ({})", self.type_name(&ty.get_function_return_type())?, params.join(", ")))
+    }
+
+    /// Returns which parameters of a function of type `ty` get the `inreg`
+    /// attribute. LLVM only passes fastcall arguments in registers when they
+    /// have it, so it goes on those the x86 backend passes in `ecx` and `edx`.
+    fn in_registers(&self, ty: &Type, conv: CallingConv) -> Result<Vec<bool>, Error> {
+        let params = ty.get_function_argument_types();
+        if conv != CallingConv::X86Fastcall {
+            return Ok(vec![false; params.len()]);
+        }
+        let abi = x86::abi::classify_call(self.ctx.get_module().data_layout(), params, &ty.get_function_return_type(), conv)?;
+        Ok(abi.args.iter().map(|location| matches!(location, ArgLocation::Reg(_))).collect())
+    }
+
+    pub fn emit_module(&mut self, file: &mut impl Write) -> Result<(), Error> {
+        let ctx = self.ctx.clone();
+        let module = ctx.get_module();
+        let functions = module.get_functions().iter().map(|f| f.borrow().clone()).collect::<Vec<_>>();
+        let globals = module.get_global_variables().iter().map(|g| g.borrow().clone()).collect::<Vec<_>>();
+        for function in &functions {
+            let ty = match self.opaque_pointers {
+                true => "ptr".to_string(),
+                false => format!("{}*", self.function_type(&function.get_type(), function.is_var_arg())?),
+            };
+            self.symbols.insert(function.get_name(), ty);
+        }
+        for global in &globals {
+            self.symbols.insert(global.get_name(), self.type_name(&global.get_type())?);
+        }
+
+        writeln!(file, "; ModuleID = '{}'", module.name())?;
+        writeln!(file, "source_filename = \"{}\"", module.name())?;
+        writeln!(file, "target datalayout = \"{}\"", datalayout(module.data_layout(), module.target_triple()))?;
+        writeln!(file, "target triple = \"{}\"", module.target_triple())?;
+        if !globals.is_empty() {
+            writeln!(file)?;
+        }
+        for global in &globals {
+            self.emit_global(file, global)?;
+        }
+        for function in &functions {
+            writeln!(file)?;
+            self.emit_function(file, function)?;
+        }
+        Ok(())
+    }
+
+    fn emit_global(&mut self, file: &mut impl Write, global: &GlobalVariable) -> Result<(), Error> {
+        let name = identifier('@', &global.get_name());
+        let ty = self.type_name(global.get_value_type())?;
+        let thread_local = if global.is_thread_local() { "thread_local " } else { "" };
+        if global.is_external() {
+            let linkage = if *global.get_linkage() == Linkage::ExternalWeakLinkage { "extern_weak" } else { "external" };
+            writeln!(file, "{} = {} {}global {}", name, linkage, thread_local, ty)?;
+            return Ok(());
+        }
+
+        let linkage = linkage(&global.get_name(), global.get_linkage(), false)?;
+        let kind = if global.is_constant() { "constant" } else { "global" };
+        let initializer = match global.get_initializer() {
+            Some(initializer) => self.constant(initializer)?,
+            None => self.constant(&Constant::zero(global.get_value_type()))?,
+        };
+        write!(file, "{} = {}{}{} {} {}", name, linkage, thread_local, kind, ty, initializer)?;
+        if let Some(section) = global.get_section() {
+            write!(file, ", section \"{}\"", section)?;
+        }
+        if let Some(align) = global.get_alignment() {
+            write!(file, ", align {}", align)?;
+        }
+        writeln!(file)?;
+        Ok(())
+    }
+
+    /// Returns the LLVM constant for `constant`, without its type.
+    fn constant(&self, constant: &Constant) -> Result<String, Error> {
+        let typed = |constant: &Constant| -> Result<String, Error> {
+            Ok(format!("{} {}", self.type_name(&constant.get_type())?, self.constant(constant)?))
+        };
+        Ok(match constant {
+            Constant::Int(value) if value.bits() == 1 => (if value.is_zero() { "false" } else { "true" }).to_string(),
+            Constant::Int(value) => value.to_string(),
+            Constant::Float(bits, value) => float_literal(f64::from_bits(*value), *bits),
+            Constant::Null(_) => "null".to_string(),
+            Constant::Undef(_) => "undef".to_string(),
+            Constant::Poison(_) => "poison".to_string(),
+            Constant::Zero(_) => "zeroinitializer".to_string(),
+            Constant::Bytes(bytes) => {
+                let mut string = String::new();
+                for byte in bytes.iter().copied() {
+                    match byte {
+                        b' '..=b'~' if byte != b'"' && byte != b'\\' => string.push(byte as char),
+                        byte => string.push_str(&format!("\\{:02X}", byte)),
+                    }
+                }
+                format!("c\"{}\"", string)
+            }
+            Constant::Array(_, elements) => {
+                let items = elements.iter().map(typed).collect::<Result<Vec<_>, _>>()?;
+                format!("[{}]", items.join(", "))
+            }
+            Constant::Struct(fields) => {
+                let items = fields.iter().map(typed).collect::<Result<Vec<_>, _>>()?;
+                format!("{{ {} }}", items.join(", "))
+            }
+            Constant::Symbol(ty, name) => self.address(name, ty)?,
+            Constant::Expr(expr) => match expr.as_ref() {
+                ConstantExpr::Cast(opcode, value, ty) => format!("{} ({} to {})", opcode, typed(value)?, self.type_name(ty)?),
+                ConstantExpr::Binary(opcode, a, b) => format!("{} ({}, {})", opcode, typed(a)?, typed(b)?),
+            },
+        })
+    }
+
+    /// Returns the constant for the address of a function or global, as a value of type `ty`.
+    fn address(&self, name: &str, ty: &Type) -> Result<String, Error> {
+        let symbol_type = self.symbols.get(name).ok_or_else(|| unsupported(format!("address of unknown symbol {}", name)))?;
+        let expected = self.type_name(ty)?;
+        let symbol = identifier('@', name);
+        Ok(if ty.is_integer() {
+            format!("ptrtoint ({} {} to {})", symbol_type, symbol, expected)
+        } else if *symbol_type != expected {
+            format!("bitcast ({} {} to {})", symbol_type, symbol, expected)
+        } else {
+            symbol
+        })
+    }
+
+    fn emit_function(&mut self, file: &mut impl Write, func: &Function) -> Result<(), Error> {
+        let name = identifier('@', &func.get_name());
+        let conv = match func.get_calling_conv() {
+            CallingConv::C => String::new(),
+            conv => format!("{} ", conv),
+        };
+        let ret = self.type_name(&func.get_function_return_type())?;
+        let is_declaration = func.is_external() || func.get_blocks().is_empty();
+        let in_registers = self.in_registers(&func.get_type(), func.get_calling_conv())?;
+        let mut params = Vec::new();
+        for (param, in_register) in func.get_params().iter().zip(in_registers) {
+            let mut ty = self.type_name(&param.get_type())?;
+            if in_register {
+                ty.push_str(" inreg");
+            }
+            params.push(if is_declaration { ty } else { format!("{} {}", ty, identifier('%', &param.get_name())) });
+        }
+        if func.is_var_arg() {
+            params.push("...".to_string());
+        }
+        if is_declaration {
+            let linkage = if *func.get_linkage() == Linkage::ExternalWeakLinkage { "extern_weak " } else { "" };
+            writeln!(file, "declare {}{}{} {}({})", linkage, conv, ret, name, params.join(", "))?;
+            return Ok(());
+        }
+
+        let linkage = linkage(&func.get_name(), func.get_linkage(), true)?;
+        writeln!(file, "define {}{}{} {}({}) {{", linkage, conv, ret, name, params.join(", "))?;
+        let mut writer = FunctionWriter { emitter: self, func, lines: Vec::new(), temps: 0 };
+        writer.write()?;
+        for line in writer.lines {
+            writeln!(file, "{}", line)?;
+        }
+        writeln!(file, "}}")?;
+        Ok(())
+    }
+}
+
+/// Writes the body of one function.
+struct FunctionWriter<'a> {
+    emitter: &'a LlvmEmitter,
+    func: &'a Function,
+    lines: Vec<String>,
+    /// The number of temporaries so far. Their names contain a `-`, which IR names can't.
+    temps: usize,
+}
+
+impl FunctionWriter<'_> {
+    fn emit(&mut self, line: String) {
+        self.lines.push(format!("  {}", line));
+    }
+
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        format!("%t-{}", self.temps)
+    }
+
+    fn type_name(&self, ty: &Type) -> Result<String, Error> {
+        self.emitter.type_name(ty)
+    }
+
+    fn write(&mut self) -> Result<(), Error> {
+        let blocks = self.func.get_blocks().clone();
+        // LLVM doesn't allow branching back to the entry block, so one that does gets a new block in front
+        let entry = blocks[0].borrow().get_name();
+        let branches_to_entry = blocks.iter().any(|block| block.borrow().get_instructions().iter().any(|inst| match inst {
+            ValueEntity::Instruction(inst) => inst.get_successors().contains(&entry),
+            _ => false,
+        }));
+        if branches_to_entry {
+            self.lines.push("t-entry:".to_string());
+            self.emit(format!("br label {}", identifier('%', &entry)));
+        }
+        for block in &blocks {
+            let block = block.borrow();
+            self.lines.push(format!("{}:", identifier('%', &block.get_name()).trim_start_matches('%')));
+            for inst in block.get_instructions() {
+                if let ValueEntity::Instruction(inst) = inst {
+                    self.write_instruction(inst)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the operand for `value`, used as a value of type `ty`.
+    fn value(&self, value: &ValueEntity, ty: &Type) -> Result<String, Error> {
+        match value {
+            ValueEntity::Instruction(inst) => Ok(identifier('%', &inst.get_name())),
+            ValueEntity::Argument(argument) => Ok(identifier('%', &argument.get_name())),
+            ValueEntity::Function(function) => self.emitter.address(&function.get_name(), ty),
+            ValueEntity::GlobalVariable(global) => self.emitter.address(&global.get_name(), ty),
+            ValueEntity::Constant(constant) => self.emitter.constant(constant),
+            ValueEntity::BasicBlock(block) => Err(unsupported(format!("basic block {} used as a value", block.get_name())).into()),
+        }
+    }
+
+    fn write_shift(&mut self, dst: &str, op: &str, a: &ValueEntity, b: &ValueEntity) -> Result<(), Error> {
+        let ty = a.get_type();
+        let Type::Integer(bits) = ty else {
+            return Err(unsupported(format!("{} on values of type {}", op, ty)).into());
+        };
+        let (x, y) = (self.value(a, &ty)?, self.value(b, &ty)?);
+        if bits >= 32 {
+            // the amount is kept below the width, which LLVM requires
+            let amount = self.temp();
+            if bits.is_power_of_two() {
+                self.emit(format!("{} = and i{} {}, {}", amount, bits, y, bits - 1));
+            } else {
+                self.emit(format!("{} = urem i{} {}, {}", amount, bits, y, bits));
+            }
+            self.emit(format!("{} = {} i{} {}, {}", dst, op, bits, x, amount));
+            return Ok(());
+        }
+        // narrower integers are shifted as 32-bit ones
+        let extend = if bits == 1 || op == "lshr" { "zext" } else { "sext" };
+        let (wide, amount, masked, shifted) = (self.temp(), self.temp(), self.temp(), self.temp());
+        self.emit(format!("{} = {} i{} {} to i32", wide, extend, bits, x));
+        self.emit(format!("{} = {} i{} {} to i32", amount, extend, bits, y));
+        self.emit(format!("{} = and i32 {}, 31", masked, amount));
+        self.emit(format!("{} = {} i32 {}, {}", shifted, op, wide, masked));
+        self.emit(format!("{} = trunc i32 {} to i{}", dst, shifted, bits));
+        Ok(())
+    }
+
+    fn write_instruction(&mut self, inst: &Instruction) -> Result<(), Error> {
+        let dst = identifier('%', &inst.get_name());
+        let kind = inst.instruction_type();
+        match kind {
+            InstructionType::Add(a, b) | InstructionType::Sub(a, b) | InstructionType::Mul(a, b) | InstructionType::SDiv(a, b)
+            | InstructionType::UDiv(a, b) | InstructionType::SRem(a, b) | InstructionType::URem(a, b) | InstructionType::And(a, b)
+            | InstructionType::Or(a, b) | InstructionType::Xor(a, b) => {
+                let ty = a.get_type();
+                if !ty.is_integer() {
+                    return Err(unsupported(format!("`{}` on values of type {}", inst, ty)).into());
+                }
+                let op = match kind {
+                    InstructionType::Add(..) => "add",
+                    InstructionType::Sub(..) => "sub",
+                    InstructionType::Mul(..) => "mul",
+                    InstructionType::SDiv(..) => "sdiv",
+                    InstructionType::UDiv(..) => "udiv",
+                    InstructionType::SRem(..) => "srem",
+                    InstructionType::URem(..) => "urem",
+                    InstructionType::And(..) => "and",
+                    InstructionType::Or(..) => "or",
+                    _ => "xor",
+                };
+                let (x, y) = (self.value(a, &ty)?, self.value(b, &ty)?);
+                let ty_name = self.type_name(&ty)?;
+                self.emit(format!("{} = {} {} {}, {}", dst, op, ty_name, x, y));
+            }
+            InstructionType::Shl(a, b) => self.write_shift(&dst, "shl", a, b)?,
+            InstructionType::AShr(a, b) => self.write_shift(&dst, "ashr", a, b)?,
+            InstructionType::LShr(a, b) => self.write_shift(&dst, "lshr", a, b)?,
+            InstructionType::Eq(a, b) | InstructionType::Ne(a, b) | InstructionType::SLt(a, b) | InstructionType::SLe(a, b)
+            | InstructionType::SGt(a, b) | InstructionType::SGe(a, b) | InstructionType::ULt(a, b) | InstructionType::ULe(a, b)
+            | InstructionType::UGt(a, b) | InstructionType::UGe(a, b) => {
+                let ty = a.get_type();
+                let predicate = match kind {
+                    InstructionType::Eq(..) => "eq",
+                    InstructionType::Ne(..) => "ne",
+                    InstructionType::SLt(..) => "slt",
+                    InstructionType::SLe(..) => "sle",
+                    InstructionType::SGt(..) => "sgt",
+                    InstructionType::SGe(..) => "sge",
+                    InstructionType::ULt(..) => "ult",
+                    InstructionType::ULe(..) => "ule",
+                    InstructionType::UGt(..) => "ugt",
+                    _ => "uge",
+                };
+                let (x, y) = (self.value(a, &ty)?, self.value(b, &ty)?);
+                let ty_name = self.type_name(&ty)?;
+                self.emit(format!("{} = icmp {} {} {}, {}", dst, predicate, ty_name, x, y));
+            }
+            InstructionType::Neg(a) => {
+                let ty = a.get_type();
+                let (x, ty_name) = (self.value(a, &ty)?, self.type_name(&ty)?);
+                self.emit(format!("{} = sub {} 0, {}", dst, ty_name, x));
+            }
+            InstructionType::FAdd(a, b, flags) | InstructionType::FSub(a, b, flags) | InstructionType::FMul(a, b, flags)
+            | InstructionType::FDiv(a, b, flags) | InstructionType::FRem(a, b, flags) => {
+                let op = match kind {
+                    InstructionType::FAdd(..) => "fadd",
+                    InstructionType::FSub(..) => "fsub",
+                    InstructionType::FMul(..) => "fmul",
+                    InstructionType::FDiv(..) => "fdiv",
+                    _ => "frem",
+                };
+                let ty = a.get_type();
+                let (x, y, ty_name) = (self.value(a, &ty)?, self.value(b, &ty)?, self.type_name(&ty)?);
+                self.emit(format!("{} = {}{} {} {}, {}", dst, op, fast_math_flags(flags), ty_name, x, y));
+            }
+            InstructionType::FNeg(a, flags) => {
+                let ty = a.get_type();
+                let (x, ty_name) = (self.value(a, &ty)?, self.type_name(&ty)?);
+                self.emit(format!("{} = fneg{} {} {}", dst, fast_math_flags(flags), ty_name, x));
+            }
+            InstructionType::FCmp(predicate, a, b, flags) => {
+                let ty = a.get_type();
+                let (x, y, ty_name) = (self.value(a, &ty)?, self.value(b, &ty)?, self.type_name(&ty)?);
+                self.emit(format!("{} = fcmp{} {} {} {}, {}", dst, fast_math_flags(flags), predicate, ty_name, x, y));
+            }
+            InstructionType::Not(a) => {
+                let ty = a.get_type();
+                let (x, ty_name) = (self.value(a, &ty)?, self.type_name(&ty)?);
+                // LLVM has no not of its own
+                self.emit(format!("{} = xor {} {}, -1", dst, ty_name, x));
+            }
+            InstructionType::Trunc(a) | InstructionType::ZExt(a) | InstructionType::SExt(a) | InstructionType::FPTrunc(a)
+            | InstructionType::FPExt(a) | InstructionType::FPToSI(a) | InstructionType::FPToUI(a) | InstructionType::SIToFP(a)
+            | InstructionType::UIToFP(a) | InstructionType::PtrToInt(a) | InstructionType::IntToPtr(a) | InstructionType::Bitcast(a) => {
+                // the conversions are LLVM's own
+                let (opcode, _) = kind.as_cast().unwrap();
+                let from = a.get_type();
+                let x = self.value(a, &from)?;
+                self.emit(format!("{} = {} {} {} to {}", dst, opcode, self.type_name(&from)?, x, self.type_name(&inst.get_type())?));
+            }
+            InstructionType::Alloca(ty, count, align) => {
+                let mut line = format!("{} = alloca {}", dst, self.type_name(ty)?);
+                if let Some(count) = count {
+                    let count_type = count.get_type();
+                    line.push_str(&format!(", {} {}", self.type_name(&count_type)?, self.value(count, &count_type)?));
+                }
+                line.push_str(&format!(", align {}", align));
+                self.emit(line);
+            }
+            InstructionType::GetElementPtr(ptr, indices) => {
+                let ptr_type = ptr.get_type();
+                let mut ty = ptr_type.get_pointer_element_type();
+                let mut line = format!("{} = getelementptr {}, {} {}", dst, self.type_name(&ty)?, self.type_name(&ptr_type)?, self.value(ptr, &ptr_type)?);
+                for (position, index) in indices.iter().enumerate() {
+                    let index_type = index.get_type();
+                    ty = match ty {
+                        // LLVM only takes i32 constants as struct indices
+                        Type::Struct(fields) if position > 0 => {
+                            let field = get_field_index(index).filter(|field| *field < fields.len())
+                                .ok_or_else(|| unsupported(format!("`{}` selects a field by {}", inst, index.get_as_ref())))?;
+                            line.push_str(&format!(", i32 {}", field));
+                            fields[field].clone()
+                        }
+                        ty => {
+                            line.push_str(&format!(", {} {}", self.type_name(&index_type)?, self.value(index, &index_type)?));
+                            match ty {
+                                Type::Array(_, element) if position > 0 => *element,
+                                ty => ty,
+                            }
+                        }
+                    };
+                }
+                self.emit(line);
+            }
+            InstructionType::Load(ptr) => {
+                let ptr_type = ptr.get_type();
+                let ty = self.type_name(&inst.get_type())?;
+                let ptr = self.value(ptr, &ptr_type)?;
+                self.emit(format!("{} = load {}, {} {}", dst, ty, self.type_name(&ptr_type)?, ptr));
+            }
+            InstructionType::Store(ptr, value) => {
+                let ptr_type = ptr.get_type();
+                let ty = ptr_type.get_pointer_element_type();
+                let (value, ptr) = (self.value(value, &ty)?, self.value(ptr, &ptr_type)?);
+                self.emit(format!("store {} {}, {} {}", self.type_name(&ty)?, value, self.type_name(&ptr_type)?, ptr));
+            }
+            InstructionType::Call(callee, args) => self.write_call(inst, &dst, callee, args)?,
+            InstructionType::Return(value) => {
+                let ty = self.func.get_function_return_type();
+                let value = self.value(value, &ty)?;
+                self.emit(format!("ret {} {}", self.type_name(&ty)?, value));
+            }
+            InstructionType::VoidReturn => self.emit("ret void".to_string()),
+            InstructionType::Branch(target) => self.emit(format!("br label {}", identifier('%', &target.get_name()))),
+            InstructionType::BranchIf(cond, if_true, if_false) => {
+                let cond = self.value(cond, &Type::Integer(1))?;
+                let (if_true, if_false) = (identifier('%', &if_true.borrow().get_name()), identifier('%', &if_false.borrow().get_name()));
+                self.emit(format!("br i1 {}, label {}, label {}", cond, if_true, if_false));
+            }
+            InstructionType::Phi(incoming) => {
+                let ty = inst.get_type();
+                let mut edges = Vec::new();
+                for (value, from) in incoming {
+                    edges.push(format!("[ {}, {} ]", self.value(value, &ty)?, identifier('%', &from.get_name())));
+                }
+                self.emit(format!("{} = phi {} {}", dst, self.type_name(&ty)?, edges.join(", ")));
+            }
+            InstructionType::Unreachable => self.emit("unreachable".to_string()),
+        }
+        Ok(())
+    }
+
+    fn write_call(&mut self, inst: &Instruction, dst: &str, callee: &ValueEntity, args: &[Box<ValueEntity>]) -> Result<(), Error> {
+        let (callee_type, is_var_arg, conv) = match callee {
+            ValueEntity::Function(function) => (function.get_type(), function.is_var_arg(), function.get_calling_conv()),
+            callee => match callee.get_type() {
+                ty if ty.is_function_type() => (ty, false, CallingConv::C),
+                ty => (ty.get_pointer_element_type(), false, CallingConv::C),
+            },
+        };
+        let params = callee_type.get_function_argument_types().clone();
+        let in_registers = self.emitter.in_registers(&callee_type, conv)?;
+        let mut values = Vec::new();
+        for (i, arg) in args.iter().enumerate() {
+            let ty = params.get(i).cloned().unwrap_or_else(|| arg.get_type());
+            let attribute = if in_registers.get(i).copied().unwrap_or(false) { " inreg" } else { "" };
+            values.push(format!("{}{} {}", self.type_name(&ty)?, attribute, self.value(arg, &ty)?));
+        }
+        // the callee is called through its own type, which only variadic ones have to spell out
+        let callee = match callee {
+            ValueEntity::Function(function) => identifier('@', &function.get_name()),
+            callee => self.value(callee, &callee.get_type())?,
+        };
+        let ty = match is_var_arg {
+            true => self.emitter.function_type(&callee_type, true)?,
+            false => self.type_name(&callee_type.get_function_return_type())?,
+        };
+        let conv = match conv {
+            CallingConv::C => String::new(),
+            conv => format!("{} ", conv),
+        };
+        let call = format!("call {}{} {}({})", conv, ty, callee, values.join(", "));
+        if inst.get_type().is_void() {
+            self.emit(call);
+        } else {
+            self.emit(format!("{} = {}", dst, call));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::parser::parse_module;
+
+    fn emit(source: &str, opaque_pointers: bool) -> Vec<String> {
+        let module = parse_module(source).unwrap_or_else(|e| panic!("{}", e));
+        let mut emitter = LlvmEmitter::new(IRContext::new(module));
+        emitter.set_opaque_pointers(opaque_pointers);
+        let mut bytes = Vec::new();
+        emitter.emit_module(&mut bytes).unwrap();
+        String::from_utf8(bytes).unwrap().lines().map(|line| line.trim().to_string()).filter(|line| !line.is_empty()).collect()
+    }
+
+    const SUM: &str = r#"
+        target triple = "x86_64-unknown-linux-gnu"
+        @message = private constant [3 x i8] c"hi\00"
+        @count = internal global i64 0
+
+        declare external function @puts(%s: [3 x i8]*) -> i32
+
+        define internal function @sum(%n: i32) -> i32 {
+        %entry:
+          %printed = call [3 x i8]* -> i32 @puts(@message)
+          branch %loop
+        %loop:
+          %i = phi i32 0, %entry, %next, %loop
+          %added = add i32 %i, %n
+          %next = add i32 %i, 1
+          %done = sgt i32 %next, %n
+          branch %done, %exit, %loop
+        %exit:
+          %old = load i64* @count
+          store i64* @count, %old
+          return i32 %added
+        }
+    "#;
+
+    #[test]
+    fn writes_loops_globals_and_calls() {
+        let lines = emit(SUM, false);
+        assert_eq!(lines[2], "target datalayout = \"e-m:e-p:64:64-i8:8-i16:16-i32:32-i64:64-i128:128-f32:32-f64:64\"");
+        assert_eq!(lines[4..], [
+            "@message = internal constant [3 x i8] c\"hi\\00\"",
+            "@count = global i64 0",
+            "declare i32 @puts([3 x i8]*)",
+            "define i32 @sum(i32 %n) {",
+            "entry:",
+            "%printed = call i32 @puts([3 x i8]* @message)",
+            "br label %loop",
+            "loop:",
+            "%i = phi i32 [ 0, %entry ], [ %next, %loop ]",
+            "%added = add i32 %i, %n",
+            "%next = add i32 %i, 1",
+            "%done = icmp sgt i32 %next, %n",
+            "br i1 %done, label %exit, label %loop",
+            "exit:",
+            "%old = load i64, i64* @count",
+            "store i64 %old, i64* @count",
+            "ret i32 %added",
+            "}",
+        ]);
+    }
+
+    #[test]
+    fn passes_fastcall_arguments_inreg_with_opaque_pointers() {
+        let lines = emit(r#"
+            target triple = "i686-unknown-linux-gnu"
+            @tls = internal thread_local global i32 1
+
+            declare external x86_fastcallcc function @callback(%x: i32, %y: i64) -> i32
+
+            define internal function @call(%p: i32*) -> i32 {
+            %entry:
+              %result = call i32, i64 -> i32 @callback(7, 8)
+              %value = load i32* %p
+              return i32 %result
+            }
+        "#, true);
+        assert_eq!(lines[4..], [
+            "@tls = thread_local global i32 1",
+            // the i64 does not fit in the registers left after the i32, so it goes on the stack
+            "declare x86_fastcallcc i32 @callback(i32 inreg, i64)",
+            "define i32 @call(ptr %p) {",
+            "entry:",
+            "%result = call x86_fastcallcc i32 @callback(i32 inreg 7, i64 8)",
+            "%value = load i32, ptr %p",
+            "ret i32 %result",
+            "}",
+        ]);
+    }
+
+    #[test]
+    fn writes_conversions() {
+        let lines = emit(r#"
+            target triple = "x86_64-unknown-linux-gnu"
+            define internal function @conv(%a: i32, %b: i64, %x: f64, %p: i8*) -> f64 {
+            %entry:
+              %wide = sext i32 %a to i64
+              %float = sitofp i64 %wide to f64
+              %single = fptrunc f64 %float to f32
+              %byte = trunc i64 %b to i8
+              %unsigned = zext i8 %byte to i32
+              %from = uitofp i32 %a to f32
+              %double = fpext f32 %from to f64
+              %int = fptosi f64 %x to i32
+              %big = fptoui f64 %x to i64
+              %bits = bitcast f64 %x to i64
+              %address = ptrtoint i8* %p to i64
+              %pointer = inttoptr i64 %address to i32*
+              return f64 %double
+            }
+        "#, true);
+        let body = lines.iter().skip_while(|l| *l != "entry:").skip(1).map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(body, [
+            "%wide = sext i32 %a to i64",
+            "%float = sitofp i64 %wide to double",
+            "%single = fptrunc double %float to float",
+            "%byte = trunc i64 %b to i8",
+            "%unsigned = zext i8 %byte to i32",
+            "%from = uitofp i32 %a to float",
+            "%double = fpext float %from to double",
+            "%int = fptosi double %x to i32",
+            "%big = fptoui double %x to i64",
+            "%bits = bitcast double %x to i64",
+            "%address = ptrtoint ptr %p to i64",
+            "%pointer = inttoptr i64 %address to ptr",
+            "ret double %double",
+            "}",
+        ]);
+    }
+
+    #[test]
+    fn writes_unsigned_operations() {
+        let lines = emit(r#"
+            target triple = "x86_64-unknown-linux-gnu"
+            define internal function @compare(%a: i32, %b: i8) -> i1 {
+            %entry:
+              %quotient = udiv i32 %a, 10
+              %rest = urem i8 %b, 3
+              %shifted = lshr i8 %b, %rest
+              %less = ult i8 %shifted, %b
+              %greater = uge i32 %quotient, %a
+              %both = and i1 %less, %greater
+              return i1 %both
+            }
+        "#, false);
+        let body = lines.iter().skip_while(|l| *l != "entry:").skip(1).map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(body, [
+            "%quotient = udiv i32 %a, 10",
+            "%rest = urem i8 %b, 3",
+            // narrow shifts happen in 32 bits
+            "%t-1 = zext i8 %b to i32",
+            "%t-2 = zext i8 %rest to i32",
+            "%t-3 = and i32 %t-2, 31",
+            "%t-4 = lshr i32 %t-1, %t-3",
+            "%shifted = trunc i32 %t-4 to i8",
+            "%less = icmp ult i8 %shifted, %b",
+            "%greater = icmp uge i32 %quotient, %a",
+            "%both = and i1 %less, %greater",
+            "ret i1 %both",
+            "}",
+        ]);
+    }
+
+    #[test]
+    fn writes_bitwise_operations_at_the_width_of_their_operands() {
+        let lines = emit(r#"
+            target triple = "x86_64-unknown-linux-gnu"
+            define internal function @flip(%a: i64, %b: i64) -> i1 {
+            %entry:
+              %both = and i64 %a, %b
+              %either = or i64 %both, 1
+              %flipped = not i64 %either
+              %odd = trunc i64 %flipped to i1
+              %even = not i1 %odd
+              return i1 %even
+            }
+        "#, false);
+        let body = lines.iter().skip_while(|l| *l != "entry:").skip(1).map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(body, [
+            "%both = and i64 %a, %b",
+            "%either = or i64 %both, 1",
+            "%flipped = xor i64 %either, -1",
+            "%odd = trunc i64 %flipped to i1",
+            "%even = xor i1 %odd, -1",
+            "ret i1 %even",
+            "}",
+        ]);
+    }
+
+    #[test]
+    fn writes_float_operations_with_fast_math_flags() {
+        let lines = emit(r#"
+            target triple = "x86_64-unknown-linux-gnu"
+            define internal function @measure(%a: f64, %b: f32) -> i1 {
+            %entry:
+              %sum = fadd fast f64 %a, 1.5
+              %half = fmul nnan f32 %b, 0.5
+              %rest = frem f64 %sum, %a
+              %negated = fneg f64 %rest
+              %less = fcmp ult f64 %negated, %a
+              %same = fcmp oeq f32 %half, %b
+              %both = and i1 %less, %same
+              return i1 %both
+            }
+        "#, false);
+        let body = lines.iter().skip_while(|l| *l != "entry:").skip(1).map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(body, [
+            // float constants are written as the bits of a double
+            "%sum = fadd fast double %a, 0x3FF8000000000000",
+            "%half = fmul nnan float %b, 0x3FE0000000000000",
+            "%rest = frem double %sum, %a",
+            "%negated = fneg double %rest",
+            "%less = fcmp ult double %negated, %a",
+            "%same = fcmp oeq float %half, %b",
+            "%both = and i1 %less, %same",
+            "ret i1 %both",
+            "}",
+        ]);
+    }
+
+    #[test]
+    fn writes_constant_expressions() {
+        let lines = emit(r#"
+            target triple = "x86_64-unknown-linux-gnu"
+            @table = internal global [2 x i32] [7, -1]
+            @end = internal global i64 add (i64 ptrtoint ([2 x i32]* @table to i64), 8)
+            @big = internal global i96 -2
+
+            define internal function @second(%a: i64) -> i64 {
+            %entry:
+              %address = add i64 %a, add (i64 ptrtoint ([2 x i32]* @table to i64), 4)
+              %pointer = inttoptr i64 %address to i32*
+              %value = load i32* %pointer
+              %wide = sext i32 %value to i64
+              %sum = add i64 %wide, undef
+              return i64 %sum
+            }
+        "#, false);
+        let globals = lines.iter().skip_while(|l| !l.starts_with("@table")).take(3).map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(globals, [
+            "@table = global [2 x i32] [i32 7, i32 -1]",
+            "@end = global i64 add (i64 ptrtoint ([2 x i32]* @table to i64), i64 8)",
+            "@big = global i96 -2",
+        ]);
+        assert!(lines.contains(&"%address = add i64 %a, add (i64 ptrtoint ([2 x i32]* @table to i64), i64 4)".to_string()));
+        assert!(lines.contains(&"%sum = add i64 %wide, undef".to_string()));
+    }
+
+    #[test]
+    fn writes_element_addresses() {
+        let source = r#"
+            target triple = "x86_64-unknown-linux-gnu"
+            define internal function @element(%p: { i8, [4 x i32] }*, %i: i64) -> i32 {
+            %entry:
+              %element = getelementptr { i8, [4 x i32] }* %p, i64 1, i32 1, i64 %i
+              %value = load i32* %element
+              store i32* %element, 7
+              return i32 %value
+            }
+        "#;
+        let lines = emit(source, false);
+        assert!(lines.contains(&"%element = getelementptr { i8, [4 x i32] }, { i8, [4 x i32] }* %p, i64 1, i32 1, i64 %i".to_string()));
+        assert!(lines.contains(&"store i32 7, i32* %element".to_string()));
+        let lines = emit(source, true);
+        assert!(lines.contains(&"%element = getelementptr { i8, [4 x i32] }, ptr %p, i64 1, i32 1, i64 %i".to_string()));
+    }
+
+    #[test]
+    fn reduces_shift_amounts_below_the_width() {
+        let lines = emit(r#"
+            target triple = "x86_64-unknown-linux-gnu"
+            define internal function @shift(%a: i96, %b: i64) -> i64 {
+            %entry:
+              %wide = shl i96 %a, %a
+              %word = lshr i64 %b, %b
+              %low = trunc i96 %wide to i64
+              %sum = add i64 %low, %word
+              return i64 %sum
+            }
+        "#, false);
+        let body = lines.iter().skip_while(|l| *l != "entry:").skip(1).take(4).map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(body, [
+            // 96 is not a power of two, so masking would not do
+            "%t-1 = urem i96 %a, 96",
+            "%wide = shl i96 %a, %t-1",
+            "%t-2 = and i64 %b, 63",
+            "%word = lshr i64 %b, %t-2",
+        ]);
+    }
+}
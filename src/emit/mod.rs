@@ -1,2 +1,5 @@
 
 pub mod asm;
+pub mod c;
+pub mod llvm;
+pub mod object;
@@ -0,0 +1,450 @@
+use std::collections::HashMap;
+use std::io::{Error, Write};
+
+pub const EM_X86_64: u16 = 62;
+
+pub const SHT_PROGBITS: u32 = 1;
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_STRTAB: u32 = 3;
+pub const SHT_RELA: u32 = 4;
+pub const SHT_NOBITS: u32 = 8;
+
+pub const SHF_WRITE: u64 = 0x1;
+pub const SHF_ALLOC: u64 = 0x2;
+pub const SHF_EXECINSTR: u64 = 0x4;
+pub const SHF_INFO_LINK: u64 = 0x40;
+pub const SHF_TLS: u64 = 0x400;
+
+const SHN_COMMON: u16 = 0xfff2;
+
+const HEADER_SIZE: u64 = 64;
+const SECTION_HEADER_SIZE: u64 = 64;
+const SYMBOL_SIZE: u64 = 24;
+const RELA_SIZE: u64 = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    Local,
+    Global,
+    Weak,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    NoType,
+    Object,
+    Function,
+    Tls,
+}
+
+/// Where a symbol is defined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolSection {
+    Undefined,
+    /// A common symbol, allocated by the linker.
+    Common,
+    /// The section with the given index in the object.
+    Section(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub binding: Binding,
+    pub kind: SymbolKind,
+    pub section: SymbolSection,
+    /// The offset in the section, or the alignment of common symbols.
+    pub value: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    pub offset: u64,
+    pub symbol: String,
+    /// The machine specific relocation type, like `R_X86_64_PC32`.
+    pub kind: u32,
+    pub addend: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    pub kind: u32,
+    pub flags: u64,
+    pub align: u64,
+    /// The contents, empty for `SHT_NOBITS` sections.
+    pub data: Vec<u8>,
+    /// The size of `SHT_NOBITS` sections.
+    pub nobits_size: u64,
+    pub relocs: Vec<Relocation>,
+}
+
+impl Section {
+    pub fn size(&self) -> u64 {
+        if self.kind == SHT_NOBITS { self.nobits_size } else { self.data.len() as u64 }
+    }
+
+    /// Pads the section to a multiple of `align` with `fill` bytes and returns the new size.
+    pub fn align_to(&mut self, align: u64, fill: u8) -> u64 {
+        self.align = self.align.max(align);
+        let size = self.size().next_multiple_of(align);
+        if self.kind == SHT_NOBITS {
+            self.nobits_size = size;
+        } else {
+            self.data.resize(size as usize, fill);
+        }
+        size
+    }
+}
+
+/// An ELF64 little-endian relocatable object file.
+#[derive(Debug, Clone)]
+pub struct ObjectFile {
+    machine: u16,
+    sections: Vec<Section>,
+    symbols: Vec<Symbol>,
+}
+
+/// Little-endian encoding of the header fields.
+trait Put {
+    fn put16(&mut self, value: u16);
+    fn put32(&mut self, value: u32);
+    fn put64(&mut self, value: u64);
+}
+
+impl Put for Vec<u8> {
+    fn put16(&mut self, value: u16) {
+        self.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn put32(&mut self, value: u32) {
+        self.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn put64(&mut self, value: u64) {
+        self.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// A string table under construction.
+struct StringTable {
+    data: Vec<u8>,
+}
+
+impl StringTable {
+    fn new() -> Self {
+        Self { data: vec![0] }
+    }
+
+    fn add(&mut self, name: &str) -> u32 {
+        if name.is_empty() {
+            return 0;
+        }
+        let offset = self.data.len() as u32;
+        self.data.extend_from_slice(name.as_bytes());
+        self.data.push(0);
+        offset
+    }
+}
+
+/// A section as it is written, with the fields only known once the file is laid out.
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entry_size: u64,
+}
+
+impl ObjectFile {
+    pub fn new(machine: u16) -> Self {
+        Self { machine, sections: Vec::new(), symbols: Vec::new() }
+    }
+
+    /// Returns the index of the section called `name`, adding it if it does not exist yet.
+    pub fn section(&mut self, name: &str, kind: u32, flags: u64) -> usize {
+        if let Some(index) = self.sections.iter().position(|section| section.name == name) {
+            return index;
+        }
+        self.sections.push(Section { name: name.to_string(), kind, flags, align: 1, data: Vec::new(), nobits_size: 0, relocs: Vec::new() });
+        self.sections.len() - 1
+    }
+
+    pub fn section_mut(&mut self, index: usize) -> &mut Section {
+        &mut self.sections[index]
+    }
+
+    pub fn add_symbol(&mut self, symbol: Symbol) {
+        self.symbols.push(symbol);
+    }
+
+    /// Returns the symbols in file order: local symbols first, then the
+    /// others, including an undefined global symbol for each name that
+    /// relocations refer to but no symbol defines.
+    fn ordered_symbols(&self) -> Vec<Symbol> {
+        let mut symbols: Vec<Symbol> = self.symbols.iter().filter(|symbol| symbol.binding == Binding::Local).cloned().collect();
+        symbols.extend(self.symbols.iter().filter(|symbol| symbol.binding != Binding::Local).cloned());
+        for reloc in self.sections.iter().flat_map(|section| &section.relocs) {
+            if !symbols.iter().any(|symbol| symbol.name == reloc.symbol) {
+                symbols.push(Symbol {
+                    name: reloc.symbol.clone(),
+                    binding: Binding::Global,
+                    kind: SymbolKind::NoType,
+                    section: SymbolSection::Undefined,
+                    value: 0,
+                    size: 0,
+                });
+            }
+        }
+        symbols
+    }
+
+    pub fn write(&self, file: &mut impl Write) -> Result<(), Error> {
+        let symbols = self.ordered_symbols();
+        // the null symbol comes first
+        let symbol_indices: HashMap<&str, u64> = symbols.iter().enumerate().map(|(index, symbol)| (symbol.name.as_str(), index as u64 + 1)).collect();
+        let first_global = 1 + symbols.iter().filter(|symbol| symbol.binding == Binding::Local).count() as u32;
+
+        let mut strtab = StringTable::new();
+        let mut symtab = vec![0; SYMBOL_SIZE as usize];
+        for symbol in &symbols {
+            let binding = match symbol.binding {
+                Binding::Local => 0,
+                Binding::Global => 1,
+                Binding::Weak => 2,
+            };
+            let kind = match symbol.kind {
+                SymbolKind::NoType => 0,
+                SymbolKind::Object => 1,
+                SymbolKind::Function => 2,
+                SymbolKind::Tls => 6,
+            };
+            let section = match symbol.section {
+                SymbolSection::Undefined => 0,
+                SymbolSection::Common => SHN_COMMON,
+                SymbolSection::Section(index) => index as u16 + 1,
+            };
+            symtab.put32(strtab.add(&symbol.name));
+            symtab.push(binding << 4 | kind);
+            symtab.push(0);
+            symtab.put16(section);
+            symtab.put64(symbol.value);
+            symtab.put64(symbol.size);
+        }
+
+        let mut shstrtab = StringTable::new();
+        let mut headers = Vec::new();
+        let mut contents: Vec<Vec<u8>> = Vec::new();
+        for section in &self.sections {
+            headers.push(SectionHeader {
+                name: shstrtab.add(&section.name),
+                kind: section.kind,
+                flags: section.flags,
+                offset: 0,
+                size: section.size(),
+                link: 0,
+                info: 0,
+                align: section.align,
+                entry_size: 0,
+            });
+            contents.push(section.data.clone());
+        }
+
+        // the symbol table follows the relocation sections
+        let relocated = self.sections.iter().filter(|section| !section.relocs.is_empty()).count();
+        let symtab_index = (self.sections.len() + relocated + 1) as u32;
+        for (index, section) in self.sections.iter().enumerate() {
+            if section.relocs.is_empty() {
+                continue;
+            }
+            let mut data = Vec::new();
+            for reloc in &section.relocs {
+                data.put64(reloc.offset);
+                data.put64(symbol_indices[reloc.symbol.as_str()] << 32 | reloc.kind as u64);
+                data.put64(reloc.addend as u64);
+            }
+            headers.push(SectionHeader {
+                name: shstrtab.add(&format!(".rela{}", section.name)),
+                kind: SHT_RELA,
+                flags: SHF_INFO_LINK,
+                offset: 0,
+                size: data.len() as u64,
+                link: symtab_index,
+                info: index as u32 + 1,
+                align: 8,
+                entry_size: RELA_SIZE,
+            });
+            contents.push(data);
+        }
+
+        headers.push(SectionHeader {
+            name: shstrtab.add(".symtab"),
+            kind: SHT_SYMTAB,
+            flags: 0,
+            offset: 0,
+            size: symtab.len() as u64,
+            link: symtab_index + 1,
+            info: first_global,
+            align: 8,
+            entry_size: SYMBOL_SIZE,
+        });
+        contents.push(symtab);
+        headers.push(SectionHeader { name: shstrtab.add(".strtab"), kind: SHT_STRTAB, flags: 0, offset: 0, size: strtab.data.len() as u64, link: 0, info: 0, align: 1, entry_size: 0 });
+        contents.push(strtab.data);
+        let shstrtab_name = shstrtab.add(".shstrtab");
+        headers.push(SectionHeader { name: shstrtab_name, kind: SHT_STRTAB, flags: 0, offset: 0, size: shstrtab.data.len() as u64, link: 0, info: 0, align: 1, entry_size: 0 });
+        contents.push(shstrtab.data);
+
+        // lay out the contents after the file header, then the section headers
+        let mut body = Vec::new();
+        for (header, data) in headers.iter_mut().zip(&contents) {
+            let offset = (HEADER_SIZE + body.len() as u64).next_multiple_of(header.align.max(1));
+            body.resize((offset - HEADER_SIZE) as usize, 0);
+            header.offset = offset;
+            body.extend_from_slice(data);
+        }
+        let section_headers = (HEADER_SIZE + body.len() as u64).next_multiple_of(8);
+        body.resize((section_headers - HEADER_SIZE) as usize, 0);
+
+        let mut out = Vec::new();
+        out.extend_from_slice(b"\x7fELF");
+        // 64-bit, little-endian, version 1, System V ABI
+        out.extend_from_slice(&[2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        out.put16(1); // ET_REL
+        out.put16(self.machine);
+        out.put32(1);
+        out.put64(0); // entry point
+        out.put64(0); // program headers
+        out.put64(section_headers);
+        out.put32(0); // flags
+        out.put16(HEADER_SIZE as u16);
+        out.put16(0); // program header size
+        out.put16(0); // program header count
+        out.put16(SECTION_HEADER_SIZE as u16);
+        out.put16(headers.len() as u16 + 1);
+        out.put16(headers.len() as u16);
+        out.extend_from_slice(&body);
+
+        // the null section
+        out.resize(out.len() + SECTION_HEADER_SIZE as usize, 0);
+        for header in &headers {
+            out.put32(header.name);
+            out.put32(header.kind);
+            out.put64(header.flags);
+            out.put64(0); // address
+            out.put64(if header.kind == SHT_NOBITS { 0 } else { header.offset });
+            out.put64(header.size);
+            out.put32(header.link);
+            out.put32(header.info);
+            out.put64(header.align);
+            out.put64(header.entry_size);
+        }
+        file.write_all(&out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn name_at(bytes: &[u8], offset: usize) -> &str {
+        let end = bytes[offset..].iter().position(|byte| *byte == 0).unwrap();
+        std::str::from_utf8(&bytes[offset..offset + end]).unwrap()
+    }
+
+    /// The fields of a section header the tests look at.
+    struct Header {
+        name: String,
+        kind: u32,
+        offset: usize,
+        size: usize,
+        link: u32,
+        info: u32,
+    }
+
+    fn headers(bytes: &[u8]) -> Vec<Header> {
+        let table = u64_at(bytes, 0x28) as usize;
+        let count = u16_at(bytes, 0x3c) as usize;
+        let names = table + 64 * u16_at(bytes, 0x3e) as usize;
+        let names = u64_at(bytes, names + 0x18) as usize;
+        (0..count).map(|index| {
+            let header = table + 64 * index;
+            Header {
+                name: name_at(bytes, names + u32_at(bytes, header) as usize).to_string(),
+                kind: u32_at(bytes, header + 4),
+                offset: u64_at(bytes, header + 0x18) as usize,
+                size: u64_at(bytes, header + 0x20) as usize,
+                link: u32_at(bytes, header + 0x28),
+                info: u32_at(bytes, header + 0x2c),
+            }
+        }).collect()
+    }
+
+    fn object() -> Vec<u8> {
+        let mut object = ObjectFile::new(EM_X86_64);
+        let data = object.section(".data", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE);
+        let section = object.section_mut(data);
+        section.data.extend_from_slice(&[1, 2, 3]);
+        let offset = section.align_to(8, 0);
+        section.relocs.push(Relocation { offset, symbol: "external".to_string(), kind: 1, addend: -4 });
+        section.data.extend_from_slice(&[0; 8]);
+        object.add_symbol(Symbol { name: "pointer".to_string(), binding: Binding::Global, kind: SymbolKind::Object, section: SymbolSection::Section(data), value: offset, size: 8 });
+        object.add_symbol(Symbol { name: "bytes".to_string(), binding: Binding::Local, kind: SymbolKind::Object, section: SymbolSection::Section(data), value: 0, size: 3 });
+        let mut bytes = Vec::new();
+        object.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn writes_the_file_header() {
+        let bytes = object();
+        assert_eq!(bytes[..7], *b"\x7fELF\x02\x01\x01");
+        assert_eq!(u16_at(&bytes, 0x10), 1);
+        assert_eq!(u16_at(&bytes, 0x12), EM_X86_64);
+        assert_eq!(u64_at(&bytes, 0x28) % 8, 0);
+        assert_eq!(u64_at(&bytes, 0x28) as usize + 64 * u16_at(&bytes, 0x3c) as usize, bytes.len());
+    }
+
+    #[test]
+    fn writes_sections_symbols_and_relocations() {
+        let bytes = object();
+        let headers = headers(&bytes);
+        let names = headers.iter().map(|header| header.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["", ".data", ".rela.data", ".symtab", ".strtab", ".shstrtab"]);
+
+        let data = &headers[1];
+        assert_eq!(bytes[data.offset..data.offset + data.size], [1, 2, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        // the null symbol, then local symbols, then the others
+        let (symtab, strtab) = (&headers[3], &headers[4]);
+        assert_eq!((symtab.link, symtab.info), (4, 2));
+        let symbols = (0..symtab.size / 24).map(|index| {
+            let symbol = symtab.offset + 24 * index;
+            (name_at(&bytes, strtab.offset + u32_at(&bytes, symbol) as usize), bytes[symbol + 4], u16_at(&bytes, symbol + 6), u64_at(&bytes, symbol + 8))
+        }).collect::<Vec<_>>();
+        assert_eq!(symbols, [("", 0, 0, 0), ("bytes", 0x01, 1, 0), ("pointer", 0x11, 1, 8), ("external", 0x10, 0, 0)]);
+
+        let rela = &headers[2];
+        assert_eq!((rela.kind, rela.link, rela.info, rela.size), (SHT_RELA, 3, 1, 24));
+        assert_eq!(u64_at(&bytes, rela.offset), 8);
+        assert_eq!(u64_at(&bytes, rela.offset + 8), 3 << 32 | 1);
+        assert_eq!(u64_at(&bytes, rela.offset + 16) as i64, -4);
+    }
+}
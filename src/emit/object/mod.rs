@@ -0,0 +1,41 @@
+use crate::ir::builder::ctx::IRContext;
+use crate::targets::triple::Arch;
+use crate::emit::asm::FrameOptions;
+use crate::error::Error;
+
+use std::io::Write;
+
+pub mod elf;
+pub mod wasm;
+pub mod x86_64;
+
+/// Writes modules as relocatable object files, without going through an assembler.
+pub struct ObjectEmitter {
+    ctx: IRContext,
+    frame_options: FrameOptions,
+}
+
+impl ObjectEmitter {
+    pub fn new(ctx: IRContext) -> Self {
+        Self {
+            ctx,
+            frame_options: FrameOptions::default(),
+        }
+    }
+
+    pub fn with_frame_options(ctx: IRContext, frame_options: FrameOptions) -> Self {
+        Self {
+            ctx,
+            frame_options,
+        }
+    }
+
+    pub fn emit_module(&mut self, file: &mut impl Write) -> Result<(), Error> {
+        let triple = self.ctx.get_module().target_triple();
+        match triple.arch() {
+            Arch::X86_64 => Ok(x86_64::emit_module(self.ctx.clone(), self.frame_options, file)?),
+            Arch::Wasm32 => Ok(wasm::emit_module(self.ctx.clone(), file)?),
+            _ => Err(Error::UnsupportedTarget(triple.clone())),
+        }
+    }
+}
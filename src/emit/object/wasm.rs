@@ -0,0 +1,417 @@
+use std::collections::HashMap;
+use std::io::{Error, Write};
+use crate::ir::builder::ctx::IRContext;
+use crate::ir::linkage::Linkage;
+use crate::emit::asm::data::{self, Datum};
+use crate::emit::asm::unsupported;
+use crate::emit::object::wasm::abi::signature;
+use crate::emit::object::wasm::inst::{FuncType, Put, ValType};
+use crate::emit::object::wasm::lower::FunctionLowering;
+use crate::targets::layout::DataLayout;
+
+pub mod abi;
+pub mod cfg;
+pub mod inst;
+pub mod lower;
+
+const SECTION_CUSTOM: u8 = 0;
+const SECTION_TYPE: u8 = 1;
+const SECTION_IMPORT: u8 = 2;
+const SECTION_FUNCTION: u8 = 3;
+const SECTION_TABLE: u8 = 4;
+const SECTION_MEMORY: u8 = 5;
+const SECTION_GLOBAL: u8 = 6;
+const SECTION_EXPORT: u8 = 7;
+const SECTION_ELEMENT: u8 = 9;
+const SECTION_CODE: u8 = 10;
+const SECTION_DATA: u8 = 11;
+
+const KIND_FUNCTION: u8 = 0;
+const KIND_TABLE: u8 = 1;
+const KIND_MEMORY: u8 = 2;
+const KIND_GLOBAL: u8 = 3;
+
+const PAGE_SIZE: u64 = 64 * 1024;
+/// Size of the stack, which sits at the bottom of memory and grows down, so
+/// that overflowing it traps instead of overwriting globals.
+const STACK_SIZE: u64 = 64 * 1024;
+/// The module imports are taken from, as with clang and wasm-ld.
+const IMPORT_MODULE: &str = "env";
+
+/// Where the address of a global variable comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GlobalAddress {
+    /// A global defined in the module, at a fixed address.
+    Memory(u32),
+    /// A global defined elsewhere, whose address is held by the imported wasm global with that index.
+    Imported(u32),
+}
+
+/// What the code of a function needs to know about the rest of the module.
+pub struct Symbols<'a> {
+    pub layout: &'a DataLayout,
+    /// The index of each function. A pointer to a function is its index in the table, which is one more.
+    pub functions: HashMap<String, u32>,
+    pub globals: HashMap<String, GlobalAddress>,
+    /// The index of the mutable wasm global holding the stack pointer.
+    pub stack_pointer: u32,
+}
+
+/// The function types of a module, each listed once.
+#[derive(Debug, Default)]
+pub struct Types {
+    types: Vec<FuncType>,
+}
+
+impl Types {
+    /// Returns the index of `ty`, adding it if it is new.
+    pub fn intern(&mut self, ty: FuncType) -> u32 {
+        match self.types.iter().position(|t| *t == ty) {
+            Some(index) => index as u32,
+            None => {
+                self.types.push(ty);
+                self.types.len() as u32 - 1
+            }
+        }
+    }
+}
+
+fn section(out: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    out.push(id);
+    out.put_bytes(contents);
+}
+
+/// Writes a vector of `count` already encoded entries.
+fn vector(count: usize, entries: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.put_u32(count as u32);
+    out.extend_from_slice(entries);
+    out
+}
+
+/// Encodes the constant expression `i32.const value`.
+fn i32_const_expr(out: &mut Vec<u8>, value: u32) {
+    out.push(0x41);
+    out.put_i64(value as i32 as i64);
+    out.push(0x0b);
+}
+
+/// Writes a module as a WebAssembly binary. Functions and globals defined
+/// elsewhere are imported from `env`, with imported globals holding the
+/// address of the variable. The linear memory holds the stack, followed by
+/// the globals of the module, and is exported along with the function
+/// table and everything that is not private.
+pub fn emit_module(ctx: IRContext, file: &mut impl Write) -> Result<(), Error> {
+    let module = ctx.get_module();
+    let layout = module.data_layout();
+    let functions = module.get_functions().iter().map(|f| f.borrow().clone()).collect::<Vec<_>>();
+    let globals = module.get_global_variables().iter().map(|g| g.borrow().clone()).collect::<Vec<_>>();
+    let (imported, defined): (Vec<_>, Vec<_>) = functions.iter().partition(|f| f.is_external() || f.get_blocks().is_empty());
+
+    let mut types = Types::default();
+    let mut symbols = Symbols { layout, functions: HashMap::new(), globals: HashMap::new(), stack_pointer: 0 };
+    let mut imports = Vec::new();
+    let mut import_count = 0;
+    for (index, function) in imported.iter().chain(&defined).enumerate() {
+        symbols.functions.insert(function.get_name(), index as u32);
+    }
+    for function in &imported {
+        let ty = types.intern(signature(&function.get_type(), function.is_var_arg())?);
+        imports.put_name(IMPORT_MODULE);
+        imports.put_name(&function.get_name());
+        imports.push(KIND_FUNCTION);
+        imports.put_u32(ty);
+        import_count += 1;
+    }
+    let mut global_count = 0;
+    for global in globals.iter().filter(|g| g.is_external()) {
+        imports.put_name(IMPORT_MODULE);
+        imports.put_name(&global.get_name());
+        imports.push(KIND_GLOBAL);
+        imports.extend_from_slice(&[ValType::I32.code(), 0x00]);
+        symbols.globals.insert(global.get_name(), GlobalAddress::Imported(global_count));
+        import_count += 1;
+        global_count += 1;
+    }
+    symbols.stack_pointer = global_count;
+
+    // thread-local globals are just globals, as an instance only ever runs one thread
+    let defined_globals = globals.iter().filter(|g| !g.is_external()).collect::<Vec<_>>();
+    let mut address = STACK_SIZE;
+    let mut addresses = Vec::new();
+    for global in &defined_globals {
+        let (size, align) = data::size_and_align(layout, global);
+        let offset = address.next_multiple_of(align.max(1));
+        addresses.push(offset);
+        symbols.globals.insert(global.get_name(), GlobalAddress::Memory(offset as u32));
+        address = offset + size;
+    }
+    let heap_base = address.next_multiple_of(16);
+    if heap_base > u32::MAX as u64 {
+        return Err(unsupported(format!("{} bytes of globals", heap_base - STACK_SIZE)));
+    }
+
+    let mut contents = Vec::new();
+    for (global, &offset) in defined_globals.iter().zip(&addresses) {
+        if *global.get_linkage() == Linkage::AppendingLinkage {
+            return Err(unsupported(format!("appending linkage on {}", global.get_name())));
+        }
+        contents.resize((offset - STACK_SIZE) as usize, 0);
+        for datum in data::global_data(layout, global)? {
+            match datum {
+                Datum::Zero(size) => contents.resize(contents.len() + size as usize, 0),
+                Datum::Int(size, value) => contents.extend_from_slice(&value.to_le_bytes()[..size as usize]),
+                Datum::F32(value) => contents.extend_from_slice(&value.to_le_bytes()),
+                Datum::F64(value) => contents.extend_from_slice(&value.to_le_bytes()),
+                Datum::Bytes(bytes) => contents.extend_from_slice(&bytes),
+                Datum::Symbol(size, symbol, addend) => {
+                    let value = match (symbols.functions.get(&symbol), symbols.globals.get(&symbol)) {
+                        (Some(index), _) => index + 1,
+                        (None, Some(GlobalAddress::Memory(address))) => *address,
+                        (None, Some(GlobalAddress::Imported(_))) => return Err(unsupported(format!("address of external global {} in data", symbol))),
+                        (None, None) => return Err(unsupported(format!("address of unknown symbol {} in data", symbol))),
+                    };
+                    contents.extend_from_slice(&(value as u64).wrapping_add(addend as u64).to_le_bytes()[..size as usize]);
+                }
+            }
+        }
+    }
+    // the rest of memory starts out zeroed
+    while contents.last() == Some(&0) {
+        contents.pop();
+    }
+
+    let mut function_section = Vec::new();
+    let mut code = Vec::new();
+    for function in &defined {
+        let ty = types.intern(signature(&function.get_type(), function.is_var_arg())?);
+        function_section.put_u32(ty);
+
+        let lowered = FunctionLowering::new(function, &symbols, &mut types).lower()?;
+        let mut body = Vec::new();
+        // locals are declared in runs of the same type
+        let mut runs: Vec<(u32, ValType)> = Vec::new();
+        for &local in &lowered.locals {
+            match runs.last_mut() {
+                Some((count, ty)) if *ty == local => *count += 1,
+                _ => runs.push((1, local)),
+            }
+        }
+        body.put_u32(runs.len() as u32);
+        for (count, ty) in runs {
+            body.put_u32(count);
+            body.push(ty.code());
+        }
+        for inst in &lowered.code {
+            inst.encode(&mut body);
+        }
+        code.put_bytes(&body);
+    }
+
+    let function_count = imported.len() + defined.len();
+    let mut exports = Vec::new();
+    let mut export_count = 0;
+    let mut export = |name: &str, kind: u8, index: u32| {
+        exports.put_name(name);
+        exports.push(kind);
+        exports.put_u32(index);
+        export_count += 1;
+    };
+    export("memory", KIND_MEMORY, 0);
+    export("__indirect_function_table", KIND_TABLE, 0);
+    for function in defined.iter().filter(|f| *f.get_linkage() != Linkage::PrivateLinkage) {
+        export(&function.get_name(), KIND_FUNCTION, symbols.functions[&function.get_name()]);
+    }
+
+    // the stack pointer, then the addresses of the exported globals and the end of the globals
+    let mut global_section = Vec::new();
+    global_section.extend_from_slice(&[ValType::I32.code(), 0x01]);
+    i32_const_expr(&mut global_section, STACK_SIZE as u32);
+    let mut defined_global_count = 1;
+    for (global, &offset) in defined_globals.iter().zip(&addresses) {
+        if *global.get_linkage() == Linkage::PrivateLinkage {
+            continue;
+        }
+        global_section.extend_from_slice(&[ValType::I32.code(), 0x00]);
+        i32_const_expr(&mut global_section, offset as u32);
+        export(&global.get_name(), KIND_GLOBAL, global_count + defined_global_count);
+        defined_global_count += 1;
+    }
+    global_section.extend_from_slice(&[ValType::I32.code(), 0x00]);
+    i32_const_expr(&mut global_section, heap_base as u32);
+    export("__heap_base", KIND_GLOBAL, global_count + defined_global_count);
+    defined_global_count += 1;
+
+    let mut type_section = Vec::new();
+    for ty in &types.types {
+        type_section.push(0x60);
+        type_section.put_u32(ty.params.len() as u32);
+        type_section.extend(ty.params.iter().map(|t| t.code()));
+        type_section.put_u32(ty.results.len() as u32);
+        type_section.extend(ty.results.iter().map(|t| t.code()));
+    }
+
+    // every function is in the table, after the null entry
+    let mut table_section = vec![0x70, 0x00];
+    table_section.put_u32(function_count as u32 + 1);
+    let mut element_section = vec![0x00];
+    i32_const_expr(&mut element_section, 1);
+    element_section.put_u32(function_count as u32);
+    for index in 0..function_count {
+        element_section.put_u32(index as u32);
+    }
+
+    let mut memory_section = vec![0x00];
+    memory_section.put_u32(heap_base.div_ceil(PAGE_SIZE) as u32);
+
+    let mut names = Vec::new();
+    names.put_u32(function_count as u32);
+    for (index, function) in imported.iter().chain(&defined).enumerate() {
+        names.put_u32(index as u32);
+        names.put_name(&function.get_name());
+    }
+    let mut name_section = Vec::new();
+    name_section.put_name("name");
+    // the subsection of function names
+    name_section.push(1);
+    name_section.put_bytes(&names);
+
+    let mut out = Vec::new();
+    out.extend_from_slice(b"\0asm");
+    out.extend_from_slice(&1u32.to_le_bytes());
+    section(&mut out, SECTION_TYPE, &vector(types.types.len(), &type_section));
+    section(&mut out, SECTION_IMPORT, &vector(import_count, &imports));
+    section(&mut out, SECTION_FUNCTION, &vector(defined.len(), &function_section));
+    section(&mut out, SECTION_TABLE, &vector(1, &table_section));
+    section(&mut out, SECTION_MEMORY, &vector(1, &memory_section));
+    section(&mut out, SECTION_GLOBAL, &vector(defined_global_count as usize, &global_section));
+    section(&mut out, SECTION_EXPORT, &vector(export_count, &exports));
+    section(&mut out, SECTION_ELEMENT, &vector(1, &element_section));
+    section(&mut out, SECTION_CODE, &vector(defined.len(), &code));
+    if !contents.is_empty() {
+        let mut segment = vec![0x00];
+        i32_const_expr(&mut segment, STACK_SIZE as u32);
+        segment.put_bytes(&contents);
+        section(&mut out, SECTION_DATA, &vector(1, &segment));
+    }
+    section(&mut out, SECTION_CUSTOM, &name_section);
+    file.write_all(&out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::parser::parse_module;
+
+    fn emit(source: &str) -> Vec<u8> {
+        let module = parse_module(source).unwrap_or_else(|e| panic!("{}", e));
+        let mut bytes = Vec::new();
+        emit_module(IRContext::new(module), &mut bytes).unwrap();
+        bytes
+    }
+
+    fn read_u32(bytes: &[u8], offset: &mut usize) -> u32 {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = bytes[*offset];
+            *offset += 1;
+            value |= ((byte & 0x7f) as u32) << shift;
+            if byte & 0x80 == 0 {
+                return value;
+            }
+            shift += 7;
+        }
+    }
+
+    /// Splits a module into the ids and contents of its sections.
+    fn sections(bytes: &[u8]) -> Vec<(u8, &[u8])> {
+        assert_eq!(bytes[..8], *b"\0asm\x01\0\0\0");
+        let mut sections = Vec::new();
+        let mut offset = 8;
+        while offset < bytes.len() {
+            let id = bytes[offset];
+            offset += 1;
+            let size = read_u32(bytes, &mut offset) as usize;
+            sections.push((id, &bytes[offset..offset + size]));
+            offset += size;
+        }
+        sections
+    }
+
+    #[test]
+    fn writes_sections_imports_exports_and_data() {
+        let bytes = emit(r#"
+            target triple = "wasm32-unknown-unknown"
+            @message = private constant [3 x i8] c"hi\00"
+            @count = internal global i32 7
+
+            declare external function @puts(%s: [3 x i8]*) -> i32
+
+            define internal function @main() -> i32 {
+            %entry:
+              %printed = call [3 x i8]* -> i32 @puts(@message)
+              return i32 %printed
+            }
+        "#);
+        let sections = sections(&bytes);
+        let ids = sections.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        assert_eq!(ids, [SECTION_TYPE, SECTION_IMPORT, SECTION_FUNCTION, SECTION_TABLE, SECTION_MEMORY, SECTION_GLOBAL, SECTION_EXPORT, SECTION_ELEMENT, SECTION_CODE, SECTION_DATA, SECTION_CUSTOM]);
+
+        let contents = |id| sections.iter().find(|(section, _)| *section == id).unwrap().1;
+        let contains = |id, needle: &[u8]| contents(id).windows(needle.len()).any(|window| window == needle);
+        // puts is imported from env, and main is the function after it
+        assert!(contains(SECTION_IMPORT, b"\x03env\x04puts\x00"));
+        assert!(contains(SECTION_EXPORT, b"\x06memory\x02\x00"));
+        assert!(contains(SECTION_EXPORT, b"\x04main\x00\x01"));
+        assert!(contains(SECTION_EXPORT, b"\x0b__heap_base\x03"));
+        // the globals follow the stack, in the order they are defined, without the zeros ending memory
+        assert_eq!(contents(SECTION_DATA), b"\x01\x00\x41\x80\x80\x04\x0b\x05hi\0\0\x07");
+        // main calls puts and returns what it got
+        assert!(contains(SECTION_CODE, &[0x10, 0x00]));
+        assert!(contains(SECTION_CUSTOM, b"\x04name"));
+    }
+
+    #[test]
+    fn imports_external_globals_by_address() {
+        let bytes = emit(r#"
+            target triple = "wasm32-unknown-unknown"
+            @errno = external global i32
+
+            define internal function @get() -> i32 {
+            %entry:
+              %value = load i32* @errno
+              return i32 %value
+            }
+        "#);
+        let sections = sections(&bytes);
+        let imports = sections.iter().find(|(id, _)| *id == SECTION_IMPORT).unwrap().1;
+        // one import: an immutable i32 global
+        assert_eq!(imports, b"\x01\x03env\x05errno\x03\x7f\x00");
+        assert!(sections.iter().all(|(id, _)| *id != SECTION_DATA));
+    }
+
+    #[test]
+    fn resolves_constant_addresses_in_data_and_code() {
+        let bytes = emit(r#"
+            target triple = "wasm32-unknown-unknown"
+            @table = internal global [2 x i32] [7, -1]
+            @end = internal global i64 add (i64 ptrtoint ([2 x i32]* @table to i64), 8)
+
+            define internal function @second() -> i32 {
+            %entry:
+              %address = add i32 0, add (i32 ptrtoint ([2 x i32]* @table to i32), 4)
+              %pointer = inttoptr i32 %address to i32*
+              %value = load i32* %pointer
+              return i32 %value
+            }
+        "#);
+        let sections = sections(&bytes);
+        let contents = |id| sections.iter().find(|(section, _)| *section == id).unwrap().1;
+        // @table is at 65536, so @end holds 65544
+        assert_eq!(contents(SECTION_DATA), b"\x01\x00\x41\x80\x80\x04\x0b\x0b\x07\0\0\0\xff\xff\xff\xff\x08\0\x01");
+        // the address is the global plus the offset
+        let code = contents(SECTION_CODE);
+        assert!(code.windows(7).any(|window| window == [0x41, 0x80, 0x80, 0x04, 0x41, 0x04, 0x6a]));
+    }
+}
@@ -0,0 +1,66 @@
+use crate::emit::asm::unsupported;
+use crate::emit::object::wasm::inst::{FuncType, ValType};
+use crate::ir::values::value::Type;
+use crate::targets::layout::DataLayout;
+use std::io::Error;
+
+pub fn is_aggregate(ty: &Type) -> bool {
+    ty.is_struct() || ty.is_array()
+}
+
+/// Returns the type of the local holding a value of type `ty`. Integers
+/// narrower than 32 bits are held sign extended, except `i1`, which is 0 or
+/// 1, and aggregates are held as the address of a copy in linear memory.
+pub fn val_type(ty: &Type) -> Result<ValType, Error> {
+    match ty {
+        Type::Integer(1 | 8 | 16 | 32) | Type::Pointer(_) | Type::FunctionType(_, _) => Ok(ValType::I32),
+        Type::Integer(64) => Ok(ValType::I64),
+        Type::Float(32) => Ok(ValType::F32),
+        Type::Float(64) => Ok(ValType::F64),
+        ty if is_aggregate(ty) => Ok(ValType::I32),
+        ty => Err(unsupported(format!("values of type {} are not supported by the wasm backend", ty))),
+    }
+}
+
+/// Returns the WebAssembly signature of a function of type `ty`, following
+/// the C ABI of clang for wasm32: aggregates are passed as the address of a
+/// copy and returned through a buffer whose address comes first, and the
+/// variable arguments of variadic functions are written to a buffer in
+/// memory whose address comes last.
+pub fn signature(ty: &Type, is_var_arg: bool) -> Result<FuncType, Error> {
+    let ret = ty.get_function_return_type();
+    let mut params = Vec::new();
+    if is_aggregate(&ret) {
+        params.push(ValType::I32);
+    }
+    for arg in ty.get_function_argument_types() {
+        params.push(val_type(arg)?);
+    }
+    if is_var_arg {
+        params.push(ValType::I32);
+    }
+    let results = match ret {
+        Type::Void => vec![],
+        ret if is_aggregate(&ret) => vec![],
+        ret => vec![val_type(&ret)?],
+    };
+    Ok(FuncType { params, results })
+}
+
+/// Returns the offset of each variable argument in the buffer passed to a
+/// variadic function, and the size of the buffer. Each argument is aligned
+/// to its own alignment, with small integers taking 4 bytes.
+pub fn var_arg_offsets(layout: &DataLayout, args: &[Type]) -> (Vec<u64>, u64) {
+    let mut offsets = Vec::new();
+    let mut size: u64 = 0;
+    for arg in args {
+        let (arg_size, align) = match arg {
+            Type::Integer(1 | 8 | 16) => (4, 4),
+            arg => (layout.size_of(arg), layout.align_of(arg)),
+        };
+        let offset = size.next_multiple_of(align);
+        offsets.push(offset);
+        size = offset + arg_size;
+    }
+    (offsets, size)
+}
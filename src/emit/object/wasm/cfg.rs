@@ -0,0 +1,162 @@
+use crate::emit::asm::unsupported;
+use crate::ir::values::function::Function;
+use crate::ir::values::value::ValueEntity;
+use std::collections::HashMap;
+use std::io::Error;
+
+/// The control flow graph of a function, with the block order and dominator
+/// tree that rebuilding structured control flow from it needs.
+pub struct Cfg {
+    /// The successors of each block, by index in the function. A block
+    /// branching to the same successor twice lists it twice.
+    pub succs: Vec<Vec<usize>>,
+    pub preds: Vec<Vec<usize>>,
+    /// The blocks reachable from the entry, in reverse postorder.
+    pub rpo: Vec<usize>,
+    /// The position of each block in `rpo`, or `None` if it is unreachable.
+    rpo_index: Vec<Option<usize>>,
+    /// The immediate dominator of each reachable block, the entry being its own.
+    idom: Vec<usize>,
+}
+
+impl Cfg {
+    /// Computes the graph of `func`, which has to be reducible, as WebAssembly
+    /// can only express loops with a single entry.
+    pub fn new(func: &Function) -> Result<Self, Error> {
+        let blocks = func.get_blocks();
+        let indices = blocks.iter().enumerate().map(|(i, block)| (block.borrow().get_name(), i)).collect::<HashMap<_, _>>();
+        let mut succs = vec![Vec::new(); blocks.len()];
+        let mut preds = vec![Vec::new(); blocks.len()];
+        for (i, block) in blocks.iter().enumerate() {
+            let Some(ValueEntity::Instruction(terminator)) = block.borrow().get_instructions().last().cloned() else {
+                continue;
+            };
+            for succ in terminator.get_successors() {
+                let succ = *indices.get(&succ).ok_or_else(|| unsupported(format!("branch to unknown block {}", succ)))?;
+                succs[i].push(succ);
+                preds[succ].push(i);
+            }
+        }
+
+        // postorder by depth-first search from the entry
+        let mut postorder = Vec::new();
+        let mut visited = vec![false; blocks.len()];
+        if !blocks.is_empty() {
+            let mut stack = vec![(0, 0)];
+            visited[0] = true;
+            while let Some((block, next)) = stack.pop() {
+                if let Some(&succ) = succs[block].get(next) {
+                    stack.push((block, next + 1));
+                    if !visited[succ] {
+                        visited[succ] = true;
+                        stack.push((succ, 0));
+                    }
+                } else {
+                    postorder.push(block);
+                }
+            }
+        }
+        let rpo = postorder.into_iter().rev().collect::<Vec<_>>();
+        let mut rpo_index = vec![None; blocks.len()];
+        for (i, block) in rpo.iter().enumerate() {
+            rpo_index[*block] = Some(i);
+        }
+
+        let mut cfg = Self { succs, preds, rpo, rpo_index, idom: vec![usize::MAX; blocks.len()] };
+        cfg.compute_dominators();
+        for &block in &cfg.rpo {
+            for &succ in &cfg.succs[block] {
+                if cfg.is_backward(block, succ) && !cfg.dominates(succ, block) {
+                    return Err(unsupported(format!("irreducible control flow in function {}", func.get_name())));
+                }
+            }
+        }
+        Ok(cfg)
+    }
+
+    /// Computes the immediate dominators with the iterative algorithm of
+    /// Cooper, Harvey and Kennedy.
+    fn compute_dominators(&mut self) {
+        let Some(&entry) = self.rpo.first() else {
+            return;
+        };
+        self.idom[entry] = entry;
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in &self.rpo[1..] {
+                let mut new_idom = None;
+                for &pred in &self.preds[block] {
+                    if self.idom[pred] == usize::MAX {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => pred,
+                        Some(other) => self.intersect(pred, other),
+                    });
+                }
+                if let Some(new_idom) = new_idom {
+                    if self.idom[block] != new_idom {
+                        self.idom[block] = new_idom;
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+
+    fn intersect(&self, mut a: usize, mut b: usize) -> usize {
+        while a != b {
+            while self.rpo_index[a] > self.rpo_index[b] {
+                a = self.idom[a];
+            }
+            while self.rpo_index[b] > self.rpo_index[a] {
+                b = self.idom[b];
+            }
+        }
+        a
+    }
+
+    pub fn is_reachable(&self, block: usize) -> bool {
+        self.rpo_index[block].is_some()
+    }
+
+    /// Returns whether `a` dominates `b`, which are both reachable.
+    pub fn dominates(&self, a: usize, mut b: usize) -> bool {
+        loop {
+            if a == b {
+                return true;
+            }
+            if self.idom[b] == b {
+                return false;
+            }
+            b = self.idom[b];
+        }
+    }
+
+    /// Returns whether the edge from `from` to `to` goes back to a block that
+    /// comes earlier in reverse postorder, which makes `to` a loop header.
+    pub fn is_backward(&self, from: usize, to: usize) -> bool {
+        self.rpo_index[to] <= self.rpo_index[from]
+    }
+
+    pub fn is_loop_header(&self, block: usize) -> bool {
+        self.preds[block].iter().any(|&pred| self.is_reachable(pred) && self.is_backward(pred, block))
+    }
+
+    /// Returns whether control reaches `block` forward from more than one place,
+    /// so that its code can't just be placed after the branch to it.
+    pub fn is_merge_node(&self, block: usize) -> bool {
+        self.preds[block].iter().filter(|&&pred| self.is_reachable(pred) && !self.is_backward(pred, block)).count() > 1
+    }
+
+    /// Returns the blocks `block` immediately dominates, in reverse postorder.
+    pub fn dominator_children(&self, block: usize) -> Vec<usize> {
+        self.rpo.iter().copied().filter(|&child| child != block && self.idom[child] == block).collect()
+    }
+
+    /// Returns the position of a reachable block in reverse postorder.
+    pub fn rpo_number(&self, block: usize) -> usize {
+        self.rpo_index[block].unwrap()
+    }
+}
@@ -0,0 +1,319 @@
+/// The types of WebAssembly values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValType {
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl ValType {
+    pub fn code(self) -> u8 {
+        match self {
+            ValType::I32 => 0x7f,
+            ValType::I64 => 0x7e,
+            ValType::F32 => 0x7d,
+            ValType::F64 => 0x7c,
+        }
+    }
+}
+
+/// The signature of a function.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FuncType {
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+}
+
+/// The immediate of loads and stores. `align` is the log2 of the alignment,
+/// which is only a hint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemArg {
+    pub align: u32,
+    pub offset: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum LoadOp {
+    I32Load = 0x28,
+    I64Load = 0x29,
+    F32Load = 0x2a,
+    F64Load = 0x2b,
+    I32Load8S = 0x2c,
+    I32Load8U = 0x2d,
+    I32Load16S = 0x2e,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum StoreOp {
+    I32Store = 0x36,
+    I64Store = 0x37,
+    F32Store = 0x38,
+    F64Store = 0x39,
+    I32Store8 = 0x3a,
+    I32Store16 = 0x3b,
+}
+
+/// Numeric instructions without immediates, by opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Op {
+    I32Eqz = 0x45,
+    I32Eq = 0x46,
+    I32Ne = 0x47,
+    I32LtS = 0x48,
+    I32LtU = 0x49,
+    I32GtS = 0x4a,
+    I32GtU = 0x4b,
+    I32LeS = 0x4c,
+    I32LeU = 0x4d,
+    I32GeS = 0x4e,
+    I32GeU = 0x4f,
+    I64Eqz = 0x50,
+    I64Eq = 0x51,
+    I64Ne = 0x52,
+    I64LtS = 0x53,
+    I64LtU = 0x54,
+    I64GtS = 0x55,
+    I64GtU = 0x56,
+    I64LeS = 0x57,
+    I64LeU = 0x58,
+    I64GeS = 0x59,
+    I64GeU = 0x5a,
+    F32Eq = 0x5b,
+    F32Ne = 0x5c,
+    F32Lt = 0x5d,
+    F32Gt = 0x5e,
+    F32Le = 0x5f,
+    F32Ge = 0x60,
+    F64Eq = 0x61,
+    F64Ne = 0x62,
+    F64Lt = 0x63,
+    F64Gt = 0x64,
+    F64Le = 0x65,
+    F64Ge = 0x66,
+    I32Add = 0x6a,
+    I32Sub = 0x6b,
+    I32Mul = 0x6c,
+    I32DivS = 0x6d,
+    I32DivU = 0x6e,
+    I32RemS = 0x6f,
+    I32RemU = 0x70,
+    I32And = 0x71,
+    I32Or = 0x72,
+    I32Xor = 0x73,
+    I32Shl = 0x74,
+    I32ShrS = 0x75,
+    I32ShrU = 0x76,
+    I64Add = 0x7c,
+    I64Sub = 0x7d,
+    I64Mul = 0x7e,
+    I64DivS = 0x7f,
+    I64DivU = 0x80,
+    I64RemS = 0x81,
+    I64RemU = 0x82,
+    I64And = 0x83,
+    I64Or = 0x84,
+    I64Xor = 0x85,
+    I64Shl = 0x86,
+    I64ShrS = 0x87,
+    I64ShrU = 0x88,
+    F32Neg = 0x8c,
+    F32Add = 0x92,
+    F32Sub = 0x93,
+    F32Mul = 0x94,
+    F32Div = 0x95,
+    F64Neg = 0x9a,
+    F64Add = 0xa0,
+    F64Sub = 0xa1,
+    F64Mul = 0xa2,
+    F64Div = 0xa3,
+    I32WrapI64 = 0xa7,
+    I32TruncF32S = 0xa8,
+    I32TruncF32U = 0xa9,
+    I32TruncF64S = 0xaa,
+    I32TruncF64U = 0xab,
+    I64ExtendI32S = 0xac,
+    I64ExtendI32U = 0xad,
+    I64TruncF32S = 0xae,
+    I64TruncF32U = 0xaf,
+    I64TruncF64S = 0xb0,
+    I64TruncF64U = 0xb1,
+    F32ConvertI32S = 0xb2,
+    F32ConvertI32U = 0xb3,
+    F32ConvertI64S = 0xb4,
+    F32ConvertI64U = 0xb5,
+    F32DemoteF64 = 0xb6,
+    F64ConvertI32S = 0xb7,
+    F64ConvertI32U = 0xb8,
+    F64ConvertI64S = 0xb9,
+    F64ConvertI64U = 0xba,
+    F64PromoteF32 = 0xbb,
+    I32ReinterpretF32 = 0xbc,
+    I64ReinterpretF64 = 0xbd,
+    F32ReinterpretI32 = 0xbe,
+    F64ReinterpretI64 = 0xbf,
+    I32Extend8S = 0xc0,
+    I32Extend16S = 0xc1,
+}
+
+/// A WebAssembly instruction. Blocks never take or produce values, since
+/// everything is passed through locals.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inst {
+    Unreachable,
+    Block,
+    Loop,
+    If,
+    Else,
+    End,
+    /// Branches to the label of the enclosing block, loop or if this many levels out.
+    Br(u32),
+    Return,
+    Call(u32),
+    /// Calls the function at the table index on top of the stack, with the given type.
+    CallIndirect(u32),
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    Load(LoadOp, MemArg),
+    Store(StoreOp, MemArg),
+    /// Copies bytes within memory, taking the destination, source and size.
+    MemoryCopy,
+    I32Const(i32),
+    I64Const(i64),
+    /// Pushes the float with these bits.
+    F32Const(u32),
+    F64Const(u64),
+    Op(Op),
+}
+
+impl Inst {
+    pub fn encode(&self, code: &mut Vec<u8>) {
+        match self {
+            Inst::Unreachable => code.push(0x00),
+            // the empty block type
+            Inst::Block => code.extend_from_slice(&[0x02, 0x40]),
+            Inst::Loop => code.extend_from_slice(&[0x03, 0x40]),
+            Inst::If => code.extend_from_slice(&[0x04, 0x40]),
+            Inst::Else => code.push(0x05),
+            Inst::End => code.push(0x0b),
+            Inst::Br(depth) => {
+                code.push(0x0c);
+                code.put_u32(*depth);
+            }
+            Inst::Return => code.push(0x0f),
+            Inst::Call(function) => {
+                code.push(0x10);
+                code.put_u32(*function);
+            }
+            Inst::CallIndirect(ty) => {
+                code.push(0x11);
+                code.put_u32(*ty);
+                code.push(0x00);
+            }
+            Inst::LocalGet(local) => {
+                code.push(0x20);
+                code.put_u32(*local);
+            }
+            Inst::LocalSet(local) => {
+                code.push(0x21);
+                code.put_u32(*local);
+            }
+            Inst::LocalTee(local) => {
+                code.push(0x22);
+                code.put_u32(*local);
+            }
+            Inst::GlobalGet(global) => {
+                code.push(0x23);
+                code.put_u32(*global);
+            }
+            Inst::GlobalSet(global) => {
+                code.push(0x24);
+                code.put_u32(*global);
+            }
+            Inst::Load(op, mem) => {
+                code.push(*op as u8);
+                code.put_u32(mem.align);
+                code.put_u32(mem.offset);
+            }
+            Inst::Store(op, mem) => {
+                code.push(*op as u8);
+                code.put_u32(mem.align);
+                code.put_u32(mem.offset);
+            }
+            Inst::MemoryCopy => {
+                code.push(0xfc);
+                code.put_u32(10);
+                code.extend_from_slice(&[0x00, 0x00]);
+            }
+            Inst::I32Const(value) => {
+                code.push(0x41);
+                code.put_i64(*value as i64);
+            }
+            Inst::I64Const(value) => {
+                code.push(0x42);
+                code.put_i64(*value);
+            }
+            Inst::F32Const(bits) => {
+                code.push(0x43);
+                code.extend_from_slice(&bits.to_le_bytes());
+            }
+            Inst::F64Const(bits) => {
+                code.push(0x44);
+                code.extend_from_slice(&bits.to_le_bytes());
+            }
+            Inst::Op(op) => code.push(*op as u8),
+        }
+    }
+}
+
+/// The LEB128 encodings of the binary format.
+pub trait Put {
+    fn put_u32(&mut self, value: u32);
+    fn put_i64(&mut self, value: i64);
+    /// Writes a length-prefixed UTF-8 string.
+    fn put_name(&mut self, name: &str);
+    /// Writes a length-prefixed byte vector.
+    fn put_bytes(&mut self, bytes: &[u8]);
+}
+
+impl Put for Vec<u8> {
+    fn put_u32(&mut self, mut value: u32) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.push(byte);
+                return;
+            }
+            self.push(byte | 0x80);
+        }
+    }
+
+    fn put_i64(&mut self, mut value: i64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            // done once the rest is the sign extension of the byte's top bit
+            if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+                self.push(byte);
+                return;
+            }
+            self.push(byte | 0x80);
+        }
+    }
+
+    fn put_name(&mut self, name: &str) {
+        self.put_bytes(name.as_bytes());
+    }
+
+    fn put_bytes(&mut self, bytes: &[u8]) {
+        self.put_u32(bytes.len() as u32);
+        self.extend_from_slice(bytes);
+    }
+}
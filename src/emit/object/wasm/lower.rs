@@ -0,0 +1,1134 @@
+use crate::emit::asm::data::{constant_data, data_bytes};
+use crate::emit::asm::unsupported;
+use crate::emit::object::wasm::abi::{is_aggregate, signature, val_type, var_arg_offsets};
+use crate::emit::object::wasm::cfg::Cfg;
+use crate::emit::object::wasm::inst::{Inst, LoadOp, MemArg, Op, StoreOp, ValType};
+use crate::emit::object::wasm::{GlobalAddress, Symbols, Types};
+use crate::ir::values::basic_block::BasicBlock;
+use crate::ir::values::constant::{Constant, Scalar};
+use crate::ir::values::function::Function;
+use crate::ir::values::instruction::{FloatPredicate, Instruction, InstructionType};
+use crate::ir::values::value::{Type, ValueEntity};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Error;
+use std::rc::Rc;
+
+/// The locals and code of a function.
+pub struct Body {
+    /// The types of the locals after the parameters.
+    pub locals: Vec<ValType>,
+    /// The instructions, including the final `end`.
+    pub code: Vec<Inst>,
+}
+
+/// What a branch can target, for each construct enclosing the code being emitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Label {
+    /// A loop, whose label is the start of the block it is headed by.
+    LoopHeadedBy(usize),
+    /// A block, whose label is the start of the block following it.
+    BlockFollowedBy(usize),
+    /// The arms of an `if`, which are never branched to.
+    If,
+}
+
+/// The part of the function's frame on the stack in linear memory.
+struct Frame {
+    /// The local holding the lowest address of the frame.
+    base: u32,
+    /// The local holding the stack pointer on entry, which is restored on return.
+    saved: u32,
+    size: u64,
+}
+
+/// Returns the instruction computing `inst` on operands of local type `ty`.
+fn numeric_op(inst: &InstructionType, ty: ValType) -> Option<Op> {
+    use ValType::*;
+    Some(match (inst, ty) {
+        (InstructionType::Add(..), I32) => Op::I32Add,
+        (InstructionType::Add(..), I64) => Op::I64Add,
+        (InstructionType::Sub(..), I32) => Op::I32Sub,
+        (InstructionType::Sub(..), I64) => Op::I64Sub,
+        (InstructionType::Mul(..), I32) => Op::I32Mul,
+        (InstructionType::Mul(..), I64) => Op::I64Mul,
+        (InstructionType::SDiv(..), I32) => Op::I32DivS,
+        (InstructionType::SDiv(..), I64) => Op::I64DivS,
+        (InstructionType::SRem(..), I32) => Op::I32RemS,
+        (InstructionType::SRem(..), I64) => Op::I64RemS,
+        (InstructionType::UDiv(..), I32) => Op::I32DivU,
+        (InstructionType::UDiv(..), I64) => Op::I64DivU,
+        (InstructionType::URem(..), I32) => Op::I32RemU,
+        (InstructionType::URem(..), I64) => Op::I64RemU,
+        (InstructionType::Shl(..), I32) => Op::I32Shl,
+        (InstructionType::Shl(..), I64) => Op::I64Shl,
+        (InstructionType::AShr(..), I32) => Op::I32ShrS,
+        (InstructionType::AShr(..), I64) => Op::I64ShrS,
+        (InstructionType::LShr(..), I32) => Op::I32ShrU,
+        (InstructionType::LShr(..), I64) => Op::I64ShrU,
+        (InstructionType::And(..), I32) => Op::I32And,
+        (InstructionType::And(..), I64) => Op::I64And,
+        (InstructionType::Or(..), I32) => Op::I32Or,
+        (InstructionType::Or(..), I64) => Op::I64Or,
+        (InstructionType::Xor(..), I32) => Op::I32Xor,
+        (InstructionType::Xor(..), I64) => Op::I64Xor,
+        (InstructionType::Eq(..), I32) => Op::I32Eq,
+        (InstructionType::Eq(..), I64) => Op::I64Eq,
+        (InstructionType::Ne(..), I32) => Op::I32Ne,
+        (InstructionType::Ne(..), I64) => Op::I64Ne,
+        (InstructionType::SLt(..), I32) => Op::I32LtS,
+        (InstructionType::SLt(..), I64) => Op::I64LtS,
+        (InstructionType::SLe(..), I32) => Op::I32LeS,
+        (InstructionType::SLe(..), I64) => Op::I64LeS,
+        (InstructionType::SGt(..), I32) => Op::I32GtS,
+        (InstructionType::SGt(..), I64) => Op::I64GtS,
+        (InstructionType::SGe(..), I32) => Op::I32GeS,
+        (InstructionType::SGe(..), I64) => Op::I64GeS,
+        (InstructionType::ULt(..), I32) => Op::I32LtU,
+        (InstructionType::ULt(..), I64) => Op::I64LtU,
+        (InstructionType::ULe(..), I32) => Op::I32LeU,
+        (InstructionType::ULe(..), I64) => Op::I64LeU,
+        (InstructionType::UGt(..), I32) => Op::I32GtU,
+        (InstructionType::UGt(..), I64) => Op::I64GtU,
+        (InstructionType::UGe(..), I32) => Op::I32GeU,
+        (InstructionType::UGe(..), I64) => Op::I64GeU,
+        (InstructionType::FAdd(..), F32) => Op::F32Add,
+        (InstructionType::FAdd(..), F64) => Op::F64Add,
+        (InstructionType::FSub(..), F32) => Op::F32Sub,
+        (InstructionType::FSub(..), F64) => Op::F64Sub,
+        (InstructionType::FMul(..), F32) => Op::F32Mul,
+        (InstructionType::FMul(..), F64) => Op::F64Mul,
+        (InstructionType::FDiv(..), F32) => Op::F32Div,
+        (InstructionType::FDiv(..), F64) => Op::F64Div,
+        _ => return None,
+    })
+}
+
+/// Returns the comparison of floats of local type `ty` that holds when
+/// `predicate` does, for the predicates that have one.
+fn float_compare_op(predicate: FloatPredicate, ty: ValType) -> Option<Op> {
+    let f64 = ty == ValType::F64;
+    Some(match predicate {
+        FloatPredicate::Oeq => if f64 { Op::F64Eq } else { Op::F32Eq },
+        FloatPredicate::Une => if f64 { Op::F64Ne } else { Op::F32Ne },
+        FloatPredicate::Olt => if f64 { Op::F64Lt } else { Op::F32Lt },
+        FloatPredicate::Ole => if f64 { Op::F64Le } else { Op::F32Le },
+        FloatPredicate::Ogt => if f64 { Op::F64Gt } else { Op::F32Gt },
+        FloatPredicate::Oge => if f64 { Op::F64Ge } else { Op::F32Ge },
+        _ => return None,
+    })
+}
+
+/// Returns the operator converting between the value types of a cast, or
+/// `None` when both are held in the same value type.
+fn conversion_op(opcode: &str, from: ValType, to: ValType) -> Option<Op> {
+    use ValType::*;
+    let signed = matches!(opcode, "sext" | "fptosi" | "sitofp");
+    let bitcast = opcode == "bitcast";
+    Some(match (from, to) {
+        (I64, I32) => Op::I32WrapI64,
+        (I32, I64) if signed => Op::I64ExtendI32S,
+        (I32, I64) => Op::I64ExtendI32U,
+        (F64, F32) => Op::F32DemoteF64,
+        (F32, F64) => Op::F64PromoteF32,
+        (F32, I32) if bitcast => Op::I32ReinterpretF32,
+        (F64, I64) if bitcast => Op::I64ReinterpretF64,
+        (I32, F32) if bitcast => Op::F32ReinterpretI32,
+        (I64, F64) if bitcast => Op::F64ReinterpretI64,
+        (F32, I32) if signed => Op::I32TruncF32S,
+        (F32, I32) => Op::I32TruncF32U,
+        (F64, I32) if signed => Op::I32TruncF64S,
+        (F64, I32) => Op::I32TruncF64U,
+        (F32, I64) if signed => Op::I64TruncF32S,
+        (F32, I64) => Op::I64TruncF32U,
+        (F64, I64) if signed => Op::I64TruncF64S,
+        (F64, I64) => Op::I64TruncF64U,
+        (I32, F32) if signed => Op::F32ConvertI32S,
+        (I32, F32) => Op::F32ConvertI32U,
+        (I64, F32) if signed => Op::F32ConvertI64S,
+        (I64, F32) => Op::F32ConvertI64U,
+        (I32, F64) if signed => Op::F64ConvertI32S,
+        (I32, F64) => Op::F64ConvertI32U,
+        (I64, F64) if signed => Op::F64ConvertI64S,
+        (I64, F64) => Op::F64ConvertI64U,
+        _ => return None,
+    })
+}
+
+/// Returns the natural alignment of a scalar of type `ty`, as a log2.
+fn align_log2(ty: &Type) -> u32 {
+    match ty {
+        Type::Integer(1 | 8) => 0,
+        Type::Integer(16) => 1,
+        Type::Integer(64) | Type::Float(64) => 3,
+        _ => 2,
+    }
+}
+
+fn load_op(ty: &Type) -> Result<LoadOp, Error> {
+    Ok(match (ty, val_type(ty)?) {
+        (Type::Integer(1), _) => LoadOp::I32Load8U,
+        (Type::Integer(8), _) => LoadOp::I32Load8S,
+        (Type::Integer(16), _) => LoadOp::I32Load16S,
+        (_, ValType::I32) => LoadOp::I32Load,
+        (_, ValType::I64) => LoadOp::I64Load,
+        (_, ValType::F32) => LoadOp::F32Load,
+        (_, ValType::F64) => LoadOp::F64Load,
+    })
+}
+
+fn store_op(ty: &Type) -> Result<StoreOp, Error> {
+    Ok(match (ty, val_type(ty)?) {
+        (Type::Integer(1 | 8), _) => StoreOp::I32Store8,
+        (Type::Integer(16), _) => StoreOp::I32Store16,
+        (_, ValType::I32) => StoreOp::I32Store,
+        (_, ValType::I64) => StoreOp::I64Store,
+        (_, ValType::F32) => StoreOp::F32Store,
+        (_, ValType::F64) => StoreOp::F64Store,
+    })
+}
+
+/// Returns whether a function needs a frame on the stack in linear memory.
+fn needs_frame(func: &Function) -> bool {
+    func.get_blocks().iter().any(|block| block.borrow().get_instructions().iter().any(|inst| match inst {
+        ValueEntity::Instruction(inst) => match inst.instruction_type() {
+            InstructionType::Alloca(..) => true,
+            InstructionType::Load(_) => is_aggregate(&inst.get_type()),
+            InstructionType::Call(callee, _) if is_aggregate(&inst.get_type()) || matches!(callee.as_ref(), ValueEntity::Function(f) if f.is_var_arg()) => true,
+            // aggregate constants are built in the frame
+            _ => inst.get_operands().iter().any(|operand| matches!(operand, ValueEntity::Constant(constant) if is_aggregate(&constant.get_type()))),
+        },
+        _ => false,
+    }))
+}
+
+/// Lowers the IR of one function into WebAssembly code, where every value
+/// lives in a local and the control flow graph is turned back into nested
+/// blocks and loops following "Beyond Relooper" by Norman Ramsey.
+pub struct FunctionLowering<'a> {
+    func: &'a Function,
+    symbols: &'a Symbols<'a>,
+    types: &'a mut Types,
+    blocks: Vec<Rc<RefCell<BasicBlock>>>,
+    code: Vec<Inst>,
+    param_count: u32,
+    locals: Vec<ValType>,
+    values: HashMap<String, u32>,
+    phi_temps: HashMap<String, u32>,
+    /// The constructs enclosing the code being emitted, innermost last.
+    context: Vec<Label>,
+    frame: Option<Frame>,
+    /// The local holding the caller's result buffer when the function returns in memory.
+    sret: Option<u32>,
+}
+
+impl<'a> FunctionLowering<'a> {
+    pub fn new(func: &'a Function, symbols: &'a Symbols<'a>, types: &'a mut Types) -> Self {
+        Self {
+            func,
+            symbols,
+            types,
+            blocks: func.get_blocks().clone(),
+            code: Vec::new(),
+            param_count: 0,
+            locals: Vec::new(),
+            values: HashMap::new(),
+            phi_temps: HashMap::new(),
+            context: Vec::new(),
+            frame: None,
+            sret: None,
+        }
+    }
+
+    pub fn lower(mut self) -> Result<Body, Error> {
+        let signature = signature(&self.func.get_type(), self.func.is_var_arg())?;
+        self.param_count = signature.params.len() as u32;
+        let first = if is_aggregate(&self.func.get_function_return_type()) {
+            self.sret = Some(0);
+            1
+        } else {
+            0
+        };
+        for (i, param) in self.func.get_params().iter().enumerate() {
+            self.values.insert(param.get_name(), first + i as u32);
+        }
+        for block in &self.blocks.clone() {
+            for inst in block.borrow().get_instructions() {
+                let ValueEntity::Instruction(inst) = inst else {
+                    continue;
+                };
+                if inst.get_type().is_void() {
+                    continue;
+                }
+                let ty = val_type(&inst.get_type())?;
+                let local = self.new_local(ty);
+                self.values.insert(inst.get_name(), local);
+                if let InstructionType::Phi(_) = inst.instruction_type() {
+                    let temp = self.new_local(ty);
+                    self.phi_temps.insert(inst.get_name(), temp);
+                }
+            }
+        }
+        if needs_frame(self.func) {
+            let base = self.new_local(ValType::I32);
+            let saved = self.new_local(ValType::I32);
+            self.frame = Some(Frame { base, saved, size: 0 });
+        }
+
+        let cfg = Cfg::new(self.func)?;
+        if let Some(&entry) = cfg.rpo.first() {
+            self.do_tree(&cfg, entry)?;
+        }
+        // the end of the body is never reached, but has to type check
+        if !signature.results.is_empty() {
+            self.emit(Inst::Unreachable);
+        }
+        self.emit(Inst::End);
+
+        if let Some(frame) = &self.frame {
+            // the stack grows down and stays 16-byte aligned
+            let prologue = [
+                Inst::GlobalGet(self.symbols.stack_pointer),
+                Inst::LocalTee(frame.saved),
+                Inst::I32Const(frame.size.next_multiple_of(16) as i32),
+                Inst::Op(Op::I32Sub),
+                Inst::LocalTee(frame.base),
+                Inst::GlobalSet(self.symbols.stack_pointer),
+            ];
+            self.code.splice(0..0, prologue);
+        }
+        Ok(Body { locals: self.locals, code: self.code })
+    }
+
+    fn new_local(&mut self, ty: ValType) -> u32 {
+        self.locals.push(ty);
+        self.param_count + self.locals.len() as u32 - 1
+    }
+
+    fn emit(&mut self, inst: Inst) {
+        self.code.push(inst);
+    }
+
+    /// Reserves `size` bytes of the frame and returns their offset from its base.
+    fn new_slot(&mut self, size: u64, align: u64) -> u64 {
+        let frame = self.frame.as_mut().expect("function has no frame");
+        let offset = frame.size.next_multiple_of(align.clamp(1, 16));
+        frame.size = offset + size;
+        offset
+    }
+
+    fn push_slot_address(&mut self, offset: u64) {
+        let base = self.frame.as_ref().expect("function has no frame").base;
+        self.emit(Inst::LocalGet(base));
+        self.emit(Inst::I32Const(offset as i32));
+        self.emit(Inst::Op(Op::I32Add));
+    }
+
+    fn local(&self, name: &str) -> Result<u32, Error> {
+        self.values.get(name).copied().ok_or_else(|| unsupported(format!("use of undefined value {}", name)))
+    }
+
+    fn set_result(&mut self, inst: &Instruction) -> Result<(), Error> {
+        let local = self.local(&inst.get_name())?;
+        self.emit(Inst::LocalSet(local));
+        Ok(())
+    }
+
+    /// Pushes `value`, used as a value of type `ty`.
+    fn push(&mut self, value: &ValueEntity, ty: &Type) -> Result<(), Error> {
+        match value {
+            ValueEntity::Instruction(inst) => {
+                let local = self.local(&inst.get_name())?;
+                self.emit(Inst::LocalGet(local));
+                Ok(())
+            }
+            ValueEntity::Argument(argument) => {
+                let local = self.local(&argument.get_name())?;
+                self.emit(Inst::LocalGet(local));
+                Ok(())
+            }
+            ValueEntity::Function(function) => self.push_symbol(&function.get_name()),
+            ValueEntity::GlobalVariable(global) => self.push_symbol(&global.get_name()),
+            ValueEntity::Constant(constant) if is_aggregate(ty) => self.push_aggregate(constant, ty),
+            ValueEntity::Constant(constant) => match constant.fold() {
+                Some(Scalar::Int(value)) if value.bits() <= 64 => self.push_int(value.to_machine_i64(), ty),
+                Some(Scalar::Float(_, bits)) => {
+                    match val_type(ty)? {
+                        ValType::F32 => self.emit(Inst::F32Const((f64::from_bits(bits) as f32).to_bits())),
+                        ValType::F64 => self.emit(Inst::F64Const(bits)),
+                        _ => return Err(unsupported(format!("float constant used as a value of type {}", ty))),
+                    }
+                    Ok(())
+                }
+                Some(Scalar::Address(symbol, offset)) => {
+                    self.push_symbol(&symbol)?;
+                    if offset != 0 {
+                        self.emit(Inst::I32Const(offset as i32));
+                        self.emit(Inst::Op(Op::I32Add));
+                    }
+                    // addresses are zero-extended into wider integers
+                    if val_type(ty)? == ValType::I64 {
+                        self.emit(Inst::Op(Op::I64ExtendI32U));
+                    }
+                    Ok(())
+                }
+                _ => Err(unsupported(format!("constant {} of type {}", constant, constant.get_type()))),
+            },
+            ValueEntity::BasicBlock(block) => Err(unsupported(format!("basic block {} used as a value", block.get_name()))),
+        }
+    }
+
+    /// Builds an aggregate constant in a frame slot and pushes its address.
+    fn push_aggregate(&mut self, constant: &Constant, ty: &Type) -> Result<(), Error> {
+        let layout = self.symbols.layout;
+        let (bytes, symbols) = data_bytes(&constant_data(layout, ty, constant)?);
+        let slot = self.new_slot(layout.size_of(ty), layout.align_of(ty));
+        let mut offset = 0;
+        for (len, op) in [(8, StoreOp::I64Store), (4, StoreOp::I32Store), (2, StoreOp::I32Store16), (1, StoreOp::I32Store8)] {
+            while bytes.len() - offset >= len {
+                let mut value = [0; 8];
+                value[..len].copy_from_slice(&bytes[offset..offset + len]);
+                self.push_slot_address(slot);
+                match op {
+                    StoreOp::I64Store => self.emit(Inst::I64Const(i64::from_le_bytes(value))),
+                    _ => self.emit(Inst::I32Const(i64::from_le_bytes(value) as i32)),
+                }
+                self.emit(Inst::Store(op, MemArg { align: 0, offset: offset as u32 }));
+                offset += len;
+            }
+        }
+        for (offset, _, symbol, addend) in symbols {
+            self.push_slot_address(slot);
+            self.push_symbol(&symbol)?;
+            if addend != 0 {
+                self.emit(Inst::I32Const(addend as i32));
+                self.emit(Inst::Op(Op::I32Add));
+            }
+            self.emit(Inst::Store(StoreOp::I32Store, MemArg { align: 0, offset: offset as u32 }));
+        }
+        self.push_slot_address(slot);
+        Ok(())
+    }
+
+    /// Pushes the address of a function or global.
+    fn push_symbol(&mut self, symbol: &str) -> Result<(), Error> {
+        // function pointers are indices into the table, where 0 is null
+        if let Some(index) = self.symbols.functions.get(symbol) {
+            self.emit(Inst::I32Const(*index as i32 + 1));
+            return Ok(());
+        }
+        match self.symbols.globals.get(symbol) {
+            Some(GlobalAddress::Memory(address)) => self.emit(Inst::I32Const(*address as i32)),
+            Some(GlobalAddress::Imported(index)) => self.emit(Inst::GlobalGet(*index)),
+            None => return Err(unsupported(format!("address of unknown symbol {}", symbol))),
+        }
+        Ok(())
+    }
+
+    /// Pushes `value` of type `ty`, with integers narrower than 32 bits
+    /// zero- rather than sign-extended if `unsigned`.
+    fn push_extended(&mut self, value: &ValueEntity, ty: &Type, unsigned: bool) -> Result<(), Error> {
+        self.push(value, ty)?;
+        let mask = match ty {
+            Type::Integer(8) if unsigned => 0xff,
+            Type::Integer(16) if unsigned => 0xffff,
+            _ => return Ok(()),
+        };
+        self.emit(Inst::I32Const(mask));
+        self.emit(Inst::Op(Op::I32And));
+        Ok(())
+    }
+
+    /// Pushes an integer constant as a value of type `ty`, in the form the
+    /// locals of that type hold it.
+    fn push_int(&mut self, value: i64, ty: &Type) -> Result<(), Error> {
+        let value = match ty {
+            Type::Integer(1) => value & 1,
+            Type::Integer(8) => value as i8 as i64,
+            Type::Integer(16) => value as i16 as i64,
+            Type::Integer(64) => value,
+            _ => value as i32 as i64,
+        };
+        match val_type(ty)? {
+            ValType::I32 => self.emit(Inst::I32Const(value as i32)),
+            ValType::I64 => self.emit(Inst::I64Const(value)),
+            _ => return Err(unsupported(format!("integer constant used as a value of type {}", ty))),
+        }
+        Ok(())
+    }
+
+    /// Brings the result of an operation on integers narrower than 32 bits
+    /// back to the form locals hold them in.
+    fn normalize(&mut self, ty: &Type) {
+        match ty {
+            Type::Integer(1) => {
+                self.emit(Inst::I32Const(1));
+                self.emit(Inst::Op(Op::I32And));
+            }
+            Type::Integer(8) => self.emit(Inst::Op(Op::I32Extend8S)),
+            Type::Integer(16) => self.emit(Inst::Op(Op::I32Extend16S)),
+            _ => {}
+        }
+    }
+
+    /// Emits the code of the dominator subtree rooted at `block`.
+    fn do_tree(&mut self, cfg: &Cfg, block: usize) -> Result<(), Error> {
+        // blocks reached from several places follow the code of the others,
+        // the one coming last in reverse postorder outermost
+        let mut merges = cfg.dominator_children(block).into_iter().filter(|&child| cfg.is_merge_node(child)).collect::<Vec<_>>();
+        merges.sort_by_key(|&child| std::cmp::Reverse(cfg.rpo_number(child)));
+        if cfg.is_loop_header(block) {
+            self.emit(Inst::Loop);
+            self.context.push(Label::LoopHeadedBy(block));
+            self.node_within(cfg, block, &merges)?;
+            self.context.pop();
+            self.emit(Inst::End);
+        } else {
+            self.node_within(cfg, block, &merges)?;
+        }
+        Ok(())
+    }
+
+    fn node_within(&mut self, cfg: &Cfg, block: usize, merges: &[usize]) -> Result<(), Error> {
+        if let Some((&merge, rest)) = merges.split_first() {
+            self.emit(Inst::Block);
+            self.context.push(Label::BlockFollowedBy(merge));
+            self.node_within(cfg, block, rest)?;
+            self.context.pop();
+            self.emit(Inst::End);
+            return self.do_tree(cfg, merge);
+        }
+
+        let bb = self.blocks[block].clone();
+        let bb = bb.borrow();
+        for inst in bb.get_instructions() {
+            let ValueEntity::Instruction(inst) = inst else {
+                continue;
+            };
+            match inst.instruction_type() {
+                InstructionType::Branch(_) => {
+                    let target = cfg.succs[block][0];
+                    self.emit_phi_copies(&bb, target)?;
+                    self.do_branch(cfg, block, target)?;
+                }
+                InstructionType::BranchIf(cond, _, _) => {
+                    let (if_true, if_false) = (cfg.succs[block][0], cfg.succs[block][1]);
+                    self.push(cond, &Type::Integer(1))?;
+                    self.emit(Inst::If);
+                    self.context.push(Label::If);
+                    self.emit_phi_copies(&bb, if_true)?;
+                    self.do_branch(cfg, block, if_true)?;
+                    self.emit(Inst::Else);
+                    self.emit_phi_copies(&bb, if_false)?;
+                    self.do_branch(cfg, block, if_false)?;
+                    self.context.pop();
+                    self.emit(Inst::End);
+                }
+                _ => self.lower_instruction(inst)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn do_branch(&mut self, cfg: &Cfg, from: usize, to: usize) -> Result<(), Error> {
+        let label = if cfg.is_backward(from, to) {
+            Label::LoopHeadedBy(to)
+        } else if cfg.is_merge_node(to) {
+            Label::BlockFollowedBy(to)
+        } else {
+            // only reached from here, so its code goes right here
+            return self.do_tree(cfg, to);
+        };
+        let depth = self.context.iter().rev().position(|enclosing| *enclosing == label)
+            .ok_or_else(|| unsupported(format!("no label to branch to block {} in function {}", self.blocks[to].borrow().get_name(), self.func.get_name())))?;
+        self.emit(Inst::Br(depth as u32));
+        Ok(())
+    }
+
+    /// Writes the incoming values of the phis in `target` for the edge coming from `block`.
+    fn emit_phi_copies(&mut self, block: &BasicBlock, target: usize) -> Result<(), Error> {
+        let target = self.blocks[target].clone();
+        for inst in target.borrow().get_instructions() {
+            let ValueEntity::Instruction(inst) = inst else {
+                continue;
+            };
+            let InstructionType::Phi(incoming) = inst.instruction_type() else {
+                continue;
+            };
+            let Some((value, _)) = incoming.iter().find(|(_, from)| from.get_name() == block.get_name()) else {
+                continue;
+            };
+            self.push(value, &inst.get_type())?;
+            let temp = self.phi_temps[&inst.get_name()];
+            self.emit(Inst::LocalSet(temp));
+        }
+        Ok(())
+    }
+
+    fn emit_epilogue(&mut self) {
+        if let Some(frame) = &self.frame {
+            let saved = frame.saved;
+            self.emit(Inst::LocalGet(saved));
+            self.emit(Inst::GlobalSet(self.symbols.stack_pointer));
+        }
+    }
+
+    fn lower_instruction(&mut self, inst: &Instruction) -> Result<(), Error> {
+        let kind = inst.instruction_type();
+        match kind {
+            InstructionType::Add(a, b) | InstructionType::Sub(a, b) | InstructionType::Mul(a, b) | InstructionType::SDiv(a, b)
+            | InstructionType::UDiv(a, b) | InstructionType::SRem(a, b) | InstructionType::URem(a, b) | InstructionType::Shl(a, b)
+            | InstructionType::AShr(a, b) | InstructionType::LShr(a, b) | InstructionType::And(a, b) | InstructionType::Or(a, b)
+            | InstructionType::Xor(a, b) => {
+                let ty = a.get_type();
+                let local_type = val_type(&ty)?;
+                let op = numeric_op(kind, local_type).ok_or_else(|| unsupported(format!("`{}` on values of type {}", inst, ty)))?;
+                let unsigned = matches!(kind, InstructionType::UDiv(..) | InstructionType::URem(..));
+                self.push_extended(a, &ty, unsigned || matches!(kind, InstructionType::LShr(..)))?;
+                self.push_extended(b, &ty, unsigned)?;
+                self.emit(Inst::Op(op));
+                self.normalize(&ty);
+                self.set_result(inst)
+            }
+            InstructionType::Eq(a, b) | InstructionType::Ne(a, b) | InstructionType::SLt(a, b) | InstructionType::SLe(a, b)
+            | InstructionType::SGt(a, b) | InstructionType::SGe(a, b) | InstructionType::ULt(a, b) | InstructionType::ULe(a, b)
+            | InstructionType::UGt(a, b) | InstructionType::UGe(a, b) => {
+                let ty = a.get_type();
+                let op = numeric_op(kind, val_type(&ty)?).ok_or_else(|| unsupported(format!("`{}` on values of type {}", inst, ty)))?;
+                let unsigned = matches!(kind, InstructionType::ULt(..) | InstructionType::ULe(..) | InstructionType::UGt(..) | InstructionType::UGe(..));
+                // booleans are held as 0 or 1, but a true one is -1 when signed
+                let (a, b) = match kind {
+                    InstructionType::SLt(..) | InstructionType::SLe(..) | InstructionType::SGt(..) | InstructionType::SGe(..)
+                        if ty == Type::Integer(1) => (b, a),
+                    _ => (a, b),
+                };
+                self.push_extended(a, &ty, unsigned)?;
+                self.push_extended(b, &ty, unsigned)?;
+                self.emit(Inst::Op(op));
+                self.set_result(inst)
+            }
+            InstructionType::Neg(a) => {
+                let ty = a.get_type();
+                let local_type = val_type(&ty)?;
+                self.push_int(0, &ty)?;
+                self.push(a, &ty)?;
+                self.emit(Inst::Op(if local_type == ValType::I64 { Op::I64Sub } else { Op::I32Sub }));
+                self.normalize(&ty);
+                self.set_result(inst)
+            }
+            InstructionType::FAdd(a, b, _) | InstructionType::FSub(a, b, _) | InstructionType::FMul(a, b, _)
+            | InstructionType::FDiv(a, b, _) => {
+                let ty = a.get_type();
+                let op = numeric_op(kind, val_type(&ty)?).ok_or_else(|| unsupported(format!("`{}` on values of type {}", inst, ty)))?;
+                self.push(a, &ty)?;
+                self.push(b, &ty)?;
+                self.emit(Inst::Op(op));
+                self.set_result(inst)
+            }
+            // there is no remainder operator, and libm is not linked in
+            InstructionType::FRem(..) => Err(unsupported(format!("floating point remainder in `{}`", inst))),
+            InstructionType::FNeg(a, _) => {
+                let ty = a.get_type();
+                let op = if val_type(&ty)? == ValType::F64 { Op::F64Neg } else { Op::F32Neg };
+                self.push(a, &ty)?;
+                self.emit(Inst::Op(op));
+                self.set_result(inst)
+            }
+            InstructionType::FCmp(predicate, a, b, _) => self.lower_float_compare(inst, *predicate, a, b),
+            InstructionType::Not(a) => {
+                let ty = a.get_type();
+                self.push(a, &ty)?;
+                // flipping the bits of a normalized value leaves it normalized
+                self.push_int(-1, &ty)?;
+                self.emit(Inst::Op(if val_type(&ty)? == ValType::I64 { Op::I64Xor } else { Op::I32Xor }));
+                self.set_result(inst)
+            }
+            InstructionType::Trunc(a) | InstructionType::ZExt(a) | InstructionType::SExt(a) | InstructionType::FPTrunc(a)
+            | InstructionType::FPExt(a) | InstructionType::FPToSI(a) | InstructionType::FPToUI(a) | InstructionType::SIToFP(a)
+            | InstructionType::UIToFP(a) | InstructionType::PtrToInt(a) | InstructionType::IntToPtr(a) | InstructionType::Bitcast(a) => {
+                let (opcode, _) = kind.as_cast().unwrap();
+                self.lower_cast(inst, opcode, a)
+            }
+            InstructionType::Alloca(ty, count, align) => self.lower_alloca(inst, ty, count.as_deref(), *align),
+            InstructionType::GetElementPtr(ptr, indices) => self.lower_element_address(inst, ptr, indices),
+            InstructionType::Load(ptr) => {
+                let ty = inst.get_type();
+                if is_aggregate(&ty) {
+                    let offset = self.new_slot(self.symbols.layout.size_of(&ty), self.symbols.layout.align_of(&ty));
+                    let dst = self.local(&inst.get_name())?;
+                    self.push_slot_address(offset);
+                    self.emit(Inst::LocalTee(dst));
+                    self.push(ptr, &ptr.get_type())?;
+                    self.emit(Inst::I32Const(self.symbols.layout.size_of(&ty) as i32));
+                    self.emit(Inst::MemoryCopy);
+                    return Ok(());
+                }
+                self.push(ptr, &ptr.get_type())?;
+                self.emit(Inst::Load(load_op(&ty)?, MemArg { align: align_log2(&ty), offset: 0 }));
+                self.set_result(inst)
+            }
+            InstructionType::Store(ptr, value) => {
+                let ty = ptr.get_type().get_pointer_element_type();
+                self.push(ptr, &ptr.get_type())?;
+                self.push(value, &ty)?;
+                if is_aggregate(&ty) {
+                    self.emit(Inst::I32Const(self.symbols.layout.size_of(&ty) as i32));
+                    self.emit(Inst::MemoryCopy);
+                } else {
+                    self.emit(Inst::Store(store_op(&ty)?, MemArg { align: align_log2(&ty), offset: 0 }));
+                }
+                Ok(())
+            }
+            InstructionType::Call(callee, args) => self.lower_call(inst, callee, args),
+            InstructionType::Return(value) => {
+                let ty = self.func.get_function_return_type();
+                if let Some(sret) = self.sret {
+                    self.emit(Inst::LocalGet(sret));
+                    self.push(value, &ty)?;
+                    self.emit(Inst::I32Const(self.symbols.layout.size_of(&ty) as i32));
+                    self.emit(Inst::MemoryCopy);
+                } else {
+                    self.push(value, &ty)?;
+                }
+                self.emit_epilogue();
+                self.emit(Inst::Return);
+                Ok(())
+            }
+            InstructionType::VoidReturn => {
+                self.emit_epilogue();
+                self.emit(Inst::Return);
+                Ok(())
+            }
+            InstructionType::Phi(_) => {
+                let temp = self.phi_temps[&inst.get_name()];
+                self.emit(Inst::LocalGet(temp));
+                self.set_result(inst)
+            }
+            InstructionType::Unreachable => {
+                self.emit(Inst::Unreachable);
+                Ok(())
+            }
+            InstructionType::Branch(_) | InstructionType::BranchIf(..) => Err(unsupported(format!("branch `{}` in the middle of a block", inst))),
+        }
+    }
+
+    /// Lowers a conversion. Float to integer conversions of values out of
+    /// the range of the result trap, as the MVP operators do.
+    /// Lowers a float comparison. Besides `une`, the comparison operators are
+    /// all false for unordered operands, so the other unordered predicates are
+    /// the inverses of ordered ones.
+    fn lower_float_compare(&mut self, inst: &Instruction, predicate: FloatPredicate, a: &ValueEntity, b: &ValueEntity) -> Result<(), Error> {
+        let ty = a.get_type();
+        let local_type = val_type(&ty)?;
+        let inverted = !predicate.is_ordered() && predicate != FloatPredicate::Une;
+        let ordered = if inverted { predicate.inverse() } else { predicate };
+        match ordered {
+            FloatPredicate::One | FloatPredicate::Ord => {
+                let (first, second, combine) = if ordered == FloatPredicate::One {
+                    ((FloatPredicate::Olt, a, b), (FloatPredicate::Ogt, a, b), Op::I32Or)
+                } else {
+                    ((FloatPredicate::Oeq, a, a), (FloatPredicate::Oeq, b, b), Op::I32And)
+                };
+                for (predicate, lhs, rhs) in [first, second] {
+                    self.push(lhs, &ty)?;
+                    self.push(rhs, &ty)?;
+                    self.emit(Inst::Op(float_compare_op(predicate, local_type).unwrap()));
+                }
+                self.emit(Inst::Op(combine));
+            }
+            _ => {
+                self.push(a, &ty)?;
+                self.push(b, &ty)?;
+                self.emit(Inst::Op(float_compare_op(ordered, local_type).unwrap()));
+            }
+        }
+        if inverted {
+            self.emit(Inst::Op(Op::I32Eqz));
+        }
+        self.set_result(inst)
+    }
+
+    fn lower_cast(&mut self, inst: &Instruction, opcode: &str, a: &ValueEntity) -> Result<(), Error> {
+        let from = a.get_type();
+        let to = inst.get_type();
+        let negate = from == Type::Integer(1) && matches!(opcode, "sext" | "sitofp");
+        if negate {
+            // a true i1 is -1 when read as signed
+            self.emit(Inst::I32Const(0));
+        }
+        self.push(a, &from)?;
+        match from {
+            Type::Integer(1) if negate => self.emit(Inst::Op(Op::I32Sub)),
+            // narrow integers are held sign extended
+            Type::Integer(bits @ (8 | 16)) if matches!(opcode, "zext" | "uitofp" | "inttoptr") => {
+                self.emit(Inst::I32Const((1 << bits) - 1));
+                self.emit(Inst::Op(Op::I32And));
+            }
+            _ => {}
+        }
+        if let Some(op) = conversion_op(opcode, val_type(&from)?, val_type(&to)?) {
+            self.emit(Inst::Op(op));
+        }
+        self.normalize(&to);
+        self.set_result(inst)
+    }
+
+    fn lower_element_address(&mut self, inst: &Instruction, ptr: &ValueEntity, indices: &[Box<ValueEntity>]) -> Result<(), Error> {
+        let (offset, scaled) = self.symbols.layout.indexed_offset(&ptr.get_type().get_pointer_element_type(), indices)
+            .ok_or_else(|| unsupported(format!("`{}` has indices its type can't take", inst)))?;
+        self.push(ptr, &ptr.get_type())?;
+        if offset != 0 {
+            self.emit(Inst::I32Const(offset as i32));
+            self.emit(Inst::Op(Op::I32Add));
+        }
+        for (index, stride) in scaled {
+            let ty = index.get_type();
+            if ty == Type::Integer(1) {
+                // a true i1 is -1 when read as signed
+                self.emit(Inst::I32Const(0));
+            }
+            self.push(index, &ty)?;
+            match ty {
+                Type::Integer(1) => self.emit(Inst::Op(Op::I32Sub)),
+                Type::Integer(64) => self.emit(Inst::Op(Op::I32WrapI64)),
+                _ => {}
+            }
+            if stride != 1 {
+                self.emit(Inst::I32Const(stride as i32));
+                self.emit(Inst::Op(Op::I32Mul));
+            }
+            self.emit(Inst::Op(Op::I32Add));
+        }
+        self.set_result(inst)
+    }
+
+    fn lower_alloca(&mut self, inst: &Instruction, ty: &Type, count: Option<&ValueEntity>, align: u64) -> Result<(), Error> {
+        let dst = self.local(&inst.get_name())?;
+        let stride = self.symbols.layout.stride_of(ty);
+        // the stack only guarantees 16-byte alignment, anything stricter is done by hand
+        let padding = align.saturating_sub(16);
+        let count = match count {
+            None => Some(1),
+            Some(ValueEntity::Constant(count)) => match count.fold() {
+                Some(Scalar::Int(count)) => Some(count.to_i64()),
+                _ => None,
+            },
+            Some(_) => None,
+        };
+        match (count, inst.instruction_type()) {
+            (Some(count), _) => {
+                let offset = self.new_slot((count as u64 * stride).max(1) + padding, align);
+                self.push_slot_address(offset);
+            }
+            (None, InstructionType::Alloca(_, Some(count), _)) => {
+                let count_type = count.get_type();
+                self.emit(Inst::GlobalGet(self.symbols.stack_pointer));
+                self.push(count, &count_type)?;
+                match count_type {
+                    Type::Integer(64) => self.emit(Inst::Op(Op::I32WrapI64)),
+                    // counts are unsigned
+                    Type::Integer(bits @ (1 | 8 | 16)) => {
+                        self.emit(Inst::I32Const((1 << bits) - 1));
+                        self.emit(Inst::Op(Op::I32And));
+                    }
+                    _ => {}
+                }
+                self.emit(Inst::I32Const(stride as i32));
+                self.emit(Inst::Op(Op::I32Mul));
+                // keep the stack pointer a multiple of 16
+                self.emit(Inst::I32Const((15 + padding) as i32));
+                self.emit(Inst::Op(Op::I32Add));
+                self.emit(Inst::I32Const(-16));
+                self.emit(Inst::Op(Op::I32And));
+                self.emit(Inst::Op(Op::I32Sub));
+                self.emit(Inst::LocalTee(dst));
+                self.emit(Inst::GlobalSet(self.symbols.stack_pointer));
+                self.emit(Inst::LocalGet(dst));
+            }
+            _ => unreachable!(),
+        }
+        if padding > 0 {
+            self.emit(Inst::I32Const(align as i32 - 1));
+            self.emit(Inst::Op(Op::I32Add));
+            self.emit(Inst::I32Const(-(align as i32)));
+            self.emit(Inst::Op(Op::I32And));
+        }
+        self.emit(Inst::LocalSet(dst));
+        Ok(())
+    }
+
+    fn lower_call(&mut self, inst: &Instruction, callee: &ValueEntity, args: &[Box<ValueEntity>]) -> Result<(), Error> {
+        let (callee_type, is_var_arg) = match callee {
+            ValueEntity::Function(function) => (function.get_type(), function.is_var_arg()),
+            // whether a function is variadic is not part of its type, so indirect calls pass every argument as a fixed one
+            callee => match callee.get_type() {
+                ty if ty.is_function_type() => (ty, false),
+                ty => (ty.get_pointer_element_type(), false),
+            },
+        };
+        let params = callee_type.get_function_argument_types().clone();
+        let ret = callee_type.get_function_return_type();
+        let layout = self.symbols.layout;
+
+        if is_aggregate(&ret) {
+            let offset = self.new_slot(layout.size_of(&ret), layout.align_of(&ret));
+            let dst = self.local(&inst.get_name())?;
+            self.push_slot_address(offset);
+            self.emit(Inst::LocalTee(dst));
+        }
+        let fixed = if is_var_arg { params.len().min(args.len()) } else { args.len() };
+        for (i, arg) in args[..fixed].iter().enumerate() {
+            let ty = params.get(i).cloned().unwrap_or_else(|| arg.get_type());
+            self.push(arg, &ty)?;
+        }
+        if is_var_arg {
+            let extra = &args[fixed..];
+            let types = extra.iter().map(|arg| arg.get_type()).collect::<Vec<_>>();
+            let (offsets, size) = var_arg_offsets(layout, &types);
+            if extra.is_empty() {
+                self.emit(Inst::I32Const(0));
+            } else {
+                let buffer = self.new_slot(size, 16);
+                let base = self.frame.as_ref().expect("function has no frame").base;
+                for ((arg, ty), offset) in extra.iter().zip(&types).zip(offsets) {
+                    let offset = buffer + offset;
+                    if is_aggregate(ty) {
+                        self.push_slot_address(offset);
+                        self.push(arg, ty)?;
+                        self.emit(Inst::I32Const(layout.size_of(ty) as i32));
+                        self.emit(Inst::MemoryCopy);
+                        continue;
+                    }
+                    // small integers are widened to a whole word
+                    let op = match ty {
+                        Type::Integer(1 | 8 | 16) => StoreOp::I32Store,
+                        ty => store_op(ty)?,
+                    };
+                    self.emit(Inst::LocalGet(base));
+                    self.push(arg, ty)?;
+                    self.emit(Inst::Store(op, MemArg { align: 0, offset: offset as u32 }));
+                }
+                self.push_slot_address(buffer);
+            }
+        }
+
+        match callee {
+            ValueEntity::Function(function) => {
+                let index = self.symbols.functions[&function.get_name()];
+                self.emit(Inst::Call(index));
+            }
+            callee => {
+                self.push(callee, &callee.get_type())?;
+                let ty = self.types.intern(signature(&callee_type, false)?);
+                self.emit(Inst::CallIndirect(ty));
+            }
+        }
+        if !ret.is_void() && !is_aggregate(&ret) {
+            self.set_result(inst)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::parser::parse_module;
+
+    fn lower(source: &str, name: &str) -> Vec<Inst> {
+        let module = parse_module(source).unwrap_or_else(|e| panic!("{}", e));
+        let functions = module.get_functions().iter().enumerate().map(|(i, f)| (f.borrow().get_name(), i as u32)).collect();
+        let symbols = Symbols { layout: module.data_layout(), functions, globals: HashMap::new(), stack_pointer: 0 };
+        let function = module.get_functions().iter().find(|f| f.borrow().get_name() == name).unwrap().borrow().clone();
+        let mut types = Types::default();
+        FunctionLowering::new(&function, &symbols, &mut types).lower().unwrap().code
+    }
+
+    #[test]
+    fn lowers_loops_and_phis_through_locals() {
+        let code = lower(r#"
+            target triple = "wasm32-unknown-unknown"
+            define internal function @sum(%n: i32) -> i32 {
+            %entry:
+              branch %loop
+            %loop:
+              %i = phi i32 0, %entry, %next, %loop
+              %total = phi i32 0, %entry, %added, %loop
+              %added = add i32 %total, %i
+              %next = add i32 %i, 1
+              %done = sgt i32 %next, %n
+              branch %done, %exit, %loop
+            %exit:
+              return i32 %added
+            }
+        "#, "sum");
+        use Inst::{Br, Else, End, I32Const, If, LocalGet, LocalSet, Loop, Return, Unreachable};
+        // %i and %total live in locals 1 and 3, and their phis are written through 2 and 4
+        let entry = [I32Const(0), LocalSet(2), I32Const(0), LocalSet(4)];
+        let phis = [LocalGet(2), LocalSet(1), LocalGet(4), LocalSet(3)];
+        let body = [
+            LocalGet(3), LocalGet(1), Inst::Op(Op::I32Add), LocalSet(5),
+            LocalGet(1), I32Const(1), Inst::Op(Op::I32Add), LocalSet(6),
+            LocalGet(6), LocalGet(0), Inst::Op(Op::I32GtS), LocalSet(7),
+        ];
+        // the exit is only reached from the loop, so it sits in the `if`, and the back edge branches out of it to the loop
+        let branch = [LocalGet(7), If, LocalGet(5), Return, Else, LocalGet(6), LocalSet(2), LocalGet(5), LocalSet(4), Br(1), End];
+        let expected = entry.into_iter().chain([Loop]).chain(phis).chain(body).chain(branch).chain([End, Unreachable, End]).collect::<Vec<_>>();
+        assert_eq!(code, expected);
+    }
+
+    #[test]
+    fn lowers_conversions() {
+        let code = lower(r#"
+            target triple = "wasm32-unknown-unknown"
+            define internal function @conv(%a: i32, %b: i64, %x: f64, %p: i8*) -> f64 {
+            %entry:
+              %wide = sext i32 %a to i64
+              %float = sitofp i64 %wide to f64
+              %single = fptrunc f64 %float to f32
+              %byte = trunc i64 %b to i8
+              %unsigned = zext i8 %byte to i32
+              %from = uitofp i32 %a to f32
+              %double = fpext f32 %from to f64
+              %int = fptosi f64 %x to i32
+              %big = fptoui f64 %x to i64
+              %bits = bitcast f64 %x to i64
+              %address = ptrtoint i8* %p to i64
+              %pointer = inttoptr i64 %address to i32*
+              return f64 %double
+            }
+        "#, "conv");
+        let op = Inst::Op;
+        let convert = |from, op: Op, to| [Inst::LocalGet(from), Inst::Op(op), Inst::LocalSet(to)];
+        let expected = [
+            convert(0, Op::I64ExtendI32S, 4),
+            convert(4, Op::F64ConvertI64S, 5),
+            convert(5, Op::F32DemoteF64, 6),
+        ].concat().into_iter()
+            // i8 locals hold the value sign-extended, so zext masks it
+            .chain([Inst::LocalGet(1), op(Op::I32WrapI64), op(Op::I32Extend8S), Inst::LocalSet(7)])
+            .chain([Inst::LocalGet(7), Inst::I32Const(255), op(Op::I32And), Inst::LocalSet(8)])
+            .chain([
+                convert(0, Op::F32ConvertI32U, 9),
+                convert(9, Op::F64PromoteF32, 10),
+                convert(2, Op::I32TruncF64S, 11),
+                convert(2, Op::I64TruncF64U, 12),
+                convert(2, Op::I64ReinterpretF64, 13),
+                // pointers are 32 bits
+                convert(3, Op::I64ExtendI32U, 14),
+                convert(14, Op::I32WrapI64, 15),
+            ].concat())
+            .chain([Inst::LocalGet(10), Inst::Return, Inst::Unreachable, Inst::End])
+            .collect::<Vec<_>>();
+        assert_eq!(code, expected);
+    }
+
+    #[test]
+    fn lowers_unsigned_operations() {
+        let code = lower(r#"
+            target triple = "wasm32-unknown-unknown"
+            define internal function @compare(%a: i32, %b: i8) -> i1 {
+            %entry:
+              %quotient = udiv i32 %a, 10
+              %rest = urem i8 %b, 3
+              %shifted = lshr i8 %b, %rest
+              %less = ult i8 %shifted, %b
+              %greater = uge i32 %quotient, %a
+              %both = and i1 %less, %greater
+              return i1 %both
+            }
+        "#, "compare");
+        use Inst::{End, I32Const, LocalGet, LocalSet, Return, Unreachable};
+        // i8 locals hold the value sign-extended, so unsigned operations mask it first
+        let byte = |local| [LocalGet(local), I32Const(255), Inst::Op(Op::I32And)];
+        let expected = [LocalGet(0), I32Const(10), Inst::Op(Op::I32DivU), LocalSet(2)].into_iter()
+            .chain(byte(1)).chain([I32Const(3), I32Const(255), Inst::Op(Op::I32And), Inst::Op(Op::I32RemU), Inst::Op(Op::I32Extend8S), LocalSet(3)])
+            .chain(byte(1)).chain([LocalGet(3), Inst::Op(Op::I32ShrU), Inst::Op(Op::I32Extend8S), LocalSet(4)])
+            .chain(byte(4)).chain(byte(1)).chain([Inst::Op(Op::I32LtU), LocalSet(5)])
+            .chain([LocalGet(2), LocalGet(0), Inst::Op(Op::I32GeU), LocalSet(6)])
+            .chain([LocalGet(5), LocalGet(6), Inst::Op(Op::I32And), I32Const(1), Inst::Op(Op::I32And), LocalSet(7)])
+            .chain([LocalGet(7), Return, Unreachable, End])
+            .collect::<Vec<_>>();
+        assert_eq!(code, expected);
+    }
+
+    #[test]
+    fn flips_bits_at_the_width_of_the_operand() {
+        let src = r#"
+            target triple = "wasm32-unknown-unknown"
+            define internal function @byte(%a: i8) -> i8 {
+            %entry:
+              %flipped = not i8 %a
+              return i8 %flipped
+            }
+
+            define internal function @wide(%a: i64, %b: i64) -> i64 {
+            %entry:
+              %both = and i64 %a, %b
+              %flipped = not i64 %both
+              return i64 %flipped
+            }
+        "#;
+        use Inst::{End, I32Const, I64Const, LocalGet, LocalSet, Return, Unreachable};
+        // a sign-extended byte stays sign-extended with all of its bits flipped
+        assert_eq!(lower(src, "byte"), [LocalGet(0), I32Const(-1), Inst::Op(Op::I32Xor), LocalSet(1), LocalGet(1), Return, Unreachable, End]);
+        assert_eq!(lower(src, "wide"), [
+            LocalGet(0), LocalGet(1), Inst::Op(Op::I64And), LocalSet(2),
+            LocalGet(2), I64Const(-1), Inst::Op(Op::I64Xor), LocalSet(3),
+            LocalGet(3), Return, Unreachable, End,
+        ]);
+    }
+
+    #[test]
+    fn lowers_float_operations() {
+        let code = lower(r#"
+            target triple = "wasm32-unknown-unknown"
+            define internal function @measure(%a: f64, %b: f32) -> i1 {
+            %entry:
+              %sum = fadd fast f64 %a, 1.5
+              %half = fmul nnan f32 %b, 0.5
+              %rest = fsub f64 %sum, %a
+              %negated = fneg f64 %rest
+              %less = fcmp ult f64 %negated, %a
+              %same = fcmp oeq f32 %half, %b
+              %both = and i1 %less, %same
+              return i1 %both
+            }
+        "#, "measure");
+        use Inst::{End, F32Const, F64Const, I32Const, LocalGet, LocalSet, Return, Unreachable};
+        let expected = [LocalGet(0), F64Const(1.5f64.to_bits()), Inst::Op(Op::F64Add), LocalSet(2)].into_iter()
+            .chain([LocalGet(1), F32Const(0.5f32.to_bits()), Inst::Op(Op::F32Mul), LocalSet(3)])
+            .chain([LocalGet(2), LocalGet(0), Inst::Op(Op::F64Sub), LocalSet(4)])
+            .chain([LocalGet(4), Inst::Op(Op::F64Neg), LocalSet(5)])
+            // ult is the negation of the ordered ge
+            .chain([LocalGet(5), LocalGet(0), Inst::Op(Op::F64Ge), Inst::Op(Op::I32Eqz), LocalSet(6)])
+            .chain([LocalGet(3), LocalGet(1), Inst::Op(Op::F32Eq), LocalSet(7)])
+            .chain([LocalGet(6), LocalGet(7), Inst::Op(Op::I32And), I32Const(1), Inst::Op(Op::I32And), LocalSet(8)])
+            .chain([LocalGet(8), Return, Unreachable, End])
+            .collect::<Vec<_>>();
+        assert_eq!(code, expected);
+    }
+
+    #[test]
+    fn lowers_element_addresses() {
+        let code = lower(r#"
+            target triple = "wasm32-unknown-unknown"
+            define internal function @element(%p: { i8, [4 x i32] }*, %i: i32) -> i32 {
+            %entry:
+              %element = getelementptr { i8, [4 x i32] }* %p, i64 1, i32 1, i32 %i
+              %value = load i32* %element
+              store i32* %element, 7
+              return i32 %value
+            }
+        "#, "element");
+        use Inst::{End, I32Const, Load, LocalGet, LocalSet, Return, Store, Unreachable};
+        let word = MemArg { align: 2, offset: 0 };
+        // the struct is 20 bytes and its array starts at 4
+        let expected = [LocalGet(0), I32Const(24), Inst::Op(Op::I32Add), LocalGet(1), I32Const(4), Inst::Op(Op::I32Mul), Inst::Op(Op::I32Add), LocalSet(2)].into_iter()
+            .chain([LocalGet(2), Load(LoadOp::I32Load, word), LocalSet(3)])
+            .chain([LocalGet(2), I32Const(7), Store(StoreOp::I32Store, word)])
+            .chain([LocalGet(3), Return, Unreachable, End])
+            .collect::<Vec<_>>();
+        assert_eq!(code, expected);
+    }
+}
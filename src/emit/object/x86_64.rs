@@ -0,0 +1,218 @@
+use std::io::Write;
+use crate::ir::builder::ctx::IRContext;
+use crate::ir::values::function::Function;
+use crate::ir::values::global::GlobalVariable;
+use crate::ir::linkage::Linkage;
+use crate::emit::asm::x86_64::compile_function;
+use crate::emit::asm::data::{self, Datum};
+use crate::emit::asm::x86_64::encode::{encode_function, RelocKind};
+use crate::emit::asm::FrameOptions;
+use crate::emit::asm::unsupported;
+use crate::emit::object::elf::{self, Binding, ObjectFile, Relocation, Symbol, SymbolKind, SymbolSection};
+
+const R_X86_64_64: u32 = 1;
+const R_X86_64_PC32: u32 = 2;
+const R_X86_64_PLT32: u32 = 4;
+const R_X86_64_GOTPCREL: u32 = 9;
+const R_X86_64_GOTTPOFF: u32 = 22;
+
+fn reloc_type(kind: RelocKind) -> u32 {
+    match kind {
+        RelocKind::Pc32 => R_X86_64_PC32,
+        RelocKind::Plt32 => R_X86_64_PLT32,
+        RelocKind::GotPcRel => R_X86_64_GOTPCREL,
+        RelocKind::GotTpOff => R_X86_64_GOTTPOFF,
+        RelocKind::Abs64 => R_X86_64_64,
+    }
+}
+
+/// Returns the binding of a symbol defined in the module, mirroring the
+/// directives the assembly emitter writes for its linkage.
+fn binding(name: &str, linkage: &Linkage) -> Result<Binding, std::io::Error> {
+    match linkage {
+        Linkage::ExternalLinkage | Linkage::InternalLinkage | Linkage::CommonLinkage => Ok(Binding::Global),
+        Linkage::PrivateLinkage => Ok(Binding::Local),
+        Linkage::ExternalWeakLinkage | Linkage::LinkonceLinkage | Linkage::WeakLinkage => Ok(Binding::Weak),
+        Linkage::AppendingLinkage => Err(unsupported(format!("appending linkage on {}", name))),
+    }
+}
+
+fn undefined(name: String, binding: Binding) -> Symbol {
+    Symbol { name, binding, kind: SymbolKind::NoType, section: SymbolSection::Undefined, value: 0, size: 0 }
+}
+
+struct X86_64ObjectEmitter {
+    ctx: IRContext,
+    frame_options: FrameOptions,
+    object: ObjectFile,
+}
+
+impl X86_64ObjectEmitter {
+    pub fn new(ctx: IRContext, frame_options: FrameOptions) -> Self {
+        Self {
+            ctx,
+            frame_options,
+            object: ObjectFile::new(elf::EM_X86_64),
+        }
+    }
+
+    pub fn emit_module(&mut self, file: &mut impl Write) -> Result<(), std::io::Error> {
+        let functions = self.ctx.get_module().get_functions().clone();
+        let globals = self.ctx.get_module().get_global_variables().clone();
+        for function in &functions {
+            self.emit_function(&function.borrow())?;
+        }
+        for global in &globals {
+            self.emit_global(&global.borrow())?;
+        }
+
+        self.object.section(".note.GNU-stack", elf::SHT_PROGBITS, 0);
+        self.object.write(file)
+    }
+
+    pub fn emit_function(&mut self, func: &Function) -> Result<(), std::io::Error> {
+        let name = func.get_name();
+        let binding = binding(&name, func.get_linkage())?;
+        if func.is_external() || func.get_blocks().is_empty() {
+            // undefined symbols are added as relocations need them, except weak ones
+            if binding == Binding::Weak {
+                self.object.add_symbol(undefined(name, binding));
+            }
+            return Ok(());
+        }
+
+        let mf = compile_function(func, self.ctx.get_module().data_layout(), self.frame_options)?;
+        let code = encode_function(&mf)?;
+
+        let text = self.object.section(".text", elf::SHT_PROGBITS, elf::SHF_ALLOC | elf::SHF_EXECINSTR);
+        let section = self.object.section_mut(text);
+        let offset = section.align_to(1, 0);
+        section.data.extend_from_slice(&code.bytes);
+        section.relocs.extend(code.relocs.into_iter().map(|reloc| Relocation {
+            offset: offset + reloc.offset,
+            symbol: reloc.symbol,
+            kind: reloc_type(reloc.kind),
+            addend: reloc.addend,
+        }));
+        self.object.add_symbol(Symbol {
+            name,
+            binding,
+            kind: SymbolKind::Function,
+            section: SymbolSection::Section(text),
+            value: offset,
+            size: code.bytes.len() as u64,
+        });
+        Ok(())
+    }
+
+    pub fn emit_global(&mut self, global: &GlobalVariable) -> Result<(), std::io::Error> {
+        let name = global.get_name();
+        let binding = binding(&name, global.get_linkage())?;
+        if global.is_external() {
+            if binding == Binding::Weak {
+                self.object.add_symbol(undefined(name, binding));
+            }
+            return Ok(());
+        }
+
+        let layout = self.ctx.get_module().data_layout().clone();
+        let (size, align) = data::size_and_align(&layout, global);
+        if *global.get_linkage() == Linkage::CommonLinkage {
+            data::check_common(global)?;
+            self.object.add_symbol(Symbol { name, binding, kind: SymbolKind::Object, section: SymbolSection::Common, value: align, size });
+            return Ok(());
+        }
+
+        let target = data::data_section(global);
+        let mut flags = elf::SHF_ALLOC;
+        if target.writable {
+            flags |= elf::SHF_WRITE;
+        }
+        if target.thread_local {
+            flags |= elf::SHF_TLS;
+        }
+        let kind = if target.nobits { elf::SHT_NOBITS } else { elf::SHT_PROGBITS };
+        let index = self.object.section(&target.name, kind, flags);
+        let section = self.object.section_mut(index);
+        let offset = section.align_to(align, 0);
+        if target.nobits {
+            section.nobits_size += size;
+        } else {
+            for datum in data::global_data(&layout, global)? {
+                match datum {
+                    Datum::Zero(size) => section.data.resize(section.data.len() + size as usize, 0),
+                    Datum::Int(size, value) => section.data.extend_from_slice(&value.to_le_bytes()[..size as usize]),
+                    Datum::F32(value) => section.data.extend_from_slice(&value.to_le_bytes()),
+                    Datum::F64(value) => section.data.extend_from_slice(&value.to_le_bytes()),
+                    Datum::Bytes(bytes) => section.data.extend_from_slice(&bytes),
+                    Datum::Symbol(_, symbol, addend) => {
+                        section.relocs.push(Relocation { offset: section.data.len() as u64, symbol, kind: reloc_type(RelocKind::Abs64), addend });
+                        section.data.extend_from_slice(&[0; 8]);
+                    }
+                }
+            }
+        }
+
+        let kind = if global.is_thread_local() { SymbolKind::Tls } else { SymbolKind::Object };
+        self.object.add_symbol(Symbol { name, binding, kind, section: SymbolSection::Section(index), value: offset, size });
+        Ok(())
+    }
+}
+
+pub fn emit_module(ctx: IRContext, frame_options: FrameOptions, file: &mut impl Write) -> Result<(), std::io::Error> {
+    let mut emitter = X86_64ObjectEmitter::new(ctx, frame_options);
+    emitter.emit_module(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use crate::ir::builder::{Builder, IRContext};
+    use crate::ir::linkage::Linkage;
+    use crate::ir::module::Module;
+    use crate::targets::{DataLayout, TargetTriple};
+
+    fn builder() -> Builder {
+        let triple = TargetTriple::new("x86_64-unknown-linux-gnu").unwrap();
+        let module = Module::new("test", DataLayout::from_triple(&triple), triple);
+        Builder::new(IRContext::new(module))
+    }
+
+    #[test]
+    fn writes_globals() -> Result<(), Error> {
+        let mut builder = builder();
+        let message = builder.create_global_string("message", "hi")?;
+        let pointer = builder.get_pointer_type(builder.get_i8_type());
+        let address = builder.const_address(&message.borrow().clone().into())?;
+        let address = builder.const_cast("bitcast", address, pointer.clone())?;
+        builder.create_global("pointer", pointer, Some(address), Linkage::InternalLinkage, false)?;
+
+        let mut bytes = Vec::new();
+        builder.emit_object(&mut bytes)?;
+        assert_eq!(bytes[..4], *b"\x7fELF");
+        let contains = |needle: &[u8]| bytes.windows(needle.len()).any(|window| window == needle);
+        assert!(contains(b"hi\0"));
+        assert!(contains(b"\0.rodata\0") && contains(b"\0.rela.data\0") && contains(b"\0.note.GNU-stack\0"));
+        assert!(contains(b"\0message\0") && contains(b"\0pointer\0"));
+        Ok(())
+    }
+
+    #[test]
+    fn writes_functions_and_their_relocations() -> Result<(), Error> {
+        let mut builder = builder();
+        let message = builder.create_global_string("message", "hi")?;
+        let puts = builder.create_function("puts", vec![message.borrow().get_type()], builder.get_i32_type(), Linkage::ExternalLinkage, false)?;
+        let main = builder.create_function("main", vec![], builder.get_i32_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main)?;
+        builder.set_insertion_point(entry);
+        builder.call(puts.borrow().clone().into(), vec![message.borrow().clone().into()], None)?;
+        builder.ret(builder.get_i32(0))?;
+
+        let mut bytes = Vec::new();
+        builder.emit_object(&mut bytes)?;
+        let contains = |needle: &[u8]| bytes.windows(needle.len()).any(|window| window == needle);
+        assert!(contains(b"\0.text\0") && contains(b"\0.rela.text\0"));
+        assert!(contains(b"\0main\0") && contains(b"\0puts\0"));
+        Ok(())
+    }
+}
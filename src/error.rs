@@ -1,5 +1,82 @@
+use crate::ir::linkage::Linkage;
+use crate::ir::values::value::Type;
+use crate::targets::triple::TargetTriple;
+use std::fmt::{Display, Formatter};
 
 #[derive(Debug)]
 pub enum Error {
     InvalidTargetTriple,
+    InvalidDataLayout,
+    /// A value has a different type than the one required.
+    TypeMismatch { expected: Type, found: Type },
+    /// An operation was given an operand of a type it does not accept.
+    InvalidOperandType { operation: &'static str, ty: Type },
+    /// A conversion can't turn a value of one type into the other.
+    InvalidCast { operation: &'static str, from: Type, to: Type },
+    /// A call passes the wrong number of arguments.
+    ArgumentCount { expected: usize, found: usize, is_var_arg: bool },
+    /// A function has no parameter at the given index.
+    NoSuchParameter { function: String, index: usize },
+    /// A struct has no field at the given index.
+    NoSuchField { ty: Type, index: usize },
+    /// A phi was created without incoming values.
+    EmptyPhi,
+    /// An instruction was built without an insertion point.
+    NoInsertionPoint,
+    /// A name is empty or contains characters names can't contain.
+    InvalidName(String),
+    /// An alignment is not a power of two.
+    InvalidAlignment(u64),
+    /// A constant global defined in the module has no initializer.
+    MissingInitializer(String),
+    /// A function or global has a linkage that doesn't allow what was asked
+    /// of it, like adding a block to an external function.
+    UnsupportedLinkage { name: String, linkage: Linkage },
+    /// There is no backend for the target.
+    UnsupportedTarget(TargetTriple),
+    /// A symbol the module refers to is neither defined in it nor found in the process.
+    UnresolvedSymbol(String),
+    /// Writing output failed, or the backend can't lower part of the module.
+    Io(std::io::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidTargetTriple => write!(f, "invalid target triple"),
+            Error::InvalidDataLayout => write!(f, "invalid data layout"),
+            Error::TypeMismatch { expected, found } => write!(f, "expected a value of type {}, found {}", expected, found),
+            Error::InvalidOperandType { operation, ty } => write!(f, "{} does not accept values of type {}", operation, ty),
+            Error::InvalidCast { operation, from, to } => write!(f, "{} cannot convert a value of type {} to {}", operation, from, to),
+            Error::ArgumentCount { expected, found, is_var_arg } => {
+                write!(f, "expected {}{} arguments, found {}", if *is_var_arg { "at least " } else { "" }, expected, found)
+            }
+            Error::NoSuchParameter { function, index } => write!(f, "function @{} has no parameter {}", function, index),
+            Error::NoSuchField { ty, index } => write!(f, "{} has no field {}", ty, index),
+            Error::EmptyPhi => write!(f, "phi has no incoming values"),
+            Error::NoInsertionPoint => write!(f, "no insertion point is set"),
+            Error::InvalidName(name) => write!(f, "invalid name \"{}\"", name),
+            Error::InvalidAlignment(align) => write!(f, "alignment must be a power of two (got {})", align),
+            Error::MissingInitializer(name) => write!(f, "constant global @{} needs an initializer", name),
+            Error::UnsupportedLinkage { name, linkage } => write!(f, "@{} has unsupported linkage {} for this operation", name, linkage),
+            Error::UnsupportedTarget(triple) => write!(f, "no backend for target {}", triple),
+            Error::UnresolvedSymbol(name) => write!(f, "undefined symbol @{}", name),
+            Error::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
 }
@@ -0,0 +1,153 @@
+use crate::interpreter::Trap;
+use std::collections::BTreeMap;
+
+/// Where the first allocation is placed, so that small integers are never valid addresses.
+const FIRST_ADDRESS: u64 = 0x1000;
+/// Unused bytes between allocations, so a pointer just past the end of one
+/// allocation never points into the next.
+const GUARD_SIZE: u64 = 16;
+
+/// What a block of memory was allocated for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocationKind {
+    Global,
+    /// A constant global, which may not be written.
+    Constant,
+    /// Memory from an `alloca`, freed when its function returns.
+    Stack,
+    /// Memory allocated by a hook, such as `malloc`.
+    Heap,
+    /// The address of a function, which can be called but not read or written.
+    Function,
+}
+
+#[derive(Debug, Clone)]
+struct Allocation {
+    kind: AllocationKind,
+    bytes: Vec<u8>,
+    /// Whether each byte has been written.
+    initialized: Vec<bool>,
+    live: bool,
+}
+
+/// The simulated memory of an interpreted program: a flat address space of
+/// separate allocations. Accesses outside a live allocation, writes to
+/// constants and reads of bytes never written are undefined behaviour.
+#[derive(Debug, Clone)]
+pub struct Memory {
+    allocations: BTreeMap<u64, Allocation>,
+    next: u64,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self { allocations: BTreeMap::new(), next: FIRST_ADDRESS }
+    }
+
+    /// Allocates `size` uninitialized bytes aligned to `align` and returns their address.
+    pub fn allocate(&mut self, size: u64, align: u64, kind: AllocationKind) -> u64 {
+        let address = self.next.next_multiple_of(align.max(1));
+        self.next = address + size.max(1) + GUARD_SIZE;
+        self.allocations.insert(address, Allocation {
+            kind,
+            bytes: vec![0; size as usize],
+            initialized: vec![false; size as usize],
+            live: true,
+        });
+        address
+    }
+
+    /// Frees the heap allocation starting at `address`.
+    pub fn free(&mut self, address: u64) -> Result<(), Trap> {
+        match self.allocations.get_mut(&address) {
+            Some(allocation) if allocation.kind == AllocationKind::Heap && allocation.live => {
+                allocation.live = false;
+                Ok(())
+            }
+            Some(allocation) if allocation.kind == AllocationKind::Heap => Err(Trap::UndefinedBehavior(format!("double free of {:#x}", address))),
+            _ => Err(Trap::UndefinedBehavior(format!("free of {:#x}, which was not allocated on the heap", address))),
+        }
+    }
+
+    /// Ends the lifetime of a stack allocation.
+    pub(crate) fn release(&mut self, address: u64) {
+        if let Some(allocation) = self.allocations.get_mut(&address) {
+            allocation.live = false;
+        }
+    }
+
+    /// Returns whether `address` is the address of a function allocation.
+    pub fn is_function(&self, address: u64) -> bool {
+        self.allocations.get(&address).is_some_and(|allocation| allocation.kind == AllocationKind::Function)
+    }
+
+    /// Finds the live allocation holding the `len` bytes at `address`, and their offset in it.
+    fn find(&self, address: u64, len: u64) -> Result<(&Allocation, usize), Trap> {
+        if address == 0 {
+            return Err(Trap::UndefinedBehavior("null pointer dereference".to_string()));
+        }
+        let Some((base, allocation)) = self.allocations.range(..=address).next_back() else {
+            return Err(Trap::UndefinedBehavior(format!("access to unallocated address {:#x}", address)));
+        };
+        if allocation.kind == AllocationKind::Function {
+            return Err(Trap::UndefinedBehavior(format!("access to the code of a function at {:#x}", address)));
+        }
+        let offset = address - base;
+        if offset + len > allocation.bytes.len() as u64 {
+            return Err(Trap::UndefinedBehavior(format!(
+                "access of {} bytes at {:#x} is out of bounds of the {}-byte allocation at {:#x}",
+                len, address, allocation.bytes.len(), base
+            )));
+        }
+        if !allocation.live {
+            let why = if allocation.kind == AllocationKind::Stack { "after its function returned" } else { "after it was freed" };
+            return Err(Trap::UndefinedBehavior(format!("access to {:#x} {}", address, why)));
+        }
+        Ok((allocation, offset as usize))
+    }
+
+    /// Reads `len` bytes, all of which must have been written.
+    pub fn read(&self, address: u64, len: u64) -> Result<&[u8], Trap> {
+        let (allocation, offset) = self.find(address, len)?;
+        let range = offset..offset + len as usize;
+        if let Some(index) = allocation.initialized[range.clone()].iter().position(|initialized| !initialized) {
+            return Err(Trap::UndefinedBehavior(format!("read of uninitialized memory at {:#x}", address + index as u64)));
+        }
+        Ok(&allocation.bytes[range])
+    }
+
+    /// Reads the NUL-terminated string at `address`, without the terminator.
+    pub fn read_c_string(&self, address: u64) -> Result<Vec<u8>, Trap> {
+        let mut string = Vec::new();
+        loop {
+            let byte = self.read(address + string.len() as u64, 1)?[0];
+            if byte == 0 {
+                return Ok(string);
+            }
+            string.push(byte);
+        }
+    }
+
+    pub fn write(&mut self, address: u64, bytes: &[u8]) -> Result<(), Trap> {
+        let (allocation, _) = self.find(address, bytes.len() as u64)?;
+        if allocation.kind == AllocationKind::Constant {
+            return Err(Trap::UndefinedBehavior(format!("write to constant memory at {:#x}", address)));
+        }
+        self.write_unchecked(address, bytes);
+        Ok(())
+    }
+
+    /// Writes to an allocation found before, ignoring whether it is constant.
+    pub(crate) fn write_unchecked(&mut self, address: u64, bytes: &[u8]) {
+        let (base, allocation) = self.allocations.range_mut(..=address).next_back().unwrap();
+        let offset = (address - base) as usize;
+        allocation.bytes[offset..offset + bytes.len()].copy_from_slice(bytes);
+        allocation.initialized[offset..offset + bytes.len()].fill(true);
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,1120 @@
+use crate::ir::apint::ApInt;
+use crate::interpreter::memory::{AllocationKind, Memory};
+use crate::ir::linkage::Linkage;
+use crate::ir::module::Module;
+use crate::ir::values::basic_block::BasicBlock;
+use crate::ir::values::function::Function;
+use crate::ir::values::constant::{Constant, Scalar};
+use crate::ir::values::instruction::{FloatPredicate, Instruction, InstructionType};
+use crate::ir::values::value::{Type, ValueEntity};
+use crate::targets::layout::DataLayout;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+pub mod memory;
+
+/// A value computed by the interpreter.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GenericValue {
+    Int(ApInt),
+    F32(f32),
+    F64(f64),
+    /// An address in the interpreter's memory, zero for null.
+    Pointer(u64),
+    Struct(Vec<GenericValue>),
+    Array(Vec<GenericValue>),
+    /// The result of a function returning void.
+    Void,
+}
+
+impl GenericValue {
+    /// Returns an integer of the given width holding `value`, truncated or sign-extended.
+    pub fn int(bits: usize, value: i64) -> Self {
+        GenericValue::Int(ApInt::from_i64(bits, value))
+    }
+
+    pub fn bool(value: bool) -> Self {
+        GenericValue::Int(ApInt::from_bool(value))
+    }
+
+    /// Returns an integer sign-extended or truncated to 64 bits. Booleans are 0 or 1.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            GenericValue::Int(int) if int.bits() == 1 => Some(!int.is_zero() as i64),
+            GenericValue::Int(int) => Some(int.to_i64()),
+            _ => None,
+        }
+    }
+
+    /// Returns a float, widened to `f64`.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            GenericValue::F32(value) => Some(*value as f64),
+            GenericValue::F64(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_pointer(&self) -> Option<u64> {
+        match self {
+            GenericValue::Pointer(address) => Some(*address),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the value can be held by a value of type `ty`.
+    pub fn has_type(&self, ty: &Type) -> bool {
+        match (self, ty) {
+            (GenericValue::Int(int), Type::Integer(bits)) => int.bits() == *bits,
+            (GenericValue::F32(_), Type::Float(32)) | (GenericValue::F64(_), Type::Float(64)) => true,
+            (GenericValue::Pointer(_), Type::Pointer(_) | Type::FunctionType(_, _)) => true,
+            (GenericValue::Array(elements), Type::Array(len, element)) => elements.len() == *len && elements.iter().all(|value| value.has_type(element)),
+            (GenericValue::Struct(values), Type::Struct(fields)) => {
+                values.len() == fields.len() && values.iter().zip(fields).all(|(value, field)| value.has_type(field))
+            }
+            (GenericValue::Void, Type::Void) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Display for GenericValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GenericValue::Int(int) if int.bits() == 1 => write!(f, "{}", !int.is_zero()),
+            GenericValue::Int(int) => write!(f, "{}", int),
+            GenericValue::F32(value) => write!(f, "{:?}", value),
+            GenericValue::F64(value) => write!(f, "{:?}", value),
+            GenericValue::Pointer(0) => write!(f, "null"),
+            GenericValue::Pointer(address) => write!(f, "{:#x}", address),
+            GenericValue::Struct(fields) => {
+                let fields = fields.iter().map(|field| field.to_string()).collect::<Vec<_>>().join(", ");
+                write!(f, "{{ {} }}", fields)
+            }
+            GenericValue::Array(elements) => {
+                let elements = elements.iter().map(|element| element.to_string()).collect::<Vec<_>>().join(", ");
+                write!(f, "[{}]", elements)
+            }
+            GenericValue::Void => write!(f, "void"),
+        }
+    }
+}
+
+/// Why execution stopped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trap {
+    /// The program did something whose behaviour is undefined, like dividing
+    /// by zero or reading memory that was never written.
+    UndefinedBehavior(String),
+    /// The program uses something the interpreter can't run, like a call to
+    /// an external function without a hook.
+    Unsupported(String),
+}
+
+impl Display for Trap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Trap::UndefinedBehavior(message) => write!(f, "undefined behavior: {}", message),
+            Trap::Unsupported(message) => write!(f, "unsupported: {}", message),
+        }
+    }
+}
+
+/// A trap, and where it happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionError {
+    pub function: String,
+    /// The block executing when the trap happened, if any.
+    pub block: Option<String>,
+    pub trap: Trap,
+}
+
+impl Display for ExecutionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.block {
+            Some(block) => write!(f, "in function @{}, block {}: {}", self.function, block, self.trap),
+            None => write!(f, "in function @{}: {}", self.function, self.trap),
+        }
+    }
+}
+
+impl std::error::Error for ExecutionError {}
+
+/// Runs an external function in place of the interpreted program: it gets
+/// the program's memory and the arguments, and returns the result.
+pub type Hook<'a> = Box<dyn FnMut(&mut Memory, &[GenericValue]) -> Result<GenericValue, Trap> + 'a>;
+
+/// The values of one function invocation.
+struct Frame {
+    values: HashMap<String, GenericValue>,
+    /// Memory from `alloca`s, released when the function returns.
+    allocas: Vec<u64>,
+}
+
+/// What to do after an instruction.
+enum Flow {
+    Next,
+    Jump(String),
+    Return(GenericValue),
+}
+
+/// Why an instruction failed: a trap of its own, or an error in a function it called.
+enum Failure {
+    Trap(Trap),
+    Call(ExecutionError),
+}
+
+impl From<Trap> for Failure {
+    fn from(trap: Trap) -> Self {
+        Failure::Trap(trap)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    SDiv,
+    UDiv,
+    SRem,
+    URem,
+    Shl,
+    AShr,
+    LShr,
+    And,
+    Or,
+    Xor,
+    FAdd,
+    FSub,
+    FMul,
+    FDiv,
+    FRem,
+}
+
+impl BinaryOp {
+    fn name(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "add",
+            BinaryOp::Sub => "sub",
+            BinaryOp::Mul => "mul",
+            BinaryOp::SDiv => "sdiv",
+            BinaryOp::UDiv => "udiv",
+            BinaryOp::SRem => "srem",
+            BinaryOp::URem => "urem",
+            BinaryOp::Shl => "shl",
+            BinaryOp::AShr => "ashr",
+            BinaryOp::LShr => "lshr",
+            BinaryOp::And => "and",
+            BinaryOp::Or => "or",
+            BinaryOp::Xor => "xor",
+            BinaryOp::FAdd => "fadd",
+            BinaryOp::FSub => "fsub",
+            BinaryOp::FMul => "fmul",
+            BinaryOp::FDiv => "fdiv",
+            BinaryOp::FRem => "frem",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Eq,
+    Ne,
+    SLt,
+    SLe,
+    SGt,
+    SGe,
+    ULt,
+    ULe,
+    UGt,
+    UGe,
+}
+
+impl Comparison {
+    fn is_unsigned(self) -> bool {
+        matches!(self, Comparison::ULt | Comparison::ULe | Comparison::UGt | Comparison::UGe)
+    }
+
+    /// Returns whether the comparison holds, where `None` means unordered.
+    fn holds(self, ordering: Option<Ordering>) -> bool {
+        match self {
+            Comparison::Eq => ordering == Some(Ordering::Equal),
+            Comparison::Ne => ordering != Some(Ordering::Equal),
+            Comparison::SLt | Comparison::ULt => ordering == Some(Ordering::Less),
+            Comparison::SLe | Comparison::ULe => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+            Comparison::SGt | Comparison::UGt => ordering == Some(Ordering::Greater),
+            Comparison::SGe | Comparison::UGe => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+        }
+    }
+}
+
+fn undefined(message: String) -> Trap {
+    Trap::UndefinedBehavior(message)
+}
+
+fn unsupported(message: String) -> Trap {
+    Trap::Unsupported(message)
+}
+
+/// Converts `value` to type `to` the way the conversion `opcode` does.
+/// Floats that don't fit the integer they are converted to are undefined.
+fn cast(opcode: &str, value: GenericValue, to: &Type) -> Result<GenericValue, Trap> {
+    let unsupported_cast = |value: &GenericValue| unsupported(format!("{} of {} to {}", opcode, value, to));
+    Ok(match (opcode, value, to) {
+        ("trunc" | "zext", GenericValue::Int(int), Type::Integer(bits)) => GenericValue::Int(int.resize(*bits)),
+        ("sext", GenericValue::Int(int), Type::Integer(bits)) => GenericValue::Int(int.sext(*bits)),
+        ("fptrunc", GenericValue::F64(value), Type::Float(32)) => GenericValue::F32(value as f32),
+        ("fpext", GenericValue::F32(value), Type::Float(64)) => GenericValue::F64(value as f64),
+        ("fptosi" | "fptoui", value @ (GenericValue::F32(_) | GenericValue::F64(_)), Type::Integer(bits)) if *bits <= 64 => {
+            let float = match value {
+                GenericValue::F32(float) => float as f64,
+                GenericValue::F64(float) => float,
+                _ => unreachable!(),
+            };
+            let truncated = float.trunc();
+            let (min, max) = if opcode == "fptosi" { (-(2f64.powi(*bits as i32 - 1)), 2f64.powi(*bits as i32 - 1)) } else { (0.0, 2f64.powi(*bits as i32)) };
+            // the negated test also catches NaN
+            if !(truncated >= min && truncated < max) {
+                return Err(undefined(format!("{} of {} to {}", opcode, value, to)));
+            }
+            if opcode == "fptosi" {
+                GenericValue::Int(ApInt::from_i64(*bits, truncated as i64))
+            } else {
+                GenericValue::Int(ApInt::from_u64(*bits, truncated as u64))
+            }
+        }
+        ("sitofp", GenericValue::Int(int), Type::Float(bits)) if int.bits() <= 64 => match bits {
+            32 => GenericValue::F32(int.to_i64() as f32),
+            _ => GenericValue::F64(int.to_i64() as f64),
+        },
+        ("uitofp", GenericValue::Int(int), Type::Float(bits)) if int.bits() <= 64 => {
+            let int = int.to_u64().unwrap();
+            match bits {
+                32 => GenericValue::F32(int as f32),
+                _ => GenericValue::F64(int as f64),
+            }
+        }
+        ("ptrtoint", GenericValue::Pointer(address), Type::Integer(bits)) => GenericValue::Int(ApInt::from_u64(*bits, address)),
+        ("inttoptr", GenericValue::Int(int), Type::Pointer(_)) => GenericValue::Pointer(int.resize(64).to_u64().unwrap()),
+        ("bitcast", GenericValue::Int(int), Type::Float(32)) => GenericValue::F32(f32::from_bits(int.to_u64().unwrap() as u32)),
+        ("bitcast", GenericValue::Int(int), Type::Float(64)) => GenericValue::F64(f64::from_bits(int.to_u64().unwrap())),
+        ("bitcast", GenericValue::F32(value), Type::Integer(bits)) => GenericValue::Int(ApInt::from_u64(*bits, value.to_bits() as u64)),
+        ("bitcast", GenericValue::F64(value), Type::Integer(bits)) => GenericValue::Int(ApInt::from_u64(*bits, value.to_bits())),
+        ("bitcast", value @ (GenericValue::Int(_) | GenericValue::F32(_) | GenericValue::F64(_) | GenericValue::Pointer(_)), _) => value,
+        (_, value, _) => return Err(unsupported_cast(&value)),
+    })
+}
+
+/// Executes a module directly, without a backend, as a reference for what
+/// the compiled code should do. Memory is simulated: every `alloca`, global
+/// and function gets an allocation of its own, and the interpreter stops at
+/// the first undefined behaviour it sees rather than carrying on like
+/// compiled code would.
+///
+/// External functions are run by hooks registered with [`Interpreter::add_hook`].
+pub struct Interpreter<'a> {
+    layout: DataLayout,
+    functions: HashMap<String, Rc<RefCell<Function>>>,
+    memory: Memory,
+    /// The address of each function and global variable.
+    symbols: HashMap<String, u64>,
+    /// The name of the function at each function address.
+    function_addresses: HashMap<u64, String>,
+    hooks: HashMap<String, Hook<'a>>,
+}
+
+impl<'a> Interpreter<'a> {
+    /// Creates an interpreter for `module`, with its globals initialized.
+    pub fn new(module: &Module) -> Result<Self, Trap> {
+        let mut interpreter = Self {
+            layout: module.data_layout().clone(),
+            functions: HashMap::new(),
+            memory: Memory::new(),
+            symbols: HashMap::new(),
+            function_addresses: HashMap::new(),
+            hooks: HashMap::new(),
+        };
+
+        for function in module.get_functions() {
+            let name = function.borrow().get_name();
+            let address = interpreter.memory.allocate(0, 1, AllocationKind::Function);
+            interpreter.symbols.insert(name.clone(), address);
+            interpreter.function_addresses.insert(address, name.clone());
+            interpreter.functions.insert(name, function.clone());
+        }
+        // every global gets its address before any is initialized, since initializers may refer to each other
+        let mut initializers = Vec::new();
+        for global in module.get_global_variables() {
+            let global = global.borrow();
+            match global.get_linkage() {
+                // a missing weak definition is null
+                Linkage::ExternalWeakLinkage => {
+                    interpreter.symbols.insert(global.get_name(), 0);
+                }
+                Linkage::ExternalLinkage => {}
+                _ => {
+                    let ty = global.get_value_type().clone();
+                    let size = interpreter.layout.size_of(&ty);
+                    let align = global.get_alignment().unwrap_or_else(|| interpreter.layout.align_of(&ty));
+                    let kind = if global.is_constant() { AllocationKind::Constant } else { AllocationKind::Global };
+                    let address = interpreter.memory.allocate(size, align, kind);
+                    interpreter.memory.write_unchecked(address, &vec![0; size as usize]);
+                    interpreter.symbols.insert(global.get_name(), address);
+                    initializers.push((address, ty.clone(), global.get_initializer().cloned().unwrap_or_else(|| Constant::Zero(ty.clone()))));
+                }
+            }
+        }
+        for (address, ty, initializer) in initializers {
+            interpreter.initialize(address, &ty, &initializer)?;
+        }
+        Ok(interpreter)
+    }
+
+    /// Makes calls to the external function `name` run `hook`.
+    pub fn add_hook(&mut self, name: &str, hook: impl FnMut(&mut Memory, &[GenericValue]) -> Result<GenericValue, Trap> + 'a) {
+        self.hooks.insert(name.to_string(), Box::new(hook));
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    /// Returns the address of a function or of a global variable defined in the module.
+    pub fn get_symbol_address(&self, name: &str) -> Option<u64> {
+        self.symbols.get(name).copied()
+    }
+
+    /// Calls the function `name` with `args` and returns its result.
+    pub fn run_function(&mut self, name: &str, args: &[GenericValue]) -> Result<GenericValue, ExecutionError> {
+        self.call(name, args.to_vec())
+    }
+
+    fn call(&mut self, name: &str, args: Vec<GenericValue>) -> Result<GenericValue, ExecutionError> {
+        let error = |trap| ExecutionError { function: name.to_string(), block: None, trap };
+        let Some(function) = self.functions.get(name).cloned() else {
+            return Err(error(unsupported(format!("no function @{} in the module", name))));
+        };
+        let function = function.borrow();
+        if function.is_external() || function.get_blocks().is_empty() {
+            return match self.hooks.get_mut(name) {
+                Some(hook) => hook(&mut self.memory, &args).map_err(error),
+                None => Err(error(unsupported(format!("call to external function @{}, which has no hook", name)))),
+            };
+        }
+
+        let params = function.get_params();
+        if args.len() < params.len() || (args.len() > params.len() && !function.is_var_arg()) {
+            return Err(error(undefined(format!("called with {} arguments, but takes {}", args.len(), params.len()))));
+        }
+        let mut frame = Frame { values: HashMap::new(), allocas: Vec::new() };
+        for (param, arg) in params.iter().zip(args) {
+            if !arg.has_type(&param.get_type()) {
+                return Err(error(undefined(format!("argument {} is {}, which is not a value of type {}", param.get_name(), arg, param.get_type()))));
+            }
+            frame.values.insert(param.get_name(), arg);
+        }
+        let result = self.execute(&function, &mut frame);
+        for address in frame.allocas {
+            self.memory.release(address);
+        }
+        result
+    }
+
+    fn execute(&mut self, function: &Function, frame: &mut Frame) -> Result<GenericValue, ExecutionError> {
+        let mut block = function.get_blocks()[0].clone();
+        let mut from: Option<String> = None;
+        loop {
+            let current = block.borrow();
+            let at = |trap| ExecutionError { function: function.get_name(), block: Some(current.get_name()), trap };
+            if let Some(from) = &from {
+                self.enter_block(frame, &current, from).map_err(at)?;
+            }
+            let mut flow = Flow::Next;
+            for inst in current.get_instructions() {
+                let ValueEntity::Instruction(inst) = inst else {
+                    return Err(at(unsupported(format!("{} is not an instruction", inst.get_as_ref()))));
+                };
+                flow = match self.execute_instruction(frame, inst) {
+                    Ok(flow) => flow,
+                    Err(Failure::Trap(trap)) => return Err(at(trap)),
+                    Err(Failure::Call(error)) => return Err(error),
+                };
+                if !matches!(flow, Flow::Next) {
+                    break;
+                }
+            }
+            let next = match flow {
+                Flow::Next => return Err(at(unsupported("block has no terminator".to_string()))),
+                Flow::Return(value) => return Ok(value),
+                Flow::Jump(target) => target,
+            };
+            let Some(next) = function.get_block(&next).cloned() else {
+                return Err(at(unsupported(format!("branch to unknown block {}", next))));
+            };
+            from = Some(current.get_name());
+            drop(current);
+            block = next;
+        }
+    }
+
+    /// Gives the phis at the start of `block` their values for the edge from `from`.
+    fn enter_block(&self, frame: &mut Frame, block: &BasicBlock, from: &str) -> Result<(), Trap> {
+        // all incoming values are read before any phi is written
+        let mut values = Vec::new();
+        for inst in block.get_instructions() {
+            let ValueEntity::Instruction(inst) = inst else {
+                continue;
+            };
+            let InstructionType::Phi(incoming) = inst.instruction_type() else {
+                continue;
+            };
+            let Some((value, _)) = incoming.iter().find(|(_, block)| block.get_name() == from) else {
+                return Err(unsupported(format!("phi {} has no incoming value for {}", inst.get_name(), from)));
+            };
+            values.push((inst.get_name(), self.value(frame, value)?));
+        }
+        frame.values.extend(values);
+        Ok(())
+    }
+
+    /// Returns the value of an operand.
+    fn value(&self, frame: &Frame, value: &ValueEntity) -> Result<GenericValue, Trap> {
+        match value {
+            ValueEntity::Instruction(_) | ValueEntity::Argument(_) => frame.values.get(&value.get_name()).cloned()
+                .ok_or_else(|| unsupported(format!("use of {} before it is defined", value.get_name()))),
+            ValueEntity::Function(_) | ValueEntity::GlobalVariable(_) => self.symbol(&value.get_name()).map(GenericValue::Pointer),
+            ValueEntity::Constant(constant) => self.constant(constant),
+            ValueEntity::BasicBlock(block) => Err(unsupported(format!("basic block {} used as a value", block.get_name()))),
+        }
+    }
+
+    fn symbol(&self, name: &str) -> Result<u64, Trap> {
+        self.symbols.get(name).copied().ok_or_else(|| unsupported(format!("@{} is external and has no definition", name)))
+    }
+
+    /// Returns the value of a constant. Undefined values are zero.
+    fn constant(&self, constant: &Constant) -> Result<GenericValue, Trap> {
+        let ty = constant.get_type();
+        match (constant, &ty) {
+            (Constant::Array(_, elements) | Constant::Struct(elements), _) => {
+                let elements = elements.iter().map(|element| self.constant(element)).collect::<Result<Vec<_>, _>>()?;
+                Ok(if ty.is_struct() { GenericValue::Struct(elements) } else { GenericValue::Array(elements) })
+            }
+            (Constant::Bytes(bytes), _) => Ok(GenericValue::Array(bytes.iter().map(|byte| GenericValue::int(8, *byte as i64)).collect())),
+            (Constant::Zero(_) | Constant::Undef(_) | Constant::Poison(_), Type::Array(len, element)) => {
+                Ok(GenericValue::Array(vec![self.constant(&Constant::zero(element))?; *len]))
+            }
+            (Constant::Zero(_) | Constant::Undef(_) | Constant::Poison(_), Type::Struct(fields)) => {
+                Ok(GenericValue::Struct(fields.iter().map(|field| self.constant(&Constant::zero(field))).collect::<Result<Vec<_>, _>>()?))
+            }
+            _ => match (constant.fold(), &ty) {
+                (Some(Scalar::Int(int)), Type::Pointer(_)) => Ok(GenericValue::Pointer(int.resize(64).to_u64().unwrap())),
+                (Some(Scalar::Int(int)), _) => Ok(GenericValue::Int(int)),
+                (Some(Scalar::Float(32, bits)), _) => Ok(GenericValue::F32(f64::from_bits(bits) as f32)),
+                (Some(Scalar::Float(_, bits)), _) => Ok(GenericValue::F64(f64::from_bits(bits))),
+                (Some(Scalar::Address(name, offset)), Type::Integer(bits)) => {
+                    Ok(GenericValue::Int(ApInt::from_u64(*bits, self.symbol(&name)?.wrapping_add(offset as u64))))
+                }
+                (Some(Scalar::Address(name, offset)), _) => Ok(GenericValue::Pointer(self.symbol(&name)?.wrapping_add(offset as u64))),
+                (None, _) => Err(unsupported(format!("constant {} has no value", constant))),
+            },
+        }
+    }
+
+    fn pointer(&self, frame: &Frame, value: &ValueEntity) -> Result<u64, Trap> {
+        match self.value(frame, value)? {
+            GenericValue::Pointer(address) => Ok(address),
+            other => Err(unsupported(format!("{} used as a pointer", other))),
+        }
+    }
+
+    fn execute_instruction(&mut self, frame: &mut Frame, inst: &Instruction) -> Result<Flow, Failure> {
+        let value = match inst.instruction_type() {
+            InstructionType::Add(a, b) => self.binary(frame, BinaryOp::Add, a, b)?,
+            InstructionType::Sub(a, b) => self.binary(frame, BinaryOp::Sub, a, b)?,
+            InstructionType::Mul(a, b) => self.binary(frame, BinaryOp::Mul, a, b)?,
+            InstructionType::SDiv(a, b) => self.binary(frame, BinaryOp::SDiv, a, b)?,
+            InstructionType::UDiv(a, b) => self.binary(frame, BinaryOp::UDiv, a, b)?,
+            InstructionType::SRem(a, b) => self.binary(frame, BinaryOp::SRem, a, b)?,
+            InstructionType::URem(a, b) => self.binary(frame, BinaryOp::URem, a, b)?,
+            InstructionType::Shl(a, b) => self.binary(frame, BinaryOp::Shl, a, b)?,
+            InstructionType::AShr(a, b) => self.binary(frame, BinaryOp::AShr, a, b)?,
+            InstructionType::LShr(a, b) => self.binary(frame, BinaryOp::LShr, a, b)?,
+            InstructionType::And(a, b) => self.binary(frame, BinaryOp::And, a, b)?,
+            InstructionType::Or(a, b) => self.binary(frame, BinaryOp::Or, a, b)?,
+            InstructionType::Xor(a, b) => self.binary(frame, BinaryOp::Xor, a, b)?,
+            InstructionType::Eq(a, b) => self.compare(frame, Comparison::Eq, a, b)?,
+            InstructionType::Ne(a, b) => self.compare(frame, Comparison::Ne, a, b)?,
+            InstructionType::SLt(a, b) => self.compare(frame, Comparison::SLt, a, b)?,
+            InstructionType::SLe(a, b) => self.compare(frame, Comparison::SLe, a, b)?,
+            InstructionType::SGt(a, b) => self.compare(frame, Comparison::SGt, a, b)?,
+            InstructionType::SGe(a, b) => self.compare(frame, Comparison::SGe, a, b)?,
+            InstructionType::ULt(a, b) => self.compare(frame, Comparison::ULt, a, b)?,
+            InstructionType::ULe(a, b) => self.compare(frame, Comparison::ULe, a, b)?,
+            InstructionType::UGt(a, b) => self.compare(frame, Comparison::UGt, a, b)?,
+            InstructionType::UGe(a, b) => self.compare(frame, Comparison::UGe, a, b)?,
+            InstructionType::Neg(a) => match self.value(frame, a)? {
+                GenericValue::Int(int) => GenericValue::Int(int.wrapping_neg()),
+                other => return Err(unsupported(format!("neg of {}", other)).into()),
+            },
+            InstructionType::FAdd(a, b, _) => self.binary(frame, BinaryOp::FAdd, a, b)?,
+            InstructionType::FSub(a, b, _) => self.binary(frame, BinaryOp::FSub, a, b)?,
+            InstructionType::FMul(a, b, _) => self.binary(frame, BinaryOp::FMul, a, b)?,
+            InstructionType::FDiv(a, b, _) => self.binary(frame, BinaryOp::FDiv, a, b)?,
+            InstructionType::FRem(a, b, _) => self.binary(frame, BinaryOp::FRem, a, b)?,
+            InstructionType::FNeg(a, _) => match self.value(frame, a)? {
+                GenericValue::F32(value) => GenericValue::F32(-value),
+                GenericValue::F64(value) => GenericValue::F64(-value),
+                other => return Err(unsupported(format!("fneg of {}", other)).into()),
+            },
+            InstructionType::FCmp(predicate, a, b, _) => self.float_compare(frame, *predicate, a, b)?,
+            InstructionType::Not(a) => match self.value(frame, a)? {
+                GenericValue::Int(int) => GenericValue::Int(int.not()),
+                other => return Err(unsupported(format!("not of {}", other)).into()),
+            },
+            InstructionType::Trunc(a) | InstructionType::ZExt(a) | InstructionType::SExt(a) | InstructionType::FPTrunc(a)
+            | InstructionType::FPExt(a) | InstructionType::FPToSI(a) | InstructionType::FPToUI(a) | InstructionType::SIToFP(a)
+            | InstructionType::UIToFP(a) | InstructionType::PtrToInt(a) | InstructionType::IntToPtr(a) | InstructionType::Bitcast(a) => {
+                let (opcode, _) = inst.instruction_type().as_cast().unwrap();
+                let value = self.value(frame, a)?;
+                cast(opcode, value, &inst.get_type())?
+            }
+            InstructionType::Alloca(ty, count, align) => {
+                let count = match count {
+                    Some(count) => match self.value(frame, count)? {
+                        GenericValue::Int(int) if int.is_negative() => return Err(undefined(format!("alloca of {} values", int)).into()),
+                        GenericValue::Int(int) => int.to_u64().ok_or_else(|| unsupported(format!("alloca of {} values", int)))?,
+                        other => return Err(unsupported(format!("alloca of {} values", other)).into()),
+                    },
+                    None => 1,
+                };
+                let size = count.checked_mul(self.layout.stride_of(ty)).ok_or_else(|| unsupported(format!("alloca of {} values of type {}", count, ty)))?;
+                let address = self.memory.allocate(size, *align, AllocationKind::Stack);
+                frame.allocas.push(address);
+                GenericValue::Pointer(address)
+            }
+            InstructionType::GetElementPtr(ptr, indices) => {
+                let (offset, scaled) = self.layout.indexed_offset(&ptr.get_type().get_pointer_element_type(), indices)
+                    .ok_or_else(|| unsupported(format!("`{}` has indices its type can't take", inst)))?;
+                let mut address = self.pointer(frame, ptr)?.wrapping_add(offset as u64);
+                for (index, stride) in scaled {
+                    match self.value(frame, index)? {
+                        GenericValue::Int(int) => address = address.wrapping_add((int.to_i64() as u64).wrapping_mul(stride)),
+                        other => return Err(unsupported(format!("{} used as an index", other)).into()),
+                    }
+                }
+                GenericValue::Pointer(address)
+            }
+            InstructionType::Load(ptr) => {
+                let address = self.pointer(frame, ptr)?;
+                self.load(address, &inst.get_type())?
+            }
+            InstructionType::Store(ptr, value) => {
+                let ty = match ptr.get_type() {
+                    Type::Pointer(ty) => *ty,
+                    _ => value.get_type(),
+                };
+                let address = self.pointer(frame, ptr)?;
+                let value = self.value(frame, value)?;
+                self.store(address, &ty, &value)?;
+                return Ok(Flow::Next);
+            }
+            InstructionType::Call(callee, args) => {
+                let name = match callee.as_ref() {
+                    ValueEntity::Function(function) => function.get_name(),
+                    callee => {
+                        let address = self.pointer(frame, callee)?;
+                        let name = self.function_addresses.get(&address).cloned()
+                            .ok_or_else(|| undefined(format!("call through {}, which is not the address of a function", GenericValue::Pointer(address))))?;
+                        name
+                    }
+                };
+                let values = args.iter().map(|arg| self.value(frame, arg)).collect::<Result<Vec<_>, _>>()?;
+                self.call(&name, values).map_err(Failure::Call)?
+            }
+            InstructionType::Return(value) => return Ok(Flow::Return(self.value(frame, value)?)),
+            InstructionType::VoidReturn => return Ok(Flow::Return(GenericValue::Void)),
+            InstructionType::Branch(target) => return Ok(Flow::Jump(target.get_name())),
+            InstructionType::BranchIf(cond, if_true, if_false) => {
+                let taken = match self.value(frame, cond)? {
+                    GenericValue::Int(int) if !int.is_zero() => if_true,
+                    GenericValue::Int(_) => if_false,
+                    other => return Err(unsupported(format!("branch on {}", other)).into()),
+                };
+                return Ok(Flow::Jump(taken.borrow().get_name()));
+            }
+            InstructionType::Unreachable => return Err(undefined("reached unreachable".to_string()).into()),
+            // phis get their values on the way into the block
+            InstructionType::Phi(_) => return Ok(Flow::Next),
+        };
+        if !inst.get_type().is_void() {
+            frame.values.insert(inst.get_name(), value);
+        }
+        Ok(Flow::Next)
+    }
+
+    fn binary(&self, frame: &Frame, op: BinaryOp, a: &ValueEntity, b: &ValueEntity) -> Result<GenericValue, Trap> {
+        let lhs = self.value(frame, a)?;
+        let rhs = self.value(frame, b)?;
+        match (&lhs, &rhs) {
+            (GenericValue::Int(x), GenericValue::Int(y)) if x.bits() == y.bits() => integer_binary(op, x, y).map(GenericValue::Int),
+            (GenericValue::F32(x), GenericValue::F32(y)) => float_binary(op, *x as f64, *y as f64).map(|value| GenericValue::F32(value as f32)),
+            (GenericValue::F64(x), GenericValue::F64(y)) => float_binary(op, *x, *y).map(GenericValue::F64),
+            _ => Err(unsupported(format!("{} of {} and {}", op.name(), lhs, rhs))),
+        }
+    }
+
+    fn compare(&self, frame: &Frame, comparison: Comparison, a: &ValueEntity, b: &ValueEntity) -> Result<GenericValue, Trap> {
+        let lhs = self.value(frame, a)?;
+        let rhs = self.value(frame, b)?;
+        let ordering = match (&lhs, &rhs) {
+            (GenericValue::Int(x), GenericValue::Int(y)) if x.bits() == y.bits() && comparison.is_unsigned() => Some(x.unsigned_cmp(y)),
+            (GenericValue::Int(x), GenericValue::Int(y)) if x.bits() == y.bits() => Some(x.signed_cmp(y)),
+            (GenericValue::Pointer(x), GenericValue::Pointer(y)) => Some(x.cmp(y)),
+            _ => return Err(unsupported(format!("comparison of {} and {}", lhs, rhs))),
+        };
+        Ok(GenericValue::bool(comparison.holds(ordering)))
+    }
+
+    fn float_compare(&self, frame: &Frame, predicate: FloatPredicate, a: &ValueEntity, b: &ValueEntity) -> Result<GenericValue, Trap> {
+        let lhs = self.value(frame, a)?;
+        let rhs = self.value(frame, b)?;
+        let ordering = match (&lhs, &rhs) {
+            (GenericValue::F32(x), GenericValue::F32(y)) => x.partial_cmp(y),
+            (GenericValue::F64(x), GenericValue::F64(y)) => x.partial_cmp(y),
+            _ => return Err(unsupported(format!("fcmp of {} and {}", lhs, rhs))),
+        };
+        Ok(GenericValue::bool(predicate.holds(ordering)))
+    }
+
+    fn pointer_bytes(&self, address: u64) -> Vec<u8> {
+        address.to_le_bytes()[..self.layout.pointer_size() as usize].to_vec()
+    }
+
+    /// Reads a value of type `ty` from memory. Padding is not read, so it may be uninitialized.
+    fn load(&self, address: u64, ty: &Type) -> Result<GenericValue, Trap> {
+        let size = self.layout.size_of(ty);
+        match ty {
+            Type::Integer(bits) => Ok(GenericValue::Int(ApInt::from_le_bytes(*bits, self.memory.read(address, size)?))),
+            Type::Float(32) => Ok(GenericValue::F32(f32::from_le_bytes(self.memory.read(address, 4)?.try_into().unwrap()))),
+            Type::Float(64) => Ok(GenericValue::F64(f64::from_le_bytes(self.memory.read(address, 8)?.try_into().unwrap()))),
+            Type::Pointer(_) | Type::FunctionType(_, _) => {
+                let mut bytes = [0; 8];
+                bytes[..size as usize].copy_from_slice(self.memory.read(address, size)?);
+                Ok(GenericValue::Pointer(u64::from_le_bytes(bytes)))
+            }
+            Type::Array(len, element) => {
+                let stride = self.layout.stride_of(element);
+                let elements = (0..*len as u64).map(|index| self.load(address + index * stride, element)).collect::<Result<_, _>>()?;
+                Ok(GenericValue::Array(elements))
+            }
+            Type::Struct(fields) => {
+                let offsets = self.layout.struct_field_offsets(fields);
+                let fields = fields.iter().zip(offsets).map(|(field, offset)| self.load(address + offset, field)).collect::<Result<_, _>>()?;
+                Ok(GenericValue::Struct(fields))
+            }
+            _ => Err(unsupported(format!("load of a value of type {}", ty))),
+        }
+    }
+
+    /// Writes a value of type `ty` to memory, leaving the padding as it was.
+    fn store(&mut self, address: u64, ty: &Type, value: &GenericValue) -> Result<(), Trap> {
+        match (ty, value) {
+            (Type::Integer(bits), GenericValue::Int(int)) if int.bits() == *bits => {
+                let bytes = int.to_le_bytes(self.layout.size_of(ty) as usize);
+                self.memory.write(address, &bytes)
+            }
+            (Type::Float(32), GenericValue::F32(value)) => self.memory.write(address, &value.to_le_bytes()),
+            (Type::Float(64), GenericValue::F64(value)) => self.memory.write(address, &value.to_le_bytes()),
+            (Type::Pointer(_) | Type::FunctionType(_, _), GenericValue::Pointer(pointer)) => {
+                let bytes = self.pointer_bytes(*pointer);
+                self.memory.write(address, &bytes)
+            }
+            (Type::Array(len, element), GenericValue::Array(elements)) if elements.len() == *len => {
+                let stride = self.layout.stride_of(element);
+                for (index, value) in elements.iter().enumerate() {
+                    self.store(address + index as u64 * stride, element, value)?;
+                }
+                Ok(())
+            }
+            (Type::Struct(fields), GenericValue::Struct(values)) if fields.len() == values.len() => {
+                let offsets = self.layout.struct_field_offsets(fields);
+                for ((field, value), offset) in fields.iter().zip(values).zip(offsets) {
+                    self.store(address + offset, field, value)?;
+                }
+                Ok(())
+            }
+            _ => Err(unsupported(format!("store of {} as a value of type {}", value, ty))),
+        }
+    }
+
+    /// Writes the initializer of a global, whose bytes start out zero.
+    fn initialize(&mut self, address: u64, ty: &Type, initializer: &Constant) -> Result<(), Trap> {
+        let bytes = match (ty, initializer) {
+            (_, Constant::Zero(_) | Constant::Undef(_) | Constant::Poison(_)) => return Ok(()),
+            (Type::Array(_, element), Constant::Array(_, elements)) => {
+                let stride = self.layout.stride_of(element);
+                for (index, initializer) in elements.iter().enumerate() {
+                    self.initialize(address + index as u64 * stride, element, initializer)?;
+                }
+                return Ok(());
+            }
+            (Type::Struct(fields), Constant::Struct(initializers)) if fields.len() == initializers.len() => {
+                let offsets = self.layout.struct_field_offsets(fields);
+                for ((field, initializer), offset) in fields.iter().zip(initializers).zip(offsets) {
+                    self.initialize(address + offset, field, initializer)?;
+                }
+                return Ok(());
+            }
+            (_, Constant::Bytes(bytes)) => bytes.clone(),
+            _ => match self.constant(initializer)? {
+                GenericValue::Int(int) => int.to_le_bytes(self.layout.size_of(ty) as usize),
+                GenericValue::F32(value) => value.to_le_bytes().to_vec(),
+                GenericValue::F64(value) => value.to_le_bytes().to_vec(),
+                GenericValue::Pointer(pointer) => self.pointer_bytes(pointer),
+                value => return Err(unsupported(format!("initializer {} for a value of type {}", value, ty))),
+            },
+        };
+        self.memory.write_unchecked(address, &bytes);
+        Ok(())
+    }
+}
+
+fn integer_binary(op: BinaryOp, x: &ApInt, y: &ApInt) -> Result<ApInt, Trap> {
+    match op {
+        BinaryOp::Add => Ok(x.wrapping_add(y)),
+        BinaryOp::Sub => Ok(x.wrapping_sub(y)),
+        BinaryOp::Mul => Ok(x.wrapping_mul(y)),
+        BinaryOp::SDiv | BinaryOp::SRem => {
+            if y.is_zero() {
+                return Err(undefined("division by zero".to_string()));
+            }
+            if x.is_signed_min() && y.is_all_ones() {
+                return Err(undefined(format!("{} / -1 overflows i{}", x, x.bits())));
+            }
+            let (quotient, remainder) = x.signed_div_rem(y);
+            Ok(if op == BinaryOp::SDiv { quotient } else { remainder })
+        }
+        BinaryOp::UDiv | BinaryOp::URem => {
+            if y.is_zero() {
+                return Err(undefined("division by zero".to_string()));
+            }
+            let (quotient, remainder) = x.unsigned_div_rem(y);
+            Ok(if op == BinaryOp::UDiv { quotient } else { remainder })
+        }
+        BinaryOp::Shl | BinaryOp::AShr | BinaryOp::LShr => {
+            let amount = match y.to_u64() {
+                Some(amount) if !y.is_negative() && amount < x.bits() as u64 => amount as usize,
+                _ => return Err(undefined(format!("shift by {}, which is not less than the width of i{}", y, x.bits()))),
+            };
+            Ok(match op {
+                BinaryOp::Shl => x.shl(amount),
+                BinaryOp::AShr => x.ashr(amount),
+                _ => x.lshr(amount),
+            })
+        }
+        BinaryOp::And => Ok(x.and(y)),
+        BinaryOp::Or => Ok(x.or(y)),
+        BinaryOp::Xor => Ok(x.xor(y)),
+        _ => Err(unsupported(format!("{} of integers", op.name()))),
+    }
+}
+
+/// Computes a float operation in `f64`. Rounding the result to `f32`
+/// afterwards gives the same value as computing in `f32`.
+fn float_binary(op: BinaryOp, x: f64, y: f64) -> Result<f64, Trap> {
+    match op {
+        BinaryOp::FAdd => Ok(x + y),
+        BinaryOp::FSub => Ok(x - y),
+        BinaryOp::FMul => Ok(x * y),
+        BinaryOp::FDiv => Ok(x / y),
+        BinaryOp::FRem => Ok(x % y),
+        _ => Err(unsupported(format!("{} of floats", op.name()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::parser::parse_module;
+
+    fn module(source: &str) -> Module {
+        parse_module(source).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[test]
+    fn runs_a_loop() {
+        let module = module(r#"
+            define internal function @sum(%n: i32) -> i32 {
+            %entry:
+              branch %loop
+            %loop:
+              %i = phi i32 0, %entry, %next, %loop
+              %total = phi i32 0, %entry, %added, %loop
+              %added = add i32 %total, %i
+              %next = add i32 %i, 1
+              %done = sgt i32 %next, %n
+              branch %done, %exit, %loop
+            %exit:
+              return i32 %added
+            }
+        "#);
+        let mut interpreter = Interpreter::new(&module).unwrap();
+        assert_eq!(interpreter.run_function("sum", &[GenericValue::int(32, 10)]), Ok(GenericValue::int(32, 55)));
+        assert_eq!(interpreter.run_function("sum", &[GenericValue::int(32, 0)]), Ok(GenericValue::int(32, 0)));
+    }
+
+    #[test]
+    fn calls_hooks_with_simulated_memory() {
+        let module = module(r#"
+            @message = private constant [3 x i8] c"hi\00"
+            @count = internal global i64 0
+
+            declare external function @puts(%s: [3 x i8]*) -> i32
+
+            define internal function @main() -> i64 {
+            %entry:
+              %slot = alloca i64, align 8
+              store i64* %slot, 2
+              %printed = call [3 x i8]* -> i32 @puts(@message)
+              %two = load i64* %slot
+              %old = load i64* @count
+              %new = add i64 %old, %two
+              store i64* @count, %new
+              return i64 %new
+            }
+        "#);
+        let mut printed = Vec::new();
+        {
+            let mut interpreter = Interpreter::new(&module).unwrap();
+            interpreter.add_hook("puts", |memory, args| {
+                printed.push(memory.read_c_string(args[0].as_pointer().unwrap())?);
+                Ok(GenericValue::int(32, 0))
+            });
+            assert_eq!(interpreter.run_function("main", &[]), Ok(GenericValue::int(64, 2)));
+            assert_eq!(interpreter.run_function("main", &[]), Ok(GenericValue::int(64, 4)));
+        }
+        assert_eq!(printed, [b"hi".to_vec(), b"hi".to_vec()]);
+    }
+
+    #[test]
+    fn traps_on_undefined_behavior() {
+        let module = module(r#"
+            declare external function @abort() -> void
+
+            define internal function @divide(%a: i32, %b: i32) -> i32 {
+            %entry:
+              %quotient = sdiv i32 %a, %b
+              return i32 %quotient
+            }
+
+            define internal function @stop() -> void {
+            %entry:
+              %none = call -> void @abort()
+              return void
+            }
+        "#);
+        let mut interpreter = Interpreter::new(&module).unwrap();
+        let error = interpreter.run_function("divide", &[GenericValue::int(32, 1), GenericValue::int(32, 0)]).unwrap_err();
+        assert_eq!(error.block.as_deref(), Some("%entry"));
+        assert!(matches!(error.trap, Trap::UndefinedBehavior(_)), "{}", error);
+        let error = interpreter.run_function("divide", &[GenericValue::int(32, i32::MIN as i64), GenericValue::int(32, -1)]).unwrap_err();
+        assert!(matches!(error.trap, Trap::UndefinedBehavior(_)), "{}", error);
+        let error = interpreter.run_function("stop", &[]).unwrap_err();
+        assert!(matches!(error.trap, Trap::Unsupported(_)), "{}", error);
+    }
+
+    #[test]
+    fn converts_between_integers_floats_and_pointers() {
+        let module = module(r#"
+            define internal function @extend(%a: i8) -> i64 {
+            %entry:
+              %signed = sext i8 %a to i64
+              %unsigned = zext i8 %a to i64
+              %sum = add i64 %signed, %unsigned
+              return i64 %sum
+            }
+
+            define internal function @halve(%x: f64) -> i32 {
+            %entry:
+              %single = fptrunc f64 %x to f32
+              %double = fpext f32 %single to f64
+              %int = fptosi f64 %double to i32
+              return i32 %int
+            }
+
+            define internal function @unsigned(%a: i32) -> f64 {
+            %entry:
+              %double = uitofp i32 %a to f64
+              return f64 %double
+            }
+
+            define internal function @bits(%x: f64) -> i64 {
+            %entry:
+              %bits = bitcast f64 %x to i64
+              %pointer = inttoptr i64 %bits to i8*
+              %address = ptrtoint i8* %pointer to i64
+              %low = trunc i64 %address to i32
+              %wide = zext i32 %low to i64
+              return i64 %wide
+            }
+        "#);
+        let mut interpreter = Interpreter::new(&module).unwrap();
+        // -1 sign-extends to -1 and zero-extends to 255
+        assert_eq!(interpreter.run_function("extend", &[GenericValue::int(8, -1)]), Ok(GenericValue::int(64, 254)));
+        // conversions to integers round toward zero
+        assert_eq!(interpreter.run_function("halve", &[GenericValue::F64(-2.75)]), Ok(GenericValue::int(32, -2)));
+        assert_eq!(interpreter.run_function("unsigned", &[GenericValue::int(32, -1)]), Ok(GenericValue::F64(4294967295.0)));
+        assert_eq!(interpreter.run_function("bits", &[GenericValue::F64(1.5)]), Ok(GenericValue::int(64, 0)));
+        assert_eq!(interpreter.run_function("bits", &[GenericValue::F64(f64::from_bits(0x1234_5678_9abc_def0))]), Ok(GenericValue::int(64, 0x9abc_def0)));
+        // floats outside the range of the integer are undefined
+        let error = interpreter.run_function("halve", &[GenericValue::F64(3e9)]).unwrap_err();
+        assert!(matches!(error.trap, Trap::UndefinedBehavior(_)), "{}", error);
+    }
+
+    #[test]
+    fn flips_and_combines_the_bits_of_integers() {
+        let module = module(r#"
+            define internal function @mask(%a: i8, %b: i8) -> i8 {
+            %entry:
+              %flipped = not i8 %a
+              %both = and i8 %flipped, %b
+              %either = or i8 %both, 1
+              return i8 %either
+            }
+        "#);
+        let mut interpreter = Interpreter::new(&module).unwrap();
+        // not 5 is -6, whose bits in common with 14 make 10
+        assert_eq!(interpreter.run_function("mask", &[GenericValue::int(8, 5), GenericValue::int(8, 14)]), Ok(GenericValue::int(8, 11)));
+        assert_eq!(interpreter.run_function("mask", &[GenericValue::int(8, -1), GenericValue::int(8, -1)]), Ok(GenericValue::int(8, 1)));
+    }
+
+    #[test]
+    fn reads_operands_as_unsigned() {
+        let module = module(r#"
+            define internal function @divide(%a: i8, %b: i8) -> i8 {
+            %entry:
+              %quotient = udiv i8 %a, %b
+              %rest = urem i8 %a, %b
+              %shifted = lshr i8 %a, 4
+              %sum = add i8 %quotient, %rest
+              %total = add i8 %sum, %shifted
+              return i8 %total
+            }
+
+            define internal function @below(%a: i8, %b: i8) -> i1 {
+            %entry:
+              %below = ult i8 %a, %b
+              return i1 %below
+            }
+        "#);
+        let mut interpreter = Interpreter::new(&module).unwrap();
+        // 0xf0 is 240: 24 and 0 from dividing by 10, and 15 from the shift
+        assert_eq!(interpreter.run_function("divide", &[GenericValue::int(8, -16), GenericValue::int(8, 10)]), Ok(GenericValue::int(8, 39)));
+        assert_eq!(interpreter.run_function("below", &[GenericValue::int(8, 1), GenericValue::int(8, -1)]), Ok(GenericValue::bool(true)));
+        assert_eq!(interpreter.run_function("below", &[GenericValue::int(8, -1), GenericValue::int(8, 1)]), Ok(GenericValue::bool(false)));
+        let error = interpreter.run_function("divide", &[GenericValue::int(8, 1), GenericValue::int(8, 0)]).unwrap_err();
+        assert!(matches!(error.trap, Trap::UndefinedBehavior(_)), "{}", error);
+    }
+
+    #[test]
+    fn compares_floats_with_and_without_ordering() {
+        let module = module(r#"
+            define internal function @rest(%a: f64, %b: f64) -> f64 {
+            %entry:
+              %rest = frem f64 %a, %b
+              %negated = fneg f64 %rest
+              %sum = fadd fast f64 %negated, 0.5
+              return f64 %sum
+            }
+
+            define internal function @ordered(%a: f64, %b: f64) -> i1 {
+            %entry:
+              %less = fcmp olt f64 %a, %b
+              return i1 %less
+            }
+
+            define internal function @unordered(%a: f64, %b: f64) -> i1 {
+            %entry:
+              %less = fcmp ult f64 %a, %b
+              return i1 %less
+            }
+        "#);
+        let mut interpreter = Interpreter::new(&module).unwrap();
+        // the remainder keeps the sign of the dividend
+        assert_eq!(interpreter.run_function("rest", &[GenericValue::F64(-7.5), GenericValue::F64(2.0)]), Ok(GenericValue::F64(2.0)));
+        let (one, nan) = (GenericValue::F64(1.0), GenericValue::F64(f64::NAN));
+        assert_eq!(interpreter.run_function("ordered", &[one.clone(), GenericValue::F64(2.0)]), Ok(GenericValue::bool(true)));
+        assert_eq!(interpreter.run_function("unordered", &[one.clone(), GenericValue::F64(2.0)]), Ok(GenericValue::bool(true)));
+        assert_eq!(interpreter.run_function("ordered", &[one.clone(), nan.clone()]), Ok(GenericValue::bool(false)));
+        assert_eq!(interpreter.run_function("unordered", &[nan, one]), Ok(GenericValue::bool(true)));
+    }
+
+    #[test]
+    fn evaluates_constant_expressions_and_wide_integers() {
+        let module = module(r#"
+            @table = internal global [2 x i32] [7, -1]
+            @big = internal global i96 -2
+
+            define internal function @second(%a: i64) -> i64 {
+            %entry:
+              %address = add i64 %a, add (i64 ptrtoint ([2 x i32]* @table to i64), 4)
+              %pointer = inttoptr i64 %address to i32*
+              %value = load i32* %pointer
+              %wide = sext i32 %value to i64
+              return i64 %wide
+            }
+
+            define internal function @halve() -> i32 {
+            %entry:
+              %big = load i96* @big
+              %half = udiv i96 %big, 2
+              %high = lshr i96 %half, 64
+              %low = trunc i96 %high to i32
+              return i32 %low
+            }
+        "#);
+        let mut interpreter = Interpreter::new(&module).unwrap();
+        assert_eq!(interpreter.run_function("second", &[GenericValue::int(64, 0)]), Ok(GenericValue::int(64, -1)));
+        assert_eq!(interpreter.run_function("second", &[GenericValue::int(64, -4)]), Ok(GenericValue::int(64, 7)));
+        // 2^96 - 2 halved has 31 ones above the low 64 bits
+        assert_eq!(interpreter.run_function("halve", &[]), Ok(GenericValue::int(32, 0x7fff_ffff)));
+    }
+
+    #[test]
+    fn indexes_into_structs_and_arrays() {
+        let module = module(r#"
+            @pair = internal global { i8, [4 x i32] } { 1, [10, 20, 30, 40] }
+
+            define internal function @element(%i: i64) -> i32 {
+            %entry:
+              %element = getelementptr { i8, [4 x i32] }* @pair, i64 0, i32 1, i64 %i
+              %value = load i32* %element
+              store i32* %element, 7
+              return i32 %value
+            }
+
+            define internal function @before(%i: i8) -> i32 {
+            %entry:
+              %next = getelementptr { i8, [4 x i32] }* @pair, i32 1
+              %array = bitcast { i8, [4 x i32] }* %next to [4 x i32]*
+              %element = getelementptr [4 x i32]* %array, i32 0, i8 %i
+              %value = load i32* %element
+              return i32 %value
+            }
+        "#);
+        let mut interpreter = Interpreter::new(&module).unwrap();
+        assert_eq!(interpreter.run_function("element", &[GenericValue::int(64, 2)]), Ok(GenericValue::int(32, 30)));
+        assert_eq!(interpreter.run_function("element", &[GenericValue::int(64, 2)]), Ok(GenericValue::int(32, 7)));
+        // indices are signed: the last element sits 4 bytes before the next struct
+        assert_eq!(interpreter.run_function("before", &[GenericValue::int(8, -1)]), Ok(GenericValue::int(32, 40)));
+    }
+}
@@ -0,0 +1,427 @@
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+
+/// An integer of any width, held as little-endian 64-bit words. The bits
+/// above the width are always zero; whether the value is signed is up to
+/// the operation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ApInt {
+    bits: usize,
+    words: Vec<u64>,
+}
+
+impl ApInt {
+    pub fn zero(bits: usize) -> Self {
+        assert!(bits > 0, "integers are at least one bit wide");
+        Self { bits, words: vec![0; bits.div_ceil(64)] }
+    }
+
+    /// Returns `value` sign-extended or truncated to `bits`.
+    pub fn from_i64(bits: usize, value: i64) -> Self {
+        let mut int = Self::zero(bits);
+        int.words.fill(if value < 0 { u64::MAX } else { 0 });
+        int.words[0] = value as u64;
+        int.normalize();
+        int
+    }
+
+    /// Returns `value` zero-extended or truncated to `bits`.
+    pub fn from_u64(bits: usize, value: u64) -> Self {
+        let mut int = Self::zero(bits);
+        int.words[0] = value;
+        int.normalize();
+        int
+    }
+
+    pub fn from_bool(value: bool) -> Self {
+        Self::from_u64(1, value as u64)
+    }
+
+    /// Parses a decimal integer, which may be negative. Returns `None` if it
+    /// fits in `bits` neither as a signed nor as an unsigned integer.
+    pub fn parse(bits: usize, text: &str) -> Option<Self> {
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text),
+        };
+        if digits.is_empty() || !digits.bytes().all(|digit| digit.is_ascii_digit()) {
+            return None;
+        }
+        // four spare bits hold a digit more than the width allows
+        let ten = Self::from_u64(bits + 4, 10);
+        let mut magnitude = Self::zero(bits + 4);
+        for digit in digits.bytes() {
+            magnitude = magnitude.wrapping_mul(&ten).wrapping_add(&Self::from_u64(bits + 4, (digit - b'0') as u64));
+            if !magnitude.lshr(bits).is_zero() {
+                return None;
+            }
+        }
+        if negative {
+            // down to the smallest signed value
+            if !magnitude.is_zero() && !magnitude.wrapping_sub(&Self::from_u64(bits + 4, 1)).lshr(bits - 1).is_zero() {
+                return None;
+            }
+            return Some(magnitude.resize(bits).wrapping_neg());
+        }
+        Some(magnitude.resize(bits))
+    }
+
+    /// Reads an integer from its little-endian in-memory representation.
+    pub fn from_le_bytes(bits: usize, bytes: &[u8]) -> Self {
+        let mut int = Self::zero(bits);
+        for (i, byte) in bytes.iter().enumerate().take(int.words.len() * 8) {
+            int.words[i / 8] |= (*byte as u64) << (i % 8 * 8);
+        }
+        int.normalize();
+        int
+    }
+
+    /// Returns the little-endian representation, zero-padded or truncated to `len` bytes.
+    pub fn to_le_bytes(&self, len: usize) -> Vec<u8> {
+        let mut bytes: Vec<u8> = self.words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        bytes.resize(len, 0);
+        bytes
+    }
+
+    pub fn bits(&self) -> usize {
+        self.bits
+    }
+
+    fn normalize(&mut self) {
+        let unused = self.words.len() * 64 - self.bits;
+        if unused > 0 {
+            *self.words.last_mut().unwrap() &= u64::MAX >> unused;
+        }
+    }
+
+    fn bit(&self, index: usize) -> bool {
+        self.words[index / 64] >> (index % 64) & 1 != 0
+    }
+
+    fn set_bit(&mut self, index: usize) {
+        self.words[index / 64] |= 1 << (index % 64);
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.words.iter().all(|word| *word == 0)
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.bit(self.bits - 1)
+    }
+
+    /// Returns whether this is the smallest signed value of its width.
+    pub fn is_signed_min(&self) -> bool {
+        self.is_negative() && (0..self.bits - 1).all(|index| !self.bit(index))
+    }
+
+    pub fn is_all_ones(&self) -> bool {
+        (0..self.bits).all(|index| self.bit(index))
+    }
+
+    /// Returns the value as a signed integer, truncated to 64 bits.
+    pub fn to_i64(&self) -> i64 {
+        if self.bits >= 64 {
+            self.words[0] as i64
+        } else {
+            let unused = 64 - self.bits;
+            (self.words[0] << unused) as i64 >> unused
+        }
+    }
+
+    /// Returns the value as a register or memory holds it: sign-extended to
+    /// 64 bits, except for booleans, which are 0 or 1.
+    pub fn to_machine_i64(&self) -> i64 {
+        if self.bits == 1 { self.words[0] as i64 } else { self.to_i64() }
+    }
+
+    /// Returns the value as an unsigned integer, if it fits in 64 bits.
+    pub fn to_u64(&self) -> Option<u64> {
+        self.words[1..].iter().all(|word| *word == 0).then_some(self.words[0])
+    }
+
+    fn to_i128(&self) -> i128 {
+        let low = self.words[0] as u128 | (self.words.get(1).copied().unwrap_or(0) as u128) << 64;
+        let unused = 128 - self.bits.min(128);
+        (low << unused) as i128 >> unused
+    }
+
+    /// Returns the value zero-extended or truncated to `bits`.
+    pub fn resize(&self, bits: usize) -> Self {
+        let mut int = Self::zero(bits);
+        let len = int.words.len().min(self.words.len());
+        int.words[..len].copy_from_slice(&self.words[..len]);
+        int.normalize();
+        int
+    }
+
+    /// Returns the value sign-extended or truncated to `bits`.
+    pub fn sext(&self, bits: usize) -> Self {
+        let mut int = self.resize(bits);
+        if self.is_negative() {
+            for index in self.bits..bits {
+                int.set_bit(index);
+            }
+        }
+        int
+    }
+
+    fn zip(&self, other: &Self, op: impl Fn(u64, u64) -> u64) -> Self {
+        assert_eq!(self.bits, other.bits, "operands have different widths");
+        let mut int = Self { bits: self.bits, words: self.words.iter().zip(&other.words).map(|(a, b)| op(*a, *b)).collect() };
+        int.normalize();
+        int
+    }
+
+    pub fn and(&self, other: &Self) -> Self {
+        self.zip(other, |a, b| a & b)
+    }
+
+    pub fn or(&self, other: &Self) -> Self {
+        self.zip(other, |a, b| a | b)
+    }
+
+    pub fn xor(&self, other: &Self) -> Self {
+        self.zip(other, |a, b| a ^ b)
+    }
+
+    pub fn not(&self) -> Self {
+        let mut int = Self { bits: self.bits, words: self.words.iter().map(|word| !word).collect() };
+        int.normalize();
+        int
+    }
+
+    pub fn wrapping_add(&self, other: &Self) -> Self {
+        assert_eq!(self.bits, other.bits, "operands have different widths");
+        let mut int = Self::zero(self.bits);
+        let mut carry = false;
+        for (i, (a, b)) in self.words.iter().zip(&other.words).enumerate() {
+            let (sum, c1) = a.overflowing_add(*b);
+            let (sum, c2) = sum.overflowing_add(carry as u64);
+            int.words[i] = sum;
+            carry = c1 || c2;
+        }
+        int.normalize();
+        int
+    }
+
+    pub fn wrapping_neg(&self) -> Self {
+        self.not().wrapping_add(&Self::from_u64(self.bits, 1))
+    }
+
+    pub fn wrapping_sub(&self, other: &Self) -> Self {
+        self.wrapping_add(&other.wrapping_neg())
+    }
+
+    pub fn wrapping_mul(&self, other: &Self) -> Self {
+        assert_eq!(self.bits, other.bits, "operands have different widths");
+        let len = self.words.len();
+        let mut words = vec![0u64; len];
+        for i in 0..len {
+            let mut carry = 0u128;
+            for j in 0..len - i {
+                let product = self.words[i] as u128 * other.words[j] as u128 + words[i + j] as u128 + carry;
+                words[i + j] = product as u64;
+                carry = product >> 64;
+            }
+        }
+        let mut int = Self { bits: self.bits, words };
+        int.normalize();
+        int
+    }
+
+    /// Shifts left by `amount`, which must be less than the width.
+    pub fn shl(&self, amount: usize) -> Self {
+        let mut int = Self::zero(self.bits);
+        for index in amount..self.bits {
+            if self.bit(index - amount) {
+                int.set_bit(index);
+            }
+        }
+        int
+    }
+
+    /// Shifts right by `amount`, which must be less than the width, copying the sign bit.
+    pub fn ashr(&self, amount: usize) -> Self {
+        let mut int = Self::zero(self.bits);
+        for index in 0..self.bits {
+            if self.bit((index + amount).min(self.bits - 1)) {
+                int.set_bit(index);
+            }
+        }
+        int
+    }
+
+    /// Shifts right by `amount`, which must be less than the width, filling with zeros.
+    pub fn lshr(&self, amount: usize) -> Self {
+        let mut int = Self::zero(self.bits);
+        for index in 0..self.bits - amount {
+            if self.bit(index + amount) {
+                int.set_bit(index);
+            }
+        }
+        int
+    }
+
+    pub fn unsigned_cmp(&self, other: &Self) -> Ordering {
+        self.words.iter().rev().cmp(other.words.iter().rev())
+    }
+
+    pub fn signed_cmp(&self, other: &Self) -> Ordering {
+        match (self.is_negative(), other.is_negative()) {
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            _ => self.unsigned_cmp(other),
+        }
+    }
+
+    /// Returns the quotient and remainder of unsigned division. `divisor` must not be zero.
+    pub fn unsigned_div_rem(&self, divisor: &Self) -> (Self, Self) {
+        // one spare bit, so that doubling the remainder never overflows
+        let divisor = divisor.resize(self.bits + 1);
+        let mut quotient = Self::zero(self.bits);
+        let mut remainder = Self::zero(self.bits + 1);
+        for index in (0..self.bits).rev() {
+            remainder = remainder.shl(1);
+            if self.bit(index) {
+                remainder.set_bit(0);
+            }
+            if remainder.unsigned_cmp(&divisor) != Ordering::Less {
+                remainder = remainder.wrapping_sub(&divisor);
+                quotient.set_bit(index);
+            }
+        }
+        (quotient, remainder.resize(self.bits))
+    }
+
+    fn magnitude(&self) -> Self {
+        if self.is_negative() { self.wrapping_neg() } else { self.clone() }
+    }
+
+    /// Returns the quotient and remainder of signed division, rounding towards
+    /// zero. `divisor` must not be zero, and the quotient must not overflow.
+    pub fn signed_div_rem(&self, divisor: &Self) -> (Self, Self) {
+        let (quotient, remainder) = self.magnitude().unsigned_div_rem(&divisor.magnitude());
+        let quotient = if self.is_negative() != divisor.is_negative() { quotient.wrapping_neg() } else { quotient };
+        let remainder = if self.is_negative() { remainder.wrapping_neg() } else { remainder };
+        (quotient, remainder)
+    }
+}
+
+impl Display for ApInt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.bits <= 128 {
+            return write!(f, "{}", self.to_i128());
+        }
+        // print the magnitude in chunks of 18 digits, which fit in a word
+        let chunk = Self::from_u64(self.bits, 1_000_000_000_000_000_000);
+        let mut magnitude = self.magnitude();
+        let mut chunks = Vec::new();
+        loop {
+            let (quotient, remainder) = magnitude.unsigned_div_rem(&chunk);
+            chunks.push(remainder.words[0]);
+            if quotient.is_zero() {
+                break;
+            }
+            magnitude = quotient;
+        }
+        if self.is_negative() {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:018}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_at_odd_widths() {
+        let max = ApInt::from_i64(7, 63);
+        assert_eq!(max.wrapping_add(&ApInt::from_i64(7, 1)), ApInt::from_i64(7, -64));
+        assert_eq!(ApInt::from_i64(7, -64).to_i64(), -64);
+        assert_eq!(ApInt::from_i64(7, -1).to_u64(), Some(127));
+        assert_eq!(ApInt::from_i64(7, -64).wrapping_neg(), ApInt::from_i64(7, -64));
+        assert!(ApInt::from_i64(7, -64).is_signed_min());
+        assert!(ApInt::from_i64(7, -1).is_all_ones());
+    }
+
+    #[test]
+    fn carries_across_words() {
+        let low = ApInt::from_u64(96, u64::MAX);
+        let sum = low.wrapping_add(&ApInt::from_u64(96, 1));
+        assert_eq!(sum, ApInt::from_u64(96, 1).shl(64));
+        assert_eq!(sum.to_u64(), None);
+        assert_eq!(low.wrapping_mul(&low).to_le_bytes(12), [1, 0, 0, 0, 0, 0, 0, 0, 0xfe, 0xff, 0xff, 0xff]);
+        assert_eq!(ApInt::from_i64(96, -1).ashr(90), ApInt::from_i64(96, -1));
+        assert_eq!(ApInt::from_le_bytes(96, &sum.to_le_bytes(12)), sum);
+    }
+
+    #[test]
+    fn compares_signed_and_unsigned() {
+        let minus_one = ApInt::from_i64(33, -1);
+        let one = ApInt::from_i64(33, 1);
+        assert_eq!(minus_one.signed_cmp(&one), Ordering::Less);
+        assert_eq!(minus_one.unsigned_cmp(&one), Ordering::Greater);
+        assert_eq!(ApInt::from_i64(33, -5).resize(64).to_i64(), (1 << 33) - 5);
+    }
+
+    #[test]
+    fn divides_and_shifts_unsigned() {
+        let minus_seven = ApInt::from_i64(33, -7);
+        let (quotient, remainder) = minus_seven.unsigned_div_rem(&ApInt::from_i64(33, 2));
+        assert_eq!(quotient, ApInt::from_u64(33, ((1 << 33) - 7) / 2));
+        assert_eq!(remainder, ApInt::from_i64(33, 1));
+        assert_eq!(minus_seven.lshr(30), ApInt::from_i64(33, 7));
+        assert_eq!(ApInt::from_i64(96, -1).lshr(90), ApInt::from_i64(96, 63));
+    }
+
+    #[test]
+    fn divides_signed_towards_zero() {
+        let (quotient, remainder) = ApInt::from_i64(7, -64).signed_div_rem(&ApInt::from_i64(7, 3));
+        assert_eq!((quotient, remainder), (ApInt::from_i64(7, -21), ApInt::from_i64(7, -1)));
+        let (quotient, remainder) = ApInt::from_i64(7, 13).signed_div_rem(&ApInt::from_i64(7, -4));
+        assert_eq!((quotient, remainder), (ApInt::from_i64(7, -3), ApInt::from_i64(7, 1)));
+        // the divisor is wider than a word
+        let big = ApInt::from_i64(129, -1).shl(100);
+        let (quotient, remainder) = big.wrapping_sub(&ApInt::from_i64(129, 5)).signed_div_rem(&ApInt::from_i64(129, 1).shl(100));
+        assert_eq!((quotient, remainder), (ApInt::from_i64(129, -1), ApInt::from_i64(129, -5)));
+    }
+
+    #[test]
+    fn parses_signed_and_unsigned_ranges() {
+        assert_eq!(ApInt::parse(7, "127"), Some(ApInt::from_i64(7, -1)));
+        assert_eq!(ApInt::parse(7, "-64"), Some(ApInt::from_i64(7, -64)));
+        assert_eq!(ApInt::parse(7, "128"), None);
+        assert_eq!(ApInt::parse(7, "-65"), None);
+        assert_eq!(ApInt::parse(7, "-0"), Some(ApInt::zero(7)));
+        assert_eq!(ApInt::parse(7, "1_0"), None);
+        assert_eq!(ApInt::parse(1, "1"), Some(ApInt::from_bool(true)));
+        assert_eq!(ApInt::parse(1, "-1"), Some(ApInt::from_bool(true)));
+        assert_eq!(ApInt::parse(1, "2"), None);
+        // 2^129 - 1 and -2^128 are the bounds at 129 bits
+        assert!(ApInt::parse(129, "680564733841876926926749214863536422911").unwrap().is_all_ones());
+        assert_eq!(ApInt::parse(129, "680564733841876926926749214863536422912"), None);
+        assert!(ApInt::parse(129, "-340282366920938463463374607431768211456").unwrap().is_signed_min());
+        assert_eq!(ApInt::parse(129, "-340282366920938463463374607431768211457"), None);
+    }
+
+    #[test]
+    fn displays_as_signed_decimal() {
+        assert_eq!(ApInt::from_i64(7, 127).to_string(), "-1");
+        assert_eq!(ApInt::from_u64(65, u64::MAX).to_string(), "18446744073709551615");
+        assert_eq!(ApInt::from_i64(65, i64::MIN).to_string(), "-9223372036854775808");
+        let power = ApInt::from_u64(200, 1).shl(128);
+        assert_eq!(power.to_string(), "340282366920938463463374607431768211456");
+        assert_eq!(power.wrapping_neg().to_string(), "-340282366920938463463374607431768211456");
+        // a zero chunk in the middle keeps its leading zeros
+        let text = "1000000000000000000000000000000000000000000000000001";
+        assert_eq!(ApInt::parse(200, text).unwrap().to_string(), text);
+        assert_eq!(ApInt::from_i64(200, -1).to_string(), "-1");
+    }
+}
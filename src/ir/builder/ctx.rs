@@ -1,11 +1,14 @@
 use crate::ir::values::basic_block::BasicBlock;
 use crate::ir::module::Module;
+use crate::ir::values::instruction::FastMathFlags;
 use std::cell::RefCell;
 use std::rc::Rc;
 
 #[derive(Clone)]
 pub struct IRContext {
     pub insertion_point: Option<Rc<RefCell<BasicBlock>>>,
+    /// The fast-math flags given to new float instructions.
+    pub fast_math_flags: FastMathFlags,
     module: Module,
 }
 
@@ -13,6 +16,7 @@ impl IRContext {
     pub fn new(module: Module) -> Self {
         Self {
             insertion_point: None,
+            fast_math_flags: FastMathFlags::default(),
             module,
         }
     }
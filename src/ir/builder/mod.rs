@@ -1,19 +1,26 @@
 pub mod ctx;
 
-use crate::ir::values::value::Value;
 use crate::ir::values::basic_block::BasicBlock;
 use crate::ir::values::value::ValueEntity;
 use crate::ir::values::instruction::Instruction;
 use crate::ir::values::instruction::InstructionType;
+use crate::ir::values::instruction::{FastMathFlags, FloatPredicate};
+use crate::ir::values::instruction::is_valid_cast;
 use crate::ir::values::value::Type;
 use crate::ir::values::function::Function;
+use crate::ir::values::global::GlobalVariable;
+use crate::ir::values::constant::{Constant, ConstantExpr, CONSTANT_BINARY_OPCODES};
+use crate::ir::apint::ApInt;
 use crate::ir::linkage::Linkage;
-use crate::utils::find_element;
-use std::borrow::Borrow;
+use crate::ir::parser::lexer::is_name_char;
+use crate::error::Error;
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use crate::emit::asm::AssemblyEmitter;
+use crate::emit::asm::{AssemblyEmitter, FrameOptions};
+use crate::emit::c::CEmitter;
+use crate::emit::llvm::LlvmEmitter;
+use crate::emit::object::ObjectEmitter;
 use std::io::Write;
 
 pub use crate::ir::builder::ctx::IRContext;
@@ -37,15 +44,23 @@ impl Builder {
         self.ctx.insertion_point = Some(insertion_point.clone());
     }
 
-    pub fn insert(&mut self, value: Instruction) {
+    /// Sets the fast-math flags given to the float instructions built from now on.
+    pub fn set_fast_math_flags(&mut self, flags: FastMathFlags) {
+        self.ctx.fast_math_flags = flags;
+    }
+
+    pub fn get_fast_math_flags(&self) -> FastMathFlags {
+        self.ctx.fast_math_flags
+    }
+
+    pub fn insert(&mut self, value: Instruction) -> Result<(), Error> {
         // we can't insert to a non-existent insertion point
         match &mut self.ctx.insertion_point {
             Some(ref mut insertion_point) => {
                 insertion_point.borrow_mut().insert(ValueEntity::Instruction(value));
+                Ok(())
             },
-            None => {
-                panic!("Cannot insert value without insertion point");
-            },
+            None => Err(Error::NoInsertionPoint),
         }
     }
     
@@ -113,272 +128,874 @@ impl Builder {
     // utility
 
     pub fn get_i32(&self, value: i32) -> ValueEntity {
-        ValueEntity::Instruction(Instruction::new(self.get_i32_type(), InstructionType::ConstantInt32(value), None))
+        Constant::from_i64(32, value as i64).into()
     }
 
     pub fn get_i64(&self, value: i64) -> ValueEntity {
-        ValueEntity::Instruction(Instruction::new(self.get_i64_type(), InstructionType::ConstantInt64(value), None))
+        Constant::from_i64(64, value).into()
     }
 
     pub fn get_bool(&self, value: bool) -> ValueEntity {
-        ValueEntity::Instruction(Instruction::new(self.get_bool_type(), InstructionType::ConstantBool(value), None))
+        Constant::from_bool(value).into()
     }
 
-    pub fn create_function(&mut self, name: &str, argument_types: Vec<Type>, return_type: Type, linkage: Linkage, is_varg: bool) -> Rc<RefCell<Function>> {
+    pub fn get_f32(&self, value: f32) -> ValueEntity {
+        Constant::from_f32(value).into()
+    }
+
+    pub fn get_f64(&self, value: f64) -> ValueEntity {
+        Constant::from_f64(value).into()
+    }
+
+    // constants
+
+    /// Returns `value` as a constant of the integer type `ty`, sign-extended or truncated to its width.
+    pub fn const_int(&self, ty: Type, value: i64) -> Result<Constant, Error> {
+        match ty {
+            Type::Integer(bits) => Ok(Constant::from_i64(bits, value)),
+            ty => Err(Error::InvalidOperandType { operation: "integer constant", ty }),
+        }
+    }
+
+    /// Returns an integer constant as wide as `value`.
+    pub fn const_apint(&self, value: ApInt) -> Constant {
+        Constant::Int(value)
+    }
+
+    pub fn const_null(&self, ty: Type) -> Result<Constant, Error> {
+        if !ty.is_pointer() {
+            return Err(Error::InvalidOperandType { operation: "null", ty });
+        }
+        Ok(Constant::Null(ty))
+    }
+
+    pub fn const_undef(&self, ty: Type) -> Result<Constant, Error> {
+        check_value_type("undef", &ty)?;
+        Ok(Constant::Undef(ty))
+    }
+
+    pub fn const_poison(&self, ty: Type) -> Result<Constant, Error> {
+        check_value_type("poison", &ty)?;
+        Ok(Constant::Poison(ty))
+    }
+
+    /// Returns the value of `ty` with all bytes zero.
+    pub fn const_zero(&self, ty: Type) -> Result<Constant, Error> {
+        check_value_type("zeroinitializer", &ty)?;
+        Ok(Constant::zero(&ty))
+    }
+
+    pub fn const_array(&self, element_type: Type, elements: Vec<Constant>) -> Result<Constant, Error> {
+        check_value_type("array", &element_type)?;
+        for element in &elements {
+            check_type(&element_type, &element.get_type())?;
+        }
+        Ok(Constant::Array(element_type, elements))
+    }
+
+    pub fn const_struct(&self, fields: Vec<Constant>) -> Constant {
+        Constant::Struct(fields)
+    }
+
+    /// Returns `value` as a nul-terminated array of `i8`.
+    pub fn const_string(&self, value: &str) -> Constant {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.push(0);
+        Constant::Bytes(bytes)
+    }
+
+    /// Returns the address of a function or global variable as a constant.
+    pub fn const_address(&self, value: &ValueEntity) -> Result<Constant, Error> {
+        match value {
+            ValueEntity::Function(function) => Ok(Constant::Symbol(function.get_type().get_pointer_to(), function.get_name())),
+            ValueEntity::GlobalVariable(global) => Ok(Constant::Symbol(global.get_type(), global.get_name())),
+            _ => Err(Error::InvalidOperandType { operation: "constant address", ty: value.get_type() }),
+        }
+    }
+
+    /// Returns the conversion `opcode` of `value` to `ty` as a constant expression.
+    pub fn const_cast(&self, opcode: &'static str, value: Constant, ty: Type) -> Result<Constant, Error> {
+        if !is_valid_cast(opcode, &value.get_type(), &ty) {
+            return Err(Error::InvalidCast { operation: opcode, from: value.get_type(), to: ty });
+        }
+        Ok(Constant::Expr(Box::new(ConstantExpr::Cast(opcode, value, ty))))
+    }
+
+    /// Returns the integer operation `opcode`, one of `add`, `sub`, `mul`,
+    /// `shl`, `lshr`, `ashr` and `xor`, as a constant expression.
+    pub fn const_binary(&self, opcode: &'static str, lhs: Constant, rhs: Constant) -> Result<Constant, Error> {
+        let ty = lhs.get_type();
+        if !CONSTANT_BINARY_OPCODES.contains(&opcode) || !ty.is_integer() {
+            return Err(Error::InvalidOperandType { operation: opcode, ty });
+        }
+        check_type(&ty, &rhs.get_type())?;
+        Ok(Constant::Expr(Box::new(ConstantExpr::Binary(opcode, lhs, rhs))))
+    }
+
+    pub fn create_function(&mut self, name: &str, argument_types: Vec<Type>, return_type: Type, linkage: Linkage, is_varg: bool) -> Result<Rc<RefCell<Function>>, Error> {
+        check_name(name)?;
         let fn_type = self.get_function_type(return_type, argument_types);
         let func = Rc::new(RefCell::new(Function::create(self.ctx.get_module().get_global_value_name(name), fn_type, linkage, is_varg)));
-        self.ctx.get_module_mut().add_function(func.clone()); // TODO: add arguments
-        func
+        self.ctx.get_module_mut().add_function(func.clone());
+        Ok(func)
+    }
+
+    /// Like `create_function`, but names the arguments. Arguments without a name are numbered.
+    pub fn create_function_with_param_names(&mut self, name: &str, params: Vec<(Type, Option<&str>)>, return_type: Type, linkage: Linkage, is_varg: bool) -> Result<Rc<RefCell<Function>>, Error> {
+        check_name(name)?;
+        let (argument_types, names): (Vec<Type>, Vec<Option<&str>>) = params.into_iter().unzip();
+        for name in names.iter().flatten() {
+            check_name(name)?;
+        }
+        let fn_type = self.get_function_type(return_type, argument_types);
+        let func = Rc::new(RefCell::new(Function::create_with_param_names(self.ctx.get_module().get_global_value_name(name), fn_type, &names, linkage, is_varg)));
+        self.ctx.get_module_mut().add_function(func.clone());
+        Ok(func)
+    }
+
+    /// Returns the argument at `index` of `function` as a value.
+    pub fn get_param(&self, function: &Rc<RefCell<Function>>, index: usize) -> Result<ValueEntity, Error> {
+        match function.borrow().get_param_by_index(index) {
+            Some(param) => Ok(ValueEntity::Argument(param.clone())),
+            None => Err(Error::NoSuchParameter { function: function.borrow().get_name(), index }),
+        }
     }
 
-    pub fn get_block_inst_name(&mut self, name: Option<&str>) -> Option<String> {
-        Some(match name {
-            Some(name) => format!("%{}", name), // TODO: check if name is valid and if it should be wrapped in ""s
+    pub fn create_global(&mut self, name: &str, ty: Type, initializer: Option<Constant>, linkage: Linkage, is_constant: bool) -> Result<Rc<RefCell<GlobalVariable>>, Error> {
+        check_name(name)?;
+        check_value_type("global", &ty)?;
+        if let Some(initializer) = &initializer {
+            check_type(&ty, &initializer.get_type())?;
+        }
+        if initializer.is_none() && is_constant && linkage != Linkage::ExternalLinkage {
+            return Err(Error::MissingInitializer(name.to_string()));
+        }
+        let global = Rc::new(RefCell::new(GlobalVariable::new(self.ctx.get_module().get_global_value_name(name), ty, initializer, linkage, is_constant)));
+        self.ctx.get_module_mut().add_global_variable(global.clone());
+        Ok(global)
+    }
+
+    /// Creates a private constant holding `value` as a nul-terminated array of `i8`.
+    pub fn create_global_string(&mut self, name: &str, value: &str) -> Result<Rc<RefCell<GlobalVariable>>, Error> {
+        let initializer = self.const_string(value);
+        let global = self.create_global(name, initializer.get_type(), Some(initializer), Linkage::PrivateLinkage, true)?;
+        global.borrow_mut().set_alignment(1)?;
+        Ok(global)
+    }
+
+    pub fn get_block_inst_name(&mut self, name: Option<&str>) -> Result<Option<String>, Error> {
+        Ok(Some(match name {
+            Some(name) => {
+                check_name(name)?;
+                format!("%{}", name)
+            }
             None => format!("%{}", match self.ctx.insertion_point.as_ref() {
-                Some(insertion_point) => insertion_point.borrow_mut().get_new_instruction_name(),
-                None => panic!("Cannot get block instruction name without insertion point"),
+                Some(insertion_point) => insertion_point.borrow().get_new_instruction_name(),
+                None => return Err(Error::NoInsertionPoint),
             })
-        }.to_string())
-    }
-
-    //pub fn get_or_create_function(&mut self, name: &str, argument_types: Vec<Type>, return_type: Type) -> &mut Function {
-    //    let new_function = if let Some(existing_function) = self.ctx.get_module().get_functions().iter().find(|x| x.get_name() == name) {
-    //        assert_eq!(existing_function.get_type(), self.get_function_type(return_type, argument_types));
-    //        existing_function
-    //    } else {
-    //        self.add_extern_function(name, argument_types, return_type)
-    //    };
-    //
-    //    // Ensure the lifetime of the returned reference doesn't exceed the borrow of `self`
-    //    new_function
-    //}
-    
+        }))
+    }
+
+    /// Returns the function the insertion point is in.
+    fn current_function(&self) -> Result<Rc<RefCell<Function>>, Error> {
+        match &self.ctx.insertion_point {
+            Some(insertion_point) => Ok(insertion_point.borrow().get_parent()),
+            None => Err(Error::NoInsertionPoint),
+        }
+    }
 
     // create instructions
-    pub fn create_block(&mut self, name: &str, function: Rc<RefCell<Function>>) -> Rc<RefCell<BasicBlock>> { // TODO: name can be empty!
+    pub fn create_block(&mut self, name: &str, function: Rc<RefCell<Function>>) -> Result<Rc<RefCell<BasicBlock>>, Error> {
+        check_name(name)?;
         let x = Rc::new(RefCell::new(BasicBlock::new(self.ctx.get_module().get_global_value_name(name), function.clone())));
-        function.borrow_mut().add_block(x.clone());
-        return x;
+        function.borrow_mut().add_block(x.clone())?;
+        Ok(x)
     }
     
-    pub fn add(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Instruction {
-        assert_eq!(lhs.get_type(), rhs.get_type());
-        assert!(lhs.get_type().is_integer() || lhs.get_type().is_float());
-        let value = Instruction::new(lhs.get_type(), InstructionType::Add(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name));
-        self.insert(value.clone());
-        value
+    pub fn add(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("add", &lhs, &rhs, false)?;
+        let value = Instruction::new(lhs.get_type(), InstructionType::Add(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
+    }
+
+    pub fn sub(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("sub", &lhs, &rhs, false)?;
+        let value = Instruction::new(lhs.get_type(), InstructionType::Sub(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
+    }
+
+    pub fn mul(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("mul", &lhs, &rhs, false)?;
+        let value = Instruction::new(lhs.get_type(), InstructionType::Mul(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
+    }
+
+    pub fn sdiv(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("sdiv", &lhs, &rhs, false)?;
+        let value = Instruction::new(lhs.get_type(), InstructionType::SDiv(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
+    }
+
+    pub fn udiv(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("udiv", &lhs, &rhs, false)?;
+        let value = Instruction::new(lhs.get_type(), InstructionType::UDiv(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
+    }
+
+    pub fn srem(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("srem", &lhs, &rhs, false)?;
+        let value = Instruction::new(lhs.get_type(), InstructionType::SRem(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
+    }
+
+    pub fn urem(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("urem", &lhs, &rhs, false)?;
+        let value = Instruction::new(lhs.get_type(), InstructionType::URem(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
+    }
+
+    pub fn shl(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("shl", &lhs, &rhs, false)?;
+        let value = Instruction::new(lhs.get_type(), InstructionType::Shl(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
+    }
+
+    pub fn ashr(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("ashr", &lhs, &rhs, false)?;
+        let value = Instruction::new(lhs.get_type(), InstructionType::AShr(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
+    }
+
+    pub fn lshr(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("lshr", &lhs, &rhs, false)?;
+        let value = Instruction::new(lhs.get_type(), InstructionType::LShr(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
+    }
+
+    pub fn and(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("and", &lhs, &rhs, false)?;
+        let value = Instruction::new(lhs.get_type(), InstructionType::And(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
+    }
+
+    pub fn or(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("or", &lhs, &rhs, false)?;
+        let value = Instruction::new(lhs.get_type(), InstructionType::Or(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
+    }
+
+    pub fn xor(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("xor", &lhs, &rhs, false)?;
+        let value = Instruction::new(lhs.get_type(), InstructionType::Xor(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
     }
-
-    pub fn sub(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Instruction {
-        assert_eq!(lhs.get_type(), rhs.get_type());
-        assert!(lhs.get_type().is_integer() || lhs.get_type().is_float());
-        let value = Instruction::new(lhs.get_type(), InstructionType::Sub(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name));
-        self.insert(value.clone());
-        value
+
+    pub fn eq(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("eq", &lhs, &rhs, false)?;
+        let value = Instruction::new(self.get_bool_type(), InstructionType::Eq(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
     }
-
-    pub fn mul(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Instruction {
-        assert_eq!(lhs.get_type(), rhs.get_type());
-        assert!(lhs.get_type().is_integer() || lhs.get_type().is_float());
-        let value = Instruction::new(lhs.get_type(), InstructionType::Mul(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name));
-        self.insert(value.clone());
-        value
+
+    pub fn ne(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("ne", &lhs, &rhs, false)?;
+        let value = Instruction::new(self.get_bool_type(), InstructionType::Ne(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
     }
-
-    pub fn div(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Instruction {
-        assert_eq!(lhs.get_type(), rhs.get_type());
-        assert!(lhs.get_type().is_integer() || lhs.get_type().is_float());
-        let value = Instruction::new(lhs.get_type(), InstructionType::Div(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name));
-        self.insert(value.clone());
-        value
+
+    pub fn slt(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("slt", &lhs, &rhs, false)?;
+        let value = Instruction::new(self.get_bool_type(), InstructionType::SLt(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
     }
-
-    pub fn rem(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Instruction {
-        assert_eq!(lhs.get_type(), rhs.get_type());
-        assert!(lhs.get_type().is_integer() || lhs.get_type().is_float());
-        let value = Instruction::new(lhs.get_type(), InstructionType::Rem(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name));
-        self.insert(value.clone());
-        value
+
+    pub fn sle(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("sle", &lhs, &rhs, false)?;
+        let value = Instruction::new(self.get_bool_type(), InstructionType::SLe(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
     }
-
-    pub fn shl(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Instruction {
-        assert_eq!(lhs.get_type(), rhs.get_type());
-        assert!(lhs.get_type().is_integer());
-        let value = Instruction::new(lhs.get_type(), InstructionType::Shl(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name));
-        self.insert(value.clone());
-        value
+
+    pub fn sgt(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("sgt", &lhs, &rhs, false)?;
+        let value = Instruction::new(self.get_bool_type(), InstructionType::SGt(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
     }
 
-    pub fn shr(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Instruction {
-        assert_eq!(lhs.get_type(), rhs.get_type());
-        assert!(lhs.get_type().is_integer());
-        let value = Instruction::new(lhs.get_type(), InstructionType::Shr(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name));
-        self.insert(value.clone());
-        value
+    pub fn sge(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("sge", &lhs, &rhs, false)?;
+        let value = Instruction::new(self.get_bool_type(), InstructionType::SGe(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
     }
 
-    pub fn and(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Instruction {
-        assert_eq!(lhs.get_type(), rhs.get_type());
-        assert!(lhs.get_type().is_integer());
-        let value = Instruction::new(self.get_bool_type(),InstructionType::And(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name));
-        self.insert(value.clone());
-        value
+    pub fn ult(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("ult", &lhs, &rhs, false)?;
+        let value = Instruction::new(self.get_bool_type(), InstructionType::ULt(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
     }
 
-    pub fn or(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Instruction {
-        assert_eq!(lhs.get_type(), rhs.get_type());
-        assert!(lhs.get_type().is_integer());
-        let value = Instruction::new(self.get_bool_type(), InstructionType::Or(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name));
-        self.insert(value.clone());
-        value
+    pub fn ule(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("ule", &lhs, &rhs, false)?;
+        let value = Instruction::new(self.get_bool_type(), InstructionType::ULe(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
     }
 
-    pub fn xor(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Instruction {
-        assert_eq!(lhs.get_type(), rhs.get_type());
-        assert!(lhs.get_type().is_integer());
-        let value = Instruction::new(lhs.get_type(), InstructionType::Xor(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name));
-        self.insert(value.clone());
-        value
+    pub fn ugt(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("ugt", &lhs, &rhs, false)?;
+        let value = Instruction::new(self.get_bool_type(), InstructionType::UGt(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
     }
 
-    pub fn eq(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Instruction {
-        assert_eq!(lhs.get_type(), rhs.get_type());
-        assert!(lhs.get_type().is_integer() || lhs.get_type().is_float());
-        let value = Instruction::new(self.get_bool_type(), InstructionType::Eq(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name));
-        self.insert(value.clone());
-        value
+    pub fn uge(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("uge", &lhs, &rhs, false)?;
+        let value = Instruction::new(self.get_bool_type(), InstructionType::UGe(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
     }
 
-    pub fn ne(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Instruction {
-        assert_eq!(lhs.get_type(), rhs.get_type());
-        assert!(lhs.get_type().is_integer() || lhs.get_type().is_float());
-        let value = Instruction::new(self.get_bool_type(), InstructionType::Ne(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name));
-        self.insert(value.clone());
-        value
+    pub fn neg(&mut self, value: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        if !value.get_type().is_integer() {
+            return Err(Error::InvalidOperandType { operation: "neg", ty: value.get_type() });
+        }
+        let value = Instruction::new(value.get_type(), InstructionType::Neg(Box::new(value)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
     }
 
-    pub fn lt(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Instruction {
-        assert_eq!(lhs.get_type(), rhs.get_type());
-        assert!(lhs.get_type().is_integer() || lhs.get_type().is_float());
-        let value = Instruction::new(self.get_bool_type(), InstructionType::Lt(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name));
-        self.insert(value.clone());
-        value
+    pub fn not(&mut self, value: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        if !value.get_type().is_integer() {
+            return Err(Error::InvalidOperandType { operation: "not", ty: value.get_type() });
+        }
+        let value = Instruction::new(value.get_type(), InstructionType::Not(Box::new(value)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
+    }
+
+    pub fn fadd(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("fadd", &lhs, &rhs, true)?;
+        let value = Instruction::new(lhs.get_type(), InstructionType::FAdd(Box::new(lhs), Box::new(rhs), self.ctx.fast_math_flags), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
     }
 
-    pub fn le(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Instruction {
-        assert_eq!(lhs.get_type(), rhs.get_type());
-        assert!(lhs.get_type().is_integer() || lhs.get_type().is_float());
-        let value = Instruction::new(self.get_bool_type(), InstructionType::Le(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name));
-        self.insert(value.clone());
-        value
+    pub fn fsub(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("fsub", &lhs, &rhs, true)?;
+        let value = Instruction::new(lhs.get_type(), InstructionType::FSub(Box::new(lhs), Box::new(rhs), self.ctx.fast_math_flags), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
     }
 
-    pub fn gt(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Instruction {
-        assert_eq!(lhs.get_type(), rhs.get_type());
-        assert!(lhs.get_type().is_integer() || lhs.get_type().is_float());
-        let value = Instruction::new(self.get_bool_type(), InstructionType::Gt(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name));
-        self.insert(value.clone());
-        value
+    pub fn fmul(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("fmul", &lhs, &rhs, true)?;
+        let value = Instruction::new(lhs.get_type(), InstructionType::FMul(Box::new(lhs), Box::new(rhs), self.ctx.fast_math_flags), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
     }
 
-    pub fn ge(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Instruction {
-        assert_eq!(lhs.get_type(), rhs.get_type());
-        assert!(lhs.get_type().is_integer() || lhs.get_type().is_float());
-        let value = Instruction::new(self.get_bool_type(), InstructionType::Ge(Box::new(lhs), Box::new(rhs)), self.get_block_inst_name(name));
-        self.insert(value.clone());
-        value
+    pub fn fdiv(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("fdiv", &lhs, &rhs, true)?;
+        let value = Instruction::new(lhs.get_type(), InstructionType::FDiv(Box::new(lhs), Box::new(rhs), self.ctx.fast_math_flags), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
     }
 
-    pub fn neg(&mut self, value: ValueEntity, name: Option<&str>) -> Instruction {
-        assert!(value.get_type().is_integer() || value.get_type().is_float());
-        let value = Instruction::new(value.get_type(), InstructionType::Neg(Box::new(value)), self.get_block_inst_name(name));
-        self.insert(value.clone());
-        value
+    pub fn frem(&mut self, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("frem", &lhs, &rhs, true)?;
+        let value = Instruction::new(lhs.get_type(), InstructionType::FRem(Box::new(lhs), Box::new(rhs), self.ctx.fast_math_flags), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
     }
 
-    pub fn not(&mut self, value: ValueEntity, name: Option<&str>) -> Instruction {
-        assert!(value.get_type().is_integer());
-        let value = Instruction::new(self.get_bool_type(), InstructionType::Not(Box::new(value)), self.get_block_inst_name(name));
-        self.insert(value.clone());
-        value
+    pub fn fneg(&mut self, value: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        if !value.get_type().is_float() {
+            return Err(Error::InvalidOperandType { operation: "fneg", ty: value.get_type() });
+        }
+        let value = Instruction::new(value.get_type(), InstructionType::FNeg(Box::new(value), self.ctx.fast_math_flags), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
+    }
+
+    pub fn fcmp(&mut self, predicate: FloatPredicate, lhs: ValueEntity, rhs: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        check_binary("fcmp", &lhs, &rhs, true)?;
+        let value = Instruction::new(self.get_bool_type(), InstructionType::FCmp(predicate, Box::new(lhs), Box::new(rhs), self.ctx.fast_math_flags), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
+    }
+
+    pub fn trunc(&mut self, value: ValueEntity, ty: Type, name: Option<&str>) -> Result<Instruction, Error> {
+        self.cast("trunc", value, ty, name)
+    }
+
+    pub fn zext(&mut self, value: ValueEntity, ty: Type, name: Option<&str>) -> Result<Instruction, Error> {
+        self.cast("zext", value, ty, name)
+    }
+
+    pub fn sext(&mut self, value: ValueEntity, ty: Type, name: Option<&str>) -> Result<Instruction, Error> {
+        self.cast("sext", value, ty, name)
+    }
+
+    pub fn fp_trunc(&mut self, value: ValueEntity, ty: Type, name: Option<&str>) -> Result<Instruction, Error> {
+        self.cast("fptrunc", value, ty, name)
+    }
+
+    pub fn fp_ext(&mut self, value: ValueEntity, ty: Type, name: Option<&str>) -> Result<Instruction, Error> {
+        self.cast("fpext", value, ty, name)
+    }
+
+    pub fn fp_to_si(&mut self, value: ValueEntity, ty: Type, name: Option<&str>) -> Result<Instruction, Error> {
+        self.cast("fptosi", value, ty, name)
+    }
+
+    pub fn fp_to_ui(&mut self, value: ValueEntity, ty: Type, name: Option<&str>) -> Result<Instruction, Error> {
+        self.cast("fptoui", value, ty, name)
+    }
+
+    pub fn si_to_fp(&mut self, value: ValueEntity, ty: Type, name: Option<&str>) -> Result<Instruction, Error> {
+        self.cast("sitofp", value, ty, name)
+    }
+
+    pub fn ui_to_fp(&mut self, value: ValueEntity, ty: Type, name: Option<&str>) -> Result<Instruction, Error> {
+        self.cast("uitofp", value, ty, name)
+    }
+
+    pub fn ptr_to_int(&mut self, value: ValueEntity, ty: Type, name: Option<&str>) -> Result<Instruction, Error> {
+        self.cast("ptrtoint", value, ty, name)
+    }
+
+    pub fn int_to_ptr(&mut self, value: ValueEntity, ty: Type, name: Option<&str>) -> Result<Instruction, Error> {
+        self.cast("inttoptr", value, ty, name)
+    }
+
+    pub fn bitcast(&mut self, value: ValueEntity, ty: Type, name: Option<&str>) -> Result<Instruction, Error> {
+        self.cast("bitcast", value, ty, name)
+    }
+
+    /// Inserts the conversion `opcode` of `value` to `ty`, after checking that
+    /// the conversion can turn the one type into the other.
+    fn cast(&mut self, opcode: &'static str, value: ValueEntity, ty: Type, name: Option<&str>) -> Result<Instruction, Error> {
+        let from = value.get_type();
+        let kind = match InstructionType::cast(opcode, Box::new(value)) {
+            Some(kind) if is_valid_cast(opcode, &from, &ty) => kind,
+            _ => return Err(Error::InvalidCast { operation: opcode, from, to: ty }),
+        };
+        let value = Instruction::new(ty, kind, self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
+    }
+
+    pub fn alloca(&mut self, ty: Type, count: Option<ValueEntity>, align: Option<u64>, name: Option<&str>) -> Result<Instruction, Error> {
+        check_value_type("alloca", &ty)?;
+        if let Some(count) = &count {
+            if !count.get_type().is_integer() {
+                return Err(Error::InvalidOperandType { operation: "alloca count", ty: count.get_type() });
+            }
+        }
+        let align = align.unwrap_or_else(|| self.get_module().data_layout().align_of(&ty));
+        if !align.is_power_of_two() {
+            return Err(Error::InvalidAlignment(align));
+        }
+        let value = Instruction::new(self.get_pointer_type(ty.clone()), InstructionType::Alloca(ty, count.map(Box::new), align), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
+    }
+
+    /// Inserts the address of field `index` of the struct `ptr` points to.
+    pub fn struct_gep(&mut self, ptr: ValueEntity, index: usize, name: Option<&str>) -> Result<Instruction, Error> {
+        let ty = ptr.get_type();
+        let fields = match &ty {
+            Type::Pointer(element) => match element.as_ref() {
+                Type::Struct(fields) => fields,
+                _ => return Err(Error::InvalidOperandType { operation: "struct_gep", ty }),
+            },
+            _ => return Err(Error::InvalidOperandType { operation: "struct_gep", ty }),
+        };
+        let Some(field) = fields.get(index) else {
+            return Err(Error::NoSuchField { ty: ty.get_pointer_element_type(), index });
+        };
+        let field_type = self.get_pointer_type(field.clone());
+        let indices = vec![Box::new(self.get_i32(0)), Box::new(self.get_i32(index as i32))];
+        let value = Instruction::new(field_type, InstructionType::GetElementPtr(Box::new(ptr), indices), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
+    }
+
+    /// Inserts the address of element `index` of the array `ptr` points to.
+    /// The index is read as signed and is not checked against the length.
+    pub fn array_gep(&mut self, ptr: ValueEntity, index: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        let element = match ptr.get_type() {
+            Type::Pointer(element) => match *element {
+                Type::Array(_, element) => *element,
+                _ => return Err(Error::InvalidOperandType { operation: "array_gep", ty: ptr.get_type() }),
+            },
+            ty => return Err(Error::InvalidOperandType { operation: "array_gep", ty }),
+        };
+        if !index.get_type().is_integer() {
+            return Err(Error::InvalidOperandType { operation: "array index", ty: index.get_type() });
+        }
+        let indices = vec![Box::new(self.get_i64(0)), Box::new(index)];
+        let value = Instruction::new(self.get_pointer_type(element), InstructionType::GetElementPtr(Box::new(ptr), indices), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
     }
 
-    pub fn load(&mut self, ty: Type, value: ValueEntity, name: Option<&str>) -> Instruction {
-        assert!(value.get_type().is_pointer());
-        let value = Instruction::new(ty, InstructionType::Load(Box::new(value)), self.get_block_inst_name(name));
-        self.insert(value.clone());
-        value
+    pub fn load(&mut self, ty: Type, value: ValueEntity, name: Option<&str>) -> Result<Instruction, Error> {
+        if !value.get_type().is_pointer() {
+            return Err(Error::InvalidOperandType { operation: "load", ty: value.get_type() });
+        }
+        let value = Instruction::new(ty, InstructionType::Load(Box::new(value)), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
     }
 
-    pub fn store(&mut self, lhs: ValueEntity, rhs: ValueEntity) -> Instruction {
-        assert!(lhs.get_type().is_pointer());
-        assert_eq!(lhs.get_type().get_pointer_element_type(), rhs.get_type());
+    pub fn store(&mut self, lhs: ValueEntity, rhs: ValueEntity) -> Result<Instruction, Error> {
+        if !lhs.get_type().is_pointer() {
+            return Err(Error::InvalidOperandType { operation: "store", ty: lhs.get_type() });
+        }
+        check_type(&lhs.get_type().get_pointer_element_type(), &rhs.get_type())?;
         let value = Instruction::new(self.get_void_type(), InstructionType::Store(Box::new(lhs), Box::new(rhs)), None);
-        self.insert(value.clone());
-        value
+        self.insert(value.clone())?;
+        Ok(value)
     }
 
-    pub fn call(&mut self, callee: ValueEntity, args: Vec<ValueEntity>, name: Option<&str>) -> Instruction {
-        assert!(callee.get_type().is_pointer());
-        for (i, arg) in args.iter().enumerate() {
-            assert_eq!(arg.get_type(), callee.get_type().get_pointer_element_type().get_function_argument_type(i as usize));
+    pub fn call(&mut self, callee: ValueEntity, args: Vec<ValueEntity>, name: Option<&str>) -> Result<Instruction, Error> {
+        // functions are called directly, anything else through a pointer to a function
+        let (ty, is_var_arg) = match &callee {
+            ValueEntity::Function(function) => (function.get_type(), function.is_var_arg()),
+            callee => match callee.get_type() {
+                Type::Pointer(ty) if ty.is_function_type() => (*ty, false),
+                ty => return Err(Error::InvalidOperandType { operation: "call", ty }),
+            },
+        };
+        let params = ty.get_function_argument_types();
+        if args.len() < params.len() || (!is_var_arg && args.len() > params.len()) {
+            return Err(Error::ArgumentCount { expected: params.len(), found: args.len(), is_var_arg });
+        }
+        for (arg, param) in args.iter().zip(params) {
+            check_type(param, &arg.get_type())?;
         }
-        let boxedArgs = args.into_iter().map(|x| Box::new(x)).collect();
-        let value = Instruction::new(callee.get_type().get_pointer_element_type().get_function_return_type(), InstructionType::Call(Box::new(callee), boxedArgs), self.get_block_inst_name(name));
-        self.insert(value.clone());
-        value
+        let boxed_args = args.into_iter().map(Box::new).collect();
+        let value = Instruction::new(ty.get_function_return_type(), InstructionType::Call(Box::new(callee), boxed_args), self.get_block_inst_name(name)?);
+        self.insert(value.clone())?;
+        Ok(value)
     }
 
-    pub fn void_ret(&mut self) -> Instruction {
-        assert_eq!(self.ctx.insertion_point.as_ref().unwrap().borrow_mut().get_parent().as_ref().borrow().get_type().get_function_return_type(), self.get_void_type(),
-                   "Return value type does not match function return type (expected {:?}, got {:?})", self.ctx.insertion_point.as_ref().unwrap().borrow_mut().get_parent().as_ref().borrow().get_function_return_type(), self.get_void_type());
+    pub fn void_ret(&mut self) -> Result<Instruction, Error> {
+        check_type(&self.current_function()?.borrow().get_function_return_type(), &self.get_void_type())?;
         let value = Instruction::new(self.get_void_type(), InstructionType::VoidReturn, None);
-        self.insert(value.clone());
-        value
+        self.insert(value.clone())?;
+        Ok(value)
     }
 
-    pub fn ret(&mut self, value: ValueEntity) -> Instruction {
-        // TODO: fix this check
-        //assert_eq!(value.get_type(), self.ctx.insertion_point.as_ref().unwrap().borrow_mut().get_parent().as_ref().borrow().get_type().get_function_return_type(),
-        //           "Return value type does not match function return type (expected {:?}, got {:?})", self.ctx.insertion_point.unwrap().clone().as_ref().borrow().get_parent().as_ref().borrow().get_function_return_type(), value.get_type());
+    pub fn ret(&mut self, value: ValueEntity) -> Result<Instruction, Error> {
+        check_type(&self.current_function()?.borrow().get_function_return_type(), &value.get_type())?;
         let value = Instruction::new(self.get_void_type(), InstructionType::Return(Box::new(value)), None);
-        self.insert(value.clone());
-        value
+        self.insert(value.clone())?;
+        Ok(value)
     }
 
-    pub fn branch(&mut self, target: ValueEntity) -> Instruction {
-        assert!(target.get_type().is_branch());
+    pub fn branch(&mut self, target: ValueEntity) -> Result<Instruction, Error> {
+        check_type(&self.get_branch_type(), &target.get_type())?;
         let value = Instruction::new(self.get_void_type(), InstructionType::Branch(Box::new(target)), None);
-        self.insert(value.clone());
-        value
+        self.insert(value.clone())?;
+        Ok(value)
     }
 
-    pub fn branch_if(&mut self, condition: ValueEntity, target: Rc<RefCell<BasicBlock>>, target_false: Rc<RefCell<BasicBlock>>) -> Instruction {
-        //assert!(condition.get_type().is_integer());
-        //assert!(target.get_type().is_branch());
+    pub fn branch_if(&mut self, condition: ValueEntity, target: Rc<RefCell<BasicBlock>>, target_false: Rc<RefCell<BasicBlock>>) -> Result<Instruction, Error> {
+        check_type(&self.get_bool_type(), &condition.get_type())?;
         let value = Instruction::new(self.get_void_type(), InstructionType::BranchIf(Box::new(condition), target, target_false), None);
-        self.insert(value.clone());
-        value
-    }
-
-    pub fn phi(&mut self, incoming: Vec<(ValueEntity, ValueEntity)>) -> Instruction {
-        assert!(incoming.iter().all(|(value, _)| value.get_type() == incoming[0].0.get_type()));
-        let boxedIncoming = incoming.clone().into_iter().map(|(value, block)| (Box::new(value), Box::new(block))).collect();
-        let value = Instruction::new(incoming[0].0.get_type(), InstructionType::Phi(boxedIncoming), None);
-        self.insert(value.clone());
-        value
+        self.insert(value.clone())?;
+        Ok(value)
+    }
+
+    pub fn phi(&mut self, incoming: Vec<(ValueEntity, ValueEntity)>) -> Result<Instruction, Error> {
+        let Some((first, _)) = incoming.first() else {
+            return Err(Error::EmptyPhi);
+        };
+        let ty = first.get_type();
+        for (value, block) in &incoming {
+            check_type(&ty, &value.get_type())?;
+            check_type(&self.get_branch_type(), &block.get_type())?;
+        }
+        let boxed_incoming = incoming.into_iter().map(|(value, block)| (Box::new(value), Box::new(block))).collect();
+        let value = Instruction::new(ty, InstructionType::Phi(boxed_incoming), self.get_block_inst_name(None)?);
+        self.insert(value.clone())?;
+        Ok(value)
     }
 
-    pub fn unreachable(&mut self) -> Instruction {
+    pub fn unreachable(&mut self) -> Result<Instruction, Error> {
         let value = Instruction::new(self.get_void_type(), InstructionType::Unreachable, None);
-        self.insert(value.clone());
-        value
+        self.insert(value.clone())?;
+        Ok(value)
     }
 
     // emitters
 
-    pub fn emit_assembly(&self, file: &mut impl Write) -> Result<(), std::io::Error> {
+    pub fn emit_assembly(&self, file: &mut impl Write) -> Result<(), Error> {
         let mut emitter = AssemblyEmitter::new(self.ctx.clone());
         emitter.emit_module(file)
     }
+
+    pub fn emit_assembly_with_frame_options(&self, file: &mut impl Write, frame_options: FrameOptions) -> Result<(), Error> {
+        let mut emitter = AssemblyEmitter::with_frame_options(self.ctx.clone(), frame_options);
+        emitter.emit_module(file)
+    }
+
+    /// Writes the module as a relocatable object file.
+    pub fn emit_object(&self, file: &mut impl Write) -> Result<(), Error> {
+        let mut emitter = ObjectEmitter::new(self.ctx.clone());
+        emitter.emit_module(file)
+    }
+
+    pub fn emit_object_with_frame_options(&self, file: &mut impl Write, frame_options: FrameOptions) -> Result<(), Error> {
+        let mut emitter = ObjectEmitter::with_frame_options(self.ctx.clone(), frame_options);
+        emitter.emit_module(file)
+    }
+
+    /// Writes the module as C99 source, whatever the target.
+    pub fn emit_c(&self, file: &mut impl Write) -> Result<(), Error> {
+        let mut emitter = CEmitter::new(self.ctx.clone());
+        emitter.emit_module(file)
+    }
+
+    /// Writes the module as textual LLVM IR, with typed pointers unless
+    /// `opaque_pointers` is set.
+    pub fn emit_llvm_ir(&self, file: &mut impl Write, opaque_pointers: bool) -> Result<(), Error> {
+        let mut emitter = LlvmEmitter::new(self.ctx.clone());
+        emitter.set_opaque_pointers(opaque_pointers);
+        emitter.emit_module(file)
+    }
+}
+
+/// Checks that a name only uses the characters the textual IR allows in names.
+fn check_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || !name.chars().all(is_name_char) {
+        return Err(Error::InvalidName(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Checks that values of `ty` can be held in memory, which rules out
+/// `void`, `branch` and bare function types.
+fn check_value_type(operation: &'static str, ty: &Type) -> Result<(), Error> {
+    if ty.is_void() || ty.is_branch() || ty.is_function_type() {
+        return Err(Error::InvalidOperandType { operation, ty: ty.clone() });
+    }
+    Ok(())
+}
+
+fn check_type(expected: &Type, found: &Type) -> Result<(), Error> {
+    if expected != found {
+        return Err(Error::TypeMismatch { expected: expected.clone(), found: found.clone() });
+    }
+    Ok(())
+}
+
+/// Checks the operands of a binary operation: both must have the same integer
+/// type, or the same float type when `float` is set.
+fn check_binary(operation: &'static str, lhs: &ValueEntity, rhs: &ValueEntity, float: bool) -> Result<(), Error> {
+    let ty = lhs.get_type();
+    if !(if float { ty.is_float() } else { ty.is_integer() }) {
+        return Err(Error::InvalidOperandType { operation, ty });
+    }
+    check_type(&ty, &rhs.get_type())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::module::Module;
+    use crate::ir::values::constant::Scalar;
+    use crate::targets::{DataLayout, TargetTriple};
+
+    fn builder() -> Builder {
+        let triple = TargetTriple::new("x86_64-unknown-linux-gnu").unwrap();
+        let module = Module::new("test", DataLayout::from_triple(&triple), triple);
+        Builder::new(IRContext::new(module))
+    }
+
+    #[test]
+    fn needs_an_insertion_point() {
+        let mut builder = builder();
+        let result = builder.add(builder.get_i32(1), builder.get_i32(2), None);
+        assert!(matches!(result, Err(Error::NoInsertionPoint)));
+    }
+
+    #[test]
+    fn rejects_mismatched_operands() -> Result<(), Error> {
+        let mut builder = builder();
+        let function = builder.create_function("f", vec![builder.get_f64_type()], builder.get_i32_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", function.clone())?;
+        builder.set_insertion_point(entry.clone());
+
+        let result = builder.add(builder.get_i32(1), builder.get_i64(2), None);
+        assert!(matches!(result, Err(Error::TypeMismatch { expected: Type::Integer(32), found: Type::Integer(64) })));
+        let result = builder.branch_if(builder.get_i32(1), entry.clone(), entry);
+        assert!(matches!(result, Err(Error::TypeMismatch { expected: Type::Integer(1), .. })));
+        let result = builder.ret(builder.get_i64(0));
+        assert_eq!(result.unwrap_err().to_string(), "expected a value of type i32, found i64");
+        let result = builder.load(builder.get_i32_type(), builder.get_i32(0), None);
+        assert!(matches!(result, Err(Error::InvalidOperandType { operation: "load", .. })));
+        // only the signed forms accept floats
+        let x = builder.get_param(&function, 0)?;
+        let result = builder.udiv(x.clone(), x, None);
+        assert!(matches!(result, Err(Error::InvalidOperandType { operation: "udiv", ty: Type::Float(64) })));
+
+        // nothing was inserted
+        assert!(function.borrow().get_blocks()[0].borrow().get_instructions().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn checks_names_parameters_and_calls() -> Result<(), Error> {
+        let mut builder = builder();
+        assert!(matches!(builder.create_function("bad name", vec![], builder.get_void_type(), Linkage::InternalLinkage, false), Err(Error::InvalidName(_))));
+        let callee = builder.create_function("callee", vec![builder.get_i32_type()], builder.get_void_type(), Linkage::ExternalLinkage, false)?;
+        assert!(matches!(builder.get_param(&callee, 1), Err(Error::NoSuchParameter { index: 1, .. })));
+        assert!(matches!(builder.create_block("entry", callee.clone()), Err(Error::UnsupportedLinkage { .. })));
+
+        let caller = builder.create_function("caller", vec![], builder.get_void_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", caller)?;
+        builder.set_insertion_point(entry);
+        let result = builder.call(callee.borrow().clone().into(), vec![], None);
+        assert!(matches!(result, Err(Error::ArgumentCount { expected: 1, found: 0, is_var_arg: false })));
+        assert!(matches!(builder.phi(vec![]), Err(Error::EmptyPhi)));
+        Ok(())
+    }
+
+    #[test]
+    fn checks_conversions() -> Result<(), Error> {
+        let mut builder = builder();
+        let function = builder.create_function("f", vec![builder.get_f64_type()], builder.get_i64_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", function.clone())?;
+        builder.set_insertion_point(entry);
+
+        let wide = builder.sext(builder.get_i32(-1), builder.get_i64_type(), None)?;
+        assert_eq!(wide.get_type(), Type::Integer(64));
+        let result = builder.trunc(builder.get_i32(1), builder.get_i64_type(), None);
+        assert!(matches!(result, Err(Error::InvalidCast { operation: "trunc", from: Type::Integer(32), to: Type::Integer(64) })));
+        let result = builder.bitcast(builder.get_param(&function, 0)?, builder.get_i32_type(), None);
+        assert_eq!(result.unwrap_err().to_string(), "bitcast cannot convert a value of type f64 to i32");
+        let result = builder.int_to_ptr(builder.get_i64(0), builder.get_i64_type(), None);
+        assert!(matches!(result, Err(Error::InvalidCast { operation: "inttoptr", .. })));
+        // an opcode that is no conversion is an error rather than a panic
+        let result = builder.cast("add", builder.get_i32(1), builder.get_i64_type(), None);
+        assert!(matches!(result, Err(Error::InvalidCast { operation: "add", .. })));
+        assert_eq!(function.borrow().get_blocks()[0].borrow().get_instructions().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn separates_integer_and_float_operations() -> Result<(), Error> {
+        let mut builder = builder();
+        let function = builder.create_function("f", vec![builder.get_f64_type()], builder.get_f64_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", function.clone())?;
+        builder.set_insertion_point(entry);
+        let x = builder.get_param(&function, 0)?;
+
+        let result = builder.add(x.clone(), x.clone(), None);
+        assert!(matches!(result, Err(Error::InvalidOperandType { operation: "add", ty: Type::Float(64) })));
+        let result = builder.fadd(builder.get_i32(1), builder.get_i32(2), None);
+        assert!(matches!(result, Err(Error::InvalidOperandType { operation: "fadd", ty: Type::Integer(32) })));
+
+        // flags apply to the float instructions built after they are set
+        builder.set_fast_math_flags(FastMathFlags::fast());
+        let sum = builder.fadd(x.clone(), builder.get_f64(0.5), None)?;
+        assert_eq!(sum.to_string(), "%1 = fadd fast f64 %0, 0.5");
+        builder.set_fast_math_flags(FastMathFlags::default());
+        let less = builder.fcmp(FloatPredicate::Olt, sum.into(), x, None)?;
+        assert_eq!(less.get_type(), Type::Integer(1));
+        assert!(!less.to_string().contains("fast"));
+        Ok(())
+    }
+
+    #[test]
+    fn types_bitwise_operations_like_their_operands() -> Result<(), Error> {
+        let mut builder = builder();
+        let function = builder.create_function("f", vec![builder.get_i64_type()], builder.get_i64_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", function.clone())?;
+        builder.set_insertion_point(entry);
+        let x = builder.get_param(&function, 0)?;
+
+        let both = builder.and(x.clone(), builder.get_i64(12), None)?;
+        assert_eq!(both.get_type(), Type::Integer(64));
+        let either = builder.or(both.into(), x, None)?;
+        assert_eq!(either.get_type(), Type::Integer(64));
+        let flipped = builder.not(either.into(), None)?;
+        assert_eq!(flipped.to_string(), "%3 = not i64 %2");
+        assert_eq!(builder.not(builder.get_bool(true), None)?.get_type(), Type::Integer(1));
+        Ok(())
+    }
+
+    #[test]
+    fn builds_and_folds_constants() -> Result<(), Error> {
+        let mut builder = builder();
+        let i64_type = builder.get_i64_type();
+        let counter = builder.create_global("counter", i64_type.clone(), Some(builder.const_int(i64_type.clone(), -1)?), Linkage::InternalLinkage, false)?;
+        assert_eq!(counter.borrow().get_initializer().unwrap().to_string(), "-1");
+
+        let result = builder.const_int(builder.get_f64_type(), 1);
+        assert!(matches!(result, Err(Error::InvalidOperandType { operation: "integer constant", .. })));
+        assert!(builder.const_null(i64_type.clone()).is_err());
+        let result = builder.const_array(i64_type.clone(), vec![builder.const_int(builder.get_i32_type(), 1)?]);
+        assert!(matches!(result, Err(Error::TypeMismatch { .. })));
+        assert!(builder.const_binary("sdiv", builder.const_int(i64_type.clone(), 1)?, builder.const_int(i64_type.clone(), 1)?).is_err());
+
+        // addresses fold to a symbol and an offset
+        let address = builder.const_address(&counter.borrow().clone().into())?;
+        let address = builder.const_cast("ptrtoint", address, i64_type.clone())?;
+        let end = builder.const_binary("add", address, builder.const_int(i64_type.clone(), 8)?)?;
+        assert_eq!(end.to_string(), "add (i64 ptrtoint (i64* @counter to i64), 8)");
+        assert_eq!(end.fold(), Some(Scalar::Address("counter".to_string(), 8)));
+        // integers wrap at their width, and shifting out every bit is poison
+        let max = builder.const_apint(ApInt::from_i64(7, 63));
+        let wrapped = builder.const_binary("add", max.clone(), builder.const_int(Type::Integer(7), 1)?)?;
+        assert_eq!(wrapped.fold(), Some(Scalar::Int(ApInt::from_i64(7, -64))));
+        let shifted = builder.const_binary("shl", max, builder.const_int(Type::Integer(7), 7)?)?;
+        assert_eq!(shifted.fold(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn computes_struct_and_array_element_types() -> Result<(), Error> {
+        let mut builder = builder();
+        let array = builder.get_array_type(builder.get_i32_type(), 4);
+        let pair = Type::Struct(vec![builder.get_i8_type(), array.clone()]);
+        let function = builder.create_function("f", vec![builder.get_pointer_type(pair.clone()), builder.get_i64_type()], builder.get_i32_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", function.clone())?;
+        builder.set_insertion_point(entry);
+
+        let field = builder.struct_gep(builder.get_param(&function, 0)?, 1, None)?;
+        assert_eq!(field.get_type(), builder.get_pointer_type(array));
+        let element = builder.array_gep(field.into(), builder.get_param(&function, 1)?, None)?;
+        assert_eq!(element.get_type(), builder.get_pointer_type(builder.get_i32_type()));
+        assert_eq!(element.to_string(), "%3 = getelementptr [4 x i32]* %2, i64 0, i64 %1");
+
+        let result = builder.struct_gep(builder.get_param(&function, 0)?, 2, None);
+        assert!(matches!(result, Err(Error::NoSuchField { index: 2, .. })));
+        let result = builder.array_gep(element.clone().into(), builder.get_i32(0), None);
+        assert!(matches!(result, Err(Error::InvalidOperandType { operation: "array_gep", .. })));
+        let result = builder.array_gep(builder.get_param(&function, 0)?, builder.get_f64(0.0), None);
+        assert!(matches!(result, Err(Error::InvalidOperandType { operation: "array_gep", .. })));
+        Ok(())
+    }
 }
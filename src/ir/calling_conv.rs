@@ -0,0 +1,25 @@
+use std::fmt::{Display, Formatter};
+
+/// How a function receives its arguments and returns its result. Targets
+/// compile conventions they do not have like `C`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum CallingConv {
+    /// The default convention of the target, cdecl on 32-bit x86.
+    #[default]
+    C,
+    /// Arguments on the stack like cdecl, but popped by the callee.
+    X86Stdcall,
+    /// The first two integer arguments of at most 32 bits in `ecx` and
+    /// `edx`, the rest on the stack, popped by the callee.
+    X86Fastcall,
+}
+
+impl Display for CallingConv {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CallingConv::C => "ccc",
+            CallingConv::X86Stdcall => "x86_stdcallcc",
+            CallingConv::X86Fastcall => "x86_fastcallcc",
+        })
+    }
+}
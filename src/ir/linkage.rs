@@ -1,5 +1,6 @@
+use std::fmt::{Display, Formatter};
 
-#[derive(Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub enum Linkage {
     ExternalLinkage,
     InternalLinkage,
@@ -11,18 +12,17 @@ pub enum Linkage {
     WeakLinkage,
 }
 
-impl PartialEq for Linkage {
-    fn eq(&self, other: &Linkage) -> bool {
-        match (self, other) {
-            (&Linkage::ExternalLinkage, &Linkage::ExternalLinkage) => true,
-            (&Linkage::InternalLinkage, &Linkage::InternalLinkage) => true,
-            (&Linkage::PrivateLinkage, &Linkage::PrivateLinkage) => true,
-            (&Linkage::ExternalWeakLinkage, &Linkage::ExternalWeakLinkage) => true,
-            (&Linkage::CommonLinkage, &Linkage::CommonLinkage) => true,
-            (&Linkage::AppendingLinkage, &Linkage::AppendingLinkage) => true,
-            (&Linkage::LinkonceLinkage, &Linkage::LinkonceLinkage) => true,
-            (&Linkage::WeakLinkage, &Linkage::WeakLinkage) => true,
-            _ => false,
-        }
+impl Display for Linkage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Linkage::ExternalLinkage => "external",
+            Linkage::InternalLinkage => "internal",
+            Linkage::PrivateLinkage => "private",
+            Linkage::ExternalWeakLinkage => "external weak",
+            Linkage::CommonLinkage => "common",
+            Linkage::AppendingLinkage => "appending",
+            Linkage::LinkonceLinkage => "linkonce",
+            Linkage::WeakLinkage => "weak",
+        })
     }
 }
@@ -1,5 +1,9 @@
 
+pub mod apint;
 pub mod module;
 pub mod values;
 pub mod builder;
 pub mod linkage;
+pub mod calling_conv;
+pub mod parser;
+pub mod verifier;
@@ -1,4 +1,5 @@
 use crate::ir::values::function::Function;
+use crate::ir::values::global::GlobalVariable;
 use crate::targets::triple::TargetTriple;
 use crate::targets::layout::DataLayout;
 use std::fmt::Display;
@@ -19,6 +20,7 @@ use std::rc::Rc;
 #[derive(Clone)]
 pub struct Module {
     functions: Vec<Rc<RefCell<Function>>>,
+    global_variables: Vec<Rc<RefCell<GlobalVariable>>>,
     name: String,
     data_layout: DataLayout,
     target_triple: TargetTriple,
@@ -30,6 +32,7 @@ impl Module {
     pub fn new(name: &str, data_layout: DataLayout, target_triple: TargetTriple) -> Self {
         Self {
             functions: Vec::new(),
+            global_variables: Vec::new(),
             name: name.to_string(),
             globals: Vec::new(),
             data_layout,
@@ -59,9 +62,26 @@ impl Module {
 
     /// Adds a function to the module.
     pub fn add_function(&mut self, function: Rc<RefCell<Function>>) {
+        self.globals.push(function.borrow().get_name());
         self.functions.push(function);
     }
 
+    /// Returns the global variables of the module.
+    pub fn get_global_variables(&self) -> &Vec<Rc<RefCell<GlobalVariable>>> {
+        &self.global_variables
+    }
+
+    /// Returns the global variable with the given name.
+    pub fn get_global_variable(&self, name: &str) -> Option<&Rc<RefCell<GlobalVariable>>> {
+        self.global_variables.iter().find(|global| global.borrow().get_name() == name)
+    }
+
+    /// Adds a global variable to the module.
+    pub fn add_global_variable(&mut self, global: Rc<RefCell<GlobalVariable>>) {
+        self.globals.push(global.borrow().get_name());
+        self.global_variables.push(global);
+    }
+
     /// Returns the name a gloval value should use.
     pub fn get_global_value_name(&self, name: &str) -> String {
         if self.globals.contains(&name.to_string()) {
@@ -86,10 +106,16 @@ impl Display for Module {
         s.push_str(&format!("source_name = \"{}\"\n", self.name));
         s.push_str(&format!("target datalayout = \"{}\"\n", self.data_layout));
         s.push_str(&format!("target triple = \"{}\"\n", self.target_triple));
-        s.push_str("\n");
+        s.push('\n');
+        for global in &self.global_variables {
+            s.push_str(&format!("{}\n", global.borrow()));
+        }
+        if !self.global_variables.is_empty() {
+            s.push('\n');
+        }
         for function in &self.functions {
             s.push_str(&function.borrow().to_string());
-            s.push_str("\n");
+            s.push('\n');
         }
         write!(f, "{}", s)
     }
@@ -0,0 +1,252 @@
+use crate::ir::parser::ParseError;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    /// Keywords and type names, like `define` or `i32`.
+    Ident(String),
+    /// A local name, including its `%`.
+    Local(String),
+    /// A global name, without its `@`.
+    Global(String),
+    Int(i64),
+    /// An integer too wide for `i64`, as written.
+    BigInt(String),
+    Float(f64),
+    /// The contents of a `"..."` string.
+    Str(String),
+    /// The contents of a `c"..."` byte string, with escapes resolved.
+    Bytes(Vec<u8>),
+    Equals,
+    Comma,
+    Colon,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Star,
+    Arrow,
+    Ellipsis,
+    Newline,
+    Eof,
+}
+
+/// A token and the line and column it starts at, both counted from 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spanned {
+    pub token: Token,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Ident(ident) => write!(f, "`{}`", ident),
+            Token::Local(name) => write!(f, "`{}`", name),
+            Token::Global(name) => write!(f, "`@{}`", name),
+            Token::Int(value) => write!(f, "`{}`", value),
+            Token::BigInt(value) => write!(f, "`{}`", value),
+            Token::Float(value) => write!(f, "`{:?}`", value),
+            Token::Str(value) => write!(f, "`\"{}\"`", value),
+            Token::Bytes(_) => write!(f, "byte string"),
+            Token::Equals => write!(f, "`=`"),
+            Token::Comma => write!(f, "`,`"),
+            Token::Colon => write!(f, "`:`"),
+            Token::LParen => write!(f, "`(`"),
+            Token::RParen => write!(f, "`)`"),
+            Token::LBracket => write!(f, "`[`"),
+            Token::RBracket => write!(f, "`]`"),
+            Token::LBrace => write!(f, "`{{`"),
+            Token::RBrace => write!(f, "`}}`"),
+            Token::Star => write!(f, "`*`"),
+            Token::Arrow => write!(f, "`->`"),
+            Token::Ellipsis => write!(f, "`...`"),
+            Token::Newline => write!(f, "end of line"),
+            Token::Eof => write!(f, "end of input"),
+        }
+    }
+}
+
+/// Returns whether `c` may appear in a local or global name.
+pub fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$')
+}
+
+struct Lexer<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl Lexer<'_> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn take_while(&mut self, pred: impl Fn(char) -> bool) -> String {
+        let mut s = String::new();
+        while let Some(c) = self.peek().filter(|c| pred(*c)) {
+            s.push(c);
+            self.bump();
+        }
+        s
+    }
+
+    fn error(&self, line: usize, column: usize, message: String) -> ParseError {
+        ParseError::new(line, column, message)
+    }
+
+    fn string(&mut self, line: usize, column: usize) -> Result<String, ParseError> {
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(s),
+                Some('\n') | None => return Err(self.error(line, column, "unterminated string".to_string())),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    /// Reads the rest of a `c"..."` string, resolving `\XX` escapes.
+    fn bytes(&mut self, line: usize, column: usize) -> Result<Vec<u8>, ParseError> {
+        let raw = self.string(line, column)?.into_bytes();
+        let mut bytes = Vec::new();
+        let mut i = 0;
+        while i < raw.len() {
+            if raw[i] != b'\\' {
+                bytes.push(raw[i]);
+                i += 1;
+                continue;
+            }
+            let byte = raw.get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            match byte {
+                Some(byte) => bytes.push(byte),
+                None => return Err(self.error(line, column, "invalid escape in byte string".to_string())),
+            }
+            i += 3;
+        }
+        Ok(bytes)
+    }
+
+    /// Reads the rest of a number whose first character, a digit or `-`, is in `text`.
+    fn number(&mut self, mut text: String, line: usize, column: usize) -> Result<Token, ParseError> {
+        text.push_str(&self.take_while(|c| c.is_ascii_digit()));
+        let mut is_float = false;
+        if self.peek() == Some('.') {
+            is_float = true;
+            text.push('.');
+            self.bump();
+            text.push_str(&self.take_while(|c| c.is_ascii_digit()));
+        }
+        if matches!(self.peek(), Some('e' | 'E')) {
+            is_float = true;
+            text.push('e');
+            self.bump();
+            if let Some(sign) = self.peek().filter(|c| matches!(c, '+' | '-')) {
+                text.push(sign);
+                self.bump();
+            }
+            text.push_str(&self.take_while(|c| c.is_ascii_digit()));
+        }
+        if is_float {
+            text.parse().map(Token::Float).map_err(|_| self.error(line, column, format!("invalid number `{}`", text)))
+        } else {
+            Ok(text.parse().map(Token::Int).unwrap_or(Token::BigInt(text)))
+        }
+    }
+
+    fn token(&mut self) -> Result<Option<Spanned>, ParseError> {
+        // skip spaces and comments, but not line ends
+        loop {
+            match self.peek() {
+                Some(c) if c.is_whitespace() && c != '\n' => {
+                    self.bump();
+                }
+                Some('#') => {
+                    self.take_while(|c| c != '\n');
+                }
+                _ => break,
+            }
+        }
+
+        let (line, column) = (self.line, self.column);
+        let Some(c) = self.bump() else {
+            return Ok(None);
+        };
+        let token = match c {
+            '\n' => Token::Newline,
+            '=' => Token::Equals,
+            ',' => Token::Comma,
+            ':' => Token::Colon,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            '{' => Token::LBrace,
+            '}' => Token::RBrace,
+            '*' => Token::Star,
+            '"' => Token::Str(self.string(line, column)?),
+            '-' if self.peek() == Some('>') => {
+                self.bump();
+                Token::Arrow
+            }
+            '-' if self.peek().is_some_and(|c| c.is_ascii_digit()) => self.number("-".to_string(), line, column)?,
+            '-' if self.peek() == Some('i') => match self.take_while(is_name_char).as_str() {
+                "inf" => Token::Float(f64::NEG_INFINITY),
+                word => return Err(self.error(line, column, format!("unexpected `-{}`", word))),
+            },
+            '.' if self.peek() == Some('.') => {
+                if self.take_while(|c| c == '.') != ".." {
+                    return Err(self.error(line, column, "expected `...`".to_string()));
+                }
+                Token::Ellipsis
+            }
+            '%' | '@' => {
+                let name = self.take_while(is_name_char);
+                if name.is_empty() {
+                    return Err(self.error(line, column, format!("expected a name after `{}`", c)));
+                }
+                if c == '%' { Token::Local(format!("%{}", name)) } else { Token::Global(name) }
+            }
+            'c' if self.peek() == Some('"') => {
+                self.bump();
+                Token::Bytes(self.bytes(line, column)?)
+            }
+            c if c.is_ascii_digit() => self.number(c.to_string(), line, column)?,
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut word = c.to_string();
+                word.push_str(&self.take_while(is_name_char));
+                Token::Ident(word)
+            }
+            c => return Err(self.error(line, column, format!("unexpected character `{}`", c))),
+        };
+        Ok(Some(Spanned { token, line, column }))
+    }
+}
+
+/// Splits `source` into tokens, ending with [`Token::Eof`].
+pub fn tokenize(source: &str) -> Result<Vec<Spanned>, ParseError> {
+    let mut lexer = Lexer { chars: source.chars().peekable(), line: 1, column: 1 };
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.token()? {
+        tokens.push(token);
+    }
+    tokens.push(Spanned { token: Token::Eof, line: lexer.line, column: lexer.column });
+    Ok(tokens)
+}
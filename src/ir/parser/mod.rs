@@ -0,0 +1,1340 @@
+pub mod lexer;
+
+use crate::ir::calling_conv::CallingConv;
+use crate::ir::linkage::Linkage;
+use crate::ir::module::Module;
+use crate::ir::parser::lexer::{tokenize, Spanned, Token};
+use crate::ir::values::basic_block::BasicBlock;
+use crate::ir::values::function::Function;
+use crate::ir::apint::ApInt;
+use crate::ir::values::constant::{Constant, ConstantExpr, CONSTANT_BINARY_OPCODES};
+use crate::ir::values::global::GlobalVariable;
+use crate::ir::values::instruction::{FastMathFlags, FloatPredicate, Instruction, InstructionType, CAST_OPCODES, get_indexed_type, is_valid_cast};
+use crate::ir::values::value::{Type, ValueEntity};
+use crate::targets::{DataLayout, TargetTriple};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+use std::str::FromStr;
+
+/// An error in textual IR, with the line and column it was found at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    pub fn new(line: usize, column: usize, message: String) -> Self {
+        Self { line, column, message }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+/// Parses a module from the text `Display for Module` produces.
+pub fn parse_module(source: &str) -> Result<Module, ParseError> {
+    Parser::new(tokenize(source)?).parse_module()
+}
+
+impl FromStr for Module {
+    type Err = ParseError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        parse_module(source)
+    }
+}
+
+/// A reference to a value, resolved once the whole function has been read.
+#[derive(Debug, Clone)]
+enum Operand {
+    Local(String),
+    Global(String),
+    Constant(ConstSyntax),
+}
+
+/// A constant as written. Most constants take their type from where they
+/// are used, so they are only typed once that is known.
+#[derive(Debug, Clone)]
+enum ConstSyntax {
+    /// An integer of any width, as written.
+    Int(String),
+    Float(f64),
+    Bool(bool),
+    Null,
+    Undef,
+    Poison,
+    Zero,
+    Bytes(Vec<u8>),
+    /// The address of a function or global, inside another constant.
+    Global(String),
+    Array(Vec<ConstSyntax>),
+    Struct(Vec<ConstSyntax>),
+    Cast(&'static str, Type, Box<ConstSyntax>, Type),
+    Binary(&'static str, Type, Box<ConstSyntax>, Box<ConstSyntax>),
+}
+
+#[derive(Debug, Clone)]
+struct ValueRef {
+    operand: Operand,
+    line: usize,
+    column: usize,
+}
+
+/// An instruction as written, before its operands are resolved.
+#[derive(Debug, Clone)]
+enum Op {
+    Binary(String, Type, ValueRef, ValueRef),
+    Unary(String, Type, ValueRef),
+    FloatBinary(String, FastMathFlags, Type, ValueRef, ValueRef),
+    FNeg(FastMathFlags, Type, ValueRef),
+    FCmp(FastMathFlags, FloatPredicate, Type, ValueRef, ValueRef),
+    Cast(String, Type, ValueRef, Type),
+    Alloca(Type, Option<(Type, ValueRef)>, u64),
+    GetElementPtr(Type, ValueRef, Vec<(Type, ValueRef)>),
+    Load(Type, ValueRef),
+    Store(Type, ValueRef, ValueRef),
+    Call(Type, ValueRef, Vec<ValueRef>),
+    Return(Option<(Type, ValueRef)>),
+    Branch(ValueRef),
+    BranchIf(ValueRef, ValueRef, ValueRef),
+    Phi(Type, Vec<(ValueRef, ValueRef)>),
+    Unreachable,
+}
+
+#[derive(Debug, Clone)]
+struct InstSyntax {
+    name: Option<String>,
+    op: Op,
+    line: usize,
+    column: usize,
+}
+
+const BINARY_OPS: &[&str] = &[
+    "add", "sub", "mul", "sdiv", "udiv", "srem", "urem", "shl", "ashr", "lshr", "and", "or", "xor",
+    "eq", "ne", "slt", "sle", "sgt", "sge", "ult", "ule", "ugt", "uge",
+];
+
+const FLOAT_BINARY_OPS: &[&str] = &["fadd", "fsub", "fmul", "fdiv", "frem"];
+
+struct Parser {
+    tokens: Vec<Spanned>,
+    pos: usize,
+    functions: HashMap<String, Rc<RefCell<Function>>>,
+    globals: HashMap<String, Rc<RefCell<GlobalVariable>>>,
+}
+
+impl Parser {
+    fn new(tokens: Vec<Spanned>) -> Self {
+        Self { tokens, pos: 0, functions: HashMap::new(), globals: HashMap::new() }
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].token
+    }
+
+    fn next(&mut self) -> Spanned {
+        let token = self.tokens[self.pos].clone();
+        if token.token != Token::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn error(&self, message: String) -> ParseError {
+        let token = &self.tokens[self.pos];
+        ParseError::new(token.line, token.column, message)
+    }
+
+    /// Reports an error at the token before the current one.
+    fn error_at_previous(&self, message: String) -> ParseError {
+        let token = &self.tokens[self.pos.saturating_sub(1)];
+        ParseError::new(token.line, token.column, message)
+    }
+
+    fn unexpected(&self, expected: &str) -> ParseError {
+        self.error(format!("expected {}, found {}", expected, self.peek()))
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == token {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_ident(&mut self, word: &str) -> bool {
+        if matches!(self.peek(), Token::Ident(ident) if ident == word) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), ParseError> {
+        if self.eat(&token) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&token.to_string()))
+        }
+    }
+
+    fn expect_ident(&mut self, word: &str) -> Result<(), ParseError> {
+        if self.eat_ident(word) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("`{}`", word)))
+        }
+    }
+
+    fn expect_int(&mut self) -> Result<i64, ParseError> {
+        match self.peek().clone() {
+            Token::Int(value) => {
+                self.next();
+                Ok(value)
+            }
+            _ => Err(self.unexpected("an integer")),
+        }
+    }
+
+    fn expect_str(&mut self) -> Result<String, ParseError> {
+        match self.peek().clone() {
+            Token::Str(value) => {
+                self.next();
+                Ok(value)
+            }
+            _ => Err(self.unexpected("a string")),
+        }
+    }
+
+    fn expect_line_end(&mut self) -> Result<(), ParseError> {
+        match self.peek() {
+            Token::Newline => {
+                self.next();
+                Ok(())
+            }
+            Token::Eof => Ok(()),
+            _ => Err(self.unexpected("end of line")),
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.eat(&Token::Newline) {}
+    }
+
+    fn parse_module(mut self) -> Result<Module, ParseError> {
+        let mut name = String::new();
+        let mut data_layout = None;
+        let mut target_triple = None;
+        let mut global_order = Vec::new();
+        let mut function_order = Vec::new();
+        let mut bodies = Vec::new();
+        let mut initializers = Vec::new();
+
+        loop {
+            self.skip_newlines();
+            match self.peek().clone() {
+                Token::Eof => break,
+                Token::Ident(word) if word == "source_name" => {
+                    self.next();
+                    self.expect(Token::Equals)?;
+                    name = self.expect_str()?;
+                }
+                Token::Ident(word) if word == "target" => {
+                    self.next();
+                    if self.eat_ident("datalayout") {
+                        self.expect(Token::Equals)?;
+                        let layout = self.expect_str()?;
+                        data_layout = Some(DataLayout::parse(&layout).map_err(|_| self.error_at_previous(format!("invalid data layout \"{}\"", layout)))?);
+                    } else if self.eat_ident("triple") {
+                        self.expect(Token::Equals)?;
+                        let triple = self.expect_str()?;
+                        target_triple = Some(TargetTriple::new(&triple).map_err(|_| self.error_at_previous(format!("invalid target triple \"{}\"", triple)))?);
+                    } else {
+                        return Err(self.unexpected("`datalayout` or `triple`"));
+                    }
+                }
+                Token::Global(_) => {
+                    let (global, initializer) = self.parse_global()?;
+                    global_order.push(global.borrow().get_name());
+                    initializers.extend(initializer.map(|initializer| (global, initializer)));
+                }
+                Token::Ident(word) if word == "define" || word == "declare" => {
+                    let function = self.parse_function_header()?;
+                    function_order.push(function.borrow().get_name());
+                    if word == "define" {
+                        // bodies may call functions defined further down, so they are read last
+                        self.expect(Token::LBrace)?;
+                        bodies.push((function, self.pos));
+                        self.skip_body()?;
+                    }
+                }
+                _ => return Err(self.unexpected("a global, a function or a target header")),
+            }
+            self.expect_line_end()?;
+        }
+
+        // initializers may use the address of anything in the module
+        for (global, initializer) in initializers {
+            let Operand::Constant(syntax) = &initializer.operand else {
+                unreachable!();
+            };
+            let ty = global.borrow().get_value_type().clone();
+            let constant = self.constant(syntax, Some(&ty)).map_err(|message| ParseError::new(initializer.line, initializer.column, message))?;
+            global.borrow_mut().set_initializer(Some(constant));
+        }
+
+        for (function, start) in bodies {
+            self.pos = start;
+            self.parse_body(&function)?;
+        }
+
+        let target_triple = match target_triple {
+            Some(triple) => triple,
+            None => TargetTriple::from_host().map_err(|_| ParseError::new(1, 1, "missing target triple".to_string()))?,
+        };
+        let data_layout = data_layout.unwrap_or_else(|| DataLayout::from_triple(&target_triple));
+        let mut module = Module::new(&name, data_layout, target_triple);
+        for name in global_order {
+            module.add_global_variable(self.globals[&name].clone());
+        }
+        for name in function_order {
+            module.add_function(self.functions[&name].clone());
+        }
+        Ok(module)
+    }
+
+    /// Skips to the token after the `}` closing a function body.
+    fn skip_body(&mut self) -> Result<(), ParseError> {
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next().token {
+                Token::LBrace => depth += 1,
+                Token::RBrace => depth -= 1,
+                Token::Eof => return Err(self.error("unterminated function body".to_string())),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn declare_name(&self, name: &str) -> Result<(), ParseError> {
+        if self.functions.contains_key(name) || self.globals.contains_key(name) {
+            return Err(self.error(format!("@{} is defined more than once", name)));
+        }
+        Ok(())
+    }
+
+    fn parse_linkage(&mut self) -> Result<Linkage, ParseError> {
+        let linkage = match self.peek() {
+            Token::Ident(word) => match word.as_str() {
+                "external" => Linkage::ExternalLinkage,
+                "internal" => Linkage::InternalLinkage,
+                "private" => Linkage::PrivateLinkage,
+                "common" => Linkage::CommonLinkage,
+                "appending" => Linkage::AppendingLinkage,
+                "linkonce" => Linkage::LinkonceLinkage,
+                "weak" => Linkage::WeakLinkage,
+                _ => return Err(self.unexpected("a linkage")),
+            },
+            _ => return Err(self.unexpected("a linkage")),
+        };
+        self.next();
+        if linkage == Linkage::ExternalLinkage && self.eat_ident("weak") {
+            return Ok(Linkage::ExternalWeakLinkage);
+        }
+        Ok(linkage)
+    }
+
+    /// Parses an optional calling convention, which defaults to `ccc`.
+    fn parse_calling_conv(&mut self) -> CallingConv {
+        if self.eat_ident("x86_stdcallcc") {
+            CallingConv::X86Stdcall
+        } else if self.eat_ident("x86_fastcallcc") {
+            CallingConv::X86Fastcall
+        } else {
+            self.eat_ident("ccc");
+            CallingConv::C
+        }
+    }
+
+    fn parse_type(&mut self) -> Result<Type, ParseError> {
+        let mut ty = match self.peek().clone() {
+            Token::Ident(word) => {
+                let bits = word.get(1..).and_then(|bits| bits.parse::<usize>().ok()).filter(|bits| *bits > 0);
+                let ty = match (word.as_str(), word.chars().next(), bits) {
+                    ("void", _, _) => Type::Void,
+                    ("branch", _, _) => Type::Branch,
+                    (_, Some('i'), Some(bits)) => Type::Integer(bits),
+                    (_, Some('f'), Some(bits)) => Type::Float(bits),
+                    _ => return Err(self.unexpected("a type")),
+                };
+                self.next();
+                ty
+            }
+            Token::LBracket => {
+                self.next();
+                let len = self.expect_int()?;
+                let len = usize::try_from(len).map_err(|_| self.error_at_previous(format!("invalid array length {}", len)))?;
+                self.expect_ident("x")?;
+                let element = self.parse_type()?;
+                self.expect(Token::RBracket)?;
+                Type::Array(len, Box::new(element))
+            }
+            Token::LBrace => {
+                self.next();
+                let mut fields = Vec::new();
+                if !self.eat(&Token::RBrace) {
+                    loop {
+                        fields.push(self.parse_type()?);
+                        if self.eat(&Token::RBrace) {
+                            break;
+                        }
+                        self.expect(Token::Comma)?;
+                    }
+                }
+                Type::Struct(fields)
+            }
+            Token::LParen => {
+                self.next();
+                let ty = self.parse_function_type(Vec::new())?;
+                self.expect(Token::RParen)?;
+                ty
+            }
+            _ => return Err(self.unexpected("a type")),
+        };
+        while self.eat(&Token::Star) {
+            ty = ty.get_pointer_to();
+        }
+        Ok(ty)
+    }
+
+    /// Parses the rest of a function type whose first parameter types have been read.
+    fn parse_function_type(&mut self, mut params: Vec<Type>) -> Result<Type, ParseError> {
+        if params.is_empty() && self.peek() != &Token::Arrow {
+            params.push(self.parse_type()?);
+        }
+        while self.eat(&Token::Comma) {
+            params.push(self.parse_type()?);
+        }
+        self.expect(Token::Arrow)?;
+        let ret = self.parse_type()?;
+        Ok(Type::FunctionType(params, Box::new(ret)))
+    }
+
+    /// Parses the callee type of a call: a bare function type, or a pointer to one.
+    fn parse_callee_type(&mut self) -> Result<Type, ParseError> {
+        if self.peek() == &Token::Arrow {
+            return self.parse_function_type(Vec::new());
+        }
+        let ty = self.parse_type()?;
+        match self.peek() {
+            Token::Comma | Token::Arrow => self.parse_function_type(vec![ty]),
+            _ => Ok(ty),
+        }
+    }
+
+    fn parse_constant(&mut self) -> Result<ConstSyntax, ParseError> {
+        let constant = match self.peek().clone() {
+            Token::Ident(word) => match word.as_str() {
+                "true" => ConstSyntax::Bool(true),
+                "false" => ConstSyntax::Bool(false),
+                "null" => ConstSyntax::Null,
+                "undef" => ConstSyntax::Undef,
+                "poison" => ConstSyntax::Poison,
+                "zeroinitializer" => ConstSyntax::Zero,
+                "NaN" => ConstSyntax::Float(f64::NAN),
+                "inf" => ConstSyntax::Float(f64::INFINITY),
+                opcode => return self.parse_constant_expr(opcode),
+            },
+            Token::Int(value) => ConstSyntax::Int(value.to_string()),
+            Token::BigInt(value) => ConstSyntax::Int(value),
+            Token::Float(value) => ConstSyntax::Float(value),
+            Token::Bytes(bytes) => ConstSyntax::Bytes(bytes),
+            Token::Global(name) => ConstSyntax::Global(name),
+            Token::LBracket | Token::LBrace => {
+                let close = if self.next().token == Token::LBracket { Token::RBracket } else { Token::RBrace };
+                let mut elements = Vec::new();
+                if !self.eat(&close) {
+                    loop {
+                        elements.push(self.parse_constant()?);
+                        if self.eat(&close) {
+                            break;
+                        }
+                        self.expect(Token::Comma)?;
+                    }
+                }
+                return Ok(if close == Token::RBracket { ConstSyntax::Array(elements) } else { ConstSyntax::Struct(elements) });
+            }
+            _ => return Err(self.unexpected("a constant")),
+        };
+        self.next();
+        Ok(constant)
+    }
+
+    /// Parses `opcode (T c to T)` or `opcode (T c, c)`.
+    fn parse_constant_expr(&mut self, opcode: &str) -> Result<ConstSyntax, ParseError> {
+        let cast = CAST_OPCODES.iter().find(|cast| **cast == opcode);
+        let binary = CONSTANT_BINARY_OPCODES.iter().find(|binary| **binary == opcode);
+        if cast.is_none() && binary.is_none() {
+            return Err(self.unexpected("a constant"));
+        }
+        self.next();
+        self.expect(Token::LParen)?;
+        let ty = self.parse_type()?;
+        let a = Box::new(self.parse_constant()?);
+        let expr = match (cast, binary) {
+            (Some(cast), _) => {
+                self.expect_ident("to")?;
+                ConstSyntax::Cast(cast, ty, a, self.parse_type()?)
+            }
+            (_, Some(binary)) => {
+                self.expect(Token::Comma)?;
+                ConstSyntax::Binary(binary, ty, a, Box::new(self.parse_constant()?))
+            }
+            _ => unreachable!(),
+        };
+        self.expect(Token::RParen)?;
+        Ok(expr)
+    }
+
+    /// Gives a constant the type `ty`, or the type it has on its own when
+    /// the context has none, like the variadic arguments of a call.
+    fn constant(&self, syntax: &ConstSyntax, ty: Option<&Type>) -> Result<Constant, String> {
+        let mismatch = |ty: &Type| {
+            let found = match syntax {
+                ConstSyntax::Int(text) => format!("integer {}", text),
+                ConstSyntax::Float(value) => format!("float {:?}", value),
+                ConstSyntax::Bool(value) => value.to_string(),
+                ConstSyntax::Null => "null".to_string(),
+                ConstSyntax::Undef => "undef".to_string(),
+                ConstSyntax::Poison => "poison".to_string(),
+                ConstSyntax::Zero => "zeroinitializer".to_string(),
+                ConstSyntax::Bytes(_) => "a byte string".to_string(),
+                ConstSyntax::Global(name) => format!("@{}", name),
+                ConstSyntax::Array(_) => "an array".to_string(),
+                ConstSyntax::Struct(_) => "a struct".to_string(),
+                ConstSyntax::Cast(_, _, _, to) => format!("a conversion to {}", to),
+                ConstSyntax::Binary(opcode, ty, _, _) => format!("{} of {}", opcode, ty),
+            };
+            format!("{} is not a constant of type {}", found, ty)
+        };
+        let Some(ty) = ty else {
+            return match syntax {
+                ConstSyntax::Int(text) => ApInt::parse(32, text).or_else(|| ApInt::parse(64, text)).map(Constant::Int)
+                    .ok_or_else(|| format!("integer {} does not fit in i64", text)),
+                ConstSyntax::Float(value) => Ok(Constant::from_f64(*value)),
+                ConstSyntax::Bool(value) => Ok(Constant::from_bool(*value)),
+                ConstSyntax::Cast(_, _, _, ty) | ConstSyntax::Binary(_, ty, _, _) => self.constant(syntax, Some(ty)),
+                _ => Err("the type of the constant is not known here".to_string()),
+            };
+        };
+        Ok(match (syntax, ty) {
+            (ConstSyntax::Int(text), Type::Integer(bits)) => {
+                Constant::Int(ApInt::parse(*bits, text).ok_or_else(|| format!("integer {} does not fit in {}", text, ty))?)
+            }
+            (ConstSyntax::Float(value), Type::Float(32)) => Constant::from_f32(*value as f32),
+            (ConstSyntax::Float(value), Type::Float(64)) => Constant::from_f64(*value),
+            (ConstSyntax::Bool(value), Type::Integer(1)) => Constant::from_bool(*value),
+            (ConstSyntax::Null, Type::Pointer(_)) => Constant::Null(ty.clone()),
+            (ConstSyntax::Undef | ConstSyntax::Poison | ConstSyntax::Zero, Type::Void | Type::Branch | Type::FunctionType(..)) => return Err(mismatch(ty)),
+            (ConstSyntax::Undef, _) => Constant::Undef(ty.clone()),
+            (ConstSyntax::Poison, _) => Constant::Poison(ty.clone()),
+            (ConstSyntax::Zero, _) => Constant::Zero(ty.clone()),
+            (ConstSyntax::Bytes(bytes), Type::Array(len, element)) if **element == Type::Integer(8) => {
+                if bytes.len() != *len {
+                    return Err(format!("expected {} bytes, found {}", len, bytes.len()));
+                }
+                Constant::Bytes(bytes.clone())
+            }
+            (ConstSyntax::Global(name), Type::Pointer(_)) => {
+                if !self.functions.contains_key(name) && !self.globals.contains_key(name) {
+                    return Err(format!("@{} is not defined", name));
+                }
+                Constant::Symbol(ty.clone(), name.clone())
+            }
+            (ConstSyntax::Array(elements), Type::Array(len, element)) => {
+                if elements.len() != *len {
+                    return Err(format!("expected {} elements, found {}", len, elements.len()));
+                }
+                let elements = elements.iter().map(|syntax| self.constant(syntax, Some(element))).collect::<Result<Vec<_>, _>>()?;
+                Constant::Array((**element).clone(), elements)
+            }
+            (ConstSyntax::Struct(fields), Type::Struct(types)) => {
+                if fields.len() != types.len() {
+                    return Err(format!("expected {} fields, found {}", types.len(), fields.len()));
+                }
+                let fields = fields.iter().zip(types).map(|(syntax, ty)| self.constant(syntax, Some(ty))).collect::<Result<Vec<_>, _>>()?;
+                Constant::Struct(fields)
+            }
+            (ConstSyntax::Cast(opcode, from, a, to), _) if to == ty => {
+                if !is_valid_cast(opcode, from, to) {
+                    return Err(format!("{} cannot convert a value of type {} to {}", opcode, from, to));
+                }
+                Constant::Expr(Box::new(ConstantExpr::Cast(opcode, self.constant(a, Some(from))?, to.clone())))
+            }
+            (ConstSyntax::Binary(opcode, operand_type, a, b), Type::Integer(_)) if operand_type == ty => {
+                let (a, b) = (self.constant(a, Some(ty))?, self.constant(b, Some(ty))?);
+                Constant::Expr(Box::new(ConstantExpr::Binary(opcode, a, b)))
+            }
+            _ => return Err(mismatch(ty)),
+        })
+    }
+
+    /// Parses `@name = linkage [thread_local] global|constant T [init] [, section "s"] [, align n]`.
+    /// The initializer is returned unresolved, since it may refer to
+    /// functions and globals further down.
+    fn parse_global(&mut self) -> Result<(Rc<RefCell<GlobalVariable>>, Option<ValueRef>), ParseError> {
+        let Token::Global(name) = self.next().token else {
+            unreachable!();
+        };
+        self.declare_name(&name)?;
+        self.expect(Token::Equals)?;
+        let linkage = self.parse_linkage()?;
+        let thread_local = self.eat_ident("thread_local");
+        let is_constant = if self.eat_ident("constant") {
+            true
+        } else {
+            self.expect_ident("global")?;
+            false
+        };
+        let ty = self.parse_type()?;
+        let initializer = match self.peek() {
+            Token::Newline | Token::Eof | Token::Comma => None,
+            _ => {
+                let Spanned { line, column, .. } = self.tokens[self.pos];
+                Some(ValueRef { operand: Operand::Constant(self.parse_constant()?), line, column })
+            }
+        };
+
+        let mut global = GlobalVariable::new(name.clone(), ty, None, linkage, is_constant);
+        global.set_thread_local(thread_local);
+        if self.eat(&Token::Comma) {
+            if self.eat_ident("section") {
+                global.set_section(&self.expect_str()?);
+                if self.eat(&Token::Comma) {
+                    self.expect_ident("align")?;
+                    global.set_alignment(self.parse_align()?).map_err(|error| self.error_at_previous(error.to_string()))?;
+                }
+            } else {
+                self.expect_ident("align")?;
+                global.set_alignment(self.parse_align()?).map_err(|error| self.error_at_previous(error.to_string()))?;
+            }
+        }
+
+        let global = Rc::new(RefCell::new(global));
+        self.globals.insert(name, global.clone());
+        Ok((global, initializer))
+    }
+
+    fn parse_align(&mut self) -> Result<u64, ParseError> {
+        let align = self.expect_int()?;
+        match u64::try_from(align) {
+            Ok(align) if align.is_power_of_two() => Ok(align),
+            _ => Err(self.error_at_previous(format!("alignment must be a power of two (got {})", align))),
+        }
+    }
+
+    /// Parses `define|declare linkage [cc] function @name(params) -> T`.
+    fn parse_function_header(&mut self) -> Result<Rc<RefCell<Function>>, ParseError> {
+        let is_definition = self.eat_ident("define");
+        if !is_definition {
+            self.expect_ident("declare")?;
+        }
+        let linkage = self.parse_linkage()?;
+        if is_definition == (linkage == Linkage::ExternalLinkage) {
+            return Err(self.error(format!("external functions must be declared, and others defined (got {} linkage)", linkage)));
+        }
+        let calling_conv = self.parse_calling_conv();
+        self.expect_ident("function")?;
+        let name = match self.peek().clone() {
+            Token::Global(name) => {
+                self.next();
+                name
+            }
+            _ => return Err(self.unexpected("a function name")),
+        };
+        self.declare_name(&name)?;
+
+        self.expect(Token::LParen)?;
+        let mut names = Vec::new();
+        let mut types = Vec::new();
+        let mut is_var_arg = false;
+        if !self.eat(&Token::RParen) {
+            loop {
+                if self.eat(&Token::Ellipsis) {
+                    is_var_arg = true;
+                    self.expect(Token::RParen)?;
+                    break;
+                }
+                match self.peek().clone() {
+                    Token::Local(param) => {
+                        if names.contains(&param) {
+                            return Err(self.error(format!("parameter {} is defined more than once", param)));
+                        }
+                        self.next();
+                        names.push(param);
+                    }
+                    _ => return Err(self.unexpected("a parameter name")),
+                }
+                self.expect(Token::Colon)?;
+                types.push(self.parse_type()?);
+                if self.eat(&Token::RParen) {
+                    break;
+                }
+                self.expect(Token::Comma)?;
+            }
+        }
+        self.expect(Token::Arrow)?;
+        let ret = self.parse_type()?;
+
+        let param_names = names.iter().map(|name| Some(&name[1..])).collect::<Vec<_>>();
+        let mut function = Function::create_with_param_names(name.clone(), Type::FunctionType(types, Box::new(ret)), &param_names, linkage, is_var_arg);
+        function.set_calling_conv(calling_conv);
+        let function = Rc::new(RefCell::new(function));
+        self.functions.insert(name, function.clone());
+        Ok(function)
+    }
+
+    fn parse_value(&mut self) -> Result<ValueRef, ParseError> {
+        let Spanned { line, column, .. } = self.tokens[self.pos];
+        let operand = match self.peek().clone() {
+            Token::Local(name) => Operand::Local(name),
+            Token::Global(name) => Operand::Global(name),
+            _ => return Ok(ValueRef { operand: Operand::Constant(self.parse_constant()?), line, column }),
+        };
+        self.next();
+        Ok(ValueRef { operand, line, column })
+    }
+
+    /// Parses the fast-math flags in front of the type of a float instruction.
+    fn parse_fast_math_flags(&mut self) -> FastMathFlags {
+        let mut flags = FastMathFlags::default();
+        while let Token::Ident(word) = self.peek() {
+            if !flags.set(word) {
+                break;
+            }
+            self.next();
+        }
+        flags
+    }
+
+    fn parse_instruction(&mut self) -> Result<InstSyntax, ParseError> {
+        let Spanned { line, column, .. } = self.tokens[self.pos];
+        let name = match self.peek().clone() {
+            Token::Local(name) => {
+                self.next();
+                self.expect(Token::Equals)?;
+                Some(name)
+            }
+            _ => None,
+        };
+        let opcode = match self.peek().clone() {
+            Token::Ident(opcode) => {
+                self.next();
+                opcode
+            }
+            _ => return Err(self.unexpected("an instruction")),
+        };
+        let op = match opcode.as_str() {
+            opcode if BINARY_OPS.contains(&opcode) => {
+                let ty = self.parse_type()?;
+                let a = self.parse_value()?;
+                self.expect(Token::Comma)?;
+                Op::Binary(opcode.to_string(), ty, a, self.parse_value()?)
+            }
+            "neg" | "not" => Op::Unary(opcode.clone(), self.parse_type()?, self.parse_value()?),
+            opcode if FLOAT_BINARY_OPS.contains(&opcode) => {
+                let flags = self.parse_fast_math_flags();
+                let ty = self.parse_type()?;
+                let a = self.parse_value()?;
+                self.expect(Token::Comma)?;
+                Op::FloatBinary(opcode.to_string(), flags, ty, a, self.parse_value()?)
+            }
+            "fneg" => Op::FNeg(self.parse_fast_math_flags(), self.parse_type()?, self.parse_value()?),
+            "fcmp" => {
+                let flags = self.parse_fast_math_flags();
+                let predicate = match self.peek() {
+                    Token::Ident(word) => FloatPredicate::from_name(word),
+                    _ => None,
+                };
+                let Some(predicate) = predicate else {
+                    return Err(self.unexpected("a float predicate"));
+                };
+                self.next();
+                let ty = self.parse_type()?;
+                let a = self.parse_value()?;
+                self.expect(Token::Comma)?;
+                Op::FCmp(flags, predicate, ty, a, self.parse_value()?)
+            }
+            opcode if CAST_OPCODES.contains(&opcode) => {
+                let ty = self.parse_type()?;
+                let a = self.parse_value()?;
+                self.expect_ident("to")?;
+                Op::Cast(opcode.to_string(), ty, a, self.parse_type()?)
+            }
+            "alloca" => {
+                let ty = self.parse_type()?;
+                self.expect(Token::Comma)?;
+                let count = if self.eat_ident("align") {
+                    None
+                } else {
+                    let count_type = self.parse_type()?;
+                    let count = self.parse_value()?;
+                    self.expect(Token::Comma)?;
+                    self.expect_ident("align")?;
+                    Some((count_type, count))
+                };
+                Op::Alloca(ty, count, self.parse_align()?)
+            }
+            "getelementptr" => {
+                let ty = self.parse_type()?;
+                let ptr = self.parse_value()?;
+                let mut indices = Vec::new();
+                while self.eat(&Token::Comma) {
+                    indices.push((self.parse_type()?, self.parse_value()?));
+                }
+                Op::GetElementPtr(ty, ptr, indices)
+            }
+            "load" => Op::Load(self.parse_type()?, self.parse_value()?),
+            "store" => {
+                let ty = self.parse_type()?;
+                let ptr = self.parse_value()?;
+                self.expect(Token::Comma)?;
+                Op::Store(ty, ptr, self.parse_value()?)
+            }
+            "call" => {
+                let ty = self.parse_callee_type()?;
+                let callee = self.parse_value()?;
+                self.expect(Token::LParen)?;
+                let mut args = Vec::new();
+                // arguments are printed with a trailing comma
+                while !self.eat(&Token::RParen) {
+                    args.push(self.parse_value()?);
+                    if !self.eat(&Token::Comma) {
+                        self.expect(Token::RParen)?;
+                        break;
+                    }
+                }
+                Op::Call(ty, callee, args)
+            }
+            "return" => {
+                if self.eat_ident("void") {
+                    Op::Return(None)
+                } else {
+                    Op::Return(Some((self.parse_type()?, self.parse_value()?)))
+                }
+            }
+            "branch" => {
+                let target = self.parse_value()?;
+                if self.eat(&Token::Comma) {
+                    let if_true = self.parse_value()?;
+                    self.expect(Token::Comma)?;
+                    Op::BranchIf(target, if_true, self.parse_value()?)
+                } else {
+                    Op::Branch(target)
+                }
+            }
+            "phi" => {
+                let ty = self.parse_type()?;
+                let mut incoming = Vec::new();
+                // pairs are printed with a trailing comma
+                while !matches!(self.peek(), Token::Newline | Token::Eof) {
+                    let value = self.parse_value()?;
+                    self.expect(Token::Comma)?;
+                    let block = self.parse_value()?;
+                    incoming.push((value, block));
+                    if !self.eat(&Token::Comma) {
+                        break;
+                    }
+                }
+                Op::Phi(ty, incoming)
+            }
+            "unreachable" => Op::Unreachable,
+            _ => return Err(ParseError::new(line, column, format!("unknown instruction `{}`", opcode))),
+        };
+
+        let has_result = !matches!(op, Op::Store(..) | Op::Return(_) | Op::Branch(_) | Op::BranchIf(..) | Op::Unreachable);
+        match (&name, has_result) {
+            (None, true) => Err(ParseError::new(line, column, format!("`{}` must be given a name", opcode))),
+            (Some(_), false) => Err(ParseError::new(line, column, format!("`{}` does not produce a value", opcode))),
+            _ => Ok(InstSyntax { name, op, line, column }),
+        }
+    }
+
+    /// Parses the blocks of a function body, starting after its `{`.
+    fn parse_body(&mut self, function: &Rc<RefCell<Function>>) -> Result<(), ParseError> {
+        let mut blocks: Vec<(String, Vec<InstSyntax>)> = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.eat(&Token::RBrace) {
+                break;
+            }
+            match self.peek().clone() {
+                Token::Local(label) if self.tokens[self.pos + 1].token == Token::Colon => {
+                    if blocks.iter().any(|(name, _)| *name == label) {
+                        return Err(self.error(format!("block {} is defined more than once", label)));
+                    }
+                    self.next();
+                    self.next();
+                    self.expect_line_end()?;
+                    blocks.push((label, Vec::new()));
+                }
+                _ => {
+                    if blocks.is_empty() {
+                        return Err(self.unexpected("a block label"));
+                    }
+                    let inst = self.parse_instruction()?;
+                    self.expect_line_end()?;
+                    blocks.last_mut().unwrap().1.push(inst);
+                }
+            }
+        }
+        FunctionBuilder::new(self, function, blocks)?.build()
+    }
+}
+
+/// Turns the parsed blocks of a function into instructions.
+///
+/// Operands are clones of the instructions they refer to, so a value used
+/// before its definition (a phi reading a value from a loop back edge, say)
+/// is first resolved to a placeholder with the right name and type. The body
+/// is then built a second time against the first build, which replaces every
+/// placeholder an instruction refers to directly.
+struct FunctionBuilder<'a> {
+    parser: &'a Parser,
+    function: Rc<RefCell<Function>>,
+    syntax: Vec<(String, Vec<InstSyntax>)>,
+    blocks: HashMap<String, Rc<RefCell<BasicBlock>>>,
+    /// Each block as it was before any instruction was added to it.
+    block_values: HashMap<String, BasicBlock>,
+    defined: HashSet<String>,
+    previous: HashMap<String, Instruction>,
+    current: HashMap<String, Instruction>,
+}
+
+impl<'a> FunctionBuilder<'a> {
+    fn new(parser: &'a Parser, function: &Rc<RefCell<Function>>, syntax: Vec<(String, Vec<InstSyntax>)>) -> Result<Self, ParseError> {
+        let mut defined = HashSet::new();
+        for inst in syntax.iter().flat_map(|(_, insts)| insts) {
+            let Some(name) = inst.name.clone() else {
+                continue;
+            };
+            if function.borrow().get_param(&name).is_some() || !defined.insert(name.clone()) {
+                return Err(ParseError::new(inst.line, inst.column, format!("value {} is defined more than once", name)));
+            }
+        }
+
+        let mut blocks = HashMap::new();
+        let mut block_values = HashMap::new();
+        for (label, _) in &syntax {
+            let block = Rc::new(RefCell::new(BasicBlock::new(label[1..].to_string(), function.clone())));
+            function.borrow_mut().add_block(block.clone()).map_err(|error| parser.error_at_previous(error.to_string()))?;
+            block_values.insert(label.clone(), block.borrow().clone());
+            blocks.insert(label.clone(), block);
+        }
+
+        Ok(Self {
+            parser,
+            function: function.clone(),
+            syntax,
+            blocks,
+            block_values,
+            defined,
+            previous: HashMap::new(),
+            current: HashMap::new(),
+        })
+    }
+
+    fn error(value: &ValueRef, message: String) -> ParseError {
+        ParseError::new(value.line, value.column, message)
+    }
+
+    fn build(mut self) -> Result<(), ParseError> {
+        let syntax = std::mem::take(&mut self.syntax);
+        for pass in 0..2 {
+            for (label, insts) in &syntax {
+                for inst in insts {
+                    let built = self.build_instruction(inst)?;
+                    if let Some(name) = &inst.name {
+                        self.current.insert(name.clone(), built.clone());
+                    }
+                    if pass == 1 {
+                        self.blocks[label].borrow_mut().insert(ValueEntity::Instruction(built));
+                    }
+                }
+            }
+            self.previous = std::mem::take(&mut self.current);
+        }
+
+        // keep names the builder hands out from clashing with the parsed ones
+        let params = self.function.borrow().get_params().iter().map(|param| param.get_name()).collect::<Vec<_>>();
+        let numbered = self.defined.iter().chain(&params).filter_map(|name| name[1..].parse::<usize>().ok()).max();
+        if let Some(max) = numbered {
+            let counter = self.function.borrow().get_instruction_counter();
+            counter.set(counter.get().max(max + 1));
+        }
+        Ok(())
+    }
+
+    fn block(&self, value: &ValueRef) -> Result<Rc<RefCell<BasicBlock>>, ParseError> {
+        match &value.operand {
+            Operand::Local(name) if self.blocks.contains_key(name) => Ok(self.blocks[name].clone()),
+            _ => Err(Self::error(value, "expected a block".to_string())),
+        }
+    }
+
+    fn block_value(&self, value: &ValueRef) -> Result<ValueEntity, ParseError> {
+        self.block(value)?;
+        let Operand::Local(name) = &value.operand else {
+            unreachable!();
+        };
+        Ok(ValueEntity::BasicBlock(self.block_values[name].clone()))
+    }
+
+    /// Resolves a value reference. `ty` is the type the context expects,
+    /// which is the type constants take.
+    fn value(&self, value: &ValueRef, ty: Option<&Type>) -> Result<ValueEntity, ParseError> {
+        Ok(match &value.operand {
+            Operand::Constant(syntax) => self.parser.constant(syntax, ty).map_err(|message| Self::error(value, message))?.into(),
+            Operand::Global(name) => {
+                if let Some(function) = self.parser.functions.get(name) {
+                    ValueEntity::Function(function.borrow().clone())
+                } else if let Some(global) = self.parser.globals.get(name) {
+                    ValueEntity::GlobalVariable(global.borrow().clone())
+                } else {
+                    return Err(Self::error(value, format!("@{} is not defined", name)));
+                }
+            }
+            Operand::Local(name) => {
+                if let Some(inst) = self.current.get(name).or_else(|| self.previous.get(name)) {
+                    inst.clone().into()
+                } else if let Some(param) = self.function.borrow().get_param(name) {
+                    param.clone().into()
+                } else if self.defined.contains(name) {
+                    // defined further down; replaced on the second pass
+                    Instruction::new(ty.cloned().unwrap_or(Type::Void), InstructionType::Unreachable, Some(name.clone())).into()
+                } else if self.blocks.contains_key(name) {
+                    ValueEntity::BasicBlock(self.block_values[name].clone())
+                } else {
+                    return Err(Self::error(value, format!("{} is not defined", name)));
+                }
+            }
+        })
+    }
+
+    fn build_instruction(&self, inst: &InstSyntax) -> Result<Instruction, ParseError> {
+        let bool_type = Type::Integer(1);
+        let (ty, instruction_type) = match &inst.op {
+            Op::Binary(opcode, ty, a, b) => {
+                let (a, b) = (Box::new(self.value(a, Some(ty))?), Box::new(self.value(b, Some(ty))?));
+                match opcode.as_str() {
+                    "add" => (ty.clone(), InstructionType::Add(a, b)),
+                    "sub" => (ty.clone(), InstructionType::Sub(a, b)),
+                    "mul" => (ty.clone(), InstructionType::Mul(a, b)),
+                    "sdiv" => (ty.clone(), InstructionType::SDiv(a, b)),
+                    "udiv" => (ty.clone(), InstructionType::UDiv(a, b)),
+                    "srem" => (ty.clone(), InstructionType::SRem(a, b)),
+                    "urem" => (ty.clone(), InstructionType::URem(a, b)),
+                    "shl" => (ty.clone(), InstructionType::Shl(a, b)),
+                    "ashr" => (ty.clone(), InstructionType::AShr(a, b)),
+                    "lshr" => (ty.clone(), InstructionType::LShr(a, b)),
+                    "xor" => (ty.clone(), InstructionType::Xor(a, b)),
+                    "and" => (ty.clone(), InstructionType::And(a, b)),
+                    "or" => (ty.clone(), InstructionType::Or(a, b)),
+                    "eq" => (bool_type, InstructionType::Eq(a, b)),
+                    "ne" => (bool_type, InstructionType::Ne(a, b)),
+                    "slt" => (bool_type, InstructionType::SLt(a, b)),
+                    "sle" => (bool_type, InstructionType::SLe(a, b)),
+                    "sgt" => (bool_type, InstructionType::SGt(a, b)),
+                    "sge" => (bool_type, InstructionType::SGe(a, b)),
+                    "ult" => (bool_type, InstructionType::ULt(a, b)),
+                    "ule" => (bool_type, InstructionType::ULe(a, b)),
+                    "ugt" => (bool_type, InstructionType::UGt(a, b)),
+                    "uge" => (bool_type, InstructionType::UGe(a, b)),
+                    _ => unreachable!(),
+                }
+            }
+            Op::Unary(opcode, ty, a) => {
+                let a = Box::new(self.value(a, Some(ty))?);
+                if opcode == "neg" {
+                    (ty.clone(), InstructionType::Neg(a))
+                } else {
+                    (ty.clone(), InstructionType::Not(a))
+                }
+            }
+            Op::FloatBinary(opcode, flags, ty, a, b) => {
+                let (a, b, flags) = (Box::new(self.value(a, Some(ty))?), Box::new(self.value(b, Some(ty))?), *flags);
+                let instruction_type = match opcode.as_str() {
+                    "fadd" => InstructionType::FAdd(a, b, flags),
+                    "fsub" => InstructionType::FSub(a, b, flags),
+                    "fmul" => InstructionType::FMul(a, b, flags),
+                    "fdiv" => InstructionType::FDiv(a, b, flags),
+                    "frem" => InstructionType::FRem(a, b, flags),
+                    _ => unreachable!(),
+                };
+                (ty.clone(), instruction_type)
+            }
+            Op::FNeg(flags, ty, a) => (ty.clone(), InstructionType::FNeg(Box::new(self.value(a, Some(ty))?), *flags)),
+            Op::FCmp(flags, predicate, ty, a, b) => {
+                let (a, b) = (Box::new(self.value(a, Some(ty))?), Box::new(self.value(b, Some(ty))?));
+                (bool_type, InstructionType::FCmp(*predicate, a, b, *flags))
+            }
+            Op::Cast(opcode, from, a, to) => {
+                let value = self.value(a, Some(from))?;
+                (to.clone(), InstructionType::cast(opcode, Box::new(value)).unwrap())
+            }
+            Op::Alloca(ty, count, align) => {
+                let count = match count {
+                    Some((count_type, count)) => Some(Box::new(self.value(count, Some(count_type))?)),
+                    None => None,
+                };
+                (ty.get_pointer_to(), InstructionType::Alloca(ty.clone(), count, *align))
+            }
+            Op::GetElementPtr(ty, ptr, indices) => {
+                let Type::Pointer(element) = ty else {
+                    return Err(Self::error(ptr, format!("cannot index through a value of type {}", ty)));
+                };
+                let indices = indices.iter()
+                    .map(|(index_type, index)| self.value(index, Some(index_type)).map(Box::new))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let indexed = get_indexed_type(element, &indices).map_err(|message| Self::error(ptr, message))?;
+                (indexed.get_pointer_to(), InstructionType::GetElementPtr(Box::new(self.value(ptr, Some(ty))?), indices))
+            }
+            Op::Load(ty, ptr) => {
+                let Type::Pointer(element) = ty else {
+                    return Err(Self::error(ptr, format!("cannot load through a value of type {}", ty)));
+                };
+                ((**element).clone(), InstructionType::Load(Box::new(self.value(ptr, Some(ty))?)))
+            }
+            Op::Store(ty, ptr, value) => {
+                let element = match ty {
+                    Type::Pointer(element) => Some(&**element),
+                    _ => None,
+                };
+                let ptr = Box::new(self.value(ptr, Some(ty))?);
+                (Type::Void, InstructionType::Store(ptr, Box::new(self.value(value, element)?)))
+            }
+            Op::Call(ty, callee, args) => {
+                let function_type = match ty {
+                    Type::FunctionType(..) => ty.clone(),
+                    Type::Pointer(element) if element.is_function_type() => (**element).clone(),
+                    _ => return Err(Self::error(callee, format!("cannot call a value of type {}", ty))),
+                };
+                let params = function_type.get_function_argument_types();
+                let args = args.iter().enumerate()
+                    .map(|(index, arg)| self.value(arg, params.get(index)).map(Box::new))
+                    .collect::<Result<Vec<_>, _>>()?;
+                (function_type.get_function_return_type(), InstructionType::Call(Box::new(self.value(callee, Some(ty))?), args))
+            }
+            Op::Return(None) => (Type::Void, InstructionType::VoidReturn),
+            Op::Return(Some((ty, value))) => (Type::Void, InstructionType::Return(Box::new(self.value(value, Some(ty))?))),
+            Op::Branch(target) => (Type::Void, InstructionType::Branch(Box::new(self.block_value(target)?))),
+            Op::BranchIf(cond, if_true, if_false) => {
+                let cond = Box::new(self.value(cond, Some(&bool_type))?);
+                (Type::Void, InstructionType::BranchIf(cond, self.block(if_true)?, self.block(if_false)?))
+            }
+            Op::Phi(ty, incoming) => {
+                if incoming.is_empty() {
+                    return Err(ParseError::new(inst.line, inst.column, "phi must have at least one incoming value".to_string()));
+                }
+                let incoming = incoming.iter()
+                    .map(|(value, block)| Ok((Box::new(self.value(value, Some(ty))?), Box::new(self.block_value(block)?))))
+                    .collect::<Result<Vec<_>, ParseError>>()?;
+                (ty.clone(), InstructionType::Phi(incoming))
+            }
+            Op::Unreachable => (Type::Void, InstructionType::Unreachable),
+        };
+        Ok(Instruction::new(ty, instruction_type, inst.name.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_module;
+
+    /// Uses every kind of global, function header and instruction the printer writes.
+    const MODULE: &str = r#"
+    target datalayout = "p-8:8 s-8:8 i-8:8 u8-1:1 i8-1:1 u16-2:2 i16-2:2 u32-4:4 i32-4:4 u64-8:8 i64-8:8 u128-16:16 i128-16:16 f32-4:4 f64-8:8"
+    target triple = "x86_64-unknown-linux"
+
+    @g = internal global i32 7, align 8
+    @tls = internal thread_local global i64 0
+    @str = private constant [4 x i8] c"abc\00"
+    @ptr = internal global i8* @str
+    @pair = weak global { i32, f64 } { 1, 2.5 }, section ".mydata"
+    @common = common global i64 0
+    @zero = internal global [2 x i16] zeroinitializer
+    @ext = external global i32
+    @float = internal global f32 1.5
+    @fn = internal global (-> void)* @nothing
+    @wide = internal global i96 -1
+    @big = internal global i128 170141183460469231731687303715884105727
+    @table = internal global [2 x i32] [7, -1]
+    @end = internal global i64 add (i64 ptrtoint ([2 x i32]* @table to i64), 8)
+    @unknown = internal global { i32, i8* } { undef, poison }
+    @none = internal global i8* null
+
+    declare external function @printf(%format: i8*, ...) -> i32
+
+    define internal function @nothing() -> void {
+    %entry:
+      return void
+    }
+
+    define internal function @elements(%p: { i8, [4 x i32] }*, %i: i64) -> i32 {
+    %entry:
+      %element = getelementptr { i8, [4 x i32] }* %p, i64 1, i32 1, i64 %i
+      %first = getelementptr { i8, [4 x i32] }* %p, i32 0, i32 0
+      %value = load i32* %element
+      store i32* %element, 7
+      return i32 %value
+    }
+
+    define internal function @constants(%a: i64) -> i64 {
+    %entry:
+      %offset = add i64 %a, sub (i64 ptrtoint ([2 x i32]* @table to i64), 4)
+      %shifted = shl i64 %offset, undef
+      %byte = trunc i64 %shifted to i8
+      %wide = zext i8 -1 to i64
+      %sum = add i64 %wide, 18446744073709551615
+      return i64 %sum
+    }
+
+    define internal function @integers(%a: i32, %b: i32) -> i32 {
+    %entry:
+      %add = add i32 %a, %b
+      %sub = sub i32 %add, 1
+      %mul = mul i32 %sub, %b
+      %div = sdiv i32 %mul, 3
+      %rem = srem i32 %div, 7
+      %shl = shl i32 %rem, 2
+      %shr = ashr i32 %shl, 1
+      %xor = xor i32 %shr, %a
+      %neg = neg i32 %xor
+      %eq = eq i32 %neg, 0
+      %not = not i1 %eq
+      %ne = ne i1 %not, false
+      %lt = slt i32 %a, %b
+      %le = sle i32 %a, %b
+      %gt = sgt i32 %a, %b
+      %ge = sge i32 %a, %b
+      %udiv = udiv i32 %a, %b
+      %urem = urem i32 %udiv, 5
+      %lshr = lshr i32 %urem, 1
+      %ult = ult i32 %lshr, %b
+      %ule = ule i32 %a, %b
+      %ugt = ugt i32 %a, %b
+      %uge = uge i32 %a, %b
+      %any = or i1 %eq, %ne
+      %all = and i1 %any, %lt
+      branch %all, %yes, %no
+    %yes:
+      branch %done
+    %no:
+      branch %done
+    %done:
+      %phi = phi i32 %neg, %yes, %xor, %no
+      return i32 %phi
+    }
+
+    define internal function @floats(%x: f64) -> f64 {
+    %entry:
+      %add = fadd f64 %x, 1.5
+      %sub = fsub nnan ninf f64 %add, %x
+      %mul = fmul fast f64 %sub, %add
+      %div = fdiv arcp f64 %mul, %x
+      %rem = frem f64 %div, inf
+      %neg = fneg nsz f64 %rem
+      %lt = fcmp ult f64 %neg, NaN
+      branch %lt, %small, %large
+    %small:
+      return f64 %div
+    %large:
+      return f64 %x
+    }
+
+    define internal function @conversions(%a: i32, %x: f64, %p: i8*) -> i64 {
+    %entry:
+      %trunc = trunc i32 %a to i8
+      %zext = zext i8 %trunc to i16
+      %sext = sext i16 %zext to i64
+      %fptrunc = fptrunc f64 %x to f32
+      %fpext = fpext f32 %fptrunc to f64
+      %fptosi = fptosi f64 %fpext to i32
+      %fptoui = fptoui f32 %fptrunc to i64
+      %sitofp = sitofp i32 %fptosi to f32
+      %uitofp = uitofp i64 %fptoui to f64
+      %ptrtoint = ptrtoint i8* %p to i64
+      %inttoptr = inttoptr i64 %ptrtoint to i32*
+      %bitcast = bitcast i32* %inttoptr to i8*
+      %bits = bitcast f64 %uitofp to i64
+      return i64 %bits
+    }
+
+    define internal function @memory(%n: i64) -> i32 {
+    %entry:
+      %one = alloca { i32, [4 x i16] }, align 8
+      %many = alloca i32, i64 %n, align 16
+      store i32* %many, 3
+      %value = load i32* %many
+      %printed = call i8* -> i32 @printf(@str, %value, 1)
+      %none = call -> void @nothing()
+      return i32 %value
+    }
+
+    define internal function @never() -> void {
+    %entry:
+      unreachable
+    }
+"#;
+
+    #[test]
+    fn printing_a_parsed_module_round_trips() {
+        let printed = parse_module(MODULE).unwrap_or_else(|e| panic!("{}", e)).to_string();
+        let reprinted = parse_module(&printed).unwrap_or_else(|e| panic!("{}\n{}", e, printed)).to_string();
+        assert_eq!(reprinted, printed);
+    }
+
+    fn error(source: &str) -> String {
+        match parse_module(source) {
+            Ok(module) => panic!("parsed\n{}", module),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn reports_errors_with_their_position() {
+        assert_eq!(error("define internal function @f() -> i32 {\n%entry:\n  return i32 %x\n}\n"), "3:14: %x is not defined");
+        assert_eq!(error("define internal function @f() -> i32 {\n%entry:\n  %x = frobnicate i32 1\n}\n"), "3:3: unknown instruction `frobnicate`");
+        assert_eq!(error("@g = internal global x32 7\n"), "1:22: expected a type, found `x32`");
+        assert_eq!(error("define internal function @f() -> i64 {\n%entry:\n  %x = zext i32 1 into i64\n}\n"), "3:19: expected `to`, found `into`");
+        assert_eq!(error("define internal function @f(%p: i32*) -> i32* {\n%entry:\n  %q = getelementptr i32* %p, i32 0, i32 1\n  return i32* %q\n}\n"), "3:27: i32 cannot be indexed into");
+        assert_eq!(error("@g = internal global i8 300\n"), "1:25: integer 300 does not fit in i8");
+        assert_eq!(error("@g = internal global i8* @missing\n"), "1:26: @missing is not defined");
+        assert_eq!(error("@g = internal global [2 x i8] [1]\n"), "1:31: expected 2 elements, found 1");
+        assert_eq!(error("define internal function @f() -> i32 {\n%entry:\n  return i32 null\n}\n"), "3:14: null is not a constant of type i32");
+    }
+
+    #[test]
+    fn numbers_new_instructions_after_the_parsed_ones() {
+        let module = parse_module(r#"
+            define internal function @f(%0: i32) -> i32 {
+            %entry:
+              %1000000 = add i32 %0, 1
+              return i32 %1000000
+            }
+        "#).unwrap_or_else(|e| panic!("{}", e));
+        let function = module.get_functions()[0].borrow();
+        assert_eq!(function.get_new_instruction_name(), "1000001");
+    }
+}
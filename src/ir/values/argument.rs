@@ -0,0 +1,46 @@
+use crate::impl_for_value;
+use crate::ir::values::value::Value;
+use crate::ir::values::value::Type;
+use std::fmt::{Display, Formatter};
+
+impl_for_value!(Argument {
+    index: usize,
+});
+
+impl Argument {
+    pub fn new(name: String, ty: Type, index: usize) -> Self {
+        Self {
+            value: Value::new(ty, name),
+            index,
+        }
+    }
+
+    pub fn get_type(&self) -> Type {
+        self.value.get_type()
+    }
+
+    pub fn get_name(&self) -> String {
+        self.value.get_name()
+    }
+
+    /// Returns the position of the argument in its function's parameter list.
+    pub fn get_index(&self) -> usize {
+        self.index
+    }
+
+    pub fn get_value(&self) -> &Value {
+        &self.value
+    }
+}
+
+impl PartialEq for Argument {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value && self.index == other.index
+    }
+}
+
+impl Display for Argument {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.value, self.value.get_type())
+    }
+}
@@ -3,26 +3,27 @@ use crate::ir::values::value::Value;
 use crate::ir::values::value::Type;
 use crate::ir::values::value::ValueEntity;
 use crate::ir::values::function::Function;
-use std::string::ToString;
-use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
 impl_for_value!(BasicBlock {
     instructions: Vec<ValueEntity>,
 
     parent: Rc<RefCell<Function>>,
-    inst_count: usize,
+    inst_count: Rc<Cell<usize>>,
 });
 
 impl BasicBlock {
     pub fn new(name: String, parent: Rc<RefCell<Function>>) -> Self {
         let value = Value::new(Type::Branch, format!("%{}", name));
+        let inst_count = parent.borrow().get_instruction_counter();
         Self {
             value,
             instructions: Vec::new(),
 
             parent,
-            inst_count: 0,
+            inst_count,
         }
     }
 
@@ -58,10 +59,11 @@ impl BasicBlock {
         &mut self.value
     }
 
-    pub fn get_new_instruction_name(&mut self) -> String {
-        let name = format!("{}", self.inst_count);
-        self.inst_count += 1;
-        name
+    pub fn get_new_instruction_name(&self) -> String {
+        // numbered per function, so values from different blocks never share a name
+        let count = self.inst_count.get();
+        self.inst_count.set(count + 1);
+        format!("{}", count)
     }
 }
 
@@ -71,12 +73,12 @@ impl PartialEq for BasicBlock {
     }
 }
 
-impl ToString for BasicBlock {
-    fn to_string(&self) -> String {
-        let mut string = format!("{}:\n", self.value.to_string());
+impl Display for BasicBlock {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}:", self.value)?;
         for instruction in &self.instructions {
-            string.push_str(&format!("  {}\n", instruction.to_string()));
+            writeln!(f, "  {}", instruction)?;
         }
-        string
+        Ok(())
     }
 }
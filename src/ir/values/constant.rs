@@ -0,0 +1,269 @@
+use crate::ir::apint::ApInt;
+use crate::ir::values::value::Type;
+use std::fmt::{Display, Formatter};
+
+/// A value fixed before the program runs. Constants are used as operands of
+/// instructions and as the initial contents of globals; unlike instructions,
+/// they have no name and live in no block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Constant {
+    /// An integer, as wide as its value.
+    Int(ApInt),
+    /// A float of the given width, as the bits of its value widened to `f64`.
+    Float(usize, u64),
+    /// The null pointer of a pointer type.
+    Null(Type),
+    /// An arbitrary value of a type, which may differ between uses.
+    Undef(Type),
+    /// A value that makes whatever depends on it undefined.
+    Poison(Type),
+    /// The value of a type whose bytes are all zero.
+    Zero(Type),
+    /// An array of constants of the given element type.
+    Array(Type, Vec<Constant>),
+    Struct(Vec<Constant>),
+    /// An array of `i8`, such as a string literal.
+    Bytes(Vec<u8>),
+    /// The address of a function or global variable, as a value of the given
+    /// pointer type, which need not be the type of the symbol.
+    Symbol(Type, String),
+    Expr(Box<ConstantExpr>),
+}
+
+/// An operation on constants, computed when the program is compiled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConstantExpr {
+    /// The conversion with the opcode of the matching instruction, to the given type.
+    Cast(&'static str, Constant, Type),
+    /// The integer operation with the opcode of the matching instruction,
+    /// one of [`CONSTANT_BINARY_OPCODES`].
+    Binary(&'static str, Constant, Constant),
+}
+
+/// The opcodes of the binary operations constant expressions can use.
+pub const CONSTANT_BINARY_OPCODES: &[&str] = &["add", "sub", "mul", "shl", "lshr", "ashr", "xor"];
+
+/// A scalar constant reduced to the value a register would hold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Scalar {
+    Int(ApInt),
+    /// A float of the given width, as in [`Constant::Float`].
+    Float(usize, u64),
+    /// The address of a symbol plus an offset in bytes.
+    Address(String, i64),
+}
+
+impl Constant {
+    pub fn from_i64(bits: usize, value: i64) -> Self {
+        Constant::Int(ApInt::from_i64(bits, value))
+    }
+
+    pub fn from_bool(value: bool) -> Self {
+        Constant::Int(ApInt::from_bool(value))
+    }
+
+    pub fn from_f32(value: f32) -> Self {
+        Constant::Float(32, (value as f64).to_bits())
+    }
+
+    pub fn from_f64(value: f64) -> Self {
+        Constant::Float(64, value.to_bits())
+    }
+
+    pub fn get_type(&self) -> Type {
+        match self {
+            Constant::Int(value) => Type::Integer(value.bits()),
+            Constant::Float(bits, _) => Type::Float(*bits),
+            Constant::Null(ty) | Constant::Undef(ty) | Constant::Poison(ty) | Constant::Zero(ty) | Constant::Symbol(ty, _) => ty.clone(),
+            Constant::Array(element, elements) => Type::Array(elements.len(), Box::new(element.clone())),
+            Constant::Struct(fields) => Type::Struct(fields.iter().map(|field| field.get_type()).collect()),
+            Constant::Bytes(bytes) => Type::Array(bytes.len(), Box::new(Type::Integer(8))),
+            Constant::Expr(expr) => match expr.as_ref() {
+                ConstantExpr::Cast(_, _, ty) => ty.clone(),
+                ConstantExpr::Binary(_, a, _) => a.get_type(),
+            },
+        }
+    }
+
+    /// Returns the constant for a value of `ty` with all bytes zero, using
+    /// the plainest form the type has.
+    pub fn zero(ty: &Type) -> Self {
+        match ty {
+            Type::Integer(bits) => Constant::Int(ApInt::zero(*bits)),
+            Type::Float(bits) => Constant::Float(*bits, 0),
+            Type::Pointer(_) => Constant::Null(ty.clone()),
+            _ => Constant::Zero(ty.clone()),
+        }
+    }
+
+    /// Returns whether all bytes of the value are known to be zero.
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Constant::Int(value) => value.is_zero(),
+            Constant::Float(_, bits) => *bits == 0,
+            Constant::Null(_) | Constant::Zero(_) => true,
+            Constant::Array(_, elements) | Constant::Struct(elements) => elements.iter().all(|element| element.is_zero()),
+            Constant::Bytes(bytes) => bytes.iter().all(|byte| *byte == 0),
+            Constant::Undef(_) | Constant::Poison(_) | Constant::Symbol(..) | Constant::Expr(_) => false,
+        }
+    }
+
+    /// Returns the names of the functions and globals whose address the constant uses.
+    pub fn symbols(&self) -> Vec<&str> {
+        match self {
+            Constant::Symbol(_, name) => vec![name],
+            Constant::Array(_, elements) | Constant::Struct(elements) => elements.iter().flat_map(|element| element.symbols()).collect(),
+            Constant::Expr(expr) => match expr.as_ref() {
+                ConstantExpr::Cast(_, a, _) => a.symbols(),
+                ConstantExpr::Binary(_, a, b) => a.symbols().into_iter().chain(b.symbols()).collect(),
+            },
+            _ => vec![],
+        }
+    }
+
+    /// Computes a scalar constant. Undefined values become zero. Returns
+    /// `None` for aggregates, and for expressions whose result depends on
+    /// more than addresses and offsets or is poison.
+    pub fn fold(&self) -> Option<Scalar> {
+        Some(match self {
+            Constant::Int(value) => Scalar::Int(value.clone()),
+            Constant::Float(bits, value) => Scalar::Float(*bits, *value),
+            Constant::Symbol(_, name) => Scalar::Address(name.clone(), 0),
+            Constant::Null(_) | Constant::Undef(_) | Constant::Poison(_) | Constant::Zero(_) => match self.get_type() {
+                Type::Integer(bits) => Scalar::Int(ApInt::zero(bits)),
+                Type::Float(bits) => Scalar::Float(bits, 0),
+                Type::Pointer(_) => Scalar::Int(ApInt::zero(64)),
+                _ => return None,
+            },
+            Constant::Array(..) | Constant::Struct(_) | Constant::Bytes(_) => return None,
+            Constant::Expr(expr) => match expr.as_ref() {
+                ConstantExpr::Cast(opcode, a, ty) => fold_cast(opcode, a.fold()?, ty)?,
+                ConstantExpr::Binary(opcode, a, b) => fold_binary(opcode, a.fold()?, b.fold()?)?,
+            },
+        })
+    }
+}
+
+fn fold_cast(opcode: &str, value: Scalar, ty: &Type) -> Option<Scalar> {
+    let to_bits = match ty {
+        Type::Integer(bits) | Type::Float(bits) => *bits,
+        _ => 64,
+    };
+    Some(match (opcode, value) {
+        // addresses keep their symbol; narrowing one keeps its low bits in a register
+        ("ptrtoint" | "inttoptr" | "bitcast", Scalar::Address(name, offset)) => Scalar::Address(name, offset),
+        ("trunc" | "zext" | "ptrtoint" | "inttoptr", Scalar::Int(value)) => Scalar::Int(value.resize(to_bits)),
+        ("sext", Scalar::Int(value)) => Scalar::Int(value.sext(to_bits)),
+        ("bitcast", Scalar::Int(value)) if ty.is_float() => {
+            let bits = value.to_u64()?;
+            Scalar::Float(to_bits, if to_bits == 32 { (f32::from_bits(bits as u32) as f64).to_bits() } else { bits })
+        }
+        ("bitcast", Scalar::Float(bits, value)) if ty.is_integer() => {
+            let value = if bits == 32 { (f64::from_bits(value) as f32).to_bits() as u64 } else { value };
+            Scalar::Int(ApInt::from_u64(to_bits, value))
+        }
+        ("bitcast", value) => value,
+        ("fptrunc" | "fpext", Scalar::Float(_, value)) => Scalar::Float(to_bits, round(to_bits, f64::from_bits(value))),
+        ("fptosi" | "fptoui", Scalar::Float(_, value)) => {
+            let value = f64::from_bits(value).trunc();
+            // out of range is poison
+            let int = if opcode == "fptosi" {
+                let min = -(2f64.powi(to_bits as i32 - 1));
+                if !(value >= min && value < -min) || to_bits > 64 {
+                    return None;
+                }
+                ApInt::from_i64(to_bits, value as i64)
+            } else {
+                if !(value > -1.0 && value < 2f64.powi(to_bits as i32)) || to_bits > 64 {
+                    return None;
+                }
+                ApInt::from_u64(to_bits, value as u64)
+            };
+            Scalar::Int(int)
+        }
+        ("sitofp", Scalar::Int(value)) if value.bits() <= 64 => {
+            let value = value.to_i64();
+            Scalar::Float(to_bits, if to_bits == 32 { (value as f32 as f64).to_bits() } else { (value as f64).to_bits() })
+        }
+        ("uitofp", Scalar::Int(value)) => {
+            let value = value.to_u64()?;
+            Scalar::Float(to_bits, if to_bits == 32 { (value as f32 as f64).to_bits() } else { (value as f64).to_bits() })
+        }
+        _ => return None,
+    })
+}
+
+/// Rounds `value` to a float of `bits` bits, and returns the bits of the result widened to `f64`.
+fn round(bits: usize, value: f64) -> u64 {
+    if bits == 32 { (value as f32 as f64).to_bits() } else { value.to_bits() }
+}
+
+fn fold_binary(opcode: &str, a: Scalar, b: Scalar) -> Option<Scalar> {
+    Some(match (opcode, a, b) {
+        ("add", Scalar::Address(name, offset), Scalar::Int(value)) | ("add", Scalar::Int(value), Scalar::Address(name, offset)) => {
+            Scalar::Address(name, offset.wrapping_add(value.to_i64()))
+        }
+        ("sub", Scalar::Address(name, offset), Scalar::Int(value)) => Scalar::Address(name, offset.wrapping_sub(value.to_i64())),
+        (opcode, Scalar::Int(a), Scalar::Int(b)) => Scalar::Int(match opcode {
+            "add" => a.wrapping_add(&b),
+            "sub" => a.wrapping_sub(&b),
+            "mul" => a.wrapping_mul(&b),
+            "xor" => a.xor(&b),
+            // shifting by the width or more is poison
+            "shl" | "lshr" | "ashr" => {
+                let amount = b.to_u64().filter(|amount| *amount < a.bits() as u64)? as usize;
+                match opcode {
+                    "shl" => a.shl(amount),
+                    "lshr" => a.lshr(amount),
+                    _ => a.ashr(amount),
+                }
+            }
+            _ => return None,
+        }),
+        _ => return None,
+    })
+}
+
+impl Display for Constant {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Constant::Int(value) if value.bits() == 1 => write!(f, "{}", !value.is_zero()),
+            Constant::Int(value) => write!(f, "{}", value),
+            Constant::Float(_, value) => write!(f, "{:?}", f64::from_bits(*value)),
+            Constant::Null(_) => write!(f, "null"),
+            Constant::Undef(_) => write!(f, "undef"),
+            Constant::Poison(_) => write!(f, "poison"),
+            Constant::Zero(_) => write!(f, "zeroinitializer"),
+            Constant::Array(_, elements) => {
+                let elements = elements.iter().map(|element| element.to_string()).collect::<Vec<_>>().join(", ");
+                write!(f, "[{}]", elements)
+            }
+            Constant::Struct(fields) => {
+                let fields = fields.iter().map(|field| field.to_string()).collect::<Vec<_>>().join(", ");
+                write!(f, "{{ {} }}", fields)
+            }
+            Constant::Bytes(bytes) => {
+                write!(f, "c\"")?;
+                for byte in bytes {
+                    if matches!(byte, b' '..=b'~') && *byte != b'"' && *byte != b'\\' {
+                        write!(f, "{}", *byte as char)?;
+                    } else {
+                        write!(f, "\\{:02X}", byte)?;
+                    }
+                }
+                write!(f, "\"")
+            }
+            Constant::Symbol(_, name) => write!(f, "@{}", name),
+            Constant::Expr(expr) => write!(f, "{}", expr),
+        }
+    }
+}
+
+impl Display for ConstantExpr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConstantExpr::Cast(opcode, a, ty) => write!(f, "{} ({} {} to {})", opcode, a.get_type(), a, ty),
+            ConstantExpr::Binary(opcode, a, b) => write!(f, "{} ({} {}, {})", opcode, a.get_type(), a, b),
+        }
+    }
+}
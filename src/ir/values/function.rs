@@ -1,45 +1,71 @@
 use crate::impl_for_value;
 use crate::ir::values::value::Value;
 use crate::ir::values::basic_block::{BasicBlock};
+use crate::ir::values::argument::Argument;
 use crate::ir::linkage::Linkage;
+use crate::ir::calling_conv::CallingConv;
+use crate::error::Error;
 use crate::ir::values::value::Type;
 use std::fmt::{Display, Formatter};
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
-use std::ops::Deref;
 
 impl_for_value!(Function {
     blocks: Vec<Rc<RefCell<BasicBlock>>>,
-    params: Vec<Value>,
+    params: Vec<Argument>,
 
     is_var_arg: bool,
 
     linkage: Linkage,
+    calling_conv: CallingConv,
+    // shared with the blocks, which number instructions without borrowing the function
+    inst_count: Rc<Cell<usize>>,
 });
 
 impl Function {
     pub fn new(name: String, params: Vec<Value>, ret_ty: Type, is_var_arg: bool, linkage: Linkage) -> Self {
         let arg_types = params.iter().map(|param| param.get_type()).collect();
         let val = Value::new(Type::FunctionType(arg_types, Box::new(ret_ty)), name);
+        let params = params.into_iter().enumerate().map(|(index, param)| Argument::new(param.get_name(), param.get_type(), index)).collect();
         Self {
             value: val,
             blocks: vec![],
             params,
             is_var_arg,
             linkage,
+            calling_conv: CallingConv::C,
+            inst_count: Rc::new(Cell::new(0)),
         }
     }
 
     pub fn create(name: String, ty: Type, linkage: Linkage, is_varg: bool) -> Self {
+        Self::create_with_param_names(name, ty, &[], linkage, is_varg)
+    }
+
+    /// Creates a function with one argument per parameter of `ty`. Arguments
+    /// without a name in `param_names` are numbered like instructions.
+    pub fn create_with_param_names(name: String, ty: Type, param_names: &[Option<&str>], linkage: Linkage, is_varg: bool) -> Self {
         assert!(ty.is_function_type());
+        assert!(param_names.len() <= ty.get_function_argument_types().len(), "More parameter names than parameters");
+        let arg_types = ty.get_function_argument_types().clone();
         let val = Value::new(ty, name);
-        Self {
+        let mut function = Self {
             value: val,
             blocks: vec![],
             params: vec![],
             is_var_arg: is_varg,
-            linkage: linkage,
+            linkage,
+            calling_conv: CallingConv::C,
+            inst_count: Rc::new(Cell::new(0)),
+        };
+        for (index, arg_type) in arg_types.into_iter().enumerate() {
+            let name = match param_names.get(index).copied().flatten() {
+                Some(name) => format!("%{}", name),
+                None => format!("%{}", function.get_new_instruction_name()),
+            };
+            function.params.push(Argument::new(name, arg_type, index));
         }
+        function
     }
 
     pub fn is_external(&self) -> bool {
@@ -62,31 +88,36 @@ impl Function {
         self.blocks.iter_mut().find(|block| block.borrow().get_name() == name)
     }
 
-    pub fn add_block(&mut self, block: Rc<RefCell<BasicBlock>>) {
+    pub fn add_block(&mut self, block: Rc<RefCell<BasicBlock>>) -> Result<(), Error> {
         if self.linkage == Linkage::ExternalLinkage {
-            panic!("Cannot add block to external function");
+            return Err(Error::UnsupportedLinkage { name: self.get_name(), linkage: self.linkage.clone() });
         }
 
         self.blocks.push(block);
+        Ok(())
     }
 
     pub fn get_blocks(&self) -> &Vec<Rc<RefCell<BasicBlock>>> {
         &self.blocks
     }
 
-    pub fn get_param(&self, name: &str) -> Option<&Value> {
+    pub fn get_params(&self) -> &Vec<Argument> {
+        &self.params
+    }
+
+    pub fn get_param(&self, name: &str) -> Option<&Argument> {
         self.params.iter().find(|param| param.get_name() == name)
     }
 
-    pub fn get_param_mut(&mut self, name: &str) -> Option<&mut Value> {
+    pub fn get_param_mut(&mut self, name: &str) -> Option<&mut Argument> {
         self.params.iter_mut().find(|param| param.get_name() == name)
     }
 
-    pub fn get_param_by_index(&self, index: usize) -> Option<&Value> {
+    pub fn get_param_by_index(&self, index: usize) -> Option<&Argument> {
         self.params.get(index)
     }
 
-    pub fn get_param_by_index_mut(&mut self, index: usize) -> Option<&mut Value> {
+    pub fn get_param_by_index_mut(&mut self, index: usize) -> Option<&mut Argument> {
         self.params.get_mut(index)
     }
 
@@ -101,6 +132,25 @@ impl Function {
     pub fn get_linkage(&self) -> &Linkage {
         &self.linkage
     }
+
+    pub fn get_calling_conv(&self) -> CallingConv {
+        self.calling_conv
+    }
+
+    pub fn set_calling_conv(&mut self, calling_conv: CallingConv) {
+        self.calling_conv = calling_conv;
+    }
+
+    pub fn get_new_instruction_name(&self) -> String {
+        let count = self.inst_count.get();
+        self.inst_count.set(count + 1);
+        format!("{}", count)
+    }
+
+    /// Returns the counter the function's instructions are numbered from.
+    pub fn get_instruction_counter(&self) -> Rc<Cell<usize>> {
+        self.inst_count.clone()
+    }
 }
 
 impl PartialEq for Function {
@@ -111,30 +161,21 @@ impl PartialEq for Function {
 
 impl Display for Function {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let linkage = match self.linkage {
-            Linkage::ExternalLinkage => "external",
-            Linkage::InternalLinkage => "internal",
-            Linkage::PrivateLinkage => "private",
-            Linkage::ExternalWeakLinkage => "external weak",
-            Linkage::CommonLinkage => "common",
-            Linkage::AppendingLinkage => "appending",
-            Linkage::LinkonceLinkage => "linkonce",
-            Linkage::WeakLinkage => "weak",
-        };
-        let mut params = String::new();
-        for param in &self.params {
-            params.push_str(&format!("{}: {}, ", param.get_name(), param.get_type().to_string()));
-        }
+        let linkage = &self.linkage;
+        let mut params = self.params.iter().map(|param| param.to_string()).collect::<Vec<_>>();
         if self.is_var_arg {
-            if params.len() > 0 {
-                params.push_str(", ");
-            }
-            params.push_str("...");
+            params.push("...".to_string());
         }
+        let params = params.join(", ");
+        // the default convention is left out
+        let calling_conv = match self.calling_conv {
+            CallingConv::C => String::new(),
+            calling_conv => format!(" {}", calling_conv),
+        };
         if self.linkage == Linkage::ExternalLinkage {
-            return write!(f, "declare {} function @{}({}) -> {}\n", linkage, self.get_name(), params, self.get_function_return_type().to_string());
+            return writeln!(f, "declare {}{} function @{}({}) -> {}", linkage, calling_conv, self.get_name(), params, self.get_function_return_type());
         }
         let body = self.blocks.iter().map(|block| block.borrow().to_string()).collect::<Vec<String>>().join("\n");
-        write!(f, "define {} function @{}({}) -> {} {{\n{}}}\n", linkage, self.get_name(), params, self.get_function_return_type().to_string(), body)
+        writeln!(f, "define {}{} function @{}({}) -> {} {{\n{}}}", linkage, calling_conv, self.get_name(), params, self.get_function_return_type(), body)
     }
 }
@@ -0,0 +1,138 @@
+use crate::impl_for_value;
+use crate::ir::values::value::Value;
+use crate::ir::values::value::Type;
+use crate::ir::values::constant::Constant;
+use crate::ir::linkage::Linkage;
+use crate::error::Error;
+use std::fmt::{Display, Formatter};
+
+impl_for_value!(GlobalVariable {
+    value_type: Type,
+    initializer: Option<Constant>,
+    linkage: Linkage,
+    is_constant: bool,
+    align: Option<u64>,
+    section: Option<String>,
+    thread_local: bool,
+});
+
+impl GlobalVariable {
+    /// Creates a global holding a value of type `ty`. The global itself is a
+    /// pointer to that value.
+    pub fn new(name: String, ty: Type, initializer: Option<Constant>, linkage: Linkage, is_constant: bool) -> Self {
+        let value = Value::new(ty.get_pointer_to(), name);
+        Self {
+            value,
+            value_type: ty,
+            initializer,
+            linkage,
+            is_constant,
+            align: None,
+            section: None,
+            thread_local: false,
+        }
+    }
+
+    pub fn get_name(&self) -> String {
+        self.value.get_name()
+    }
+
+    /// Returns the type of the global, which is a pointer to its value type.
+    pub fn get_type(&self) -> Type {
+        self.value.get_type()
+    }
+
+    pub fn get_value_type(&self) -> &Type {
+        &self.value_type
+    }
+
+    pub fn get_initializer(&self) -> Option<&Constant> {
+        self.initializer.as_ref()
+    }
+
+    pub fn set_initializer(&mut self, initializer: Option<Constant>) {
+        self.initializer = initializer;
+    }
+
+    pub fn get_linkage(&self) -> &Linkage {
+        &self.linkage
+    }
+
+    pub fn set_linkage(&mut self, linkage: Linkage) {
+        self.linkage = linkage;
+    }
+
+    /// Returns whether the global is defined elsewhere.
+    pub fn is_external(&self) -> bool {
+        matches!(self.linkage, Linkage::ExternalLinkage | Linkage::ExternalWeakLinkage)
+    }
+
+    /// Returns whether the value of the global never changes.
+    pub fn is_constant(&self) -> bool {
+        self.is_constant
+    }
+
+    pub fn set_constant(&mut self, is_constant: bool) {
+        self.is_constant = is_constant;
+    }
+
+    /// Returns the alignment requested for the global, if any.
+    pub fn get_alignment(&self) -> Option<u64> {
+        self.align
+    }
+
+    pub fn set_alignment(&mut self, align: u64) -> Result<(), Error> {
+        if !align.is_power_of_two() {
+            return Err(Error::InvalidAlignment(align));
+        }
+        self.align = Some(align);
+        Ok(())
+    }
+
+    /// Returns the section the global is placed in, if not the default one.
+    pub fn get_section(&self) -> Option<&str> {
+        self.section.as_deref()
+    }
+
+    pub fn set_section(&mut self, section: &str) {
+        self.section = Some(section.to_string());
+    }
+
+    pub fn is_thread_local(&self) -> bool {
+        self.thread_local
+    }
+
+    pub fn set_thread_local(&mut self, thread_local: bool) {
+        self.thread_local = thread_local;
+    }
+
+    pub fn get_value(&self) -> &Value {
+        &self.value
+    }
+}
+
+impl PartialEq for GlobalVariable {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl Display for GlobalVariable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "@{} = {}", self.get_name(), self.linkage)?;
+        if self.thread_local {
+            write!(f, " thread_local")?;
+        }
+        write!(f, " {} {}", if self.is_constant { "constant" } else { "global" }, self.value_type)?;
+        if let Some(initializer) = &self.initializer {
+            write!(f, " {}", initializer)?;
+        }
+        if let Some(section) = &self.section {
+            write!(f, ", section \"{}\"", section)?;
+        }
+        if let Some(align) = self.align {
+            write!(f, ", align {}", align)?;
+        }
+        Ok(())
+    }
+}
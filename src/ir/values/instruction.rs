@@ -1,10 +1,12 @@
 use crate::impl_for_value;
 use crate::ir::values::value::Value;
 use crate::ir::values::value::Type;
-use std::string::ToString;
+use crate::ir::values::constant::Scalar;
+use std::fmt::{Display, Formatter};
 use crate::ir::values::value::ValueEntity;
 use crate::ir::values::basic_block::BasicBlock;
 use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::rc::Rc;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -12,21 +14,63 @@ pub enum InstructionType {
     Add(Box<ValueEntity>, Box<ValueEntity>),
     Sub(Box<ValueEntity>, Box<ValueEntity>),
     Mul(Box<ValueEntity>, Box<ValueEntity>),
-    Div(Box<ValueEntity>, Box<ValueEntity>),
-    Rem(Box<ValueEntity>, Box<ValueEntity>),
+    /// Division and remainder, reading the operands as signed or unsigned
+    /// integers. Floats are divided by `FDiv` and `FRem`.
+    SDiv(Box<ValueEntity>, Box<ValueEntity>),
+    UDiv(Box<ValueEntity>, Box<ValueEntity>),
+    SRem(Box<ValueEntity>, Box<ValueEntity>),
+    URem(Box<ValueEntity>, Box<ValueEntity>),
     Shl(Box<ValueEntity>, Box<ValueEntity>),
-    Shr(Box<ValueEntity>, Box<ValueEntity>),
+    /// Right shifts filling with the sign bit (arithmetic) or with zeros (logical).
+    AShr(Box<ValueEntity>, Box<ValueEntity>),
+    LShr(Box<ValueEntity>, Box<ValueEntity>),
     And(Box<ValueEntity>, Box<ValueEntity>),
     Or(Box<ValueEntity>, Box<ValueEntity>),
     Xor(Box<ValueEntity>, Box<ValueEntity>),
     Eq(Box<ValueEntity>, Box<ValueEntity>),
     Ne(Box<ValueEntity>, Box<ValueEntity>),
-    Lt(Box<ValueEntity>, Box<ValueEntity>),
-    Le(Box<ValueEntity>, Box<ValueEntity>),
-    Gt(Box<ValueEntity>, Box<ValueEntity>),
-    Ge(Box<ValueEntity>, Box<ValueEntity>),
+    /// Orderings of the operands read as signed integers. Floats are
+    /// compared by `FCmp`.
+    SLt(Box<ValueEntity>, Box<ValueEntity>),
+    SLe(Box<ValueEntity>, Box<ValueEntity>),
+    SGt(Box<ValueEntity>, Box<ValueEntity>),
+    SGe(Box<ValueEntity>, Box<ValueEntity>),
+    /// Orderings of the operands read as unsigned integers.
+    ULt(Box<ValueEntity>, Box<ValueEntity>),
+    ULe(Box<ValueEntity>, Box<ValueEntity>),
+    UGt(Box<ValueEntity>, Box<ValueEntity>),
+    UGe(Box<ValueEntity>, Box<ValueEntity>),
     Neg(Box<ValueEntity>),
     Not(Box<ValueEntity>),
+    /// Float arithmetic, with the assumptions it is allowed to make.
+    FAdd(Box<ValueEntity>, Box<ValueEntity>, FastMathFlags),
+    FSub(Box<ValueEntity>, Box<ValueEntity>, FastMathFlags),
+    FMul(Box<ValueEntity>, Box<ValueEntity>, FastMathFlags),
+    FDiv(Box<ValueEntity>, Box<ValueEntity>, FastMathFlags),
+    /// The remainder of a division truncated toward zero, like C's `fmod`.
+    FRem(Box<ValueEntity>, Box<ValueEntity>, FastMathFlags),
+    FNeg(Box<ValueEntity>, FastMathFlags),
+    FCmp(FloatPredicate, Box<ValueEntity>, Box<ValueEntity>, FastMathFlags),
+    /// Conversions of a value to the type of the instruction.
+    Trunc(Box<ValueEntity>),
+    ZExt(Box<ValueEntity>),
+    SExt(Box<ValueEntity>),
+    FPTrunc(Box<ValueEntity>),
+    FPExt(Box<ValueEntity>),
+    FPToSI(Box<ValueEntity>),
+    FPToUI(Box<ValueEntity>),
+    SIToFP(Box<ValueEntity>),
+    UIToFP(Box<ValueEntity>),
+    PtrToInt(Box<ValueEntity>),
+    IntToPtr(Box<ValueEntity>),
+    Bitcast(Box<ValueEntity>),
+    /// Reserves stack memory for `count` values of a type (one when absent), with the given alignment.
+    Alloca(Type, Option<Box<ValueEntity>>, u64),
+    /// The address of a value inside the memory a pointer points to, like
+    /// LLVM's `getelementptr`. The first index steps over whole values of the
+    /// type pointed to, and each further one selects an array element or a
+    /// struct field, which must be given by a constant.
+    GetElementPtr(Box<ValueEntity>, Vec<Box<ValueEntity>>),
     Load(Box<ValueEntity>),
     Store(Box<ValueEntity>, Box<ValueEntity>),
     Call(Box<ValueEntity>, Vec<Box<ValueEntity>>),
@@ -36,10 +80,289 @@ pub enum InstructionType {
     Phi(Vec<(Box<ValueEntity>, Box<ValueEntity>)>),
     VoidReturn,
     Unreachable,
+}
+
+/// Assumptions a float operation is allowed to make, as in LLVM. None of
+/// them change the result of an operation whose assumptions hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub struct FastMathFlags {
+    /// The operations may be reassociated.
+    pub reassoc: bool,
+    /// The operands and the result are not NaN.
+    pub nnan: bool,
+    /// The operands and the result are not infinite.
+    pub ninf: bool,
+    /// The sign of a zero does not matter.
+    pub nsz: bool,
+    /// A division may be replaced by a multiplication with the reciprocal.
+    pub arcp: bool,
+    /// The operation may be fused with others, like a multiply and an add.
+    pub contract: bool,
+    /// Functions may be approximated.
+    pub afn: bool,
+}
+
+impl FastMathFlags {
+    /// The names of the flags, as written in the textual IR. `fast` stands for all of them.
+    pub const NAMES: &'static [&'static str] = &["reassoc", "nnan", "ninf", "nsz", "arcp", "contract", "afn"];
+
+    /// Returns the flags with every assumption allowed.
+    pub fn fast() -> Self {
+        Self { reassoc: true, nnan: true, ninf: true, nsz: true, arcp: true, contract: true, afn: true }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Sets the flag called `name`, or all of them for `fast`. Returns
+    /// false if there is no flag of that name.
+    pub fn set(&mut self, name: &str) -> bool {
+        match name {
+            "fast" => *self = Self::fast(),
+            _ => match self.flags_mut().into_iter().zip(Self::NAMES).find(|(_, flag)| **flag == name) {
+                Some((flag, _)) => *flag = true,
+                None => return false,
+            },
+        }
+        true
+    }
+
+    fn flags(&self) -> [bool; 7] {
+        [self.reassoc, self.nnan, self.ninf, self.nsz, self.arcp, self.contract, self.afn]
+    }
+
+    fn flags_mut(&mut self) -> [&mut bool; 7] {
+        [&mut self.reassoc, &mut self.nnan, &mut self.ninf, &mut self.nsz, &mut self.arcp, &mut self.contract, &mut self.afn]
+    }
+}
+
+impl Display for FastMathFlags {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if *self == Self::fast() {
+            return write!(f, "fast");
+        }
+        let names = self.flags().into_iter().zip(Self::NAMES).filter(|(set, _)| *set).map(|(_, name)| *name).collect::<Vec<_>>();
+        write!(f, "{}", names.join(" "))
+    }
+}
+
+/// What `fcmp` checks. Ordered predicates are false when either operand is
+/// NaN, and unordered ones are true.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FloatPredicate {
+    Oeq,
+    Ogt,
+    Oge,
+    Olt,
+    Ole,
+    /// Ordered and not equal.
+    One,
+    /// Neither operand is NaN.
+    Ord,
+    Ueq,
+    Ugt,
+    Uge,
+    Ult,
+    Ule,
+    Une,
+    /// Either operand is NaN.
+    Uno,
+}
+
+impl FloatPredicate {
+    pub const ALL: &'static [FloatPredicate] = &[
+        FloatPredicate::Oeq, FloatPredicate::Ogt, FloatPredicate::Oge, FloatPredicate::Olt, FloatPredicate::Ole, FloatPredicate::One,
+        FloatPredicate::Ord, FloatPredicate::Ueq, FloatPredicate::Ugt, FloatPredicate::Uge, FloatPredicate::Ult, FloatPredicate::Ule,
+        FloatPredicate::Une, FloatPredicate::Uno,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FloatPredicate::Oeq => "oeq",
+            FloatPredicate::Ogt => "ogt",
+            FloatPredicate::Oge => "oge",
+            FloatPredicate::Olt => "olt",
+            FloatPredicate::Ole => "ole",
+            FloatPredicate::One => "one",
+            FloatPredicate::Ord => "ord",
+            FloatPredicate::Ueq => "ueq",
+            FloatPredicate::Ugt => "ugt",
+            FloatPredicate::Uge => "uge",
+            FloatPredicate::Ult => "ult",
+            FloatPredicate::Ule => "ule",
+            FloatPredicate::Une => "une",
+            FloatPredicate::Uno => "uno",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|predicate| predicate.name() == name)
+    }
+
+    /// Returns whether the predicate holds for operands ordered as
+    /// `ordering`, where `None` means unordered.
+    pub fn holds(self, ordering: Option<Ordering>) -> bool {
+        let Some(ordering) = ordering else {
+            return !self.is_ordered();
+        };
+        match self {
+            FloatPredicate::Oeq | FloatPredicate::Ueq => ordering == Ordering::Equal,
+            FloatPredicate::Ogt | FloatPredicate::Ugt => ordering == Ordering::Greater,
+            FloatPredicate::Oge | FloatPredicate::Uge => ordering != Ordering::Less,
+            FloatPredicate::Olt | FloatPredicate::Ult => ordering == Ordering::Less,
+            FloatPredicate::Ole | FloatPredicate::Ule => ordering != Ordering::Greater,
+            FloatPredicate::One | FloatPredicate::Une => ordering != Ordering::Equal,
+            FloatPredicate::Ord => true,
+            FloatPredicate::Uno => false,
+        }
+    }
+
+    /// Returns whether the predicate is false for unordered operands.
+    pub fn is_ordered(self) -> bool {
+        matches!(self, FloatPredicate::Oeq | FloatPredicate::Ogt | FloatPredicate::Oge | FloatPredicate::Olt
+            | FloatPredicate::Ole | FloatPredicate::One | FloatPredicate::Ord)
+    }
+
+    /// Returns the predicate that holds exactly when this one does not.
+    pub fn inverse(self) -> Self {
+        match self {
+            FloatPredicate::Oeq => FloatPredicate::Une,
+            FloatPredicate::Ogt => FloatPredicate::Ule,
+            FloatPredicate::Oge => FloatPredicate::Ult,
+            FloatPredicate::Olt => FloatPredicate::Uge,
+            FloatPredicate::Ole => FloatPredicate::Ugt,
+            FloatPredicate::One => FloatPredicate::Ueq,
+            FloatPredicate::Ord => FloatPredicate::Uno,
+            FloatPredicate::Ueq => FloatPredicate::One,
+            FloatPredicate::Ugt => FloatPredicate::Ole,
+            FloatPredicate::Uge => FloatPredicate::Olt,
+            FloatPredicate::Ult => FloatPredicate::Oge,
+            FloatPredicate::Ule => FloatPredicate::Ogt,
+            FloatPredicate::Une => FloatPredicate::Oeq,
+            FloatPredicate::Uno => FloatPredicate::Ord,
+        }
+    }
+}
 
-    ConstantInt32(i32),
-    ConstantInt64(i64),
-    ConstantBool(bool),
+impl Display for FloatPredicate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Returns `opcode` followed by the fast-math flags that are set, if any.
+fn with_flags(opcode: &str, flags: &FastMathFlags) -> String {
+    if flags.is_empty() {
+        opcode.to_string()
+    } else {
+        format!("{} {}", opcode, flags)
+    }
+}
+
+/// The opcodes of the conversions, as written in the textual IR.
+pub const CAST_OPCODES: &[&str] = &[
+    "trunc", "zext", "sext", "fptrunc", "fpext", "fptosi", "fptoui", "sitofp", "uitofp", "ptrtoint", "inttoptr", "bitcast",
+];
+
+impl InstructionType {
+    /// Returns the conversion `opcode` names applied to `value`.
+    pub fn cast(opcode: &str, value: Box<ValueEntity>) -> Option<Self> {
+        Some(match opcode {
+            "trunc" => InstructionType::Trunc(value),
+            "zext" => InstructionType::ZExt(value),
+            "sext" => InstructionType::SExt(value),
+            "fptrunc" => InstructionType::FPTrunc(value),
+            "fpext" => InstructionType::FPExt(value),
+            "fptosi" => InstructionType::FPToSI(value),
+            "fptoui" => InstructionType::FPToUI(value),
+            "sitofp" => InstructionType::SIToFP(value),
+            "uitofp" => InstructionType::UIToFP(value),
+            "ptrtoint" => InstructionType::PtrToInt(value),
+            "inttoptr" => InstructionType::IntToPtr(value),
+            "bitcast" => InstructionType::Bitcast(value),
+            _ => return None,
+        })
+    }
+
+    /// Returns the opcode of a conversion and the value it converts.
+    pub fn as_cast(&self) -> Option<(&'static str, &ValueEntity)> {
+        Some(match self {
+            InstructionType::Trunc(a) => ("trunc", a),
+            InstructionType::ZExt(a) => ("zext", a),
+            InstructionType::SExt(a) => ("sext", a),
+            InstructionType::FPTrunc(a) => ("fptrunc", a),
+            InstructionType::FPExt(a) => ("fpext", a),
+            InstructionType::FPToSI(a) => ("fptosi", a),
+            InstructionType::FPToUI(a) => ("fptoui", a),
+            InstructionType::SIToFP(a) => ("sitofp", a),
+            InstructionType::UIToFP(a) => ("uitofp", a),
+            InstructionType::PtrToInt(a) => ("ptrtoint", a),
+            InstructionType::IntToPtr(a) => ("inttoptr", a),
+            InstructionType::Bitcast(a) => ("bitcast", a),
+            _ => return None,
+        })
+    }
+}
+
+/// Returns whether the conversion `opcode` can turn a value of type `from`
+/// into one of type `to`. Integers and floats only change width in the
+/// direction the opcode says, and `bitcast` keeps the number of bits, so it
+/// turns pointers only into other pointers.
+pub fn is_valid_cast(opcode: &str, from: &Type, to: &Type) -> bool {
+    let is_pointer = |ty: &Type| ty.is_pointer() || ty.is_function_type();
+    match (opcode, from, to) {
+        ("trunc", Type::Integer(from), Type::Integer(to)) | ("fptrunc", Type::Float(from), Type::Float(to)) => to < from,
+        ("zext" | "sext", Type::Integer(from), Type::Integer(to)) | ("fpext", Type::Float(from), Type::Float(to)) => to > from,
+        ("fptosi" | "fptoui", Type::Float(_), Type::Integer(_)) | ("sitofp" | "uitofp", Type::Integer(_), Type::Float(_)) => true,
+        ("ptrtoint", from, Type::Integer(_)) => is_pointer(from),
+        ("inttoptr", Type::Integer(_), to) => to.is_pointer(),
+        ("bitcast", Type::Integer(from) | Type::Float(from), Type::Integer(to) | Type::Float(to)) => from == to,
+        ("bitcast", from, to) => is_pointer(from) && to.is_pointer(),
+        _ => false,
+    }
+}
+
+/// Returns the field a constant struct index selects, if it is one.
+pub fn get_field_index(index: &ValueEntity) -> Option<usize> {
+    match index {
+        ValueEntity::Constant(constant) => match constant.fold() {
+            Some(Scalar::Int(value)) => value.to_u64().and_then(|value| usize::try_from(value).ok()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns the type of the value `indices` select in memory holding a `ty`,
+/// as a `getelementptr` through a pointer to `ty` does, or why they can't.
+pub fn get_indexed_type(ty: &Type, indices: &[Box<ValueEntity>]) -> Result<Type, String> {
+    if indices.is_empty() {
+        return Err("getelementptr needs at least one index".to_string());
+    }
+    if ty.is_void() || ty.is_branch() || ty.is_function_type() {
+        return Err(format!("values of type {} have no size to step over", ty));
+    }
+    let mut ty = ty.clone();
+    for (position, index) in indices.iter().enumerate() {
+        if !index.get_type().is_integer() {
+            return Err(format!("index {} has type {} but an integer was expected", index.get_as_ref(), index.get_type()));
+        }
+        // the first index steps over whole values, and keeps the type
+        if position == 0 {
+            continue;
+        }
+        ty = match ty {
+            Type::Array(_, element) => *element,
+            Type::Struct(fields) => match get_field_index(index) {
+                Some(field) if field < fields.len() => fields[field].clone(),
+                Some(_) => return Err(format!("{} has no field {}", Type::Struct(fields), index.get_as_ref())),
+                None => return Err(format!("fields of {} must be selected by an integer constant, not {}", Type::Struct(fields), index.get_as_ref())),
+            },
+            ty => return Err(format!("{} cannot be indexed into", ty)),
+        };
+    }
+    Ok(ty)
 }
 
 impl_for_value!(Instruction {
@@ -60,19 +383,53 @@ impl Instruction {
     }
 
     pub fn get_name(&self) -> String {
-        // constant instructions don't have names
-        // we return it's values
-        match &self.instruction_type {
-            InstructionType::ConstantInt32(a) => return a.to_string(),
-            InstructionType::ConstantInt64(a) => return a.to_string(),
-            InstructionType::ConstantBool(a) =>  return a.to_string(),
-            _ => return self.value.get_name()
-        }
+        self.value.get_name()
     }
 
     pub fn instruction_type(&self) -> &InstructionType {
         &self.instruction_type
     }
+
+    /// Returns whether the instruction ends a basic block.
+    pub fn is_terminator(&self) -> bool {
+        matches!(self.instruction_type, InstructionType::Return(_) | InstructionType::VoidReturn | InstructionType::Branch(_)
+            | InstructionType::BranchIf(_, _, _) | InstructionType::Unreachable)
+    }
+
+    /// Returns the values the instruction reads. Branch targets and the
+    /// incoming blocks of a phi are not included.
+    pub fn get_operands(&self) -> Vec<&ValueEntity> {
+        match &self.instruction_type {
+            InstructionType::Add(a, b) | InstructionType::Sub(a, b) | InstructionType::Mul(a, b) | InstructionType::SDiv(a, b)
+            | InstructionType::UDiv(a, b) | InstructionType::SRem(a, b) | InstructionType::URem(a, b) | InstructionType::Shl(a, b)
+            | InstructionType::AShr(a, b) | InstructionType::LShr(a, b) | InstructionType::And(a, b) | InstructionType::Or(a, b)
+            | InstructionType::Xor(a, b) | InstructionType::Eq(a, b) | InstructionType::Ne(a, b) | InstructionType::SLt(a, b)
+            | InstructionType::SLe(a, b) | InstructionType::SGt(a, b) | InstructionType::SGe(a, b) | InstructionType::ULt(a, b)
+            | InstructionType::ULe(a, b) | InstructionType::UGt(a, b) | InstructionType::UGe(a, b)
+            | InstructionType::FAdd(a, b, _) | InstructionType::FSub(a, b, _) | InstructionType::FMul(a, b, _)
+            | InstructionType::FDiv(a, b, _) | InstructionType::FRem(a, b, _) | InstructionType::FCmp(_, a, b, _)
+            | InstructionType::Store(a, b) => vec![a, b],
+            InstructionType::Neg(a) | InstructionType::Not(a) | InstructionType::FNeg(a, _) | InstructionType::Load(a) | InstructionType::Return(a)
+            | InstructionType::BranchIf(a, _, _) | InstructionType::Trunc(a) | InstructionType::ZExt(a) | InstructionType::SExt(a)
+            | InstructionType::FPTrunc(a) | InstructionType::FPExt(a) | InstructionType::FPToSI(a) | InstructionType::FPToUI(a)
+            | InstructionType::SIToFP(a) | InstructionType::UIToFP(a) | InstructionType::PtrToInt(a) | InstructionType::IntToPtr(a)
+            | InstructionType::Bitcast(a) => vec![a],
+            InstructionType::Alloca(_, count, _) => count.iter().map(|count| count.as_ref()).collect(),
+            InstructionType::GetElementPtr(ptr, indices) => std::iter::once(ptr.as_ref()).chain(indices.iter().map(|index| index.as_ref())).collect(),
+            InstructionType::Call(callee, args) => std::iter::once(callee.as_ref()).chain(args.iter().map(|arg| arg.as_ref())).collect(),
+            InstructionType::Phi(incoming) => incoming.iter().map(|(value, _)| value.as_ref()).collect(),
+            InstructionType::Branch(_) | InstructionType::VoidReturn | InstructionType::Unreachable => vec![],
+        }
+    }
+
+    /// Returns the names of the blocks a terminator may transfer control to.
+    pub fn get_successors(&self) -> Vec<String> {
+        match &self.instruction_type {
+            InstructionType::Branch(target) => vec![target.get_name()],
+            InstructionType::BranchIf(_, if_true, if_false) => vec![if_true.borrow().get_name(), if_false.borrow().get_name()],
+            _ => vec![],
+        }
+    }
 }
 
 impl PartialEq for Instruction {
@@ -81,52 +438,82 @@ impl PartialEq for Instruction {
     }
 }
 
-impl ToString for Instruction {
-    fn to_string(&self) -> String {
+impl Display for Instruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match &self.instruction_type {
-            InstructionType::Add(a, b) => format!("{} = add {} {}, {}", self.value.to_string(), a.get_type().to_string(), a.get_as_ref(), b.get_as_ref()),
-            InstructionType::Sub(a, b) => format!("{} = sub {} {}, {}", self.value.to_string(), a.get_type().to_string(), a.get_as_ref(), b.get_as_ref()),
-            InstructionType::Mul(a, b) => format!("{} = mul {} {}, {}", self.value.to_string(), a.get_type().to_string(), a.get_as_ref(), b.get_as_ref()),
-            InstructionType::Div(a, b) => format!("{} = div {} {}, {}", self.value.to_string(), a.get_type().to_string(), a.get_as_ref(), b.get_as_ref()),
-            InstructionType::Rem(a, b) => format!("{} = rem {} {}, {}", self.value.to_string(), a.get_type().to_string(), a.get_as_ref(), b.get_as_ref()),
-            InstructionType::Shl(a, b) => format!("{} = shl {} {}, {}", self.value.to_string(), a.get_type().to_string(), a.get_as_ref(), b.get_as_ref()),
-            InstructionType::Shr(a, b) => format!("{} = shr {} {}, {}", self.value.to_string(), a.get_type().to_string(), a.get_as_ref(), b.get_as_ref()),
-            InstructionType::And(a, b) => format!("{} = and {} {}, {}", self.value.to_string(), a.get_type().to_string(), a.get_as_ref(), b.get_as_ref()),
-            InstructionType::Or(a, b) => format!("{} = or {} {}, {}", self.value.to_string(), a.get_type().to_string(), a.get_as_ref(), b.get_as_ref()),
-            InstructionType::Xor(a, b) => format!("{} = xor {} {}, {}", self.value.to_string(), a.get_type().to_string(), a.get_as_ref(), b.get_as_ref()),
-            InstructionType::Eq(a, b) => format!("{} = eq {} {}, {}", self.value.to_string(), a.get_type().to_string(), a.get_as_ref(), b.get_as_ref()),
-            InstructionType::Ne(a, b) => format!("{} = ne {} {}, {}", self.value.to_string(), a.get_type().to_string(), a.get_as_ref(), b.get_as_ref()),
-            InstructionType::Lt(a, b) => format!("{} = lt {} {}, {}", self.value.to_string(), a.get_type().to_string(), a.get_as_ref(), b.get_as_ref()),
-            InstructionType::Le(a, b) => format!("{} = le {} {}, {}", self.value.to_string(), a.get_type().to_string(), a.get_as_ref(), b.get_as_ref()),
-            InstructionType::Gt(a, b) => format!("{} = gt {} {}, {}", self.value.to_string(), a.get_type().to_string(), a.get_as_ref(), b.get_as_ref()),
-            InstructionType::Ge(a, b) => format!("{} = ge {} {}, {}", self.value.to_string(), a.get_type().to_string(), a.get_as_ref(), b.get_as_ref()),
-            InstructionType::Neg(a) => format!("{} = neg {} {}", self.value.to_string(), a.get_type().to_string(), a.get_as_ref()),
-            InstructionType::Not(a) => format!("{} = not {} {}", self.value.to_string(), a.get_type().to_string(), a.get_as_ref()),
-            InstructionType::Load(a) => format!("{} = load {} {}", self.value.to_string(), a.get_type().to_string(), a.get_as_ref()),
-            InstructionType::Store(a, b) => format!("store {} {}, {}", a.get_type().to_string(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::Add(a, b) => write!(f, "{} = add {} {}, {}", self.value, a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::Sub(a, b) => write!(f, "{} = sub {} {}, {}", self.value, a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::Mul(a, b) => write!(f, "{} = mul {} {}, {}", self.value, a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::SDiv(a, b) => write!(f, "{} = sdiv {} {}, {}", self.value, a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::UDiv(a, b) => write!(f, "{} = udiv {} {}, {}", self.value, a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::SRem(a, b) => write!(f, "{} = srem {} {}, {}", self.value, a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::URem(a, b) => write!(f, "{} = urem {} {}, {}", self.value, a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::Shl(a, b) => write!(f, "{} = shl {} {}, {}", self.value, a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::AShr(a, b) => write!(f, "{} = ashr {} {}, {}", self.value, a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::LShr(a, b) => write!(f, "{} = lshr {} {}, {}", self.value, a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::And(a, b) => write!(f, "{} = and {} {}, {}", self.value, a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::Or(a, b) => write!(f, "{} = or {} {}, {}", self.value, a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::Xor(a, b) => write!(f, "{} = xor {} {}, {}", self.value, a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::Eq(a, b) => write!(f, "{} = eq {} {}, {}", self.value, a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::Ne(a, b) => write!(f, "{} = ne {} {}, {}", self.value, a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::SLt(a, b) => write!(f, "{} = slt {} {}, {}", self.value, a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::SLe(a, b) => write!(f, "{} = sle {} {}, {}", self.value, a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::SGt(a, b) => write!(f, "{} = sgt {} {}, {}", self.value, a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::SGe(a, b) => write!(f, "{} = sge {} {}, {}", self.value, a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::ULt(a, b) => write!(f, "{} = ult {} {}, {}", self.value, a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::ULe(a, b) => write!(f, "{} = ule {} {}, {}", self.value, a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::UGt(a, b) => write!(f, "{} = ugt {} {}, {}", self.value, a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::UGe(a, b) => write!(f, "{} = uge {} {}, {}", self.value, a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::Neg(a) => write!(f, "{} = neg {} {}", self.value, a.get_type(), a.get_as_ref()),
+            InstructionType::Not(a) => write!(f, "{} = not {} {}", self.value, a.get_type(), a.get_as_ref()),
+            InstructionType::FAdd(a, b, flags) => write!(f, "{} = {} {} {}, {}", self.value, with_flags("fadd", flags), a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::FSub(a, b, flags) => write!(f, "{} = {} {} {}, {}", self.value, with_flags("fsub", flags), a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::FMul(a, b, flags) => write!(f, "{} = {} {} {}, {}", self.value, with_flags("fmul", flags), a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::FDiv(a, b, flags) => write!(f, "{} = {} {} {}, {}", self.value, with_flags("fdiv", flags), a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::FRem(a, b, flags) => write!(f, "{} = {} {} {}, {}", self.value, with_flags("frem", flags), a.get_type(), a.get_as_ref(), b.get_as_ref()),
+            InstructionType::FNeg(a, flags) => write!(f, "{} = {} {} {}", self.value, with_flags("fneg", flags), a.get_type(), a.get_as_ref()),
+            InstructionType::FCmp(predicate, a, b, flags) => {
+                write!(f, "{} = {} {} {} {}, {}", self.value, with_flags("fcmp", flags), predicate, a.get_type(), a.get_as_ref(), b.get_as_ref())
+            }
+            InstructionType::Trunc(a) | InstructionType::ZExt(a) | InstructionType::SExt(a) | InstructionType::FPTrunc(a)
+            | InstructionType::FPExt(a) | InstructionType::FPToSI(a) | InstructionType::FPToUI(a) | InstructionType::SIToFP(a)
+            | InstructionType::UIToFP(a) | InstructionType::PtrToInt(a) | InstructionType::IntToPtr(a) | InstructionType::Bitcast(a) => {
+                let (opcode, _) = self.instruction_type.as_cast().unwrap();
+                write!(f, "{} = {} {} {} to {}", self.value, opcode, a.get_type(), a.get_as_ref(), self.get_type())
+            }
+            InstructionType::Alloca(ty, count, align) => match count {
+                Some(count) => write!(f, "{} = alloca {}, {} {}, align {}", self.value, ty, count.get_type(), count.get_as_ref(), align),
+                None => write!(f, "{} = alloca {}, align {}", self.value, ty, align),
+            },
+            InstructionType::GetElementPtr(ptr, indices) => {
+                write!(f, "{} = getelementptr {} {}", self.value, ptr.get_type(), ptr.get_as_ref())?;
+                for index in indices {
+                    write!(f, ", {} {}", index.get_type(), index.get_as_ref())?;
+                }
+                Ok(())
+            }
+            InstructionType::Load(a) => write!(f, "{} = load {} {}", self.value, a.get_type(), a.get_as_ref()),
+            InstructionType::Store(a, b) => write!(f, "store {} {}, {}", a.get_type(), a.get_as_ref(), b.get_as_ref()),
             InstructionType::Call(a, b) => {
                 let mut args = String::new();
                 for arg in b {
                     args.push_str(&format!("{}, ", arg.get_as_ref()));
                 }
-                format!("{} = call {} {}({})", self.value.to_string(), a.get_type().to_string(), a.get_as_ref(), args)
+                write!(f, "{} = call {} {}({})", self.value, a.get_type(), a.get_as_ref(), args)
             },
-            InstructionType::Return(a) => format!("return {} {}", a.get_type().to_string(), a.get_as_ref()),
-            InstructionType::Branch(a) => format!("branch {}", a.get_as_ref()),
-            InstructionType::BranchIf(a, b, c) => format!("branch {}, {}, {}", a.get_as_ref(), b.borrow().get_name(), c.borrow().get_name()),
+            InstructionType::Return(a) => write!(f, "return {} {}", a.get_type(), a.get_as_ref()),
+            InstructionType::Branch(a) => write!(f, "branch {}", a.get_as_ref()),
+            InstructionType::BranchIf(a, b, c) => write!(f, "branch {}, {}, {}", a.get_as_ref(), b.borrow().get_name(), c.borrow().get_name()),
             InstructionType::Phi(a) => {
                 let mut args = String::new();
                 for arg in a {
-                    args.push_str(&format!("{}, ", arg.0.to_string()));
+                    args.push_str(&format!("{}, ", arg.0.get_as_ref()));
                     args.push_str(&format!("{}, ", arg.1.get_as_ref()));
                 }
-                format!("{} = phi {} {}", self.value.to_string(), a[0].0.get_type().to_string(), args)
+                write!(f, "{} = phi {} {}", self.value, a[0].0.get_type(), args)
             },
-            InstructionType::Unreachable => format!("unreachable"),
-            InstructionType::VoidReturn => format!("return void"),
-            InstructionType::ConstantInt32(a) => format!("{}", a),
-            InstructionType::ConstantInt64(a) => format!("{}", a),
-            InstructionType::ConstantBool(a) => format!("{}", a),
+            InstructionType::Unreachable => write!(f, "unreachable"),
+            InstructionType::VoidReturn => write!(f, "return void"),
         }
     }
 }
@@ -3,3 +3,6 @@ pub mod value;
 pub mod function;
 pub mod basic_block;
 pub mod instruction;
+pub mod global;
+pub mod argument;
+pub mod constant;
@@ -1,8 +1,11 @@
 use crate::ir::values::function::Function;
 use crate::ir::values::basic_block::BasicBlock;
 use crate::ir::values::instruction::Instruction;
+use crate::ir::values::global::GlobalVariable;
+use crate::ir::values::argument::Argument;
+use crate::ir::values::constant::Constant;
 
-use std::string::ToString;
+use std::fmt::{Display, Formatter};
 
 #[derive(Debug, Clone, Eq)]
 pub struct Value {
@@ -16,6 +19,9 @@ pub enum ValueEntity {
     Function(Function),
     BasicBlock(BasicBlock),
     Instruction(Instruction),
+    GlobalVariable(GlobalVariable),
+    Argument(Argument),
+    Constant(Constant),
 }
 
 #[derive(Debug, Clone, Eq)]
@@ -48,6 +54,24 @@ impl From<Instruction> for ValueEntity {
     }
 }
 
+impl From<GlobalVariable> for ValueEntity {
+    fn from(global: GlobalVariable) -> Self {
+        Self::GlobalVariable(global)
+    }
+}
+
+impl From<Argument> for ValueEntity {
+    fn from(argument: Argument) -> Self {
+        Self::Argument(argument)
+    }
+}
+
+impl From<Constant> for ValueEntity {
+    fn from(constant: Constant) -> Self {
+        Self::Constant(constant)
+    }
+}
+
 impl Value {
     pub fn new(ty: Type, name: String) -> Self {
         Self {
@@ -76,59 +100,35 @@ impl Value {
 
 impl Type {
     pub fn is_integer(&self) -> bool {
-        match self {
-            Type::Integer(_) => true,
-            _ => false,
-        }
+        matches!(self, Type::Integer(_))
     }
 
     pub fn is_float(&self) -> bool {
-        match self {
-            Type::Float(_) => true,
-            _ => false,
-        }
+        matches!(self, Type::Float(_))
     }
 
     pub fn is_function(&self) -> bool {
-        match self {
-            Type::FunctionType(_, _) => true,
-            _ => false,
-        }
+        matches!(self, Type::FunctionType(_, _))
     }
 
     pub fn is_pointer(&self) -> bool {
-        match self {
-            Type::Pointer(_) => true,
-            _ => false,
-        }
+        matches!(self, Type::Pointer(_))
     }
 
     pub fn is_array(&self) -> bool {
-        match self {
-            Type::Array(_, _) => true,
-            _ => false,
-        }
+        matches!(self, Type::Array(_, _))
     }
 
     pub fn is_struct(&self) -> bool {
-        match self {
-            Type::Struct(_) => true,
-            _ => false,
-        }
+        matches!(self, Type::Struct(_))
     }
 
     pub fn is_void(&self) -> bool {
-        match self {
-            Type::Void => true,
-            _ => false,
-        }
+        matches!(self, Type::Void)
     }
 
     pub fn is_branch(&self) -> bool {
-        match self {
-            Type::Branch => true,
-            _ => false,
-        }
+        matches!(self, Type::Branch)
     }
 
     pub fn get_pointer_element_type(&self) -> Type {
@@ -164,10 +164,7 @@ impl Type {
     }
 
     pub fn is_function_type(&self) -> bool {
-        match self {
-            Type::FunctionType(_, _) => true,
-            _ => false,
-        }
+        matches!(self, Type::FunctionType(_, _))
     }
 }
 
@@ -183,39 +180,44 @@ macro_rules! impl_for_value {
     }
 }
 
-impl ToString for Value {
-    fn to_string(&self) -> String {
-        self.name.clone()
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
     }
 }
 
-impl ToString for Type {
-    fn to_string(&self) -> String {
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            Type::Integer(size) => format!("i{}", size),
-            Type::Float(size) => format!("f{}", size),
+            Type::Integer(size) => write!(f, "i{}", size),
+            Type::Float(size) => write!(f, "f{}", size),
             Type::FunctionType(args, ret) => {
                 let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().join(", ");
-                format!("{} -> {}", args, ret.to_string())
+                write!(f, "{} -> {}", args, ret)
             }
-            Type::Pointer(ty) => format!("{}*", ty.to_string()),
-            Type::Array(len, ty) => format!("[{} x {}]", len, ty.to_string()),
+            // parenthesized, so that `(i32 -> i32)*` isn't read as a function returning `i32*`
+            Type::Pointer(ty) if ty.is_function_type() => write!(f, "({})*", ty),
+            Type::Pointer(ty) => write!(f, "{}*", ty),
+            Type::Array(len, ty) => write!(f, "[{} x {}]", len, ty),
             Type::Struct(tys) => {
                 let tys = tys.iter().map(|ty| ty.to_string()).collect::<Vec<_>>().join(", ");
-                format!("{{ {} }}", tys)
+                write!(f, "{{ {} }}", tys)
             }
-            Type::Void => "void".to_string(),
-            Type::Branch => "branch".to_string(),
+            Type::Void => write!(f, "void"),
+            Type::Branch => write!(f, "branch"),
         }
     }
 }
 
-impl ToString for ValueEntity {
-    fn to_string(&self) -> String {
+impl Display for ValueEntity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            ValueEntity::Function(function) => function.to_string(),
-            ValueEntity::BasicBlock(basic_block) => basic_block.to_string(),
-            ValueEntity::Instruction(instruction) => instruction.to_string(),
+            ValueEntity::Function(function) => write!(f, "{}", function),
+            ValueEntity::BasicBlock(basic_block) => write!(f, "{}", basic_block),
+            ValueEntity::Instruction(instruction) => write!(f, "{}", instruction),
+            ValueEntity::GlobalVariable(global) => write!(f, "{}", global),
+            ValueEntity::Argument(argument) => write!(f, "{}", argument),
+            ValueEntity::Constant(constant) => write!(f, "{}", constant),
         }
     }
 }
@@ -226,6 +228,9 @@ impl ValueEntity {
             ValueEntity::Function(function) => function.get_type(),
             ValueEntity::BasicBlock(basic_block) => basic_block.get_type(),
             ValueEntity::Instruction(instruction) => instruction.get_type(),
+            ValueEntity::GlobalVariable(global) => global.get_type(),
+            ValueEntity::Argument(argument) => argument.get_type(),
+            ValueEntity::Constant(constant) => constant.get_type(),
         }
     }
 
@@ -234,11 +239,24 @@ impl ValueEntity {
             ValueEntity::Function(function) => function.get_name(),
             ValueEntity::BasicBlock(basic_block) => basic_block.get_name(),
             ValueEntity::Instruction(instruction) => instruction.get_name(),
+            ValueEntity::GlobalVariable(global) => global.get_name(),
+            ValueEntity::Argument(argument) => argument.get_name(),
+            // constants have no name, so they go by their value
+            ValueEntity::Constant(constant) => constant.to_string(),
         }
     }
 
+    /// Returns whether the value is a constant, which has no name and lives in no block.
+    pub fn is_constant(&self) -> bool {
+        matches!(self, ValueEntity::Constant(_))
+    }
+
     pub fn get_as_ref(&self) -> String {
-        self.get_name().to_string()
+        match self {
+            ValueEntity::Function(function) => format!("@{}", function.get_name()),
+            ValueEntity::GlobalVariable(global) => format!("@{}", global.get_name()),
+            _ => self.get_name().to_string(),
+        }
     }
 }
 
@@ -246,7 +264,7 @@ impl ValueEntity {
 
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
-        todo!()
+        self.ty == other.ty && self.name == other.name
     }
 }
 
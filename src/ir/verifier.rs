@@ -0,0 +1,719 @@
+use crate::ir::calling_conv::CallingConv;
+use crate::ir::module::Module;
+use crate::ir::values::constant::{Constant, ConstantExpr, CONSTANT_BINARY_OPCODES};
+use crate::ir::values::function::Function;
+use crate::ir::values::global::GlobalVariable;
+use crate::ir::values::instruction::{get_indexed_type, is_valid_cast, Instruction, InstructionType};
+use crate::ir::values::value::{Type, ValueEntity};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+/// A problem the verifier found in a function or in the initializer of a global.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The function or global the problem is in.
+    pub symbol: String,
+    pub is_global: bool,
+    /// The block the problem is in, if it is about a single block.
+    pub block: Option<String>,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let kind = if self.is_global { "global" } else { "function" };
+        match &self.block {
+            Some(block) => write!(f, "in {} @{}, block {}: {}", kind, self.symbol, block, self.message),
+            None => write!(f, "in {} @{}: {}", kind, self.symbol, self.message),
+        }
+    }
+}
+
+/// Checks every global and function of `module`, returning the problems found.
+pub fn verify_module(module: &Module) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for global in module.get_global_variables() {
+        let global = global.borrow();
+        for message in verify_initializer(module, &global) {
+            diagnostics.push(Diagnostic { symbol: global.get_name(), is_global: true, block: None, message });
+        }
+    }
+    diagnostics.extend(module.get_functions().iter().flat_map(|function| verify_function(&function.borrow())));
+    diagnostics
+}
+
+/// Checks that the initializer of `global` is a well-formed constant of its
+/// type whose addresses are all known when the module is linked.
+fn verify_initializer(module: &Module, global: &GlobalVariable) -> Vec<String> {
+    let Some(initializer) = global.get_initializer() else {
+        return Vec::new();
+    };
+    let mut problems = Vec::new();
+    if initializer.get_type() != *global.get_value_type() {
+        problems.push(format!("initializer has type {} but {} was expected", initializer.get_type(), global.get_value_type()));
+    }
+    problems.extend(verify_constant(initializer));
+    for symbol in initializer.symbols() {
+        let global = module.get_global_variables().iter().find(|global| global.borrow().get_name() == symbol);
+        if global.is_some_and(|global| global.borrow().is_thread_local()) {
+            // each thread has its own copy, at an address only known at run time
+            problems.push(format!("initializer uses the address of thread-local @{}", symbol));
+        } else if global.is_none() && !module.get_functions().iter().any(|function| function.borrow().get_name() == symbol) {
+            problems.push(format!("initializer uses @{}, which is not in the module", symbol));
+        }
+    }
+    problems
+}
+
+/// Checks the types inside a constant, returning the problems found.
+fn verify_constant(constant: &Constant) -> Vec<String> {
+    let is_value_type = |ty: &Type| !matches!(ty, Type::Void | Type::Branch | Type::FunctionType(..));
+    let mut problems = Vec::new();
+    match constant {
+        Constant::Int(_) | Constant::Bytes(_) => {}
+        Constant::Float(bits, _) => {
+            if *bits != 32 && *bits != 64 {
+                problems.push(format!("float constant {} has type f{}", constant, bits));
+            }
+        }
+        Constant::Null(ty) | Constant::Symbol(ty, _) => {
+            if !ty.is_pointer() {
+                problems.push(format!("{} has type {}, which is not a pointer", constant, ty));
+            }
+        }
+        Constant::Undef(ty) | Constant::Poison(ty) | Constant::Zero(ty) => {
+            if !is_value_type(ty) {
+                problems.push(format!("{} has type {}, which has no values", constant, ty));
+            }
+        }
+        Constant::Array(element_type, elements) => {
+            for element in elements {
+                if element.get_type() != *element_type {
+                    problems.push(format!("element {} of {} has type {} but {} was expected", element, constant, element.get_type(), element_type));
+                }
+                problems.extend(verify_constant(element));
+            }
+        }
+        Constant::Struct(fields) => problems.extend(fields.iter().flat_map(verify_constant)),
+        Constant::Expr(expr) => match expr.as_ref() {
+            ConstantExpr::Cast(opcode, a, ty) => {
+                if !is_valid_cast(opcode, &a.get_type(), ty) {
+                    problems.push(format!("`{}` cannot convert a value of type {} to {}", constant, a.get_type(), ty));
+                }
+                problems.extend(verify_constant(a));
+            }
+            ConstantExpr::Binary(opcode, a, b) => {
+                if !CONSTANT_BINARY_OPCODES.contains(opcode) || !a.get_type().is_integer() || a.get_type() != b.get_type() {
+                    problems.push(format!("`{}` does not accept operands of types {} and {}", constant, a.get_type(), b.get_type()));
+                }
+                problems.extend(verify_constant(a));
+                problems.extend(verify_constant(b));
+            }
+        },
+    }
+    problems
+}
+
+/// Checks the structure, typing and SSA form of `function`, returning the
+/// problems found. An empty list means the function is well formed.
+pub fn verify_function(function: &Function) -> Vec<Diagnostic> {
+    let mut verifier = Verifier::new(function);
+    verifier.verify();
+    verifier.diagnostics
+}
+
+struct Verifier<'a> {
+    function: &'a Function,
+    diagnostics: Vec<Diagnostic>,
+    /// Block names, in function order.
+    blocks: Vec<String>,
+    block: Option<String>,
+    /// Where each named instruction is defined: block index and position.
+    defs: HashMap<String, (usize, usize)>,
+    preds: Vec<Vec<usize>>,
+    /// `dominators[b]` holds the blocks that dominate `b`, or `None` if `b` is unreachable.
+    dominators: Vec<Option<HashSet<usize>>>,
+}
+
+impl<'a> Verifier<'a> {
+    fn new(function: &'a Function) -> Self {
+        Self {
+            function,
+            diagnostics: Vec::new(),
+            blocks: function.get_blocks().iter().map(|block| block.borrow().get_name()).collect(),
+            block: None,
+            defs: HashMap::new(),
+            preds: Vec::new(),
+            dominators: Vec::new(),
+        }
+    }
+
+    fn error(&mut self, message: String) {
+        self.diagnostics.push(Diagnostic {
+            symbol: self.function.get_name(),
+            is_global: false,
+            block: self.block.clone(),
+            message,
+        });
+    }
+
+    fn block_index(&self, name: &str) -> Option<usize> {
+        self.blocks.iter().position(|block| block == name)
+    }
+
+    fn verify(&mut self) {
+        if self.function.is_var_arg() && self.function.get_calling_conv() != CallingConv::C {
+            // the callee cannot pop arguments it does not know the size of
+            self.error(format!("variadic functions must use the ccc calling convention (got {})", self.function.get_calling_conv()));
+        }
+        if self.function.is_external() {
+            if !self.blocks.is_empty() {
+                self.error("external function has a body".to_string());
+            }
+            return;
+        }
+        if self.blocks.is_empty() {
+            self.error("function has no blocks".to_string());
+            return;
+        }
+
+        let mut seen = HashSet::new();
+        for name in self.blocks.clone() {
+            if !seen.insert(name.clone()) {
+                self.error(format!("block {} is defined more than once", name));
+            }
+        }
+
+        self.verify_structure();
+        self.compute_dominators();
+        let blocks = self.function.get_blocks().clone();
+        for (index, block) in blocks.iter().enumerate() {
+            let block = block.borrow();
+            self.block = Some(block.get_name());
+            for (position, inst) in block.get_instructions().iter().enumerate() {
+                match inst {
+                    ValueEntity::Instruction(inst) => {
+                        self.verify_types(inst);
+                        self.verify_operands(inst, index, position);
+                    }
+                    value => self.error(format!("{} is not an instruction", value.get_as_ref())),
+                }
+            }
+        }
+        self.block = None;
+    }
+
+    /// Checks terminators and phi placement, and records definitions and the CFG.
+    fn verify_structure(&mut self) {
+        let blocks = self.function.get_blocks().clone();
+        self.preds = vec![Vec::new(); blocks.len()];
+        for (index, block) in blocks.iter().enumerate() {
+            let block = block.borrow();
+            self.block = Some(block.get_name());
+            let instructions = block.get_instructions();
+            if instructions.is_empty() {
+                self.error("block is empty".to_string());
+                continue;
+            }
+
+            let mut phis_done = false;
+            for (position, inst) in instructions.iter().enumerate() {
+                let ValueEntity::Instruction(inst) = inst else {
+                    continue;
+                };
+                let last = position + 1 == instructions.len();
+                if inst.is_terminator() && !last {
+                    self.error(format!("terminator `{}` is not at the end of the block", inst));
+                }
+                if last && !inst.is_terminator() {
+                    self.error("block does not end in a terminator".to_string());
+                }
+                if matches!(inst.instruction_type(), InstructionType::Phi(_)) {
+                    if phis_done {
+                        self.error(format!("phi {} is not at the start of the block", inst.get_name()));
+                    }
+                } else {
+                    phis_done = true;
+                }
+
+                if !inst.get_type().is_void() {
+                    let name = inst.get_name();
+                    if name.is_empty() {
+                        self.error(format!("instruction `{}` produces a value but has no name", inst));
+                    } else if self.defs.insert(name.clone(), (index, position)).is_some() {
+                        self.error(format!("value {} is defined more than once", name));
+                    }
+                }
+
+                for successor in inst.get_successors() {
+                    match self.block_index(&successor) {
+                        Some(successor) => {
+                            if !self.preds[successor].contains(&index) {
+                                self.preds[successor].push(index);
+                            }
+                        }
+                        None => self.error(format!("branch to block {} which is not in the function", successor)),
+                    }
+                }
+            }
+        }
+        self.block = None;
+    }
+
+    fn compute_dominators(&mut self) {
+        let count = self.blocks.len();
+        let mut reachable = vec![false; count];
+        let mut stack = vec![0];
+        let succs = self.successors();
+        while let Some(block) = stack.pop() {
+            if !std::mem::replace(&mut reachable[block], true) {
+                stack.extend(succs[block].iter().copied());
+            }
+        }
+
+        let all = (0..count).filter(|block| reachable[*block]).collect::<HashSet<_>>();
+        let mut dominators = (0..count).map(|block| match (block, reachable[block]) {
+            (0, _) => Some(HashSet::from([0])),
+            (_, true) => Some(all.clone()),
+            (_, false) => None,
+        }).collect::<Vec<_>>();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in 1..count {
+                if !reachable[block] {
+                    continue;
+                }
+                let mut new = self.preds[block].iter()
+                    .filter_map(|pred| dominators[*pred].clone())
+                    .reduce(|a, b| a.intersection(&b).copied().collect())
+                    .unwrap_or_default();
+                new.insert(block);
+                if Some(&new) != dominators[block].as_ref() {
+                    dominators[block] = Some(new);
+                    changed = true;
+                }
+            }
+        }
+        self.dominators = dominators;
+    }
+
+    fn successors(&self) -> Vec<Vec<usize>> {
+        let mut succs = vec![Vec::new(); self.blocks.len()];
+        for (pred, preds) in self.preds.iter().enumerate() {
+            for block in preds {
+                succs[*block].push(pred);
+            }
+        }
+        succs
+    }
+
+    fn dominates(&self, a: usize, b: usize) -> bool {
+        self.dominators[b].as_ref().is_none_or(|dominators| dominators.contains(&a))
+    }
+
+    /// Checks that every value `inst` reads is available where it is read.
+    fn verify_operands(&mut self, inst: &Instruction, block: usize, position: usize) {
+        if let InstructionType::Phi(incoming) = inst.instruction_type() {
+            let mut froms = Vec::new();
+            for (value, from) in incoming {
+                let ValueEntity::BasicBlock(from) = from.as_ref() else {
+                    self.error(format!("phi {} has incoming value from {}, which is not a block", inst.get_name(), from.get_as_ref()));
+                    continue;
+                };
+                let Some(from) = self.block_index(&from.get_name()) else {
+                    self.error(format!("phi {} has incoming value from block {} which is not in the function", inst.get_name(), from.get_name()));
+                    continue;
+                };
+                if froms.contains(&from) {
+                    self.error(format!("phi {} has more than one incoming value from block {}", inst.get_name(), self.blocks[from]));
+                }
+                froms.push(from);
+                // the value must be available at the end of the incoming block
+                self.verify_use(inst, value, from, usize::MAX);
+            }
+            let mut preds = self.preds[block].clone();
+            preds.sort();
+            froms.sort();
+            froms.dedup();
+            if preds != froms {
+                let names = |blocks: &[usize]| blocks.iter().map(|block| self.blocks[*block].clone()).collect::<Vec<_>>().join(", ");
+                self.error(format!("phi {} has incoming blocks [{}] but the block's predecessors are [{}]", inst.get_name(), names(&froms), names(&preds)));
+            }
+            return;
+        }
+
+        for value in inst.get_operands() {
+            self.verify_use(inst, value, block, position);
+        }
+    }
+
+    fn verify_use(&mut self, inst: &Instruction, value: &ValueEntity, block: usize, position: usize) {
+        match value {
+            ValueEntity::Instruction(value) => {
+                let name = value.get_name();
+                match self.defs.get(&name).copied() {
+                    None => self.error(format!("`{}` uses {}, which is not defined in the function", inst, name)),
+                    Some((def_block, def_position)) => {
+                        let available = if def_block == block {
+                            def_position < position
+                        } else {
+                            self.dominates(def_block, block)
+                        };
+                        if !available {
+                            self.error(format!("{} does not dominate its use in `{}`", name, inst));
+                        }
+                    }
+                }
+            }
+            ValueEntity::Argument(argument) if self.function.get_param_by_index(argument.get_index()) != Some(argument) => {
+                self.error(format!("`{}` uses argument {}, which belongs to another function", inst, argument.get_name()));
+            }
+            ValueEntity::BasicBlock(block) => self.error(format!("`{}` uses block {} as a value", inst, block.get_name())),
+            ValueEntity::Constant(constant) => {
+                for problem in verify_constant(constant) {
+                    self.error(format!("in `{}`: {}", inst, problem));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn expect_type(&mut self, inst: &Instruction, what: &str, actual: &Type, expected: &Type) {
+        if actual != expected {
+            self.error(format!("{} of `{}` has type {} but {} was expected", what, inst, actual, expected));
+        }
+    }
+
+    fn verify_binary(&mut self, inst: &Instruction, a: &ValueEntity, b: &ValueEntity, float: bool) {
+        let ty = a.get_type();
+        self.expect_type(inst, "second operand", &b.get_type(), &ty);
+        if !(if float { ty.is_float() } else { ty.is_integer() }) {
+            self.error(format!("`{}` does not accept operands of type {}", inst, ty));
+        }
+    }
+
+    /// Checks the operand and result types of `inst`.
+    fn verify_types(&mut self, inst: &Instruction) {
+        let bool_type = Type::Integer(1);
+        match inst.instruction_type() {
+            InstructionType::Add(a, b) | InstructionType::Sub(a, b) | InstructionType::Mul(a, b)
+            | InstructionType::SDiv(a, b) | InstructionType::SRem(a, b) | InstructionType::UDiv(a, b) | InstructionType::URem(a, b)
+            | InstructionType::Shl(a, b) | InstructionType::AShr(a, b) | InstructionType::LShr(a, b) | InstructionType::And(a, b)
+            | InstructionType::Or(a, b) | InstructionType::Xor(a, b) => {
+                self.verify_binary(inst, a, b, false);
+                self.expect_type(inst, "result", &inst.get_type(), &a.get_type());
+            }
+            InstructionType::Eq(a, b) | InstructionType::Ne(a, b) | InstructionType::SLt(a, b) | InstructionType::SLe(a, b)
+            | InstructionType::SGt(a, b) | InstructionType::SGe(a, b) | InstructionType::ULt(a, b) | InstructionType::ULe(a, b)
+            | InstructionType::UGt(a, b) | InstructionType::UGe(a, b) => {
+                self.verify_binary(inst, a, b, false);
+                self.expect_type(inst, "result", &inst.get_type(), &bool_type);
+            }
+            InstructionType::Neg(a) | InstructionType::Not(a) => {
+                if !a.get_type().is_integer() {
+                    self.error(format!("`{}` does not accept an operand of type {}", inst, a.get_type()));
+                }
+                self.expect_type(inst, "result", &inst.get_type(), &a.get_type());
+            }
+            InstructionType::FAdd(a, b, _) | InstructionType::FSub(a, b, _) | InstructionType::FMul(a, b, _)
+            | InstructionType::FDiv(a, b, _) | InstructionType::FRem(a, b, _) => {
+                self.verify_binary(inst, a, b, true);
+                self.expect_type(inst, "result", &inst.get_type(), &a.get_type());
+            }
+            InstructionType::FNeg(a, _) => {
+                if !a.get_type().is_float() {
+                    self.error(format!("`{}` does not accept an operand of type {}", inst, a.get_type()));
+                }
+                self.expect_type(inst, "result", &inst.get_type(), &a.get_type());
+            }
+            InstructionType::FCmp(_, a, b, _) => {
+                self.verify_binary(inst, a, b, true);
+                self.expect_type(inst, "result", &inst.get_type(), &bool_type);
+            }
+            InstructionType::Trunc(a) | InstructionType::ZExt(a) | InstructionType::SExt(a) | InstructionType::FPTrunc(a)
+            | InstructionType::FPExt(a) | InstructionType::FPToSI(a) | InstructionType::FPToUI(a) | InstructionType::SIToFP(a)
+            | InstructionType::UIToFP(a) | InstructionType::PtrToInt(a) | InstructionType::IntToPtr(a) | InstructionType::Bitcast(a) => {
+                let (opcode, _) = inst.instruction_type().as_cast().unwrap();
+                if !is_valid_cast(opcode, &a.get_type(), &inst.get_type()) {
+                    self.error(format!("`{}` cannot convert a value of type {} to {}", inst, a.get_type(), inst.get_type()));
+                }
+            }
+            InstructionType::Alloca(ty, count, _) => {
+                if let Some(count) = count {
+                    if !count.get_type().is_integer() {
+                        self.error(format!("element count of `{}` has type {} but an integer was expected", inst, count.get_type()));
+                    }
+                }
+                self.expect_type(inst, "result", &inst.get_type(), &ty.get_pointer_to());
+            }
+            InstructionType::GetElementPtr(ptr, indices) => match ptr.get_type() {
+                Type::Pointer(element) => match get_indexed_type(&element, indices) {
+                    Ok(indexed) => self.expect_type(inst, "result", &inst.get_type(), &indexed.get_pointer_to()),
+                    Err(problem) => self.error(format!("in `{}`: {}", inst, problem)),
+                },
+                ty => self.error(format!("address of `{}` has type {} but a pointer was expected", inst, ty)),
+            },
+            InstructionType::Load(ptr) => {
+                if !ptr.get_type().is_pointer() {
+                    self.error(format!("address of `{}` has type {} but a pointer was expected", inst, ptr.get_type()));
+                }
+            }
+            InstructionType::Store(ptr, value) => {
+                if ptr.get_type().is_pointer() {
+                    self.expect_type(inst, "stored value", &value.get_type(), &ptr.get_type().get_pointer_element_type());
+                } else {
+                    self.error(format!("address of `{}` has type {} but a pointer was expected", inst, ptr.get_type()));
+                }
+            }
+            InstructionType::Call(callee, args) => self.verify_call(inst, callee, args),
+            InstructionType::Return(value) => {
+                let expected = self.function.get_function_return_type();
+                self.expect_type(inst, "returned value", &value.get_type(), &expected);
+            }
+            InstructionType::VoidReturn => {
+                let expected = self.function.get_function_return_type();
+                if !expected.is_void() {
+                    self.error(format!("`{}` in a function returning {}", inst, expected));
+                }
+            }
+            InstructionType::Branch(target) => {
+                if !matches!(target.as_ref(), ValueEntity::BasicBlock(_)) {
+                    self.error(format!("target of `{}` is not a block", inst));
+                }
+            }
+            InstructionType::BranchIf(cond, _, _) => self.expect_type(inst, "condition", &cond.get_type(), &bool_type),
+            InstructionType::Phi(incoming) => {
+                if incoming.is_empty() {
+                    self.error(format!("phi {} has no incoming values", inst.get_name()));
+                }
+                for (value, _) in incoming {
+                    self.expect_type(inst, "incoming value", &value.get_type(), &inst.get_type());
+                }
+            }
+            InstructionType::Unreachable => {}
+        }
+    }
+
+    fn verify_call(&mut self, inst: &Instruction, callee: &ValueEntity, args: &[Box<ValueEntity>]) {
+        let (ty, is_var_arg) = match callee {
+            ValueEntity::Function(function) => (function.get_type(), function.is_var_arg()),
+            callee => match callee.get_type() {
+                Type::Pointer(ty) if ty.is_function_type() => (*ty, false),
+                ty => {
+                    self.error(format!("callee of `{}` has type {}, which is not a function", inst, ty));
+                    return;
+                }
+            },
+        };
+        let params = ty.get_function_argument_types();
+        if args.len() < params.len() || (!is_var_arg && args.len() > params.len()) {
+            self.error(format!("`{}` passes {} arguments to a function taking {}{}", inst, args.len(), params.len(), if is_var_arg { " or more" } else { "" }));
+        }
+        for (index, (arg, param)) in args.iter().zip(params).enumerate() {
+            self.expect_type(inst, &format!("argument {}", index), &arg.get_type(), param);
+        }
+        self.expect_type(inst, "result", &inst.get_type(), &ty.get_function_return_type());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::ir::builder::{Builder, IRContext};
+    use crate::ir::linkage::Linkage;
+    use crate::ir::parser::parse_module;
+    use crate::ir::values::basic_block::BasicBlock;
+    use crate::targets::{DataLayout, TargetTriple};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn builder() -> Builder {
+        let triple = TargetTriple::new("x86_64-unknown-linux-gnu").unwrap();
+        let module = Module::new("test", DataLayout::from_triple(&triple), triple);
+        Builder::new(IRContext::new(module))
+    }
+
+    fn block_value(block: &Rc<RefCell<BasicBlock>>) -> ValueEntity {
+        block.borrow().clone().into()
+    }
+
+    /// Builds a diamond `entry -> (left | right) -> merge` and lets `merge`
+    /// be filled in by the caller.
+    fn diamond(builder: &mut Builder, merge: impl FnOnce(&mut Builder, [&Rc<RefCell<BasicBlock>>; 3], ValueEntity) -> Result<(), Error>) -> Result<Vec<Diagnostic>, Error> {
+        let function = builder.create_function("f", vec![], builder.get_i32_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", function.clone())?;
+        let left = builder.create_block("left", function.clone())?;
+        let right = builder.create_block("right", function.clone())?;
+        let exit = builder.create_block("merge", function.clone())?;
+        builder.set_insertion_point(entry.clone());
+        let condition = builder.eq(builder.get_i32(1), builder.get_i32(2), None)?;
+        builder.branch_if(condition.into(), left.clone(), right.clone())?;
+        builder.set_insertion_point(left.clone());
+        let value = builder.add(builder.get_i32(1), builder.get_i32(2), None)?;
+        builder.branch(block_value(&exit))?;
+        builder.set_insertion_point(right.clone());
+        builder.branch(block_value(&exit))?;
+        builder.set_insertion_point(exit);
+        merge(builder, [&left, &right, &entry], value.into())?;
+        let diagnostics = verify_function(&function.borrow());
+        Ok(diagnostics)
+    }
+
+    #[test]
+    fn accepts_well_formed_functions() -> Result<(), Error> {
+        let mut builder = builder();
+        let diagnostics = diamond(&mut builder, |builder, [left, right, _], value| {
+            let phi = builder.phi(vec![(value, block_value(left)), (builder.get_i32(0), block_value(right))])?;
+            builder.ret(phi.into())?;
+            Ok(())
+        })?;
+        assert_eq!(diagnostics, []);
+        Ok(())
+    }
+
+    #[test]
+    fn checks_phi_incoming_blocks_against_predecessors() -> Result<(), Error> {
+        let mut builder = builder();
+        let diagnostics = diamond(&mut builder, |builder, [left, _, entry], value| {
+            let phi = builder.phi(vec![(value, block_value(left)), (builder.get_i32(0), block_value(entry))])?;
+            builder.ret(phi.into())?;
+            Ok(())
+        })?;
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].block.as_deref(), Some("%merge"));
+        assert!(diagnostics[0].message.ends_with("has incoming blocks [%entry, %left] but the block's predecessors are [%left, %right]"), "{}", diagnostics[0]);
+        Ok(())
+    }
+
+    #[test]
+    fn reports_uses_not_dominated_by_their_definition() -> Result<(), Error> {
+        let mut builder = builder();
+        let diagnostics = diamond(&mut builder, |builder, _, value| {
+            builder.ret(value)?;
+            Ok(())
+        })?;
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.contains("does not dominate its use in `ret"), "{}", diagnostics[0]);
+        Ok(())
+    }
+
+    #[test]
+    fn reports_blocks_without_terminators() -> Result<(), Error> {
+        let mut builder = builder();
+        let function = builder.create_function("f", vec![], builder.get_i32_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", function.clone())?;
+        builder.set_insertion_point(entry);
+        builder.add(builder.get_i32(1), builder.get_i32(2), None)?;
+        let diagnostics = verify_module(builder.get_module());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].to_string(), "in function @f, block %entry: block does not end in a terminator");
+        Ok(())
+    }
+
+    #[test]
+    fn reports_conversions_between_unrelated_types() {
+        let module = parse_module(r#"
+            define internal function @f(%a: i64) -> i32 {
+            %entry:
+              %narrow = sext i64 %a to i32
+              return i32 %narrow
+            }
+        "#).unwrap();
+        let diagnostics = verify_module(&module);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "`%narrow = sext i64 %a to i32` cannot convert a value of type i64 to i32");
+    }
+
+    #[test]
+    fn reports_unsigned_operations_on_floats() {
+        let module = parse_module(r#"
+            define internal function @f(%x: f64) -> i1 {
+            %entry:
+              %below = ult f64 %x, %x
+              return i1 %below
+            }
+        "#).unwrap();
+        let diagnostics = verify_module(&module);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "`%below = ult f64 %x, %x` does not accept operands of type f64");
+    }
+
+    #[test]
+    fn reports_float_operations_on_integers() {
+        let module = parse_module(r#"
+            define internal function @f(%a: i32) -> i32 {
+            %entry:
+              %sum = fadd i32 %a, %a
+              return i32 %sum
+            }
+        "#).unwrap();
+        let diagnostics = verify_module(&module);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "`%sum = fadd i32 %a, %a` does not accept operands of type i32");
+    }
+
+    #[test]
+    fn reports_bitwise_operations_of_another_type() -> Result<(), Error> {
+        let mut builder = builder();
+        let function = builder.create_function("f", vec![builder.get_i32_type()], builder.get_i32_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", function.clone())?;
+        builder.set_insertion_point(entry);
+        let x = builder.get_param(&function, 0)?;
+        // an `and` of integers is no boolean telling whether any bit is set
+        let both = InstructionType::And(Box::new(x.clone()), Box::new(x));
+        builder.insert(Instruction::new(Type::Integer(1), both, Some("%both".to_string())))?;
+        builder.ret(builder.get_i32(0))?;
+        let diagnostics = verify_module(builder.get_module());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "result of `%both = and i32 %0, %0` has type i1 but i32 was expected");
+        Ok(())
+    }
+
+    #[test]
+    fn reports_initializers_using_thread_local_addresses() {
+        let module = parse_module(r#"
+            @tls = internal thread_local global i32 0
+            @pointer = internal global i32* @tls
+        "#).unwrap();
+        let diagnostics = verify_module(&module);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].to_string(), "in global @pointer: initializer uses the address of thread-local @tls");
+    }
+
+    #[test]
+    fn checks_constants_built_by_hand() -> Result<(), Error> {
+        let mut builder = builder();
+        let function = builder.create_function("f", vec![], builder.get_i32_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", function.clone())?;
+        builder.set_insertion_point(entry);
+        // the builder checks its operands, but a constant can also be put together directly
+        let mixed = Constant::Expr(Box::new(ConstantExpr::Binary("add", Constant::from_i64(32, 1), Constant::from_i64(64, 2))));
+        builder.ret(mixed.into())?;
+        let diagnostics = verify_module(builder.get_module());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "in `return i32 add (i32 1, 2)`: `add (i32 1, 2)` does not accept operands of types i32 and i64");
+        Ok(())
+    }
+
+    #[test]
+    fn checks_the_type_a_getelementptr_selects() -> Result<(), Error> {
+        let mut builder = builder();
+        let array = builder.get_array_type(builder.get_i32_type(), 4);
+        let function = builder.create_function("f", vec![builder.get_pointer_type(array)], builder.get_i32_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", function.clone())?;
+        builder.set_insertion_point(entry);
+        let element = builder.array_gep(builder.get_param(&function, 0)?, builder.get_i32(1), None)?;
+        // the same address typed as a pointer to the whole array
+        let InstructionType::GetElementPtr(ptr, indices) = element.instruction_type().clone() else {
+            unreachable!();
+        };
+        builder.insert(Instruction::new(ptr.get_type(), InstructionType::GetElementPtr(ptr, indices), Some("%wrong".to_string())))?;
+        builder.ret(builder.get_i32(0))?;
+        let diagnostics = verify_module(builder.get_module());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "result of `%wrong = getelementptr [4 x i32]* %0, i64 0, i32 1` has type [4 x i32]* but i32* was expected");
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+use std::ffi::{c_char, c_int, c_long, c_void, CStr};
+use std::io::Error;
+
+const PROT_READ: c_int = 0x1;
+const PROT_WRITE: c_int = 0x2;
+const PROT_EXEC: c_int = 0x4;
+const MAP_PRIVATE: c_int = 0x02;
+const MAP_ANONYMOUS: c_int = 0x20;
+const SC_PAGESIZE: c_int = 30;
+
+extern "C" {
+    fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+    fn mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    fn sysconf(name: c_int) -> c_long;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+}
+
+pub fn page_size() -> usize {
+    // SAFETY: sysconf has no preconditions
+    unsafe { sysconf(SC_PAGESIZE) as usize }
+}
+
+/// Looks a symbol up in the running process and the libraries it loaded.
+pub fn lookup_symbol(name: &CStr) -> Option<usize> {
+    // SAFETY: a null handle is RTLD_DEFAULT, and `name` is NUL-terminated
+    let address = unsafe { dlsym(std::ptr::null_mut(), name.as_ptr()) };
+    (!address.is_null()).then_some(address as usize)
+}
+
+/// Anonymous memory, readable and writable until part of it is made executable.
+pub struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    /// Maps `len` bytes, which must be a multiple of the page size.
+    pub fn new(len: usize) -> Result<Self, Error> {
+        // SAFETY: an anonymous private mapping at an address of the kernel's choosing aliases nothing
+        let ptr = unsafe { mmap(std::ptr::null_mut(), len, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0) };
+        if ptr as isize == -1 {
+            return Err(Error::last_os_error());
+        }
+        Ok(Self { ptr: ptr as *mut u8, len })
+    }
+
+    pub fn address(&self) -> usize {
+        self.ptr as usize
+    }
+
+    /// Returns the mapping as bytes. Must not be called once part of it is executable.
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        // SAFETY: the mapping is `len` bytes long and writable
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+
+    /// Makes the first `len` bytes readable and executable, and no longer writable.
+    pub fn make_executable(&mut self, len: usize) -> Result<(), Error> {
+        // SAFETY: the range is page aligned and inside the mapping
+        if len > 0 && unsafe { mprotect(self.ptr as *mut c_void, len, PROT_READ | PROT_EXEC) } != 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: the mapping came from mmap with this length and is not used after this
+        unsafe {
+            munmap(self.ptr as *mut c_void, self.len);
+        }
+    }
+}
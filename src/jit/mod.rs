@@ -0,0 +1,302 @@
+use crate::emit::asm::x86_64::compile_function;
+use crate::emit::asm::data::{self, Datum};
+use crate::emit::asm::x86_64::encode::{encode_function, Reloc, RelocKind};
+use crate::emit::asm::FrameOptions;
+use crate::emit::asm::unsupported;
+use crate::error::Error;
+use crate::ir::linkage::Linkage;
+use crate::ir::module::Module;
+use crate::jit::memory::{lookup_symbol, page_size, Mapping};
+use crate::targets::triple::Arch;
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+
+mod memory;
+
+/// Size of a PLT stub, `jmp qword ptr [rip + slot]` padded with `int3`.
+const STUB_SIZE: usize = 8;
+
+/// Where a symbol defined in the module lives in the image.
+#[derive(Debug, Clone, Copy)]
+enum Location {
+    Text(usize),
+    Data(usize),
+}
+
+/// The code and data of a module before they are placed in memory.
+#[derive(Default)]
+struct Image {
+    text: Vec<u8>,
+    /// Relocations of the text, with offsets into it.
+    relocs: Vec<Reloc>,
+    data: Vec<u8>,
+    /// Addresses to store in the data, with their offset into it and the offset added to them.
+    data_symbols: Vec<(usize, String, i64)>,
+    data_align: usize,
+    symbols: HashMap<String, Location>,
+    functions: Vec<String>,
+}
+
+/// Compiles a module into the memory of the running process and hands out
+/// its functions. Externals the module declares are looked up in the
+/// process, so a module can call `printf` or any other function the process
+/// links to. The code stays mapped as long as the engine lives.
+///
+/// Thread-local globals are not supported.
+pub struct ExecutionEngine {
+    memory: Mapping,
+    functions: HashMap<String, usize>,
+    globals: HashMap<String, usize>,
+}
+
+impl ExecutionEngine {
+    pub fn new(module: &Module) -> Result<Self, Error> {
+        Self::with_frame_options(module, FrameOptions::default())
+    }
+
+    pub fn with_frame_options(module: &Module, frame_options: FrameOptions) -> Result<Self, Error> {
+        if module.target_triple().arch() != Arch::X86_64 {
+            return Err(Error::UnsupportedTarget(module.target_triple().clone()));
+        }
+        let image = build_image(module, frame_options)?;
+
+        // externals declared weak may be missing, everything else has to be found
+        let mut weak = HashSet::new();
+        for function in module.get_functions() {
+            let function = function.borrow();
+            if matches!(function.get_linkage(), Linkage::ExternalWeakLinkage | Linkage::LinkonceLinkage | Linkage::WeakLinkage) {
+                weak.insert(function.get_name());
+            }
+        }
+        for global in module.get_global_variables() {
+            if *global.borrow().get_linkage() == Linkage::ExternalWeakLinkage {
+                weak.insert(global.borrow().get_name());
+            }
+        }
+        let mut externals = HashMap::new();
+        let referenced = image.relocs.iter().map(|reloc| &reloc.symbol).chain(image.data_symbols.iter().map(|(_, symbol, _)| symbol));
+        for symbol in referenced {
+            if image.symbols.contains_key(symbol) || externals.contains_key(symbol) {
+                continue;
+            }
+            let name = CString::new(symbol.as_str()).map_err(|_| Error::InvalidName(symbol.clone()))?;
+            match lookup_symbol(&name) {
+                Some(address) => externals.insert(symbol.clone(), address),
+                None if weak.contains(symbol) => externals.insert(symbol.clone(), 0),
+                None => return Err(Error::UnresolvedSymbol(symbol.clone())),
+            };
+        }
+
+        // calls to externals go through a stub, since they may be out of reach
+        // of a 32-bit displacement, and the stubs and GOT accesses read the
+        // address from a slot
+        let mut stubs = Vec::new();
+        let mut slots = Vec::new();
+        for reloc in &image.relocs {
+            let symbol = &reloc.symbol;
+            if reloc.kind == RelocKind::Plt32 && externals.contains_key(symbol) && !stubs.contains(symbol) {
+                stubs.push(symbol.clone());
+            }
+            if (reloc.kind == RelocKind::GotPcRel || stubs.contains(symbol)) && !slots.contains(symbol) {
+                slots.push(symbol.clone());
+            }
+        }
+
+        // text and stubs come first, then the slots and data on their own pages
+        let page = page_size();
+        let stubs_start = image.text.len().next_multiple_of(STUB_SIZE);
+        let executable = (stubs_start + stubs.len() * STUB_SIZE).next_multiple_of(page);
+        let slots_start = executable;
+        let data_start = (slots_start + slots.len() * 8).next_multiple_of(image.data_align);
+        let len = (data_start + image.data.len()).next_multiple_of(page).max(page);
+
+        let mut memory = Mapping::new(len)?;
+        let base = memory.address();
+        let address = |symbol: &str| -> usize {
+            match image.symbols.get(symbol) {
+                Some(Location::Text(offset)) => base + offset,
+                Some(Location::Data(offset)) => base + data_start + offset,
+                None => externals[symbol],
+            }
+        };
+        let slot_address = |symbol: &str| base + slots_start + 8 * slots.iter().position(|slot| slot == symbol).unwrap();
+        let stub_address = |symbol: &str| base + stubs_start + STUB_SIZE * stubs.iter().position(|stub| stub == symbol).unwrap();
+
+        let bytes = memory.bytes_mut();
+        bytes[..image.text.len()].copy_from_slice(&image.text);
+        bytes[image.text.len()..executable].fill(0xcc);
+        for (i, symbol) in stubs.iter().enumerate() {
+            let stub = stubs_start + i * STUB_SIZE;
+            let distance = slot_address(symbol) as i64 - (base + stub + 6) as i64;
+            bytes[stub..stub + 2].copy_from_slice(&[0xff, 0x25]);
+            bytes[stub + 2..stub + 6].copy_from_slice(&(distance as i32).to_le_bytes());
+        }
+        for (i, symbol) in slots.iter().enumerate() {
+            let slot = slots_start + 8 * i;
+            bytes[slot..slot + 8].copy_from_slice(&(address(symbol) as u64).to_le_bytes());
+        }
+        bytes[data_start..data_start + image.data.len()].copy_from_slice(&image.data);
+        for (offset, symbol, addend) in &image.data_symbols {
+            let offset = data_start + offset;
+            bytes[offset..offset + 8].copy_from_slice(&(address(symbol) as u64).wrapping_add(*addend as u64).to_le_bytes());
+        }
+
+        for reloc in &image.relocs {
+            let target = match reloc.kind {
+                RelocKind::Pc32 => address(&reloc.symbol),
+                RelocKind::Plt32 if stubs.contains(&reloc.symbol) => stub_address(&reloc.symbol),
+                RelocKind::Plt32 => address(&reloc.symbol),
+                RelocKind::GotPcRel => slot_address(&reloc.symbol),
+                RelocKind::GotTpOff | RelocKind::Abs64 => return Err(unsupported(format!("{:?} relocation against {} in the JIT", reloc.kind, reloc.symbol)).into()),
+            };
+            let place = base + reloc.offset as usize;
+            let value = i32::try_from(target as i64 + reloc.addend - place as i64)
+                .map_err(|_| unsupported(format!("{} is out of reach of a 32-bit displacement", reloc.symbol)))?;
+            let offset = reloc.offset as usize;
+            bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+
+        let functions = image.functions.iter().map(|name| (name.clone(), address(name))).collect();
+        let globals = image.symbols.iter()
+            .filter(|(_, location)| matches!(location, Location::Data(_)))
+            .map(|(name, _)| (name.clone(), address(name)))
+            .collect();
+        memory.make_executable(executable)?;
+        Ok(Self { memory, functions, globals })
+    }
+
+    /// Returns the address of a function defined in the module.
+    pub fn get_function_address(&self, name: &str) -> Option<usize> {
+        self.functions.get(name).copied()
+    }
+
+    /// Returns the address of a global variable defined in the module.
+    pub fn get_global_address(&self, name: &str) -> Option<usize> {
+        self.globals.get(name).copied()
+    }
+
+    /// Returns a function defined in the module as a function pointer of type `F`.
+    ///
+    /// # Safety
+    ///
+    /// `F` must be an `extern "C" fn` type whose parameters and return type
+    /// match the signature of the function, and the pointer must not be called
+    /// after the engine is dropped.
+    pub unsafe fn get_function<F: Copy>(&self, name: &str) -> Option<F> {
+        assert_eq!(std::mem::size_of::<F>(), std::mem::size_of::<usize>(), "F must be a function pointer");
+        let address = self.get_function_address(name)?;
+        Some(std::mem::transmute_copy(&address))
+    }
+
+    /// Returns the start of the memory holding the module.
+    pub fn base_address(&self) -> usize {
+        self.memory.address()
+    }
+}
+
+/// Compiles the functions of the module and lays out its globals.
+fn build_image(module: &Module, frame_options: FrameOptions) -> Result<Image, Error> {
+    let layout = module.data_layout();
+    let mut image = Image { data_align: 1, ..Image::default() };
+
+    for function in module.get_functions() {
+        let function = function.borrow();
+        if function.is_external() || function.get_blocks().is_empty() {
+            continue;
+        }
+        let name = function.get_name();
+        let code = encode_function(&compile_function(&function, layout, frame_options)?)?;
+        let offset = image.text.len().next_multiple_of(16);
+        image.text.resize(offset, 0xcc);
+        image.text.extend_from_slice(&code.bytes);
+        image.relocs.extend(code.relocs.into_iter().map(|reloc| Reloc { offset: reloc.offset + offset as u64, ..reloc }));
+        image.symbols.insert(name.clone(), Location::Text(offset));
+        image.functions.push(name);
+    }
+
+    for global in module.get_global_variables() {
+        let global = global.borrow();
+        if global.is_external() {
+            continue;
+        }
+        let name = global.get_name();
+        if global.is_thread_local() {
+            return Err(unsupported(format!("thread-local global {} in the JIT", name)).into());
+        }
+        if *global.get_linkage() == Linkage::CommonLinkage {
+            data::check_common(&global)?;
+        }
+        let (size, align) = data::size_and_align(layout, &global);
+        let offset = image.data.len().next_multiple_of(align as usize);
+        image.data.resize(offset, 0);
+        image.data_align = image.data_align.max(align as usize);
+        for datum in data::global_data(layout, &global)? {
+            match datum {
+                Datum::Zero(size) => image.data.resize(image.data.len() + size as usize, 0),
+                Datum::Int(size, value) => image.data.extend_from_slice(&value.to_le_bytes()[..size as usize]),
+                Datum::F32(value) => image.data.extend_from_slice(&value.to_le_bytes()),
+                Datum::F64(value) => image.data.extend_from_slice(&value.to_le_bytes()),
+                Datum::Bytes(bytes) => image.data.extend_from_slice(&bytes),
+                Datum::Symbol(_, symbol, addend) => {
+                    image.data_symbols.push((image.data.len(), symbol, addend));
+                    image.data.extend_from_slice(&[0; 8]);
+                }
+            }
+        }
+        debug_assert_eq!(image.data.len(), offset + size as usize);
+        image.symbols.insert(name, Location::Data(offset));
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::builder::{Builder, IRContext};
+    use crate::targets::{DataLayout, TargetTriple};
+
+    fn builder() -> Builder {
+        let triple = TargetTriple::new("x86_64-unknown-linux-gnu").unwrap();
+        let module = Module::new("test", DataLayout::from_triple(&triple), triple);
+        Builder::new(IRContext::new(module))
+    }
+
+    #[test]
+    fn runs_functions_that_use_globals_and_externals() -> Result<(), Error> {
+        let mut builder = builder();
+        let counter = builder.create_global("counter", builder.get_i64_type(), Some(builder.const_int(builder.get_i64_type(), 40)?), Linkage::InternalLinkage, false)?;
+        let labs = builder.create_function("labs", vec![builder.get_i64_type()], builder.get_i64_type(), Linkage::ExternalLinkage, false)?;
+        let bump = builder.create_function("bump", vec![builder.get_i64_type()], builder.get_i64_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", bump.clone())?;
+        builder.set_insertion_point(entry);
+        let amount = builder.call(labs.borrow().clone().into(), vec![builder.get_param(&bump, 0)?], None)?;
+        let old = builder.load(builder.get_i64_type(), counter.borrow().clone().into(), None)?;
+        let new = builder.add(old.into(), amount.into(), None)?;
+        builder.store(counter.borrow().clone().into(), new.clone().into())?;
+        builder.ret(new.into())?;
+
+        let engine = ExecutionEngine::new(builder.get_module())?;
+        let bump = unsafe { engine.get_function::<extern "C" fn(i64) -> i64>("bump") }.unwrap();
+        assert_eq!(bump(-1), 41);
+        assert_eq!(bump(2), 43);
+        let counter = engine.get_global_address("counter").unwrap();
+        assert_eq!(unsafe { *(counter as *const i64) }, 43);
+        assert!(engine.get_function_address("labs").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn reports_missing_externals() -> Result<(), Error> {
+        let mut builder = builder();
+        let missing = builder.create_function("sslb_missing_function", vec![], builder.get_void_type(), Linkage::ExternalLinkage, false)?;
+        let main = builder.create_function("main", vec![], builder.get_void_type(), Linkage::InternalLinkage, false)?;
+        let entry = builder.create_block("entry", main)?;
+        builder.set_insertion_point(entry);
+        builder.call(missing.borrow().clone().into(), vec![], None)?;
+        builder.void_ret()?;
+
+        let error = ExecutionEngine::new(builder.get_module()).err().unwrap();
+        assert!(error.to_string().contains("sslb_missing_function"), "{}", error);
+        Ok(())
+    }
+}
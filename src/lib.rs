@@ -3,4 +3,6 @@ pub mod ir;
 pub mod targets;
 pub mod error;
 pub mod emit;
-pub(crate) mod utils;
+pub mod interpreter;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub mod jit;
@@ -1,6 +1,9 @@
 use crate::targets::triple::TargetTriple;
 use crate::targets::triple::Arch;
-use std::fmt::Debug;
+use crate::error::Error;
+use crate::ir::values::constant::Scalar;
+use crate::ir::values::instruction::get_field_index;
+use crate::ir::values::value::{Type, ValueEntity};
 use std::fmt::Formatter;
 use std::fmt;
 use std::fmt::Display;
@@ -112,6 +115,9 @@ impl DataLayout {
             Arch::X86 => Self::new_x86(),
             Arch::Arm => Self::new_arm(),
             Arch::Aarch64 => Self::new_aarch64(),
+            Arch::Riscv32 => Self::new_riscv32(),
+            Arch::Riscv64 => Self::new_riscv64(),
+            Arch::Wasm32 => Self::new_wasm32(),
             _ => Self::default(),
         }
     }
@@ -120,8 +126,14 @@ impl DataLayout {
         Self::new(8)
     }
 
+    /// The i386 System V ABI aligns 64-bit integers and doubles to only 4 bytes.
     pub fn new_x86() -> Self {
-        Self::new(4)
+        Self {
+            u64_align: 4,
+            i64_align: 4,
+            f64_align: 4,
+            ..Self::new(4)
+        }
     }
 
     pub fn new_arm() -> Self {
@@ -132,8 +144,49 @@ impl DataLayout {
         Self::new(8)
     }
 
-    pub fn default() -> Self {
-        Self::new_x86_64()
+    pub fn new_riscv32() -> Self {
+        Self::new(4)
+    }
+
+    pub fn new_riscv64() -> Self {
+        Self::new(8)
+    }
+
+    pub fn new_wasm32() -> Self {
+        Self::new(4)
+    }
+
+    /// Parses a data layout in the form written by its `Display` implementation,
+    /// e.g. `p-8:8 s-8:8 ...`. Entries that are left out keep their x86_64 values.
+    pub fn parse(layout: &str) -> Result<Self, Error> {
+        let mut result = Self::default();
+        for entry in layout.split_whitespace() {
+            let (key, value) = entry.split_once('-').ok_or(Error::InvalidDataLayout)?;
+            let (size, align) = value.split_once(':').ok_or(Error::InvalidDataLayout)?;
+            let size = size.parse().map_err(|_| Error::InvalidDataLayout)?;
+            let align = align.parse().map_err(|_| Error::InvalidDataLayout)?;
+            let (size_field, align_field) = match key {
+                "p" => (&mut result.pointer_size, &mut result.pointer_align),
+                "s" => (&mut result.usize_size, &mut result.usize_align),
+                "i" => (&mut result.isize_size, &mut result.isize_align),
+                "u8" => (&mut result.u8_size, &mut result.u8_align),
+                "i8" => (&mut result.i8_size, &mut result.i8_align),
+                "u16" => (&mut result.u16_size, &mut result.u16_align),
+                "i16" => (&mut result.i16_size, &mut result.i16_align),
+                "u32" => (&mut result.u32_size, &mut result.u32_align),
+                "i32" => (&mut result.i32_size, &mut result.i32_align),
+                "u64" => (&mut result.u64_size, &mut result.u64_align),
+                "i64" => (&mut result.i64_size, &mut result.i64_align),
+                "u128" => (&mut result.u128_size, &mut result.u128_align),
+                "i128" => (&mut result.i128_size, &mut result.i128_align),
+                "f32" => (&mut result.f32_size, &mut result.f32_align),
+                "f64" => (&mut result.f64_size, &mut result.f64_align),
+                _ => return Err(Error::InvalidDataLayout),
+            };
+            *size_field = size;
+            *align_field = align;
+        }
+        Ok(result)
     }
 
     /// Returns the size of a pointer.
@@ -273,7 +326,7 @@ impl DataLayout {
 
     /// Returns the alignment of a `f32`.
     pub fn f32_align(&self) -> u64 {
-        self.f32_size
+        self.f32_align
     }
 
     /// Returns the size of a `f64`.
@@ -285,6 +338,108 @@ impl DataLayout {
     pub fn f64_align(&self) -> u64 {
         self.f64_align
     }
+
+    /// Returns the number of bytes a value of the given type occupies in memory.
+    pub fn size_of(&self, ty: &Type) -> u64 {
+        match ty {
+            Type::Integer(bits) => match bits {
+                0..=8 => self.i8_size,
+                9..=16 => self.i16_size,
+                17..=32 => self.i32_size,
+                33..=64 => self.i64_size,
+                65..=128 => self.i128_size,
+                _ => (*bits as u64).div_ceil(64) * 8,
+            },
+            Type::Float(32) => self.f32_size,
+            Type::Float(64) => self.f64_size,
+            Type::Float(bits) => (*bits as u64).div_ceil(8),
+            Type::Pointer(_) | Type::FunctionType(_, _) => self.pointer_size,
+            Type::Array(len, element) => *len as u64 * self.stride_of(element),
+            Type::Struct(fields) => {
+                let end = match (fields.last(), self.struct_field_offsets(fields).last()) {
+                    (Some(last), Some(offset)) => offset + self.size_of(last),
+                    _ => 0,
+                };
+                end.next_multiple_of(self.align_of(ty))
+            }
+            Type::Void | Type::Branch => 0,
+        }
+    }
+
+    /// Returns the alignment required for a value of the given type.
+    pub fn align_of(&self, ty: &Type) -> u64 {
+        match ty {
+            Type::Integer(bits) => match bits {
+                0..=8 => self.i8_align,
+                9..=16 => self.i16_align,
+                17..=32 => self.i32_align,
+                33..=64 => self.i64_align,
+                _ => self.i128_align,
+            },
+            Type::Float(32) => self.f32_align,
+            Type::Float(64) => self.f64_align,
+            Type::Float(bits) => (*bits as u64).div_ceil(8).next_power_of_two(),
+            Type::Pointer(_) | Type::FunctionType(_, _) => self.pointer_align,
+            Type::Array(_, element) => self.align_of(element),
+            Type::Struct(fields) => fields.iter().map(|field| self.align_of(field)).max().unwrap_or(1),
+            Type::Void | Type::Branch => 1,
+        }
+    }
+
+    /// Returns the distance between consecutive elements of an array of the given type.
+    pub fn stride_of(&self, ty: &Type) -> u64 {
+        self.size_of(ty).next_multiple_of(self.align_of(ty))
+    }
+
+    /// Returns the byte offset of the value `indices` select in memory
+    /// holding a `ty`, as a `getelementptr` computes it: the sum of the
+    /// constant offset returned and each index left in the list times its
+    /// stride. Constant indices are folded into the offset, and all indices
+    /// are read as signed. Returns `None` if the indices can't index `ty`.
+    pub fn indexed_offset<'a>(&self, ty: &Type, indices: &'a [Box<ValueEntity>]) -> Option<(i64, Vec<(&'a ValueEntity, u64)>)> {
+        let mut offset: i64 = 0;
+        let mut scaled = Vec::new();
+        let mut ty = ty.clone();
+        for (position, index) in indices.iter().enumerate() {
+            let element = match (position, &ty) {
+                (0, _) => ty.clone(),
+                (_, Type::Array(_, element)) => (**element).clone(),
+                (_, Type::Struct(fields)) => {
+                    let field = get_field_index(index).filter(|field| *field < fields.len())?;
+                    offset = offset.wrapping_add(self.struct_field_offsets(fields)[field] as i64);
+                    ty = fields[field].clone();
+                    continue;
+                }
+                _ => return None,
+            };
+            let stride = self.stride_of(&element);
+            match index.as_ref() {
+                ValueEntity::Constant(constant) => match constant.fold() {
+                    Some(Scalar::Int(value)) => offset = offset.wrapping_add(value.to_i64().wrapping_mul(stride as i64)),
+                    _ => return None,
+                },
+                index => scaled.push((index, stride)),
+            }
+            ty = element;
+        }
+        Some((offset, scaled))
+    }
+
+    /// Returns the byte offset of each field of a struct with the given field types.
+    pub fn struct_field_offsets(&self, fields: &[Type]) -> Vec<u64> {
+        let mut offset: u64 = 0;
+        fields.iter().map(|field| {
+            let field_offset = offset.next_multiple_of(self.align_of(field));
+            offset = field_offset + self.size_of(field);
+            field_offset
+        }).collect()
+    }
+}
+
+impl Default for DataLayout {
+    fn default() -> Self {
+        Self::new_x86_64()
+    }
 }
 
 impl Display for DataLayout {
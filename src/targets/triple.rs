@@ -35,6 +35,12 @@ pub enum Arch {
     Powerpc64,
     /// IBM zSeries architecture (s390x).
     S390x,
+    /// 32-bit RISC-V architecture.
+    Riscv32,
+    /// 64-bit RISC-V architecture.
+    Riscv64,
+    /// 32-bit WebAssembly.
+    Wasm32,
 }
 
 /// Represents different vendors for the target.
@@ -107,6 +113,9 @@ impl TargetTriple {
             "powerpc" => Arch::Powerpc,
             "powerpc64" => Arch::Powerpc64,
             "s390x" => Arch::S390x,
+            "riscv32" => Arch::Riscv32,
+            "riscv64" => Arch::Riscv64,
+            "wasm32" => Arch::Wasm32,
             _ => return Err(Error::InvalidTargetTriple),
         };
 
@@ -158,6 +167,9 @@ impl TargetTriple {
             Arch::Powerpc => "powerpc",
             Arch::Powerpc64 => "powerpc64",
             Arch::S390x => "s390x",
+            Arch::Riscv32 => "riscv32",
+            Arch::Riscv64 => "riscv64",
+            Arch::Wasm32 => "wasm32",
         };
 
         let vendor = match self.vendor {
@@ -200,16 +212,22 @@ impl TargetTriple {
             Arch::Arm
         } else if cfg!(target_arch = "aarch64") {
             Arch::Aarch64
+        } else if cfg!(all(target_arch = "mips", target_endian = "little")) {
+            Arch::Mipsel
         } else if cfg!(target_arch = "mips") {
             Arch::Mips
-        } else if cfg!(target_arch = "mipsel") {
-            Arch::Mipsel
         } else if cfg!(target_arch = "powerpc") {
             Arch::Powerpc
         } else if cfg!(target_arch = "powerpc64") {
             Arch::Powerpc64
         } else if cfg!(target_arch = "s390x") {
             Arch::S390x
+        } else if cfg!(target_arch = "riscv32") {
+            Arch::Riscv32
+        } else if cfg!(target_arch = "riscv64") {
+            Arch::Riscv64
+        } else if cfg!(target_arch = "wasm32") {
+            Arch::Wasm32
         } else {
             return Err(Error::InvalidTargetTriple);
         };
@@ -220,7 +238,7 @@ impl TargetTriple {
             Vendor::Apple
         } else if cfg!(target_vendor = "pc") {
             Vendor::PC
-        } else if cfg!(target_vendor = "scei") {
+        } else if cfg!(target_vendor = "sony") {
             Vendor::SCEI
         } else {
             return Err(Error::InvalidTargetTriple);
@@ -230,14 +248,12 @@ impl TargetTriple {
             TargetOS::Unknown
         } else if cfg!(target_os = "linux") {
             TargetOS::Linux
-        } else if cfg!(target_os = "darwin") {
+        } else if cfg!(target_os = "macos") {
             TargetOS::Darwin
         } else if cfg!(target_os = "windows") {
             TargetOS::Windows
         } else if cfg!(target_os = "android") {
             TargetOS::Android
-        } else if cfg!(target_os = "nacl") {
-            TargetOS::NaCl
         } else if cfg!(target_os = "freebsd") {
             TargetOS::FreeBSD
         } else if cfg!(target_os = "openbsd") {
@@ -250,12 +266,8 @@ impl TargetTriple {
             TargetOS::Solaris
         } else if cfg!(target_os = "haiku") {
             TargetOS::Haiku
-        } else if cfg!(target_os = "minix") {
-            TargetOS::Minix
         } else if cfg!(target_os = "rtems") {
             TargetOS::RTEMS
-        } else if cfg!(target_os = "naclarm") {
-            TargetOS::NaClArm
         } else if cfg!(target_os = "emscripten") {
             TargetOS::Emscripten
         } else if cfg!(target_os = "fuchsia") {